@@ -1,8 +1,16 @@
+pub mod aarch64;
 pub mod block;
 pub mod codegen;
 pub mod context;
+pub mod frame;
 pub mod func;
 pub mod imm;
 pub mod inst;
+pub mod lower;
 pub mod operand;
+pub mod regalloc;
 pub mod regs;
+pub mod sched;
+pub mod target;
+pub mod verify;
+pub mod x86_64;
@@ -0,0 +1,721 @@
+//! AArch64 Code Generation.
+//!
+//! The assembly is emitted directly from the IR, keeping every value in a
+//! stack slot, see [`lower`](super::lower). The values are loaded into the
+//! scratch registers `x9-11` and `v16-17` around each instruction, and
+//! `x16-17` are left for materializing the immediates and the addresses.
+//!
+//! Values of types narrower than 64 bits are kept in the `w` registers, the
+//! `i8` ones sign-extended and the `i1` ones as 0 or 1, like in the RISC-V
+//! backend. The arguments are passed by AAPCS64, see [`arg_locs`].
+//!
+//! The frame pointer `x29` and the link register `x30` are saved at the top
+//! of the frame, so the stack arguments are addressed from `x29`.
+//!
+//! This backend does not go through the machine IR: the instruction kinds, the
+//! register file, the register allocator and the frame lowering there are the
+//! RISC-V ones, see [`codegen`](super::codegen). So there is no register
+//! allocation, `--emit mir` is refused, and the code is only checked by the
+//! tests here, without an AArch64 toolchain to assemble it.
+
+use std::fmt::Display;
+
+use super::context::DataItem;
+use super::lower::{self, SourceComments, StackFrame};
+use super::target::{CodegenOptions, Target, TargetDesc, TargetError};
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::analysis::Cfg;
+use crate::ir::{
+    self,
+    CastOp,
+    ConstantValue,
+    FloatBinaryOp,
+    FloatCmpCond,
+    IntBinaryOp,
+    IntCmpCond,
+    Section,
+    TargetInfo,
+    Ty,
+    Value,
+};
+
+/// Where an argument is passed by the calling convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgLoc {
+    /// In the register of the number, `x` or `v` by the type.
+    Reg(u8),
+    /// In the stack, at the offset from the bottom of the argument area.
+    Stack(i64),
+}
+
+/// Assign the arguments of the types to the registers and the stack.
+///
+/// By AAPCS64, the integer arguments go to `x0-7` and the floating-point ones
+/// to `v0-7`, independently. The rest go to the stack, in 8-byte slots.
+fn arg_locs(ctx: &ir::Context, tys: &[Ty]) -> Vec<ArgLoc> {
+    let mut int_regs = 0..8;
+    let mut float_regs = 0..8;
+    let mut offset = 0;
+    tys.iter()
+        .map(|ty| {
+            let reg = if ty.is_float(ctx) {
+                float_regs.next()
+            } else {
+                int_regs.next()
+            };
+            reg.map(ArgLoc::Reg).unwrap_or_else(|| {
+                offset += 8;
+                ArgLoc::Stack(offset - 8)
+            })
+        })
+        .collect()
+}
+
+/// Get the name of the register of the number holding a value of the type.
+fn reg(ctx: &ir::Context, ty: Ty, n: u8) -> String {
+    match (ty.is_float(ctx), ty.bitwidth(ctx)) {
+        (true, 64) => format!("d{}", n),
+        (true, _) => format!("s{}", n),
+        (false, 64) => format!("x{}", n),
+        (false, _) => format!("w{}", n),
+    }
+}
+
+/// Get the `i`-th scratch register for a value of the type.
+fn scratch(ctx: &ir::Context, ty: Ty, i: u8) -> String {
+    if ty.is_float(ctx) {
+        reg(ctx, ty, 16 + i)
+    } else {
+        reg(ctx, ty, 9 + i)
+    }
+}
+
+/// Get the load instruction of a type.
+fn load_op(ctx: &ir::Context, ty: Ty) -> &'static str {
+    match ty.bitwidth(ctx) {
+        1 => "ldrb",
+        8 => "ldrsb",
+        _ => "ldr",
+    }
+}
+
+/// Get the store instruction of a type.
+fn store_op(ctx: &ir::Context, ty: Ty) -> &'static str {
+    match ty.bitwidth(ctx) {
+        1 | 8 => "strb",
+        _ => "str",
+    }
+}
+
+/// Get the condition code of an integer comparison.
+fn int_cond(cond: IntCmpCond) -> &'static str {
+    match cond {
+        IntCmpCond::Eq => "eq",
+        IntCmpCond::Ne => "ne",
+        IntCmpCond::Slt => "lt",
+        IntCmpCond::Sle => "le",
+        IntCmpCond::Sgt => "gt",
+        IntCmpCond::Sge => "ge",
+    }
+}
+
+/// The AArch64 target, with the AAPCS64 calling convention.
+pub struct AArch64;
+
+impl Target for AArch64 {
+    fn name(&self) -> &'static str { "aarch64" }
+
+    fn info(&self) -> TargetInfo { TargetInfo { ptr_size: 8 } }
+
+    fn cc(&self) -> &'static str { "aarch64-linux-gnu-gcc" }
+
+    fn emit_asm(&self, ctx: &ir::Context, options: &CodegenOptions) -> Result<String, TargetError> {
+        if options.cpu != TargetDesc::default().name {
+            return Err(TargetError::UnknownCpu(options.cpu.clone(), self.name()));
+        }
+        Ok(emit(ctx, options.verbose_asm))
+    }
+}
+
+/// Emit the assembly of a module, commented with the source lines if
+/// `verbose_asm` is set.
+pub fn emit(ctx: &ir::Context, verbose_asm: bool) -> String {
+    let mut out = String::new();
+    out.push_str("\t.text\n");
+    for func in ctx.funcs() {
+        if !func.is_declaration(ctx) {
+            FuncEmitter::new(ctx, func, verbose_asm, &mut out).emit();
+        }
+    }
+    for global in ctx.globals() {
+        emit_global(ctx, global, &mut out);
+    }
+    out
+}
+
+/// Emit the data of a global variable.
+fn emit_global(ctx: &ir::Context, global: ir::Global, out: &mut String) {
+    let name = global.name(ctx);
+    let value = global.value(ctx);
+    let mut items = Vec::new();
+    lower::constant_items(ctx, value, &mut items);
+    let section = match global.section(ctx) {
+        Section::Rodata => ".section .rodata",
+        // An explicit hint cannot drop the initializer.
+        Section::Bss if value.is_zero() => ".bss",
+        Section::Data | Section::Bss => ".data",
+    };
+    let size = value.ty().bytewidth(ctx);
+    out.push_str(&format!("\t{}\n", section));
+    out.push_str(&format!("\t.global {}\n", name));
+    out.push_str(&format!(
+        "\t.p2align {}\n",
+        global.align(ctx).trailing_zeros()
+    ));
+    out.push_str(&format!("\t.type {}, %object\n", name));
+    out.push_str(&format!("\t.size {}, {}\n", name, size));
+    out.push_str(&format!("{}:\n", name));
+    for item in items {
+        let item = match item {
+            DataItem::Byte(value) => format!(".byte {}", value),
+            DataItem::Word(value) => format!(".word {}", value),
+            DataItem::Symbol(label) => format!(".xword {}", label),
+            DataItem::Symbol32(label) => format!(".word {}", label),
+            DataItem::Zero(size) => format!(".zero {}", size),
+        };
+        out.push_str(&format!("\t{}\n", item));
+    }
+    out.push('\n');
+}
+
+/// The emitter of a function.
+struct FuncEmitter<'a> {
+    ctx: &'a ir::Context,
+    func: ir::Func,
+    frame: StackFrame,
+    /// The counter of the local labels within the function.
+    label_counter: u32,
+    comments: SourceComments<'a>,
+    out: &'a mut String,
+}
+
+impl<'a> FuncEmitter<'a> {
+    fn new(ctx: &'a ir::Context, func: ir::Func, verbose_asm: bool, out: &'a mut String) -> Self {
+        let frame = StackFrame::new(ctx, func, |tys| {
+            arg_locs(ctx, tys)
+                .into_iter()
+                .filter(|loc| matches!(loc, ArgLoc::Stack(_)))
+                .count()
+        });
+        Self {
+            ctx,
+            func,
+            frame,
+            label_counter: 0,
+            comments: SourceComments::new(ctx.source().filter(|_| verbose_asm), "//"),
+            out,
+        }
+    }
+
+    /// Append an instruction.
+    fn inst(&mut self, inst: impl Display) { self.out.push_str(&format!("\t{}\n", inst)); }
+
+    /// Append a label.
+    fn label(&mut self, label: impl Display) { self.out.push_str(&format!("{}:\n", label)); }
+
+    /// Generate a local label, for the branches within a lowered instruction.
+    fn new_label(&mut self) -> String {
+        let label = format!(".L{}.{}", self.func.name(self.ctx), self.label_counter);
+        self.label_counter += 1;
+        label
+    }
+
+    /// Materialize an immediate with `movz` and `movk`, 16 bits at a time.
+    fn mov_imm(&mut self, rd: &str, imm: u64) {
+        let chunks = if rd.starts_with('w') { 2 } else { 4 };
+        self.inst(format!("movz {}, #{}", rd, imm & 0xffff));
+        for i in 1..chunks {
+            let chunk = (imm >> (16 * i)) & 0xffff;
+            if chunk != 0 {
+                self.inst(format!("movk {}, #{}, lsl #{}", rd, chunk, 16 * i));
+            }
+        }
+    }
+
+    /// Compute the address at the offset from `sp` into `rd`.
+    fn sp_addr(&mut self, rd: &str, offset: i64) {
+        if offset <= 4095 {
+            self.inst(format!("add {}, sp, #{}", rd, offset));
+        } else {
+            self.mov_imm(rd, offset as u64);
+            self.inst(format!("add {}, sp, {}", rd, rd));
+        }
+    }
+
+    /// Get the memory operand at the offset from `sp`.
+    ///
+    /// The offsets out of the range of the unsigned displacement are added in
+    /// `x17` first.
+    fn stack_mem(&mut self, offset: i64) -> String {
+        if offset <= 4095 {
+            format!("[sp, #{}]", offset)
+        } else {
+            self.sp_addr("x17", offset);
+            "[x17]".to_string()
+        }
+    }
+
+    /// Load a value into the register of the number.
+    ///
+    /// # Returns
+    ///
+    /// The name of the register.
+    fn load_into(&mut self, value: Value, n: u8) -> String {
+        let ctx = self.ctx;
+        let ty = value.ty(ctx);
+        let rd = reg(ctx, ty, n);
+        if let Some(offset) = self.frame.alloca(value) {
+            self.sp_addr(&rd, offset);
+            return rd;
+        }
+        let Some(constant) = value.as_const(ctx) else {
+            let mem = self.stack_mem(self.frame.slot(value));
+            self.inst(format!("ldr {}, {}", rd, mem));
+            return rd;
+        };
+        match constant {
+            ConstantValue::GlobalRef { name, .. } => {
+                self.inst(format!("adrp {}, {}", rd, name));
+                self.inst(format!("add {}, {}, :lo12:{}", rd, rd, name));
+            }
+            ConstantValue::Float32 { bits, .. } => {
+                self.mov_imm("w16", *bits as u64);
+                self.inst(format!("fmov {}, w16", rd));
+            }
+            ConstantValue::Int1 { value, .. } => self.mov_imm(&rd, *value as u64),
+            ConstantValue::Int8 { value, .. } => self.mov_imm(&rd, *value as i32 as u32 as u64),
+            ConstantValue::Int32 { value, .. } => self.mov_imm(&rd, *value as u32 as u64),
+            ConstantValue::Undef { .. } | ConstantValue::AggregateZero { .. } => {
+                let zero = if ty.bitwidth(ctx) == 64 { "xzr" } else { "wzr" };
+                let mov = if ty.is_float(ctx) { "fmov" } else { "mov" };
+                self.inst(format!("{} {}, {}", mov, rd, zero));
+            }
+            ConstantValue::Array { .. } => unreachable!("array values are not in registers"),
+        }
+        rd
+    }
+
+    /// Load a value into the `i`-th scratch register of its type.
+    fn load(&mut self, value: Value, i: u8) -> String {
+        let n = if value.ty(self.ctx).is_float(self.ctx) {
+            16 + i
+        } else {
+            9 + i
+        };
+        self.load_into(value, n)
+    }
+
+    /// Store a value from the register into its slot.
+    fn store(&mut self, value: Value, rs: &str) {
+        let mem = self.stack_mem(self.frame.slot(value));
+        self.inst(format!("str {}, {}", rs, mem));
+    }
+
+    /// Normalize the result of an operation on a type of `bits` bits in the
+    /// register of the number.
+    fn normalize(&mut self, n: u8, bits: usize) {
+        match bits {
+            1 => self.inst(format!("and w{}, w{}, #1", n, n)),
+            8 => self.inst(format!("sxtb w{}, w{}", n, n)),
+            _ => {}
+        }
+    }
+
+    fn emit(mut self) {
+        let ctx = self.ctx;
+        let name = self.func.name(ctx).to_string();
+        self.inst(format!(".global {}", name));
+        self.inst(".p2align 2");
+        self.inst(format!(".type {}, %function", name));
+        self.label(&name);
+
+        self.inst("stp x29, x30, [sp, #-16]!");
+        self.inst("mov x29, sp");
+        let size = self.frame.size();
+        if size <= 4095 {
+            self.inst(format!("sub sp, sp, #{}", size));
+        } else {
+            self.mov_imm("x16", size as u64);
+            self.inst("sub sp, sp, x16");
+        }
+
+        let params = self.func.params(ctx).to_vec();
+        let tys = params.iter().map(|param| param.ty(ctx)).collect::<Vec<_>>();
+        for (&param, loc) in params.iter().zip(arg_locs(ctx, &tys)) {
+            let rs = match loc {
+                ArgLoc::Reg(n) => reg(ctx, param.ty(ctx), n),
+                ArgLoc::Stack(offset) => {
+                    let rd = scratch(ctx, param.ty(ctx), 0);
+                    self.inst(format!("ldr {}, [x29, #{}]", rd, 16 + offset));
+                    rd
+                }
+            };
+            self.store(param, &rs);
+        }
+
+        let cfg = Cfg::new(ctx, self.func);
+        for block in self.func.iter(ctx) {
+            if !cfg.is_reachable(block) {
+                continue;
+            }
+            let label = lower::block_label(ctx, block);
+            match self.comments.block(ctx, block) {
+                Some(comment) => self.out.push_str(&format!("{}:\t\t\t{}\n", label, comment)),
+                None => self.label(label),
+            }
+            for inst in block.iter(ctx) {
+                if let Some(comment) = self.comments.inst(ctx, inst) {
+                    self.inst(comment);
+                }
+                self.emit_inst(inst);
+            }
+        }
+        self.out.push('\n');
+    }
+
+    /// Emit the copies on the edge to a successor of a branch, and the jump.
+    fn emit_edge(&mut self, branch: ir::Inst, succ_idx: usize) {
+        let ctx = self.ctx;
+        let copies = lower::edge_copies(ctx, branch, succ_idx);
+        for &(dst, src) in copies.iter() {
+            let rs = self.load(src, 0);
+            let mem = self.stack_mem(self.frame.shadow(dst));
+            self.inst(format!("str {}, {}", rs, mem));
+        }
+        for &(dst, _) in copies.iter() {
+            let rd = scratch(ctx, dst.ty(ctx), 0);
+            let mem = self.stack_mem(self.frame.shadow(dst));
+            self.inst(format!("ldr {}, {}", rd, mem));
+            self.store(dst, &rd);
+        }
+        let succ = branch.successor(ctx, succ_idx);
+        self.inst(format!("b {}", lower::block_label(ctx, succ)));
+    }
+
+    fn emit_inst(&mut self, inst: ir::Inst) {
+        let ctx = self.ctx;
+        let result = inst.result(ctx);
+        match inst.kind(ctx) {
+            ir::InstKind::Alloca { .. } | ir::InstKind::Phi => {}
+            ir::InstKind::Load => {
+                let ptr = self.load(inst.operand(ctx, 0), 0);
+                let result = result.unwrap();
+                let rd = scratch(ctx, result.ty(ctx), 1);
+                self.inst(format!(
+                    "{} {}, [{}]",
+                    load_op(ctx, result.ty(ctx)),
+                    rd,
+                    ptr
+                ));
+                self.store(result, &rd);
+            }
+            ir::InstKind::Store => {
+                let val = inst.operand(ctx, 0);
+                let rs = self.load(val, 1);
+                let ptr = self.load(inst.operand(ctx, 1), 0);
+                self.inst(format!("{} {}, [{}]", store_op(ctx, val.ty(ctx)), rs, ptr));
+            }
+            ir::InstKind::GetElementPtr { bound_ty } => {
+                self.load(inst.operand(ctx, 0), 0);
+                let mut ty = *bound_ty;
+                let mut offset = 0i64;
+                for (i, idx) in inst.operand_iter(ctx).skip(1).enumerate() {
+                    if i > 0 {
+                        ty = ty.as_array(ctx).unwrap().0;
+                    }
+                    let size = ty.bytewidth(ctx) as i64;
+                    if let Some(idx) = idx.as_const_int(ctx) {
+                        offset += idx as i64 * size;
+                        continue;
+                    }
+                    if self.load(idx, 1).starts_with('w') {
+                        self.inst("sxtw x10, w10");
+                    }
+                    self.mov_imm("x11", size as u64);
+                    self.inst("madd x9, x10, x11, x9");
+                }
+                if offset != 0 {
+                    self.mov_imm("x11", offset as u64);
+                    self.inst("add x9, x9, x11");
+                }
+                self.store(result.unwrap(), "x9");
+            }
+            ir::InstKind::Call => {
+                let args = inst.operand_iter(ctx).skip(1).collect::<Vec<_>>();
+                let tys = args.iter().map(|arg| arg.ty(ctx)).collect::<Vec<_>>();
+                let locs = arg_locs(ctx, &tys);
+                for (&arg, &loc) in args.iter().zip(locs.iter()) {
+                    if let ArgLoc::Stack(offset) = loc {
+                        let rs = self.load(arg, 0);
+                        self.inst(format!("str {}, [sp, #{}]", rs, offset));
+                    }
+                }
+                for (&arg, &loc) in args.iter().zip(locs.iter()) {
+                    if let ArgLoc::Reg(n) = loc {
+                        self.load_into(arg, n);
+                    }
+                }
+                self.inst(format!("bl {}", inst.callee(ctx)));
+                if let Some(result) = result {
+                    self.store(result, &reg(ctx, result.ty(ctx), 0));
+                }
+            }
+            ir::InstKind::Br => self.emit_edge(inst, 0),
+            ir::InstKind::CondBr => {
+                let cond = self.load(inst.operand(ctx, 0), 0);
+                let else_label = self.new_label();
+                self.inst(format!("cbz {}, {}", cond, else_label));
+                self.emit_edge(inst, 0);
+                self.label(else_label);
+                self.emit_edge(inst, 1);
+            }
+            ir::InstKind::Switch { cases } => {
+                let x = self.load(inst.operand(ctx, 0), 0);
+                let labels = cases.iter().map(|_| self.new_label()).collect::<Vec<_>>();
+                for (&case, label) in cases.iter().zip(labels.iter()) {
+                    self.mov_imm("w10", case as u32 as u64);
+                    self.inst(format!("cmp {}, w10", x));
+                    self.inst(format!("b.eq {}", label));
+                }
+                self.emit_edge(inst, 0);
+                for (i, label) in labels.into_iter().enumerate() {
+                    self.label(label);
+                    self.emit_edge(inst, i + 1);
+                }
+            }
+            ir::InstKind::Ret => {
+                if let Some(val) = inst.operand_iter(ctx).next() {
+                    self.load_into(val, 0);
+                }
+                self.inst("mov sp, x29");
+                self.inst("ldp x29, x30, [sp], #16");
+                self.inst("ret");
+            }
+            ir::InstKind::IntBinary { op } => {
+                let lhs = inst.operand(ctx, 0);
+                let bits = lhs.ty(ctx).bitwidth(ctx);
+                let l = self.load(lhs, 0);
+                let r = self.load(inst.operand(ctx, 1), 1);
+                let t = format!("{}11", &l[..1]);
+                let unsigned = matches!(
+                    op,
+                    IntBinaryOp::LShr | IntBinaryOp::UDiv | IntBinaryOp::URem
+                );
+                if bits == 8 && unsigned {
+                    // The unsigned operations see the sign-extended bits otherwise.
+                    self.inst("and w9, w9, #0xff");
+                    if !matches!(op, IntBinaryOp::LShr) {
+                        self.inst("and w10, w10, #0xff");
+                    }
+                }
+                let mnemonic = match op {
+                    IntBinaryOp::Add => "add",
+                    IntBinaryOp::Sub => "sub",
+                    IntBinaryOp::Mul => "mul",
+                    IntBinaryOp::SDiv => "sdiv",
+                    IntBinaryOp::UDiv => "udiv",
+                    IntBinaryOp::Shl => "lsl",
+                    IntBinaryOp::LShr => "lsr",
+                    IntBinaryOp::AShr => "asr",
+                    IntBinaryOp::And => "and",
+                    IntBinaryOp::Or => "orr",
+                    IntBinaryOp::Xor => "eor",
+                    IntBinaryOp::SMulHi if bits <= 32 => {
+                        // The full product of two sign-extended words fits in
+                        // 64 bits.
+                        self.inst("smull x9, w9, w10");
+                        self.inst(format!("asr x9, x9, #{}", bits));
+                        ""
+                    }
+                    IntBinaryOp::SMulHi => "smulh",
+                    IntBinaryOp::SMin | IntBinaryOp::SMax => {
+                        let cond = if matches!(op, IntBinaryOp::SMin) {
+                            "lt"
+                        } else {
+                            "gt"
+                        };
+                        self.inst(format!("cmp {}, {}", l, r));
+                        self.inst(format!("csel {}, {}, {}, {}", l, l, r, cond));
+                        ""
+                    }
+                    IntBinaryOp::SRem | IntBinaryOp::URem => {
+                        let div = if matches!(op, IntBinaryOp::SRem) {
+                            "sdiv"
+                        } else {
+                            "udiv"
+                        };
+                        self.inst(format!("{} {}, {}, {}", div, t, l, r));
+                        self.inst(format!("msub {}, {}, {}, {}", l, t, r, l));
+                        ""
+                    }
+                    IntBinaryOp::ICmp { cond } => {
+                        self.inst(format!("cmp {}, {}", l, r));
+                        self.inst(format!("cset w9, {}", int_cond(*cond)));
+                        self.store(result.unwrap(), "w9");
+                        return;
+                    }
+                };
+                if !mnemonic.is_empty() {
+                    self.inst(format!("{} {}, {}, {}", mnemonic, l, l, r));
+                }
+                self.normalize(9, bits);
+                self.store(result.unwrap(), &l);
+            }
+            ir::InstKind::FloatBinary { op } => {
+                let l = self.load(inst.operand(ctx, 0), 0);
+                let r = self.load(inst.operand(ctx, 1), 1);
+                let mnemonic = match op {
+                    FloatBinaryOp::FAdd => "fadd",
+                    FloatBinaryOp::FSub => "fsub",
+                    FloatBinaryOp::FMul => "fmul",
+                    FloatBinaryOp::FDiv => "fdiv",
+                    FloatBinaryOp::FCmp { cond } => {
+                        // The unordered results clear all the conditions used.
+                        self.inst(format!("fcmp {}, {}", l, r));
+                        let cond = match cond {
+                            FloatCmpCond::Oeq => "eq",
+                            FloatCmpCond::Olt => "mi",
+                            FloatCmpCond::Ole => "ls",
+                            FloatCmpCond::Ogt => "gt",
+                            FloatCmpCond::Oge => "ge",
+                            FloatCmpCond::One => {
+                                self.inst("cset w9, mi");
+                                self.inst("cset w10, gt");
+                                self.inst("orr w9, w9, w10");
+                                self.store(result.unwrap(), "w9");
+                                return;
+                            }
+                        };
+                        self.inst(format!("cset w9, {}", cond));
+                        self.store(result.unwrap(), "w9");
+                        return;
+                    }
+                };
+                self.inst(format!("{} {}, {}, {}", mnemonic, l, l, r));
+                self.store(result.unwrap(), &l);
+            }
+            ir::InstKind::Cast { op } => {
+                let val = inst.operand(ctx, 0);
+                let result = result.unwrap();
+                let from = val.ty(ctx).bitwidth(ctx);
+                let to = result.ty(ctx).bitwidth(ctx);
+                let rs = self.load(val, 0);
+                let rd = scratch(ctx, result.ty(ctx), 0);
+                match op {
+                    CastOp::SiToFp => {
+                        // `i1` is kept as 0 or 1, but `true` is -1 as a signed
+                        // value.
+                        if from == 1 {
+                            self.inst("neg w9, w9");
+                        }
+                        self.inst(format!("scvtf {}, {}", rd, rs));
+                    }
+                    CastOp::FpToSi => {
+                        self.inst(format!("fcvtzs {}, {}", rd, rs));
+                        self.normalize(9, to);
+                    }
+                    CastOp::Zext if from == 32 => self.inst("mov w9, w9"),
+                    CastOp::Zext => self.inst(format!("and x9, x9, #{}", (1u64 << from) - 1)),
+                    CastOp::Sext => match from {
+                        1 => self.inst("sbfx x9, x9, #0, #1"),
+                        8 => self.inst("sxtb x9, w9"),
+                        _ => self.inst("sxtw x9, w9"),
+                    },
+                    CastOp::Trunc => self.normalize(9, to),
+                }
+                self.store(result, &rd);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Context, Func, Global, Inst};
+
+    #[test]
+    fn test_emit() {
+        // int g = 3;
+        // int f(int a0, ..., int a8) { return a0 + a8 * g; }
+        // int main() { x = 0; do { x = x + 1; } while (x < 10); return f(x, ..., x); }
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let value = ConstantValue::i32(&mut ctx, 3);
+        Global::new(&mut ctx, "g".to_string(), value);
+        let push = |ctx: &mut Context, block: Block, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+
+        let f = Func::new(&mut ctx, "f".to_string(), i32);
+        let params = (0..9)
+            .map(|_| f.add_param(&mut ctx, i32))
+            .collect::<Vec<_>>();
+        let entry = Block::new(&mut ctx);
+        f.push_back(&mut ctx, entry).unwrap();
+        let g = Value::global_ref(&mut ctx, "g".to_string(), i32);
+        let inst = Inst::load(&mut ctx, g, i32);
+        let load = push(&mut ctx, entry, inst).unwrap();
+        let inst = Inst::ibinary(&mut ctx, IntBinaryOp::Mul, params[8], load);
+        let mul = push(&mut ctx, entry, inst).unwrap();
+        let inst = Inst::ibinary(&mut ctx, IntBinaryOp::Add, params[0], mul);
+        let add = push(&mut ctx, entry, inst);
+        let inst = Inst::ret(&mut ctx, add);
+        push(&mut ctx, entry, inst);
+
+        let main = Func::new(&mut ctx, "main".to_string(), i32);
+        let entry = Block::new(&mut ctx);
+        let body = Block::new(&mut ctx);
+        let exit = Block::new(&mut ctx);
+        for block in [entry, body, exit] {
+            main.push_back(&mut ctx, block).unwrap();
+        }
+        let zero = Value::i32(&mut ctx, 0);
+        let one = Value::i32(&mut ctx, 1);
+        let ten = Value::i32(&mut ctx, 10);
+        let inst = Inst::br_with_args(&mut ctx, body, vec![zero]);
+        push(&mut ctx, entry, inst);
+        let x = body.add_param(&mut ctx, i32);
+        let inst = Inst::ibinary(&mut ctx, IntBinaryOp::Add, x, one);
+        let next = push(&mut ctx, body, inst).unwrap();
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let inst = Inst::ibinary(&mut ctx, slt, next, ten);
+        let cmp = push(&mut ctx, body, inst).unwrap();
+        let inst = Inst::cond_br_with_args(&mut ctx, cmp, body, vec![next], exit, vec![]);
+        push(&mut ctx, body, inst);
+        let inst = Inst::call(&mut ctx, f, vec![next; 9]);
+        let call = push(&mut ctx, exit, inst);
+        let inst = Inst::ret(&mut ctx, call);
+        push(&mut ctx, exit, inst);
+
+        let asm = emit(&ctx, false).replace('\t', "");
+        let expected = [
+            // the ninth argument is passed on the stack
+            "f:\nstp x29, x30, [sp, #-16]!\nmov x29, sp\nsub sp, sp, #96\n",
+            "ldr w9, [x29, #16]\nstr w9, [sp, #64]\n",
+            "adrp x9, g\nadd x9, x9, :lo12:g\nldr w10, [x9]\n",
+            "mov sp, x29\nldp x29, x30, [sp], #16\nret\n",
+            // the outgoing argument is stored below the value slots
+            "ldr w9, [sp, #16]\nstr w9, [sp, #0]\n",
+            "bl f\nstr w0, ",
+            "cmp w9, w10\ncset w9, lt\n",
+            ".data\n.global g\n.p2align 2\n.type g, %object\n.size g, 4\ng:\n.word 3\n",
+        ];
+        for expected in expected {
+            assert!(asm.contains(expected), "{}", asm);
+        }
+    }
+}
@@ -10,6 +10,9 @@ pub struct MBlockData {
     /// The label of the block.
     label: MLabel,
 
+    /// The alignment of the block in bytes, 1 if not aligned.
+    align: u32,
+
     /// The head machine instruction of the block.
     head: Option<MInst>,
 
@@ -35,6 +38,7 @@ impl MBlock {
     pub fn new(mctx: &mut MContext, label: impl Into<MLabel>) -> Self {
         mctx.alloc(MBlockData {
             label: label.into(),
+            align: 1,
             head: None,
             tail: None,
             next: None,
@@ -48,6 +52,17 @@ impl MBlock {
         &self.deref(arena).label
     }
 
+    /// Get the alignment of the block in bytes.
+    pub fn align(self, arena: &MContext) -> u32 {
+        self.deref(arena).align
+    }
+
+    /// Align the block to `align` bytes, a power of two, e.g., for the
+    /// headers of the hot loops.
+    pub fn set_align(self, arena: &mut MContext, align: u32) {
+        self.deref_mut(arena).align = align;
+    }
+
     /// Get the instructions size of the block.
     pub fn size(self, arena: &MContext) -> usize {
         let mut size = 0;
@@ -1,19 +1,198 @@
 //! Target Code Generation.
 //!
-//! The assembly code is generated here.
+//! The assembly code is generated here, in three stages:
+//!
+//! 1. [`CodegenContext::codegen`] lowers the IR into machine instructions on
+//!    virtual registers. Phis and block parameters become copies on the
+//!    incoming edges, splitting the critical ones.
+//! 2. [`CodegenContext::regalloc`] assigns the physical registers, see
+//!    [`regalloc`](super::regalloc).
+//! 3. [`CodegenContext::after_regalloc`] schedules the instructions, see
+//!    [`sched`](super::sched), lays out the stack frames, inserts the prologues
+//!    and the epilogues, and resolves the stack slots, see
+//!    [`frame`](super::frame).
+//!
+//! Values of types narrower than 64 bits are kept sign-extended in the
+//! registers, except `i1`, which is kept as 0 or 1. Floating-point values live
+//! in the `f` registers, and are passed by the LP64D calling convention, see
+//! [`arg_locs`](CodegenContext::arg_locs).
+//!
+//! A call immediately followed by a return of its result becomes a tail call
+//! where the frames allow, see [`is_tail_call`](CodegenContext::is_tail_call).
+//!
+//! The register width follows the pointer size of the IR, see
+//! [`TargetInfo`]. On RV32, the registers are the words, so the `w`
+//! instructions are replaced by the plain ones (see
+//! [`MContext::xlen`](super::context::MContext::xlen)), the arguments take
+//! 4-byte stack slots by the ILP32D calling convention, and the 64-bit integers
+//! are not supported.
+//!
+//! The instructions of the optional extensions are only selected if enabled
+//! in [`TargetFeatures`], e.g., `sh2add` for the array indexing with Zba, and
+//! `min`, `max` and `sext.b` with Zbb.
+//!
+//! The headers of the hot loops, by the estimated block frequencies, are
+//! aligned to the fetch width with `.balign`, so each iteration starts on a
+//! fresh fetch block. The colder loops are left unaligned, not worth the
+//! padding.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use rayon::prelude::*;
 
 use super::block::MBlock;
-use super::context::MContext;
+use super::context::{DataItem, MContext, RawData};
 use super::func::{MFunc, MLabel};
 use super::imm::Imm12;
-use super::inst::{AluOpRRI, AluOpRRR, LoadOp, MInst, MInstKind, StoreOp};
+use super::inst::{
+    AluOpRR,
+    AluOpRRI,
+    AluOpRRR,
+    BranchOp,
+    FpuOpRR,
+    FpuOpRRR,
+    LoadOp,
+    MInst,
+    StoreOp,
+};
 use super::operand::{MOperand, MOperandKind, MemLoc};
-use super::regs::{self, Reg};
+use super::regs::{self, PReg, Reg, RegKind};
+use super::target::{CodegenOptions, Target, TargetDesc, TargetError, TargetFeatures};
+use super::verify::{self, Problem, Stage, VerifyError};
+use super::{frame, lower, regalloc, sched};
+use crate::infra::intern::Symbol;
 use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
-use crate::infra::storage::ArenaPtr;
-use crate::ir::{self, ConstantValue, IntBinaryOp, Ty, Value};
+use crate::infra::timing;
+use crate::ir::analysis::{BlockFreqs, BranchProbs, Cfg, DomTree, LoopInfo};
+use crate::ir::{
+    self,
+    CastOp,
+    ConstantValue,
+    FloatBinaryOp,
+    FloatCmpCond,
+    IntBinaryOp,
+    IntCmpCond,
+    Section,
+    TargetInfo,
+    Ty,
+    Usable,
+    Value,
+};
+
+/// Where an argument is passed by the calling convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgLoc {
+    /// In a register, possibly an integer one for a floating-point value.
+    Reg(PReg),
+    /// In the stack, at the offset from the bottom of the argument area.
+    Stack(i64),
+}
+
+/// The alignment of the headers of the hot loops in bytes.
+const LOOP_ALIGN: u32 = 16;
+
+/// The minimum frequency of a loop header relative to the entry, for the loop
+/// to be aligned.
+const HOT_LOOP_FREQ: f64 = 4.0;
+
+/// The RV64GC target, with the LP64D calling convention.
+pub struct Riscv64;
+
+impl Target for Riscv64 {
+    fn name(&self) -> &'static str { "riscv64" }
+
+    fn info(&self) -> TargetInfo { TargetInfo { ptr_size: 8 } }
+
+    fn cc(&self) -> &'static str { "riscv64-linux-gnu-gcc" }
+
+    fn emit_asm(&self, ctx: &ir::Context, options: &CodegenOptions) -> Result<String, TargetError> {
+        emit_asm(ctx, options, self.name())
+    }
+
+    fn emit_mir(&self, ctx: &ir::Context, options: &CodegenOptions) -> Result<String, TargetError> {
+        emit_mir(ctx, options, self.name())
+    }
+}
+
+/// The RV32GC target, with the ILP32D calling convention.
+pub struct Riscv32;
+
+impl Target for Riscv32 {
+    fn name(&self) -> &'static str { "riscv32" }
+
+    fn info(&self) -> TargetInfo { TargetInfo { ptr_size: 4 } }
+
+    fn cc(&self) -> &'static str { "riscv32-unknown-elf-gcc" }
+
+    fn emit_asm(&self, ctx: &ir::Context, options: &CodegenOptions) -> Result<String, TargetError> {
+        emit_asm(ctx, options, self.name())
+    }
+
+    fn emit_mir(&self, ctx: &ir::Context, options: &CodegenOptions) -> Result<String, TargetError> {
+        emit_mir(ctx, options, self.name())
+    }
+}
+
+/// Generate the RISC-V assembly of a module, for the register width of its
+/// pointer size.
+fn emit_asm(
+    ctx: &ir::Context,
+    options: &CodegenOptions,
+    name: &'static str,
+) -> Result<String, TargetError> {
+    let mctx = generate(ctx, options, name, |_, _| {})?;
+    Ok(timing::time("emission", || mctx.display().to_string()))
+}
+
+/// Generate the machine IR of a module before and after the register
+/// allocation, see [`DisplayMir`](super::context::DisplayMir).
+fn emit_mir(
+    ctx: &ir::Context,
+    options: &CodegenOptions,
+    name: &'static str,
+) -> Result<String, TargetError> {
+    let mut mir = String::new();
+    generate(ctx, options, name, |mctx, stage| {
+        if stage != Stage::Frame {
+            mir += &format!("# after the {}\n\n{}", stage, mctx.display_mir());
+        }
+    })?;
+    Ok(mir)
+}
+
+/// Run the code generation, showing the machine code to `observe` after each
+/// stage.
+fn generate(
+    ctx: &ir::Context,
+    options: &CodegenOptions,
+    name: &'static str,
+    mut observe: impl FnMut(&MContext, Stage),
+) -> Result<MContext, TargetError> {
+    let target = TargetDesc::from_name(&options.cpu)
+        .ok_or_else(|| TargetError::UnknownCpu(options.cpu.clone(), name))?;
+    let mut codegen_ctx = CodegenContext::new(ctx);
+    codegen_ctx.set_target(target);
+    codegen_ctx.set_omit_frame_pointer(options.omit_frame_pointer);
+    codegen_ctx.set_schedule(options.schedule);
+    codegen_ctx.set_verbose_asm(options.verbose_asm);
+    codegen_ctx.set_stack_protector(options.stack_protector);
+    codegen_ctx.set_features(options.features);
+    codegen_ctx.set_verify_machineinstrs(options.verify_machineinstrs);
+    let mut after = |codegen_ctx: &CodegenContext, stage| {
+        observe(&codegen_ctx.mctx, stage);
+        match options.verify_machineinstrs {
+            true => codegen_ctx.verify(stage),
+            false => Ok(()),
+        }
+    };
+    timing::time(&Stage::Isel.to_string(), || codegen_ctx.codegen());
+    after(&codegen_ctx, Stage::Isel)?;
+    timing::time(&Stage::RegAlloc.to_string(), || codegen_ctx.regalloc())?;
+    after(&codegen_ctx, Stage::RegAlloc)?;
+    timing::time(&Stage::Frame.to_string(), || codegen_ctx.after_regalloc());
+    after(&codegen_ctx, Stage::Frame)?;
+    Ok(codegen_ctx.finish())
+}
 
 pub struct CodegenContext<'s> {
     /// The machine code context.
@@ -27,13 +206,27 @@ pub struct CodegenContext<'s> {
     ///
     /// We want to get machine function by the name when generating call
     /// instruction, so simply map the function name to the machine function.
-    pub funcs: HashMap<String, MFunc>,
+    pub funcs: HashMap<Symbol, MFunc>,
 
     /// Mapping IR blocks to machine blocks.
     pub blocks: HashMap<ir::Block, MBlock>,
 
     /// Other global labels, for global variables/constants.
-    pub globals: HashMap<String, MLabel>,
+    pub globals: HashMap<Symbol, MLabel>,
+
+    /// The target core, deciding between the alternative sequences.
+    pub(super) target: TargetDesc,
+    /// If the functions omit the frame pointer, see [`frame`](super::frame).
+    pub(super) omit_frame_pointer: bool,
+    /// If the instructions are scheduled, see [`sched`](super::sched).
+    pub(super) schedule: bool,
+    /// If the frames containing arrays are guarded by a stack canary, see
+    /// [`frame`](super::frame).
+    pub(super) stack_protector: bool,
+    /// The optional extensions the instruction selection may use.
+    pub(super) features: TargetFeatures,
+    /// If the register allocation is checked, see [`regalloc::verify`].
+    pub(super) verify_machineinstrs: bool,
 
     /// The current function.
     pub(super) curr_func: Option<MFunc>,
@@ -56,12 +249,45 @@ impl<'s> CodegenContext<'s> {
             funcs: HashMap::default(),
             blocks: HashMap::default(),
             globals: HashMap::default(),
+            target: TargetDesc::default(),
+            omit_frame_pointer: false,
+            schedule: true,
+            stack_protector: false,
+            features: TargetFeatures::default(),
+            verify_machineinstrs: false,
             curr_func: None,
             curr_block: None,
             label_counter: 0,
         }
     }
 
+    /// Set the target core to generate code for.
+    pub fn set_target(&mut self, target: TargetDesc) { self.target = target; }
+
+    /// Set whether to omit the frame pointer, freeing `s0` for the register
+    /// allocation.
+    pub fn set_omit_frame_pointer(&mut self, omit: bool) { self.omit_frame_pointer = omit; }
+
+    /// Set whether to schedule the instructions for the in-order cores.
+    pub fn set_schedule(&mut self, schedule: bool) { self.schedule = schedule; }
+
+    /// Set whether to guard the frames containing arrays by a stack canary.
+    pub fn set_stack_protector(&mut self, protect: bool) { self.stack_protector = protect; }
+
+    /// Set whether to check the register allocation.
+    pub fn set_verify_machineinstrs(&mut self, verify: bool) { self.verify_machineinstrs = verify; }
+
+    /// Set the optional extensions to use.
+    pub fn set_features(&mut self, features: TargetFeatures) { self.features = features; }
+
+    /// Set whether to comment the assembly with the source lines, if the IR
+    /// has the source attached.
+    pub fn set_verbose_asm(&mut self, verbose: bool) {
+        if let Some(source) = self.ctx.source().filter(|_| verbose) {
+            self.mctx.set_source(source.clone());
+        }
+    }
+
     /// Finish the code generation and return the machine code context.
     pub fn finish(self) -> MContext { self.mctx }
 
@@ -71,435 +297,2492 @@ impl<'s> CodegenContext<'s> {
     /// Get a mutable reference to the machine code context.
     pub fn mctx_mut(&mut self) -> &mut MContext { &mut self.mctx }
 
+    /// Generate a unique label for blocks without a name.
+    pub fn new_label(&mut self) -> MLabel {
+        let label = MLabel::from(format!(".L{}", self.label_counter));
+        self.label_counter += 1;
+        label
+    }
+
+    /// Get the width of the general registers in bits, which is the pointer
+    /// width of the IR.
+    pub fn xlen(&self) -> u32 { self.ctx.target_info().ptr_size * 8 }
+
     /// Do the code generation.
     pub fn codegen(&mut self) {
-        // Generate plcaceholders for all the functions and blocks.
+        self.mctx
+            .set_arch(format!("rv{}gc{}", self.xlen(), self.features.isa_suffix()));
+
+        // Generate placeholders for all the functions and blocks.
         for func in self.ctx.funcs() {
             let name = func.name(self.ctx);
             let label = MLabel::from(name);
 
             let mfunc = MFunc::new(&mut self.mctx, label);
-            self.funcs.insert(name.to_string(), mfunc);
+            mfunc.set_external(&mut self.mctx, func.is_declaration(self.ctx));
+            mfunc.set_omit_frame_pointer(&mut self.mctx, self.omit_frame_pointer);
+            let has_arrays = func.iter(self.ctx).any(|block| {
+                block.iter(self.ctx).any(|inst| match inst.kind(self.ctx) {
+                    ir::InstKind::Alloca { ty } => ty.as_array(self.ctx).is_some(),
+                    _ => false,
+                })
+            });
+            mfunc.set_stack_protector(&mut self.mctx, self.stack_protector && has_arrays);
+            self.funcs.insert(name, mfunc);
 
             for block in func.iter(self.ctx) {
-                let mblock = MBlock::new(&mut self.mctx, format!(".{}", block.name(self.ctx)));
+                let name = block.name(self.ctx);
+                let label = format!(".L{}", name.trim_start_matches('%'));
+                let mblock = MBlock::new(&mut self.mctx, label);
                 let _ = mfunc.push_back(&mut self.mctx, mblock);
                 self.blocks.insert(block, mblock);
             }
         }
 
-        // TODO: There are several things to be handled before translating instructions:
-        //  1. External functions and corresponding signatures.
-        //  2. Global variables/constants.
+        for global in self.ctx.globals() {
+            self.codegen_global(global);
+        }
 
-        // XXX: This is just a demonstration, you may refactor this part entirely.
         for func in self.ctx.funcs() {
-            self.curr_func = Some(self.funcs[func.name(self.ctx)]);
-            let mfunc = self.curr_func.unwrap();
+            if !func.is_declaration(self.ctx) {
+                self.codegen_func(func);
+            }
+        }
+    }
 
-            // TODO: Incoming parameters can be handled here.
+    /// Generate the data of a global variable.
+    fn codegen_global(&mut self, global: ir::Global) {
+        let label = MLabel::from(global.name(self.ctx));
+        let value = global.value(self.ctx);
+        let mut items = Vec::new();
+        lower::constant_items(self.ctx, value, &mut items);
+        let data = match global.section(self.ctx) {
+            Section::Rodata => RawData::ReadOnly(items),
+            // An explicit hint cannot drop the initializer.
+            Section::Bss if value.is_zero() => RawData::Bss(value.ty().bytewidth(self.ctx)),
+            Section::Data | Section::Bss => RawData::Data(items),
+        };
+        let align = global.align(self.ctx);
+        self.mctx.add_raw_data(label.clone(), data, align);
+        self.globals.insert(global.name(self.ctx), label);
+    }
 
-            // XXX: You can use dominance/cfg to generate better assembly.
+    /// Generate the code of a function definition.
+    fn codegen_func(&mut self, func: ir::Func) {
+        let mfunc = self.funcs[&func.name(self.ctx)];
+        self.curr_func = Some(mfunc);
 
-            // Translate the instructions.
-            for block in func.iter(self.ctx) {
-                self.curr_block = Some(self.blocks[&block]);
-                let mblock = self.curr_block.unwrap();
-
-                for inst in block.iter(self.ctx) {
-                    // TODO: Translate the instruction.
-                    match inst.kind(self.ctx) {
-                        ir::InstKind::Alloca { ty } => {
-                            // Allocate space on the stack.
-                            let size = (ty.bitwidth(self.ctx) + 7) / 8;
-                            mfunc.add_storage_stack_size(&mut self.mctx, size as u64);
-                            // Because the stack grows downward, we need to use negative offset.
-                            let offset = -(mfunc.storage_stack_size(&self.mctx) as i64);
-                            let mem_loc = MemLoc::Slot { offset };
-                            let ty = inst.result(self.ctx).unwrap().ty(self.ctx);
-                            let mopd = MOperand {
-                                ty,
-                                kind: MOperandKind::Mem(mem_loc),
-                            };
-                            // Insert the result into the lowered map.
-                            self.lowered.insert(inst.result(self.ctx).unwrap(), mopd);
-                        }
-                        ir::InstKind::Store => {
-                            let val = inst.operand(self.ctx, 0);
-                            let ptr = inst.operand(self.ctx, 1);
-                            let mem_loc = match self.lowered[&ptr].kind {
-                                MOperandKind::Mem(mem_loc) => mem_loc,
-                                _ => unreachable!(),
-                            };
-                            // Here we use a helper function to generate the store instruction.
-                            // You can change the implementation of the helper functions as you
-                            // like. Or you can also not use helper functions.
-                            self.gen_store(val, mem_loc);
-                        }
-                        ir::InstKind::Load => {
-                            let ptr = inst.operand(self.ctx, 0);
-                            let mem_loc = match self.lowered[&ptr].kind {
-                                MOperandKind::Mem(mem_loc) => mem_loc,
-                                // There might be other cases, but i'll just panic for now.
-                                _ => unreachable!(),
-                            };
-                            let ty = inst.result(self.ctx).unwrap().ty(self.ctx);
-                            let mopd = self.gen_load(ty, mem_loc);
-                            self.lowered.insert(inst.result(self.ctx).unwrap(), mopd);
-                        }
-                        ir::InstKind::IntBinary { op } => {
-                            // TODO: Here's a simple example, you may need to handle more cases.
-                            let lhs = inst.operand(self.ctx, 0);
-                            let rhs = inst.operand(self.ctx, 1);
-                            let mopd = self.gen_int_binary(*op, lhs, rhs);
-                            self.lowered.insert(inst.result(self.ctx).unwrap(), mopd);
-                        }
-                        ir::InstKind::Ret => {
-                            // TODO: You can handle multiple return values as you like.
-                            if inst.operand_iter(self.ctx).count() == 1 {
-                                let val = inst.operand(self.ctx, 0);
-                                self.gen_ret_move(val);
-                            }
-                            // The `ret` should be generated in function
-                            // epilogue, after register allocation.
-                        }
-                        &ir::InstKind::Br => {
-                            // You can also encapsulate this into a helper function for cleaner
-                            // code.
-                            if inst.operand_iter(self.ctx).count() == 0 {
-                                // Unconditional branch.
-                                let target = inst.successor(self.ctx, 0);
-                                let target_block = self.blocks[&target];
-                                let j = MInst::j(&mut self.mctx, target_block);
-                                mblock.push_back(&mut self.mctx, j).unwrap();
-                            } else {
-                                // TODO: Handle conditional branch.
-                                todo!()
-                            }
-                        }
-                        // TODO: Add more instuctions.
-                        _ => {
-                            todo!()
-                        }
-                    }
+        // Unreachable blocks are dropped, so the rest can be lowered in the
+        // preorder of the dominator tree, with definitions before uses.
+        let cfg = Cfg::new(self.ctx, func);
+        let domtree = DomTree::new(&cfg);
+        for block in func.iter(self.ctx) {
+            if !cfg.is_reachable(block) {
+                self.blocks[&block].remove(&mut self.mctx);
+            }
+        }
+
+        // The entry is not aligned, as the padding would be run through.
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+        let probs = BranchProbs::new(self.ctx, &cfg, &loop_info);
+        let freqs = BlockFreqs::new(&cfg, &loop_info, &probs);
+        for lp in loop_info.loops() {
+            let header = loop_info.header(lp);
+            if Some(header) != cfg.entry() && freqs.freq(header) >= HOT_LOOP_FREQ {
+                self.blocks[&header].set_align(&mut self.mctx, LOOP_ALIGN);
+            }
+        }
+
+        // Phis and block parameters are defined on the incoming edges, so their
+        // registers are created in advance.
+        for block in func.iter(self.ctx).filter(|&block| cfg.is_reachable(block)) {
+            let phis = block
+                .iter(self.ctx)
+                .take_while(|inst| inst.is_phi(self.ctx))
+                .map(|phi| phi.result(self.ctx).unwrap());
+            let values = block.params(self.ctx).iter().copied().chain(phis);
+            for value in values.collect::<Vec<_>>() {
+                let kind = Self::reg_kind(self.ctx, value.ty(self.ctx));
+                let reg = self.mctx.new_vreg(kind).into();
+                self.set_reg(value, reg);
+            }
+        }
+
+        self.curr_block = Some(self.blocks[&func.head(self.ctx).unwrap()]);
+        let params = func.params(self.ctx).to_vec();
+        let locs = Self::arg_locs(self.ctx, params.iter().map(|param| param.ty(self.ctx)));
+        for (param, loc) in params.into_iter().zip(locs) {
+            let ty = param.ty(self.ctx);
+            let reg: Reg = self.mctx.new_vreg(Self::reg_kind(self.ctx, ty)).into();
+            let inst = match loc {
+                ArgLoc::Reg(arg) if arg.kind() == reg.kind() => {
+                    MInst::mv(&mut self.mctx, reg, arg.into())
+                }
+                ArgLoc::Reg(arg) => {
+                    let op = Self::fmv_op(self.ctx, ty, RegKind::Float);
+                    MInst::raw_fpu_rr(&mut self.mctx, op, reg, arg.into())
+                }
+                ArgLoc::Stack(offset) => {
+                    let op = Self::load_op(self.ctx, ty);
+                    let loc = MemLoc::Incoming { offset };
+                    MInst::raw_load(&mut self.mctx, op, reg, loc)
                 }
+            };
+            self.push(inst);
+            self.set_reg(param, reg);
+        }
+
+        for block in domtree.preorder().collect::<Vec<_>>() {
+            self.curr_block = Some(self.blocks[&block]);
+            for inst in block.iter(self.ctx) {
+                self.mctx.set_curr_loc(inst.loc(self.ctx));
+                self.codegen_inst(inst);
             }
         }
+        self.mctx.set_curr_loc(None);
     }
 
-    pub fn regalloc(&mut self) {
-        // This is an extremely simple register allocator, which just assigns
-        // the first available register to each virtual register. It's only for
-        // demonstration.
-        // TODO: You need to implement a real register allocator to replace this.
-        let mut available_regs = vec![
-            regs::t0(),
-            regs::t1(),
-            regs::t2(),
-            regs::t3(),
-            regs::t4(),
-            regs::t5(),
-            regs::t6(),
-        ];
-
-        for function in self.funcs.values() {
-            let mut reg_map: HashMap<Reg, Reg> = HashMap::new();
-
-            // Map virtual registers to physical registers.
-            for block in function.iter(&self.mctx) {
-                for inst in block.iter(&self.mctx) {
-                    match inst.kind(self.mctx()) {
-                        MInstKind::AluRRI { rd, rs, .. } => {
-                            for reg in [rd, rs] {
-                                if reg.is_vreg() && !reg_map.contains_key(reg) {
-                                    let preg = available_regs.pop().unwrap();
-                                    reg_map.insert(*reg, preg.into());
-                                }
-                            }
-                        }
-                        MInstKind::AluRRR { rd, rs1, rs2, .. } => {
-                            for reg in [rd, rs1, rs2] {
-                                if reg.is_vreg() && !reg_map.contains_key(reg) {
-                                    let preg = available_regs.pop().unwrap();
-                                    reg_map.insert(*reg, preg.into());
-                                }
-                            }
-                        }
-                        MInstKind::Load { rd, .. } => {
-                            if rd.is_vreg() && !reg_map.contains_key(rd) {
-                                let preg = available_regs.pop().unwrap();
-                                reg_map.insert(*rd, preg.into());
-                            }
-                        }
-                        MInstKind::Store { rs, .. } => {
-                            if rs.is_vreg() && !reg_map.contains_key(rs) {
-                                let preg = available_regs.pop().unwrap();
-                                reg_map.insert(*rs, preg.into());
-                            }
-                        }
-                        MInstKind::Li { rd, .. } => {
-                            if rd.is_vreg() && !reg_map.contains_key(rd) {
-                                let preg = available_regs.pop().unwrap();
-                                reg_map.insert(*rd, preg.into());
-                            }
-                        }
-                        MInstKind::J { .. } => {} /* XXX: We do not encourage using this naive
-                                                   * register allocator in your work. But if you
-                                                   * really want to use, you may need to handle
-                                                   * other instructions. */
-                    }
+    /// Check that the registers holding integer values are defined by the
+    /// instructions keeping them sign-extended, see
+    /// [`MInstKind::keeps_sext_word`](super::inst::MInstKind::keeps_sext_word).
+    ///
+    /// A value left with garbage in the upper 32 bits would be silently
+    /// observed by the 64-bit comparisons and branches.
+    fn verify_words(&self, mfunc: MFunc) -> Result<(), VerifyError> {
+        let words = self
+            .lowered
+            .values()
+            .filter(|mopd| mopd.ty.is_integer(self.ctx))
+            .filter_map(|mopd| match mopd.kind {
+                MOperandKind::Reg(reg) if reg.is_vreg() => Some(reg),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        for block in mfunc.iter(&self.mctx) {
+            for inst in block.iter(&self.mctx) {
+                let kind = inst.kind(&self.mctx);
+                let word = kind.defs().into_iter().find(|reg| words.contains(reg));
+                if let Some(reg) = word.filter(|_| !kind.keeps_sext_word()) {
+                    return Err(VerifyError {
+                        stage: Stage::Isel,
+                        block: block.label(&self.mctx).to_string(),
+                        inst: inst.display(&self.mctx).to_string(),
+                        problem: Problem::NotSextWord(reg),
+                    });
                 }
             }
+        }
+        Ok(())
+    }
 
-            // Replace virtual registers with physical registers.
-            let mut curr_block = function.head(&self.mctx);
-            while let Some(block) = curr_block {
-                let mut curr_inst = block.head(&self.mctx);
-                while let Some(inst) = curr_inst {
-                    match &mut inst.kind_mut(&mut self.mctx) {
-                        MInstKind::AluRRI { rd, rs, .. } => {
-                            for reg in [rd, rs] {
-                                if let Some(vreg) = reg_map.get(reg) {
-                                    *reg = *vreg;
-                                }
-                            }
-                        }
-                        MInstKind::AluRRR { rd, rs1, rs2, .. } => {
-                            for reg in [rd, rs1, rs2] {
-                                if let Some(vreg) = reg_map.get(reg) {
-                                    *reg = *vreg;
-                                }
-                            }
-                        }
-                        MInstKind::Load { rd, .. } => {
-                            if let Some(vreg) = reg_map.get(rd) {
-                                *rd = *vreg;
-                            }
-                        }
-                        MInstKind::Store { rs, .. } => {
-                            if let Some(vreg) = reg_map.get(rs) {
-                                *rs = *vreg;
-                            }
-                        }
-                        MInstKind::Li { rd, .. } => {
-                            if let Some(vreg) = reg_map.get(rd) {
-                                *rd = *vreg;
-                            }
+    /// Generate the code of an instruction, appending to the current block.
+    fn codegen_inst(&mut self, inst: ir::Inst) {
+        let result = inst.result(self.ctx);
+        match inst.kind(self.ctx) {
+            ir::InstKind::Alloca { ty } => {
+                let size = ty.bytewidth(self.ctx) as u64;
+                let align = ty.align(self.ctx) as u64;
+                let mfunc = self.curr_func.unwrap();
+                let offset = mfunc.alloc_storage_slot(&mut self.mctx, size, align);
+                let mopd = MOperand {
+                    ty: result.unwrap().ty(self.ctx),
+                    kind: MOperandKind::Mem(MemLoc::Slot { offset }),
+                };
+                self.lowered.insert(result.unwrap(), mopd);
+            }
+            ir::InstKind::Phi => {}
+            ir::InstKind::Load => {
+                let loc = self.mem_loc(inst.operand(self.ctx, 0));
+                let ty = result.unwrap().ty(self.ctx);
+                let mopd = self.gen_load(ty, loc);
+                self.lowered.insert(result.unwrap(), mopd);
+            }
+            ir::InstKind::Store => {
+                let val = inst.operand(self.ctx, 0);
+                let loc = self.mem_loc(inst.operand(self.ctx, 1));
+                self.gen_store(val, loc);
+            }
+            ir::InstKind::GetElementPtr { bound_ty } => {
+                let loc = self.gen_gep(*bound_ty, inst);
+                let mopd = MOperand {
+                    ty: result.unwrap().ty(self.ctx),
+                    kind: MOperandKind::Mem(loc),
+                };
+                self.lowered.insert(result.unwrap(), mopd);
+            }
+            ir::InstKind::Call if self.is_tail_call(inst) => self.gen_tail_call(inst),
+            ir::InstKind::Call => self.gen_call(inst),
+            ir::InstKind::Br => {
+                let target = self.edge_target(inst, 0);
+                let inst = MInst::j(&mut self.mctx, target);
+                self.push(inst);
+            }
+            ir::InstKind::CondBr => {
+                let cond = inst.operand(self.ctx, 0);
+                let (op, rs1, rs2) = match self.fused_cmp(cond) {
+                    Some((cmp, lhs, rhs)) => {
+                        let lhs = self.value_reg(lhs);
+                        let rhs = self.value_reg(rhs);
+                        match cmp {
+                            IntCmpCond::Eq => (BranchOp::Beq, lhs, rhs),
+                            IntCmpCond::Ne => (BranchOp::Bne, lhs, rhs),
+                            IntCmpCond::Slt => (BranchOp::Blt, lhs, rhs),
+                            IntCmpCond::Sge => (BranchOp::Bge, lhs, rhs),
+                            IntCmpCond::Sgt => (BranchOp::Blt, rhs, lhs),
+                            IntCmpCond::Sle => (BranchOp::Bge, rhs, lhs),
                         }
-                        MInstKind::J { .. } => {} /* XXX: We do not encourage using this naive
-                                                   * register allocator in your work. But if you
-                                                   * really want to use, you may need to handle
-                                                   * other instructions. */
                     }
-                    curr_inst = inst.next(&self.mctx);
+                    None => (BranchOp::Bne, self.value_reg(cond), regs::zero().into()),
+                };
+                let then_target = self.edge_target(inst, 0);
+                let else_target = self.edge_target(inst, 1);
+                let branch = MInst::branch(&mut self.mctx, op, rs1, rs2, then_target);
+                self.push(branch);
+                let inst = MInst::j(&mut self.mctx, else_target);
+                self.push(inst);
+            }
+            ir::InstKind::Switch { cases } => self.gen_switch(inst, cases),
+            ir::InstKind::Ret
+                if inst
+                    .prev(self.ctx)
+                    .is_some_and(|prev| self.is_tail_call(prev)) =>
+            {
+                // Lowered along with the tail call.
+            }
+            ir::InstKind::Ret => {
+                if inst.operand_iter(self.ctx).count() == 1 {
+                    let val = inst.operand(self.ctx, 0);
+                    let ret_reg = self.gen_ret_move(val);
+                    let inst = MInst::ret(&mut self.mctx, Some(ret_reg));
+                    self.push(inst);
+                } else {
+                    let inst = MInst::ret(&mut self.mctx, None);
+                    self.push(inst);
                 }
-                curr_block = block.next(&self.mctx);
+            }
+            ir::InstKind::IntBinary { .. } if self.fused_cmp(result.unwrap()).is_some() => {
+                // Lowered along with the branch.
+            }
+            ir::InstKind::IntBinary { op } => {
+                let lhs = inst.operand(self.ctx, 0);
+                let rhs = inst.operand(self.ctx, 1);
+                let reg = self.gen_int_binary(*op, lhs, rhs);
+                self.set_reg(result.unwrap(), reg);
+            }
+            ir::InstKind::FloatBinary { op } => {
+                let lhs = inst.operand(self.ctx, 0);
+                let rhs = inst.operand(self.ctx, 1);
+                let reg = self.gen_float_binary(*op, lhs, rhs);
+                self.set_reg(result.unwrap(), reg);
+            }
+            ir::InstKind::Cast { op } => {
+                let val = inst.operand(self.ctx, 0);
+                let reg = self.gen_cast(*op, val, result.unwrap().ty(self.ctx));
+                self.set_reg(result.unwrap(), reg);
             }
         }
     }
 
-    /// Do the code generation after register allocation.
-    pub fn after_regalloc(&mut self) {
-        // TODO: The stack frame is determined after register allocation, so
-        // we need to add instructions to adjust the stack frame.
-        //
-        // There should be two stages:
-        //  1. Prologue: Save the callee-saved registers and adjust the stack frame.
-        //  2. Epilogue: Restore the callee-saved registers and handle return.
-        //
-        // Depending on your implementation, you may need to adjust stack slots
-        // offsets after these two stages.
-        todo!("after register allocation");
-    }
-
-    /// Emit the assembly code.
+    /// Get the comparison to fuse into the conditional branch using `cond`.
     ///
-    /// It's not necessary to implement this function, you can also handle the
-    /// emission directly in `main.rs`.
-    pub fn emit(&mut self) {
-        // TODO: Emit the assembly code.
+    /// The comparison is fused only if the branch is its only user and is in
+    /// the same block, so the 0/1 value never needs to be materialized.
+    fn fused_cmp(&self, cond: Value) -> Option<(IntCmpCond, Value, Value)> {
+        let cmp = cond.def_inst(self.ctx)?;
+        let ir::InstKind::IntBinary {
+            op: IntBinaryOp::ICmp { cond: cmp_cond },
+        } = *cmp.kind(self.ctx)
+        else {
+            return None;
+        };
+        let mut users = cond.users(self.ctx).into_iter();
+        let user = users.next()?.inst();
+        if users.next().is_some()
+            || !matches!(user.kind(self.ctx), ir::InstKind::CondBr)
+            || user.container(self.ctx) != cmp.container(self.ctx)
+        {
+            return None;
+        }
+        Some((cmp_cond, cmp.operand(self.ctx, 0), cmp.operand(self.ctx, 1)))
     }
 
-    /// Generate a store instruction and append it to the current block.
-    /// Here we just demonstrate the idea. Bugs may exist. You can
-    /// refactor this entirely as your own way.
-    pub fn gen_store(&mut self, val: Value, mem_loc: MemLoc) {
+    /// Append an instruction to the current block.
+    fn push(&mut self, inst: MInst) {
         let curr_block = self.curr_block.unwrap();
+        curr_block.push_back(&mut self.mctx, inst).unwrap();
+    }
 
-        // XXX: You might want to encapsulate this check into a function, since it'll be
-        // used multiple times.
-        let src = match &val.try_deref(self.ctx).unwrap().kind {
-            ir::ValueKind::Constant { value } => {
-                // XXX: You also might want to encapsulate this check into a function.
-                match value {
-                    ir::ConstantValue::Int32 { value, .. } => {
-                        if value == &0 {
-                            // Use zero register for zero value.
-                            regs::zero().into()
-                        } else {
-                            // Sign-extend the 32-bit value to 64-bit
-                            let (li, r) = MInst::li(&mut self.mctx, *value as u64);
-                            curr_block.push_back(&mut self.mctx, li).unwrap();
-                            r
-                        }
-                    }
-                    ConstantValue::Undef { .. } => {
-                        // We treat undef as zero temporarily.
-                        regs::zero().into()
-                    }
-                    _ => todo!(),
-                }
-            }
-            ir::ValueKind::InstResult { .. } => {
-                // XXX: You also might want to encapsulate this check into a function.
-                let mopd = self.lowered[&val];
-                match mopd.kind {
-                    MOperandKind::Reg(reg) => reg,
-                    _ => todo!(),
+    /// Append instructions to the current block.
+    fn push_all(&mut self, insts: Vec<MInst>) {
+        let curr_block = self.curr_block.unwrap();
+        curr_block.extend(&mut self.mctx, insts).unwrap();
+    }
+
+    /// Record the register holding a value.
+    fn set_reg(&mut self, value: Value, reg: Reg) {
+        let mopd = MOperand {
+            ty: value.ty(self.ctx),
+            kind: MOperandKind::Reg(reg),
+        };
+        self.lowered.insert(value, mopd);
+    }
+
+    /// Get a register holding a value, materializing constants, addresses of
+    /// globals and stack slots in the current block.
+    fn value_reg(&mut self, value: Value) -> Reg {
+        let ty = value.ty(self.ctx);
+        if let Some(constant) = value.as_const(self.ctx).filter(|_| ty.is_float(self.ctx)) {
+            let bits = match constant {
+                ConstantValue::Float32 { bits, .. } => *bits as i32 as i64,
+                _ => 0,
+            };
+            let bits = self.imm_reg(bits);
+            let op = Self::fmv_op(self.ctx, ty, RegKind::Float);
+            return self.fpu_rr(op, bits);
+        }
+        if let Some(constant) = value.as_const(self.ctx) {
+            let imm = match constant {
+                ConstantValue::Int1 { value, .. } => *value as i64,
+                ConstantValue::Int8 { value, .. } => *value as i64,
+                ConstantValue::Int32 { value, .. } => *value as i64,
+                ConstantValue::Undef { .. } | ConstantValue::AggregateZero { .. } => 0,
+                ConstantValue::GlobalRef { name, .. } => {
+                    let (insts, rd) = MInst::load_symbol(&mut self.mctx, MLabel::from(name));
+                    self.push_all(insts);
+                    return rd;
                 }
+                ConstantValue::Array { .. } => panic!("array constants are not first-class values"),
+                ConstantValue::Float32 { .. } => unreachable!(),
+            };
+            return self.imm_reg(imm);
+        }
+        match self.lowered[&value].kind {
+            MOperandKind::Reg(reg) => reg,
+            MOperandKind::Mem(loc) => {
+                let (inst, rd) = MInst::load_addr(&mut self.mctx, loc);
+                self.push(inst);
+                rd
             }
-            ir::ValueKind::Param { .. } => {
-                // TODO: Handle parameters.
-                todo!()
-            }
-        };
+            MOperandKind::Imm(reg, _) => reg,
+            MOperandKind::Undef => regs::zero().into(),
+        }
+    }
+
+    /// Get a register holding an immediate.
+    fn imm_reg(&mut self, imm: i64) -> Reg {
+        if imm == 0 {
+            return regs::zero().into();
+        }
+        let (insts, rd) = MInst::load_imm(&mut self.mctx, imm);
+        self.push_all(insts);
+        rd
+    }
+
+    /// Get the memory location a pointer points to.
+    fn mem_loc(&mut self, ptr: Value) -> MemLoc {
+        if let Some(MOperand {
+            kind: MOperandKind::Mem(loc),
+            ..
+        }) = self.lowered.get(&ptr)
+        {
+            return *loc;
+        }
+        let base = self.value_reg(ptr);
+        MemLoc::RegOffset { base, offset: 0 }
+    }
+
+    /// Get the kind of the registers holding the values of a type.
+    fn reg_kind(ctx: &ir::Context, ty: Ty) -> RegKind {
+        if ty.is_float(ctx) {
+            RegKind::Float
+        } else {
+            RegKind::General
+        }
+    }
 
-        let bitwidth = val.ty(self.ctx).bitwidth(self.ctx);
-        // Different store instructions for different bitwidths.
-        let op = match bitwidth {
-            8 => StoreOp::Sb,
-            16 => StoreOp::Sh,
-            32 => StoreOp::Sw,
-            64 => StoreOp::Sd,
+    /// Get the move of the bits of a floating-point type into a register of
+    /// `kind` from one of the other kind.
+    fn fmv_op(ctx: &ir::Context, ty: Ty, kind: RegKind) -> FpuOpRR {
+        match (ty.bitwidth(ctx), kind) {
+            (32, RegKind::Float) => FpuOpRR::FmvWX,
+            (32, RegKind::General) => FpuOpRR::FmvXW,
+            (_, RegKind::Float) => FpuOpRR::FmvDX,
+            (_, RegKind::General) => FpuOpRR::FmvXD,
+        }
+    }
+
+    /// Get the load instruction for a type.
+    fn load_op(ctx: &ir::Context, ty: Ty) -> LoadOp {
+        if ty.is_float(ctx) {
+            return match ty.bytewidth(ctx) {
+                4 => LoadOp::Flw,
+                _ => LoadOp::Fld,
+            };
+        }
+        match ty.bytewidth(ctx) {
+            1 => LoadOp::Lb,
+            2 => LoadOp::Lh,
+            4 => LoadOp::Lw,
+            8 => LoadOp::Ld,
             _ => unreachable!(),
+        }
+    }
+
+    /// Get the store instruction for a type.
+    fn store_op(ctx: &ir::Context, ty: Ty) -> StoreOp {
+        if ty.is_float(ctx) {
+            return match ty.bytewidth(ctx) {
+                4 => StoreOp::Fsw,
+                _ => StoreOp::Fsd,
+            };
+        }
+        match ty.bytewidth(ctx) {
+            1 => StoreOp::Sb,
+            2 => StoreOp::Sh,
+            4 => StoreOp::Sw,
+            8 => StoreOp::Sd,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Append an ALU instruction with an immediate to the current block.
+    ///
+    /// If the immediate does not fit in 12 bits, it is loaded into a register
+    /// first, and the operation on two registers is used instead.
+    fn alu_rri(&mut self, op: AluOpRRI, rs: Reg, imm: i64) -> Reg {
+        let Some(imm12) = Imm12::try_from_i64(imm) else {
+            let rrr_op = op.as_rrr().expect("shift amount out of range");
+            let imm = self.imm_reg(imm);
+            return self.alu_rrr(rrr_op, rs, imm);
         };
+        let (inst, rd) = MInst::alu_rri(&mut self.mctx, op, rs, imm12);
+        self.push(inst);
+        rd
+    }
 
-        // s[bhwd] src, loc
-        let store = MInst::store(&mut self.mctx, op, src, mem_loc);
-        // Append the store instruction to the current block.
-        curr_block.push_back(&mut self.mctx, store).unwrap();
+    /// Append an ALU instruction with two registers to the current block.
+    fn alu_rrr(&mut self, op: AluOpRRR, rs1: Reg, rs2: Reg) -> Reg {
+        let (inst, rd) = MInst::alu_rrr(&mut self.mctx, op, rs1, rs2);
+        self.push(inst);
+        rd
     }
 
-    /// Generate a load instruction and append it to the current block.
-    /// /// Here we just demonstrate the idea. Bugs may exist. You can
-    /// refactor this entirely as your own way.
-    pub fn gen_load(&mut self, ty: Ty, mem_loc: MemLoc) -> MOperand {
+    /// Append an ALU instruction with one register to the current block.
+    fn alu_rr(&mut self, op: AluOpRR, rs: Reg) -> Reg {
+        let (inst, rd) = MInst::alu_rr(&mut self.mctx, op, rs);
+        self.push(inst);
+        rd
+    }
+
+    /// Append a floating-point instruction with two registers to the current
+    /// block.
+    fn fpu_rrr(&mut self, op: FpuOpRRR, rs1: Reg, rs2: Reg) -> Reg {
+        let (inst, rd) = MInst::fpu_rrr(&mut self.mctx, op, rs1, rs2);
+        self.push(inst);
+        rd
+    }
+
+    /// Append a floating-point conversion or move to the current block.
+    fn fpu_rr(&mut self, op: FpuOpRR, rs: Reg) -> Reg {
+        let (inst, rd) = MInst::fpu_rr(&mut self.mctx, op, rs);
+        self.push(inst);
+        rd
+    }
+
+    /// Append a copy of a register to the current block.
+    fn copy(&mut self, rs: Reg) -> Reg {
+        let rd = self.mctx.new_vreg(rs.kind()).into();
+        let inst = MInst::mv(&mut self.mctx, rd, rs);
+        self.push(inst);
+        rd
+    }
+
+    /// Get the block to jump to for a successor of a branch.
+    ///
+    /// The copies to the phis and the block parameters of the successor are
+    /// generated in the current block if it is the only predecessor edge out
+    /// of it, or in a new block on the edge otherwise.
+    fn edge_target(&mut self, branch: ir::Inst, succ_idx: usize) -> MBlock {
+        let block = branch.container(self.ctx).unwrap();
+        let succ = branch.successor(self.ctx, succ_idx);
+        let mut copies = succ
+            .params(self.ctx)
+            .iter()
+            .copied()
+            .zip(branch.successor_args(self.ctx, succ_idx))
+            .collect::<Vec<_>>();
+        copies.extend(
+            succ.iter(self.ctx)
+                .take_while(|inst| inst.is_phi(self.ctx))
+                .map(|phi| (phi.result(self.ctx).unwrap(), phi.incoming(self.ctx, block))),
+        );
+        let target = self.blocks[&succ];
+        if copies.is_empty() {
+            return target;
+        }
+
         let curr_block = self.curr_block.unwrap();
+        let edge_block = if matches!(branch.kind(self.ctx), ir::InstKind::Br) {
+            curr_block
+        } else {
+            let label = self.new_label();
+            let edge_block = MBlock::new(&mut self.mctx, label);
+            curr_block.insert_after(&mut self.mctx, edge_block).unwrap();
+            edge_block
+        };
 
-        let bitwidth = ty.bitwidth(self.ctx);
-        let op = match bitwidth {
-            8 => LoadOp::Lb,
-            16 => LoadOp::Lh,
-            32 => LoadOp::Lw,
-            64 => LoadOp::Ld,
-            _ => unreachable!(),
+        // The copies are parallel, so all the sources are read first.
+        self.curr_block = Some(edge_block);
+        let temps = copies
+            .iter()
+            .map(|&(_, src)| {
+                let src = self.value_reg(src);
+                self.copy(src)
+            })
+            .collect::<Vec<_>>();
+        for (&(dst, _), temp) in copies.iter().zip(temps) {
+            let MOperandKind::Reg(dst) = self.lowered[&dst].kind else {
+                unreachable!()
+            };
+            let inst = MInst::mv(&mut self.mctx, dst, temp);
+            self.push(inst);
+        }
+        if edge_block != curr_block {
+            let inst = MInst::j(&mut self.mctx, target);
+            self.push(inst);
+        }
+        self.curr_block = Some(curr_block);
+
+        if edge_block == curr_block {
+            target
+        } else {
+            edge_block
+        }
+    }
+
+    /// Generate a switch, as a jump table if the cases are dense enough, or as
+    /// a chain of comparisons otherwise.
+    ///
+    /// The jump table is indexed by the value minus the smallest case, after
+    /// an unsigned bounds check jumping to the default destination, which also
+    /// fills the holes of the table.
+    fn gen_switch(&mut self, switch: ir::Inst, cases: &[i32]) {
+        /// The minimum number of cases to use a jump table.
+        const JUMP_TABLE_MIN_CASES: usize = 4;
+        /// The minimum percentage of the table entries that are cases.
+        const JUMP_TABLE_MIN_DENSITY: i64 = 40;
+
+        let x = self.value_reg(switch.operand(self.ctx, 0));
+        let default = self.edge_target(switch, 0);
+        let mut targets = cases
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| (value as i64, self.edge_target(switch, i + 1)))
+            .collect::<Vec<_>>();
+        targets.sort_by_key(|&(value, _)| value);
+
+        let (Some(&(min, _)), Some(&(max, _))) = (targets.first(), targets.last()) else {
+            let inst = MInst::j(&mut self.mctx, default);
+            self.push(inst);
+            return;
         };
+        let range = max - min + 1;
+        if targets.len() < JUMP_TABLE_MIN_CASES
+            || (targets.len() as i64) * 100 < range * JUMP_TABLE_MIN_DENSITY
+        {
+            for (value, target) in targets {
+                let rs = self.imm_reg(value);
+                let inst = MInst::branch(&mut self.mctx, BranchOp::Beq, x, rs, target);
+                self.push(inst);
+            }
+            let inst = MInst::j(&mut self.mctx, default);
+            self.push(inst);
+            return;
+        }
 
-        // l[bhwd] rd, loc
-        let (load, rd) = MInst::load(&mut self.mctx, op, mem_loc);
-        curr_block.push_back(&mut self.mctx, load).unwrap();
+        let mut entries = vec![default; range as usize];
+        for &(value, target) in targets.iter() {
+            entries[(value - min) as usize] = target;
+        }
+        let label = self.new_label();
+        let xlen = self.xlen();
+        let items = entries
+            .iter()
+            .map(|entry| {
+                let label = entry.label(&self.mctx).clone();
+                match xlen {
+                    32 => DataItem::Symbol32(label),
+                    _ => DataItem::Symbol(label),
+                }
+            })
+            .collect();
+        let entry_size = xlen as usize / 8;
+        self.mctx
+            .add_raw_data(label.clone(), RawData::ReadOnly(items), entry_size);
+
+        // The subtraction is exact on RV64, so the values below the smallest
+        // case wrap around to the large unsigned indices. On RV32, it wraps
+        // around within the range of `i32` instead, which is just as good.
+        let idx = self.alu_rri(AluOpRRI::Addi, x, -min);
+        let bound = self.imm_reg(range);
+        let inst = MInst::branch(&mut self.mctx, BranchOp::Bgeu, idx, bound, default);
+        self.push(inst);
+        let offset = self.alu_rri(AluOpRRI::Slli, idx, entry_size.trailing_zeros() as i64);
+        let (insts, table) = MInst::load_symbol(&mut self.mctx, label);
+        self.push_all(insts);
+        let base = self.alu_rrr(AluOpRRR::Add, table, offset);
+        let loc = MemLoc::RegOffset { base, offset: 0 };
+        let (inst, addr) = MInst::load(&mut self.mctx, LoadOp::xreg(xlen), loc);
+        self.push(inst);
+        entries.sort_by_key(|entry| entry.label(&self.mctx).to_string());
+        entries.dedup();
+        let inst = MInst::jr(&mut self.mctx, addr, entries);
+        self.push(inst);
+    }
+
+    /// Generate a store instruction and append it to the current block.
+    pub fn gen_store(&mut self, val: Value, mem_loc: MemLoc) {
+        let src = self.value_reg(val);
+        let op = Self::store_op(self.ctx, val.ty(self.ctx));
+        let inst = MInst::store(&mut self.mctx, op, src, mem_loc);
+        self.push(inst);
+    }
 
+    /// Generate a load instruction and append it to the current block.
+    pub fn gen_load(&mut self, ty: Ty, mem_loc: MemLoc) -> MOperand {
+        let op = Self::load_op(self.ctx, ty);
+        let (load, rd) = MInst::load(&mut self.mctx, op, mem_loc);
+        self.push(load);
         MOperand {
             ty,
             kind: MOperandKind::Reg(rd),
         }
     }
 
-    /// Generate an integer binary operation and append it to the current block.
-    /// Here we just demonstrate the idea. Bugs may exist. You can
-    /// refactor this entirely as your own way.
-    pub fn gen_int_binary(&mut self, op: IntBinaryOp, lhs: Value, rhs: Value) -> MOperand {
-        let curr_block = self.curr_block.unwrap();
+    /// Generate the address computation of a `getelementptr`.
+    ///
+    /// The constant part of the offset is folded into the returned location,
+    /// so it ends up in the displacement of the loads and stores using it,
+    /// and only the indices in registers need explicit arithmetic.
+    fn gen_gep(&mut self, bound_ty: Ty, gep: ir::Inst) -> MemLoc {
+        let mut ty = bound_ty;
+        let mut offset = 0i64;
+        // The sum of the indices in registers, with the shift of the last one
+        // pending, to fold it into a `shNadd`.
+        let mut index: Option<(Reg, u32)> = None;
+        for (i, idx) in gep.operand_iter(self.ctx).skip(1).enumerate() {
+            if i > 0 {
+                ty = ty.as_array(self.ctx).unwrap().0;
+            }
+            let size = ty.bytewidth(self.ctx) as i64;
+            if let Some(idx) = idx.as_const_int(self.ctx) {
+                offset += idx as i64 * size;
+                continue;
+            }
+            let idx = self.value_reg(idx);
+            let scaled = if size.count_ones() == 1 {
+                (idx, size.trailing_zeros())
+            } else {
+                let size = self.imm_reg(size);
+                (self.alu_rrr(AluOpRRR::Mul, idx, size), 0)
+            };
+            index = Some(match index {
+                Some((idx, shamt)) => {
+                    let scaled = self.shl(scaled.0, scaled.1);
+                    (self.add_scaled(scaled, idx, shamt), 0)
+                }
+                None => scaled,
+            });
+        }
+
+        let loc = self.mem_loc(gep.operand(self.ctx, 0));
+        let loc = match (loc, index) {
+            (loc, None) => loc,
+            (MemLoc::RegOffset { base, offset }, Some((index, shamt))) => MemLoc::RegOffset {
+                base: self.add_scaled(base, index, shamt),
+                offset,
+            },
+            (loc, Some((index, shamt))) => {
+                let (inst, base) = MInst::load_addr(&mut self.mctx, loc);
+                self.push(inst);
+                MemLoc::RegOffset {
+                    base: self.add_scaled(base, index, shamt),
+                    offset: 0,
+                }
+            }
+        };
+        self.offset_loc(loc, offset)
+    }
+
+    /// Shift a register left by a constant amount.
+    fn shl(&mut self, rs: Reg, shamt: u32) -> Reg {
+        match shamt {
+            0 => rs,
+            _ => self.alu_rri(AluOpRRI::Slli, rs, shamt as i64),
+        }
+    }
 
-        // TODO: We only handle reg + reg here, you need to handle other cases.
-        let lhs_reg = match &lhs.try_deref(self.ctx).unwrap().kind {
-            ir::ValueKind::Constant { .. } => {
-                todo!()
+    /// Add a register shifted left by `shamt` to a base, in one `shNadd` if
+    /// the target has Zba and the shift is at most 3.
+    fn add_scaled(&mut self, base: Reg, index: Reg, shamt: u32) -> Reg {
+        let shadd = match shamt {
+            1 => Some(AluOpRRR::Sh1add),
+            2 => Some(AluOpRRR::Sh2add),
+            3 => Some(AluOpRRR::Sh3add),
+            _ => None,
+        };
+        match shadd.filter(|_| self.features.zba) {
+            Some(op) => self.alu_rrr(op, index, base),
+            None => {
+                let index = self.shl(index, shamt);
+                self.alu_rrr(AluOpRRR::Add, base, index)
             }
-            ir::ValueKind::InstResult { .. } => {
-                let mopd = self.lowered[&lhs];
-                match mopd.kind {
-                    MOperandKind::Reg(reg) => reg,
-                    _ => todo!(),
+        }
+    }
+
+    /// Add a constant offset to a memory location.
+    ///
+    /// The stack slots are resolved with the frame layout, but the offset from
+    /// a register must fit in the 12-bit displacement, otherwise it is added
+    /// to the base register first.
+    fn offset_loc(&mut self, loc: MemLoc, offset: i64) -> MemLoc {
+        match loc {
+            MemLoc::RegOffset { base, offset: off } => {
+                let offset = off + offset;
+                if Imm12::try_from_i64(offset).is_some() {
+                    MemLoc::RegOffset { base, offset }
+                } else {
+                    MemLoc::RegOffset {
+                        base: self.alu_rri(AluOpRRI::Addi, base, offset),
+                        offset: 0,
+                    }
                 }
             }
-            ir::ValueKind::Param { .. } => {
-                todo!()
+            MemLoc::Slot { offset: off } => MemLoc::Slot {
+                offset: off + offset,
+            },
+            MemLoc::Incoming { offset: off } => MemLoc::Incoming {
+                offset: off + offset,
+            },
+        }
+    }
+
+    /// Assign the arguments of the types to the registers and the stack.
+    ///
+    /// By the LP64D calling convention, the floating-point arguments go to
+    /// `fa0-7`, and once they run out, to the remaining integer registers
+    /// `a0-7` like the integer arguments. The rest go to the stack, in 8-byte
+    /// slots, or 4-byte ones by the ILP32D calling convention on RV32.
+    fn arg_locs(ctx: &ir::Context, tys: impl IntoIterator<Item = Ty>) -> Vec<ArgLoc> {
+        let mut int_regs = regs::ARG_REGS.into_iter();
+        let mut float_regs = regs::FLOAT_ARG_REGS.into_iter();
+        let slot_size = ctx.target_info().ptr_size as i64;
+        let mut offset = 0;
+        tys.into_iter()
+            .map(|ty| {
+                let reg = match Self::reg_kind(ctx, ty) {
+                    RegKind::Float => float_regs.next().or_else(|| int_regs.next()),
+                    RegKind::General => int_regs.next(),
+                };
+                reg.map(ArgLoc::Reg).unwrap_or_else(|| {
+                    offset += slot_size;
+                    ArgLoc::Stack(offset - slot_size)
+                })
+            })
+            .collect()
+    }
+
+    /// Generate a call, with the arguments passed by the calling convention.
+    fn gen_call(&mut self, call: ir::Inst) {
+        let args = call.operand_iter(self.ctx).skip(1).collect::<Vec<_>>();
+        let locs = Self::arg_locs(self.ctx, args.iter().map(|arg| arg.ty(self.ctx)));
+
+        // The arguments on the stack are stored first, so the argument
+        // registers are not clobbered by materializing the values.
+        let mut outgoing = 0;
+        for (&arg, &loc) in args.iter().zip(locs.iter()) {
+            let ArgLoc::Stack(offset) = loc else {
+                continue;
+            };
+            let src = self.value_reg(arg);
+            let op = match src.kind() {
+                RegKind::General => StoreOp::xreg(self.xlen()),
+                RegKind::Float => Self::store_op(self.ctx, arg.ty(self.ctx)),
+            };
+            let loc = MemLoc::RegOffset {
+                base: regs::sp().into(),
+                offset,
+            };
+            let inst = MInst::store(&mut self.mctx, op, src, loc);
+            self.push(inst);
+            outgoing = (offset + self.ctx.target_info().ptr_size as i64) as u64;
+        }
+        let mfunc = self.curr_func.unwrap();
+        mfunc.update_outgoing_stack_size(&mut self.mctx, outgoing);
+
+        let arg_regs = self.gen_arg_moves(&args, &locs);
+        let callee = MLabel::from(call.callee(self.ctx));
+        let inst = MInst::call(&mut self.mctx, callee, arg_regs);
+        self.push(inst);
+
+        if let Some(result) = call.result(self.ctx) {
+            let ret_reg = Self::ret_reg(self.ctx, result.ty(self.ctx));
+            let reg = self.copy(ret_reg.into());
+            self.set_reg(result, reg);
+        }
+    }
+
+    /// Move the arguments passed in registers into the argument registers.
+    ///
+    /// The values are materialized before any of the moves, so no argument
+    /// register is clobbered early.
+    ///
+    /// Returns the argument registers used.
+    fn gen_arg_moves(&mut self, args: &[Value], locs: &[ArgLoc]) -> Vec<PReg> {
+        let srcs = args
+            .iter()
+            .zip(locs.iter())
+            .filter_map(|(&arg, &loc)| match loc {
+                ArgLoc::Reg(arg_reg) => Some((arg, arg_reg, self.value_reg(arg))),
+                ArgLoc::Stack(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let mut arg_regs = Vec::new();
+        for (arg, arg_reg, src) in srcs {
+            let inst = if arg_reg.kind() == src.kind() {
+                MInst::mv(&mut self.mctx, arg_reg.into(), src)
+            } else {
+                let op = Self::fmv_op(self.ctx, arg.ty(self.ctx), RegKind::General);
+                MInst::raw_fpu_rr(&mut self.mctx, op, arg_reg.into(), src)
+            };
+            self.push(inst);
+            arg_regs.push(arg_reg);
+        }
+        arg_regs
+    }
+
+    /// Check if a call can be lowered into a tail call.
+    ///
+    /// The call must be immediately followed by a return of its result, and
+    /// pass all the arguments in registers, since the outgoing argument area
+    /// is gone with the frame of the caller. The caller must not have any
+    /// allocas either, whose addresses may be passed to the callee.
+    fn is_tail_call(&self, call: ir::Inst) -> bool {
+        if !matches!(call.kind(self.ctx), ir::InstKind::Call) {
+            return false;
+        }
+        let Some(ret) = call.next(self.ctx) else {
+            return false;
+        };
+        if !matches!(ret.kind(self.ctx), ir::InstKind::Ret) {
+            return false;
+        }
+        let returns_result = match call.result(self.ctx) {
+            Some(result) => {
+                ret.operand_iter(self.ctx).count() == 1 && ret.operand(self.ctx, 0) == result
             }
+            None => ret.operand_iter(self.ctx).count() == 0,
         };
+        if !returns_result {
+            return false;
+        }
+        let args = call.operand_iter(self.ctx).skip(1);
+        let locs = Self::arg_locs(self.ctx, args.map(|arg| arg.ty(self.ctx)));
+        if locs.iter().any(|loc| matches!(loc, ArgLoc::Stack(_))) {
+            return false;
+        }
+        let func = call
+            .container(self.ctx)
+            .unwrap()
+            .container(self.ctx)
+            .unwrap();
+        func.iter(self.ctx).all(|block| {
+            block
+                .iter(self.ctx)
+                .all(|inst| !matches!(inst.kind(self.ctx), ir::InstKind::Alloca { .. }))
+        })
+    }
+
+    /// Generate a tail call, jumping to the callee after the epilogue.
+    ///
+    /// The arguments are all in registers, see
+    /// [`is_tail_call`](Self::is_tail_call), and the callee returns to the
+    /// caller of the current function directly.
+    fn gen_tail_call(&mut self, call: ir::Inst) {
+        let args = call.operand_iter(self.ctx).skip(1).collect::<Vec<_>>();
+        let locs = Self::arg_locs(self.ctx, args.iter().map(|arg| arg.ty(self.ctx)));
+        let arg_regs = self.gen_arg_moves(&args, &locs);
+        let callee = MLabel::from(call.callee(self.ctx));
+        let inst = MInst::tail_call(&mut self.mctx, callee, arg_regs);
+        self.push(inst);
+    }
+
+    /// Get the register holding the return value of a type.
+    fn ret_reg(ctx: &ir::Context, ty: Ty) -> PReg {
+        match Self::reg_kind(ctx, ty) {
+            RegKind::General => regs::a0(),
+            RegKind::Float => regs::fa0(),
+        }
+    }
+
+    /// Sign-extend the low `bits` bits of a register, to keep the narrow
+    /// values in the canonical form.
+    fn sext(&mut self, rs: Reg, bits: usize) -> Reg {
+        let sext_op = match bits {
+            8 => Some(AluOpRR::SextB),
+            16 => Some(AluOpRR::SextH),
+            _ => None,
+        };
+        if let Some(op) = sext_op.filter(|_| self.features.zbb) {
+            return self.alu_rr(op, rs);
+        }
+        let shamt = self.xlen() as i64 - bits as i64;
+        let rd = self.alu_rri(AluOpRRI::Slli, rs, shamt);
+        self.alu_rri(AluOpRRI::Srai, rd, shamt)
+    }
+
+    /// Normalize the result of an operation on a type of `bits` bits.
+    fn normalize(&mut self, rs: Reg, bits: usize) -> Reg {
+        match bits {
+            1 => self.alu_rri(AluOpRRI::Andi, rs, 1),
+            8 => self.sext(rs, 8),
+            _ => rs,
+        }
+    }
+
+    /// Generate an integer binary operation and append it to the current
+    /// block.
+    pub fn gen_int_binary(&mut self, op: IntBinaryOp, lhs: Value, rhs: Value) -> Reg {
+        let ty = lhs.ty(self.ctx);
+        let bits = ty.bitwidth(self.ctx);
+        let word = bits <= 32;
+        let rhs_const = rhs.as_const_int(self.ctx).map(|c| c as i64);
+        let mut lhs_reg = self.value_reg(lhs);
 
-        let rhs_reg = match &rhs.try_deref(self.ctx).unwrap().kind {
-            ir::ValueKind::Constant { .. } => {
-                todo!()
+        if let Some(d) = rhs_const.filter(|_| bits == 32) {
+            if let Some(rd) = self.gen_div_by_const(op, lhs_reg, d as i32) {
+                return rd;
             }
-            ir::ValueKind::InstResult { .. } => {
-                let mopd = self.lowered[&rhs];
-                match mopd.kind {
-                    MOperandKind::Reg(reg) => reg,
-                    _ => todo!(),
-                }
+        }
+
+        // Operations with an immediate.
+        let imm_op = match (op, rhs_const) {
+            (IntBinaryOp::Add, Some(c)) => Some((
+                if word {
+                    AluOpRRI::Addiw
+                } else {
+                    AluOpRRI::Addi
+                },
+                c,
+            )),
+            (IntBinaryOp::Sub, Some(c)) => Some((
+                if word {
+                    AluOpRRI::Addiw
+                } else {
+                    AluOpRRI::Addi
+                },
+                -c,
+            )),
+            (IntBinaryOp::And, Some(c)) => Some((AluOpRRI::Andi, c)),
+            (IntBinaryOp::Or, Some(c)) => Some((AluOpRRI::Ori, c)),
+            (IntBinaryOp::Xor, Some(c)) => Some((AluOpRRI::Xori, c)),
+            (IntBinaryOp::Shl, Some(c)) if bits == 32 => Some((AluOpRRI::Slliw, c & 31)),
+            (IntBinaryOp::LShr, Some(c)) if bits == 32 => Some((AluOpRRI::Srliw, c & 31)),
+            (IntBinaryOp::AShr, Some(c)) if bits == 32 => Some((AluOpRRI::Sraiw, c & 31)),
+            _ => None,
+        };
+        if let Some((alu_op, imm)) = imm_op.filter(|(_, imm)| Imm12::try_from_i64(*imm).is_some()) {
+            let rd = self.alu_rri(alu_op, lhs_reg, imm);
+            return self.normalize(rd, bits);
+        }
+
+        let mut rhs_reg = self.value_reg(rhs);
+        let unsigned = matches!(
+            op,
+            IntBinaryOp::LShr | IntBinaryOp::UDiv | IntBinaryOp::URem
+        );
+        if bits == 8 && unsigned {
+            // The unsigned operations see the sign-extended bits otherwise.
+            lhs_reg = self.alu_rri(AluOpRRI::Andi, lhs_reg, 0xff);
+            if !matches!(op, IntBinaryOp::LShr) {
+                rhs_reg = self.alu_rri(AluOpRRI::Andi, rhs_reg, 0xff);
+            }
+        }
+        let rrr = |word_op, op| if word { word_op } else { op };
+        let rd = match op {
+            IntBinaryOp::Add => self.alu_rrr(rrr(AluOpRRR::Addw, AluOpRRR::Add), lhs_reg, rhs_reg),
+            IntBinaryOp::Sub => self.alu_rrr(rrr(AluOpRRR::Subw, AluOpRRR::Sub), lhs_reg, rhs_reg),
+            IntBinaryOp::Mul => self.alu_rrr(rrr(AluOpRRR::Mulw, AluOpRRR::Mul), lhs_reg, rhs_reg),
+            IntBinaryOp::SMulHi if word && self.xlen() == 64 => {
+                // The full product of two sign-extended words fits in 64 bits.
+                let product = self.alu_rrr(AluOpRRR::Mul, lhs_reg, rhs_reg);
+                self.alu_rri(AluOpRRI::Srai, product, bits as i64)
+            }
+            IntBinaryOp::SMulHi => self.alu_rrr(AluOpRRR::Mulh, lhs_reg, rhs_reg),
+            IntBinaryOp::SDiv => self.alu_rrr(rrr(AluOpRRR::Divw, AluOpRRR::Div), lhs_reg, rhs_reg),
+            IntBinaryOp::UDiv => {
+                self.alu_rrr(rrr(AluOpRRR::Divuw, AluOpRRR::Divu), lhs_reg, rhs_reg)
             }
-            ir::ValueKind::Param { .. } => {
-                todo!()
+            IntBinaryOp::SRem => self.alu_rrr(rrr(AluOpRRR::Remw, AluOpRRR::Rem), lhs_reg, rhs_reg),
+            IntBinaryOp::URem => {
+                self.alu_rrr(rrr(AluOpRRR::Remuw, AluOpRRR::Remu), lhs_reg, rhs_reg)
             }
+            IntBinaryOp::Shl => self.alu_rrr(rrr(AluOpRRR::Sllw, AluOpRRR::Sll), lhs_reg, rhs_reg),
+            IntBinaryOp::LShr => self.alu_rrr(rrr(AluOpRRR::Srlw, AluOpRRR::Srl), lhs_reg, rhs_reg),
+            IntBinaryOp::AShr => self.alu_rrr(rrr(AluOpRRR::Sraw, AluOpRRR::Sra), lhs_reg, rhs_reg),
+            IntBinaryOp::And => self.alu_rrr(AluOpRRR::And, lhs_reg, rhs_reg),
+            IntBinaryOp::Or => self.alu_rrr(AluOpRRR::Or, lhs_reg, rhs_reg),
+            IntBinaryOp::Xor => self.alu_rrr(AluOpRRR::Xor, lhs_reg, rhs_reg),
+            IntBinaryOp::SMin | IntBinaryOp::SMax => {
+                // The result is one of the operands, already normalized.
+                return self.gen_min_max(op, lhs_reg, rhs_reg);
+            }
+            IntBinaryOp::ICmp { cond } => return self.gen_icmp(cond, lhs_reg, rhs_reg),
         };
+        self.normalize(rd, bits)
+    }
 
-        let bitwidth = lhs.ty(self.ctx).bitwidth(self.ctx);
+    /// Generate a signed minimum or maximum, with `min` and `max` if the
+    /// target has Zbb, or without branches otherwise, e.g., for the minimum:
+    ///
+    /// ```text
+    /// mask = -(slt b, a)              ; all ones if b is the result
+    /// rd   = a ^ ((a ^ b) & mask)
+    /// ```
+    fn gen_min_max(&mut self, op: IntBinaryOp, a: Reg, b: Reg) -> Reg {
+        let min = matches!(op, IntBinaryOp::SMin);
+        if self.features.zbb {
+            let op = if min { AluOpRRR::Min } else { AluOpRRR::Max };
+            return self.alu_rrr(op, a, b);
+        }
+        let (lt, gt) = if min { (b, a) } else { (a, b) };
+        let take_b = self.alu_rrr(AluOpRRR::Slt, lt, gt);
+        let mask = self.alu_rrr(AluOpRRR::Sub, regs::zero().into(), take_b);
+        let diff = self.alu_rrr(AluOpRRR::Xor, a, b);
+        let diff = self.alu_rrr(AluOpRRR::And, diff, mask);
+        self.alu_rrr(AluOpRRR::Xor, a, diff)
+    }
 
+    /// Generate a division or a remainder of a 32-bit value by a constant
+    /// without the divider, if it is cheaper on the target.
+    ///
+    /// The signed division by `d` is computed as in Granlund and Montgomery,
+    /// "Division by Invariant Integers using Multiplication", with the full
+    /// product of the sign-extended dividend and the magic number `m` fitting
+    /// in 64 bits:
+    ///
+    /// ```text
+    /// l = ceil(log2(|d|)), m = 2^(31 + l) / |d| + 1
+    /// q = (x * m) >> (31 + l) - (x >> 63)        ; negated if d < 0
+    /// ```
+    ///
+    /// The powers of two are done with shifts, biasing the negative dividends
+    /// to round towards zero, and the remainders are `x - q * d`. Only the
+    /// unsigned division by powers of two is handled, as SysY has no unsigned
+    /// integers and the other cases are rare. On RV32, the product does not
+    /// fit in a register, so only the powers of two are done.
+    ///
+    /// # Returns
+    ///
+    /// The register holding the result, or `None` if the divider is used.
+    fn gen_div_by_const(&mut self, op: IntBinaryOp, x: Reg, d: i32) -> Option<Reg> {
+        let costs = self.target.costs;
+        // The number of instructions to materialize a constant in a register,
+        // and to use it as an operand, where small ones fit in the immediate.
+        let imm_len = |imm: i64| match imm {
+            0 => 0,
+            imm => MInst::raw_load_imm(&mut MContext::default(), x, imm).len() as u32,
+        };
+        let imm_operand_len = |imm| match Imm12::try_from_i64(imm) {
+            Some(_) => 0,
+            None => imm_len(imm),
+        };
+        let ad = d.unsigned_abs();
+        let k = ad.trailing_zeros() as i64;
         match op {
-            IntBinaryOp::Add => {
-                // addw rd, rs1, rs2
-                let alu_op = match bitwidth {
-                    32 => AluOpRRR::Addw,
-                    // TODO: other bitwidths?
-                    _ => todo!(),
+            IntBinaryOp::SDiv | IntBinaryOp::SRem if d != i32::MIN && ad >= 2 => {}
+            IntBinaryOp::UDiv | IntBinaryOp::URem if (d as u32).is_power_of_two() && d != 1 => {}
+            _ => return None,
+        }
+
+        if let IntBinaryOp::UDiv | IntBinaryOp::URem = op {
+            let k = (d as u32).trailing_zeros() as i64;
+            let mask = (1 << k) - 1;
+            if op == IntBinaryOp::UDiv {
+                return Some(self.alu_rri(AluOpRRI::Srliw, x, k));
+            }
+            let cost = (1 + imm_operand_len(mask)) * costs.alu;
+            if cost >= costs.div {
+                return None;
+            }
+            return Some(self.alu_rri(AluOpRRI::Andi, x, mask));
+        }
+
+        let is_rem = op == IntBinaryOp::SRem;
+        let xlen = self.xlen() as i64;
+        if ad.is_power_of_two() {
+            let mask = -(1i64 << k);
+            let alus = match is_rem {
+                false => 3 + (k > 1) as u32 + (d < 0) as u32,
+                true => 4 + (k > 1) as u32 + imm_operand_len(mask),
+            };
+            if alus * costs.alu >= costs.div {
+                return None;
+            }
+            // The bias is `2^k - 1` for the negative dividends, 0 otherwise.
+            let bias = if k == 1 {
+                self.alu_rri(AluOpRRI::Srli, x, xlen - 1)
+            } else {
+                let sign = self.alu_rri(AluOpRRI::Srai, x, xlen - 1);
+                self.alu_rri(AluOpRRI::Srli, sign, xlen - k)
+            };
+            let biased = self.alu_rrr(AluOpRRR::Add, x, bias);
+            if is_rem {
+                let rounded = self.alu_rri(AluOpRRI::Andi, biased, mask);
+                return Some(self.alu_rrr(AluOpRRR::Subw, x, rounded));
+            }
+            let q = self.alu_rri(AluOpRRI::Sraiw, biased, k);
+            if d < 0 {
+                return Some(self.alu_rrr(AluOpRRR::Subw, regs::zero().into(), q));
+            }
+            return Some(q);
+        }
+
+        if xlen == 32 {
+            return None;
+        }
+        let l = 32 - (ad - 1).leading_zeros() as i64;
+        let m = ((1u64 << (31 + l)) / ad as u64 + 1) as i64;
+        let mut cost = (imm_len(m) + 3) * costs.alu + costs.mul;
+        if is_rem {
+            cost += (imm_len(d as i64) + 1) * costs.alu + costs.mul;
+        }
+        if cost >= costs.div {
+            return None;
+        }
+        let m = self.imm_reg(m);
+        let product = self.alu_rrr(AluOpRRR::Mul, x, m);
+        let q = self.alu_rri(AluOpRRI::Srai, product, 31 + l);
+        let sign = self.alu_rri(AluOpRRI::Srai, x, 63);
+        let q = if d < 0 {
+            self.alu_rrr(AluOpRRR::Subw, sign, q)
+        } else {
+            self.alu_rrr(AluOpRRR::Subw, q, sign)
+        };
+        if !is_rem {
+            return Some(q);
+        }
+        let d = self.imm_reg(d as i64);
+        let prod = self.alu_rrr(AluOpRRR::Mulw, q, d);
+        Some(self.alu_rrr(AluOpRRR::Subw, x, prod))
+    }
+
+    /// Generate a comparison, with the result as 0 or 1.
+    fn gen_icmp(&mut self, cond: IntCmpCond, lhs: Reg, rhs: Reg) -> Reg {
+        match cond {
+            IntCmpCond::Eq => {
+                let diff = self.alu_rrr(AluOpRRR::Xor, lhs, rhs);
+                self.alu_rri(AluOpRRI::Sltiu, diff, 1)
+            }
+            IntCmpCond::Ne => {
+                let diff = self.alu_rrr(AluOpRRR::Xor, lhs, rhs);
+                self.alu_rrr(AluOpRRR::Sltu, regs::zero().into(), diff)
+            }
+            IntCmpCond::Slt => self.alu_rrr(AluOpRRR::Slt, lhs, rhs),
+            IntCmpCond::Sgt => self.alu_rrr(AluOpRRR::Slt, rhs, lhs),
+            IntCmpCond::Sle => {
+                let gt = self.alu_rrr(AluOpRRR::Slt, rhs, lhs);
+                self.alu_rri(AluOpRRI::Xori, gt, 1)
+            }
+            IntCmpCond::Sge => {
+                let lt = self.alu_rrr(AluOpRRR::Slt, lhs, rhs);
+                self.alu_rri(AluOpRRI::Xori, lt, 1)
+            }
+        }
+    }
+
+    /// Generate a floating-point binary operation and append it to the
+    /// current block.
+    ///
+    /// There are only `feq`, `flt` and `fle`, so the other comparisons swap
+    /// the operands, and `one` combines two `flt`s.
+    pub fn gen_float_binary(&mut self, op: FloatBinaryOp, lhs: Value, rhs: Value) -> Reg {
+        let double = lhs.ty(self.ctx).bitwidth(self.ctx) == 64;
+        let pick = |single, double_op| if double { double_op } else { single };
+        let lhs = self.value_reg(lhs);
+        let rhs = self.value_reg(rhs);
+        let (feq, flt, fle) = (
+            pick(FpuOpRRR::FeqS, FpuOpRRR::FeqD),
+            pick(FpuOpRRR::FltS, FpuOpRRR::FltD),
+            pick(FpuOpRRR::FleS, FpuOpRRR::FleD),
+        );
+        match op {
+            FloatBinaryOp::FAdd => self.fpu_rrr(pick(FpuOpRRR::FaddS, FpuOpRRR::FaddD), lhs, rhs),
+            FloatBinaryOp::FSub => self.fpu_rrr(pick(FpuOpRRR::FsubS, FpuOpRRR::FsubD), lhs, rhs),
+            FloatBinaryOp::FMul => self.fpu_rrr(pick(FpuOpRRR::FmulS, FpuOpRRR::FmulD), lhs, rhs),
+            FloatBinaryOp::FDiv => self.fpu_rrr(pick(FpuOpRRR::FdivS, FpuOpRRR::FdivD), lhs, rhs),
+            FloatBinaryOp::FCmp { cond } => match cond {
+                FloatCmpCond::Oeq => self.fpu_rrr(feq, lhs, rhs),
+                FloatCmpCond::Olt => self.fpu_rrr(flt, lhs, rhs),
+                FloatCmpCond::Ole => self.fpu_rrr(fle, lhs, rhs),
+                FloatCmpCond::Ogt => self.fpu_rrr(flt, rhs, lhs),
+                FloatCmpCond::Oge => self.fpu_rrr(fle, rhs, lhs),
+                FloatCmpCond::One => {
+                    let lt = self.fpu_rrr(flt, lhs, rhs);
+                    let gt = self.fpu_rrr(flt, rhs, lhs);
+                    self.alu_rrr(AluOpRRR::Or, lt, gt)
+                }
+            },
+        }
+    }
+
+    /// Generate a cast between integer types, or between integer and
+    /// floating-point types.
+    fn gen_cast(&mut self, op: CastOp, val: Value, ty: Ty) -> Reg {
+        let from = val.ty(self.ctx).bitwidth(self.ctx);
+        let to = ty.bitwidth(self.ctx);
+        let rs = self.value_reg(val);
+        match op {
+            CastOp::SiToFp => {
+                // `i1` is kept as 0 or 1, but `true` is -1 as a signed value.
+                let rs = if from == 1 {
+                    self.alu_rrr(AluOpRRR::Subw, regs::zero().into(), rs)
+                } else {
+                    rs
                 };
-                let (add, rd) = MInst::alu_rrr(&mut self.mctx, alu_op, lhs_reg, rhs_reg);
-                curr_block.push_back(&mut self.mctx, add).unwrap();
+                let op = if to == 64 {
+                    FpuOpRR::FcvtDW
+                } else {
+                    FpuOpRR::FcvtSW
+                };
+                self.fpu_rr(op, rs)
+            }
+            CastOp::FpToSi => {
+                let op = if from == 64 {
+                    FpuOpRR::FcvtWD
+                } else {
+                    FpuOpRR::FcvtWS
+                };
+                let rd = self.fpu_rr(op, rs);
+                match to {
+                    1 => self.alu_rri(AluOpRRI::Andi, rd, 1),
+                    8 => self.sext(rd, 8),
+                    _ => rd,
+                }
+            }
+            CastOp::Zext => match from {
+                1 => self.copy(rs),
+                32 => {
+                    let rd = self.alu_rri(AluOpRRI::Slli, rs, 32);
+                    self.alu_rri(AluOpRRI::Srli, rd, 32)
+                }
+                _ => self.alu_rri(AluOpRRI::Andi, rs, (1 << from) - 1),
+            },
+            CastOp::Sext if from == 1 => self.alu_rrr(AluOpRRR::Subw, regs::zero().into(), rs),
+            CastOp::Sext => self.copy(rs),
+            CastOp::Trunc => match to {
+                1 => self.alu_rri(AluOpRRI::Andi, rs, 1),
+                32 => self.alu_rri(AluOpRRI::Addiw, rs, 0),
+                _ => self.sext(rs, to),
+            },
+        }
+    }
 
-                MOperand {
-                    ty: lhs.ty(self.ctx),
-                    kind: MOperandKind::Reg(rd),
+    /// Generate the move of the return value to `a0`, or `fa0` for the
+    /// floating-point values.
+    ///
+    /// # Returns
+    ///
+    /// The register holding the return value.
+    pub fn gen_ret_move(&mut self, val: Value) -> PReg {
+        let ret_reg = Self::ret_reg(self.ctx, val.ty(self.ctx));
+        let src = self.value_reg(val);
+        let inst = MInst::mv(&mut self.mctx, ret_reg.into(), src);
+        self.push(inst);
+        ret_reg
+    }
+
+    /// Allocate the registers of all the functions.
+    ///
+    /// The functions are [planned](regalloc::plan) in parallel first. If the
+    /// machine code is verified, so is the allocation, see
+    /// [`regalloc::verify`].
+    pub fn regalloc(&mut self) -> Result<(), VerifyError> {
+        let mfuncs = self
+            .ctx
+            .funcs()
+            .map(|func| self.funcs[&func.name(self.ctx)])
+            .filter(|mfunc| !mfunc.is_external(&self.mctx))
+            .collect::<Vec<_>>();
+        let mctx = &self.mctx;
+        let plans = mfuncs
+            .par_iter()
+            .map(|&mfunc| regalloc::plan(mctx, mfunc))
+            .collect::<Vec<_>>();
+        for (mfunc, plan) in mfuncs.into_iter().zip(plans) {
+            regalloc::allocate_planned(&mut self.mctx, mfunc, plan, self.verify_machineinstrs)?;
+        }
+        Ok(())
+    }
+
+    /// Do the code generation after register allocation.
+    ///
+    /// The instructions are scheduled, the frames are laid out, the prologues
+    /// and the epilogues are inserted, and the stack slots are resolved to
+    /// offsets from `sp` or `s0`.
+    pub fn after_regalloc(&mut self) {
+        for func in self.ctx.funcs() {
+            let mfunc = self.funcs[&func.name(self.ctx)];
+            if !mfunc.is_external(&self.mctx) {
+                if self.schedule {
+                    sched::schedule(&mut self.mctx, mfunc, &self.target.costs);
                 }
+                frame::lower(&mut self.mctx, mfunc);
             }
-            // TODO: Add more ops.
-            _ => todo!(),
         }
     }
 
-    /// Generate a move instruction needed for return value and append it to the
-    /// current block. Here we just demonstrate the idea. Bugs may exist.
-    /// You can refactor this entirely as your own way.
-    pub fn gen_ret_move(&mut self, val: Value) {
-        let curr_block = self.curr_block.unwrap();
+    /// Check the machine code of the functions after a stage, see
+    /// [`verify`](super::verify).
+    ///
+    /// After the instruction selection on RV64, the 32-bit integers are also
+    /// checked to stay sign-extended. The registers are the words on RV32.
+    pub fn verify(&self, stage: Stage) -> Result<(), VerifyError> {
+        for func in self.ctx.funcs() {
+            let mfunc = self.funcs[&func.name(self.ctx)];
+            if !mfunc.is_external(&self.mctx) {
+                verify::verify_func(&self.mctx, mfunc, stage, self.features)?;
+                if stage == Stage::Isel && self.xlen() == 64 {
+                    self.verify_words(mfunc)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::inst::MInstKind;
+    use crate::backend::target::CostTable;
+    use crate::ir::{Block, Context, Func, Global, Inst};
+
+    /// A simulator of the machine code after the frame lowering, to check the
+    /// generated code without a RISC-V machine. On RV32, the integer registers
+    /// hold the 32-bit values sign-extended.
+    struct Machine<'a> {
+        mctx: &'a MContext,
+        funcs: &'a HashMap<Symbol, MFunc>,
+        regs: [u64; 32],
+        fregs: [u64; 32],
+        mem: Vec<u8>,
+    }
 
-        // We only handle reg here, you need to handle other cases.
-        let src = match &val.try_deref(self.ctx).unwrap().kind {
-            ir::ValueKind::Constant { .. } => {
-                todo!()
+    impl Machine<'_> {
+        const GARBAGE: u64 = 0xdead_beef_dead_beef;
+
+        fn read(&self, reg: Reg) -> u64 {
+            match reg {
+                Reg::P(preg) if preg == regs::zero() => 0,
+                Reg::P(preg) if preg.kind() == RegKind::Float => self.fregs[preg.num() as usize],
+                Reg::P(preg) => self.regs[preg.num() as usize],
+                Reg::V(_) => panic!("virtual register after allocation"),
             }
-            ir::ValueKind::InstResult { .. } => {
-                let mopd = self.lowered[&val];
-                match mopd.kind {
-                    MOperandKind::Reg(reg) => reg,
-                    _ => todo!(),
+        }
+
+        fn write(&mut self, reg: Reg, value: u64) {
+            match reg {
+                Reg::P(preg) if preg == regs::zero() => {}
+                Reg::P(preg) if preg.kind() == RegKind::Float => {
+                    self.fregs[preg.num() as usize] = value
+                }
+                Reg::P(preg) if self.mctx.xlen() == 32 => {
+                    self.regs[preg.num() as usize] = value as i32 as i64 as u64
                 }
+                Reg::P(preg) => self.regs[preg.num() as usize] = value,
+                Reg::V(_) => panic!("virtual register after allocation"),
             }
-            ir::ValueKind::Param { .. } => {
-                todo!()
+        }
+
+        fn addr(&self, loc: MemLoc) -> usize {
+            let MemLoc::RegOffset { base, offset } = loc else {
+                panic!("unresolved stack slot");
+            };
+            self.read(base).wrapping_add(offset as u64) as usize
+        }
+
+        /// Get the first instruction from a block on, skipping empty blocks.
+        fn first_inst(&self, mut block: Option<MBlock>) -> Option<MInst> {
+            while let Some(b) = block {
+                if let Some(inst) = b.head(self.mctx) {
+                    return Some(inst);
+                }
+                block = b.next(self.mctx);
             }
+            None
+        }
+
+        fn call(&mut self, func: MFunc) {
+            let preserved = std::iter::once(regs::sp())
+                .chain(std::iter::once(regs::fp()))
+                .chain(regs::CALLEE_SAVED)
+                .chain(regs::FLOAT_CALLEE_SAVED)
+                .map(|preg| (preg, self.read(preg.into())))
+                .collect::<Vec<_>>();
+
+            let sext32 = |v: u64| v as i32 as i64 as u64;
+            let rv32 = self.mctx.xlen() == 32;
+            // The singles are NaN-boxed in the 64-bit registers.
+            let (f32_of, of_f32) = (
+                |v: u64| f32::from_bits(v as u32),
+                |v: f32| v.to_bits() as u64 | 0xffff_ffff_0000_0000,
+            );
+            let mut curr = self.first_inst(func.head(self.mctx));
+            while let Some(inst) = curr {
+                let block = inst.container(self.mctx).unwrap();
+                curr = inst
+                    .next(self.mctx)
+                    .or_else(|| self.first_inst(block.next(self.mctx)));
+                match inst.kind(self.mctx) {
+                    MInstKind::AluRRI { op, rd, rs, imm } => {
+                        let (v, i) = (self.read(*rs), imm.as_i16() as i64 as u64);
+                        let result = match op {
+                            AluOpRRI::Addi => v.wrapping_add(i),
+                            AluOpRRI::Addiw => sext32(v.wrapping_add(i)),
+                            AluOpRRI::Slli if rv32 => v << (i & 31),
+                            AluOpRRI::Srli if rv32 => (v as u32 >> (i & 31)) as u64,
+                            AluOpRRI::Srai if rv32 => ((v as i32) >> (i & 31)) as u64,
+                            AluOpRRI::Slli => v << (i & 63),
+                            AluOpRRI::Slliw => sext32(v << (i & 31)),
+                            AluOpRRI::Srli => v >> (i & 63),
+                            AluOpRRI::Srliw => sext32((v as u32 >> (i & 31)) as u64),
+                            AluOpRRI::Srai => ((v as i64) >> (i & 63)) as u64,
+                            AluOpRRI::Sraiw => ((v as i32) >> (i & 31)) as i64 as u64,
+                            AluOpRRI::Xori => v ^ i,
+                            AluOpRRI::Ori => v | i,
+                            AluOpRRI::Andi => v & i,
+                            AluOpRRI::Slti => ((v as i64) < (i as i64)) as u64,
+                            AluOpRRI::Sltiu => (v < i) as u64,
+                        };
+                        self.write(*rd, result);
+                    }
+                    MInstKind::AluRRR { op, rd, rs1, rs2 } => {
+                        let (a, b) = (self.read(*rs1), self.read(*rs2));
+                        let result = match op {
+                            AluOpRRR::Add => a.wrapping_add(b),
+                            AluOpRRR::Addw => sext32(a.wrapping_add(b)),
+                            AluOpRRR::Sub => a.wrapping_sub(b),
+                            AluOpRRR::Subw => sext32(a.wrapping_sub(b)),
+                            AluOpRRR::Xor => a ^ b,
+                            AluOpRRR::Or => a | b,
+                            AluOpRRR::And => a & b,
+                            AluOpRRR::Slt => ((a as i64) < (b as i64)) as u64,
+                            AluOpRRR::Sltu => (a < b) as u64,
+                            AluOpRRR::Mul => a.wrapping_mul(b),
+                            AluOpRRR::Mulw => sext32(a.wrapping_mul(b)),
+                            AluOpRRR::Div | AluOpRRR::Rem | AluOpRRR::Divu | AluOpRRR::Remu
+                                if !rv32 =>
+                            {
+                                unimplemented!("{}", op)
+                            }
+                            AluOpRRR::Mulh if rv32 => {
+                                ((sext32(a) as i64 * sext32(b) as i64) >> 32) as u64
+                            }
+                            AluOpRRR::Divw | AluOpRRR::Div => {
+                                (a as i32).wrapping_div(b as i32) as i64 as u64
+                            }
+                            AluOpRRR::Remw | AluOpRRR::Rem => {
+                                (a as i32).wrapping_rem(b as i32) as i64 as u64
+                            }
+                            AluOpRRR::Divuw | AluOpRRR::Divu => {
+                                sext32((a as u32 / b as u32) as u64)
+                            }
+                            AluOpRRR::Remuw | AluOpRRR::Remu => {
+                                sext32((a as u32 % b as u32) as u64)
+                            }
+                            AluOpRRR::Sh1add => (a << 1).wrapping_add(b),
+                            AluOpRRR::Sh2add => (a << 2).wrapping_add(b),
+                            AluOpRRR::Sh3add => (a << 3).wrapping_add(b),
+                            AluOpRRR::Min => (a as i64).min(b as i64) as u64,
+                            AluOpRRR::Max => (a as i64).max(b as i64) as u64,
+                            op => unimplemented!("{}", op),
+                        };
+                        self.write(*rd, result);
+                    }
+                    MInstKind::AluRR { op, rd, rs } => {
+                        let v = self.read(*rs);
+                        let result = match op {
+                            AluOpRR::SextB => v as i8 as i64 as u64,
+                            AluOpRR::SextH => v as i16 as i64 as u64,
+                        };
+                        self.write(*rd, result);
+                    }
+                    MInstKind::Load { op, rd, loc } => {
+                        let addr = self.addr(*loc);
+                        let bytes = |n: usize| {
+                            let mut buf = [0; 8];
+                            buf[..n].copy_from_slice(&self.mem[addr..addr + n]);
+                            u64::from_le_bytes(buf)
+                        };
+                        let value = match op {
+                            LoadOp::Lw => bytes(4) as i32 as i64 as u64,
+                            LoadOp::Ld | LoadOp::Fld => bytes(8),
+                            LoadOp::Flw => bytes(4) | 0xffff_ffff_0000_0000,
+                            op => unimplemented!("{}", op),
+                        };
+                        self.write(*rd, value);
+                    }
+                    MInstKind::Store { op, rs, loc } => {
+                        let (addr, value) = (self.addr(*loc), self.read(*rs).to_le_bytes());
+                        let n = match op {
+                            StoreOp::Sw | StoreOp::Fsw => 4,
+                            StoreOp::Sd | StoreOp::Fsd => 8,
+                            op => unimplemented!("{}", op),
+                        };
+                        self.mem[addr..addr + n].copy_from_slice(&value[..n]);
+                    }
+                    MInstKind::FpuRRR { op, rd, rs1, rs2 } => {
+                        let (a, b) = (self.read(*rs1), self.read(*rs2));
+                        let (x, y) = (f32_of(a), f32_of(b));
+                        let (p, q) = (f64::from_bits(a), f64::from_bits(b));
+                        let result = match op {
+                            FpuOpRRR::FaddS => of_f32(x + y),
+                            FpuOpRRR::FsubS => of_f32(x - y),
+                            FpuOpRRR::FmulS => of_f32(x * y),
+                            FpuOpRRR::FdivS => of_f32(x / y),
+                            FpuOpRRR::FeqS => (x == y) as u64,
+                            FpuOpRRR::FltS => (x < y) as u64,
+                            FpuOpRRR::FleS => (x <= y) as u64,
+                            FpuOpRRR::FaddD => (p + q).to_bits(),
+                            FpuOpRRR::FsubD => (p - q).to_bits(),
+                            FpuOpRRR::FmulD => (p * q).to_bits(),
+                            FpuOpRRR::FdivD => (p / q).to_bits(),
+                            FpuOpRRR::FeqD => (p == q) as u64,
+                            FpuOpRRR::FltD => (p < q) as u64,
+                            FpuOpRRR::FleD => (p <= q) as u64,
+                            FpuOpRRR::FsgnjS | FpuOpRRR::FsgnjD if a == b => a,
+                            op => unimplemented!("{}", op),
+                        };
+                        self.write(*rd, result);
+                    }
+                    MInstKind::FpuRR { op, rd, rs } => {
+                        let v = self.read(*rs);
+                        let result = match op {
+                            FpuOpRR::FcvtSW => of_f32(v as i32 as f32),
+                            FpuOpRR::FcvtDW => (v as i32 as f64).to_bits(),
+                            FpuOpRR::FcvtWS => f32_of(v) as i32 as i64 as u64,
+                            FpuOpRR::FcvtWD => f64::from_bits(v) as i32 as i64 as u64,
+                            FpuOpRR::FcvtSD => of_f32(f64::from_bits(v) as f32),
+                            FpuOpRR::FcvtDS => (f32_of(v) as f64).to_bits(),
+                            FpuOpRR::FmvWX => v | 0xffff_ffff_0000_0000,
+                            FpuOpRR::FmvXW => v as i32 as i64 as u64,
+                            FpuOpRR::FmvDX | FpuOpRR::FmvXD => v,
+                        };
+                        self.write(*rd, result);
+                    }
+                    MInstKind::Lui { rd, imm } => self.write(*rd, imm.as_i64() as u64),
+                    MInstKind::LoadAddr { rd, loc } => self.write(*rd, self.addr(*loc) as u64),
+                    MInstKind::J { target } => curr = self.first_inst(Some(*target)),
+                    MInstKind::Branch {
+                        op,
+                        rs1,
+                        rs2,
+                        target,
+                    } => {
+                        let (a, b) = (self.read(*rs1), self.read(*rs2));
+                        let taken = match op {
+                            BranchOp::Beq => a == b,
+                            BranchOp::Bne => a != b,
+                            BranchOp::Blt => (a as i64) < (b as i64),
+                            BranchOp::Bge => (a as i64) >= (b as i64),
+                            BranchOp::Bltu => a < b,
+                            BranchOp::Bgeu => a >= b,
+                        };
+                        if taken {
+                            curr = self.first_inst(Some(*target));
+                        }
+                    }
+                    MInstKind::Call { callee, .. } => {
+                        self.regs[regs::ra().num() as usize] = Self::GARBAGE;
+                        self.call(self.funcs[&Symbol::intern(&callee.to_string())]);
+                        for preg in regs::CALLER_SAVED
+                            .into_iter()
+                            .chain(regs::FLOAT_CALLER_SAVED)
+                        {
+                            if preg != regs::a0() && preg != regs::fa0() {
+                                self.write(preg.into(), Self::GARBAGE);
+                            }
+                        }
+                    }
+                    MInstKind::TailCall { callee, .. } => {
+                        // The callee preserves the same registers, and returns
+                        // in place of the current function.
+                        for &(preg, value) in preserved.iter() {
+                            assert_eq!(self.read(preg.into()), value, "{}", preg);
+                        }
+                        let callee = self.funcs[&Symbol::intern(&callee.to_string())];
+                        curr = self.first_inst(callee.head(self.mctx));
+                    }
+                    MInstKind::Ret { .. } => {
+                        for &(preg, value) in preserved.iter() {
+                            assert_eq!(self.read(preg.into()), value, "{}", preg);
+                        }
+                        return;
+                    }
+                    MInstKind::LuiHi { .. } | MInstKind::AddiLo { .. } | MInstKind::Jr { .. } => {
+                        unimplemented!()
+                    }
+                }
+            }
+            panic!("no return");
+        }
+    }
+
+    #[test]
+    fn test_codegen() {
+        // sum(a0, ..., a9) = a0 + 2 * a1 + ... + 10 * a9
+        // pressure(x): v_k = x + k for k in 0..30, t = sum(x, 0, ...);
+        //              return v_0 + ... + v_29 + t
+        // main: arr = alloca [10 x i32]
+        //       loop: i = phi [0, entry], [i + 1, loop]; arr[i] = i * i
+        //       exit: r = sum(arr[0], ..., arr[9]); return pressure(r)
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let arr_ty = Ty::array(&mut ctx, i32, 10);
+        let consts = (0..31).map(|v| Value::i32(&mut ctx, v)).collect::<Vec<_>>();
+        let ibinary = |ctx: &mut Context, block: Block, op, lhs, rhs| {
+            let inst = Inst::ibinary(ctx, op, lhs, rhs);
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+
+        let sum = Func::new(&mut ctx, "sum".to_string(), i32);
+        let params = (0..10)
+            .map(|_| sum.add_param(&mut ctx, i32))
+            .collect::<Vec<_>>();
+        let entry = Block::new(&mut ctx);
+        sum.push_back(&mut ctx, entry).unwrap();
+        let mut acc = consts[0];
+        for (k, &param) in params.iter().enumerate() {
+            let scaled = ibinary(&mut ctx, entry, IntBinaryOp::Mul, param, consts[k + 1]);
+            acc = ibinary(&mut ctx, entry, IntBinaryOp::Add, acc, scaled);
+        }
+        let ret = Inst::ret(&mut ctx, Some(acc));
+        entry.push_back(&mut ctx, ret).unwrap();
+
+        let pressure = Func::new(&mut ctx, "pressure".to_string(), i32);
+        let x = pressure.add_param(&mut ctx, i32);
+        let entry = Block::new(&mut ctx);
+        pressure.push_back(&mut ctx, entry).unwrap();
+        let live = (0..30)
+            .map(|k| ibinary(&mut ctx, entry, IntBinaryOp::Add, x, consts[k]))
+            .collect::<Vec<_>>();
+        let mut args = vec![x];
+        args.extend(std::iter::repeat_n(consts[0], 9));
+        let call = Inst::call(&mut ctx, sum, args);
+        entry.push_back(&mut ctx, call).unwrap();
+        let mut acc = call.result(&ctx).unwrap();
+        for &value in live.iter().rev() {
+            acc = ibinary(&mut ctx, entry, IntBinaryOp::Add, acc, value);
+        }
+        let ret = Inst::ret(&mut ctx, Some(acc));
+        entry.push_back(&mut ctx, ret).unwrap();
+
+        let main = Func::new(&mut ctx, "main".to_string(), i32);
+        let [entry, body, exit] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, body, exit] {
+            main.push_back(&mut ctx, block).unwrap();
+        }
+        let alloca = Inst::alloca(&mut ctx, arr_ty);
+        entry.push_back(&mut ctx, alloca).unwrap();
+        let arr = alloca.result(&ctx).unwrap();
+        let br = Inst::br(&mut ctx, body);
+        entry.push_back(&mut ctx, br).unwrap();
+
+        let phi = Inst::phi(&mut ctx, i32);
+        body.push_back(&mut ctx, phi).unwrap();
+        let i = phi.result(&ctx).unwrap();
+        let gep = Inst::getelementptr(&mut ctx, arr_ty, arr, vec![consts[0], i]);
+        body.push_back(&mut ctx, gep).unwrap();
+        let square = ibinary(&mut ctx, body, IntBinaryOp::Mul, i, i);
+        let ptr = gep.result(&ctx).unwrap();
+        let store = Inst::store(&mut ctx, square, ptr);
+        body.push_back(&mut ctx, store).unwrap();
+        let next = ibinary(&mut ctx, body, IntBinaryOp::Add, i, consts[1]);
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let cond = ibinary(&mut ctx, body, slt, next, consts[10]);
+        let cond_br = Inst::cond_br(&mut ctx, cond, body, exit);
+        body.push_back(&mut ctx, cond_br).unwrap();
+        phi.insert_incoming(&mut ctx, entry, consts[0]);
+        phi.insert_incoming(&mut ctx, body, next);
+
+        let args = (0..10)
+            .map(|k| {
+                let gep = Inst::getelementptr(&mut ctx, arr_ty, arr, vec![consts[0], consts[k]]);
+                exit.push_back(&mut ctx, gep).unwrap();
+                let ptr = gep.result(&ctx).unwrap();
+                let load = Inst::load(&mut ctx, ptr, i32);
+                exit.push_back(&mut ctx, load).unwrap();
+                load.result(&ctx).unwrap()
+            })
+            .collect();
+        let call = Inst::call(&mut ctx, sum, args);
+        exit.push_back(&mut ctx, call).unwrap();
+        let r = call.result(&ctx).unwrap();
+        let call = Inst::call(&mut ctx, pressure, vec![r]);
+        exit.push_back(&mut ctx, call).unwrap();
+        let result = call.result(&ctx);
+        let ret = Inst::ret(&mut ctx, result);
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        for omit_frame_pointer in [false, true] {
+            let mut codegen_ctx = CodegenContext::new(&ctx);
+            codegen_ctx.set_omit_frame_pointer(omit_frame_pointer);
+            codegen_ctx.codegen();
+            codegen_ctx.regalloc().unwrap();
+            codegen_ctx.after_regalloc();
+            let funcs = codegen_ctx.funcs.clone();
+            let blocks = codegen_ctx.blocks.clone();
+            let mctx = codegen_ctx.finish();
+
+            let asm = mctx.display().to_string();
+            assert!(!asm.contains("$r") && !asm.contains("???"), "{}", asm);
+            assert!(asm.contains("call sum") && asm.contains("call pressure"));
+            assert!(asm.contains("sd ra, "));
+            // the incoming arguments of `sum` are addressed from `sp` instead
+            assert_eq!(asm.contains("s0"), !omit_frame_pointer, "{}", asm);
+            // the loop condition is fused into the branch
+            assert!(asm.contains("blt ") && !asm.contains("slt "), "{}", asm);
+            // the i32 arithmetic stays sign-extended
+            assert!(asm.contains("mulw ") && !asm.contains("mul "), "{}", asm);
+            // the constant indices are folded into the displacement from sp
+            assert!(asm.contains("lw a1, 20(sp)"), "{}", asm);
+            // only the loop is aligned
+            assert_eq!(asm.matches(".balign").count(), 1, "{}", asm);
+            let label = blocks[&body].label(&mctx);
+            assert!(
+                asm.contains(&format!("\t.balign 16\n{}:", label)),
+                "{}",
+                asm
+            );
+
+            let mut machine = Machine {
+                mctx: &mctx,
+                funcs: &funcs,
+                regs: [Machine::GARBAGE; 32],
+                fregs: [Machine::GARBAGE; 32],
+                mem: vec![0; 1 << 16],
+            };
+            machine.regs[regs::sp().num() as usize] = 1 << 16;
+            machine.call(funcs[&Symbol::intern("main")]);
+            // sum(k * k) = 2310, pressure(2310) = 31 * 2310 + 435
+            assert_eq!(
+                machine.regs[regs::a0().num() as usize] as i32,
+                31 * 2310 + 435
+            );
+        }
+    }
+
+    #[test]
+    fn test_float_codegen() {
+        // mix(f_0, ..., f_9, n) = f_0 * 1.0 + ... + f_9 * 10.0 + n
+        // main: y = sitofp(7) / 2.0; r = mix(0.5, ..., 9.5, 3) + y
+        //       if r > 100.0 { r = r * 2.0 }
+        //       return fptosi(r) + zext(y == 3.5)
+        let mut ctx = Context::new(8);
+        let i1 = Ty::i1(&mut ctx);
+        let i32 = Ty::i32(&mut ctx);
+        let f32 = Ty::f32(&mut ctx);
+        let fbinary = |ctx: &mut Context, block: Block, op, lhs, rhs| {
+            let inst = Inst::fbinary(ctx, op, lhs, rhs);
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+        let cast = |ctx: &mut Context, block: Block, op, val, ty| {
+            let inst = Inst::cast(ctx, op, val, ty);
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+
+        let mix = Func::new(&mut ctx, "mix".to_string(), f32);
+        let params = (0..10)
+            .map(|_| mix.add_param(&mut ctx, f32))
+            .collect::<Vec<_>>();
+        let n = mix.add_param(&mut ctx, i32);
+        let entry = Block::new(&mut ctx);
+        mix.push_back(&mut ctx, entry).unwrap();
+        let mut acc = Value::f32(&mut ctx, 0.0);
+        for (k, &param) in params.iter().enumerate() {
+            let scale = Value::f32(&mut ctx, (k + 1) as f32);
+            let scaled = fbinary(&mut ctx, entry, FloatBinaryOp::FMul, param, scale);
+            acc = fbinary(&mut ctx, entry, FloatBinaryOp::FAdd, acc, scaled);
+        }
+        let n = cast(&mut ctx, entry, CastOp::SiToFp, n, f32);
+        let acc = fbinary(&mut ctx, entry, FloatBinaryOp::FAdd, acc, n);
+        let ret = Inst::ret(&mut ctx, Some(acc));
+        entry.push_back(&mut ctx, ret).unwrap();
+
+        let main = Func::new(&mut ctx, "main".to_string(), i32);
+        let [entry, then, exit] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, then, exit] {
+            main.push_back(&mut ctx, block).unwrap();
+        }
+        let seven = Value::i32(&mut ctx, 7);
+        let seven = cast(&mut ctx, entry, CastOp::SiToFp, seven, f32);
+        let two = Value::f32(&mut ctx, 2.0);
+        let y = fbinary(&mut ctx, entry, FloatBinaryOp::FDiv, seven, two);
+        let mut args = (0..10)
+            .map(|k| Value::f32(&mut ctx, k as f32 + 0.5))
+            .collect::<Vec<_>>();
+        args.push(Value::i32(&mut ctx, 3));
+        let call = Inst::call(&mut ctx, mix, args);
+        entry.push_back(&mut ctx, call).unwrap();
+        let r = call.result(&ctx).unwrap();
+        let r = fbinary(&mut ctx, entry, FloatBinaryOp::FAdd, r, y);
+        let hundred = Value::f32(&mut ctx, 100.0);
+        let ogt = FloatBinaryOp::FCmp {
+            cond: FloatCmpCond::Ogt,
         };
+        let cond = fbinary(&mut ctx, entry, ogt, r, hundred);
+        let cond_br = Inst::cond_br(&mut ctx, cond, then, exit);
+        entry.push_back(&mut ctx, cond_br).unwrap();
 
-        // addi a0, src, 0
-        let mv = MInst::raw_alu_rri(
-            &mut self.mctx,
-            AluOpRRI::Addi,
-            regs::a0().into(),
-            src,
-            Imm12::try_from_i64(0).unwrap(),
+        let doubled = fbinary(&mut ctx, then, FloatBinaryOp::FMul, r, two);
+        let br = Inst::br(&mut ctx, exit);
+        then.push_back(&mut ctx, br).unwrap();
+
+        let phi = Inst::phi(&mut ctx, f32);
+        exit.push_back(&mut ctx, phi).unwrap();
+        phi.insert_incoming(&mut ctx, entry, r);
+        phi.insert_incoming(&mut ctx, then, doubled);
+        let r = phi.result(&ctx).unwrap();
+        let r = cast(&mut ctx, exit, CastOp::FpToSi, r, i32);
+        let expected_y = Value::f32(&mut ctx, 3.5);
+        let oeq = FloatBinaryOp::FCmp {
+            cond: FloatCmpCond::Oeq,
+        };
+        let eq = fbinary(&mut ctx, exit, oeq, y, expected_y);
+        let eq = cast(&mut ctx, exit, CastOp::Zext, eq, i32);
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, r, eq);
+        exit.push_back(&mut ctx, add).unwrap();
+        let result = add.result(&ctx);
+        let ret = Inst::ret(&mut ctx, result);
+        exit.push_back(&mut ctx, ret).unwrap();
+        assert_eq!(eq.ty(&ctx), i32);
+        assert_eq!(cond.ty(&ctx), i1);
+
+        let mut codegen_ctx = CodegenContext::new(&ctx);
+        codegen_ctx.codegen();
+        codegen_ctx.regalloc().unwrap();
+        codegen_ctx.after_regalloc();
+        let funcs = codegen_ctx.funcs.clone();
+        let mctx = codegen_ctx.finish();
+
+        let asm = mctx.display().to_string();
+        assert!(!asm.contains("$f") && !asm.contains("???"), "{}", asm);
+        // the last floating-point arguments are passed in integer registers
+        assert!(
+            asm.contains("fa7") && asm.contains("fmv.x.w a1, "),
+            "{}",
+            asm
         );
-        curr_block.push_back(&mut self.mctx, mv).unwrap();
+        assert!(
+            asm.contains("fmul.s ") && asm.contains("fdiv.s "),
+            "{}",
+            asm
+        );
+        // `ogt` swaps the operands of `flt`
+        assert!(asm.contains("flt.s ") && asm.contains("feq.s "), "{}", asm);
+        assert!(asm.contains("fcvt.s.w ") && asm.contains("rtz"), "{}", asm);
+
+        let mut machine = Machine {
+            mctx: &mctx,
+            funcs: &funcs,
+            regs: [Machine::GARBAGE; 32],
+            fregs: [Machine::GARBAGE; 32],
+            mem: vec![0; 1 << 10],
+        };
+        machine.regs[regs::sp().num() as usize] = 1 << 10;
+        machine.call(funcs[&Symbol::intern("main")]);
+        // mix(...) = 357.5 + 3, r = 364.0 * 2.0
+        assert_eq!(machine.regs[regs::a0().num() as usize], 729);
+    }
+
+    #[test]
+    fn test_tail_call() {
+        // is_even(n): return n == 0 ? 1 : is_odd(n - 1)
+        // is_odd(n): return n == 0 ? 0 : is_even(n - 1)
+        // main: return is_even(1000)
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let [zero, one, n] = [0, 1, 1000].map(|v| Value::i32(&mut ctx, v));
+        let is_even = Func::new(&mut ctx, "is_even".to_string(), i32);
+        let is_odd = Func::new(&mut ctx, "is_odd".to_string(), i32);
+        for (func, other, base) in [(is_even, is_odd, one), (is_odd, is_even, zero)] {
+            let param = func.add_param(&mut ctx, i32);
+            let [entry, base_case, rec_case] = [(); 3].map(|_| Block::new(&mut ctx));
+            for block in [entry, base_case, rec_case] {
+                func.push_back(&mut ctx, block).unwrap();
+            }
+            let eq = IntBinaryOp::ICmp {
+                cond: IntCmpCond::Eq,
+            };
+            let cmp = Inst::ibinary(&mut ctx, eq, param, zero);
+            entry.push_back(&mut ctx, cmp).unwrap();
+            let cond = cmp.result(&ctx).unwrap();
+            let cond_br = Inst::cond_br(&mut ctx, cond, base_case, rec_case);
+            entry.push_back(&mut ctx, cond_br).unwrap();
+
+            let ret = Inst::ret(&mut ctx, Some(base));
+            base_case.push_back(&mut ctx, ret).unwrap();
+
+            let sub = Inst::ibinary(&mut ctx, IntBinaryOp::Sub, param, one);
+            rec_case.push_back(&mut ctx, sub).unwrap();
+            let arg = sub.result(&ctx).unwrap();
+            let call = Inst::call(&mut ctx, other, vec![arg]);
+            rec_case.push_back(&mut ctx, call).unwrap();
+            let result = call.result(&ctx);
+            let ret = Inst::ret(&mut ctx, result);
+            rec_case.push_back(&mut ctx, ret).unwrap();
+        }
+        let main = Func::new(&mut ctx, "main".to_string(), i32);
+        let entry = Block::new(&mut ctx);
+        main.push_back(&mut ctx, entry).unwrap();
+        let call = Inst::call(&mut ctx, is_even, vec![n]);
+        entry.push_back(&mut ctx, call).unwrap();
+        let result = call.result(&ctx);
+        let ret = Inst::ret(&mut ctx, result);
+        entry.push_back(&mut ctx, ret).unwrap();
+
+        let mut codegen_ctx = CodegenContext::new(&ctx);
+        codegen_ctx.codegen();
+        codegen_ctx.regalloc().unwrap();
+        codegen_ctx.after_regalloc();
+        let funcs = codegen_ctx.funcs.clone();
+        let mctx = codegen_ctx.finish();
+
+        let asm = mctx.display().to_string();
+        assert!(
+            asm.contains("tail is_even") && asm.contains("tail is_odd"),
+            "{}",
+            asm
+        );
+        // no frame is needed, since `ra` is never clobbered
+        assert!(!asm.contains("call ") && !asm.contains("sp"), "{}", asm);
+
+        // the stack would overflow with the recursive calls
+        let mut machine = Machine {
+            mctx: &mctx,
+            funcs: &funcs,
+            regs: [Machine::GARBAGE; 32],
+            fregs: [Machine::GARBAGE; 32],
+            mem: vec![0; 64],
+        };
+        machine.regs[regs::sp().num() as usize] = 64;
+        machine.call(funcs[&Symbol::intern("main")]);
+        assert_eq!(machine.regs[regs::a0().num() as usize], 1);
+    }
+
+    #[test]
+    fn test_switch() {
+        // dense(x): switch x { 1, 3 => 10, 2, 6 => 20, 5 => 30, _ => -1 }
+        // sparse(x): switch x { 1 => 10, 100 => 20, -10000 => 30, _ => -1 }
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let build = |ctx: &mut Context, name: &str, cases: &[(i32, usize)]| {
+            let func = Func::new(ctx, name.to_string(), i32);
+            let x = func.add_param(ctx, i32);
+            let entry = Block::new(ctx);
+            func.push_back(ctx, entry).unwrap();
+            let dests = [-1, 10, 20, 30].map(|result| {
+                let block = Block::new(ctx);
+                func.push_back(ctx, block).unwrap();
+                let result = Value::i32(ctx, result);
+                let ret = Inst::ret(ctx, Some(result));
+                block.push_back(ctx, ret).unwrap();
+                block
+            });
+            let cases = cases.iter().map(|&(value, dest)| (value, dests[dest]));
+            let switch = Inst::switch(ctx, x, dests[0], cases.collect());
+            entry.push_back(ctx, switch).unwrap();
+            switch
+        };
+        let dense = [(1, 1), (2, 2), (3, 1), (5, 3), (6, 2)];
+        let switch = build(&mut ctx, "dense", &dense);
+        build(&mut ctx, "sparse", &[(1, 1), (100, 2), (-10000, 3)]);
+        let ir = switch.display(&ctx).to_string();
+        assert!(ir.starts_with("switch i32 %"), "{}", ir);
+        assert!(ir.contains(" [i32 1, label %") && ir.contains(" i32 6, label %"));
+
+        let mut codegen_ctx = CodegenContext::new(&ctx);
+        codegen_ctx.codegen();
+        codegen_ctx.regalloc().unwrap();
+        codegen_ctx.after_regalloc();
+        let funcs = codegen_ctx.funcs.clone();
+        let mctx = codegen_ctx.finish();
+
+        let asm = mctx.display().to_string();
+        // the table has the entries from 1 to 6, with the default at 4
+        let table = asm.split(".section .rodata").nth(1).unwrap_or_default();
+        assert_eq!(table.matches(".dword .L").count(), 6, "{}", asm);
+        assert!(!table.contains(".global"), "{}", asm);
+        assert!(asm.contains("bgeu ") && asm.contains("jr "), "{}", asm);
+        assert_eq!(asm.matches("beq ").count(), 3, "{}", asm);
+
+        for (x, expected) in [
+            (1, 10),
+            (100, 20),
+            (-10000, 30),
+            (0, -1),
+            (-1, -1),
+            (101, -1),
+        ] {
+            let mut machine = Machine {
+                mctx: &mctx,
+                funcs: &funcs,
+                regs: [Machine::GARBAGE; 32],
+                fregs: [Machine::GARBAGE; 32],
+                mem: vec![0; 64],
+            };
+            machine.regs[regs::sp().num() as usize] = 64;
+            machine.regs[regs::a0().num() as usize] = x as i64 as u64;
+            machine.call(funcs[&Symbol::intern("sparse")]);
+            assert_eq!(machine.regs[regs::a0().num() as usize] as i32, expected);
+        }
+    }
+
+    #[test]
+    fn test_gep_displacement() {
+        // f(p): arr = alloca [1024 x i32]
+        //       s = p[511] + p[512] + p[-512] + p[-513]
+        //       arr[10] = s; arr[1000] = s; return arr[10] + arr[1000]
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let ptr_ty = Ty::ptr(&mut ctx);
+        let arr_ty = Ty::array(&mut ctx, i32, 1024);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let p = func.add_param(&mut ctx, ptr_ty);
+        let block = Block::new(&mut ctx);
+        func.push_back(&mut ctx, block).unwrap();
+        let push = |ctx: &mut Context, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+        let alloca = Inst::alloca(&mut ctx, arr_ty);
+        let arr = push(&mut ctx, alloca).unwrap();
+        let mut sum = Value::i32(&mut ctx, 0);
+        for k in [511, 512, -512, -513] {
+            let k = Value::i32(&mut ctx, k);
+            let gep = Inst::getelementptr(&mut ctx, i32, p, vec![k]);
+            let ptr = push(&mut ctx, gep).unwrap();
+            let load = Inst::load(&mut ctx, ptr, i32);
+            let loaded = push(&mut ctx, load).unwrap();
+            let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, sum, loaded);
+            sum = push(&mut ctx, add).unwrap();
+        }
+        let zero = Value::i32(&mut ctx, 0);
+        let elems = [10, 1000].map(|k| {
+            let k = Value::i32(&mut ctx, k);
+            let gep = Inst::getelementptr(&mut ctx, arr_ty, arr, vec![zero, k]);
+            let ptr = push(&mut ctx, gep).unwrap();
+            let store = Inst::store(&mut ctx, sum, ptr);
+            push(&mut ctx, store);
+            ptr
+        });
+        let [near, far] = elems.map(|ptr| {
+            let load = Inst::load(&mut ctx, ptr, i32);
+            push(&mut ctx, load).unwrap()
+        });
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, near, far);
+        let result = push(&mut ctx, add);
+        let ret = Inst::ret(&mut ctx, result);
+        push(&mut ctx, ret);
+
+        let mut codegen_ctx = CodegenContext::new(&ctx);
+        codegen_ctx.set_verify_machineinstrs(true);
+        codegen_ctx.codegen();
+        codegen_ctx.verify(Stage::Isel).unwrap();
+        codegen_ctx.regalloc().unwrap();
+        codegen_ctx.after_regalloc();
+        codegen_ctx.verify(Stage::Frame).unwrap();
+        let funcs = codegen_ctx.funcs.clone();
+        let mctx = codegen_ctx.finish();
+
+        let asm = mctx.display().to_string();
+        // the offsets fitting in 12 bits are folded into the displacement
+        assert!(asm.contains(", 2044(a0)\n"), "{}", asm);
+        assert!(asm.contains(", -2048(a0)\n"), "{}", asm);
+        // but not the ones just out of range, added to the base first
+        assert_eq!(asm.matches("(a0)\n").count(), 2, "{}", asm);
+        assert_eq!(asm.matches(", a0, ").count(), 2, "{}", asm);
+        // the constant indices into the array are offsets from sp, in 12 bits
+        // or computed from sp once the frame is laid out
+        assert!(asm.contains(", 40(sp)\n"), "{}", asm);
+        assert!(asm.contains(", sp, "), "{}", asm);
+
+        let mut machine = Machine {
+            mctx: &mctx,
+            funcs: &funcs,
+            regs: [Machine::GARBAGE; 32],
+            fregs: [Machine::GARBAGE; 32],
+            mem: vec![0; 1 << 16],
+        };
+        let base = 8192;
+        for (k, value) in [(511, 1i32), (512, 20), (-512, 300), (-513, 4000)] {
+            let addr = (base + 4 * k) as usize;
+            machine.mem[addr..addr + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        machine.regs[regs::sp().num() as usize] = 1 << 16;
+        machine.regs[regs::a0().num() as usize] = base as u64;
+        machine.call(funcs[&Symbol::intern("f")]);
+        assert_eq!(machine.regs[regs::a0().num() as usize] as i32, 2 * 4321);
+    }
+
+    #[test]
+    fn test_word_arithmetic() {
+        // f(a, b): return (a + b < 0)
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let [a, b] = [(); 2].map(|_| func.add_param(&mut ctx, i32));
+        let block = Block::new(&mut ctx);
+        func.push_back(&mut ctx, block).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, a, b);
+        block.push_back(&mut ctx, add).unwrap();
+        let zero = Value::i32(&mut ctx, 0);
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let sum = add.result(&ctx).unwrap();
+        let cmp = Inst::ibinary(&mut ctx, slt, sum, zero);
+        block.push_back(&mut ctx, cmp).unwrap();
+        let result = cmp.result(&ctx);
+        let ret = Inst::ret(&mut ctx, result);
+        block.push_back(&mut ctx, ret).unwrap();
+
+        let find_addw = |codegen_ctx: &CodegenContext| {
+            let mctx = &codegen_ctx.mctx;
+            codegen_ctx.funcs[&Symbol::intern("f")]
+                .iter(mctx)
+                .flat_map(|block| block.iter(mctx))
+                .find(|inst| {
+                    matches!(
+                        inst.kind(mctx),
+                        MInstKind::AluRRR {
+                            op: AluOpRRR::Addw,
+                            ..
+                        }
+                    )
+                })
+        };
+
+        let mut codegen_ctx = CodegenContext::new(&ctx);
+        codegen_ctx.codegen();
+        codegen_ctx.verify(Stage::Isel).unwrap();
+        assert!(find_addw(&codegen_ctx).is_some());
+        codegen_ctx.regalloc().unwrap();
+        codegen_ctx.after_regalloc();
+        let funcs = codegen_ctx.funcs.clone();
+        let mctx = codegen_ctx.finish();
+
+        // the sum overflowing 32 bits is negative, not 1 << 31
+        let mut machine = Machine {
+            mctx: &mctx,
+            funcs: &funcs,
+            regs: [Machine::GARBAGE; 32],
+            fregs: [Machine::GARBAGE; 32],
+            mem: vec![0; 64],
+        };
+        machine.regs[regs::sp().num() as usize] = 64;
+        machine.regs[regs::a0().num() as usize] = i32::MAX as u64;
+        machine.regs[regs::a1().num() as usize] = 1;
+        machine.call(funcs[&Symbol::intern("f")]);
+        assert_eq!(machine.regs[regs::a0().num() as usize], 1);
+
+        // the 64-bit add is rejected after the instruction selection
+        let mut codegen_ctx = CodegenContext::new(&ctx);
+        codegen_ctx.codegen();
+        let addw = find_addw(&codegen_ctx).unwrap();
+        match addw.kind_mut(&mut codegen_ctx.mctx) {
+            MInstKind::AluRRR { op, .. } => *op = AluOpRRR::Add,
+            _ => unreachable!(),
+        }
+        let err = codegen_ctx.verify(Stage::Isel).unwrap_err();
+        assert!(err.inst.starts_with("add "), "{}", err);
+        assert!(matches!(err.problem, Problem::NotSextWord(_)), "{}", err);
+    }
+
+    #[test]
+    fn test_bit_manipulation() {
+        // f(i, x, y): arr = alloca [4 x i32]; arr[i] = smin x, y
+        //             return sext (trunc arr[i] to i8) + smax x, y
+        let mut ctx = Context::new(8);
+        let i8 = Ty::i8(&mut ctx);
+        let i32 = Ty::i32(&mut ctx);
+        let arr_ty = Ty::array(&mut ctx, i32, 4);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let [i, x, y] = [(); 3].map(|_| func.add_param(&mut ctx, i32));
+        let block = Block::new(&mut ctx);
+        func.push_back(&mut ctx, block).unwrap();
+        let push = |ctx: &mut Context, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+        let alloca = Inst::alloca(&mut ctx, arr_ty);
+        let arr = push(&mut ctx, alloca).unwrap();
+        let zero = Value::i32(&mut ctx, 0);
+        let gep = Inst::getelementptr(&mut ctx, arr_ty, arr, vec![zero, i]);
+        let ptr = push(&mut ctx, gep).unwrap();
+        let min = Inst::ibinary(&mut ctx, IntBinaryOp::SMin, x, y);
+        let min = push(&mut ctx, min).unwrap();
+        let store = Inst::store(&mut ctx, min, ptr);
+        push(&mut ctx, store);
+        let load = Inst::load(&mut ctx, ptr, i32);
+        let loaded = push(&mut ctx, load).unwrap();
+        let trunc = Inst::cast(&mut ctx, CastOp::Trunc, loaded, i8);
+        let trunc = push(&mut ctx, trunc).unwrap();
+        let sext = Inst::cast(&mut ctx, CastOp::Sext, trunc, i32);
+        let sext = push(&mut ctx, sext).unwrap();
+        let max = Inst::ibinary(&mut ctx, IntBinaryOp::SMax, x, y);
+        let max = push(&mut ctx, max).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, sext, max);
+        let result = push(&mut ctx, add);
+        let ret = Inst::ret(&mut ctx, result);
+        push(&mut ctx, ret);
+
+        for (zba, zbb) in [(false, false), (true, true)] {
+            let mut codegen_ctx = CodegenContext::new(&ctx);
+            codegen_ctx.set_features(TargetFeatures { zba, zbb });
+            codegen_ctx.codegen();
+            codegen_ctx.regalloc().unwrap();
+            codegen_ctx.after_regalloc();
+            let funcs = codegen_ctx.funcs.clone();
+            let mctx = codegen_ctx.finish();
+
+            let asm = mctx.display().to_string();
+            assert_eq!(asm.contains("\tsh2add "), zba, "{}", asm);
+            for mnemonic in ["\tmin ", "\tmax ", "\tsext.b "] {
+                assert_eq!(asm.contains(mnemonic), zbb, "{}", asm);
+            }
+            assert_eq!(asm.contains("rv64gc_zba_zbb"), zba && zbb, "{}", asm);
+
+            for (i, x, y) in [(0, 1, 2), (3, -300, 7), (2, i32::MAX, i32::MIN)] {
+                let mut machine = Machine {
+                    mctx: &mctx,
+                    funcs: &funcs,
+                    regs: [Machine::GARBAGE; 32],
+                    fregs: [Machine::GARBAGE; 32],
+                    mem: vec![0; 64],
+                };
+                machine.regs[regs::sp().num() as usize] = 64;
+                for (reg, value) in [(regs::a0(), i), (regs::a1(), x), (regs::a2(), y)] {
+                    machine.regs[reg.num() as usize] = value as i64 as u64;
+                }
+                machine.call(funcs[&Symbol::intern("f")]);
+                let expected = (x.min(y) as i8 as i32).wrapping_add(x.max(y));
+                assert_eq!(machine.regs[regs::a0().num() as usize] as i32, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_emit_mir() {
+        // f(a, b): return a + b
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let a = func.add_param(&mut ctx, i32);
+        let b = func.add_param(&mut ctx, i32);
+        let entry = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, a, b);
+        entry.push_back(&mut ctx, add).unwrap();
+        let sum = add.result(&ctx);
+        let ret = Inst::ret(&mut ctx, sum);
+        entry.push_back(&mut ctx, ret).unwrap();
+
+        let options = CodegenOptions::default();
+        let mir = Riscv64.emit_mir(&ctx, &options).unwrap();
+        let (before, after) = mir.split_once("# after the register allocation").unwrap();
+        assert!(before.starts_with("# after the instruction selection"));
+        // the virtual registers are only before the allocation
+        assert!(before.contains("addw $r"));
+        assert!(!after.contains('$'));
+        assert!(after.contains("addw a0, a0, a1"));
+        assert!(after.contains("\tret a0\n"));
+    }
+
+    #[test]
+    fn test_data_sections() {
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let ptr = Ty::ptr(&mut ctx);
+        let arr_ty = Ty::array(&mut ctx, i32, 100);
+        let ptrs_ty = Ty::array(&mut ctx, ptr, 2);
+
+        // int data[100] = {1, -1};
+        let mut elems = vec![
+            ConstantValue::i32(&mut ctx, 1),
+            ConstantValue::i32(&mut ctx, -1),
+        ];
+        elems.resize_with(100, || ConstantValue::i32(&mut ctx, 0));
+        let data = ConstantValue::Array { ty: arr_ty, elems };
+        Global::new(&mut ctx, "data".to_string(), data);
+        // const float scale = 2.5;
+        let scale = ConstantValue::f32(&mut ctx, 2.5);
+        let scale = Global::new(&mut ctx, "scale".to_string(), scale);
+        scale.set_const(&mut ctx, true);
+        // int zeros[100];
+        let zeros = ConstantValue::AggregateZero { ty: arr_ty };
+        Global::new(&mut ctx, "zeros".to_string(), zeros);
+        // int *ptrs[2] = {&data, 0};
+        let elems = vec![
+            ConstantValue::global_ref(&mut ctx, "data".to_string(), arr_ty),
+            ConstantValue::AggregateZero { ty: ptr },
+        ];
+        let ptrs = ConstantValue::Array { ty: ptrs_ty, elems };
+        Global::new(&mut ctx, "ptrs".to_string(), ptrs);
+
+        let mut codegen_ctx = CodegenContext::new(&ctx);
+        codegen_ctx.codegen();
+        let asm = codegen_ctx.finish().display().to_string();
+        let expected = [
+            ".data\n.global data\n.align 2\n.type data, @object\n.size data, 400\ndata:\n\
+             .word 1\n.word 4294967295\n.zero 392",
+            ".section .rodata\n.global scale\n.align 2\n.type scale, @object\n.size scale, 4\nscale:\n\
+             .word 1075838976",
+            // the zero-initialized data takes no space in the binary
+            ".bss\n.global zeros\n.align 2\n.type zeros, @object\n.size zeros, 400\nzeros:\n\
+             .zero 400",
+            // the address of a global is left to the linker
+            ".data\n.global ptrs\n.align 3\n.type ptrs, @object\n.size ptrs, 16\nptrs:\n\
+             .dword data\n.zero 8",
+        ];
+        let asm = asm.replace('\t', "");
+        for expected in expected {
+            assert!(asm.contains(expected), "{}", asm);
+        }
     }
 
-    // TODO: Add more helper functions.
+    #[test]
+    fn test_div_by_const() { check_div_by_const(8); }
+
+    #[test]
+    fn test_div_by_const_rv32() { check_div_by_const(4); }
+
+    fn check_div_by_const(ptr_size: u32) {
+        use IntBinaryOp::{SDiv, SRem, UDiv, URem};
+
+        let divisors = [1, 2, 3, 7, 8, 10, 641, 1 << 20, i32::MAX, i32::MIN];
+        let divisors = divisors
+            .into_iter()
+            .flat_map(|d| [d, d.wrapping_neg()])
+            .collect::<Vec<_>>();
+        let dividends = [0, 1, 7, 100, 123456789, i32::MAX];
+        let dividends = dividends
+            .into_iter()
+            .flat_map(|x| [x, -x])
+            .chain([i32::MIN])
+            .collect::<Vec<_>>();
+
+        // f_k(x) = x op d
+        let mut ctx = Context::new(ptr_size);
+        let i32 = Ty::i32(&mut ctx);
+        let mut cases = Vec::new();
+        for op in [SDiv, SRem, UDiv, URem] {
+            for &d in divisors.iter() {
+                let name = format!("f{}", cases.len());
+                let func = Func::new(&mut ctx, name.clone(), i32);
+                let x = func.add_param(&mut ctx, i32);
+                let block = Block::new(&mut ctx);
+                func.push_back(&mut ctx, block).unwrap();
+                let d_value = Value::i32(&mut ctx, d);
+                let div = Inst::ibinary(&mut ctx, op, x, d_value);
+                block.push_back(&mut ctx, div).unwrap();
+                let result = div.result(&ctx);
+                let ret = Inst::ret(&mut ctx, result);
+                block.push_back(&mut ctx, ret).unwrap();
+                cases.push((name, op, d));
+            }
+        }
+        // the divider is used for the divisors 1, -1 and `i32::MIN`, and for
+        // the unsigned divisors other than powers of two; on RV32, only the
+        // powers of two are expanded
+        let expanded = |op, d: i32| match op {
+            SDiv | SRem if ptr_size == 4 => {
+                d.unsigned_abs() >= 2 && d != i32::MIN && d.unsigned_abs().is_power_of_two()
+            }
+            SDiv | SRem => d.unsigned_abs() >= 2 && d != i32::MIN,
+            _ => (d as u32).is_power_of_two() && d != 1,
+        };
+
+        let slow_mul = TargetDesc {
+            name: "slow-mul",
+            costs: CostTable {
+                alu: 1,
+                mul: 40,
+                div: 20,
+                load: 3,
+            },
+        };
+        let n_divs = |expanded: &dyn Fn(IntBinaryOp, i32) -> bool| {
+            cases.iter().filter(|(_, op, d)| !expanded(*op, *d)).count()
+        };
+        for (target, n_divs) in [
+            (TargetDesc::default(), n_divs(&expanded)),
+            // only the powers of two are expanded if the multiplication is slow
+            (
+                slow_mul,
+                n_divs(&|op, d| expanded(op, d) && d.unsigned_abs().is_power_of_two()),
+            ),
+        ] {
+            let mut codegen_ctx = CodegenContext::new(&ctx);
+            codegen_ctx.set_target(target);
+            codegen_ctx.codegen();
+            codegen_ctx.regalloc().unwrap();
+            codegen_ctx.after_regalloc();
+            let funcs = codegen_ctx.funcs.clone();
+            let mctx = codegen_ctx.finish();
+
+            let asm = mctx.display().to_string();
+            let divs = asm
+                .lines()
+                .filter(|line| line.starts_with("\tdiv") || line.starts_with("\trem"))
+                .count();
+            assert_eq!(divs, n_divs, "{}", target.name);
+
+            for (name, op, d) in cases.iter() {
+                for &x in dividends.iter() {
+                    let expected = match op {
+                        SDiv => x.wrapping_div(*d),
+                        SRem => x.wrapping_rem(*d),
+                        UDiv => (x as u32 / *d as u32) as i32,
+                        _ => (x as u32 % *d as u32) as i32,
+                    };
+                    let mut machine = Machine {
+                        mctx: &mctx,
+                        funcs: &funcs,
+                        regs: [Machine::GARBAGE; 32],
+                        fregs: [Machine::GARBAGE; 32],
+                        mem: vec![0; 1 << 10],
+                    };
+                    machine.regs[regs::sp().num() as usize] = 1 << 10;
+                    machine.regs[regs::a0().num() as usize] = x as i64 as u64;
+                    machine.call(funcs[&Symbol::intern(name)]);
+                    assert_eq!(
+                        machine.regs[regs::a0().num() as usize],
+                        expected as i64 as u64,
+                        "{} {} {} on {}",
+                        op,
+                        x,
+                        d,
+                        target.name
+                    );
+                }
+            }
+        }
+    }
 }
@@ -2,16 +2,60 @@ use core::fmt;
 
 use super::block::MBlockData;
 use super::func::{MFuncData, MLabel};
-use super::inst::MInstData;
-use super::regs::{RegKind, VReg};
+use super::inst::{MInstData, MInstKind};
+use super::operand::MemLoc;
+use super::regs::{PReg, RegKind, VReg};
 use crate::infra::linked_list::LinkedListContainer;
 use crate::infra::storage::GenericArena;
+use crate::ir::Source;
+
+/// A piece of initialized data, emitted as an assembler directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataItem {
+    /// A byte, i.e., `.byte`.
+    Byte(u8),
+    /// A 32-bit word, i.e., `.word`.
+    Word(u32),
+    /// The 64-bit address of a symbol, i.e., `.dword`, resolved by the linker.
+    Symbol(MLabel),
+    /// The 32-bit address of a symbol, i.e., `.word`, on the 32-bit targets.
+    Symbol32(MLabel),
+    /// Zero bytes, i.e., `.zero`.
+    Zero(usize),
+}
+
+impl DataItem {
+    /// Get the size of the item in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            DataItem::Byte(_) => 1,
+            DataItem::Word(_) => 4,
+            DataItem::Symbol(_) => 8,
+            DataItem::Symbol32(_) => 4,
+            DataItem::Zero(size) => *size,
+        }
+    }
+}
+
+impl fmt::Display for DataItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataItem::Byte(value) => write!(f, ".byte {}", value),
+            DataItem::Word(value) => write!(f, ".word {}", value),
+            DataItem::Symbol(label) => write!(f, ".dword {}", label),
+            DataItem::Symbol32(label) => write!(f, ".word {}", label),
+            DataItem::Zero(size) => write!(f, ".zero {}", size),
+        }
+    }
+}
 
 /// The raw data of the machine code.
 /// e.g. the data section, the bss section.
 pub enum RawData {
-    /// Bytes of the data, declared in the data section.
-    Bytes(Vec<u8>),
+    /// Initialized data, declared in the data section.
+    Data(Vec<DataItem>),
+    /// Read-only data, declared in the rodata section.
+    ReadOnly(Vec<DataItem>),
     /// Zero-initialized bytes of the data, declared in the bss section.
     ///
     /// The field is the size of the zero-initialized data.
@@ -30,14 +74,19 @@ pub struct MContext {
     /// The arena of the functions.
     pub(super) funcs: GenericArena<MFuncData>,
 
-    /// raw data sections
-    raw_data: Vec<(MLabel, RawData)>,
+    /// raw data sections, with the alignment in bytes
+    raw_data: Vec<(MLabel, RawData, usize)>,
 
     /// The counter of the virtual registers.
     vreg_counter: u32,
 
     /// The architecture string.
     arch: String,
+
+    /// The source code to interleave with the assembly, if any.
+    source: Option<Source>,
+    /// The source offset the new instructions are tagged with.
+    curr_loc: Option<u32>,
 }
 
 impl MContext {
@@ -52,8 +101,14 @@ impl MContext {
     }
 
     /// Add a piece of raw data to the context.
-    pub fn add_raw_data(&mut self, label: impl Into<MLabel>, data: RawData) {
-        self.raw_data.push((label.into(), data));
+    ///
+    /// # Parameters
+    /// - `label`: The label of the data.
+    /// - `data`: The content of the data.
+    /// - `align`: The alignment of the data in bytes, must be a power of two.
+    pub fn add_raw_data(&mut self, label: impl Into<MLabel>, data: RawData, align: usize) {
+        debug_assert!(align.is_power_of_two());
+        self.raw_data.push((label.into(), data, align));
     }
 
     /// Set the architecture string to `arch`.
@@ -62,8 +117,113 @@ impl MContext {
     /// Get the architecture string.
     pub fn arch(&self) -> &str { &self.arch }
 
+    /// Get the width of the general registers in bits, 32 for the `rv32`
+    /// architectures and 64 otherwise.
+    pub fn xlen(&self) -> u32 {
+        if self.arch.starts_with("rv32") {
+            32
+        } else {
+            64
+        }
+    }
+
+    /// Set the source code to interleave with the assembly as comments.
+    pub fn set_source(&mut self, source: Source) { self.source = Some(source); }
+
+    /// Set the byte offset in the source to tag the new instructions with.
+    pub fn set_curr_loc(&mut self, loc: Option<u32>) { self.curr_loc = loc; }
+
+    pub fn curr_loc(&self) -> Option<u32> { self.curr_loc }
+
     /// Display the machine code context.
-    pub fn display(&self) -> DisplayMContext { DisplayMContext { mctx: self } }
+    pub fn display(&self) -> DisplayMContext<'_> { DisplayMContext { mctx: self } }
+
+    /// Display the functions as the machine IR, see [`DisplayMir`].
+    pub fn display_mir(&self) -> DisplayMir<'_> { DisplayMir { mctx: self } }
+}
+
+/// The machine IR of the functions, for debugging the backend.
+///
+/// Unlike the assembly, the virtual registers and the stack slots may appear,
+/// the calls and the returns list the registers they read, the blocks list
+/// their successors, and the functions the callee-saved registers they save.
+/// The data is not printed.
+pub struct DisplayMir<'a> {
+    mctx: &'a MContext,
+}
+
+impl fmt::Display for DisplayMir<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mctx = self.mctx;
+        let loc = |loc: &MemLoc| match loc {
+            MemLoc::Slot { offset } => format!("slot[{}]", offset),
+            MemLoc::Incoming { offset } => format!("incoming[{}]", offset),
+            MemLoc::RegOffset { .. } => loc.to_string(),
+        };
+        let regs = |regs: &[PReg]| {
+            regs.iter()
+                .map(PReg::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        for func_data in mctx.funcs.iter() {
+            let func = func_data.self_ptr();
+            if func.is_external(mctx) {
+                continue;
+            }
+            write!(f, "function {}", func.label(mctx))?;
+            let saved_regs = func.saved_regs(mctx);
+            if !saved_regs.is_empty() {
+                write!(f, ", saves {}", regs(&saved_regs))?;
+            }
+            writeln!(f, ":")?;
+
+            for block in func.iter(mctx) {
+                let succs = block
+                    .iter(mctx)
+                    .flat_map(|inst| inst.kind(mctx).successors())
+                    .map(|succ| succ.label(mctx).to_string())
+                    .collect::<Vec<_>>();
+                match succs.is_empty() {
+                    true => writeln!(f, "{}:", block.label(mctx))?,
+                    false => writeln!(
+                        f,
+                        "{}:\t\t\t# succs: {}",
+                        block.label(mctx),
+                        succs.join(", ")
+                    )?,
+                }
+                for inst in block.iter(mctx) {
+                    match inst.kind(mctx) {
+                        MInstKind::Load { op, rd, loc: l } => {
+                            writeln!(f, "\t{} {}, {}", op, rd, loc(l))?
+                        }
+                        MInstKind::Store { op, rs, loc: l } => {
+                            writeln!(f, "\t{} {}, {}", op, rs, loc(l))?
+                        }
+                        MInstKind::LoadAddr {
+                            rd,
+                            loc: l @ (MemLoc::Slot { .. } | MemLoc::Incoming { .. }),
+                        } => writeln!(f, "\taddi {}, {}", rd, loc(l))?,
+                        MInstKind::Call { callee, args } => {
+                            writeln!(f, "\tcall {}({})", callee, regs(args))?
+                        }
+                        MInstKind::TailCall { callee, args } => {
+                            writeln!(f, "\ttail {}({})", callee, regs(args))?
+                        }
+                        MInstKind::Ret { value } => match value {
+                            Some(value) => writeln!(f, "\tret {}", value)?,
+                            None => writeln!(f, "\tret")?,
+                        },
+                        _ => writeln!(f, "\t{}", inst.display(mctx))?,
+                    }
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct DisplayMContext<'a> {
@@ -90,9 +250,34 @@ impl fmt::Display for DisplayMContext<'_> {
             writeln!(f, "\t.type {}, @function", func.label(self.mctx))?;
             writeln!(f, "{}:", func.label(self.mctx))?;
 
+            // The source lines are commented when they change, and on the
+            // labels of the blocks.
+            let mut last_line = None;
             for block in func.iter(self.mctx) {
-                writeln!(f, "{}:", block.label(self.mctx))?;
+                if block.align(self.mctx) > 1 {
+                    writeln!(f, "\t.balign {}", block.align(self.mctx))?;
+                }
+                let source = self.mctx.source.as_ref();
+                let loc = block.iter(self.mctx).find_map(|inst| inst.loc(self.mctx));
+                match source.zip(loc) {
+                    Some((source, loc)) => {
+                        writeln!(
+                            f,
+                            "{}:\t\t\t# {}",
+                            block.label(self.mctx),
+                            source.describe(loc)
+                        )?;
+                        last_line = Some(source.line(loc));
+                    }
+                    None => writeln!(f, "{}:", block.label(self.mctx))?,
+                }
                 for inst in block.iter(self.mctx) {
+                    if let Some((source, loc)) = source.zip(inst.loc(self.mctx)) {
+                        if last_line != Some(source.line(loc)) {
+                            writeln!(f, "\t# {}", source.describe(loc))?;
+                            last_line = Some(source.line(loc));
+                        }
+                    }
                     writeln!(f, "\t{}", inst.display(self.mctx))?;
                 }
             }
@@ -101,27 +286,32 @@ impl fmt::Display for DisplayMContext<'_> {
         }
 
         // The data section, generating the data.
-        for (label, raw_data) in self.mctx.raw_data.iter() {
+        for (label, raw_data, align) in self.mctx.raw_data.iter() {
+            // The `.align` directive takes the log2 of the alignment on RISC-V.
+            let align = align.trailing_zeros();
+            let (section, size) = match raw_data {
+                RawData::Data(items) => (".data", items.iter().map(DataItem::size).sum()),
+                RawData::ReadOnly(items) => {
+                    (".section .rodata", items.iter().map(DataItem::size).sum())
+                }
+                RawData::Bss(size) => (".bss", *size),
+            };
+            writeln!(f, "\t{}", section)?;
+            // The `.L` labels, e.g., of the jump tables, are local.
+            if !label.to_string().starts_with(".L") {
+                writeln!(f, "\t.global {}", label)?;
+            }
+            writeln!(f, "\t.align {}", align)?;
             writeln!(f, "\t.type {}, @object", label)?;
+            writeln!(f, "\t.size {}, {}", label, size)?;
+            writeln!(f, "{}:", label)?;
             match raw_data {
-                RawData::Bytes(bytes) => {
-                    writeln!(f, "\t.data")?;
-                    writeln!(f, "\t.global {}", label)?;
-                    writeln!(f, "\t.align 2")?;
-                    writeln!(f, "{}:", label)?;
-                    for byte in bytes.iter() {
-                        writeln!(f, "\t.byte {}", byte)?;
+                RawData::Data(items) | RawData::ReadOnly(items) => {
+                    for item in items.iter() {
+                        writeln!(f, "\t{}", item)?;
                     }
-                    writeln!(f)?;
-                }
-                RawData::Bss(size) => {
-                    writeln!(f, "\t.bss")?;
-                    writeln!(f, "\t.global {}", label)?;
-                    writeln!(f, "\t.align 2")?;
-                    writeln!(f, "{}:", label)?;
-                    writeln!(f, "\t.zero {}", size)?;
-                    writeln!(f)?;
                 }
+                RawData::Bss(size) => writeln!(f, "\t.zero {}", size)?,
             }
             writeln!(f)?;
         }
@@ -0,0 +1,538 @@
+//! Stack frame lowering.
+//!
+//! After the register allocation, the size of each part of the frame is
+//! known, so the frame is laid out, the prologue and the epilogues are
+//! inserted, and the stack slots are resolved into offsets from `sp` or `s0`.
+//!
+//! The frame pointer `s0` points to the stack pointer at the entry, so the
+//! incoming arguments are right above it:
+//!
+//! ```text
+//!        +-------------------+ <-- s0
+//!        |   ra, s0, s1...   |     saved registers
+//!        +-------------------+
+//!        |    local slots    |     allocas and spilled registers
+//!        +-------------------+
+//!        | outgoing args #8+ |
+//!        +-------------------+ <-- sp
+//! ```
+//!
+//! Each part is aligned to 16 bytes, as required for `sp` by the calling
+//! convention.
+//!
+//! If the function [omits the frame pointer](MFunc::omits_frame_pointer),
+//! `s0` is saved only if allocated, and the incoming arguments are addressed
+//! from `sp` with the frame size, which is always known statically. The
+//! frame pointer is kept by default, so debuggers can walk the stack.
+//!
+//! If the function has the [stack protector](MFunc::has_stack_protector), a
+//! canary is kept at the top of the local slots, right below the saved `ra`.
+//! The prologue copies it from `__stack_chk_guard`, and each epilogue checks it
+//! against the guard, calling `__stack_chk_fail` if an overflowing write to
+//! the local arrays has clobbered it.
+//!
+//! Leaf functions, i.e., the ones making no calls, never clobber `ra`, so it
+//! is not saved. A leaf function without any stack slots or used
+//! callee-saved registers needs no frame at all, and neither the frame
+//! pointer, so the prologue and the epilogues are empty. Tail calls do not
+//! count, since they leave with the epilogue and `ra` intact, like returns.
+
+use super::block::MBlock;
+use super::context::MContext;
+use super::func::{MFunc, MLabel};
+use super::imm::Imm12;
+use super::inst::{AluOpRRI, AluOpRRR, BranchOp, LoadOp, MInst, MInstKind, StoreOp};
+use super::operand::MemLoc;
+use super::regs::{self, PReg, Reg, RegKind};
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+
+/// The layout of the stack frame of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameLayout {
+    /// The registers saved in the prologue, from the top of the frame.
+    ///
+    /// `ra` and `s0` come first, then the used callee-saved registers,
+    /// including the floating-point ones. `ra` is not saved in leaf functions.
+    /// Without the frame pointer, `s0` is one of the callee-saved registers.
+    pub saved_regs: Vec<PReg>,
+    /// If `s0` holds the frame pointer.
+    pub frame_pointer: bool,
+    /// The size of the saved register area.
+    pub saved_size: i64,
+    /// The size of the outgoing argument area.
+    pub outgoing_size: i64,
+    /// The size of the local slots and the outgoing argument area.
+    pub locals_size: i64,
+    /// The slot of the stack canary, if the function has the stack protector.
+    pub canary: Option<i64>,
+}
+
+impl FrameLayout {
+    /// Compute the frame layout of a function.
+    pub fn new(mctx: &MContext, func: MFunc) -> Self {
+        let is_leaf = func.iter(mctx).all(|block| {
+            block
+                .iter(mctx)
+                .all(|inst| !matches!(inst.kind(mctx), MInstKind::Call { .. }))
+        });
+        let frameless =
+            is_leaf && func.storage_stack_size(mctx) == 0 && func.saved_regs(mctx).is_empty();
+        let frame_pointer = !func.omits_frame_pointer(mctx) && !frameless;
+        let saved_regs = (!is_leaf)
+            .then_some(regs::ra())
+            .into_iter()
+            .chain(frame_pointer.then_some(regs::fp()))
+            .chain(func.saved_regs(mctx))
+            .collect::<Vec<_>>();
+        let saved_size = (8 * saved_regs.len() as u64).next_multiple_of(16);
+        let outgoing_size = func.outgoing_stack_size(mctx).next_multiple_of(16);
+        let mut storage_size = func.storage_stack_size(mctx);
+        let canary = func.has_stack_protector(mctx).then(|| {
+            storage_size = storage_size.next_multiple_of(8) + 8;
+            storage_size as i64 - 8
+        });
+        let locals_size = (outgoing_size + storage_size).next_multiple_of(16);
+        Self {
+            saved_regs,
+            frame_pointer,
+            saved_size: saved_size as i64,
+            outgoing_size: outgoing_size as i64,
+            locals_size: locals_size as i64,
+            canary,
+        }
+    }
+
+    /// Get the total size of the frame.
+    pub fn frame_size(&self) -> i64 { self.saved_size + self.locals_size }
+
+    /// Get the location of the `i`-th saved register, with `sp` pointing to
+    /// the bottom of the saved register area.
+    fn saved_loc(&self, i: usize) -> MemLoc {
+        MemLoc::RegOffset {
+            base: regs::sp().into(),
+            offset: self.saved_size - 8 * (i as i64 + 1),
+        }
+    }
+
+    /// Resolve a stack location into a base register and an offset.
+    ///
+    /// Returns `None` if the location is not on the stack.
+    pub fn resolve(&self, loc: MemLoc) -> Option<(PReg, i64)> {
+        match loc {
+            MemLoc::Slot { offset } => Some((regs::sp(), self.outgoing_size + offset)),
+            MemLoc::Incoming { offset } if self.frame_pointer => Some((regs::fp(), offset)),
+            MemLoc::Incoming { offset } => Some((regs::sp(), self.frame_size() + offset)),
+            MemLoc::RegOffset { .. } => None,
+        }
+    }
+}
+
+/// Lower the frame of a function, see the [module docs](self).
+pub fn lower(mctx: &mut MContext, func: MFunc) -> FrameLayout {
+    let layout = FrameLayout::new(mctx, func);
+    let sp: Reg = regs::sp().into();
+    let fp: Reg = regs::fp().into();
+
+    // Prologue.
+    let mut prologue = adjust_sp(mctx, -layout.saved_size);
+    for (i, &reg) in layout.saved_regs.iter().enumerate() {
+        let op = match reg.kind() {
+            RegKind::General => StoreOp::xreg(mctx.xlen()),
+            RegKind::Float => StoreOp::Fsd,
+        };
+        let store = MInst::store(mctx, op, reg.into(), layout.saved_loc(i));
+        prologue.push(store);
+    }
+    if layout.frame_pointer {
+        prologue.push(raw_addi(mctx, fp, sp, layout.saved_size));
+    }
+    prologue.extend(adjust_sp(mctx, -layout.locals_size));
+    if let Some(offset) = layout.canary {
+        let guard = regs::SCRATCH[1].into();
+        prologue.extend(load_guard(mctx, guard));
+        let op = StoreOp::xreg(mctx.xlen());
+        let store = MInst::store(mctx, op, guard, MemLoc::Slot { offset });
+        prologue.push(store);
+    }
+    let entry = func.head(mctx).unwrap();
+    match entry.head(mctx) {
+        Some(head) => head.extend_before(mctx, prologue).unwrap(),
+        None => entry.extend(mctx, prologue).unwrap(),
+    }
+
+    let insts = func
+        .iter(mctx)
+        .flat_map(|block| block.iter(mctx).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let mut chk_fail = None;
+    for inst in insts {
+        // Epilogue.
+        if let MInstKind::Ret { .. } | MInstKind::TailCall { .. } = inst.kind(mctx) {
+            if let Some(offset) = layout.canary {
+                let fail = *chk_fail.get_or_insert_with(|| chk_fail_block(mctx, func));
+                let [canary, guard] = regs::SCRATCH.map(Reg::from);
+                let op = LoadOp::xreg(mctx.xlen());
+                let load = MInst::raw_load(mctx, op, canary, MemLoc::Slot { offset });
+                let mut check = vec![load];
+                check.extend(load_guard(mctx, guard));
+                check.push(MInst::branch(mctx, BranchOp::Bne, canary, guard, fail));
+                inst.extend_before(mctx, check).unwrap();
+                resolve_slot(mctx, &layout, load);
+            }
+            let mut epilogue = if layout.frame_pointer {
+                vec![raw_addi(mctx, sp, fp, -layout.saved_size)]
+            } else {
+                adjust_sp(mctx, layout.locals_size)
+            };
+            for (i, &reg) in layout.saved_regs.iter().enumerate() {
+                let op = match reg.kind() {
+                    RegKind::General => LoadOp::xreg(mctx.xlen()),
+                    RegKind::Float => LoadOp::Fld,
+                };
+                let load = MInst::raw_load(mctx, op, reg.into(), layout.saved_loc(i));
+                epilogue.push(load);
+            }
+            epilogue.extend(adjust_sp(mctx, layout.saved_size));
+            inst.extend_before(mctx, epilogue).unwrap();
+            continue;
+        }
+        resolve_slot(mctx, &layout, inst);
+    }
+    layout
+}
+
+/// Create the instructions loading the value of `__stack_chk_guard`.
+fn load_guard(mctx: &mut MContext, rd: Reg) -> Vec<MInst> {
+    let mut insts = MInst::raw_load_symbol(mctx, rd, MLabel::from("__stack_chk_guard"));
+    let loc = MemLoc::RegOffset {
+        base: rd,
+        offset: 0,
+    };
+    insts.push(MInst::raw_load(mctx, LoadOp::xreg(mctx.xlen()), rd, loc));
+    insts
+}
+
+/// Create the block at the end of a function calling `__stack_chk_fail`, which
+/// does not return.
+fn chk_fail_block(mctx: &mut MContext, func: MFunc) -> MBlock {
+    let label = format!(".L{}.stack_chk_fail", func.label(mctx));
+    let block = MBlock::new(mctx, label);
+    func.push_back(mctx, block).unwrap();
+    let call = MInst::call(mctx, MLabel::from("__stack_chk_fail"), Vec::new());
+    block.push_back(mctx, call).unwrap();
+    block
+}
+
+/// Create `addi rd, rs, imm`, with `imm` fitting in 12 bits.
+fn raw_addi(mctx: &mut MContext, rd: Reg, rs: Reg, imm: i64) -> MInst {
+    let imm = Imm12::try_from_i64(imm).unwrap();
+    MInst::raw_alu_rri(mctx, AluOpRRI::Addi, rd, rs, imm)
+}
+
+/// Create the instructions adding a constant to `sp`.
+fn adjust_sp(mctx: &mut MContext, imm: i64) -> Vec<MInst> {
+    let sp: Reg = regs::sp().into();
+    if imm == 0 {
+        return Vec::new();
+    }
+    if Imm12::try_from_i64(imm).is_some() {
+        return vec![raw_addi(mctx, sp, sp, imm)];
+    }
+    let scratch = regs::SCRATCH[1].into();
+    let mut insts = MInst::raw_load_imm(mctx, scratch, imm);
+    insts.push(MInst::raw_alu_rrr(mctx, AluOpRRR::Add, sp, sp, scratch));
+    insts
+}
+
+/// Resolve the stack slot used by an instruction into an offset from `sp`
+/// or `s0`.
+///
+/// If the offset does not fit in 12 bits, the address is computed in the
+/// destination register of loads and address computations, or in a scratch
+/// register not holding the stored value of stores.
+fn resolve_slot(mctx: &mut MContext, layout: &FrameLayout, inst: MInst) {
+    let (loc, temp) = match inst.kind(mctx) {
+        MInstKind::Load { rd, loc, .. } | MInstKind::LoadAddr { rd, loc } => (*loc, *rd),
+        MInstKind::Store { rs, loc, .. } => {
+            let [scratch0, scratch1] = regs::SCRATCH.map(Reg::from);
+            (*loc, if *rs == scratch1 { scratch0 } else { scratch1 })
+        }
+        _ => return,
+    };
+    let Some((base, offset)) = layout.resolve(loc) else {
+        return;
+    };
+    let resolved = if Imm12::try_from_i64(offset).is_some() {
+        MemLoc::RegOffset {
+            base: base.into(),
+            offset,
+        }
+    } else {
+        let mut insts = MInst::raw_load_imm(mctx, temp, offset);
+        insts.push(MInst::raw_alu_rrr(
+            mctx,
+            AluOpRRR::Add,
+            temp,
+            base.into(),
+            temp,
+        ));
+        inst.extend_before(mctx, insts).unwrap();
+        MemLoc::RegOffset {
+            base: temp,
+            offset: 0,
+        }
+    };
+    match inst.kind_mut(mctx) {
+        MInstKind::Load { loc, .. }
+        | MInstKind::Store { loc, .. }
+        | MInstKind::LoadAddr { loc, .. } => *loc = resolved,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::block::MBlock;
+    use crate::backend::func::MLabel;
+
+    #[test]
+    fn test_frame_lowering() {
+        let mut mctx = MContext::default();
+        let func = MFunc::new(&mut mctx, "f");
+        let block = MBlock::new(&mut mctx, ".Lbb_0");
+        func.push_back(&mut mctx, block).unwrap();
+        func.update_outgoing_stack_size(&mut mctx, 24);
+        let near = func.alloc_storage_slot(&mut mctx, 4, 4);
+        func.alloc_storage_slot(&mut mctx, 4096, 8);
+        let far = func.alloc_storage_slot(&mut mctx, 4, 4);
+        func.add_saved_reg(&mut mctx, regs::s1());
+
+        let (load, _) = MInst::load(&mut mctx, LoadOp::Lw, MemLoc::Slot { offset: near });
+        let store = MInst::store(
+            &mut mctx,
+            StoreOp::Sw,
+            regs::a0().into(),
+            MemLoc::Slot { offset: far },
+        );
+        let call = MInst::call(&mut mctx, MLabel::from("g"), Vec::new());
+        let ret = MInst::ret(&mut mctx, None);
+        block.extend(&mut mctx, [load, store, call, ret]).unwrap();
+
+        let layout = lower(&mut mctx, func);
+        assert_eq!(layout.saved_regs, [regs::ra(), regs::fp(), regs::s1()]);
+        assert_eq!(layout.saved_size, 32);
+        assert_eq!(layout.outgoing_size, 32);
+        assert_eq!(layout.locals_size, 4144);
+        assert_eq!(layout.frame_size(), 4176);
+
+        let asm = block
+            .iter(&mctx)
+            .map(|inst| inst.display(&mctx).to_string())
+            .collect::<Vec<_>>();
+        let expected = [
+            "addi sp, sp, -32",
+            "sd ra, 24(sp)",
+            "sd s0, 16(sp)",
+            "sd s1, 8(sp)",
+            "addi s0, sp, 32",
+            "lui t6, 1048575",
+            "addiw t6, t6, -48",
+            "add sp, sp, t6",
+            "lw $r0, 32(sp)",
+            // the slot is out of the range of the 12-bit immediate
+            "lui t6, 1",
+            "addiw t6, t6, 40",
+            "add t6, sp, t6",
+            "sw a0, 0(t6)",
+            "call g",
+            "addi sp, s0, -32",
+            "ld ra, 24(sp)",
+            "ld s0, 16(sp)",
+            "ld s1, 8(sp)",
+            "addi sp, sp, 32",
+            "ret",
+        ];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn test_frame_lowering_without_fp() {
+        let mut mctx = MContext::default();
+        let func = MFunc::new(&mut mctx, "f");
+        func.set_omit_frame_pointer(&mut mctx, true);
+        let block = MBlock::new(&mut mctx, ".Lbb_0");
+        func.push_back(&mut mctx, block).unwrap();
+        let slot = func.alloc_storage_slot(&mut mctx, 4, 4);
+        func.add_saved_reg(&mut mctx, regs::fp());
+
+        let (load, _) = MInst::load(&mut mctx, LoadOp::Lw, MemLoc::Incoming { offset: 8 });
+        let store = MInst::store(
+            &mut mctx,
+            StoreOp::Sw,
+            regs::fp().into(),
+            MemLoc::Slot { offset: slot },
+        );
+        let call = MInst::call(&mut mctx, MLabel::from("g"), Vec::new());
+        let ret = MInst::ret(&mut mctx, None);
+        block.extend(&mut mctx, [load, store, call, ret]).unwrap();
+
+        let layout = lower(&mut mctx, func);
+        assert_eq!(layout.saved_regs, [regs::ra(), regs::fp()]);
+        assert!(!layout.frame_pointer);
+        assert_eq!(layout.frame_size(), 32);
+
+        let asm = block
+            .iter(&mctx)
+            .map(|inst| inst.display(&mctx).to_string())
+            .collect::<Vec<_>>();
+        let expected = [
+            "addi sp, sp, -16",
+            "sd ra, 8(sp)",
+            "sd s0, 0(sp)",
+            "addi sp, sp, -16",
+            // the incoming arguments are above the whole frame
+            "lw $r0, 40(sp)",
+            "sw s0, 0(sp)",
+            "call g",
+            "addi sp, sp, 16",
+            "ld ra, 8(sp)",
+            "ld s0, 0(sp)",
+            "addi sp, sp, 16",
+            "ret",
+        ];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn test_leaf_frame_lowering() {
+        let mut mctx = MContext::default();
+        let [frameless, leaf] = ["frameless", "leaf"].map(|name| MFunc::new(&mut mctx, name));
+        for func in [frameless, leaf] {
+            let label = format!(".L{}", func.label(&mctx));
+            let block = MBlock::new(&mut mctx, label);
+            func.push_back(&mut mctx, block).unwrap();
+            let (load, _) = MInst::load(&mut mctx, LoadOp::Lw, MemLoc::Incoming { offset: 0 });
+            let ret = MInst::ret(&mut mctx, None);
+            block.extend(&mut mctx, [load, ret]).unwrap();
+        }
+        leaf.add_saved_reg(&mut mctx, regs::s1());
+
+        let asm = |mctx: &MContext, func: MFunc| {
+            let block = func.head(mctx).unwrap();
+            block
+                .iter(mctx)
+                .map(|inst| inst.display(mctx).to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // neither `ra` nor the frame pointer is needed
+        let layout = lower(&mut mctx, frameless);
+        assert!(layout.saved_regs.is_empty() && !layout.frame_pointer);
+        assert_eq!(layout.frame_size(), 0);
+        assert_eq!(asm(&mctx, frameless), ["lw $r0, 0(sp)", "ret"]);
+
+        // `s1` is saved, but `ra` is never clobbered
+        let layout = lower(&mut mctx, leaf);
+        assert_eq!(layout.saved_regs, [regs::fp(), regs::s1()]);
+        let expected = [
+            "addi sp, sp, -16",
+            "sd s0, 8(sp)",
+            "sd s1, 0(sp)",
+            "addi s0, sp, 16",
+            "lw $r1, 0(s0)",
+            "addi sp, s0, -16",
+            "ld s0, 8(sp)",
+            "ld s1, 0(sp)",
+            "addi sp, sp, 16",
+            "ret",
+        ];
+        assert_eq!(asm(&mctx, leaf), expected);
+    }
+
+    #[test]
+    fn test_tail_call_frame_lowering() {
+        let mut mctx = MContext::default();
+        let func = MFunc::new(&mut mctx, "f");
+        let block = MBlock::new(&mut mctx, ".Lbb_0");
+        func.push_back(&mut mctx, block).unwrap();
+        func.add_saved_reg(&mut mctx, regs::s1());
+
+        let tail = MInst::tail_call(&mut mctx, MLabel::from("g"), vec![regs::a0()]);
+        block.push_back(&mut mctx, tail).unwrap();
+
+        // the callee returns to the caller with `ra` intact
+        let layout = lower(&mut mctx, func);
+        assert_eq!(layout.saved_regs, [regs::fp(), regs::s1()]);
+
+        let asm = block
+            .iter(&mctx)
+            .map(|inst| inst.display(&mctx).to_string())
+            .collect::<Vec<_>>();
+        let expected = [
+            "addi sp, sp, -16",
+            "sd s0, 8(sp)",
+            "sd s1, 0(sp)",
+            "addi s0, sp, 16",
+            "addi sp, s0, -16",
+            "ld s0, 8(sp)",
+            "ld s1, 0(sp)",
+            "addi sp, sp, 16",
+            "tail g",
+        ];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn test_stack_protector_frame_lowering() {
+        let mut mctx = MContext::default();
+        let func = MFunc::new(&mut mctx, "f");
+        func.set_stack_protector(&mut mctx, true);
+        let block = MBlock::new(&mut mctx, ".Lbb_0");
+        func.push_back(&mut mctx, block).unwrap();
+        let array = func.alloc_storage_slot(&mut mctx, 12, 4);
+
+        let store = MInst::store(
+            &mut mctx,
+            StoreOp::Sw,
+            regs::a0().into(),
+            MemLoc::Slot { offset: array },
+        );
+        let ret = MInst::ret(&mut mctx, None);
+        block.extend(&mut mctx, [store, ret]).unwrap();
+
+        // the canary is above the array, right below the saved registers
+        let layout = lower(&mut mctx, func);
+        assert_eq!(layout.canary, Some(16));
+        assert_eq!(layout.locals_size, 32);
+
+        let asm = func
+            .iter(&mctx)
+            .flat_map(|block| block.iter(&mctx).collect::<Vec<_>>())
+            .map(|inst| inst.display(&mctx).to_string())
+            .collect::<Vec<_>>();
+        let expected = [
+            "addi sp, sp, -16",
+            "sd s0, 8(sp)",
+            "addi s0, sp, 16",
+            "addi sp, sp, -32",
+            "lui t6, %hi(__stack_chk_guard)",
+            "addi t6, t6, %lo(__stack_chk_guard)",
+            "ld t6, 0(t6)",
+            "sd t6, 16(sp)",
+            "sw a0, 0(sp)",
+            "ld t5, 16(sp)",
+            "lui t6, %hi(__stack_chk_guard)",
+            "addi t6, t6, %lo(__stack_chk_guard)",
+            "ld t6, 0(t6)",
+            "bne t5, t6, .Lf.stack_chk_fail",
+            "addi sp, s0, -16",
+            "ld s0, 8(sp)",
+            "addi sp, sp, 16",
+            "ret",
+            // the failure path is shared by the epilogues
+            "call __stack_chk_fail",
+        ];
+        assert_eq!(asm, expected);
+    }
+}
@@ -58,6 +58,12 @@ pub struct MFuncData {
     /// If this function is an external function, i.e. only the declaration
     /// appeared in the IR.
     is_external: bool,
+    /// If `s0` is allocated as a callee-saved register instead of keeping the
+    /// frame pointer, with the stack slots addressed from `sp`.
+    omit_frame_pointer: bool,
+    /// If the frame is guarded by a stack canary, see
+    /// [`frame`](super::frame).
+    stack_protector: bool,
     // linked-list stuff.
     head: Option<MBlock>,
     tail: Option<MBlock>,
@@ -94,6 +100,8 @@ impl MFunc {
             outgoing_stack_size: 0,
             saved_regs: BTreeSet::default(),
             is_external: false,
+            omit_frame_pointer: false,
+            stack_protector: false,
             head: None,
             tail: None,
         })
@@ -102,6 +110,43 @@ impl MFunc {
     /// Check if the function is an external function (library function).
     pub fn is_external(self, mctx: &MContext) -> bool { self.deref(mctx).is_external }
 
+    /// Mark the function as external or not.
+    pub fn set_external(self, mctx: &mut MContext, is_external: bool) {
+        self.deref_mut(mctx).is_external = is_external;
+    }
+
+    /// Check if the function omits the frame pointer.
+    pub fn omits_frame_pointer(self, mctx: &MContext) -> bool {
+        self.deref(mctx).omit_frame_pointer
+    }
+
+    /// Set whether the function omits the frame pointer, freeing `s0` for the
+    /// register allocation.
+    pub fn set_omit_frame_pointer(self, mctx: &mut MContext, omit: bool) {
+        self.deref_mut(mctx).omit_frame_pointer = omit;
+    }
+
+    /// Check if the frame of the function is guarded by a stack canary.
+    pub fn has_stack_protector(self, mctx: &MContext) -> bool { self.deref(mctx).stack_protector }
+
+    /// Set whether to guard the frame of the function by a stack canary.
+    pub fn set_stack_protector(self, mctx: &mut MContext, protect: bool) {
+        self.deref_mut(mctx).stack_protector = protect;
+    }
+
+    /// Allocate a slot in the storage stack.
+    ///
+    /// # Returns
+    ///
+    /// The offset of the slot from the start of the local slots, as used by
+    /// [`MemLoc::Slot`](super::operand::MemLoc::Slot).
+    pub fn alloc_storage_slot(self, mctx: &mut MContext, size: u64, align: u64) -> i64 {
+        debug_assert!(align.is_power_of_two());
+        let offset = self.storage_stack_size(mctx).next_multiple_of(align);
+        self.deref_mut(mctx).storage_stack_size = offset + size;
+        offset as i64
+    }
+
     /// Add the function's storage stack size by `size`.
     pub fn add_storage_stack_size(self, mctx: &mut MContext, size: u64) {
         self.deref_mut(mctx).storage_stack_size += size;
@@ -42,6 +42,33 @@ impl fmt::Display for Imm12 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.as_i16()) }
 }
 
+/// 20-bit immediate used in `lui`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Imm20(u32);
+
+impl Imm20 {
+    /// Try to create an Imm20 from an i64, as the unsigned 20 bits.
+    ///
+    /// Returns None if the value is out of range.
+    pub fn try_from_i64(x: i64) -> Option<Self> {
+        if (0..=0xfffff).contains(&x) {
+            Some(Imm20(x as u32))
+        } else {
+            None
+        }
+    }
+
+    /// Get the value of `lui` with this immediate, sign-extended.
+    pub fn as_i64(&self) -> i64 { ((self.0 << 12) as i32) as i64 }
+
+    /// Get the raw bits.
+    pub fn bits(&self) -> u32 { self.0 }
+}
+
+impl fmt::Display for Imm20 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.0) }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -2,15 +2,19 @@ use std::fmt;
 
 use super::block::MBlock;
 use super::context::MContext;
-use super::imm::Imm12;
+use super::func::MLabel;
+use super::imm::{Imm12, Imm20};
 use super::operand::MemLoc;
-use super::regs::{Reg, RegKind};
+use super::regs::{self, PReg, Reg, RegKind};
 use crate::infra::linked_list::LinkedListNode;
 use crate::infra::storage::{Arena, ArenaPtr, GenericPtr};
 
 /// The data of the machine instruction.
 pub struct MInstData {
     kind: MInstKind,
+    /// The byte offset in the source of the statement the instruction is
+    /// generated from, if known.
+    loc: Option<u32>,
     next: Option<MInst>,
     prev: Option<MInst>,
     parent: Option<MBlock>,
@@ -39,15 +43,283 @@ pub enum MInstKind {
         rs1: Reg,
         rs2: Reg,
     },
+    /// ALU instructions with two registers (rd and rs), e.g., `sext.b`.
+    AluRR { op: AluOpRR, rd: Reg, rs: Reg },
+    /// Floating-point instructions with three registers (rd, and two rs-s).
+    FpuRRR {
+        op: FpuOpRRR,
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    /// Floating-point conversions and moves between the register kinds.
+    FpuRR { op: FpuOpRR, rd: Reg, rs: Reg },
     /// Load instructions.
     Load { op: LoadOp, rd: Reg, loc: MemLoc },
     /// Store instructions.
     Store { op: StoreOp, rs: Reg, loc: MemLoc },
-    /// Load immediate pseudo instruction.
-    Li { rd: Reg, imm: u64 },
+    /// Load upper immediate, i.e., `rd = sext(imm << 12)`.
+    Lui { rd: Reg, imm: Imm20 },
     /// Jump instructions.
     J { target: MBlock },
-    // TODO: add more instructions as you need.
+    /// Indirect jump through a register, i.e., `jr rs`, with the blocks it
+    /// may jump to, e.g., the entries of a jump table.
+    Jr { rs: Reg, targets: Vec<MBlock> },
+    /// Conditional branch instructions, comparing two registers.
+    Branch {
+        op: BranchOp,
+        rs1: Reg,
+        rs2: Reg,
+        target: MBlock,
+    },
+    /// The upper 20 bits of the address of a symbol, i.e.,
+    /// `lui rd, %hi(label)`.
+    LuiHi { rd: Reg, label: MLabel },
+    /// Add the lower 12 bits of the address of a symbol, i.e.,
+    /// `addi rd, rs, %lo(label)`.
+    AddiLo { rd: Reg, rs: Reg, label: MLabel },
+    /// The address of a memory location, i.e., `addi rd, base, offset` once
+    /// the stack slots are resolved.
+    LoadAddr { rd: Reg, loc: MemLoc },
+    /// Call pseudo instruction, with the registers passing the arguments.
+    Call { callee: MLabel, args: Vec<PReg> },
+    /// Return pseudo instruction, with the register holding the return value.
+    Ret { value: Option<PReg> },
+    /// Tail call pseudo instruction, i.e., `tail callee`, jumping to the
+    /// callee after the epilogue, which then returns to the caller directly.
+    TailCall { callee: MLabel, args: Vec<PReg> },
+}
+
+impl MInstKind {
+    /// Get the registers read by the instruction.
+    pub fn uses(&self) -> Vec<Reg> {
+        let loc_base = |loc: &MemLoc| match loc {
+            MemLoc::RegOffset { base, .. } => Some(*base),
+            _ => None,
+        };
+        match self {
+            MInstKind::AluRRI { rs, .. } | MInstKind::AddiLo { rs, .. } => vec![*rs],
+            MInstKind::AluRRR { rs1, rs2, .. } | MInstKind::FpuRRR { rs1, rs2, .. } => {
+                vec![*rs1, *rs2]
+            }
+            MInstKind::AluRR { rs, .. }
+            | MInstKind::FpuRR { rs, .. }
+            | MInstKind::Jr { rs, .. } => {
+                vec![*rs]
+            }
+            MInstKind::Load { loc, .. } | MInstKind::LoadAddr { loc, .. } => {
+                loc_base(loc).into_iter().collect()
+            }
+            MInstKind::Store { rs, loc, .. } => std::iter::once(*rs).chain(loc_base(loc)).collect(),
+            MInstKind::Branch { rs1, rs2, .. } => vec![*rs1, *rs2],
+            MInstKind::Call { args, .. } | MInstKind::TailCall { args, .. } => {
+                args.iter().map(|&arg| arg.into()).collect()
+            }
+            MInstKind::Ret { value } => value.iter().map(|&value| value.into()).collect(),
+            MInstKind::Lui { .. } | MInstKind::J { .. } | MInstKind::LuiHi { .. } => Vec::new(),
+        }
+    }
+
+    /// Get the registers written by the instruction, including the ones
+    /// clobbered by calls.
+    pub fn defs(&self) -> Vec<Reg> {
+        match self {
+            MInstKind::AluRRI { rd, .. }
+            | MInstKind::AluRRR { rd, .. }
+            | MInstKind::AluRR { rd, .. }
+            | MInstKind::FpuRRR { rd, .. }
+            | MInstKind::FpuRR { rd, .. }
+            | MInstKind::Load { rd, .. }
+            | MInstKind::Lui { rd, .. }
+            | MInstKind::LuiHi { rd, .. }
+            | MInstKind::AddiLo { rd, .. }
+            | MInstKind::LoadAddr { rd, .. } => vec![*rd],
+            MInstKind::Call { .. } => std::iter::once(regs::ra())
+                .chain(regs::CALLER_SAVED)
+                .chain(regs::FLOAT_CALLER_SAVED)
+                .map(Reg::from)
+                .collect(),
+            MInstKind::Store { .. }
+            | MInstKind::J { .. }
+            | MInstKind::Jr { .. }
+            | MInstKind::Branch { .. }
+            | MInstKind::Ret { .. }
+            | MInstKind::TailCall { .. } => Vec::new(),
+        }
+    }
+
+    /// Get the register operands read by the instruction, for rewriting.
+    ///
+    /// The fixed physical registers of calls and returns are not included.
+    pub fn uses_mut(&mut self) -> Vec<&mut Reg> {
+        fn loc_base(loc: &mut MemLoc) -> Option<&mut Reg> {
+            match loc {
+                MemLoc::RegOffset { base, .. } => Some(base),
+                _ => None,
+            }
+        }
+        match self {
+            MInstKind::AluRRI { rs, .. } | MInstKind::AddiLo { rs, .. } => vec![rs],
+            MInstKind::AluRRR { rs1, rs2, .. } | MInstKind::FpuRRR { rs1, rs2, .. } => {
+                vec![rs1, rs2]
+            }
+            MInstKind::AluRR { rs, .. }
+            | MInstKind::FpuRR { rs, .. }
+            | MInstKind::Jr { rs, .. } => {
+                vec![rs]
+            }
+            MInstKind::Load { loc, .. } | MInstKind::LoadAddr { loc, .. } => {
+                loc_base(loc).into_iter().collect()
+            }
+            MInstKind::Store { rs, loc, .. } => std::iter::once(rs).chain(loc_base(loc)).collect(),
+            MInstKind::Branch { rs1, rs2, .. } => vec![rs1, rs2],
+            MInstKind::Lui { .. }
+            | MInstKind::J { .. }
+            | MInstKind::LuiHi { .. }
+            | MInstKind::Call { .. }
+            | MInstKind::Ret { .. }
+            | MInstKind::TailCall { .. } => Vec::new(),
+        }
+    }
+
+    /// Get the register operands written by the instruction, for rewriting.
+    ///
+    /// The registers clobbered by calls are not included.
+    pub fn defs_mut(&mut self) -> Vec<&mut Reg> {
+        match self {
+            MInstKind::AluRRI { rd, .. }
+            | MInstKind::AluRRR { rd, .. }
+            | MInstKind::AluRR { rd, .. }
+            | MInstKind::FpuRRR { rd, .. }
+            | MInstKind::FpuRR { rd, .. }
+            | MInstKind::Load { rd, .. }
+            | MInstKind::Lui { rd, .. }
+            | MInstKind::LuiHi { rd, .. }
+            | MInstKind::AddiLo { rd, .. }
+            | MInstKind::LoadAddr { rd, .. } => vec![rd],
+            MInstKind::Store { .. }
+            | MInstKind::J { .. }
+            | MInstKind::Jr { .. }
+            | MInstKind::Branch { .. }
+            | MInstKind::Call { .. }
+            | MInstKind::Ret { .. }
+            | MInstKind::TailCall { .. } => Vec::new(),
+        }
+    }
+
+    /// Get the blocks the instruction may jump to.
+    pub fn successors(&self) -> Vec<MBlock> {
+        match self {
+            MInstKind::J { target } | MInstKind::Branch { target, .. } => vec![*target],
+            MInstKind::Jr { targets, .. } => targets.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Check if the instruction is a register move, i.e., `addi rd, rs, 0`,
+    /// or `fsgnj rd, rs, rs` for the floating-point registers.
+    ///
+    /// # Returns
+    ///
+    /// The destination and the source registers, if it is a move.
+    pub fn as_move(&self) -> Option<(Reg, Reg)> {
+        match self {
+            MInstKind::AluRRI {
+                op: AluOpRRI::Addi,
+                rd,
+                rs,
+                imm,
+            } if imm.as_i16() == 0 => Some((*rd, *rs)),
+            MInstKind::FpuRRR {
+                op: FpuOpRRR::FsgnjS | FpuOpRRR::FsgnjD,
+                rd,
+                rs1,
+                rs2,
+            } if rs1 == rs2 => Some((*rd, *rs1)),
+            _ => None,
+        }
+    }
+
+    /// Check if the result is a sign-extended 32-bit value, given that the
+    /// register operands are.
+    ///
+    /// The 64-bit arithmetic may carry into the upper 32 bits, so the 32-bit
+    /// values must be computed with the W-suffixed instructions instead.
+    pub fn keeps_sext_word(&self) -> bool {
+        match self {
+            MInstKind::AluRRI { op, rs, imm, .. } => match op {
+                // Moves and small constants.
+                AluOpRRI::Addi => imm.as_i16() == 0 || *rs == regs::zero().into(),
+                AluOpRRI::Srai => imm.as_i16() >= 32,
+                AluOpRRI::Srli => imm.as_i16() > 32,
+                AluOpRRI::Slli => false,
+                AluOpRRI::Addiw
+                | AluOpRRI::Slliw
+                | AluOpRRI::Srliw
+                | AluOpRRI::Sraiw
+                | AluOpRRI::Xori
+                | AluOpRRI::Ori
+                | AluOpRRI::Andi
+                | AluOpRRI::Slti
+                | AluOpRRI::Sltiu => true,
+            },
+            MInstKind::AluRRR { op, .. } => matches!(
+                op,
+                AluOpRRR::Addw
+                    | AluOpRRR::Subw
+                    | AluOpRRR::Sllw
+                    | AluOpRRR::Srlw
+                    | AluOpRRR::Sraw
+                    | AluOpRRR::Mulw
+                    | AluOpRRR::Divw
+                    | AluOpRRR::Divuw
+                    | AluOpRRR::Remw
+                    | AluOpRRR::Remuw
+                    | AluOpRRR::Xor
+                    | AluOpRRR::Or
+                    | AluOpRRR::And
+                    | AluOpRRR::Slt
+                    | AluOpRRR::Sltu
+                    | AluOpRRR::Min
+                    | AluOpRRR::Max
+            ),
+            MInstKind::AluRR { .. } => true,
+            MInstKind::Load { op, .. } => matches!(
+                op,
+                LoadOp::Lb | LoadOp::Lh | LoadOp::Lw | LoadOp::Lbu | LoadOp::Lhu
+            ),
+            MInstKind::Lui { .. } => true,
+            // The comparisons produce 0 or 1.
+            MInstKind::FpuRRR { op, .. } => op.defines_int(),
+            MInstKind::FpuRR { op, .. } => {
+                matches!(op, FpuOpRR::FcvtWS | FpuOpRR::FcvtWD | FpuOpRR::FmvXW)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BranchOp {
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+}
+
+impl fmt::Display for BranchOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BranchOp::Beq => write!(f, "beq"),
+            BranchOp::Bne => write!(f, "bne"),
+            BranchOp::Blt => write!(f, "blt"),
+            BranchOp::Bge => write!(f, "bge"),
+            BranchOp::Bltu => write!(f, "bltu"),
+            BranchOp::Bgeu => write!(f, "bgeu"),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -63,6 +335,17 @@ pub enum LoadOp {
     Fld,
 }
 
+impl LoadOp {
+    /// Get the load of a whole general register, `ld` or `lw` by the register
+    /// width.
+    pub fn xreg(xlen: u32) -> Self {
+        match xlen {
+            32 => LoadOp::Lw,
+            _ => LoadOp::Ld,
+        }
+    }
+}
+
 impl fmt::Display for LoadOp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -89,6 +372,17 @@ pub enum StoreOp {
     Fsd,
 }
 
+impl StoreOp {
+    /// Get the store of a whole general register, `sd` or `sw` by the register
+    /// width.
+    pub fn xreg(xlen: u32) -> Self {
+        match xlen {
+            32 => StoreOp::Sw,
+            _ => StoreOp::Sd,
+        }
+    }
+}
+
 impl fmt::Display for StoreOp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -119,6 +413,42 @@ pub enum AluOpRRI {
     Sltiu,
 }
 
+impl AluOpRRI {
+    /// Get the operation for the register width. On RV32, the registers are
+    /// the words, and the `w` operations do not exist.
+    pub fn for_xlen(self, xlen: u32) -> Self {
+        match (self, xlen) {
+            (AluOpRRI::Addiw, 32) => AluOpRRI::Addi,
+            (AluOpRRI::Slliw, 32) => AluOpRRI::Slli,
+            (AluOpRRI::Srliw, 32) => AluOpRRI::Srli,
+            (AluOpRRI::Sraiw, 32) => AluOpRRI::Srai,
+            (op, _) => op,
+        }
+    }
+
+    /// Get the operation on two registers computing the same, for the
+    /// immediates not fitting in 12 bits.
+    ///
+    /// Returns `None` for shifts, whose amounts always fit.
+    pub fn as_rrr(self) -> Option<AluOpRRR> {
+        match self {
+            AluOpRRI::Addi => Some(AluOpRRR::Add),
+            AluOpRRI::Addiw => Some(AluOpRRR::Addw),
+            AluOpRRI::Xori => Some(AluOpRRR::Xor),
+            AluOpRRI::Ori => Some(AluOpRRR::Or),
+            AluOpRRI::Andi => Some(AluOpRRR::And),
+            AluOpRRI::Slti => Some(AluOpRRR::Slt),
+            AluOpRRI::Sltiu => Some(AluOpRRR::Sltu),
+            AluOpRRI::Slli
+            | AluOpRRI::Slliw
+            | AluOpRRI::Srli
+            | AluOpRRI::Srliw
+            | AluOpRRI::Srai
+            | AluOpRRI::Sraiw => None,
+        }
+    }
+}
+
 impl fmt::Display for AluOpRRI {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -171,6 +501,33 @@ pub enum AluOpRRR {
     Remu,
     Remuw,
     Rew,
+    // zba
+    Sh1add,
+    Sh2add,
+    Sh3add,
+    // zbb
+    Min,
+    Max,
+}
+
+impl AluOpRRR {
+    /// Get the operation for the register width, see
+    /// [`AluOpRRI::for_xlen`].
+    pub fn for_xlen(self, xlen: u32) -> Self {
+        match (self, xlen) {
+            (AluOpRRR::Addw, 32) => AluOpRRR::Add,
+            (AluOpRRR::Subw, 32) => AluOpRRR::Sub,
+            (AluOpRRR::Sllw, 32) => AluOpRRR::Sll,
+            (AluOpRRR::Srlw, 32) => AluOpRRR::Srl,
+            (AluOpRRR::Sraw, 32) => AluOpRRR::Sra,
+            (AluOpRRR::Mulw, 32) => AluOpRRR::Mul,
+            (AluOpRRR::Divw, 32) => AluOpRRR::Div,
+            (AluOpRRR::Divuw, 32) => AluOpRRR::Divu,
+            (AluOpRRR::Remw, 32) => AluOpRRR::Rem,
+            (AluOpRRR::Remuw, 32) => AluOpRRR::Remu,
+            (op, _) => op,
+        }
+    }
 }
 
 impl fmt::Display for AluOpRRR {
@@ -205,6 +562,150 @@ impl fmt::Display for AluOpRRR {
             AluOpRRR::Remu => write!(f, "remu"),
             AluOpRRR::Remuw => write!(f, "remuw"),
             AluOpRRR::Rew => write!(f, "rew"),
+            AluOpRRR::Sh1add => write!(f, "sh1add"),
+            AluOpRRR::Sh2add => write!(f, "sh2add"),
+            AluOpRRR::Sh3add => write!(f, "sh3add"),
+            AluOpRRR::Min => write!(f, "min"),
+            AluOpRRR::Max => write!(f, "max"),
+        }
+    }
+}
+
+/// The ALU operations on one register, from the Zbb extension.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AluOpRR {
+    /// `sext.b`, sign-extending the low 8 bits.
+    SextB,
+    /// `sext.h`, sign-extending the low 16 bits.
+    SextH,
+}
+
+impl fmt::Display for AluOpRR {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AluOpRR::SextB => write!(f, "sext.b"),
+            AluOpRR::SextH => write!(f, "sext.h"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FpuOpRRR {
+    FaddS,
+    FaddD,
+    FsubS,
+    FsubD,
+    FmulS,
+    FmulD,
+    FdivS,
+    FdivD,
+    FeqS,
+    FeqD,
+    FltS,
+    FltD,
+    FleS,
+    FleD,
+    FsgnjS,
+    FsgnjD,
+}
+
+impl FpuOpRRR {
+    /// Check if the result is written to a general purpose register, i.e.,
+    /// for the comparisons.
+    pub fn defines_int(self) -> bool {
+        matches!(
+            self,
+            FpuOpRRR::FeqS
+                | FpuOpRRR::FeqD
+                | FpuOpRRR::FltS
+                | FpuOpRRR::FltD
+                | FpuOpRRR::FleS
+                | FpuOpRRR::FleD
+        )
+    }
+}
+
+impl fmt::Display for FpuOpRRR {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FpuOpRRR::FaddS => write!(f, "fadd.s"),
+            FpuOpRRR::FaddD => write!(f, "fadd.d"),
+            FpuOpRRR::FsubS => write!(f, "fsub.s"),
+            FpuOpRRR::FsubD => write!(f, "fsub.d"),
+            FpuOpRRR::FmulS => write!(f, "fmul.s"),
+            FpuOpRRR::FmulD => write!(f, "fmul.d"),
+            FpuOpRRR::FdivS => write!(f, "fdiv.s"),
+            FpuOpRRR::FdivD => write!(f, "fdiv.d"),
+            FpuOpRRR::FeqS => write!(f, "feq.s"),
+            FpuOpRRR::FeqD => write!(f, "feq.d"),
+            FpuOpRRR::FltS => write!(f, "flt.s"),
+            FpuOpRRR::FltD => write!(f, "flt.d"),
+            FpuOpRRR::FleS => write!(f, "fle.s"),
+            FpuOpRRR::FleD => write!(f, "fle.d"),
+            FpuOpRRR::FsgnjS => write!(f, "fsgnj.s"),
+            FpuOpRRR::FsgnjD => write!(f, "fsgnj.d"),
+        }
+    }
+}
+
+/// The floating-point operations on one register.
+///
+/// The conversions to integers round towards zero, as the casts in C.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FpuOpRR {
+    /// `fcvt.s.w`, from a 32-bit integer to a single.
+    FcvtSW,
+    /// `fcvt.d.w`, from a 32-bit integer to a double.
+    FcvtDW,
+    /// `fcvt.w.s`, from a single to a 32-bit integer.
+    FcvtWS,
+    /// `fcvt.w.d`, from a double to a 32-bit integer.
+    FcvtWD,
+    /// `fcvt.s.d`, from a double to a single.
+    FcvtSD,
+    /// `fcvt.d.s`, from a single to a double.
+    FcvtDS,
+    /// `fmv.w.x`, the bits of a single from a general purpose register.
+    FmvWX,
+    /// `fmv.d.x`, the bits of a double from a general purpose register.
+    FmvDX,
+    /// `fmv.x.w`, the bits of a single to a general purpose register.
+    FmvXW,
+    /// `fmv.x.d`, the bits of a double to a general purpose register.
+    FmvXD,
+}
+
+impl FpuOpRR {
+    /// Get the kind of the destination register.
+    pub fn rd_kind(self) -> RegKind {
+        match self {
+            FpuOpRR::FcvtWS | FpuOpRR::FcvtWD | FpuOpRR::FmvXW | FpuOpRR::FmvXD => RegKind::General,
+            _ => RegKind::Float,
+        }
+    }
+
+    /// Get the kind of the source register.
+    pub fn rs_kind(self) -> RegKind {
+        match self {
+            FpuOpRR::FcvtSW | FpuOpRR::FcvtDW | FpuOpRR::FmvWX | FpuOpRR::FmvDX => RegKind::General,
+            _ => RegKind::Float,
+        }
+    }
+}
+
+impl fmt::Display for FpuOpRR {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FpuOpRR::FcvtSW => write!(f, "fcvt.s.w"),
+            FpuOpRR::FcvtDW => write!(f, "fcvt.d.w"),
+            FpuOpRR::FcvtWS => write!(f, "fcvt.w.s"),
+            FpuOpRR::FcvtWD => write!(f, "fcvt.w.d"),
+            FpuOpRR::FcvtSD => write!(f, "fcvt.s.d"),
+            FpuOpRR::FcvtDS => write!(f, "fcvt.d.s"),
+            FpuOpRR::FmvWX => write!(f, "fmv.w.x"),
+            FpuOpRR::FmvDX => write!(f, "fmv.d.x"),
+            FpuOpRR::FmvXW => write!(f, "fmv.x.w"),
+            FpuOpRR::FmvXD => write!(f, "fmv.x.d"),
         }
     }
 }
@@ -221,27 +722,41 @@ impl MInst {
 
     pub fn kind_mut(self, mctx: &mut MContext) -> &mut MInstKind { &mut self.deref_mut(mctx).kind }
 
-    pub fn display(self, mctx: &MContext) -> DisplayMInst { DisplayMInst { mctx, inst: self } }
+    pub fn display(self, mctx: &MContext) -> DisplayMInst<'_> { DisplayMInst { mctx, inst: self } }
+
+    /// Get the byte offset in the source of the statement the instruction is
+    /// generated from.
+    pub fn loc(self, mctx: &MContext) -> Option<u32> { self.deref(mctx).loc }
+
+    /// Create a new instruction, not linked to any block.
+    fn new(mctx: &mut MContext, kind: MInstKind) -> Self {
+        let loc = mctx.curr_loc();
+        mctx.alloc(MInstData {
+            kind,
+            loc,
+            next: None,
+            prev: None,
+            parent: None,
+        })
+    }
+
+    /// Unlink the instruction from its block and deallocate it.
+    pub fn remove(self, mctx: &mut MContext) {
+        self.unlink(mctx);
+        mctx.try_dealloc(self).unwrap();
+    }
 
     // XXX: These instruction creation methods are just for demonstration.
     // You can refactor them as you need.
 
-    /// Create a new `li` instruction.
+    /// Create the instructions loading an immediate.
     ///
     /// imm: The immediate value.
     ///
-    /// Returns (inst, rd).
-    pub fn li(mctx: &mut MContext, imm: u64) -> (Self, Reg) {
+    /// Returns (insts, rd).
+    pub fn load_imm(mctx: &mut MContext, imm: i64) -> (Vec<Self>, Reg) {
         let rd = mctx.new_vreg(RegKind::General).into();
-        let kind = MInstKind::Li { rd, imm };
-        let data = MInstData {
-            kind,
-            next: None,
-            prev: None,
-            parent: None,
-        };
-        let inst = mctx.alloc(data);
-        (inst, rd)
+        (Self::raw_load_imm(mctx, rd, imm), rd)
     }
 
     /// Create a new `load` instruction.
@@ -251,10 +766,15 @@ impl MInst {
     ///
     /// Returns (inst, rd).
     pub fn load(mctx: &mut MContext, op: LoadOp, loc: MemLoc) -> (Self, Reg) {
-        let rd = mctx.new_vreg(RegKind::General).into();
+        let kind = match op {
+            LoadOp::Flw | LoadOp::Fld => RegKind::Float,
+            _ => RegKind::General,
+        };
+        let rd = mctx.new_vreg(kind).into();
         let kind = MInstKind::Load { op, rd, loc };
         let data = MInstData {
             kind,
+            loc: mctx.curr_loc(),
             next: None,
             prev: None,
             parent: None,
@@ -274,6 +794,7 @@ impl MInst {
         let kind = MInstKind::Store { op, rs, loc };
         let data = MInstData {
             kind,
+            loc: mctx.curr_loc(),
             next: None,
             prev: None,
             parent: None,
@@ -290,9 +811,11 @@ impl MInst {
     /// Returns (inst, rd).
     pub fn alu_rrr(mctx: &mut MContext, op: AluOpRRR, rs1: Reg, rs2: Reg) -> (Self, Reg) {
         let rd = mctx.new_vreg(RegKind::General).into();
+        let op = op.for_xlen(mctx.xlen());
         let kind = MInstKind::AluRRR { op, rd, rs1, rs2 };
         let data = MInstData {
             kind,
+            loc: mctx.curr_loc(),
             next: None,
             prev: None,
             parent: None,
@@ -310,9 +833,11 @@ impl MInst {
     /// Returns (inst, rd).
     pub fn alu_rri(mctx: &mut MContext, op: AluOpRRI, rs: Reg, imm: Imm12) -> (Self, Reg) {
         let rd = mctx.new_vreg(RegKind::General).into();
+        let op = op.for_xlen(mctx.xlen());
         let kind = MInstKind::AluRRI { op, rd, rs, imm };
         let data = MInstData {
             kind,
+            loc: mctx.curr_loc(),
             next: None,
             prev: None,
             parent: None,
@@ -332,9 +857,11 @@ impl MInst {
     ///
     /// Returns the instruction.
     pub fn raw_alu_rri(mctx: &mut MContext, op: AluOpRRI, rd: Reg, rs: Reg, imm: Imm12) -> Self {
+        let op = op.for_xlen(mctx.xlen());
         let kind = MInstKind::AluRRI { op, rd, rs, imm };
         let data = MInstData {
             kind,
+            loc: mctx.curr_loc(),
             next: None,
             prev: None,
             parent: None,
@@ -351,6 +878,7 @@ impl MInst {
         let kind = MInstKind::J { target };
         let data = MInstData {
             kind,
+            loc: mctx.curr_loc(),
             next: None,
             prev: None,
             parent: None,
@@ -358,45 +886,311 @@ impl MInst {
         mctx.alloc(data)
     }
 
-    // TODO: add more instruction creation methods as you need.
+    /// Create a new `jr` instruction.
+    ///
+    /// rs: The register holding the address to jump to.
+    /// targets: The blocks the address may point to.
+    ///
+    /// Returns the instruction.
+    pub fn jr(mctx: &mut MContext, rs: Reg, targets: Vec<MBlock>) -> Self {
+        Self::new(mctx, MInstKind::Jr { rs, targets })
+    }
+
+    /// Create the instructions loading an immediate using raw values.
+    ///
+    /// The immediate is built by `lui` and `addiw` if it fits in 32 bits, or
+    /// by shifting the upper bits built recursively, and adding the lower 12
+    /// bits.
+    ///
+    /// rd: The destination register.
+    /// imm: The immediate value.
+    ///
+    /// Returns the instructions.
+    pub fn raw_load_imm(mctx: &mut MContext, rd: Reg, imm: i64) -> Vec<Self> {
+        // The lower 12 bits, sign-extended, as added by `addi`.
+        let lo12 = (imm << 52) >> 52;
+        if i32::try_from(imm).is_ok() {
+            let hi20 = (imm.wrapping_sub(lo12) >> 12) & 0xfffff;
+            if hi20 == 0 {
+                let imm = Imm12::try_from_i64(lo12).unwrap();
+                let zero = regs::zero().into();
+                return vec![Self::raw_alu_rri(mctx, AluOpRRI::Addi, rd, zero, imm)];
+            }
+            let lui = Self::new(
+                mctx,
+                MInstKind::Lui {
+                    rd,
+                    imm: Imm20::try_from_i64(hi20).unwrap(),
+                },
+            );
+            let mut insts = vec![lui];
+            if lo12 != 0 {
+                // `addiw` wraps around 32 bits, e.g., for `0x7fffffff`.
+                let imm = Imm12::try_from_i64(lo12).unwrap();
+                insts.push(Self::raw_alu_rri(mctx, AluOpRRI::Addiw, rd, rd, imm));
+            }
+            return insts;
+        }
+
+        let hi = imm.wrapping_sub(lo12) >> 12;
+        let shamt = hi.trailing_zeros();
+        let mut insts = Self::raw_load_imm(mctx, rd, hi >> shamt);
+        let shamt = Imm12::try_from_i64(12 + shamt as i64).unwrap();
+        insts.push(Self::raw_alu_rri(mctx, AluOpRRI::Slli, rd, rd, shamt));
+        if lo12 != 0 {
+            let imm = Imm12::try_from_i64(lo12).unwrap();
+            insts.push(Self::raw_alu_rri(mctx, AluOpRRI::Addi, rd, rd, imm));
+        }
+        insts
+    }
+
+    /// Create a new `load` instruction using raw values.
+    ///
+    /// op: LoadOp
+    /// rd: The destination register.
+    /// loc: The memory location.
+    ///
+    /// Returns the instruction.
+    pub fn raw_load(mctx: &mut MContext, op: LoadOp, rd: Reg, loc: MemLoc) -> Self {
+        Self::new(mctx, MInstKind::Load { op, rd, loc })
+    }
+
+    /// Create a new `alu_rrr` instruction using raw values.
+    ///
+    /// op: AluOpRRR
+    /// rd: The destination register.
+    /// rs1: The first source register.
+    /// rs2: The second source register.
+    ///
+    /// Returns the instruction.
+    pub fn raw_alu_rrr(mctx: &mut MContext, op: AluOpRRR, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        let op = op.for_xlen(mctx.xlen());
+        Self::new(mctx, MInstKind::AluRRR { op, rd, rs1, rs2 })
+    }
+
+    /// Create a register move, i.e., `addi rd, rs, 0`, or `fsgnj.d rd, rs,
+    /// rs` for the floating-point registers.
+    ///
+    /// rd: The destination register.
+    /// rs: The source register.
+    ///
+    /// Returns the instruction.
+    pub fn mv(mctx: &mut MContext, rd: Reg, rs: Reg) -> Self {
+        match rd.kind() {
+            RegKind::General => {
+                let imm = Imm12::try_from_i64(0).unwrap();
+                Self::raw_alu_rri(mctx, AluOpRRI::Addi, rd, rs, imm)
+            }
+            RegKind::Float => Self::raw_fpu_rrr(mctx, FpuOpRRR::FsgnjD, rd, rs, rs),
+        }
+    }
+
+    /// Create a new `fpu_rrr` instruction.
+    ///
+    /// op: FpuOpRRR
+    /// rs1: The first source register.
+    /// rs2: The second source register.
+    ///
+    /// Returns (inst, rd).
+    pub fn fpu_rrr(mctx: &mut MContext, op: FpuOpRRR, rs1: Reg, rs2: Reg) -> (Self, Reg) {
+        let kind = if op.defines_int() {
+            RegKind::General
+        } else {
+            RegKind::Float
+        };
+        let rd = mctx.new_vreg(kind).into();
+        (Self::raw_fpu_rrr(mctx, op, rd, rs1, rs2), rd)
+    }
+
+    /// Create a new `fpu_rrr` instruction using raw values.
+    ///
+    /// op: FpuOpRRR
+    /// rd: The destination register.
+    /// rs1: The first source register.
+    /// rs2: The second source register.
+    ///
+    /// Returns the instruction.
+    pub fn raw_fpu_rrr(mctx: &mut MContext, op: FpuOpRRR, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        Self::new(mctx, MInstKind::FpuRRR { op, rd, rs1, rs2 })
+    }
+
+    /// Create a new `alu_rr` instruction.
+    ///
+    /// op: AluOpRR
+    /// rs: The source register.
+    ///
+    /// Returns (inst, rd).
+    pub fn alu_rr(mctx: &mut MContext, op: AluOpRR, rs: Reg) -> (Self, Reg) {
+        let rd = mctx.new_vreg(RegKind::General).into();
+        (Self::new(mctx, MInstKind::AluRR { op, rd, rs }), rd)
+    }
+
+    /// Create a new `fpu_rr` instruction.
+    ///
+    /// op: FpuOpRR
+    /// rs: The source register.
+    ///
+    /// Returns (inst, rd).
+    pub fn fpu_rr(mctx: &mut MContext, op: FpuOpRR, rs: Reg) -> (Self, Reg) {
+        let rd = mctx.new_vreg(op.rd_kind()).into();
+        (Self::raw_fpu_rr(mctx, op, rd, rs), rd)
+    }
+
+    /// Create a new `fpu_rr` instruction using raw values.
+    ///
+    /// op: FpuOpRR
+    /// rd: The destination register.
+    /// rs: The source register.
+    ///
+    /// Returns the instruction.
+    pub fn raw_fpu_rr(mctx: &mut MContext, op: FpuOpRR, rd: Reg, rs: Reg) -> Self {
+        Self::new(mctx, MInstKind::FpuRR { op, rd, rs })
+    }
+
+    /// Create a new conditional branch instruction.
+    ///
+    /// op: BranchOp
+    /// rs1: The first register to compare.
+    /// rs2: The second register to compare.
+    /// target: The block to jump to if the condition holds.
+    ///
+    /// Returns the instruction.
+    pub fn branch(mctx: &mut MContext, op: BranchOp, rs1: Reg, rs2: Reg, target: MBlock) -> Self {
+        Self::new(
+            mctx,
+            MInstKind::Branch {
+                op,
+                rs1,
+                rs2,
+                target,
+            },
+        )
+    }
+
+    /// Create the instructions loading the address of a symbol, i.e.,
+    /// `lui rd, %hi(label)` and `addi rd, rd, %lo(label)`.
+    ///
+    /// label: The symbol to load the address of.
+    ///
+    /// Returns (insts, rd).
+    pub fn load_symbol(mctx: &mut MContext, label: MLabel) -> (Vec<Self>, Reg) {
+        let rd = mctx.new_vreg(RegKind::General).into();
+        (Self::raw_load_symbol(mctx, rd, label), rd)
+    }
+
+    /// Create the instructions loading the address of a symbol using raw
+    /// values.
+    ///
+    /// rd: The destination register.
+    /// label: The symbol to load the address of.
+    ///
+    /// Returns the instructions.
+    pub fn raw_load_symbol(mctx: &mut MContext, rd: Reg, label: MLabel) -> Vec<Self> {
+        let hi = Self::new(
+            mctx,
+            MInstKind::LuiHi {
+                rd,
+                label: label.clone(),
+            },
+        );
+        let lo = Self::new(mctx, MInstKind::AddiLo { rd, rs: rd, label });
+        vec![hi, lo]
+    }
+
+    /// Create a new instruction computing the address of a memory location.
+    ///
+    /// loc: The memory location.
+    ///
+    /// Returns (inst, rd).
+    pub fn load_addr(mctx: &mut MContext, loc: MemLoc) -> (Self, Reg) {
+        let rd = mctx.new_vreg(RegKind::General).into();
+        (Self::new(mctx, MInstKind::LoadAddr { rd, loc }), rd)
+    }
+
+    /// Create a new instruction computing the address of a memory location
+    /// using raw values.
+    ///
+    /// rd: The destination register.
+    /// loc: The memory location.
+    ///
+    /// Returns the instruction.
+    pub fn raw_load_addr(mctx: &mut MContext, rd: Reg, loc: MemLoc) -> Self {
+        Self::new(mctx, MInstKind::LoadAddr { rd, loc })
+    }
+
+    /// Create a new `call` instruction.
+    ///
+    /// callee: The label of the called function.
+    /// args: The registers holding the arguments.
+    ///
+    /// Returns the instruction.
+    pub fn call(mctx: &mut MContext, callee: MLabel, args: Vec<PReg>) -> Self {
+        Self::new(mctx, MInstKind::Call { callee, args })
+    }
+
+    /// Create a new `ret` instruction.
+    ///
+    /// value: The register holding the return value, if any.
+    ///
+    /// Returns the instruction.
+    pub fn ret(mctx: &mut MContext, value: Option<PReg>) -> Self {
+        Self::new(mctx, MInstKind::Ret { value })
+    }
+
+    /// Create a new `tail` instruction.
+    ///
+    /// callee: The label of the called function.
+    /// args: The registers holding the arguments.
+    ///
+    /// Returns the instruction.
+    pub fn tail_call(mctx: &mut MContext, callee: MLabel, args: Vec<PReg>) -> Self {
+        Self::new(mctx, MInstKind::TailCall { callee, args })
+    }
+}
+
+impl fmt::Display for MemLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemLoc::RegOffset { base, offset } => write!(f, "{}({})", offset, base),
+            MemLoc::Slot { offset } => write!(f, "{}(??? SLOT)", offset),
+            MemLoc::Incoming { offset } => write!(f, "{}(??? INCOMING)", offset),
+        }
+    }
 }
 
 impl fmt::Display for DisplayMInst<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.inst.deref(self.mctx).kind {
-            MInstKind::Li { rd, imm } => write!(f, "li {}, {}", rd, imm),
-            MInstKind::Load { op, rd, loc } => {
-                let slot = match loc {
-                    MemLoc::RegOffset { base, offset } => {
-                        format!("{}({})", offset, base)
-                    }
-                    MemLoc::Slot { offset } => {
-                        format!("{}(??? SLOT)", offset)
-                    }
-                    MemLoc::Incoming { offset } => {
-                        format!("{}(??? INCOMING)", offset)
-                    }
-                };
-                write!(f, "{} {}, {}", op, rd, slot)
-            }
-            MInstKind::Store { op, rs, loc } => {
-                let slot = match loc {
-                    MemLoc::RegOffset { base, offset } => {
-                        format!("{}({})", offset, base)
-                    }
-                    MemLoc::Slot { offset } => {
-                        format!("{}(??? SLOT)", offset)
-                    }
-                    MemLoc::Incoming { offset } => {
-                        format!("{}(??? INCOMING)", offset)
-                    }
-                };
-                write!(f, "{} {}, {}", op, rs, slot)
-            }
+        match &self.inst.deref(self.mctx).kind {
+            MInstKind::Lui { rd, imm } => write!(f, "lui {}, {}", rd, imm),
+            MInstKind::Load { op, rd, loc } => write!(f, "{} {}, {}", op, rd, loc),
+            MInstKind::Store { op, rs, loc } => write!(f, "{} {}, {}", op, rs, loc),
             MInstKind::AluRRR { op, rd, rs1, rs2 } => write!(f, "{} {}, {}, {}", op, rd, rs1, rs2),
             MInstKind::AluRRI { op, rd, rs, imm } => write!(f, "{} {}, {}, {}", op, rd, rs, imm),
+            MInstKind::AluRR { op, rd, rs } => write!(f, "{} {}, {}", op, rd, rs),
+            MInstKind::FpuRRR { op, rd, rs1, rs2 } => write!(f, "{} {}, {}, {}", op, rd, rs1, rs2),
+            MInstKind::FpuRR { op, rd, rs } => match op {
+                FpuOpRR::FcvtWS | FpuOpRR::FcvtWD => write!(f, "{} {}, {}, rtz", op, rd, rs),
+                _ => write!(f, "{} {}, {}", op, rd, rs),
+            },
             MInstKind::J { target } => write!(f, "j {}", target.label(self.mctx)),
-            // TODO: implement display for more machine instructions
+            MInstKind::Jr { rs, .. } => write!(f, "jr {}", rs),
+            MInstKind::Branch {
+                op,
+                rs1,
+                rs2,
+                target,
+            } => write!(f, "{} {}, {}, {}", op, rs1, rs2, target.label(self.mctx)),
+            MInstKind::LuiHi { rd, label } => write!(f, "lui {}, %hi({})", rd, label),
+            MInstKind::AddiLo { rd, rs, label } => write!(f, "addi {}, {}, %lo({})", rd, rs, label),
+            MInstKind::LoadAddr { rd, loc } => match loc {
+                MemLoc::RegOffset { base, offset } => {
+                    write!(f, "addi {}, {}, {}", rd, base, offset)
+                }
+                _ => write!(f, "addi {}, {}", rd, loc),
+            },
+            MInstKind::Call { callee, .. } => write!(f, "call {}", callee),
+            MInstKind::Ret { .. } => write!(f, "ret"),
+            MInstKind::TailCall { callee, .. } => write!(f, "tail {}", callee),
         }
     }
 }
@@ -445,3 +1239,57 @@ impl Arena<MInst> for MContext {
 
     fn try_dealloc(&mut self, ptr: MInst) -> Option<MInstData> { self.insts.try_dealloc(ptr.0) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_imm() {
+        let mut mctx = MContext::default();
+        let values = [
+            0,
+            1,
+            -1,
+            2047,
+            -2048,
+            2048,
+            -2049,
+            0x1000,
+            0x7fff_ffff,
+            i32::MIN as i64,
+            0x8000_0000,
+            0xffff_ffff,
+            0x1_0000_0000,
+            0x1234_5678_9abc_def0,
+            -0x1_2345_6789,
+            i64::MAX,
+            i64::MIN,
+        ];
+        for imm in values {
+            let rd = regs::t0().into();
+            let insts = MInst::raw_load_imm(&mut mctx, rd, imm);
+            if i32::try_from(imm).is_ok() {
+                assert!(insts.len() <= 2, "{}", imm);
+            }
+            let mut value = 0i64;
+            for inst in insts {
+                value = match inst.kind(&mctx) {
+                    MInstKind::Lui { imm, .. } => imm.as_i64(),
+                    MInstKind::AluRRI { op, rs, imm, .. } => {
+                        let rs = if *rs == rd { value } else { 0 };
+                        let imm = imm.as_i16() as i64;
+                        match op {
+                            AluOpRRI::Addi => rs.wrapping_add(imm),
+                            AluOpRRI::Addiw => rs.wrapping_add(imm) as i32 as i64,
+                            AluOpRRI::Slli => rs << imm,
+                            _ => unreachable!(),
+                        }
+                    }
+                    _ => unreachable!(),
+                };
+            }
+            assert_eq!(value, imm);
+        }
+    }
+}
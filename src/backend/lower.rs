@@ -0,0 +1,222 @@
+//! Shared lowering of the targets emitting assembly directly from the IR.
+//!
+//! The RISC-V backend selects the instructions into the machine IR and
+//! allocates the registers. The other targets keep every value in a stack
+//! slot instead, and load the operands into scratch registers around each
+//! instruction. The code is slow, but simple enough to check the
+//! machine-independent layers against another architecture.
+//!
+//! The frame of a function is addressed from the stack pointer, from the
+//! bottom:
+//!
+//! - The outgoing arguments passed on the stack.
+//! - The slots of the values, 8 bytes each.
+//! - The shadow slots of the phis and the block parameters. The copies on an
+//!   edge are parallel, so the sources are all read into the shadows before any
+//!   destination is written.
+//! - The memory of the allocas.
+//!
+//! The saved registers above are up to the targets.
+
+use std::collections::HashMap;
+
+use super::context::DataItem;
+use super::func::MLabel;
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::{self, ConstantValue, Source, Value};
+
+/// The stack frame of a function, see the [module docs](self).
+pub struct StackFrame {
+    /// The offsets of the value slots.
+    slots: HashMap<Value, i64>,
+    /// The offsets of the shadow slots.
+    shadows: HashMap<Value, i64>,
+    /// The offsets of the memory of the allocas.
+    allocas: HashMap<Value, i64>,
+    /// The size of the frame, a multiple of 16.
+    size: i64,
+}
+
+impl StackFrame {
+    /// Lay out the frame of a function.
+    ///
+    /// `stack_args` is the number of the 8-byte stack slots taken by the
+    /// arguments of a call, by the calling convention of the target.
+    pub fn new(ctx: &ir::Context, func: ir::Func, stack_args: impl Fn(&[ir::Ty]) -> usize) -> Self {
+        let mut outgoing = 0;
+        let mut values = func.params(ctx).to_vec();
+        let mut copied = Vec::new();
+        let mut allocas = Vec::new();
+        for block in func.iter(ctx) {
+            values.extend(block.params(ctx));
+            copied.extend(block.params(ctx));
+            for inst in block.iter(ctx) {
+                match inst.kind(ctx) {
+                    // The address of the memory is not kept in a slot.
+                    ir::InstKind::Alloca { ty } => {
+                        allocas.push((inst.result(ctx).unwrap(), *ty));
+                        continue;
+                    }
+                    ir::InstKind::Phi => copied.push(inst.result(ctx).unwrap()),
+                    ir::InstKind::Call => {
+                        let tys = inst
+                            .operand_iter(ctx)
+                            .skip(1)
+                            .map(|arg| arg.ty(ctx))
+                            .collect::<Vec<_>>();
+                        outgoing = outgoing.max(8 * stack_args(&tys) as i64);
+                    }
+                    _ => {}
+                }
+                values.extend(inst.result(ctx));
+            }
+        }
+
+        let mut offset = outgoing;
+        let mut alloc = |size: i64, align: i64| {
+            offset = (offset + align - 1) & -align;
+            offset += size;
+            offset - size
+        };
+        let slots = values.into_iter().map(|v| (v, alloc(8, 8))).collect();
+        let shadows = copied.into_iter().map(|v| (v, alloc(8, 8))).collect();
+        let allocas = allocas
+            .into_iter()
+            .map(|(v, ty)| {
+                (
+                    v,
+                    alloc(ty.bytewidth(ctx) as i64, ty.align(ctx).max(1) as i64),
+                )
+            })
+            .collect();
+        Self {
+            slots,
+            shadows,
+            allocas,
+            size: (offset + 15) & -16,
+        }
+    }
+
+    /// Get the offset of the slot of a value.
+    pub fn slot(&self, value: Value) -> i64 { self.slots[&value] }
+
+    /// Get the offset of the shadow slot of a phi or a block parameter.
+    pub fn shadow(&self, value: Value) -> i64 { self.shadows[&value] }
+
+    /// Get the offset of the memory of an alloca, or `None` if the value is
+    /// not one.
+    pub fn alloca(&self, value: Value) -> Option<i64> { self.allocas.get(&value).copied() }
+
+    /// Get the size of the frame.
+    pub fn size(&self) -> i64 { self.size }
+}
+
+/// Get the copies on the edge to a successor of a branch, as pairs of the
+/// destination and the source.
+///
+/// The destinations are the block parameters and the phis of the successor.
+pub fn edge_copies(ctx: &ir::Context, branch: ir::Inst, succ_idx: usize) -> Vec<(Value, Value)> {
+    let block = branch.container(ctx).unwrap();
+    let succ = branch.successor(ctx, succ_idx);
+    let mut copies = succ
+        .params(ctx)
+        .iter()
+        .copied()
+        .zip(branch.successor_args(ctx, succ_idx))
+        .collect::<Vec<_>>();
+    copies.extend(
+        succ.iter(ctx)
+            .take_while(|inst| inst.is_phi(ctx))
+            .map(|phi| (phi.result(ctx).unwrap(), phi.incoming(ctx, block))),
+    );
+    copies
+}
+
+/// Get the label of a block.
+pub fn block_label(ctx: &ir::Context, block: ir::Block) -> String {
+    format!(".L{}", block.name(ctx).trim_start_matches('%'))
+}
+
+/// The comments of the source lines, interleaved with the assembly of a
+/// function.
+pub struct SourceComments<'a> {
+    /// The source, or `None` if the assembly is not commented.
+    source: Option<&'a Source>,
+    /// The comment marker of the assembler.
+    marker: &'static str,
+    /// The line commented last.
+    last_line: Option<usize>,
+}
+
+impl<'a> SourceComments<'a> {
+    pub fn new(source: Option<&'a Source>, marker: &'static str) -> Self {
+        Self {
+            source,
+            marker,
+            last_line: None,
+        }
+    }
+
+    /// Get the comment of the label of a block, the line of its first tagged
+    /// instruction. The allocas are skipped, they are hoisted to the entry.
+    pub fn block(&mut self, ctx: &ir::Context, block: ir::Block) -> Option<String> {
+        let loc = block
+            .iter(ctx)
+            .filter(|inst| !matches!(inst.kind(ctx), ir::InstKind::Alloca { .. }))
+            .find_map(|inst| inst.loc(ctx));
+        self.comment(loc)
+    }
+
+    /// Get the comment before an instruction, if it starts another line.
+    pub fn inst(&mut self, ctx: &ir::Context, inst: ir::Inst) -> Option<String> {
+        if matches!(inst.kind(ctx), ir::InstKind::Alloca { .. }) {
+            return None;
+        }
+        let loc = inst.loc(ctx);
+        let line = self.source.zip(loc).map(|(source, loc)| source.line(loc));
+        if line.is_none() || line == self.last_line {
+            return None;
+        }
+        self.comment(loc)
+    }
+
+    fn comment(&mut self, loc: Option<u32>) -> Option<String> {
+        let (source, loc) = self.source.zip(loc)?;
+        self.last_line = Some(source.line(loc));
+        Some(format!("{} {}", self.marker, source.describe(loc)))
+    }
+}
+
+/// Append the data directives of a constant.
+///
+/// The zero parts, e.g., the tails of partially initialized arrays, are
+/// merged into single `.zero` directives.
+pub fn constant_items(ctx: &ir::Context, value: &ConstantValue, items: &mut Vec<DataItem>) {
+    if value.is_zero() || matches!(value, ConstantValue::Undef { .. }) {
+        let size = value.ty().bytewidth(ctx);
+        match items.last_mut() {
+            Some(DataItem::Zero(zeros)) => *zeros += size,
+            _ => items.push(DataItem::Zero(size)),
+        }
+        return;
+    }
+    match value {
+        ConstantValue::Int1 { value, .. } => items.push(DataItem::Byte(*value as u8)),
+        ConstantValue::Int8 { value, .. } => items.push(DataItem::Byte(*value as u8)),
+        ConstantValue::Int32 { value, .. } => items.push(DataItem::Word(*value as u32)),
+        ConstantValue::Float32 { bits, .. } => items.push(DataItem::Word(*bits)),
+        ConstantValue::Array { elems, .. } => {
+            for elem in elems {
+                constant_items(ctx, elem, items);
+            }
+        }
+        ConstantValue::GlobalRef { name, .. } => {
+            let label = MLabel::from(name);
+            match ctx.target_info().ptr_size {
+                4 => items.push(DataItem::Symbol32(label)),
+                _ => items.push(DataItem::Symbol(label)),
+            }
+        }
+        ConstantValue::Undef { .. } | ConstantValue::AggregateZero { .. } => unreachable!(),
+    }
+}
@@ -0,0 +1,889 @@
+//! Register allocation.
+//!
+//! The liveness of the registers is computed on the machine code, as lists of
+//! ranges of program points. Each instruction has two points, one where the
+//! operands are read and one where the results are written, so an instruction
+//! can reuse the register of an operand read for the last time.
+//!
+//! Virtual registers are assigned in the order of their first live points,
+//! each to the first allocatable register whose ranges do not overlap with
+//! its own. The physical registers fixed by calls, returns and parameter
+//! passing take part in the liveness as well, so a virtual register live
+//! across a call never gets a caller-saved register.
+//!
+//! A virtual register that cannot be assigned is split: its value lives in a
+//! stack slot, and each block reloads it into a new virtual register before
+//! the first use and after each call, so the new registers are short and
+//! local to blocks. A virtual register live across calls is split as well if
+//! it would be the first to occupy a callee-saved register, and the loads and
+//! the stores out of loops are cheaper than saving and restoring the register.
+//! The allocation is then retried with the new registers.
+//!
+//! A new register that still cannot be assigned is spilled: each use reloads
+//! it into one of the [scratch registers](regs::scratch) of its kind, and each
+//! definition stores it back.
+//!
+//! The general purpose and the floating-point registers are allocated
+//! alike, each virtual register only to the physical registers of its kind.
+//!
+//! The first assignment only reads the machine code, so the functions are
+//! [planned](plan) in parallel, on the threads of `--jobs`, and only the
+//! splits, the retries and the rewrites are done one function at a time.
+//!
+//! With `--verify-machineinstrs`, the result is checked by [`verify`], which
+//! follows the values of the virtual registers through the physical registers
+//! and the spill slots, so an allocation bug is reported instead of
+//! miscompiling.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::block::MBlock;
+use super::context::MContext;
+use super::func::MFunc;
+use super::inst::{LoadOp, MInst, MInstKind, StoreOp};
+use super::operand::MemLoc;
+use super::regs::{self, PReg, Reg, RegKind};
+use super::verify::{Problem, Stage, VerifyError};
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+
+/// A range of program points, both ends included.
+type Range = (u32, u32);
+
+/// Get the registers that can be assigned to virtual registers, in the order
+/// of preference.
+///
+/// Caller-saved registers come first, so callee-saved ones are only saved
+/// when needed. The general purpose registers come before the floating-point
+/// ones. `s0` is allocatable only if the function omits the frame pointer.
+pub fn allocatable(omit_frame_pointer: bool) -> Vec<PReg> {
+    regs::CALLER_SAVED
+        .into_iter()
+        .filter(|reg| !regs::SCRATCH.contains(reg))
+        .chain(regs::CALLEE_SAVED)
+        .chain(omit_frame_pointer.then_some(regs::fp()))
+        .chain(
+            regs::FLOAT_CALLER_SAVED
+                .into_iter()
+                .filter(|reg| !regs::FLOAT_SCRATCH.contains(reg)),
+        )
+        .chain(regs::FLOAT_CALLEE_SAVED)
+        .collect()
+}
+
+/// Get the load and the store to reload and spill a register of a kind, with
+/// the width of the general registers.
+fn spill_ops(kind: RegKind, xlen: u32) -> (LoadOp, StoreOp) {
+    match kind {
+        RegKind::General => (LoadOp::xreg(xlen), StoreOp::xreg(xlen)),
+        RegKind::Float => (LoadOp::Fld, StoreOp::Fsd),
+    }
+}
+
+/// The live ranges of the registers in a function.
+pub struct Liveness {
+    /// The program point of the first instruction of each block.
+    pub block_starts: HashMap<MBlock, u32>,
+    /// The live ranges of each register, sorted by the start points.
+    pub ranges: HashMap<Reg, Vec<Range>>,
+    /// The program points where calls clobber the caller-saved registers.
+    pub calls: Vec<u32>,
+}
+
+impl Liveness {
+    /// Get the program point where an instruction reads its operands.
+    ///
+    /// The instruction writes its results at the next point.
+    fn use_point(idx: u32) -> u32 { 2 * idx }
+
+    /// Compute the liveness of the virtual registers and the allocatable
+    /// physical registers in a function.
+    pub fn new(mctx: &MContext, func: MFunc) -> Self {
+        let allocatable = allocatable(func.omits_frame_pointer(mctx))
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let tracked = |reg: &Reg| match reg {
+            Reg::V(_) => true,
+            Reg::P(preg) => allocatable.contains(preg),
+        };
+
+        let blocks = func.iter(mctx).collect::<Vec<_>>();
+        let mut gen: HashMap<MBlock, HashSet<Reg>> = HashMap::new();
+        let mut kill: HashMap<MBlock, HashSet<Reg>> = HashMap::new();
+        let mut succs: HashMap<MBlock, Vec<MBlock>> = HashMap::new();
+        for &block in blocks.iter() {
+            let (gen, kill) = (
+                gen.entry(block).or_default(),
+                kill.entry(block).or_default(),
+            );
+            for inst in block.iter(mctx) {
+                let kind = inst.kind(mctx);
+                for reg in kind.uses().into_iter().filter(tracked) {
+                    if !kill.contains(&reg) {
+                        gen.insert(reg);
+                    }
+                }
+                kill.extend(kind.defs().into_iter().filter(tracked));
+                succs.entry(block).or_default().extend(kind.successors());
+            }
+        }
+
+        let mut live_in: HashMap<MBlock, HashSet<Reg>> = HashMap::new();
+        let mut live_out: HashMap<MBlock, HashSet<Reg>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in blocks.iter().rev() {
+                let out = succs
+                    .get(&block)
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|succ| live_in.get(succ).into_iter().flatten().copied())
+                    .collect::<HashSet<_>>();
+                let mut live = out
+                    .difference(&kill[&block])
+                    .copied()
+                    .collect::<HashSet<_>>();
+                live.extend(gen[&block].iter().copied());
+                if live_in.get(&block) != Some(&live) {
+                    live_in.insert(block, live);
+                    changed = true;
+                }
+                live_out.insert(block, out);
+            }
+        }
+
+        let mut block_starts = HashMap::new();
+        let mut ranges: HashMap<Reg, Vec<Range>> = HashMap::new();
+        let mut calls = Vec::new();
+        let mut idx = 0;
+        for &block in blocks.iter() {
+            let len = block.iter(mctx).count() as u32;
+            let start = Self::use_point(idx);
+            block_starts.insert(block, start);
+            idx += len;
+            let end = Self::use_point(idx).saturating_sub(1).max(start);
+
+            // Scan backwards, with the end point of each live register.
+            let mut live = live_out[&block]
+                .iter()
+                .map(|&reg| (reg, end))
+                .collect::<HashMap<_, _>>();
+            for (i, inst) in (0..len).rev().zip(block.iter_rev(mctx)) {
+                let point = Self::use_point(start / 2 + i);
+                let kind = inst.kind(mctx);
+                if let MInstKind::Call { .. } = kind {
+                    calls.push(point + 1);
+                }
+                for reg in kind.defs().into_iter().filter(tracked) {
+                    let range_end = live.remove(&reg).unwrap_or(point + 1);
+                    ranges.entry(reg).or_default().push((point + 1, range_end));
+                }
+                for reg in kind.uses().into_iter().filter(tracked) {
+                    live.entry(reg).or_insert(point);
+                }
+            }
+            for (reg, range_end) in live {
+                ranges.entry(reg).or_default().push((start, range_end));
+            }
+        }
+        for ranges in ranges.values_mut() {
+            ranges.sort_unstable();
+        }
+
+        calls.sort_unstable();
+
+        Self {
+            block_starts,
+            ranges,
+            calls,
+        }
+    }
+
+    /// Check if a register is live across a call.
+    pub fn crosses_call(&self, reg: Reg) -> bool {
+        self.ranges[&reg].iter().any(|&(start, end)| {
+            let idx = self.calls.partition_point(|&point| point < start);
+            self.calls.get(idx).is_some_and(|&point| point <= end)
+        })
+    }
+}
+
+/// The ranges occupied by a physical register, without overlaps.
+#[derive(Default)]
+struct Occupancy(BTreeMap<u32, u32>);
+
+impl Occupancy {
+    /// Check if a range overlaps with any occupied one.
+    fn overlaps(&self, (start, end): Range) -> bool {
+        self.0
+            .range(..=end)
+            .next_back()
+            .is_some_and(|(_, &occupied_end)| occupied_end >= start)
+    }
+
+    /// Occupy a range, merging it with the overlapping ones.
+    fn insert(&mut self, (mut start, mut end): Range) {
+        while let Some((&s, &e)) = self.0.range(..=end).next_back() {
+            if e < start {
+                break;
+            }
+            self.0.remove(&s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+        self.0.insert(start, end);
+    }
+}
+
+/// The result of assigning the registers once.
+struct Assignment {
+    /// The physical registers assigned to the virtual registers.
+    assigned: HashMap<Reg, PReg>,
+    /// The virtual registers not assigned.
+    failed: Vec<Reg>,
+}
+
+/// Assign the physical registers to the virtual registers, by the liveness.
+///
+/// A virtual register live across calls, which would be the first to occupy
+/// a callee-saved register, fails instead if splitting it is cheaper than
+/// saving and restoring the register.
+fn assign(
+    mctx: &MContext,
+    func: MFunc,
+    liveness: &Liveness,
+    splittable: impl Fn(Reg) -> bool,
+) -> Assignment {
+    let allocatable = allocatable(func.omits_frame_pointer(mctx));
+
+    let mut occupied: HashMap<PReg, Occupancy> = HashMap::new();
+    let mut vregs = Vec::new();
+    for (&reg, ranges) in liveness.ranges.iter() {
+        match reg {
+            Reg::P(preg) => {
+                let occupancy = occupied.entry(preg).or_default();
+                for &range in ranges {
+                    occupancy.insert(range);
+                }
+            }
+            Reg::V(_) => vregs.push(reg),
+        }
+    }
+    vregs.sort_by_key(|reg| match reg {
+        Reg::V(vreg) => (liveness.ranges[reg][0], vreg.num()),
+        Reg::P(_) => unreachable!(),
+    });
+
+    // Prefer the register on the other side of a move, to remove the move.
+    let mut hints: HashMap<Reg, Vec<Reg>> = HashMap::new();
+    for block in func.iter(mctx) {
+        for inst in block.iter(mctx) {
+            if let Some((rd, rs)) = inst.kind(mctx).as_move() {
+                hints.entry(rd).or_default().push(rs);
+                hints.entry(rs).or_default().push(rd);
+            }
+        }
+    }
+
+    let mut assigned: HashMap<Reg, PReg> = HashMap::new();
+    let mut failed = Vec::new();
+    for vreg in vregs {
+        let ranges = &liveness.ranges[&vreg];
+        let hinted = hints
+            .get(&vreg)
+            .into_iter()
+            .flatten()
+            .filter_map(|reg| match reg {
+                Reg::P(preg) if allocatable.contains(preg) => Some(*preg),
+                Reg::P(_) => None,
+                Reg::V(_) => assigned.get(reg).copied(),
+            });
+        let free = hinted.chain(allocatable.iter().copied()).find(|preg| {
+            preg.kind() == vreg.kind()
+                && occupied
+                    .get(preg)
+                    .is_none_or(|occupancy| !ranges.iter().any(|&range| occupancy.overlaps(range)))
+        });
+        let free = free.filter(|preg| {
+            let newly_saved = regs::is_callee_saved(*preg) && !occupied.contains_key(preg);
+            !(newly_saved && liveness.crosses_call(vreg) && splittable(vreg))
+        });
+        match free {
+            Some(preg) => {
+                let occupancy = occupied.entry(preg).or_default();
+                for &range in ranges {
+                    occupancy.insert(range);
+                }
+                assigned.insert(vreg, preg);
+            }
+            None => failed.push(vreg),
+        }
+    }
+
+    Assignment { assigned, failed }
+}
+
+/// Get the blocks that are in cycles of the control flow graph.
+fn cyclic_blocks(mctx: &MContext, func: MFunc) -> HashSet<MBlock> {
+    let succs = func
+        .iter(mctx)
+        .map(|block| {
+            let succs = block
+                .iter(mctx)
+                .flat_map(|inst| inst.kind(mctx).successors())
+                .collect::<Vec<_>>();
+            (block, succs)
+        })
+        .collect::<HashMap<_, _>>();
+    let mut cyclic = HashSet::new();
+    for &block in succs.keys() {
+        let mut visited = HashSet::new();
+        let mut stack = succs[&block].clone();
+        while let Some(curr) = stack.pop() {
+            if curr == block {
+                cyclic.insert(block);
+                break;
+            }
+            if visited.insert(curr) {
+                stack.extend(succs.get(&curr).into_iter().flatten().copied());
+            }
+        }
+    }
+    cyclic
+}
+
+/// The estimated cost of a reload or a store in a cycle, relative to one
+/// out of cycles.
+const CYCLE_WEIGHT: u32 = 8;
+
+/// Estimate the cost of [splitting](split) a virtual register, by the
+/// number of the inserted loads and stores, weighted by the cycles.
+fn split_cost(mctx: &MContext, func: MFunc, vreg: Reg, cyclic: &HashSet<MBlock>) -> u32 {
+    let mut cost = 0;
+    for block in func.iter(mctx) {
+        let weight = if cyclic.contains(&block) {
+            CYCLE_WEIGHT
+        } else {
+            1
+        };
+        let mut loaded = false;
+        for inst in block.iter(mctx) {
+            let kind = inst.kind(mctx);
+            if kind.uses().contains(&vreg) && !loaded {
+                cost += weight;
+            }
+            if kind.defs().contains(&vreg) {
+                cost += weight;
+            }
+            loaded = !matches!(kind, MInstKind::Call { .. })
+                && (loaded || kind.uses().contains(&vreg) || kind.defs().contains(&vreg));
+        }
+    }
+    cost
+}
+
+/// Split the live range of a virtual register.
+///
+/// The value is stored to its stack slot after each definition, and each
+/// block reloads it before the first use, and again after each call, into a
+/// new virtual register for the uses up to the next call. So the new
+/// registers are local to blocks and not live across calls.
+///
+/// # Returns
+///
+/// The new virtual registers.
+fn split(mctx: &mut MContext, func: MFunc, vreg: Reg, offset: i64) -> Vec<Reg> {
+    let mut new_regs = Vec::new();
+    let loc = MemLoc::Slot { offset };
+    let (load_op, store_op) = spill_ops(vreg.kind(), mctx.xlen());
+    for block in func.iter(mctx).collect::<Vec<_>>() {
+        let mut curr: Option<Reg> = None;
+        for inst in block.iter(mctx).collect::<Vec<_>>() {
+            if inst.kind(mctx).uses().contains(&vreg) {
+                let new_reg = match curr {
+                    Some(new_reg) => new_reg,
+                    None => {
+                        let new_reg = mctx.new_vreg(vreg.kind()).into();
+                        let reload = MInst::raw_load(mctx, load_op, new_reg, loc);
+                        inst.insert_before(mctx, reload).unwrap();
+                        new_regs.push(new_reg);
+                        new_reg
+                    }
+                };
+                curr = Some(new_reg);
+                for reg in inst.kind_mut(mctx).uses_mut() {
+                    if *reg == vreg {
+                        *reg = new_reg;
+                    }
+                }
+            }
+            if inst.kind(mctx).defs().contains(&vreg) {
+                let new_reg = match curr {
+                    Some(new_reg) => new_reg,
+                    None => {
+                        let new_reg = mctx.new_vreg(vreg.kind()).into();
+                        new_regs.push(new_reg);
+                        new_reg
+                    }
+                };
+                curr = Some(new_reg);
+                for reg in inst.kind_mut(mctx).defs_mut() {
+                    if *reg == vreg {
+                        *reg = new_reg;
+                    }
+                }
+                let store = MInst::store(mctx, store_op, new_reg, loc);
+                inst.insert_after(mctx, store).unwrap();
+            }
+            if let MInstKind::Call { .. } = inst.kind(mctx) {
+                curr = None;
+            }
+        }
+    }
+    new_regs
+}
+
+/// The first assignment of the registers of a function, before any split.
+pub struct Plan {
+    /// The blocks in cycles, where the splits are costly.
+    cyclic: HashSet<MBlock>,
+    assignment: Assignment,
+}
+
+/// Assign the registers of a function once, without changing it.
+///
+/// This only reads the machine code, so the functions can be planned in
+/// parallel, and then [allocated](allocate_planned) one at a time.
+pub fn plan(mctx: &MContext, func: MFunc) -> Plan {
+    let cyclic = cyclic_blocks(mctx, func);
+    let liveness = Liveness::new(mctx, func);
+    // Saving and restoring a callee-saved register costs two instructions.
+    let splittable = |vreg| split_cost(mctx, func, vreg, &cyclic) <= 2;
+    let assignment = assign(mctx, func, &liveness, splittable);
+    Plan { cyclic, assignment }
+}
+
+/// Allocate the registers of a function.
+///
+/// A virtual register that cannot be assigned is split first, and the new
+/// registers that still cannot be assigned are spilled. All the virtual
+/// registers are replaced by physical ones, the used callee-saved registers
+/// are recorded in the function, and the stack slots of the split and the
+/// spilled registers are allocated in the storage stack.
+///
+/// If `check` is set, the allocation is checked by [`verify`].
+pub fn allocate(mctx: &mut MContext, func: MFunc, check: bool) -> Result<(), VerifyError> {
+    let plan = plan(mctx, func);
+    allocate_planned(mctx, func, plan, check)
+}
+
+/// Allocate the registers of a function from its [`plan`], see [`allocate`].
+///
+/// The function must not be changed since it is planned.
+pub fn allocate_planned(
+    mctx: &mut MContext,
+    func: MFunc,
+    plan: Plan,
+    check: bool,
+) -> Result<(), VerifyError> {
+    let Plan {
+        cyclic,
+        mut assignment,
+    } = plan;
+    // The stack slots of the split registers, inherited by the new ones.
+    let mut slots: HashMap<Reg, i64> = HashMap::new();
+    loop {
+        let Assignment { assigned, failed } = assignment;
+
+        let to_split = failed
+            .iter()
+            .copied()
+            .filter(|vreg| !slots.contains_key(vreg))
+            .collect::<Vec<_>>();
+        if to_split.is_empty() {
+            for vreg in failed {
+                slots
+                    .entry(vreg)
+                    .or_insert_with(|| func.alloc_storage_slot(mctx, 8, 8));
+            }
+            let snapshot = check.then(|| Snapshot::new(mctx, func));
+            rewrite(mctx, func, &assigned, &slots);
+            if let Some(snapshot) = snapshot {
+                verify(mctx, func, &snapshot)?;
+            }
+            remove_identity_moves(mctx, func);
+            for preg in assigned.into_values() {
+                if regs::is_callee_saved(preg) {
+                    func.add_saved_reg(mctx, preg);
+                }
+            }
+            return Ok(());
+        }
+
+        for vreg in to_split {
+            let offset = func.alloc_storage_slot(mctx, 8, 8);
+            for new_reg in split(mctx, func, vreg, offset) {
+                slots.insert(new_reg, offset);
+            }
+        }
+
+        let liveness = Liveness::new(mctx, func);
+        let splittable =
+            |vreg| !slots.contains_key(&vreg) && split_cost(mctx, func, vreg, &cyclic) <= 2;
+        assignment = assign(mctx, func, &liveness, splittable);
+    }
+}
+
+/// Replace the virtual registers with the assigned physical registers, and
+/// insert the reloads and the stores of the spilled ones.
+fn rewrite(
+    mctx: &mut MContext,
+    func: MFunc,
+    assigned: &HashMap<Reg, PReg>,
+    slots: &HashMap<Reg, i64>,
+) {
+    let insts = func
+        .iter(mctx)
+        .flat_map(|block| block.iter(mctx).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    for inst in insts {
+        // The scratch registers holding the spilled registers read here.
+        let mut reloaded: Vec<(Reg, PReg)> = Vec::new();
+        for reg in inst.kind_mut(mctx).uses_mut() {
+            if let Some(&preg) = assigned.get(reg) {
+                *reg = preg.into();
+            } else if slots.contains_key(reg) {
+                let scratch = match reloaded.iter().find(|(spilled, _)| spilled == reg) {
+                    Some(&(_, scratch)) => scratch,
+                    None => {
+                        let same_kind = reloaded
+                            .iter()
+                            .filter(|(spilled, _)| spilled.kind() == reg.kind())
+                            .count();
+                        let scratch = regs::scratch(reg.kind())[same_kind];
+                        reloaded.push((*reg, scratch));
+                        scratch
+                    }
+                };
+                *reg = scratch.into();
+            }
+        }
+        for (spilled, scratch) in reloaded {
+            let loc = MemLoc::Slot {
+                offset: slots[&spilled],
+            };
+            let (load_op, _) = spill_ops(spilled.kind(), mctx.xlen());
+            let reload = MInst::raw_load(mctx, load_op, scratch.into(), loc);
+            inst.insert_before(mctx, reload).unwrap();
+        }
+
+        let mut stored = Vec::new();
+        for reg in inst.kind_mut(mctx).defs_mut() {
+            if let Some(&preg) = assigned.get(reg) {
+                *reg = preg.into();
+            } else if let Some(&offset) = slots.get(reg) {
+                let kind = reg.kind();
+                *reg = regs::scratch(kind)[0].into();
+                stored.push((kind, offset));
+            }
+        }
+        for (kind, offset) in stored {
+            let loc = MemLoc::Slot { offset };
+            let scratch = regs::scratch(kind)[0].into();
+            let (_, store_op) = spill_ops(kind, mctx.xlen());
+            let store = MInst::store(mctx, store_op, scratch, loc);
+            inst.insert_after(mctx, store).unwrap();
+        }
+    }
+}
+
+/// Remove the moves between the virtual registers assigned the same physical
+/// register, which are useless after the rewriting.
+fn remove_identity_moves(mctx: &mut MContext, func: MFunc) {
+    let moves = func
+        .iter(mctx)
+        .flat_map(|block| block.iter(mctx))
+        .filter(|inst| inst.kind(mctx).as_move().is_some_and(|(rd, rs)| rd == rs))
+        .collect::<Vec<_>>();
+    for inst in moves {
+        inst.remove(mctx);
+    }
+}
+
+/// The registers read and written by the instructions of a function before
+/// the rewriting, to check the allocation with [`verify`].
+pub struct Snapshot(HashMap<MInst, (Vec<Reg>, Vec<Reg>)>);
+
+impl Snapshot {
+    pub fn new(mctx: &MContext, func: MFunc) -> Self {
+        let operands = func
+            .iter(mctx)
+            .flat_map(|block| block.iter(mctx))
+            .map(|inst| {
+                let kind = inst.kind(mctx);
+                (inst, (kind.uses(), kind.defs()))
+            })
+            .collect();
+        Self(operands)
+    }
+}
+
+/// The values in the physical registers and the spill slots at a point, as
+/// the registers before the allocation, or `None` if they differ between
+/// the paths.
+///
+/// A physical register not in the map holds its value at the function entry,
+/// e.g., an argument, which is the register itself.
+#[derive(Clone, Default, PartialEq, Eq)]
+struct Values {
+    regs: HashMap<PReg, Option<Reg>>,
+    slots: HashMap<i64, Option<Reg>>,
+}
+
+impl Values {
+    fn reg(&self, preg: PReg) -> Option<Reg> {
+        self.regs.get(&preg).copied().unwrap_or(Some(preg.into()))
+    }
+
+    /// Keep the values that are the same in both.
+    fn meet(&mut self, other: &Values) {
+        let pregs = self
+            .regs
+            .keys()
+            .chain(other.regs.keys())
+            .copied()
+            .collect::<HashSet<_>>();
+        for preg in pregs {
+            let value = Some(self.reg(preg)).filter(|&value| value == other.reg(preg));
+            self.regs.insert(preg, value.flatten());
+        }
+        for (offset, value) in self.slots.iter_mut() {
+            if other.slots.get(offset) != Some(value) {
+                *value = None;
+            }
+        }
+    }
+
+    /// Apply an instruction, checking that it reads the expected values.
+    fn apply(&mut self, mctx: &MContext, snapshot: &Snapshot, inst: MInst) -> Result<(), Problem> {
+        let kind = inst.kind(mctx);
+        let physical = |reg: Reg| match reg {
+            Reg::P(preg) => Ok(preg),
+            Reg::V(_) => Err(Problem::VirtualReg(reg)),
+        };
+        let Some((uses, defs)) = snapshot.0.get(&inst) else {
+            // The reloads and the stores of the spilled registers.
+            match kind {
+                MInstKind::Load {
+                    rd,
+                    loc: MemLoc::Slot { offset },
+                    ..
+                } => {
+                    let value = self.slots.get(offset).copied().flatten();
+                    self.regs.insert(physical(*rd)?, value);
+                }
+                MInstKind::Store {
+                    rs,
+                    loc: MemLoc::Slot { offset },
+                    ..
+                } => {
+                    let value = self.reg(physical(*rs)?);
+                    self.slots.insert(*offset, value);
+                }
+                _ => {}
+            }
+            return Ok(());
+        };
+
+        for (reg, &expected) in kind.uses().into_iter().zip(uses) {
+            let preg = physical(reg)?;
+            match self.reg(preg) {
+                Some(found) if found == expected => {}
+                Some(found) => {
+                    return Err(Problem::WrongValue {
+                        preg,
+                        expected,
+                        found,
+                    })
+                }
+                None => return Err(Problem::Undefined { preg, expected }),
+            }
+        }
+        for (reg, &value) in kind.defs().into_iter().zip(defs) {
+            self.regs.insert(physical(reg)?, Some(value));
+        }
+        Ok(())
+    }
+}
+
+/// Check the allocation of a function after the rewriting.
+///
+/// The values are followed from the entry through all the paths: each
+/// instruction must find the value of each virtual register it read before
+/// the allocation in the physical register assigned to it, so a register
+/// assigned to two values live at the same time, or a value not reloaded
+/// from its slot, is reported.
+pub fn verify(mctx: &MContext, func: MFunc, snapshot: &Snapshot) -> Result<(), VerifyError> {
+    let blocks = func.iter(mctx).collect::<Vec<_>>();
+    let succs = blocks
+        .iter()
+        .map(|&block| {
+            let succs = block
+                .iter(mctx)
+                .flat_map(|inst| inst.kind(mctx).successors())
+                .collect::<Vec<_>>();
+            (block, succs)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut values_in: HashMap<MBlock, Values> = HashMap::new();
+    if let Some(&entry) = blocks.first() {
+        values_in.insert(entry, Values::default());
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in blocks.iter() {
+            let Some(mut values) = values_in.get(&block).cloned() else {
+                continue;
+            };
+            for inst in block.iter(mctx) {
+                // The reads are checked once the values are stable.
+                let _ = values.apply(mctx, snapshot, inst);
+            }
+            for succ in succs[&block].iter() {
+                let mut succ_values = values.clone();
+                if let Some(old) = values_in.get(succ) {
+                    succ_values.meet(old);
+                }
+                if values_in.get(succ) != Some(&succ_values) {
+                    values_in.insert(*succ, succ_values);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    for &block in blocks.iter() {
+        let Some(mut values) = values_in.get(&block).cloned() else {
+            continue;
+        };
+        for inst in block.iter(mctx) {
+            values
+                .apply(mctx, snapshot, inst)
+                .map_err(|problem| VerifyError {
+                    stage: Stage::RegAlloc,
+                    block: block.label(mctx).to_string(),
+                    inst: inst.display(mctx).to_string(),
+                    problem,
+                })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::inst::{AluOpRRR, BranchOp};
+
+    #[test]
+    fn test_split_around_call() {
+        // bb0: v = 7; j bb1
+        // bb1: call f; [bne v, zero, bb1 if looping]; a0 = v; ret
+        let build = |looping: bool| {
+            let mut mctx = MContext::default();
+            let func = MFunc::new(&mut mctx, "g");
+            let [bb0, bb1] = [".Lbb_0", ".Lbb_1"].map(|label| MBlock::new(&mut mctx, label));
+            func.push_back(&mut mctx, bb0).unwrap();
+            func.push_back(&mut mctx, bb1).unwrap();
+            let (mut insts, v) = MInst::load_imm(&mut mctx, 7);
+            insts.push(MInst::j(&mut mctx, bb1));
+            bb0.extend(&mut mctx, insts).unwrap();
+            let call = MInst::call(&mut mctx, "f".into(), Vec::new());
+            bb1.push_back(&mut mctx, call).unwrap();
+            if looping {
+                let zero = regs::zero().into();
+                let branch = MInst::branch(&mut mctx, BranchOp::Bne, v, zero, bb1);
+                bb1.push_back(&mut mctx, branch).unwrap();
+            }
+            let mv = MInst::mv(&mut mctx, regs::a0().into(), v);
+            let ret = MInst::ret(&mut mctx, Some(regs::a0()));
+            bb1.extend(&mut mctx, [mv, ret]).unwrap();
+
+            allocate(&mut mctx, func, true).unwrap();
+            let asm = func
+                .iter(&mctx)
+                .flat_map(|block| block.iter(&mctx))
+                .map(|inst| inst.display(&mctx).to_string())
+                .collect::<Vec<_>>();
+            (func.saved_regs(&mctx), asm)
+        };
+
+        // Storing and reloading once is cheaper than saving a register.
+        let (saved_regs, asm) = build(false);
+        assert!(saved_regs.is_empty());
+        let expected = [
+            "addi t0, zero, 7",
+            "sd t0, 0(??? SLOT)",
+            "j .Lbb_1",
+            "call f",
+            "ld a0, 0(??? SLOT)",
+            "ret",
+        ];
+        assert_eq!(asm, expected);
+
+        // But not reloading in a loop.
+        let (saved_regs, asm) = build(true);
+        assert_eq!(saved_regs, [regs::s1()]);
+        assert!(asm.contains(&"bne s1, zero, .Lbb_1".to_string()));
+    }
+
+    #[test]
+    fn test_verify() {
+        // v0 = 1; v1 = 2; a0 = v0 + v1; ret
+        let build = |mctx: &mut MContext| {
+            let func = MFunc::new(mctx, "f");
+            let block = MBlock::new(mctx, ".Lbb_0");
+            func.push_back(mctx, block).unwrap();
+            let (mut insts, v0) = MInst::load_imm(mctx, 1);
+            let (more, v1) = MInst::load_imm(mctx, 2);
+            insts.extend(more);
+            let add = MInst::raw_alu_rrr(mctx, AluOpRRR::Add, regs::a0().into(), v0, v1);
+            let ret = MInst::ret(mctx, Some(regs::a0()));
+            insts.extend([add, ret]);
+            block.extend(mctx, insts).unwrap();
+            (func, v0, v1)
+        };
+
+        let mut mctx = MContext::default();
+        let (func, v0, v1) = build(&mut mctx);
+        let snapshot = Snapshot::new(&mctx, func);
+        let assigned = HashMap::from([(v0, regs::t0()), (v1, regs::t1())]);
+        rewrite(&mut mctx, func, &assigned, &HashMap::new());
+        assert_eq!(verify(&mctx, func, &snapshot), Ok(()));
+
+        // both live at the add, but in the same register
+        let mut mctx = MContext::default();
+        let (func, v0, v1) = build(&mut mctx);
+        let snapshot = Snapshot::new(&mctx, func);
+        let assigned = HashMap::from([(v0, regs::t0()), (v1, regs::t0())]);
+        rewrite(&mut mctx, func, &assigned, &HashMap::new());
+        let err = verify(&mctx, func, &snapshot).unwrap_err();
+        assert!(
+            matches!(err.problem, Problem::WrongValue { expected, found, .. }
+            if expected == v0 && found == v1)
+        );
+        assert_eq!(err.stage, Stage::RegAlloc);
+        assert_eq!(err.inst, "add a0, t0, t0");
+
+        // left unassigned
+        let mut mctx = MContext::default();
+        let (func, v0, _) = build(&mut mctx);
+        let snapshot = Snapshot::new(&mctx, func);
+        let assigned = HashMap::from([(v0, regs::t0())]);
+        rewrite(&mut mctx, func, &assigned, &HashMap::new());
+        let err = verify(&mctx, func, &snapshot).unwrap_err();
+        assert!(matches!(err.problem, Problem::VirtualReg(_)));
+    }
+}
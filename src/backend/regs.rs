@@ -5,6 +5,8 @@ use std::fmt;
 pub enum RegKind {
     /// The general purpose register.
     General,
+    /// The floating-point register.
+    Float,
 }
 
 /// The register.
@@ -113,6 +115,17 @@ impl fmt::Display for PReg {
                 31 => "t6",
                 _ => "<invalid>",
             },
+            RegKind::Float => {
+                let (prefix, idx) = match self.num() {
+                    0..=7 => ("ft", self.num()),
+                    8..=9 => ("fs", self.num() - 8),
+                    10..=17 => ("fa", self.num() - 10),
+                    18..=27 => ("fs", self.num() - 16),
+                    28..=31 => ("ft", self.num() - 20),
+                    _ => return write!(f, "<invalid>"),
+                };
+                return write!(f, "{}{}", prefix, idx);
+            }
         };
         write!(f, "{}", str)
     }
@@ -125,6 +138,7 @@ impl fmt::Display for VReg {
             "{}{}",
             match self.1 {
                 RegKind::General => "$r",
+                RegKind::Float => "$f",
             },
             self.0
         )
@@ -206,4 +220,118 @@ pub const fn t5() -> PReg { PReg::new(30, RegKind::General) }
 
 pub const fn t6() -> PReg { PReg::new(31, RegKind::General) }
 
-// TODO: You may need to add more registers here.
+/// The registers to pass the integer arguments, in order.
+pub const ARG_REGS: [PReg; 8] = [a0(), a1(), a2(), a3(), a4(), a5(), a6(), a7()];
+
+/// The registers not preserved across calls, except `ra`, which is saved in
+/// the prologue anyway.
+pub const CALLER_SAVED: [PReg; 15] = [
+    t0(),
+    t1(),
+    t2(),
+    t3(),
+    t4(),
+    t5(),
+    t6(),
+    a0(),
+    a1(),
+    a2(),
+    a3(),
+    a4(),
+    a5(),
+    a6(),
+    a7(),
+];
+
+/// The registers preserved across calls, except the frame pointer `s0`.
+pub const CALLEE_SAVED: [PReg; 11] = [
+    s1(),
+    s2(),
+    s3(),
+    s4(),
+    s5(),
+    s6(),
+    s7(),
+    s8(),
+    s9(),
+    s10(),
+    s11(),
+];
+
+/// The registers reserved for the code inserted after register allocation,
+/// e.g., to reload spilled values or to materialize large offsets.
+pub const SCRATCH: [PReg; 2] = [t5(), t6()];
+
+/// Get the `n`-th floating-point register, e.g., `fpr(10)` is `fa0`.
+pub const fn fpr(num: u8) -> PReg { PReg::new(num, RegKind::Float) }
+
+pub const fn fa0() -> PReg { fpr(10) }
+
+/// The registers to pass the floating-point arguments, in order.
+pub const FLOAT_ARG_REGS: [PReg; 8] = [
+    fpr(10),
+    fpr(11),
+    fpr(12),
+    fpr(13),
+    fpr(14),
+    fpr(15),
+    fpr(16),
+    fpr(17),
+];
+
+/// The floating-point registers not preserved across calls, i.e., `ft0-11`
+/// and `fa0-7`.
+pub const FLOAT_CALLER_SAVED: [PReg; 20] = [
+    fpr(0),
+    fpr(1),
+    fpr(2),
+    fpr(3),
+    fpr(4),
+    fpr(5),
+    fpr(6),
+    fpr(7),
+    fpr(28),
+    fpr(29),
+    fpr(30),
+    fpr(31),
+    fpr(10),
+    fpr(11),
+    fpr(12),
+    fpr(13),
+    fpr(14),
+    fpr(15),
+    fpr(16),
+    fpr(17),
+];
+
+/// The floating-point registers preserved across calls, i.e., `fs0-11`.
+pub const FLOAT_CALLEE_SAVED: [PReg; 12] = [
+    fpr(8),
+    fpr(9),
+    fpr(18),
+    fpr(19),
+    fpr(20),
+    fpr(21),
+    fpr(22),
+    fpr(23),
+    fpr(24),
+    fpr(25),
+    fpr(26),
+    fpr(27),
+];
+
+/// The floating-point counterparts of [SCRATCH], i.e., `ft10` and `ft11`.
+pub const FLOAT_SCRATCH: [PReg; 2] = [fpr(30), fpr(31)];
+
+/// Get the scratch registers of a kind.
+pub const fn scratch(kind: RegKind) -> [PReg; 2] {
+    match kind {
+        RegKind::General => SCRATCH,
+        RegKind::Float => FLOAT_SCRATCH,
+    }
+}
+
+/// Check if a register is preserved across calls, including `s0`.
+pub fn is_callee_saved(preg: PReg) -> bool {
+    preg == s0() || CALLEE_SAVED.contains(&preg) || FLOAT_CALLEE_SAVED.contains(&preg)
+}
@@ -0,0 +1,272 @@
+//! Instruction Scheduling.
+//!
+//! The target boards are in-order cores, which stall on an instruction until
+//! its operands are ready. A list scheduler reorders the instructions within
+//! each block, so the loads and the multiplications and divisions are
+//! separated from their uses by independent instructions where possible.
+//!
+//! The scheduling runs after the register allocation, so the register pressure
+//! is not increased, but the reuse of the physical registers constrains the
+//! order. The blocks are split into regions at the calls and the control flow
+//! instructions, which stay in place, and each region is scheduled on its own:
+//!
+//! - An instruction reading a register comes after the last write to it, at
+//!   least the latency of the writer later if possible.
+//! - An instruction writing a register comes after the last write to it and all
+//!   the reads since.
+//! - Memory accesses are not reordered across stores, since the addresses are
+//!   not analyzed.
+//!
+//! Among the ready instructions, the one with the longest latency path to the
+//! end of the region is picked first, ties broken by the original order.
+
+use std::collections::HashMap;
+
+use super::context::MContext;
+use super::func::MFunc;
+use super::inst::{AluOpRRR, FpuOpRRR, MInst, MInstKind};
+use super::regs::{self, Reg};
+use super::target::CostTable;
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+
+/// Get the latency of an instruction, until its result can be used.
+pub fn latency(kind: &MInstKind, costs: &CostTable) -> u32 {
+    match kind {
+        MInstKind::Load { .. } => costs.load,
+        MInstKind::AluRRR { op, .. } => match op {
+            AluOpRRR::Mul
+            | AluOpRRR::Mulw
+            | AluOpRRR::Mulh
+            | AluOpRRR::Mulhsu
+            | AluOpRRR::Mulhu => costs.mul,
+            AluOpRRR::Div
+            | AluOpRRR::Divw
+            | AluOpRRR::Divu
+            | AluOpRRR::Divuw
+            | AluOpRRR::Rem
+            | AluOpRRR::Remw
+            | AluOpRRR::Remu
+            | AluOpRRR::Remuw => costs.div,
+            _ => costs.alu,
+        },
+        MInstKind::FpuRRR { op, .. } => match op {
+            FpuOpRRR::FmulS | FpuOpRRR::FmulD => costs.mul,
+            FpuOpRRR::FdivS | FpuOpRRR::FdivD => costs.div,
+            _ => costs.alu,
+        },
+        _ => costs.alu,
+    }
+}
+
+/// Check if an instruction must stay in place, i.e., a call or a control flow
+/// instruction.
+fn is_barrier(kind: &MInstKind) -> bool {
+    matches!(
+        kind,
+        MInstKind::Call { .. } | MInstKind::Ret { .. } | MInstKind::TailCall { .. }
+    ) || !kind.successors().is_empty()
+}
+
+/// Schedule the instructions of a function, see the [module docs](self).
+pub fn schedule(mctx: &mut MContext, func: MFunc, costs: &CostTable) {
+    let blocks = func.iter(mctx).collect::<Vec<_>>();
+    for block in blocks {
+        let insts = block.iter(mctx).collect::<Vec<_>>();
+        let mut order = Vec::with_capacity(insts.len());
+        let mut region = Vec::new();
+        for inst in insts.iter().copied() {
+            if is_barrier(inst.kind(mctx)) {
+                order.extend(schedule_region(mctx, &region, costs));
+                region.clear();
+                order.push(inst);
+            } else {
+                region.push(inst);
+            }
+        }
+        order.extend(schedule_region(mctx, &region, costs));
+
+        if order == insts {
+            continue;
+        }
+        for &inst in insts.iter() {
+            inst.unlink(mctx);
+        }
+        block.extend(mctx, order).unwrap();
+    }
+}
+
+/// Schedule a region of instructions without barriers.
+///
+/// Returns the instructions in the new order.
+fn schedule_region(mctx: &MContext, region: &[MInst], costs: &CostTable) -> Vec<MInst> {
+    if region.len() <= 1 {
+        return region.to_vec();
+    }
+
+    // The dependencies of each instruction, with the latencies.
+    let mut deps: Vec<Vec<(usize, u32)>> = vec![Vec::new(); region.len()];
+    let mut last_def = HashMap::<Reg, usize>::new();
+    let mut uses_since_def = HashMap::<Reg, Vec<usize>>::new();
+    let mut last_store = None;
+    let mut loads_since_store = Vec::new();
+    let tracked = |reg: &Reg| *reg != Reg::from(regs::zero());
+    for (i, inst) in region.iter().enumerate() {
+        let kind = inst.kind(mctx);
+        for reg in kind.uses().into_iter().filter(tracked) {
+            if let Some(&def) = last_def.get(&reg) {
+                deps[i].push((def, latency(region[def].kind(mctx), costs)));
+            }
+        }
+        for reg in kind.defs().into_iter().filter(tracked) {
+            if let Some(&def) = last_def.get(&reg) {
+                deps[i].push((def, 0));
+            }
+            for &user in uses_since_def.get(&reg).into_iter().flatten() {
+                deps[i].push((user, 0));
+            }
+        }
+        match kind {
+            MInstKind::Load { .. } => {
+                deps[i].extend(last_store.map(|store| (store, 0)));
+                loads_since_store.push(i);
+            }
+            MInstKind::Store { .. } => {
+                deps[i].extend(last_store.map(|store| (store, 0)));
+                deps[i].extend(loads_since_store.drain(..).map(|load| (load, 0)));
+                last_store = Some(i);
+            }
+            _ => {}
+        }
+        for reg in kind.uses().into_iter().filter(tracked) {
+            uses_since_def.entry(reg).or_default().push(i);
+        }
+        for reg in kind.defs().into_iter().filter(tracked) {
+            last_def.insert(reg, i);
+            uses_since_def.remove(&reg);
+        }
+    }
+
+    // The longest latency path from each instruction to the end.
+    let mut heights = region
+        .iter()
+        .map(|inst| latency(inst.kind(mctx), costs))
+        .collect::<Vec<_>>();
+    for i in (0..region.len()).rev() {
+        for &(dep, lat) in deps[i].iter() {
+            heights[dep] = heights[dep].max(heights[i] + lat);
+        }
+    }
+
+    let mut n_deps = deps.iter().map(Vec::len).collect::<Vec<_>>();
+    let mut succs = vec![Vec::new(); region.len()];
+    for (i, deps) in deps.iter().enumerate() {
+        for &(dep, lat) in deps {
+            succs[dep].push((i, lat));
+        }
+    }
+    let mut ready_at = vec![0; region.len()];
+    let mut scheduled = vec![false; region.len()];
+    let mut order = Vec::with_capacity(region.len());
+    let mut cycle = 0;
+    while order.len() < region.len() {
+        let candidates = (0..region.len()).filter(|&i| !scheduled[i] && n_deps[i] == 0);
+        // Prefer the instructions without stalls, then the critical ones.
+        let best = candidates
+            .min_by_key(|&i| (ready_at[i].max(cycle), std::cmp::Reverse(heights[i]), i))
+            .unwrap();
+        let issue = ready_at[best].max(cycle);
+        cycle = issue + 1;
+        scheduled[best] = true;
+        order.push(region[best]);
+        for &(succ, lat) in succs[best].iter() {
+            n_deps[succ] -= 1;
+            ready_at[succ] = ready_at[succ].max(issue + lat);
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::block::MBlock;
+    use crate::backend::func::MLabel;
+    use crate::backend::imm::Imm12;
+    use crate::backend::inst::{AluOpRRI, LoadOp, StoreOp};
+    use crate::backend::operand::MemLoc;
+    use crate::backend::target::TargetDesc;
+
+    #[test]
+    fn test_schedule() {
+        let mut mctx = MContext::default();
+        let func = MFunc::new(&mut mctx, "f");
+        let block = MBlock::new(&mut mctx, ".Lbb_0");
+        func.push_back(&mut mctx, block).unwrap();
+        let [a0, a1, a2, a3, a4, t0] = [
+            regs::a0(),
+            regs::a1(),
+            regs::a2(),
+            regs::a3(),
+            regs::a4(),
+            regs::t0(),
+        ]
+        .map(Reg::from);
+        let zero = regs::zero().into();
+        let at = |base| MemLoc::RegOffset { base, offset: 0 };
+
+        let insts = vec![
+            MInst::raw_load(&mut mctx, LoadOp::Lw, a1, at(a0)),
+            MInst::raw_alu_rrr(&mut mctx, AluOpRRR::Mulw, a2, a1, a1),
+            MInst::raw_alu_rrr(&mut mctx, AluOpRRR::Addw, a3, a2, a1),
+            // independent of the load and the multiplication
+            MInst::raw_alu_rri(
+                &mut mctx,
+                AluOpRRI::Addi,
+                a4,
+                zero,
+                Imm12::try_from_i64(1).unwrap(),
+            ),
+            MInst::raw_alu_rri(
+                &mut mctx,
+                AluOpRRI::Addi,
+                t0,
+                zero,
+                Imm12::try_from_i64(2).unwrap(),
+            ),
+            // the load cannot be moved above the store
+            MInst::store(&mut mctx, StoreOp::Sw, t0, at(a0)),
+            MInst::raw_load(&mut mctx, LoadOp::Lw, t0, at(a0)),
+            MInst::call(&mut mctx, MLabel::from("g"), Vec::new()),
+            MInst::raw_alu_rri(
+                &mut mctx,
+                AluOpRRI::Addi,
+                a0,
+                zero,
+                Imm12::try_from_i64(3).unwrap(),
+            ),
+            MInst::ret(&mut mctx, Some(regs::a0())),
+        ];
+        block.extend(&mut mctx, insts).unwrap();
+
+        schedule(&mut mctx, func, &TargetDesc::default().costs);
+        let asm = block
+            .iter(&mctx)
+            .map(|inst| inst.display(&mctx).to_string())
+            .collect::<Vec<_>>();
+        let expected = [
+            "lw a1, 0(a0)",
+            "addi t0, zero, 2",
+            "sw t0, 0(a0)",
+            // the load latency is hidden
+            "mulw a2, a1, a1",
+            "lw t0, 0(a0)",
+            "addi a4, zero, 1",
+            // and so is the multiplication latency
+            "addw a3, a2, a1",
+            "call g",
+            "addi a0, zero, 3",
+            "ret",
+        ];
+        assert_eq!(asm, expected);
+    }
+}
@@ -0,0 +1,279 @@
+//! The description of the targets.
+//!
+//! The architectures are the implementations of [`Target`], found by their
+//! names in [`TARGETS`], so the driver dispatches on `--target` without
+//! knowing the backends. Each backend keeps its registers, calling convention
+//! and instruction selection to itself.
+//!
+//! The RISC-V cores are described by [`TargetDesc`]. The instruction
+//! selection consults the costs there to choose between the alternative
+//! sequences, e.g., to expand a division by a constant into multiplications
+//! and shifts only when it is faster than the divider. The instruction
+//! scheduler uses them as the latencies, see [`sched`](super::sched).
+//!
+//! The optional RISC-V extensions enabled by `--mattr` are described by
+//! [`TargetFeatures`], queried by the instruction selection before using
+//! their instructions.
+
+use super::aarch64::AArch64;
+use super::codegen::{Riscv32, Riscv64};
+use super::verify::VerifyError;
+use super::x86_64::X86_64;
+use crate::ir::{self, TargetInfo};
+
+/// The errors of selecting a target.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TargetError {
+    #[error("unknown target `{0}`")]
+    UnknownTarget(String),
+    #[error("unknown cpu `{0}` for target `{1}`")]
+    UnknownCpu(String, &'static str),
+    #[error("unknown target feature `{0}`")]
+    UnknownFeature(String),
+    #[error("target `{0}` has no machine IR")]
+    NoMachineIr(&'static str),
+    #[error("invalid machine code: {0}")]
+    InvalidMachineCode(#[from] VerifyError),
+}
+
+/// The options of the code generation.
+///
+/// The options not applying to a target are ignored by it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodegenOptions {
+    /// The core to tune the code for, see [`TargetDesc`].
+    pub cpu: String,
+    /// If the functions omit the frame pointer, see [`frame`](super::frame).
+    pub omit_frame_pointer: bool,
+    /// If the instructions are scheduled, see [`sched`](super::sched).
+    pub schedule: bool,
+    /// If the assembly is commented with the source lines, see
+    /// [`Source`](crate::ir::Source).
+    pub verbose_asm: bool,
+    /// If the frames containing arrays are guarded by a stack canary, see
+    /// [`frame`](super::frame).
+    pub stack_protector: bool,
+    /// The optional extensions the code may use.
+    pub features: TargetFeatures,
+    /// If the machine code is checked after each stage, see
+    /// [`verify`](super::verify).
+    pub verify_machineinstrs: bool,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        Self {
+            cpu: TargetDesc::default().name.to_string(),
+            omit_frame_pointer: false,
+            schedule: true,
+            verbose_asm: false,
+            stack_protector: false,
+            features: TargetFeatures::default(),
+            verify_machineinstrs: false,
+        }
+    }
+}
+
+/// A target architecture.
+///
+/// The trait is the interface the driver dispatches `--target` to: the IR
+/// properties the target needs, the C compiler of its toolchain, and the
+/// generation of the assembly from a module. The register classes, the calling
+/// convention, the legalization of the IR types and the instruction selection
+/// are left to the implementations, which do not share them: the RISC-V
+/// targets lower through the machine IR, see [`codegen`](super::codegen),
+/// while AArch64 and x86-64 print the assembly directly from the IR. The
+/// machine-independent layers only see the pointer size, when generating the
+/// IR.
+pub trait Target: Sync {
+    /// The name of the target, as passed to `--target`.
+    fn name(&self) -> &'static str;
+
+    /// The properties of the target the IR depends on, e.g., the size of the
+    /// pointers.
+    fn info(&self) -> TargetInfo;
+
+    /// The C compiler assembling and linking the code, see
+    /// [`driver`](crate::driver).
+    fn cc(&self) -> &'static str;
+
+    /// Generate the assembly of a module.
+    fn emit_asm(&self, ctx: &ir::Context, options: &CodegenOptions) -> Result<String, TargetError>;
+
+    /// Generate the machine IR of a module before and after the register
+    /// allocation, for debugging the backend.
+    ///
+    /// The targets printing the assembly directly from the IR have none.
+    fn emit_mir(&self, ctx: &ir::Context, options: &CodegenOptions) -> Result<String, TargetError> {
+        let _ = (ctx, options);
+        Err(TargetError::NoMachineIr(self.name()))
+    }
+}
+
+/// The known targets, the first being the default.
+pub const TARGETS: [&dyn Target; 4] = [&Riscv64, &Riscv32, &AArch64, &X86_64];
+
+/// Find a target by its name.
+pub fn target_by_name(name: &str) -> Result<&'static dyn Target, TargetError> {
+    TARGETS
+        .into_iter()
+        .find(|target| target.name() == name)
+        .ok_or_else(|| TargetError::UnknownTarget(name.to_string()))
+}
+
+/// The rough latencies of the integer instructions, in cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostTable {
+    /// The simple ALU instructions, e.g., additions, shifts and `lui`.
+    pub alu: u32,
+    /// The multiplications.
+    pub mul: u32,
+    /// The divisions and the remainders.
+    pub div: u32,
+    /// The loads, until the loaded value can be used.
+    pub load: u32,
+}
+
+/// The description of a target core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetDesc {
+    /// The name of the core, as passed to `--mcpu`.
+    pub name: &'static str,
+    /// The costs of the instructions.
+    pub costs: CostTable,
+}
+
+impl TargetDesc {
+    /// The known cores, the first being the default.
+    pub const CORES: [TargetDesc; 3] = [
+        TargetDesc {
+            name: "generic",
+            costs: CostTable {
+                alu: 1,
+                mul: 3,
+                div: 20,
+                load: 3,
+            },
+        },
+        TargetDesc {
+            name: "sifive-u74",
+            costs: CostTable {
+                alu: 1,
+                mul: 3,
+                div: 34,
+                load: 3,
+            },
+        },
+        // A core with a fast divider, where only the cheapest expansions pay
+        // off.
+        TargetDesc {
+            name: "thead-c910",
+            costs: CostTable {
+                alu: 1,
+                mul: 3,
+                div: 12,
+                load: 4,
+            },
+        },
+    ];
+
+    /// Find a core by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::CORES.into_iter().find(|core| core.name == name)
+    }
+}
+
+impl Default for TargetDesc {
+    fn default() -> Self { Self::CORES[0] }
+}
+
+/// The optional RISC-V extensions, none enabled by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TargetFeatures {
+    /// The address generation, i.e., `sh1add`, `sh2add` and `sh3add`.
+    pub zba: bool,
+    /// The basic bit manipulation, e.g., `min`, `max`, `sext.b` and `sext.h`.
+    pub zbb: bool,
+}
+
+impl TargetFeatures {
+    /// Parse the comma-separated features, as passed to `--mattr`, e.g.,
+    /// `+zba,+zbb`. A feature is disabled by a leading `-`.
+    pub fn parse(attrs: &str) -> Result<Self, TargetError> {
+        let mut features = Self::default();
+        for attr in attrs.split(',').filter(|attr| !attr.is_empty()) {
+            let (enable, name) = match attr.strip_prefix('-') {
+                Some(name) => (false, name),
+                None => (true, attr.strip_prefix('+').unwrap_or(attr)),
+            };
+            match name {
+                "zba" => features.zba = enable,
+                "zbb" => features.zbb = enable,
+                _ => return Err(TargetError::UnknownFeature(name.to_string())),
+            }
+        }
+        Ok(features)
+    }
+
+    /// Get the suffix of the ISA string for the enabled extensions, e.g.,
+    /// `_zba_zbb`.
+    pub fn isa_suffix(&self) -> String {
+        [("zba", self.zba), ("zbb", self.zbb)]
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| format!("_{}", name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_by_name() {
+        for target in TARGETS {
+            assert_eq!(target_by_name(target.name()).unwrap().name(), target.name());
+        }
+        assert_eq!(TARGETS[0].name(), "riscv64");
+        assert_eq!(target_by_name("riscv32").unwrap().info().ptr_size, 4);
+        assert_eq!(
+            target_by_name("mips").err(),
+            Some(TargetError::UnknownTarget("mips".to_string()))
+        );
+
+        let ctx = ir::Context::new(8);
+        let options = CodegenOptions {
+            cpu: "thead-c910".to_string(),
+            verify_machineinstrs: true,
+            ..CodegenOptions::default()
+        };
+        assert!(Riscv64.emit_asm(&ctx, &options).is_ok());
+        // the cores are only known to the RISC-V target
+        assert_eq!(
+            X86_64.emit_asm(&ctx, &options),
+            Err(TargetError::UnknownCpu("thead-c910".to_string(), "x86_64"))
+        );
+    }
+
+    #[test]
+    fn test_target_features() {
+        let features = TargetFeatures::parse("+zba,+zbb,-zba").unwrap();
+        assert_eq!(
+            features,
+            TargetFeatures {
+                zba: false,
+                zbb: true
+            }
+        );
+        assert_eq!(features.isa_suffix(), "_zbb");
+        assert_eq!(
+            TargetFeatures::parse("").unwrap(),
+            TargetFeatures::default()
+        );
+        assert_eq!(
+            TargetFeatures::parse("+v"),
+            Err(TargetError::UnknownFeature("v".to_string()))
+        );
+    }
+}
@@ -0,0 +1,437 @@
+//! The verifier of the machine code.
+//!
+//! The instructions are built by many hands, the instruction selection, the
+//! register allocation and the frame lowering, and the assembler is the first
+//! to notice most mistakes, if at all. With `--verify-machineinstrs`, the code
+//! is checked after each of the stages instead, see [`Stage`]:
+//!
+//! - the registers are of the kinds the formats expect, e.g., no `f` register
+//!   as the base of a load;
+//! - the immediates are in range, e.g., the shift amounts below the register
+//!   width, and the offsets fit in 12 bits once the slots are resolved;
+//! - the instructions exist on the target, e.g., no `addw` on RV32, and no
+//!   `sh2add` without Zba;
+//! - the 32-bit integers are computed by the instructions keeping them
+//!   sign-extended on RV64, e.g., `addw` instead of `add`, see
+//!   [`CodegenContext::verify`](super::codegen::CodegenContext::verify);
+//! - the jumps and the returns end their blocks, and the conditional branches
+//!   are only followed by branches, and target the blocks of the function;
+//! - the reserved registers are not written, e.g., `gp` and `tp`, `sp` before
+//!   the frame lowering, and the scratch registers before the register
+//!   allocation;
+//! - the virtual registers are gone after the register allocation, and the
+//!   stack slots after the frame lowering;
+//! - each register read after the register allocation holds the value read
+//!   before it, see [`regalloc::verify`](super::regalloc::verify).
+
+use std::fmt;
+
+use super::context::MContext;
+use super::func::MFunc;
+use super::inst::{AluOpRR, AluOpRRI, AluOpRRR, LoadOp, MInst, MInstKind, StoreOp};
+use super::operand::MemLoc;
+use super::regs::{self, PReg, Reg, RegKind};
+use super::target::TargetFeatures;
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::infra::storage::Arena;
+
+/// The stages of the code generation the code is checked after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// The instruction selection, on virtual registers.
+    Isel,
+    /// The register allocation, with the spill code on the stack slots.
+    RegAlloc,
+    /// The frame lowering, with the prologues, the epilogues and the stack
+    /// slots resolved.
+    Frame,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Isel => write!(f, "instruction selection"),
+            Stage::RegAlloc => write!(f, "register allocation"),
+            Stage::Frame => write!(f, "frame lowering"),
+        }
+    }
+}
+
+/// The problems found by the verifier.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Problem {
+    #[error("{0} is of the wrong kind")]
+    WrongRegKind(Reg),
+    #[error("the immediate {0} is out of range")]
+    ImmOutOfRange(i64),
+    #[error("the instruction is not available on the target")]
+    Unsupported,
+    #[error("the instruction is followed by another one in the block")]
+    NotLast,
+    #[error("the target {0} is not a block of the function")]
+    ForeignBlock(String),
+    #[error("the reserved register {0} is written")]
+    ReservedReg(PReg),
+    #[error("{0} is not allocated")]
+    VirtualReg(Reg),
+    #[error("the stack slot is not resolved")]
+    UnresolvedSlot,
+    #[error("the 32-bit value in {0} is not sign-extended")]
+    NotSextWord(Reg),
+    #[error("{expected} is read from {preg}, which holds {found}")]
+    WrongValue {
+        preg: PReg,
+        expected: Reg,
+        found: Reg,
+    },
+    #[error("{expected} is read from {preg}, which is not defined on all paths")]
+    Undefined { preg: PReg, expected: Reg },
+}
+
+/// An error found by the verifier, in an instruction.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("`{inst}` in {block} after the {stage}: {problem}")]
+pub struct VerifyError {
+    pub stage: Stage,
+    /// The label of the block of the instruction.
+    pub block: String,
+    /// The instruction, as printed in the assembly.
+    pub inst: String,
+    pub problem: Problem,
+}
+
+/// Check the machine code of a function after a stage.
+pub fn verify_func(
+    mctx: &MContext,
+    func: MFunc,
+    stage: Stage,
+    features: TargetFeatures,
+) -> Result<(), VerifyError> {
+    let verifier = Verifier {
+        mctx,
+        func,
+        stage,
+        features,
+    };
+    for block in func.iter(mctx) {
+        let mut prev: Option<&MInstKind> = None;
+        for inst in block.iter(mctx) {
+            let kind = inst.kind(mctx);
+            let err = |problem| VerifyError {
+                stage,
+                block: block.label(mctx).to_string(),
+                inst: inst.display(mctx).to_string(),
+                problem,
+            };
+            // Only the branches may follow a branch, falling through at last.
+            let after_terminator = match prev {
+                Some(MInstKind::Branch { .. }) => {
+                    !matches!(kind, MInstKind::Branch { .. } | MInstKind::J { .. })
+                }
+                Some(
+                    MInstKind::J { .. }
+                    | MInstKind::Jr { .. }
+                    | MInstKind::Ret { .. }
+                    | MInstKind::TailCall { .. },
+                ) => true,
+                _ => false,
+            };
+            if after_terminator {
+                return Err(err(Problem::NotLast));
+            }
+            verifier.check(inst).map_err(err)?;
+            prev = Some(kind);
+        }
+    }
+    Ok(())
+}
+
+struct Verifier<'a> {
+    mctx: &'a MContext,
+    func: MFunc,
+    stage: Stage,
+    features: TargetFeatures,
+}
+
+impl Verifier<'_> {
+    /// Check an instruction on its own.
+    fn check(&self, inst: MInst) -> Result<(), Problem> {
+        let kind = inst.kind(self.mctx);
+        self.check_kinds(kind)?;
+        self.check_imms(kind)?;
+        if !self.is_supported(kind) {
+            return Err(Problem::Unsupported);
+        }
+        for target in kind.successors() {
+            match self.mctx.try_deref(target) {
+                None => return Err(Problem::ForeignBlock("a removed block".to_string())),
+                Some(_) if target.container(self.mctx) != Some(self.func) => {
+                    let label = target.label(self.mctx).to_string();
+                    return Err(Problem::ForeignBlock(label));
+                }
+                Some(_) => {}
+            }
+        }
+
+        let regs = kind.uses().into_iter().chain(kind.defs());
+        for reg in regs {
+            if reg.is_vreg() && self.stage >= Stage::RegAlloc {
+                return Err(Problem::VirtualReg(reg));
+            }
+        }
+        // The registers clobbered by the calls are not written by the caller.
+        if !matches!(kind, MInstKind::Call { .. }) {
+            for reg in kind.defs() {
+                match reg {
+                    Reg::P(preg) if self.is_reserved(preg) => {
+                        return Err(Problem::ReservedReg(preg))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the kinds of the register operands.
+    fn check_kinds(&self, kind: &MInstKind) -> Result<(), Problem> {
+        use RegKind::{Float, General};
+
+        let loc_base = |loc: &MemLoc| match loc {
+            MemLoc::RegOffset { base, .. } => vec![(*base, General)],
+            MemLoc::Slot { .. } | MemLoc::Incoming { .. } => Vec::new(),
+        };
+        let operands = match kind {
+            MInstKind::AluRRI { rd, rs, .. } | MInstKind::AluRR { rd, rs, .. } => {
+                vec![(*rd, General), (*rs, General)]
+            }
+            MInstKind::AluRRR { rd, rs1, rs2, .. } => {
+                vec![(*rd, General), (*rs1, General), (*rs2, General)]
+            }
+            MInstKind::FpuRRR { op, rd, rs1, rs2 } => {
+                let rd_kind = if op.defines_int() { General } else { Float };
+                vec![(*rd, rd_kind), (*rs1, Float), (*rs2, Float)]
+            }
+            MInstKind::FpuRR { op, rd, rs } => vec![(*rd, op.rd_kind()), (*rs, op.rs_kind())],
+            MInstKind::Load { op, rd, loc } => {
+                let rd_kind = match op {
+                    LoadOp::Flw | LoadOp::Fld => Float,
+                    _ => General,
+                };
+                std::iter::once((*rd, rd_kind))
+                    .chain(loc_base(loc))
+                    .collect()
+            }
+            MInstKind::Store { op, rs, loc } => {
+                let rs_kind = match op {
+                    StoreOp::Fsw | StoreOp::Fsd => Float,
+                    _ => General,
+                };
+                std::iter::once((*rs, rs_kind))
+                    .chain(loc_base(loc))
+                    .collect()
+            }
+            MInstKind::Lui { rd, .. } | MInstKind::LuiHi { rd, .. } => vec![(*rd, General)],
+            MInstKind::AddiLo { rd, rs, .. } => vec![(*rd, General), (*rs, General)],
+            MInstKind::LoadAddr { rd, loc } => std::iter::once((*rd, General))
+                .chain(loc_base(loc))
+                .collect(),
+            MInstKind::Jr { rs, .. } => vec![(*rs, General)],
+            MInstKind::Branch { rs1, rs2, .. } => vec![(*rs1, General), (*rs2, General)],
+            MInstKind::J { .. }
+            | MInstKind::Call { .. }
+            | MInstKind::Ret { .. }
+            | MInstKind::TailCall { .. } => Vec::new(),
+        };
+        match operands.into_iter().find(|(reg, kind)| reg.kind() != *kind) {
+            Some((reg, _)) => Err(Problem::WrongRegKind(reg)),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the immediates not limited by their types, i.e., the shift
+    /// amounts, and the offsets of the resolved memory locations.
+    fn check_imms(&self, kind: &MInstKind) -> Result<(), Problem> {
+        let xlen = self.mctx.xlen() as i64;
+        if let MInstKind::AluRRI { op, imm, .. } = kind {
+            let limit = match op {
+                AluOpRRI::Slli | AluOpRRI::Srli | AluOpRRI::Srai => xlen,
+                AluOpRRI::Slliw | AluOpRRI::Srliw | AluOpRRI::Sraiw => 32,
+                _ => return Ok(()),
+            };
+            let imm = imm.as_i16() as i64;
+            if !(0..limit).contains(&imm) {
+                return Err(Problem::ImmOutOfRange(imm));
+            }
+        }
+
+        let loc = match kind {
+            MInstKind::Load { loc, .. }
+            | MInstKind::Store { loc, .. }
+            | MInstKind::LoadAddr { loc, .. } => loc,
+            _ => return Ok(()),
+        };
+        match loc {
+            MemLoc::RegOffset { offset, .. } if !(-2048..=2047).contains(offset) => {
+                Err(Problem::ImmOutOfRange(*offset))
+            }
+            MemLoc::Slot { .. } | MemLoc::Incoming { .. } if self.stage >= Stage::Frame => {
+                Err(Problem::UnresolvedSlot)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check if the instruction exists on the target, by the register width
+    /// and the enabled extensions.
+    fn is_supported(&self, kind: &MInstKind) -> bool {
+        let rv64 = self.mctx.xlen() == 64;
+        match kind {
+            MInstKind::AluRRI {
+                op: AluOpRRI::Addiw | AluOpRRI::Slliw | AluOpRRI::Srliw | AluOpRRI::Sraiw,
+                ..
+            } => rv64,
+            MInstKind::AluRRR { op, .. } => match op {
+                AluOpRRR::Addw
+                | AluOpRRR::Subw
+                | AluOpRRR::Sllw
+                | AluOpRRR::Srlw
+                | AluOpRRR::Sraw
+                | AluOpRRR::Mulw
+                | AluOpRRR::Divw
+                | AluOpRRR::Divuw
+                | AluOpRRR::Remw
+                | AluOpRRR::Remuw => rv64,
+                AluOpRRR::Sh1add | AluOpRRR::Sh2add | AluOpRRR::Sh3add => self.features.zba,
+                AluOpRRR::Min | AluOpRRR::Max => self.features.zbb,
+                _ => true,
+            },
+            MInstKind::AluRR {
+                op: AluOpRR::SextB | AluOpRR::SextH,
+                ..
+            } => self.features.zbb,
+            MInstKind::Load { op, .. } => !matches!(op, LoadOp::Ld | LoadOp::Lwu) || rv64,
+            MInstKind::Store { op, .. } => !matches!(op, StoreOp::Sd) || rv64,
+            _ => true,
+        }
+    }
+
+    /// Check if a register may not be written at the stage.
+    ///
+    /// `sp` and the frame pointer are only written by the prologues and the
+    /// epilogues, and the scratch registers by the code inserted after the
+    /// register allocation.
+    fn is_reserved(&self, preg: PReg) -> bool {
+        if [regs::zero(), regs::gp(), regs::tp()].contains(&preg) {
+            return true;
+        }
+        if self.stage < Stage::Frame {
+            let fp = !self.func.omits_frame_pointer(self.mctx) && preg == regs::fp();
+            if preg == regs::sp() || fp {
+                return true;
+            }
+        }
+        self.stage < Stage::RegAlloc && regs::scratch(preg.kind()).contains(&preg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::block::MBlock;
+    use crate::backend::imm::Imm12;
+    use crate::backend::inst::FpuOpRRR;
+
+    /// Verify a function of one block built by `build`, ending with `ret`.
+    fn check(
+        arch: &str,
+        stage: Stage,
+        build: impl FnOnce(&mut MContext, MBlock) -> Vec<MInst>,
+    ) -> Result<(), Problem> {
+        let mut mctx = MContext::default();
+        mctx.set_arch(arch);
+        let func = MFunc::new(&mut mctx, "f");
+        let block = MBlock::new(&mut mctx, ".Lbb_0");
+        func.push_back(&mut mctx, block).unwrap();
+        let mut insts = build(&mut mctx, block);
+        insts.push(MInst::ret(&mut mctx, None));
+        block.extend(&mut mctx, insts).unwrap();
+        verify_func(&mctx, func, stage, TargetFeatures::default()).map_err(|err| err.problem)
+    }
+
+    #[test]
+    fn test_verify() {
+        let a0: Reg = regs::a0().into();
+        let imm = |imm| Imm12::try_from_i64(imm).unwrap();
+
+        let result = check("rv64gc", Stage::Isel, |mctx, _| {
+            let (mut insts, v) = MInst::load_imm(mctx, 100_000);
+            insts.push(MInst::raw_alu_rri(mctx, AluOpRRI::Slli, a0, v, imm(63)));
+            insts
+        });
+        assert_eq!(result, Ok(()));
+
+        // a virtual register left after the allocation
+        let result = check("rv64gc", Stage::RegAlloc, |mctx, _| {
+            MInst::load_imm(mctx, 1).0
+        });
+        assert!(matches!(result, Err(Problem::VirtualReg(_))));
+
+        // an addition of the floating-point registers
+        let fa0 = regs::fa0().into();
+        let result = check("rv64gc", Stage::Frame, |mctx, _| {
+            vec![MInst::raw_alu_rrr(mctx, AluOpRRR::Add, a0, fa0, fa0)]
+        });
+        assert_eq!(result, Err(Problem::WrongRegKind(fa0)));
+        let result = check("rv64gc", Stage::Frame, |mctx, _| {
+            vec![MInst::raw_fpu_rrr(mctx, FpuOpRRR::FeqD, a0, fa0, fa0)]
+        });
+        assert_eq!(result, Ok(()));
+
+        // the shift amounts are below the register width
+        let result = check("rv32gc", Stage::Frame, |mctx, _| {
+            vec![MInst::raw_alu_rri(mctx, AluOpRRI::Slli, a0, a0, imm(32))]
+        });
+        assert_eq!(result, Err(Problem::ImmOutOfRange(32)));
+
+        // no `ld` on RV32, and no `sh1add` without Zba
+        let loc = MemLoc::RegOffset {
+            base: a0,
+            offset: 0,
+        };
+        let result = check("rv32gc", Stage::Frame, |mctx, _| {
+            vec![MInst::raw_load(mctx, LoadOp::Ld, a0, loc)]
+        });
+        assert_eq!(result, Err(Problem::Unsupported));
+        let result = check("rv64gc", Stage::Frame, |mctx, _| {
+            vec![MInst::raw_alu_rrr(mctx, AluOpRRR::Sh1add, a0, a0, a0)]
+        });
+        assert_eq!(result, Err(Problem::Unsupported));
+
+        // the slots are resolved by the frame lowering
+        let slot = MemLoc::Slot { offset: 0 };
+        let result = check("rv64gc", Stage::Frame, |mctx, _| {
+            vec![MInst::raw_load(mctx, LoadOp::Ld, a0, slot)]
+        });
+        assert_eq!(result, Err(Problem::UnresolvedSlot));
+
+        // `sp` is only adjusted by the prologues and the epilogues
+        let sp = regs::sp().into();
+        let adjust = |mctx: &mut MContext, _| {
+            vec![MInst::raw_alu_rri(mctx, AluOpRRI::Addi, sp, sp, imm(-16))]
+        };
+        assert_eq!(
+            check("rv64gc", Stage::RegAlloc, adjust),
+            Err(Problem::ReservedReg(regs::sp()))
+        );
+        assert_eq!(check("rv64gc", Stage::Frame, adjust), Ok(()));
+
+        // a jump in the middle of the block
+        let result = check("rv64gc", Stage::Frame, |mctx, block| {
+            let j = MInst::j(mctx, block);
+            let (insts, _) = MInst::load_imm(mctx, 1);
+            std::iter::once(j).chain(insts).collect()
+        });
+        assert_eq!(result, Err(Problem::NotLast));
+    }
+}
@@ -0,0 +1,733 @@
+//! x86-64 Code Generation.
+//!
+//! The assembly is emitted directly from the IR in the AT&T syntax, keeping
+//! every value in a stack slot, see [`lower`](super::lower), so the generated
+//! code can be assembled and run on the development machines without an
+//! emulator. The values are loaded into the scratch registers `rax`, `rcx`
+//! and `rdx`, which the divisions and the shifts need anyway, and `xmm8-9`
+//! around each instruction, and `r11` is left for materializing the
+//! immediates.
+//!
+//! Values of types narrower than 64 bits are kept in the 32-bit registers,
+//! the `i8` ones sign-extended and the `i1` ones as 0 or 1, like in the
+//! RISC-V backend. The arguments are passed by the System V ABI, see
+//! [`arg_locs`].
+//!
+//! Like the AArch64 backend, it does not share the machine IR, the register
+//! allocator or the frame lowering, which are specific to RISC-V. The code
+//! is slow, but it runs the tests natively with the same results.
+
+use std::fmt::Display;
+
+use super::context::DataItem;
+use super::lower::{self, SourceComments, StackFrame};
+use super::target::{CodegenOptions, Target, TargetDesc, TargetError};
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::analysis::Cfg;
+use crate::ir::{
+    self,
+    CastOp,
+    ConstantValue,
+    FloatBinaryOp,
+    FloatCmpCond,
+    IntBinaryOp,
+    IntCmpCond,
+    Section,
+    TargetInfo,
+    Ty,
+    Value,
+};
+
+/// The general purpose registers used, as the 64-bit, 32-bit and 8-bit names.
+const GPRS: [[&str; 3]; 9] = [
+    ["rax", "eax", "al"],
+    ["rcx", "ecx", "cl"],
+    ["rdx", "edx", "dl"],
+    ["rdi", "edi", "dil"],
+    ["rsi", "esi", "sil"],
+    ["r8", "r8d", "r8b"],
+    ["r9", "r9d", "r9b"],
+    ["r10", "r10d", "r10b"],
+    ["r11", "r11d", "r11b"],
+];
+
+/// The integer argument registers, as the indices in [`GPRS`].
+const ARG_GPRS: [u8; 6] = [3, 4, 2, 1, 5, 6];
+
+/// Where an argument is passed by the calling convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgLoc {
+    /// In the register of the number, in [`GPRS`] or `xmm` by the type.
+    Reg(u8),
+    /// In the stack, at the offset from the bottom of the argument area.
+    Stack(i64),
+}
+
+/// Assign the arguments of the types to the registers and the stack.
+///
+/// By the System V ABI, the integer arguments go to `rdi`, `rsi`, `rdx`,
+/// `rcx`, `r8` and `r9`, and the floating-point ones to `xmm0-7`,
+/// independently. The rest go to the stack, in 8-byte slots.
+fn arg_locs(ctx: &ir::Context, tys: &[Ty]) -> Vec<ArgLoc> {
+    let mut int_regs = ARG_GPRS.into_iter();
+    let mut float_regs = 0..8;
+    let mut offset = 0;
+    tys.iter()
+        .map(|ty| {
+            let reg = if ty.is_float(ctx) {
+                float_regs.next()
+            } else {
+                int_regs.next()
+            };
+            reg.map(ArgLoc::Reg).unwrap_or_else(|| {
+                offset += 8;
+                ArgLoc::Stack(offset - 8)
+            })
+        })
+        .collect()
+}
+
+/// Get the name of the register of the number holding a value of the type.
+fn reg(ctx: &ir::Context, ty: Ty, n: u8) -> String {
+    match (ty.is_float(ctx), ty.bitwidth(ctx)) {
+        (true, _) => format!("%xmm{}", n),
+        (false, 64) => format!("%{}", GPRS[n as usize][0]),
+        (false, _) => format!("%{}", GPRS[n as usize][1]),
+    }
+}
+
+/// Get the suffix of the move between a register and memory for a type.
+fn mov(ctx: &ir::Context, ty: Ty) -> &'static str {
+    match (ty.is_float(ctx), ty.bitwidth(ctx)) {
+        (true, 64) => "movsd",
+        (true, _) => "movss",
+        (false, 64) => "movq",
+        (false, _) => "movl",
+    }
+}
+
+/// Get the suffix of the integer instructions on a type.
+fn suffix(ctx: &ir::Context, ty: Ty) -> &'static str {
+    if ty.bitwidth(ctx) == 64 {
+        "q"
+    } else {
+        "l"
+    }
+}
+
+/// Get the condition code of an integer comparison.
+fn int_cond(cond: IntCmpCond) -> &'static str {
+    match cond {
+        IntCmpCond::Eq => "e",
+        IntCmpCond::Ne => "ne",
+        IntCmpCond::Slt => "l",
+        IntCmpCond::Sle => "le",
+        IntCmpCond::Sgt => "g",
+        IntCmpCond::Sge => "ge",
+    }
+}
+
+/// The x86-64 target, with the System V calling convention.
+pub struct X86_64;
+
+impl Target for X86_64 {
+    fn name(&self) -> &'static str { "x86_64" }
+
+    fn info(&self) -> TargetInfo { TargetInfo { ptr_size: 8 } }
+
+    fn cc(&self) -> &'static str { "cc" }
+
+    fn emit_asm(&self, ctx: &ir::Context, options: &CodegenOptions) -> Result<String, TargetError> {
+        if options.cpu != TargetDesc::default().name {
+            return Err(TargetError::UnknownCpu(options.cpu.clone(), self.name()));
+        }
+        Ok(emit(ctx, options.verbose_asm))
+    }
+}
+
+/// Emit the assembly of a module, commented with the source lines if
+/// `verbose_asm` is set.
+pub fn emit(ctx: &ir::Context, verbose_asm: bool) -> String {
+    let mut out = String::new();
+    out.push_str("\t.text\n");
+    for func in ctx.funcs() {
+        if !func.is_declaration(ctx) {
+            FuncEmitter::new(ctx, func, verbose_asm, &mut out).emit();
+        }
+    }
+    for global in ctx.globals() {
+        emit_global(ctx, global, &mut out);
+    }
+    out.push_str("\t.section .note.GNU-stack,\"\",@progbits\n");
+    out
+}
+
+/// Emit the data of a global variable.
+fn emit_global(ctx: &ir::Context, global: ir::Global, out: &mut String) {
+    let name = global.name(ctx);
+    let value = global.value(ctx);
+    let mut items = Vec::new();
+    lower::constant_items(ctx, value, &mut items);
+    let section = match global.section(ctx) {
+        Section::Rodata => ".section .rodata",
+        // An explicit hint cannot drop the initializer.
+        Section::Bss if value.is_zero() => ".bss",
+        Section::Data | Section::Bss => ".data",
+    };
+    let size = value.ty().bytewidth(ctx);
+    out.push_str(&format!("\t{}\n", section));
+    out.push_str(&format!("\t.globl {}\n", name));
+    out.push_str(&format!(
+        "\t.p2align {}\n",
+        global.align(ctx).trailing_zeros()
+    ));
+    out.push_str(&format!("\t.type {}, @object\n", name));
+    out.push_str(&format!("\t.size {}, {}\n", name, size));
+    out.push_str(&format!("{}:\n", name));
+    for item in items {
+        let item = match item {
+            DataItem::Byte(value) => format!(".byte {}", value),
+            DataItem::Word(value) => format!(".long {}", value),
+            DataItem::Symbol(label) => format!(".quad {}", label),
+            DataItem::Symbol32(label) => format!(".long {}", label),
+            DataItem::Zero(size) => format!(".zero {}", size),
+        };
+        out.push_str(&format!("\t{}\n", item));
+    }
+    out.push('\n');
+}
+
+/// The emitter of a function.
+struct FuncEmitter<'a> {
+    ctx: &'a ir::Context,
+    func: ir::Func,
+    frame: StackFrame,
+    /// The counter of the local labels within the function.
+    label_counter: u32,
+    comments: SourceComments<'a>,
+    out: &'a mut String,
+}
+
+impl<'a> FuncEmitter<'a> {
+    fn new(ctx: &'a ir::Context, func: ir::Func, verbose_asm: bool, out: &'a mut String) -> Self {
+        let frame = StackFrame::new(ctx, func, |tys| {
+            arg_locs(ctx, tys)
+                .into_iter()
+                .filter(|loc| matches!(loc, ArgLoc::Stack(_)))
+                .count()
+        });
+        Self {
+            ctx,
+            func,
+            frame,
+            label_counter: 0,
+            comments: SourceComments::new(ctx.source().filter(|_| verbose_asm), "#"),
+            out,
+        }
+    }
+
+    /// Append an instruction.
+    fn inst(&mut self, inst: impl Display) { self.out.push_str(&format!("\t{}\n", inst)); }
+
+    /// Append a label.
+    fn label(&mut self, label: impl Display) { self.out.push_str(&format!("{}:\n", label)); }
+
+    /// Generate a local label, for the branches within a lowered instruction.
+    fn new_label(&mut self) -> String {
+        let label = format!(".L{}.{}", self.func.name(self.ctx), self.label_counter);
+        self.label_counter += 1;
+        label
+    }
+
+    /// Add an immediate to a 64-bit register.
+    ///
+    /// The immediates out of the range of the sign-extended 32 bits are
+    /// materialized in `r11` first.
+    fn add_imm(&mut self, rd: &str, imm: i64) {
+        if i32::try_from(imm).is_ok() {
+            self.inst(format!("addq ${}, {}", imm, rd));
+        } else {
+            self.inst(format!("movabsq ${}, %r11", imm));
+            self.inst(format!("addq %r11, {}", rd));
+        }
+    }
+
+    /// Load a value into the register of the number.
+    ///
+    /// # Returns
+    ///
+    /// The name of the register.
+    fn load_into(&mut self, value: Value, n: u8) -> String {
+        let ctx = self.ctx;
+        let ty = value.ty(ctx);
+        let rd = reg(ctx, ty, n);
+        if let Some(offset) = self.frame.alloca(value) {
+            self.inst(format!("leaq {}(%rsp), {}", offset, rd));
+            return rd;
+        }
+        let Some(constant) = value.as_const(ctx) else {
+            let offset = self.frame.slot(value);
+            self.inst(format!("{} {}(%rsp), {}", mov(ctx, ty), offset, rd));
+            return rd;
+        };
+        match constant {
+            ConstantValue::GlobalRef { name, .. } => {
+                self.inst(format!("leaq {}(%rip), {}", name, rd));
+            }
+            ConstantValue::Float32 { bits, .. } => {
+                self.inst(format!("movl ${}, %r11d", bits));
+                self.inst(format!("movd %r11d, {}", rd));
+            }
+            ConstantValue::Int1 { value, .. } => {
+                self.inst(format!("movl ${}, {}", *value as i32, rd))
+            }
+            ConstantValue::Int8 { value, .. } => self.inst(format!("movl ${}, {}", value, rd)),
+            ConstantValue::Int32 { value, .. } => self.inst(format!("movl ${}, {}", value, rd)),
+            ConstantValue::Undef { .. } | ConstantValue::AggregateZero { .. } => {
+                if ty.is_float(ctx) {
+                    self.inst(format!("xorps {}, {}", rd, rd));
+                } else {
+                    // Clearing the 32-bit register clears the upper half too.
+                    let rd = GPRS[n as usize][1];
+                    self.inst(format!("xorl %{}, %{}", rd, rd));
+                }
+            }
+            ConstantValue::Array { .. } => unreachable!("array values are not in registers"),
+        }
+        rd
+    }
+
+    /// Load a value into the `i`-th scratch register of its type.
+    fn load(&mut self, value: Value, i: u8) -> String {
+        let n = if value.ty(self.ctx).is_float(self.ctx) {
+            8 + i
+        } else {
+            i
+        };
+        self.load_into(value, n)
+    }
+
+    /// Store a value from the register into its slot.
+    fn store(&mut self, value: Value, rs: &str) {
+        let ty = value.ty(self.ctx);
+        let offset = self.frame.slot(value);
+        self.inst(format!("{} {}, {}(%rsp)", mov(self.ctx, ty), rs, offset));
+    }
+
+    /// Normalize the result of an operation on a type of `bits` bits in
+    /// `eax`.
+    fn normalize(&mut self, bits: usize) {
+        match bits {
+            1 => self.inst("andl $1, %eax"),
+            8 => self.inst("movsbl %al, %eax"),
+            _ => {}
+        }
+    }
+
+    fn emit(mut self) {
+        let ctx = self.ctx;
+        let name = self.func.name(ctx).to_string();
+        self.inst(format!(".globl {}", name));
+        self.inst(".p2align 4");
+        self.inst(format!(".type {}, @function", name));
+        self.label(&name);
+
+        self.inst("pushq %rbp");
+        self.inst("movq %rsp, %rbp");
+        self.inst(format!("subq ${}, %rsp", self.frame.size()));
+
+        let params = self.func.params(ctx).to_vec();
+        let tys = params.iter().map(|param| param.ty(ctx)).collect::<Vec<_>>();
+        for (&param, loc) in params.iter().zip(arg_locs(ctx, &tys)) {
+            let ty = param.ty(ctx);
+            let rs = match loc {
+                ArgLoc::Reg(n) => reg(ctx, ty, n),
+                ArgLoc::Stack(offset) => {
+                    let rd = reg(ctx, ty, if ty.is_float(ctx) { 8 } else { 0 });
+                    self.inst(format!("{} {}(%rbp), {}", mov(ctx, ty), 16 + offset, rd));
+                    rd
+                }
+            };
+            self.store(param, &rs);
+        }
+
+        let cfg = Cfg::new(ctx, self.func);
+        for block in self.func.iter(ctx) {
+            if !cfg.is_reachable(block) {
+                continue;
+            }
+            let label = lower::block_label(ctx, block);
+            match self.comments.block(ctx, block) {
+                Some(comment) => self.out.push_str(&format!("{}:\t\t\t{}\n", label, comment)),
+                None => self.label(label),
+            }
+            for inst in block.iter(ctx) {
+                if let Some(comment) = self.comments.inst(ctx, inst) {
+                    self.inst(comment);
+                }
+                self.emit_inst(inst);
+            }
+        }
+        self.out.push('\n');
+    }
+
+    /// Emit the copies on the edge to a successor of a branch, and the jump.
+    fn emit_edge(&mut self, branch: ir::Inst, succ_idx: usize) {
+        let ctx = self.ctx;
+        let copies = lower::edge_copies(ctx, branch, succ_idx);
+        for &(dst, src) in copies.iter() {
+            let rs = self.load(src, 0);
+            let offset = self.frame.shadow(dst);
+            self.inst(format!(
+                "{} {}, {}(%rsp)",
+                mov(ctx, dst.ty(ctx)),
+                rs,
+                offset
+            ));
+        }
+        for &(dst, _) in copies.iter() {
+            let ty = dst.ty(ctx);
+            let rd = reg(ctx, ty, if ty.is_float(ctx) { 8 } else { 0 });
+            let offset = self.frame.shadow(dst);
+            self.inst(format!("{} {}(%rsp), {}", mov(ctx, ty), offset, rd));
+            self.store(dst, &rd);
+        }
+        let succ = branch.successor(ctx, succ_idx);
+        self.inst(format!("jmp {}", lower::block_label(ctx, succ)));
+    }
+
+    fn emit_inst(&mut self, inst: ir::Inst) {
+        let ctx = self.ctx;
+        let result = inst.result(ctx);
+        match inst.kind(ctx) {
+            ir::InstKind::Alloca { .. } | ir::InstKind::Phi => {}
+            ir::InstKind::Load => {
+                self.load(inst.operand(ctx, 0), 0);
+                let result = result.unwrap();
+                let ty = result.ty(ctx);
+                let (op, rd) = match ty.bitwidth(ctx) {
+                    1 if !ty.is_float(ctx) => ("movzbl", "%ecx".to_string()),
+                    8 => ("movsbl", "%ecx".to_string()),
+                    _ if ty.is_float(ctx) => (mov(ctx, ty), reg(ctx, ty, 9)),
+                    _ => (mov(ctx, ty), reg(ctx, ty, 1)),
+                };
+                self.inst(format!("{} (%rax), {}", op, rd));
+                self.store(result, &rd);
+            }
+            ir::InstKind::Store => {
+                let val = inst.operand(ctx, 0);
+                let rs = self.load(val, 1);
+                self.load(inst.operand(ctx, 1), 0);
+                let ty = val.ty(ctx);
+                match ty.bitwidth(ctx) {
+                    1 | 8 => self.inst("movb %cl, (%rax)"),
+                    _ => self.inst(format!("{} {}, (%rax)", mov(ctx, ty), rs)),
+                }
+            }
+            ir::InstKind::GetElementPtr { bound_ty } => {
+                self.load(inst.operand(ctx, 0), 0);
+                let mut ty = *bound_ty;
+                let mut offset = 0i64;
+                for (i, idx) in inst.operand_iter(ctx).skip(1).enumerate() {
+                    if i > 0 {
+                        ty = ty.as_array(ctx).unwrap().0;
+                    }
+                    let size = ty.bytewidth(ctx) as i64;
+                    if let Some(idx) = idx.as_const_int(ctx) {
+                        offset += idx as i64 * size;
+                        continue;
+                    }
+                    if self.load(idx, 1) == "%ecx" {
+                        self.inst("movslq %ecx, %rcx");
+                    }
+                    self.inst(format!("imulq ${}, %rcx, %rcx", size));
+                    self.inst("addq %rcx, %rax");
+                }
+                if offset != 0 {
+                    self.add_imm("%rax", offset);
+                }
+                self.store(result.unwrap(), "%rax");
+            }
+            ir::InstKind::Call => {
+                let args = inst.operand_iter(ctx).skip(1).collect::<Vec<_>>();
+                let tys = args.iter().map(|arg| arg.ty(ctx)).collect::<Vec<_>>();
+                let locs = arg_locs(ctx, &tys);
+                for (&arg, &loc) in args.iter().zip(locs.iter()) {
+                    if let ArgLoc::Stack(offset) = loc {
+                        let rs = self.load(arg, 0);
+                        let op = mov(ctx, arg.ty(ctx));
+                        self.inst(format!("{} {}, {}(%rsp)", op, rs, offset));
+                    }
+                }
+                let mut n_floats = 0;
+                for (&arg, &loc) in args.iter().zip(locs.iter()) {
+                    if let ArgLoc::Reg(n) = loc {
+                        self.load_into(arg, n);
+                        n_floats += arg.ty(ctx).is_float(ctx) as u32;
+                    }
+                }
+                // The variadic functions take the number of the vector
+                // registers used in `al`.
+                self.inst(format!("movl ${}, %eax", n_floats));
+                self.inst(format!("call {}", inst.callee(ctx)));
+                if let Some(result) = result {
+                    self.store(result, &reg(ctx, result.ty(ctx), 0));
+                }
+            }
+            ir::InstKind::Br => self.emit_edge(inst, 0),
+            ir::InstKind::CondBr => {
+                let cond = self.load(inst.operand(ctx, 0), 0);
+                let else_label = self.new_label();
+                self.inst(format!("testl {}, {}", cond, cond));
+                self.inst(format!("je {}", else_label));
+                self.emit_edge(inst, 0);
+                self.label(else_label);
+                self.emit_edge(inst, 1);
+            }
+            ir::InstKind::Switch { cases } => {
+                let x = self.load(inst.operand(ctx, 0), 0);
+                let labels = cases.iter().map(|_| self.new_label()).collect::<Vec<_>>();
+                for (&case, label) in cases.iter().zip(labels.iter()) {
+                    self.inst(format!("cmpl ${}, {}", case, x));
+                    self.inst(format!("je {}", label));
+                }
+                self.emit_edge(inst, 0);
+                for (i, label) in labels.into_iter().enumerate() {
+                    self.label(label);
+                    self.emit_edge(inst, i + 1);
+                }
+            }
+            ir::InstKind::Ret => {
+                if let Some(val) = inst.operand_iter(ctx).next() {
+                    self.load_into(val, 0);
+                }
+                self.inst("leave");
+                self.inst("ret");
+            }
+            ir::InstKind::IntBinary { op } => self.emit_int_binary(inst, *op),
+            ir::InstKind::FloatBinary { op } => {
+                let l = self.load(inst.operand(ctx, 0), 0);
+                let r = self.load(inst.operand(ctx, 1), 1);
+                let p = if inst.operand(ctx, 0).ty(ctx).bitwidth(ctx) == 64 {
+                    "d"
+                } else {
+                    "s"
+                };
+                let mnemonic = match op {
+                    FloatBinaryOp::FAdd => "add",
+                    FloatBinaryOp::FSub => "sub",
+                    FloatBinaryOp::FMul => "mul",
+                    FloatBinaryOp::FDiv => "div",
+                    FloatBinaryOp::FCmp { cond } => {
+                        // The unordered results set `ZF`, `PF` and `CF`, so
+                        // `a` and `ae` are false on them, while `e` and `ne`
+                        // need the parity checked.
+                        let (lhs, rhs) = match cond {
+                            FloatCmpCond::Olt | FloatCmpCond::Ole => (r, l),
+                            _ => (l, r),
+                        };
+                        self.inst(format!("ucomis{} {}, {}", p, rhs, lhs));
+                        match cond {
+                            FloatCmpCond::Oeq | FloatCmpCond::One => {
+                                let cc = if *cond == FloatCmpCond::Oeq {
+                                    "e"
+                                } else {
+                                    "ne"
+                                };
+                                self.inst(format!("set{} %al", cc));
+                                self.inst("setnp %cl");
+                                self.inst("andb %cl, %al");
+                            }
+                            FloatCmpCond::Ogt | FloatCmpCond::Olt => self.inst("seta %al"),
+                            FloatCmpCond::Oge | FloatCmpCond::Ole => self.inst("setae %al"),
+                        }
+                        self.inst("movzbl %al, %eax");
+                        self.store(result.unwrap(), "%eax");
+                        return;
+                    }
+                };
+                self.inst(format!("{}s{} {}, {}", mnemonic, p, r, l));
+                self.store(result.unwrap(), &l);
+            }
+            ir::InstKind::Cast { op } => {
+                let val = inst.operand(ctx, 0);
+                let result = result.unwrap();
+                let from = val.ty(ctx).bitwidth(ctx);
+                let to = result.ty(ctx).bitwidth(ctx);
+                let rs = self.load(val, 0);
+                let rd = reg(
+                    ctx,
+                    result.ty(ctx),
+                    if result.ty(ctx).is_float(ctx) { 8 } else { 0 },
+                );
+                let p = |bits| if bits == 64 { "d" } else { "s" };
+                match op {
+                    CastOp::SiToFp => {
+                        // `i1` is kept as 0 or 1, but `true` is -1 as a signed
+                        // value.
+                        if from == 1 {
+                            self.inst("negl %eax");
+                        }
+                        let s = if from == 64 { "q" } else { "l" };
+                        self.inst(format!("cvtsi2s{}{} {}, {}", p(to), s, rs, rd));
+                    }
+                    CastOp::FpToSi => {
+                        self.inst(format!("cvtts{}2si {}, {}", p(from), rs, rd));
+                        self.normalize(to);
+                    }
+                    CastOp::Zext if from == 32 => self.inst("movl %eax, %eax"),
+                    CastOp::Zext => self.inst(format!("andq ${}, %rax", (1u64 << from) - 1)),
+                    CastOp::Sext => match from {
+                        1 => {
+                            self.inst("shlq $63, %rax");
+                            self.inst("sarq $63, %rax");
+                        }
+                        8 => self.inst("movsbq %al, %rax"),
+                        _ => self.inst("movslq %eax, %rax"),
+                    },
+                    CastOp::Trunc => self.normalize(to),
+                }
+                self.store(result, &rd);
+            }
+        }
+    }
+
+    /// Emit an integer binary operation, with the operands in `eax` and `ecx`
+    /// and the result in `eax`.
+    fn emit_int_binary(&mut self, inst: ir::Inst, op: IntBinaryOp) {
+        let ctx = self.ctx;
+        let lhs = inst.operand(ctx, 0);
+        let ty = lhs.ty(ctx);
+        let bits = ty.bitwidth(ctx);
+        let s = suffix(ctx, ty);
+        let l = self.load(lhs, 0);
+        let r = self.load(inst.operand(ctx, 1), 1);
+        let unsigned = matches!(
+            op,
+            IntBinaryOp::LShr | IntBinaryOp::UDiv | IntBinaryOp::URem
+        );
+        if bits == 8 && unsigned {
+            // The unsigned operations see the sign-extended bits otherwise.
+            self.inst("andl $0xff, %eax");
+            if !matches!(op, IntBinaryOp::LShr) {
+                self.inst("andl $0xff, %ecx");
+            }
+        }
+        let mut rd = l.clone();
+        match op {
+            IntBinaryOp::Add => self.inst(format!("add{} {}, {}", s, r, l)),
+            IntBinaryOp::Sub => self.inst(format!("sub{} {}, {}", s, r, l)),
+            IntBinaryOp::Mul => self.inst(format!("imul{} {}, {}", s, r, l)),
+            IntBinaryOp::And => self.inst(format!("and{} {}, {}", s, r, l)),
+            IntBinaryOp::Or => self.inst(format!("or{} {}, {}", s, r, l)),
+            IntBinaryOp::Xor => self.inst(format!("xor{} {}, {}", s, r, l)),
+            IntBinaryOp::Shl => self.inst(format!("shl{} %cl, {}", s, l)),
+            IntBinaryOp::LShr => self.inst(format!("shr{} %cl, {}", s, l)),
+            IntBinaryOp::AShr => self.inst(format!("sar{} %cl, {}", s, l)),
+            IntBinaryOp::SMin | IntBinaryOp::SMax => {
+                let cc = if matches!(op, IntBinaryOp::SMin) {
+                    "g"
+                } else {
+                    "l"
+                };
+                self.inst(format!("cmp{} {}, {}", s, r, l));
+                self.inst(format!("cmov{}{} {}, {}", cc, s, r, l));
+            }
+            IntBinaryOp::SMulHi if bits <= 32 => {
+                // The full product of two sign-extended words fits in 64 bits.
+                self.inst("movslq %eax, %rax");
+                self.inst("movslq %ecx, %rcx");
+                self.inst("imulq %rcx, %rax");
+                self.inst(format!("sarq ${}, %rax", bits));
+            }
+            IntBinaryOp::SMulHi => {
+                self.inst("imulq %rcx");
+                rd = "%rdx".to_string();
+            }
+            IntBinaryOp::SDiv | IntBinaryOp::SRem | IntBinaryOp::UDiv | IntBinaryOp::URem => {
+                if unsigned {
+                    self.inst("xorl %edx, %edx");
+                    self.inst(format!("div{} {}", s, r));
+                } else {
+                    self.inst(if bits == 64 { "cqto" } else { "cltd" });
+                    self.inst(format!("idiv{} {}", s, r));
+                }
+                if matches!(op, IntBinaryOp::SRem | IntBinaryOp::URem) {
+                    rd = reg(ctx, ty, 2);
+                    if bits <= 8 {
+                        self.inst("movl %edx, %eax");
+                        rd = l.clone();
+                    }
+                }
+            }
+            IntBinaryOp::ICmp { cond } => {
+                self.inst(format!("cmp{} {}, {}", s, r, l));
+                self.inst(format!("set{} %al", int_cond(cond)));
+                self.inst("movzbl %al, %eax");
+                self.store(inst.result(ctx).unwrap(), "%eax");
+                return;
+            }
+        }
+        self.normalize(bits);
+        self.store(inst.result(ctx).unwrap(), &rd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Context, Func, Inst};
+
+    #[test]
+    fn test_emit() {
+        // int rem(int x, int y) { return x % y; }
+        // int lt(float a, float b) { return a < b; }
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let f32 = Ty::f32(&mut ctx);
+        let push = |ctx: &mut Context, block: Block, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+
+        let rem = Func::new(&mut ctx, "rem".to_string(), i32);
+        let x = rem.add_param(&mut ctx, i32);
+        let y = rem.add_param(&mut ctx, i32);
+        let entry = Block::new(&mut ctx);
+        rem.push_back(&mut ctx, entry).unwrap();
+        let inst = Inst::ibinary(&mut ctx, IntBinaryOp::SRem, x, y);
+        let r = push(&mut ctx, entry, inst);
+        let inst = Inst::ret(&mut ctx, r);
+        push(&mut ctx, entry, inst);
+
+        let lt = Func::new(&mut ctx, "lt".to_string(), i32);
+        let a = lt.add_param(&mut ctx, f32);
+        let b = lt.add_param(&mut ctx, f32);
+        let entry = Block::new(&mut ctx);
+        lt.push_back(&mut ctx, entry).unwrap();
+        let olt = FloatBinaryOp::FCmp {
+            cond: FloatCmpCond::Olt,
+        };
+        let inst = Inst::fbinary(&mut ctx, olt, a, b);
+        let cmp = push(&mut ctx, entry, inst).unwrap();
+        let inst = Inst::cast(&mut ctx, CastOp::Zext, cmp, i32);
+        let r = push(&mut ctx, entry, inst);
+        let inst = Inst::ret(&mut ctx, r);
+        push(&mut ctx, entry, inst);
+
+        let asm = emit(&ctx, false).replace('\t', "");
+        let expected = [
+            "rem:\npushq %rbp\nmovq %rsp, %rbp\nsubq $32, %rsp\n\
+             movl %edi, 0(%rsp)\nmovl %esi, 8(%rsp)\n",
+            // the remainder is left in `edx`
+            "cltd\nidivl %ecx\nmovl %edx, ",
+            "leave\nret\n",
+            // the unordered operands clear `a`, so the operands are swapped
+            "movss 0(%rsp), %xmm8\nmovss 8(%rsp), %xmm9\nucomiss %xmm8, %xmm9\nseta %al\n\
+             movzbl %al, %eax\n",
+        ];
+        for expected in expected {
+            assert!(asm.contains(expected), "{}", asm);
+        }
+    }
+}
@@ -1,30 +1,193 @@
-use std::path::Path;
-use std::process::Command as SysCommand;
+use std::io::IsTerminal;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use clap::{Arg, ArgMatches, Command};
-use nkucc::frontend::{irgen, preprocess, SysYParser};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use nkucc::backend::target::{self, CodegenOptions, TargetFeatures, TARGETS};
+use nkucc::config::{find_config, Config, ConfigValue, CONFIG_FILE};
+use nkucc::driver::{self, LinkOptions};
+use nkucc::frontend::{irgen_with, preprocess, Diagnostic, IrGenOptions, SysYParser};
+use nkucc::infra::{diff, timing};
+use nkucc::ir::interp::interpret;
+use nkucc::ir::passes::{opt_pipeline, register_passes, Profile, ProfileUse};
+use nkucc::ir::passman::{PassManager, PrintOptions};
+use nkucc::ir::{GraphOptions, Source};
 
-fn parse_arguments() -> ArgMatches {
+/// The kinds of the output written to `-o`.
+const EMIT_KINDS: [&str; 4] = ["ast", "ir", "mir", "asm"];
+
+/// The options whose values in the configuration are paths, relative to the
+/// directory of the file.
+const CONFIG_PATHS: [&str; 9] = [
+    "runtime",
+    "out-dir",
+    "emit-ast",
+    "emit-llvm-ir",
+    "emit-mir",
+    "emit-cfg",
+    "emit-domtree",
+    "emit-callgraph",
+    "profile-use",
+];
+
+fn command() -> Command {
     Command::new("nkucc")
+        .about("A SysY compiler, e.g., `nkucc -S -o foo.s foo.sy -O1`")
+        .arg(Arg::new("output").short('o').help(
+            "The output executable, or the output of --emit, by default `a.out`, or the source \
+             with the extension of the output",
+        ))
+        .arg(Arg::new("source").required(true).help(
+            "The source code, or a directory to compile all the .sy files under, continuing past \
+             the failures",
+        ))
         .arg(
-            Arg::new("output")
-                .short('o')
-                .required(true)
-                .help("The output assembly"),
+            Arg::new("out-dir")
+                .long("out-dir")
+                .conflicts_with("output")
+                .help(
+                    "Write the outputs of a directory to this one, mirroring the sources, instead \
+                     of alongside them",
+                ),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Recompile the source whenever it changes, and print the diff of the output \
+                     of --emit or -S against the previous one",
+                ),
+        )
+        .arg(
+            Arg::new("interpret")
+                .long("interpret")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["output", "emit", "s_flag", "watch"])
+                .help(
+                    "Run the IR after the passes with the built-in runtime, instead of generating \
+                     code, and exit with its exit code",
+                ),
         )
-        .arg(Arg::new("source").required(true).help("The source code"))
         .arg(
             Arg::new("s_flag")
                 .short('S')
                 .action(clap::ArgAction::Count)
-                .help("Output an assembly file"),
+                .help("Output an assembly file, i.e., --emit=asm"),
+        )
+        .arg(
+            Arg::new("emit")
+                .long("emit")
+                .value_parser(EMIT_KINDS)
+                .conflicts_with("s_flag")
+                .help("Output the AST, the IR, the machine IR or the assembly instead"),
+        )
+        .arg(
+            Arg::new("error-format")
+                .long("error-format")
+                .value_parser(["human", "json"])
+                .default_value("human")
+                .help("Report the errors for humans, or as JSON lines to stderr for tools"),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .help("Color the errors, by default if stderr is a terminal and NO_COLOR is unset"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(clap::ArgAction::SetTrue)
+                .help("Report the stages of the compilation to stderr"),
+        )
+        .arg(Arg::new("cc").long("cc").help(
+            "The C compiler assembling and linking the code, by default the one of the target",
+        ))
+        .arg(
+            Arg::new("runtime")
+                .long("runtime")
+                .action(clap::ArgAction::Append)
+                .help("Link the given runtime source, object or archive, not the bundled one"),
+        )
+        .arg(
+            Arg::new("static")
+                .long("static")
+                .action(clap::ArgAction::SetTrue)
+                .help("Link the executable statically"),
         )
         .arg(
             Arg::new("opt")
                 .short('O')
-                .help("Optimization level")
+                .long("opt-level")
+                .value_parser(clap::value_parser!(u8).range(0..=3))
+                .help("Optimization level, e.g., -O1")
                 .default_value("0"),
         )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_parser(clap::value_parser!(usize))
+                .help("The threads of the per-function work, e.g., the passes and the register allocation, 0 for all the cores")
+                .default_value("1"),
+        )
+        .arg(
+            Arg::new("target")
+                .long("target")
+                .help(format!(
+                    "The target architecture, one of {}",
+                    TARGETS.map(|target| target.name()).join(", ")
+                ))
+                .default_value(TARGETS[0].name()),
+        )
+        .arg(
+            Arg::new("mcpu")
+                .long("mcpu")
+                .help("The target core to tune the generated code for")
+                .default_value("generic"),
+        )
+        .arg(
+            Arg::new("mattr")
+                .long("mattr")
+                .help("The optional RISC-V extensions to use, e.g., +zba,+zbb")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("omit-frame-pointer")
+                .long("omit-frame-pointer")
+                .action(clap::ArgAction::SetTrue)
+                .help("Address the stack from sp and allocate s0 as a general register"),
+        )
+        .arg(
+            Arg::new("no-schedule")
+                .long("no-schedule")
+                .action(clap::ArgAction::SetTrue)
+                .help("Keep the instructions in the order of the instruction selection"),
+        )
+        .arg(
+            Arg::new("stack-protector")
+                .long("stack-protector")
+                .action(clap::ArgAction::SetTrue)
+                .help("Guard the frames containing arrays against overflows with a canary"),
+        )
+        .arg(
+            Arg::new("verbose-asm")
+                .long("verbose-asm")
+                .action(clap::ArgAction::SetTrue)
+                .help("Comment the assembly with the source lines"),
+        )
+        .arg(
+            Arg::new("verify-machineinstrs")
+                .long("verify-machineinstrs")
+                .action(clap::ArgAction::SetTrue)
+                .help("Check the machine code after each stage of the code generation"),
+        )
         .arg(
             Arg::new("emit-ast")
                 .long("emit-ast")
@@ -35,35 +198,668 @@ fn parse_arguments() -> ArgMatches {
                 .long("emit-llvm-ir")
                 .help("Emit the IR to the specified file"),
         )
-        .get_matches()
+        .arg(
+            Arg::new("emit-mir")
+                .long("emit-mir")
+                .help("Emit the machine IR before and after the register allocation to the file"),
+        )
+        .arg(
+            Arg::new("emit-cfg").long("emit-cfg").help(
+                "Write the control-flow graph of each function as a .dot file to the directory",
+            ),
+        )
+        .arg(
+            Arg::new("emit-domtree")
+                .long("emit-domtree")
+                .help("Write the dominator tree of each function as a .dot file to the directory"),
+        )
+        .arg(
+            Arg::new("emit-postdomtree")
+                .long("emit-postdomtree")
+                .action(clap::ArgAction::SetTrue)
+                .requires("emit-domtree")
+                .help("Also write the post-dominator trees with --emit-domtree"),
+        )
+        .arg(
+            Arg::new("emit-callgraph")
+                .long("emit-callgraph")
+                .help("Write the call graph of the module as a .dot file to the directory"),
+        )
+        .group(
+            ArgGroup::new("graphs")
+                .args(["emit-cfg", "emit-domtree", "emit-callgraph"])
+                .multiple(true),
+        )
+        .arg(
+            Arg::new("graph-before")
+                .long("graph-before")
+                .requires("graphs")
+                .conflicts_with("graph-after")
+                .help(
+                    "Write the graphs before each run of the pass, instead of after the pipeline",
+                ),
+        )
+        .arg(
+            Arg::new("graph-after")
+                .long("graph-after")
+                .requires("graphs")
+                .help("Write the graphs after each run of the pass, instead of after the pipeline"),
+        )
+        .arg(
+            Arg::new("sanitize")
+                .long("sanitize")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Check the divisors are not zero at run time, aborting with the location in \
+                     the source otherwise",
+                ),
+        )
+        .arg(
+            Arg::new("coverage")
+                .long("coverage")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Count the executions of the source lines, written at exit to $SYSY_COVERAGE \
+                     or sysy.cov, for sysy-cov",
+                ),
+        )
+        .arg(
+            Arg::new("profile-generate")
+                .long("profile-generate")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Count the entries of the functions and the branches taken, written at exit \
+                     to $SYSY_PROFILE or sysy.profdata",
+                ),
+        )
+        .arg(
+            Arg::new("profile-use")
+                .long("profile-use")
+                .value_name("FILE")
+                .help(
+                    "Optimize with the counts of a profile written by --profile-generate, instead \
+                     of the static branch probabilities",
+                ),
+        )
+        .arg(
+            Arg::new("passes").long("passes").help(
+                "Run the comma-separated list of passes on the IR, instead of the -O pipeline",
+            ),
+        )
+        .arg(
+            Arg::new("disable-pass")
+                .long("disable-pass")
+                .action(clap::ArgAction::Append)
+                .help("Skip the specified pass in the pipeline"),
+        )
+        .arg(
+            Arg::new("time-passes")
+                .long("time-passes")
+                .action(clap::ArgAction::SetTrue)
+                .help("Report the time spent in each pass to stderr"),
+        )
+        .arg(
+            Arg::new("verify-each")
+                .long("verify-each")
+                .action(clap::ArgAction::SetTrue)
+                .help("Verify the IR after each pass, and report the first pass breaking it"),
+        )
+        .arg(
+            Arg::new("timings")
+                .long("timings")
+                .action(clap::ArgAction::SetTrue)
+                .help("Report the time and the peak memory of each phase to stderr"),
+        )
+        .arg(
+            Arg::new("print-before-all")
+                .long("print-before-all")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print the IR before each pass to stderr"),
+        )
+        .arg(
+            Arg::new("print-after-all")
+                .long("print-after-all")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print the IR after each pass to stderr"),
+        )
+        .arg(
+            Arg::new("print-func")
+                .long("print-func")
+                .help("Only print the specified function before or after the passes"),
+        )
+        .arg(Arg::new("config").long("config").help(format!(
+            "Read the defaults of the options from the file, instead of the {} found in the \
+             working directory or its ancestors",
+            CONFIG_FILE
+        )))
+        .arg(
+            Arg::new("no-config")
+                .long("no-config")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("config")
+                .help(format!(
+                    "Do not read the defaults of the options from {}",
+                    CONFIG_FILE
+                )),
+        )
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Hello, NKUCC!");
+/// Parse the command line, with the defaults of the configuration file.
+///
+/// The options in the file are inserted before the ones of the command line,
+/// except those also given on the command line, which override them.
+fn parse_arguments() -> Result<ArgMatches, String> {
+    let matches = command().get_matches();
+    let path = match matches.get_one::<String>("config") {
+        _ if matches.get_flag("no-config") => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => std::env::current_dir()
+            .ok()
+            .and_then(|dir| find_config(&dir)),
+    };
+    let Some(path) = path else {
+        return Ok(matches);
+    };
+    let config = Config::load(&path).map_err(|err| err.to_string())?;
+    let cmd = command();
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let defaults = config_args(&cmd, &matches, &config)?;
+    args.splice(1..1, defaults.into_iter().map(Into::into));
+    let matches = cmd
+        .try_get_matches_from(args)
+        .unwrap_or_else(|err| err.exit());
+    if matches.get_flag("verbose") {
+        eprintln!("nkucc: using the defaults of `{}`", path.display());
+    }
+    Ok(matches)
+}
 
-    let matches = parse_arguments();
+/// Get the options of the command line given by a configuration file, except
+/// those already on the command line.
+///
+/// Each key is the long name of an option. The options taking a value are
+/// given a string or an integer, or an array of them if repeatable, and the
+/// flags are given a boolean.
+fn config_args(
+    cmd: &Command,
+    matches: &ArgMatches,
+    config: &Config,
+) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for entry in config.entries.iter() {
+        let key = entry.key.as_str();
+        let error = |msg: &str| {
+            let path = config.path.display();
+            format!("{}:{}: `{}` {}", path, entry.line, key, msg)
+        };
+        let arg = cmd
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key) && !["config", "no-config"].contains(&key))
+            .ok_or_else(|| error("is not an option"))?;
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let values = match &entry.value {
+            ConfigValue::Array(values) if matches!(arg.get_action(), ArgAction::Append) => {
+                values.iter().collect()
+            }
+            ConfigValue::Array(_) => return Err(error("takes a single value")),
+            value => vec![value],
+        };
+        for value in values {
+            match (value, arg.get_action().takes_values()) {
+                (ConfigValue::Boolean(true), false) => args.push(format!("--{}", key)),
+                (ConfigValue::Boolean(false), false) => {}
+                (_, false) => return Err(error("is a flag, set to true or false")),
+                (ConfigValue::String(_) | ConfigValue::Integer(_), true) => {
+                    let mut value = value.to_string();
+                    if CONFIG_PATHS.contains(&key) {
+                        value = config.dir().join(value).to_string_lossy().into_owned();
+                    }
+                    args.push(format!("--{}={}", key, value));
+                }
+                (_, true) => return Err(error("takes a string or an integer")),
+            }
+        }
+    }
+    Ok(args)
+}
 
-    // Extract arguments
-    let output = matches.get_one::<String>("output");
-    let emit_llvm_ir = matches.get_one::<String>("emit-llvm-ir");
-    let opt_level = matches.get_one::<String>("opt").unwrap();
-    let source = matches.get_one::<String>("source").unwrap();
-    let emit_assembly = matches.get_count("s_flag") > 0;
+fn main() -> ExitCode {
+    let matches = match parse_arguments() {
+        Ok(matches) => matches,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let file = matches.get_one::<String>("source").unwrap().clone();
+    let json = matches.get_one::<String>("error-format").unwrap() == "json";
+    let color = match matches.get_one::<String>("color").unwrap().as_str() {
+        "always" => true,
+        "never" => false,
+        _ => std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    };
+    if json {
+        // keep the output parsable even if the compiler crashes
+        let file = file.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            let payload = info.payload();
+            let msg = match (
+                payload.downcast_ref::<&str>(),
+                payload.downcast_ref::<String>(),
+            ) {
+                (Some(msg), _) => msg,
+                (_, Some(msg)) => msg.as_str(),
+                _ => "unknown panic",
+            };
+            let mut diag = Diagnostic::error(None, format!("internal compiler error: {}", msg));
+            if let Some(loc) = info.location() {
+                diag = diag.with_note(format!("panicked at {}", loc));
+            }
+            eprintln!("{}", diag.to_json(&Source::new(file.as_str(), "")));
+        }));
+    }
+    timing::set_enabled(matches.get_flag("timings"));
+    let jobs = *matches.get_one::<usize>("jobs").unwrap();
+    if let Err(err) = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build_global()
+    {
+        eprintln!("error: cannot start {} threads: {}", jobs, err);
+        return ExitCode::FAILURE;
+    }
+
+    let path = Path::new(&file);
+    if path.is_dir() {
+        if matches.contains_id("output") {
+            eprintln!("error: `-o` names one output, use `--out-dir` for a directory");
+            return ExitCode::FAILURE;
+        }
+        if let Some(flag) = ["watch", "interpret"]
+            .into_iter()
+            .find(|&f| matches.get_flag(f))
+        {
+            eprintln!("error: `--{}` takes one source, not a directory", flag);
+            return ExitCode::FAILURE;
+        }
+        return compile_dir(&matches, path, json, color);
+    }
+    if matches.get_flag("watch") {
+        return watch(&matches, &file, json, color);
+    }
+    match compile_file(&matches, &file, None, json, color) {
+        Ok(Some(code)) => ExitCode::from(code),
+        Ok(None) => ExitCode::SUCCESS,
+        Err(()) => ExitCode::FAILURE,
+    }
+}
+
+/// Compile a source file, and report the error if any.
+///
+/// The output is the one of `-o` or the default one, unless given. With
+/// `--interpret`, the exit code of the program is returned.
+fn compile_file(
+    matches: &ArgMatches,
+    file: &str,
+    output: Option<&Path>,
+    json: bool,
+    color: bool,
+) -> Result<Option<u8>, ()> {
+    let (source, result) = match std::fs::read_to_string(file) {
+        Ok(text) => {
+            let text = timing::time("preprocessing", || preprocess(&text));
+            let source = Source::new(file, text);
+            let result = compile(matches, &source, output);
+            (source, result)
+        }
+        Err(err) => {
+            let err = format!("cannot read `{}`: {}", file, err).into();
+            (Source::new(file, ""), Err(err))
+        }
+    };
+    if matches.get_flag("timings") {
+        eprint!("{}", timing::report(&timing::take()));
+    }
+    let err = match result {
+        Ok(code) => return Ok(code),
+        Err(err) => err,
+    };
+    let diag = match err.downcast::<Diagnostic>() {
+        Ok(diag) => *diag,
+        Err(err) => Diagnostic::error(None, err.to_string()),
+    };
+    match json {
+        true => eprintln!("{}", diag.to_json(&source)),
+        false => eprint!("{}", diag.render(&source, color)),
+    }
+    Err(())
+}
+
+/// Find the `.sy` files under a directory, recursively, sorted by path.
+fn find_sources(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut sources = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "sy") {
+                sources.push(path);
+            }
+        }
+    }
+    sources.sort();
+    Ok(sources)
+}
+
+/// Compile all the `.sy` files under a directory, and print a summary table.
+///
+/// The failures are reported as in the compilation of a single file, and the
+/// next files are compiled anyway, even after an internal compiler error. The
+/// outputs are the sources with the extension of the output, or without one
+/// for the executables, under `--out-dir` if given.
+fn compile_dir(matches: &ArgMatches, dir: &Path, json: bool, color: bool) -> ExitCode {
+    let sources = match find_sources(dir) {
+        Ok(sources) => sources,
+        Err(err) => {
+            eprintln!("error: cannot read `{}`: {}", dir.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let out_dir = matches.get_one::<String>("out-dir").map(PathBuf::from);
+
+    let mut results = Vec::new();
+    for source in sources {
+        let mut output = default_output(&source, emit_kind(matches), true);
+        if let Some(out_dir) = &out_dir {
+            output = out_dir.join(output.strip_prefix(dir).unwrap());
+            if let Err(err) = std::fs::create_dir_all(output.parent().unwrap()) {
+                eprintln!("error: cannot create `{}`: {}", output.display(), err);
+                results.push((source, "error", Duration::ZERO));
+                continue;
+            }
+        }
+        let file = source.to_string_lossy();
+        let start = Instant::now();
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            compile_file(matches, &file, Some(&output), json, color)
+        }));
+        let status = match result {
+            Ok(Ok(_)) => "ok",
+            Ok(Err(())) => "error",
+            Err(_) => "ice",
+        };
+        results.push((source, status, start.elapsed()));
+    }
 
-    // Validate source file
-    let src = std::fs::read_to_string(source)?;
+    println!("===== Batch summary =====");
+    println!("{:>6}  {:>10}  file", "status", "time (ms)");
+    for (source, status, time) in results.iter() {
+        let ms = time.as_secs_f64() * 1000.0;
+        println!("{:>6}  {:>10.3}  {}", status, ms, source.display());
+    }
+    let count = |status| results.iter().filter(|(_, s, _)| *s == status).count();
+    println!(
+        "{} compiled, {} failed, {} internal errors",
+        count("ok"),
+        count("error"),
+        count("ice")
+    );
+    match count("ok") == results.len() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
 
-    let src = preprocess(&src);
+/// Recompile a source whenever it is modified, and print the diff of the
+/// output against the one of the previous compilation to stdout, until
+/// interrupted.
+///
+/// The modification time of the source is polled, so the editors replacing
+/// the file on saving are followed too. The errors are reported as usual, and
+/// the next compilations are diffed against the last output compiled.
+fn watch(matches: &ArgMatches, file: &str, json: bool, color: bool) -> ExitCode {
+    let Some(emit) = emit_kind(matches) else {
+        eprintln!("error: `--watch` diffs the output of --emit or -S, not an executable");
+        return ExitCode::FAILURE;
+    };
+    let output = match matches.get_one::<String>("output") {
+        Some(output) => PathBuf::from(output),
+        None => default_output(Path::new(file), Some(emit), false),
+    };
+    let modified = || {
+        std::fs::metadata(file)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
 
-    let mut ast = SysYParser::new().parse(&src).unwrap();
+    let mut previous: Option<String> = None;
+    loop {
+        let start = modified();
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            compile_file(matches, file, Some(&output), json, color)
+        }));
+        let text = match result {
+            Ok(Ok(_)) => std::fs::read_to_string(&output).ok(),
+            _ => None,
+        };
+        match (&previous, &text) {
+            (_, None) => {}
+            (None, Some(_)) => println!("nkucc: wrote `{}`", output.display()),
+            (Some(previous), Some(text)) => match diff::unified_diff(previous, text, 3) {
+                diff if diff.is_empty() => println!("nkucc: `{}` unchanged", output.display()),
+                diff => {
+                    println!(
+                        "--- {} (previous)\n+++ {}",
+                        output.display(),
+                        output.display()
+                    );
+                    print!("{}", diff);
+                }
+            },
+        }
+        previous = text.or(previous);
+        println!("nkucc: watching `{}`", file);
+        while modified() == start {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
 
-    ast.type_check();
+/// Get the kind of the output, by `--emit` or `-S`, or `None` for an
+/// executable.
+fn emit_kind(matches: &ArgMatches) -> Option<&str> {
+    match matches.get_one::<String>("emit") {
+        Some(emit) => Some(emit.as_str()),
+        None if matches.get_count("s_flag") > 0 => Some("asm"),
+        None => None,
+    }
+}
 
-    let ir = irgen(&ast, 8);
+/// Get the output of a source without `-o`: the source with the extension of
+/// the output, or `a.out` for an executable, unless named after the source.
+fn default_output(source: &Path, emit: Option<&str>, named_exe: bool) -> PathBuf {
+    match emit {
+        Some(emit) => {
+            let ext = match emit {
+                "ir" => "ll",
+                "asm" => "s",
+                _ => emit,
+            };
+            source.with_extension(ext)
+        }
+        None if named_exe => source.with_extension(""),
+        None => PathBuf::from("a.out"),
+    }
+}
+
+fn compile(
+    matches: &ArgMatches,
+    src: &Source,
+    output: Option<&Path>,
+) -> Result<Option<u8>, Box<dyn std::error::Error>> {
+    // Extract arguments
+    let source = src.file();
+    let emit_llvm_ir = matches.get_one::<String>("emit-llvm-ir");
+    let opt_level = *matches.get_one::<u8>("opt").unwrap();
+    let emit = emit_kind(matches);
+    let output = match (output, matches.get_one::<String>("output")) {
+        (Some(output), _) => output.to_path_buf(),
+        (None, Some(output)) => PathBuf::from(output),
+        (None, None) => default_output(Path::new(source), emit, false),
+    };
+    let verbose = matches.get_flag("verbose");
+    let log = |msg: String| {
+        if verbose {
+            eprintln!("nkucc: {}", msg);
+        }
+    };
+
+    log(format!("parsing `{}`", source));
+    // the lexer is driven by the parser, so they are timed together
+    let mut ast = timing::time("parsing", || SysYParser::new().parse(src.text()))
+        .map_err(Diagnostic::from)?;
+    timing::time("type checking", || ast.type_check())?;
+    if let Some(ast_file) = matches.get_one::<String>("emit-ast") {
+        std::fs::write(ast_file, format!("{:#?}\n", ast))?;
+    }
+    if emit == Some("ast") {
+        std::fs::write(&output, format!("{:#?}\n", ast))?;
+        return Ok(None);
+    }
+
+    let target = target::target_by_name(matches.get_one::<String>("target").unwrap())?;
+    log(format!("generating the IR for `{}`", target.name()));
+    let options = IrGenOptions {
+        sanitize: matches.get_flag("sanitize"),
+    };
+    let mut ir = timing::time("IR generation", || {
+        irgen_with(&ast, target.info(), src, options)
+    });
+
+    let features = TargetFeatures::parse(matches.get_one::<String>("mattr").unwrap())?;
+    let mut pm = PassManager::default();
+    register_passes(&mut pm);
+    // the blocks of the source are counted, before the passes change them, and
+    // so are the branches of a profile
+    if let Some(path) = matches.get_one::<String>("profile-use") {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("cannot read `{}`: {}", path, err))?;
+        let profile = Profile::parse(&text).map_err(|err| format!("`{}`: {}", path, err))?;
+        pm.add_pass(Box::new(ProfileUse::new(profile)));
+    }
+    if matches.get_flag("coverage") {
+        pm.add_pass_by_name("coverage")?;
+    }
+    if matches.get_flag("profile-generate") {
+        pm.add_pass_by_name("profile-generate")?;
+    }
+    match matches.get_one::<String>("passes") {
+        Some(passes) => pm.add_pipeline(passes)?,
+        None => pm.add_pipeline(&opt_pipeline(opt_level, features.zbb))?,
+    }
+    for name in matches
+        .get_many::<String>("disable-pass")
+        .unwrap_or_default()
+    {
+        pm.disable_pass(name)?;
+    }
+    pm.set_print_options(PrintOptions {
+        before_all: matches.get_flag("print-before-all"),
+        after_all: matches.get_flag("print-after-all"),
+        func: matches.get_one::<String>("print-func").cloned(),
+    });
+    pm.set_time_passes(matches.get_flag("time-passes"));
+    pm.set_parallel(*matches.get_one::<usize>("jobs").unwrap() != 1);
+    pm.set_verify_each(matches.get_flag("verify-each"));
+    let graphs = GraphOptions {
+        cfg: matches.get_one::<String>("emit-cfg").map(PathBuf::from),
+        domtree: matches.get_one::<String>("emit-domtree").map(PathBuf::from),
+        postdomtree: matches.get_flag("emit-postdomtree"),
+        callgraph: matches
+            .get_one::<String>("emit-callgraph")
+            .map(PathBuf::from),
+        before: matches.get_one::<String>("graph-before").cloned(),
+        after: matches.get_one::<String>("graph-after").cloned(),
+    };
+    for dir in [&graphs.cfg, &graphs.domtree, &graphs.callgraph]
+        .into_iter()
+        .flatten()
+    {
+        std::fs::create_dir_all(dir)?;
+    }
+    if graphs.enabled() {
+        pm.set_graph_options(graphs.clone())?;
+    }
+    log("optimizing the IR".to_string());
+    timing::time("optimization", || pm.try_run(&mut ir))?;
+    if matches.get_flag("time-passes") {
+        eprint!("{}", pm.timing_report());
+    }
+    if graphs.enabled() && !graphs.around_pass() {
+        graphs
+            .write(&ir, "")
+            .map_err(|(path, err)| format!("cannot write `{}`: {}", path.display(), err))?;
+    }
 
     if let Some(ir_file) = emit_llvm_ir {
-        std::fs::write(ir_file, ir.to_string()).unwrap();
+        std::fs::write(ir_file, ir.to_string())?;
+    }
+    if emit == Some("ir") {
+        std::fs::write(&output, ir.to_string())?;
+        return Ok(None);
+    }
+    if matches.get_flag("interpret") {
+        log("interpreting the IR".to_string());
+        let code = interpret(&ir).map_err(|err| format!("the program failed: {}", err))?;
+        return Ok(Some(code));
+    }
+
+    let options = CodegenOptions {
+        cpu: matches.get_one::<String>("mcpu").unwrap().clone(),
+        omit_frame_pointer: matches.get_flag("omit-frame-pointer"),
+        schedule: !matches.get_flag("no-schedule"),
+        verbose_asm: matches.get_flag("verbose-asm"),
+        stack_protector: matches.get_flag("stack-protector"),
+        features,
+        verify_machineinstrs: matches.get_flag("verify-machineinstrs"),
+    };
+    if let Some(mir_file) = matches.get_one::<String>("emit-mir") {
+        std::fs::write(mir_file, target.emit_mir(&ir, &options)?)?;
+    }
+    if emit == Some("mir") {
+        std::fs::write(&output, target.emit_mir(&ir, &options)?)?;
+        return Ok(None);
     }
+    log("generating the assembly".to_string());
+    let asm = timing::time("code generation", || target.emit_asm(&ir, &options))?;
+    if emit == Some("asm") {
+        std::fs::write(&output, asm)?;
+        return Ok(None);
+    }
+
+    let runtime = matches
+        .get_many::<String>("runtime")
+        .unwrap_or_default()
+        .map(Into::into)
+        .collect::<Vec<_>>();
+    let link_options = LinkOptions {
+        cc: matches
+            .get_one::<String>("cc")
+            .cloned()
+            .unwrap_or_else(|| target.cc().to_string()),
+        bundled_runtime: runtime.is_empty(),
+        runtime,
+        static_link: matches.get_flag("static"),
+    };
+    log(format!(
+        "linking `{}` with `{}`",
+        output.display(),
+        link_options.cc
+    ));
+    timing::time("linking", || driver::link(&asm, &output, &link_options))?;
 
-    Ok(())
+    Ok(None)
 }
@@ -0,0 +1,66 @@
+//! The report of the coverage of a SysY program compiled with `--coverage`.
+//!
+//! The source is annotated with the counts of the lines in the dumps of its
+//! runs, summed, e.g., over the test suite:
+//!
+//! ```text
+//! SYSY_COVERAGE=1.cov ./foo < 1.in
+//! SYSY_COVERAGE=2.cov ./foo < 2.in
+//! sysy-cov foo.sy 1.cov 2.cov
+//! ```
+
+use std::process::ExitCode;
+
+use clap::{Arg, ArgAction, Command};
+use nkucc::ir::passes::coverage_report;
+use nkucc::ir::Source;
+
+fn command() -> Command {
+    Command::new("sysy-cov")
+        .about("Annotate a SysY source with the line counts of its runs")
+        .arg(Arg::new("source").required(true).help("The source file"))
+        .arg(
+            Arg::new("dumps")
+                .action(ArgAction::Append)
+                .default_value("sysy.cov")
+                .help("The dumps of the runs of the program"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .help("Write the report to the file, instead of stdout"),
+        )
+}
+
+fn main() -> ExitCode {
+    let matches = command().get_matches();
+    let read = |path: &String| {
+        std::fs::read_to_string(path).map_err(|err| format!("cannot read `{}`: {}", path, err))
+    };
+    let report = || -> Result<String, String> {
+        let file = matches.get_one::<String>("source").unwrap();
+        let source = Source::new(file.as_str(), read(file)?);
+        let dumps = matches
+            .get_many::<String>("dumps")
+            .unwrap()
+            .map(read)
+            .collect::<Result<Vec<_>, _>>()?;
+        let dumps = dumps.iter().map(String::as_str).collect::<Vec<_>>();
+        coverage_report(&source, &dumps).map_err(|err| err.to_string())
+    };
+    let result = report().and_then(|report| match matches.get_one::<String>("output") {
+        Some(output) => std::fs::write(output, report)
+            .map_err(|err| format!("cannot write `{}`: {}", output, err)),
+        None => {
+            print!("{}", report);
+            Ok(())
+        }
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! The reducer of a SysY program to a minimal one still interesting, e.g.,
+//! still miscompiled.
+//!
+//! The interestingness test is a command, given the path of a candidate as its
+//! last argument, and exiting with 0 if the candidate is interesting, as the
+//! ones of C-Reduce, e.g.,
+//!
+//! ```text
+//! sysyc-reduce foo.sy -- ./interesting.sh
+//! ```
+//!
+//! with `interesting.sh` comparing the output of the program compiled with
+//! `-O2` against the one of `--interpret`. The result is written to
+//! `foo.reduced.sy`.
+
+use std::cell::Cell;
+use std::path::Path;
+use std::process::{Command as Process, ExitCode, Stdio};
+
+use clap::{Arg, ArgAction, Command};
+use nkucc::frontend::reduce;
+
+fn command() -> Command {
+    Command::new("sysyc-reduce")
+        .about("Reduce a SysY program while an interestingness test passes")
+        .arg(
+            Arg::new("input")
+                .required(true)
+                .help("The program to reduce"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .help("The reduced program, `<input>.reduced.sy` by default"),
+        )
+        .arg(
+            Arg::new("test")
+                .last(true)
+                .required(true)
+                .action(ArgAction::Append)
+                .help("The interestingness test, run with the path of a candidate"),
+        )
+}
+
+fn main() -> ExitCode {
+    let matches = command().get_matches();
+    let input = matches.get_one::<String>("input").unwrap();
+    let output = match matches.get_one::<String>("output") {
+        Some(output) => output.clone(),
+        None => Path::new(input)
+            .with_extension("reduced.sy")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let test = matches
+        .get_many::<String>("test")
+        .unwrap()
+        .collect::<Vec<_>>();
+    let candidate = std::env::temp_dir().join(format!("sysyc-reduce-{}.sy", std::process::id()));
+
+    let tests = Cell::new(0);
+    let interesting = |src: &str| -> Result<bool, String> {
+        tests.set(tests.get() + 1);
+        std::fs::write(&candidate, src)
+            .map_err(|err| format!("cannot write `{}`: {}", candidate.display(), err))?;
+        Process::new(test[0])
+            .args(&test[1..])
+            .arg(&candidate)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .map_err(|err| format!("cannot run `{}`: {}", test[0], err))
+    };
+
+    let run = || -> Result<(), String> {
+        let src = std::fs::read_to_string(input)
+            .map_err(|err| format!("cannot read `{}`: {}", input, err))?;
+        if !interesting(&src)? {
+            return Err(format!("`{}` is not interesting", input));
+        }
+        // the errors of the test are kept for after the reduction, the
+        // candidates failing meanwhile
+        let mut error = None;
+        let reduced = reduce(&src, |src| {
+            interesting(src).unwrap_or_else(|err| {
+                error.get_or_insert(err);
+                false
+            })
+        })
+        .ok_or_else(|| format!("`{}` is not a valid program", input))?;
+        if let Some(err) = error {
+            return Err(err);
+        }
+        std::fs::write(&output, &reduced)
+            .map_err(|err| format!("cannot write `{}`: {}", output, err))?;
+        eprintln!(
+            "reduced `{}` from {} to {} bytes, in {} tests, to `{}`",
+            input,
+            src.len(),
+            reduced.len(),
+            tests.get(),
+            output
+        );
+        Ok(())
+    };
+    let result = run();
+    let _ = std::fs::remove_file(&candidate);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
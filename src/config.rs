@@ -0,0 +1,317 @@
+//! The configuration file of a project, `sysyc.toml`.
+//!
+//! The file gives the defaults of the command line, so the test scripts of a
+//! project do not repeat them, e.g.:
+//!
+//! ```toml
+//! # compile for the board
+//! target = "riscv64"
+//! opt-level = 2
+//! mattr = "+zba,+zbb"
+//! disable-pass = ["loop-unroll"]
+//! runtime = ["lib/sylib.a"]
+//! error-format = "json"
+//! ```
+//!
+//! The file is looked up in the working directory and its ancestors, see
+//! [`find_config`]. Only the subset of TOML needed for this is parsed: the
+//! key-value pairs at the top level, whose values are strings, integers,
+//! booleans, or arrays of them, and the comments. The keys are not checked
+//! here, the driver maps them to its options.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+/// The name of the configuration file.
+pub const CONFIG_FILE: &str = "sysyc.toml";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read `{0}`: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("{0}:{1}: {2}")]
+    Syntax(PathBuf, usize, String),
+}
+
+/// A value in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<ConfigValue>),
+}
+
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigValue::String(s) => write!(f, "{}", s),
+            ConfigValue::Integer(n) => write!(f, "{}", n),
+            ConfigValue::Boolean(b) => write!(f, "{}", b),
+            ConfigValue::Array(values) => {
+                let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", values.join(","))
+            }
+        }
+    }
+}
+
+/// A key-value pair of the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: ConfigValue,
+    /// The line of the key, from 1.
+    pub line: usize,
+}
+
+/// A configuration file.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The path of the file.
+    pub path: PathBuf,
+    /// The pairs, in the order of the file.
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    /// Read and parse a configuration file.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|err| ConfigError::Read(path.into(), err))?;
+        let entries = parse_config(&text)
+            .map_err(|(line, msg)| ConfigError::Syntax(path.into(), line, msg))?;
+        Ok(Config {
+            path: path.into(),
+            entries,
+        })
+    }
+
+    /// Get the directory of the file, which the relative paths in it are
+    /// relative to.
+    pub fn dir(&self) -> &Path { self.path.parent().unwrap_or(Path::new(".")) }
+}
+
+/// Find the configuration file in the directory or the nearest ancestor.
+pub fn find_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Parse the text of a configuration file, or get the line and the message of
+/// the error.
+pub fn parse_config(text: &str) -> Result<Vec<ConfigEntry>, (usize, String)> {
+    let mut parser = Parser {
+        rest: text,
+        line: 1,
+    };
+    let mut entries: Vec<ConfigEntry> = Vec::new();
+    loop {
+        parser.skip_blank(true);
+        let Some(c) = parser.peek() else {
+            return Ok(entries);
+        };
+        let line = parser.line;
+        if c == '[' {
+            return Err((line, "tables are not supported".to_string()));
+        }
+        let key = parser.key()?;
+        if entries.iter().any(|entry| entry.key == key) {
+            return Err((line, format!("duplicate key `{}`", key)));
+        }
+        parser.skip_blank(false);
+        parser.expect('=')?;
+        parser.skip_blank(false);
+        let value = parser.value()?;
+        parser.skip_blank(false);
+        match parser.bump() {
+            None | Some('\n') => {}
+            Some(c) => return Err((parser.line, format!("unexpected `{}` after the value", c))),
+        }
+        entries.push(ConfigEntry { key, value, line });
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> { self.rest.chars().next() }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), (usize, String)> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => Err((self.line, format!("expected `{}`, found `{}`", expected, c))),
+            None => Err((self.line, format!("expected `{}`", expected))),
+        }
+    }
+
+    /// Skip the spaces and the comments, and the newlines if asked.
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {}
+                '\n' if newlines => {}
+                '#' => {
+                    let end = self.rest.find('\n').unwrap_or(self.rest.len());
+                    self.rest = &self.rest[end..];
+                    continue;
+                }
+                _ => return,
+            }
+            self.bump();
+        }
+    }
+
+    fn key(&mut self) -> Result<String, (usize, String)> {
+        if matches!(self.peek(), Some('"' | '\'')) {
+            return self.string();
+        }
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            let c = self.peek().unwrap();
+            return Err((self.line, format!("expected a key, found `{}`", c)));
+        }
+        let key = self.rest[..end].to_string();
+        self.rest = &self.rest[end..];
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<ConfigValue, (usize, String)> {
+        match self.peek() {
+            Some('"' | '\'') => self.string().map(ConfigValue::String),
+            Some('[') => {
+                self.bump();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_blank(true);
+                    if self.peek() == Some(']') {
+                        break;
+                    }
+                    values.push(self.value()?);
+                    self.skip_blank(true);
+                    if self.peek() != Some(']') {
+                        self.expect(',')?;
+                    }
+                }
+                self.bump();
+                Ok(ConfigValue::Array(values))
+            }
+            Some(_) => {
+                let end = self
+                    .rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || "+-_".contains(c)))
+                    .unwrap_or(self.rest.len());
+                let word = &self.rest[..end];
+                let value = match word {
+                    "true" => ConfigValue::Boolean(true),
+                    "false" => ConfigValue::Boolean(false),
+                    _ => word
+                        .replace('_', "")
+                        .parse()
+                        .ok()
+                        .filter(|_| !word.starts_with('_') && !word.ends_with('_'))
+                        .map(ConfigValue::Integer)
+                        .ok_or_else(|| (self.line, format!("invalid value `{}`", word)))?,
+                };
+                self.rest = &self.rest[end..];
+                Ok(value)
+            }
+            None => Err((self.line, "expected a value".to_string())),
+        }
+    }
+
+    /// Parse a basic string with the escapes, or a literal string without.
+    fn string(&mut self) -> Result<String, (usize, String)> {
+        let line = self.line;
+        let quote = self.bump().unwrap();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(s),
+                Some('\\') if quote == '"' => {
+                    let c = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(c @ ('"' | '\\')) => c,
+                        Some(c) => return Err((self.line, format!("invalid escape `\\{}`", c))),
+                        None => break,
+                    };
+                    s.push(c);
+                }
+                Some('\n') | None => break,
+                Some(c) => s.push(c),
+            }
+        }
+        Err((line, "unterminated string".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let text = "# defaults\n\
+                    target = \"x86_64\"  # the host\n\
+                    opt-level = 2\n\
+                    static = true\n\
+                    'print-func' = 'main'\n\
+                    disable-pass = [\n  \"licm\", # slow\n  \"gvn\",\n]\n\
+                    mattr = \"+zba,\\\"\"\n";
+        let entries = parse_config(text).unwrap();
+        let pairs = entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.clone(), entry.line))
+            .collect::<Vec<_>>();
+        let string = |s: &str| ConfigValue::String(s.to_string());
+        assert_eq!(
+            pairs,
+            [
+                ("target", string("x86_64"), 2),
+                ("opt-level", ConfigValue::Integer(2), 3),
+                ("static", ConfigValue::Boolean(true), 4),
+                ("print-func", string("main"), 5),
+                (
+                    "disable-pass",
+                    ConfigValue::Array(vec![string("licm"), string("gvn")]),
+                    6
+                ),
+                ("mattr", string("+zba,\""), 10),
+            ]
+        );
+        assert_eq!(pairs[4].1.to_string(), "licm,gvn");
+        assert_eq!(parse_config("").unwrap(), []);
+
+        let error = |text| parse_config(text).unwrap_err();
+        assert_eq!(error("a = 1\na = 2").0, 2);
+        assert_eq!(error("\n[build]\n"), (2, "tables are not supported".into()));
+        assert_eq!(error("a = \"b\nc = 1").0, 1);
+        assert_eq!(error("a = yes").1, "invalid value `yes`");
+        assert_eq!(error("a = 1 2").1, "unexpected `2` after the value");
+        assert_eq!(error("a = [1 2]").1, "expected `,`, found `2`");
+        assert_eq!(error("= 1").1, "expected a key, found `=`");
+    }
+}
@@ -0,0 +1,137 @@
+//! Assembling and linking the generated code into an executable.
+//!
+//! The compiler only emits assembly. To produce a runnable binary in one
+//! command, the assembly is written to a temporary file, and the C compiler of
+//! the target assembles it and links it with the SysY runtime, e.g.:
+//!
+//! ```text
+//! riscv64-linux-gnu-gcc -o foo /tmp/nkucc-1234-0/nkucc.s sylib.c
+//! ```
+//!
+//! The SysY runtime is bundled as C sources, see [`SYLIB_C`], and compiled
+//! along with the assembly unless another runtime is given, so the programs
+//! link without an external library for each target.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use thiserror::Error;
+
+/// The source of the bundled SysY runtime.
+pub const SYLIB_C: &str = include_str!("runtime/sylib.c");
+/// The header of the bundled SysY runtime, included by [`SYLIB_C`].
+pub const SYLIB_H: &str = include_str!("runtime/sylib.h");
+
+#[derive(Debug, Error)]
+pub enum LinkError {
+    #[error("cannot write `{0}`: {1}")]
+    Write(PathBuf, std::io::Error),
+    #[error("cannot run `{0}`: {1}")]
+    Spawn(String, std::io::Error),
+    #[error("`{0}` failed with {1}")]
+    Failed(String, ExitStatus),
+}
+
+/// The options of assembling and linking.
+#[derive(Debug, Clone, Default)]
+pub struct LinkOptions {
+    /// The C compiler, e.g., `riscv64-linux-gnu-gcc`.
+    pub cc: String,
+    /// The runtime linked with the code, as sources, objects or archives.
+    pub runtime: Vec<PathBuf>,
+    /// If the bundled runtime is compiled and linked with the code.
+    pub bundled_runtime: bool,
+    /// If the executable is linked statically, e.g., to run it with
+    /// `qemu-riscv64` without the target libraries installed.
+    pub static_link: bool,
+}
+
+/// Build the command assembling and linking an assembly file.
+pub fn link_command(asm: &Path, output: &Path, options: &LinkOptions) -> Command {
+    let mut cmd = Command::new(&options.cc);
+    cmd.arg("-o").arg(output).arg(asm);
+    cmd.args(&options.runtime);
+    if options.static_link {
+        cmd.arg("-static");
+    }
+    cmd
+}
+
+/// Assemble and link the assembly into an executable.
+///
+/// The assembly and the bundled runtime are written to a temporary directory,
+/// which is removed afterwards, whether the command succeeds or not.
+pub fn link(asm: &str, output: &Path, options: &LinkOptions) -> Result<(), LinkError> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "nkucc-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = link_in(&dir, asm, output, options);
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+/// Link with the temporary files in a directory.
+fn link_in(dir: &Path, asm: &str, output: &Path, options: &LinkOptions) -> Result<(), LinkError> {
+    let write = |name: &str, contents: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, contents)
+            .map(|_| path.clone())
+            .map_err(|err| LinkError::Write(path, err))
+    };
+    std::fs::create_dir_all(dir).map_err(|err| LinkError::Write(dir.to_path_buf(), err))?;
+    let asm_file = write("nkucc.s", asm)?;
+    let mut options = options.clone();
+    if options.bundled_runtime {
+        write("sylib.h", SYLIB_H)?;
+        let sylib = write("sylib.c", SYLIB_C)?;
+        options.runtime.push(sylib);
+    }
+    let status = link_command(&asm_file, output, &options).status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(LinkError::Failed(options.cc.clone(), status)),
+        Err(err) => Err(LinkError::Spawn(options.cc.clone(), err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_command() {
+        let options = LinkOptions {
+            cc: "riscv64-linux-gnu-gcc".to_string(),
+            runtime: vec![PathBuf::from("sylib.c")],
+            bundled_runtime: false,
+            static_link: true,
+        };
+        let cmd = link_command(Path::new("foo.s"), Path::new("foo"), &options);
+        assert_eq!(cmd.get_program(), "riscv64-linux-gnu-gcc");
+        let args = cmd.get_args().collect::<Vec<_>>();
+        assert_eq!(args, ["-o", "foo", "foo.s", "sylib.c", "-static"]);
+    }
+
+    #[test]
+    fn test_link_missing_cc() {
+        let options = LinkOptions {
+            cc: "nkucc-no-such-cc".to_string(),
+            bundled_runtime: true,
+            ..LinkOptions::default()
+        };
+        let err = link("", Path::new("foo"), &options).unwrap_err();
+        assert!(matches!(err, LinkError::Spawn(..)));
+    }
+
+    #[test]
+    fn test_bundled_runtime() {
+        // the timing states declared in the header are defined in the source
+        assert!(SYLIB_H.contains("extern int _sysy_idx;"));
+        assert!(SYLIB_C.contains("\nint _sysy_idx;"));
+        assert!(SYLIB_C.contains("#include \"sylib.h\""));
+    }
+}
@@ -1,11 +1,17 @@
 mod ast;
+mod diagnostic;
+mod generate;
 mod irgen;
 mod parse;
 mod preprocess;
+mod reduce;
 mod types;
 
 pub use ast::*;
+pub use diagnostic::*;
+pub use generate::*;
 pub use irgen::*;
 pub use parse::*;
 pub use preprocess::*;
+pub use reduce::*;
 pub use types::*;
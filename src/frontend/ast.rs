@@ -1,9 +1,10 @@
 //! Abstract Syntax Tree (AST) for the SysY language.
 
-use std::collections::HashMap;
-
+use super::diagnostic::{Diagnostic, Span};
 use super::irgen::IrGenResult;
 use super::types::{Type, TypeKind as Tk};
+use crate::infra::hash::HashMap;
+use crate::infra::intern::{Symbol, ToSymbol};
 
 /// Represents a constant value that can be evaluated at compile time.
 #[derive(Debug, Clone)]
@@ -82,7 +83,9 @@ impl ComptimeVal {
         Self::Bool(lhs && rhs)
     }
 
-    // Comptime value operations are used in constant folding.
+    // Comptime value operations are used in constant folding. They wrap
+    // around on overflow, like the instructions they fold, and the division
+    // and the remainder by zero are left to the caller, see `Expr::try_fold`.
     // Your compiler can still work without these operations, but it will be less
     // efficient.
     //
@@ -130,7 +133,7 @@ impl std::ops::Neg for ComptimeVal {
         use ComptimeVal as Cv;
         match self {
             Cv::Bool(a) => Cv::Int(-(a as i32)),
-            Cv::Int(a) => Cv::Int(-a),
+            Cv::Int(a) => Cv::Int(a.wrapping_neg()),
             Cv::Undef(_) => panic!("negating undefined comptime value"),
         }
     }
@@ -155,12 +158,12 @@ impl std::ops::Add for ComptimeVal {
     fn add(self, other: Self) -> Self {
         use ComptimeVal as Cv;
         match (self, other) {
-            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a + b),
+            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a.wrapping_add(b)),
 
             // coercion situations, bool -> int
-            (Cv::Bool(a), Cv::Int(b)) => Cv::Int(a as i32 + b),
-            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a + b as i32),
-            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int(a as i32 + b as i32),
+            (Cv::Bool(a), Cv::Int(b)) => Cv::Int((a as i32).wrapping_add(b)),
+            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a.wrapping_add(b as i32)),
+            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int((a as i32).wrapping_add(b as i32)),
 
             _ => panic!("unsupported addition"),
         }
@@ -174,12 +177,12 @@ impl std::ops::Sub for ComptimeVal {
         use ComptimeVal as Cv;
 
         match (self, other) {
-            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a - b),
+            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a.wrapping_sub(b)),
 
             // coercion situations, bool -> int
-            (Cv::Bool(a), Cv::Int(b)) => Cv::Int(a as i32 - b),
-            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a - b as i32),
-            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int(a as i32 - b as i32),
+            (Cv::Bool(a), Cv::Int(b)) => Cv::Int((a as i32).wrapping_sub(b)),
+            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a.wrapping_sub(b as i32)),
+            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int((a as i32).wrapping_sub(b as i32)),
 
             _ => panic!("unsupported subtraction"),
         }
@@ -192,12 +195,12 @@ impl std::ops::Mul for ComptimeVal {
     fn mul(self, other: Self) -> Self {
         use ComptimeVal as Cv;
         match (self, other) {
-            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a * b),
+            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a.wrapping_mul(b)),
 
             // coercion situations, bool -> int
-            (Cv::Bool(a), Cv::Int(b)) => Cv::Int(a as i32 * b),
-            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a * b as i32),
-            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int(a as i32 * b as i32),
+            (Cv::Bool(a), Cv::Int(b)) => Cv::Int((a as i32).wrapping_mul(b)),
+            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a.wrapping_mul(b as i32)),
+            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int((a as i32).wrapping_mul(b as i32)),
 
             _ => panic!("unsupported multiplication"),
         }
@@ -210,12 +213,12 @@ impl std::ops::Div for ComptimeVal {
     fn div(self, other: Self) -> Self {
         use ComptimeVal as Cv;
         match (self, other) {
-            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a / b),
+            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a.wrapping_div(b)),
 
             // coercion situations, bool -> int
-            (Cv::Bool(a), Cv::Int(b)) => Cv::Int(a as i32 / b),
-            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a / b as i32),
-            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int(a as i32 / b as i32),
+            (Cv::Bool(a), Cv::Int(b)) => Cv::Int((a as i32).wrapping_div(b)),
+            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a.wrapping_div(b as i32)),
+            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int((a as i32).wrapping_div(b as i32)),
 
             _ => panic!("unsupported division"),
         }
@@ -228,12 +231,12 @@ impl std::ops::Rem for ComptimeVal {
     fn rem(self, other: Self) -> Self {
         use ComptimeVal as Cv;
         match (self, other) {
-            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a % b),
+            (Cv::Int(a), Cv::Int(b)) => Cv::Int(a.wrapping_rem(b)),
 
             // bool -> int
-            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int(a as i32 % b as i32),
-            (Cv::Bool(a), Cv::Int(b)) => Cv::Int(a as i32 % b),
-            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a % b as i32),
+            (Cv::Bool(a), Cv::Bool(b)) => Cv::Int((a as i32).wrapping_rem(b as i32)),
+            (Cv::Bool(a), Cv::Int(b)) => Cv::Int((a as i32).wrapping_rem(b)),
+            (Cv::Int(a), Cv::Bool(b)) => Cv::Int(a.wrapping_rem(b as i32)),
 
             _ => panic!("unsupported remainder"),
         }
@@ -269,7 +272,7 @@ pub enum UnaryOp {
 /// Function call.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FuncCall {
-    pub ident: String,
+    pub ident: Symbol,
     pub args: Vec<Expr>,
 }
 
@@ -279,7 +282,7 @@ pub struct FuncCall {
 /// Its usually on the left side of an assignment.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LVal {
-    pub ident: String,
+    pub ident: Symbol,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -306,6 +309,8 @@ pub struct Expr {
     /// Type of the expression.
     /// Its generated during type checking.
     pub ty: Option<Type>,
+    /// The byte range of the expression in the source, if parsed.
+    pub span: Option<Span>,
 }
 
 impl PartialEq for Expr {
@@ -320,27 +325,38 @@ impl Expr {
         Self {
             kind: ExprKind::Const(val),
             ty: Some(ty),
+            span: None,
         }
     }
 
+    /// Create a binary expression, spanning from the left hand side to the
+    /// right hand side.
     pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
+        let span = lhs.span.zip(rhs.span).map(|(lhs, rhs)| Span {
+            start: lhs.start,
+            end: rhs.end,
+        });
         Self {
             kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
             ty: None,
+            span,
         }
     }
 
     pub fn unary(op: UnaryOp, expr: Expr) -> Self {
+        let span = expr.span;
         Self {
             kind: ExprKind::Unary(op, Box::new(expr)),
             ty: None,
+            span,
         }
     }
 
-    pub fn func_call(ident: String, args: Vec<Expr>) -> Self {
+    pub fn func_call(ident: Symbol, args: Vec<Expr>) -> Self {
         Self {
             kind: ExprKind::FuncCall(FuncCall { ident, args }),
             ty: None,
+            span: None,
         }
     }
 
@@ -348,9 +364,16 @@ impl Expr {
         Self {
             kind: ExprKind::LVal(lval),
             ty: None,
+            span: None,
         }
     }
 
+    /// Locate the expression in the source.
+    pub fn with_span(mut self, start: usize, end: usize) -> Self {
+        self.span = Some(Span { start, end });
+        self
+    }
+
     pub fn coercion(expr: Expr, to: Type) -> Self {
         if let Some(ref from) = expr.ty {
             if from == &to {
@@ -358,9 +381,11 @@ impl Expr {
             }
         }
 
+        let span = expr.span;
         Self {
             kind: ExprKind::Coercion(Box::new(expr)),
             ty: Some(to),
+            span,
         }
     }
 }
@@ -421,7 +446,8 @@ pub enum BlockItem {
 /// e.g. `{ ... }`
 #[derive(Debug)]
 pub struct Block {
-    pub items: Vec<BlockItem>,
+    /// The items, with their byte ranges in the source.
+    pub items: Vec<(Span, BlockItem)>,
 }
 
 /// Declaration.
@@ -442,7 +468,7 @@ pub enum Decl {
 /// ```
 #[derive(Debug)]
 pub struct ConstDef {
-    pub ident: String,
+    pub ident: Symbol,
     pub init: Expr,
 }
 
@@ -455,7 +481,7 @@ pub struct ConstDef {
 /// ```
 #[derive(Debug)]
 pub struct VarDef {
-    pub ident: String,
+    pub ident: Symbol,
     pub init: Option<Expr>,
 }
 
@@ -487,7 +513,7 @@ pub struct VarDecl {
 #[derive(Debug)]
 pub struct FuncFParam {
     pub ty: Type,
-    pub ident: String,
+    pub ident: Symbol,
 }
 
 /// Function definition.
@@ -497,7 +523,7 @@ pub struct FuncDef {
     /// Type of the return value.
     pub ret_ty: Type,
     /// Identifier of the function. (Name of the function)
-    pub ident: String,
+    pub ident: Symbol,
     /// Parameters of the function.
     pub params: Vec<FuncFParam>,
     /// Body of the function. It contains a block of statements.
@@ -518,7 +544,8 @@ pub enum Item {
 /// It contains a series of global items.
 #[derive(Debug)]
 pub struct CompUnit {
-    pub items: Vec<Item>,
+    /// The items, with their byte ranges in the source.
+    pub items: Vec<(Span, Item)>,
 }
 
 /// Symbol table entry.
@@ -551,7 +578,7 @@ impl SymbolEntry {
 pub struct SymbolTable {
     /// Stack of scopes.
     /// Each scope has its own hashmap of symbols.
-    stack: Vec<HashMap<String, SymbolEntry>>,
+    stack: Vec<HashMap<Symbol, SymbolEntry>>,
 
     /// The current return type of the function.
     pub curr_ret_ty: Option<Type>,
@@ -565,12 +592,12 @@ impl SymbolTable {
     pub fn leave_scope(&mut self) { self.stack.pop(); }
 
     /// Insert a symbol into the current scope.
-    pub fn insert(&mut self, name: impl Into<String>, entry: SymbolEntry) {
+    pub fn insert(&mut self, name: impl Into<Symbol>, entry: SymbolEntry) {
         self.stack.last_mut().unwrap().insert(name.into(), entry);
     }
 
     /// Insert a symbol into the `upper`-th scope from the current scope.
-    pub fn insert_upper(&mut self, name: impl Into<String>, entry: SymbolEntry, upper: usize) {
+    pub fn insert_upper(&mut self, name: impl Into<Symbol>, entry: SymbolEntry, upper: usize) {
         self.stack
             .iter_mut()
             .rev()
@@ -580,9 +607,10 @@ impl SymbolTable {
     }
 
     /// Lookup a symbol in the symbol table.
-    pub fn lookup(&self, name: &str) -> Option<&SymbolEntry> {
+    pub fn lookup(&self, name: impl ToSymbol) -> Option<&SymbolEntry> {
+        let name = name.to_symbol()?;
         for scope in self.stack.iter().rev() {
-            if let Some(entry) = scope.get(name) {
+            if let Some(entry) = scope.get(&name) {
                 return Some(entry);
             }
         }
@@ -590,9 +618,10 @@ impl SymbolTable {
     }
 
     /// Lookup a symbol in the symbol table.
-    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut SymbolEntry> {
+    pub fn lookup_mut(&mut self, name: impl ToSymbol) -> Option<&mut SymbolEntry> {
+        let name = name.to_symbol()?;
         for scope in self.stack.iter_mut().rev() {
-            if let Some(entry) = scope.get_mut(name) {
+            if let Some(entry) = scope.get_mut(&name) {
                 return Some(entry);
             }
         }
@@ -607,7 +636,10 @@ impl SymbolTable {
 
 impl CompUnit {
     /// Type check the compilation unit.
-    pub fn type_check(&mut self) {
+    ///
+    /// The errors are located at the innermost expression or item they are
+    /// found in, see [`Diagnostic::or_span`].
+    pub fn type_check(&mut self) -> Result<(), Diagnostic> {
         let mut symtable = SymbolTable::default();
         symtable.enter_scope();
 
@@ -615,17 +647,19 @@ impl CompUnit {
         symtable.register_sysylib();
 
         // type check each item
-        for item in self.items.iter_mut() {
-            item.type_check(&mut symtable);
+        for (span, item) in self.items.iter_mut() {
+            item.type_check(&mut symtable)
+                .map_err(|diag| diag.or_span(*span))?;
         }
 
         symtable.leave_scope();
+        Ok(())
     }
 }
 
 impl Item {
     /// Type check the item.
-    pub fn type_check(&mut self, symtable: &mut SymbolTable) {
+    pub fn type_check(&mut self, symtable: &mut SymbolTable) -> Result<(), Diagnostic> {
         match self {
             Item::Decl(decl) => match decl {
                 Decl::ConstDecl(decl) => decl.type_check(symtable),
@@ -644,21 +678,22 @@ impl Item {
                 let mut param_tys = Vec::new();
                 for param in params.iter() {
                     param_tys.push(param.ty.clone());
-                    symtable.insert(param.ident.clone(), SymbolEntry::from_ty(param.ty.clone()));
+                    symtable.insert(param.ident, SymbolEntry::from_ty(param.ty.clone()));
                 }
 
                 let func_ty = Type::func(param_tys, ret_ty.clone());
 
                 // Insert the function symbol into the scope above the current scope, since we
                 // are in the parameters scope
-                symtable.insert_upper(ident.clone(), SymbolEntry::from_ty(func_ty), 1);
+                symtable.insert_upper(*ident, SymbolEntry::from_ty(func_ty), 1);
                 symtable.curr_ret_ty = Some(ret_ty.clone());
 
                 // Type check the function body
-                body.type_check(symtable);
+                body.type_check(symtable)?;
 
                 symtable.curr_ret_ty = None;
                 symtable.leave_scope();
+                Ok(())
             }
         }
     }
@@ -666,7 +701,7 @@ impl Item {
 
 impl ConstDecl {
     /// Type check the constant declaration.
-    pub fn type_check(&mut self, symtable: &mut SymbolTable) {
+    pub fn type_check(&mut self, symtable: &mut SymbolTable) -> Result<(), Diagnostic> {
         let mut new_defs = Vec::new();
         for mut def in self.defs.drain(..) {
             // TODO: array type checking
@@ -674,15 +709,25 @@ impl ConstDecl {
             let ty = self.ty.clone();
 
             // Type check the init expression
-            def.init = def.init.type_check(Some(&ty), symtable);
+            def.init = def.init.type_check(Some(&ty), symtable)?;
 
             // Fold the init expression into a constant value
-            let folded = def.init.try_fold(symtable).expect("non-constant init");
+            let folded = def.init.try_fold(symtable).ok_or_else(|| {
+                Diagnostic::error(
+                    Some("E0102"),
+                    format!(
+                        "the initializer of constant `{}` is not a constant",
+                        def.ident
+                    ),
+                )
+            })?;
+            let span = def.init.span;
             def.init = Expr::const_(folded.clone());
+            def.init.span = span;
 
             // Insert the constant into the symbol table
             symtable.insert(
-                def.ident.clone(),
+                def.ident,
                 SymbolEntry {
                     ty,
                     comptime: Some(folded),
@@ -692,12 +737,13 @@ impl ConstDecl {
             new_defs.push(def);
         }
         self.defs = new_defs;
+        Ok(())
     }
 }
 
 impl VarDecl {
     /// Type check the variable declaration.
-    pub fn type_check(&mut self, symtable: &mut SymbolTable) {
+    pub fn type_check(&mut self, symtable: &mut SymbolTable) -> Result<(), Diagnostic> {
         let mut new_defs = Vec::new();
         for mut def in self.defs.drain(..) {
             // TODO: array type checking
@@ -710,12 +756,13 @@ impl VarDecl {
                 .map(|init| {
                     // fold as much as possible
                     // XXX: what if we do not fold here?
-                    let typed_init = init.type_check(Some(&ty), symtable);
-                    match typed_init.try_fold(symtable) {
+                    let typed_init = init.type_check(Some(&ty), symtable)?;
+                    Ok::<_, Diagnostic>(match typed_init.try_fold(symtable) {
                         Some(val) => Expr::const_(val),
                         None => typed_init,
-                    }
+                    })
                 })
+                .transpose()?
                 // TODO: assign undef
                 .unwrap_or_else(|| {
                     let undef = ComptimeVal::undef(ty.clone());
@@ -725,109 +772,106 @@ impl VarDecl {
             def.init = Some(init);
 
             // Insert the variable into the symbol table
-            symtable.insert(def.ident.clone(), SymbolEntry::from_ty(ty));
+            symtable.insert(def.ident, SymbolEntry::from_ty(ty));
             new_defs.push(def);
         }
         self.defs = new_defs;
+        Ok(())
     }
 }
 
 impl Block {
     /// Type check the block.
-    pub fn type_check(&mut self, symtable: &mut SymbolTable) {
+    pub fn type_check(&mut self, symtable: &mut SymbolTable) -> Result<(), Diagnostic> {
         // Enter a new scope
         symtable.enter_scope();
         let mut new_items = Vec::new();
 
         // Type check each block item in the block
-        for item in self.items.drain(..) {
+        for (span, item) in self.items.drain(..) {
             let item = match item {
                 BlockItem::Decl(decl) => match decl {
-                    Decl::ConstDecl(mut decl) => {
-                        decl.type_check(symtable);
-                        BlockItem::Decl(Decl::ConstDecl(decl))
-                    }
-                    Decl::VarDecl(mut decl) => {
-                        decl.type_check(symtable);
-                        BlockItem::Decl(Decl::VarDecl(decl))
-                    }
+                    Decl::ConstDecl(mut decl) => decl
+                        .type_check(symtable)
+                        .map(|_| BlockItem::Decl(Decl::ConstDecl(decl))),
+                    Decl::VarDecl(mut decl) => decl
+                        .type_check(symtable)
+                        .map(|_| BlockItem::Decl(Decl::VarDecl(decl))),
                 },
-                BlockItem::Stmt(stmt) => {
-                    let stmt = stmt.type_check(symtable);
-                    BlockItem::Stmt(stmt)
-                }
+                BlockItem::Stmt(stmt) => stmt.type_check(symtable).map(BlockItem::Stmt),
             };
-            new_items.push(item);
+            // the innermost item is the most precise location
+            new_items.push((span, item.map_err(|diag| diag.or_span(span))?));
         }
         self.items = new_items;
         symtable.leave_scope();
+        Ok(())
     }
 }
 
 impl Stmt {
     /// Type check the statement.
-    pub fn type_check(self, symtable: &mut SymbolTable) -> Self {
-        match self {
+    pub fn type_check(self, symtable: &mut SymbolTable) -> Result<Self, Diagnostic> {
+        let stmt = match self {
             Stmt::Assign(LVal { ident }, expr) => {
                 // lookup the variable in the symbol table
-                let entry = symtable.lookup(&ident).expect("variable not found");
+                let entry = symtable.lookup(ident).ok_or_else(|| not_found(ident))?;
 
                 // TODO: array type checking
 
                 let ty = &entry.ty;
 
                 // Type check the expression
-                let expr = expr.type_check(Some(ty), symtable);
+                let expr = expr.type_check(Some(ty), symtable)?;
                 Stmt::Assign(LVal { ident }, expr)
             }
             Stmt::Expr(ExprStmt { expr }) => {
                 // Type check the expression
-                let expr = expr.map(|expr| expr.type_check(None, symtable));
+                let expr = expr
+                    .map(|expr| expr.type_check(None, symtable))
+                    .transpose()?;
                 Stmt::Expr(ExprStmt { expr })
             }
             Stmt::Block(mut block) => {
                 // Type check the block
-                block.type_check(symtable);
+                block.type_check(symtable)?;
                 Stmt::Block(block)
             }
             Stmt::Break => Stmt::Break,
             Stmt::Continue => Stmt::Continue,
             Stmt::Return(ReturnStmt { expr }) => {
-                // Type check the return expression
-                let expr =
-                    expr.map(|expr| expr.type_check(symtable.curr_ret_ty.as_ref(), symtable));
-
-                // Void return
-                if expr.is_none() {
-                    return Stmt::Return(ReturnStmt { expr });
-                }
-
-                let mut expr = expr.unwrap();
-                let ret_ty = symtable.curr_ret_ty.as_ref().unwrap();
-
-                if ret_ty.is_int() {
-                    // Coerce the expression to int if needed
-                    expr = Expr::coercion(expr, Type::int());
-                } else {
-                    panic!("unsupported return type");
+                let ret_ty = symtable.curr_ret_ty.clone().unwrap();
+                let Some(expr) = expr else {
+                    // Void return
+                    return Ok(Stmt::Return(ReturnStmt { expr: None }));
+                };
+                if !ret_ty.is_int() {
+                    return Err(mismatched(&ret_ty, "a value"));
                 }
 
+                // Type check the return expression, and coerce it to int if
+                // needed
+                let expr = expr.type_check(Some(&ret_ty), symtable)?;
+                let expr = Expr::coercion(expr, Type::int());
                 Stmt::Return(ReturnStmt { expr: Some(expr) })
             }
             Stmt::If(cond, then_block, else_block) => {
                 // Type check the condition expression and the blocks
-                let cond = cond.type_check(Some(&Type::bool()), symtable);
-                let then_block = then_block.type_check(symtable);
-                let else_block = else_block.map(|block| block.type_check(symtable));
+                let cond = cond.type_check(Some(&Type::bool()), symtable)?;
+                let then_block = then_block.type_check(symtable)?;
+                let else_block = else_block
+                    .map(|block| block.type_check(symtable))
+                    .transpose()?;
                 Stmt::If(cond, Box::new(then_block), else_block.map(Box::new))
             }
             Stmt::While(cond, block) => {
                 // Type check the condition expression and the block
-                let cond = cond.type_check(Some(&Type::bool()), symtable);
-                let block = block.type_check(symtable);
+                let cond = cond.type_check(Some(&Type::bool()), symtable)?;
+                let block = block.type_check(symtable)?;
                 Stmt::While(cond, Box::new(block))
             }
-        }
+        };
+        Ok(stmt)
     }
 }
 
@@ -836,6 +880,8 @@ impl Expr {
     pub fn ty(&self) -> &Type { self.ty.as_ref().unwrap() }
 
     /// Try to fold the expression into a constant value.
+    ///
+    /// A division or a remainder by zero is not folded.
     pub fn try_fold(&self, symtable: &SymbolTable) -> Option<ComptimeVal> {
         match &self.kind {
             ExprKind::Const(val) => Some(val.clone()),
@@ -849,6 +895,8 @@ impl Expr {
                     Bo::Add => Some(lhs + rhs),
                     Bo::Sub => Some(lhs - rhs),
                     Bo::Mul => Some(lhs * rhs),
+                    // left to the type checking to report
+                    Bo::Div | Bo::Mod if rhs.is_zero() => None,
                     Bo::Div => Some(lhs / rhs),
                     Bo::Mod => Some(lhs % rhs),
                     Bo::Lt => Some(ComptimeVal::bool(lhs < rhs)),
@@ -872,7 +920,7 @@ impl Expr {
             ExprKind::FuncCall(_) => None,
             ExprKind::LVal(LVal { ident }) => {
                 // TODO: what if there are indices?
-                let entry = symtable.lookup(ident).unwrap();
+                let entry = symtable.lookup(*ident).unwrap();
                 Some(entry.comptime.as_ref()?.clone())
             }
             ExprKind::Coercion(expr) => {
@@ -906,19 +954,42 @@ impl Expr {
     /// Type check the expression.
     /// If `expect` is `Some`, the expression is expected to be coerced to the
     /// given type.
-    pub fn type_check(self, expect: Option<&Type>, symtable: &SymbolTable) -> Self {
+    ///
+    /// The errors are located at the innermost expression they are found in,
+    /// and the checked expression keeps the span of the original one.
+    pub fn type_check(
+        self,
+        expect: Option<&Type>,
+        symtable: &SymbolTable,
+    ) -> Result<Self, Diagnostic> {
+        let span = self.span;
+        let mut expr = self
+            .type_check_unlocated(expect, symtable)
+            .map_err(|diag| match span {
+                Some(span) => diag.or_span(span),
+                None => diag,
+            })?;
+        expr.span = span;
+        Ok(expr)
+    }
+
+    fn type_check_unlocated(
+        self,
+        expect: Option<&Type>,
+        symtable: &SymbolTable,
+    ) -> Result<Self, Diagnostic> {
         // If the expression is already known, and no expected type is
         // given, return the expression as is.
         if self.ty.is_some() && expect.is_none() {
-            return self;
+            return Ok(self);
         }
 
         let mut expr = match self.kind {
             ExprKind::Const(_) => self,
             ExprKind::Binary(op, lhs, rhs) => {
                 // Type check the left and right hand side expressions
-                let mut lhs = lhs.type_check(None, symtable);
-                let mut rhs = rhs.type_check(None, symtable);
+                let mut lhs = lhs.type_check(None, symtable)?;
+                let mut rhs = rhs.type_check(None, symtable)?;
 
                 let lhs_ty = lhs.ty();
                 let rhs_ty = rhs.ty();
@@ -933,13 +1004,23 @@ impl Expr {
                     }
                     _ => {
                         if lhs_ty != rhs_ty {
-                            panic!("unsupported type coercion: {:?} -> {:?}", lhs_ty, rhs_ty);
+                            return Err(mismatched(lhs_ty, &format!("`{}`", rhs_ty)));
                         }
                     }
                 }
 
                 let lhs_ty = lhs.ty().clone();
 
+                // Dividing by zero is undefined, so it is reported if known
+                if matches!(op, BinaryOp::Div | BinaryOp::Mod)
+                    && rhs.try_fold(symtable).is_some_and(|rhs| rhs.is_zero())
+                {
+                    return Err(Diagnostic::error(
+                        Some("E0106"),
+                        "this operation will divide by zero",
+                    ));
+                }
+
                 // Create the binary expression
                 let mut expr = Expr::binary(op, lhs, rhs);
                 match op {
@@ -964,16 +1045,34 @@ impl Expr {
             ExprKind::Coercion(_) => unreachable!(),
             ExprKind::FuncCall(FuncCall { ident, args }) => {
                 // Lookup the function in the symbol table
-                let entry = symtable.lookup(&ident).unwrap();
+                let entry = symtable.lookup(ident).ok_or_else(|| not_found(ident))?;
+                if !matches!(entry.ty.kind(), Tk::Func(..)) {
+                    return Err(Diagnostic::error(
+                        Some("E0104"),
+                        format!("`{}` is not a function", ident),
+                    )
+                    .with_note(format!("`{}` has type `{}`", ident, entry.ty)));
+                }
 
                 let (param_tys, ret_ty) = entry.ty.unwrap_func();
+                if args.len() != param_tys.len() {
+                    return Err(Diagnostic::error(
+                        Some("E0105"),
+                        format!(
+                            "`{}` takes {} arguments, but {} are given",
+                            ident,
+                            param_tys.len(),
+                            args.len()
+                        ),
+                    ));
+                }
 
                 // Type check the arguments
                 let args = args
                     .into_iter()
                     .zip(param_tys)
                     .map(|(arg, ty)| arg.type_check(Some(ty), symtable))
-                    .collect();
+                    .collect::<Result<_, _>>()?;
 
                 // Create the function call expression
                 let mut expr = Expr::func_call(ident, args);
@@ -982,7 +1081,7 @@ impl Expr {
             }
             ExprKind::LVal(LVal { ident }) => {
                 // Lookup the variable in the symbol table
-                let entry = symtable.lookup(&ident).unwrap();
+                let entry = symtable.lookup(ident).ok_or_else(|| not_found(ident))?;
 
                 // Create the left value expression
                 let mut expr = Expr::lval(LVal { ident });
@@ -991,7 +1090,7 @@ impl Expr {
             }
             ExprKind::Unary(op, expr) => {
                 // Type check the expression
-                let mut expr = expr.type_check(None, symtable);
+                let mut expr = expr.type_check(None, symtable)?;
 
                 // Coerce the expression to int if needed
                 let ty = match op {
@@ -1004,7 +1103,10 @@ impl Expr {
                         if ty.is_int() {
                             ty.clone()
                         } else {
-                            panic!("unsupported type for negation: {:?}", ty);
+                            return Err(Diagnostic::error(
+                                Some("E0103"),
+                                format!("cannot negate a value of type `{}`", ty),
+                            ));
                         }
                     }
                     UnaryOp::Not => {
@@ -1014,7 +1116,10 @@ impl Expr {
                         } else if ty.is_int() {
                             // TODO: How do we convert int to bool?
                         } else {
-                            panic!("unsupported type for logical not: {:?}", ty);
+                            return Err(Diagnostic::error(
+                                Some("E0103"),
+                                format!("cannot apply `!` to a value of type `{}`", ty),
+                            ));
                         }
                         Type::bool()
                     }
@@ -1039,7 +1144,7 @@ impl Expr {
                 }
                 expr.ty = Some(ty.clone());
             } else if ty != expr.ty() {
-                panic!("unsupported type coercion: {:?}", ty);
+                return Err(mismatched(ty, &format!("`{}`", expr.ty())));
             }
         }
 
@@ -1048,10 +1153,27 @@ impl Expr {
             expr = Expr::const_(comptime);
         }
 
-        expr
+        Ok(expr)
     }
 }
 
+/// The error of an undefined symbol.
+fn not_found(ident: Symbol) -> Diagnostic {
+    Diagnostic::error(
+        Some("E0101"),
+        format!("cannot find `{}` in this scope", ident),
+    )
+}
+
+/// The error of a value of another type than expected, e.g., `a value` or
+/// `` `void` ``.
+fn mismatched(expected: &Type, found: &str) -> Diagnostic {
+    Diagnostic::error(
+        Some("E0103"),
+        format!("mismatched types: expected `{}`, found {}", expected, found),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1167,6 +1289,21 @@ mod tests {
         let neg_expr = Expr::unary(UnaryOp::Neg, expr1);
         let neg_result = neg_expr.try_fold(&SymbolTable::default()).unwrap();
         assert_eq!(neg_result, ComptimeVal::int(-8));
+
+        // Test division by zero and overflow
+        let zero = Expr::const_(ComptimeVal::int(0));
+        let div_zero = Expr::binary(BinaryOp::Div, expr2.clone(), zero.clone());
+        assert_eq!(div_zero.try_fold(&SymbolTable::default()), None);
+        let mod_zero = Expr::binary(BinaryOp::Mod, expr2, zero);
+        assert_eq!(mod_zero.try_fold(&SymbolTable::default()), None);
+        assert_eq!(
+            ComptimeVal::int(i32::MIN) / ComptimeVal::int(-1),
+            ComptimeVal::int(i32::MIN)
+        );
+        assert_eq!(
+            ComptimeVal::int(i32::MAX) + ComptimeVal::int(1),
+            ComptimeVal::int(i32::MIN)
+        );
     }
 
     #[test]
@@ -1176,27 +1313,28 @@ mod tests {
         symtable.enter_scope();
         symtable.insert("x", SymbolEntry::from_ty(Type::int()));
 
-        let expr = Expr::lval(LVal {
-            ident: "x".to_string(),
-        });
+        let expr = Expr::lval(LVal { ident: "x".into() });
         // expect: None
-        let typed_expr = expr.clone().type_check(None, symtable);
+        let typed_expr = expr.clone().type_check(None, symtable).unwrap();
         assert!(typed_expr.ty().is_int());
         // expect: bool, int to bool
-        let typed_expr = expr.clone().type_check(Some(&Type::bool()), symtable);
+        let typed_expr = expr
+            .clone()
+            .type_check(Some(&Type::bool()), symtable)
+            .unwrap();
         assert!(typed_expr.ty().is_bool());
         // expect: int, int to int
-        let typed_expr = expr.clone().type_check(Some(&Type::int()), symtable);
+        let typed_expr = expr
+            .clone()
+            .type_check(Some(&Type::int()), symtable)
+            .unwrap();
         assert!(typed_expr.ty().is_int());
 
         // Test for undefined variable
-        let expr_undefined = Expr::lval(LVal {
-            ident: "y".to_string(),
-        });
-        let panic_type_check = std::panic::catch_unwind(|| {
-            expr_undefined.type_check(None, symtable);
-        });
-        assert!(panic_type_check.is_err());
+        let expr_undefined = Expr::lval(LVal { ident: "y".into() });
+        let err = expr_undefined.type_check(None, symtable).unwrap_err();
+        assert_eq!(err.code, Some("E0101"));
+        assert_eq!(err.message, "cannot find `y` in this scope");
 
         symtable.leave_scope();
     }
@@ -0,0 +1,369 @@
+//! Diagnostics of the compilation.
+//!
+//! The errors found in the source code are reported as [`Diagnostic`]s, with
+//! a code, the byte range of the offending code, and notes, so they can be
+//! shown to a user with [`Diagnostic::render`], or consumed by tools as JSON
+//! lines with [`Diagnostic::to_json`], e.g., by editors and the grading
+//! harness with `--error-format=json`.
+//!
+//! For a user, the offending line is shown with the span underlined, and
+//! optionally colored with the ANSI escapes, like `rustc`:
+//!
+//! ```text
+//! error[E0101]: cannot find `x` in this scope
+//!  --> a.sy:2:3
+//!   |
+//! 2 |   return x;
+//!   |   ^^^^^^^^^
+//! ```
+//!
+//! The codes of the syntax errors are:
+//!
+//! - `E0001`: a character no token starts with.
+//! - `E0002`: the source ends in the middle of a construct.
+//! - `E0003`: a token not expected at its position.
+//! - `E0004`: a token after the end of the compilation unit.
+//!
+//! The codes of the semantic errors, located at the innermost expression or
+//! item they are found in, are:
+//!
+//! - `E0101`: an undefined variable or function.
+//! - `E0102`: a constant initialized with a non-constant expression.
+//! - `E0103`: a value of another type than expected.
+//! - `E0104`: a call of something not a function.
+//! - `E0105`: a call with a wrong number of arguments.
+//! - `E0106`: a division or a remainder by a constant zero.
+//!
+//! The errors outside the source code, e.g., an unreadable file, have no code
+//! and no span.
+
+use std::fmt;
+
+use lalrpop_util::lexer::Token;
+use lalrpop_util::ParseError;
+
+use crate::ir::Source;
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    /// The ANSI style of the label and the underline.
+    fn style(&self) -> &'static str {
+        match self {
+            Self::Error => "1;31",
+            Self::Warning => "1;33",
+            Self::Note => "1;32",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+            Self::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A byte range in the source code, the end being exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// An error or a warning about the compiled code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The code identifying the kind of the diagnostic, e.g., `E0003`.
+    pub code: Option<&'static str>,
+    pub severity: Severity,
+    pub message: String,
+    /// The offending code, if any.
+    pub span: Option<Span>,
+    /// The additional explanations, e.g., the expected tokens.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Create an error without a span.
+    pub fn error(code: Option<&'static str>, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_span(mut self, start: usize, end: usize) -> Self {
+        self.span = Some(Span { start, end });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Locate the diagnostic at a span, unless it is already located.
+    pub fn or_span(mut self, span: Span) -> Self {
+        self.span = self.span.or(Some(span));
+        self
+    }
+
+    /// Get the 1-based line and column of the start and the end of the span.
+    fn position(&self, source: &Source) -> Option<(usize, usize, usize, usize)> {
+        let span = self.span?;
+        let (start, end) = (span.start as u32, span.end as u32);
+        Some((
+            source.line(start),
+            source.column(start),
+            source.line(end),
+            source.column(end),
+        ))
+    }
+
+    /// Render the diagnostic for a user, with the first line of the span
+    /// underlined, and colored with the ANSI escapes if asked.
+    pub fn render(&self, source: &Source, color: bool) -> String {
+        let paint = |style: &str, text: &str| match color {
+            true => format!("\x1b[{}m{}\x1b[0m", style, text),
+            false => text.to_string(),
+        };
+        let label = match self.code {
+            Some(code) => format!("{}[{}]", self.severity, code),
+            None => self.severity.to_string(),
+        };
+        let mut out = format!(
+            "{}{}\n",
+            paint(self.severity.style(), &label),
+            paint("1", &format!(": {}", self.message))
+        );
+
+        let position = self.position(source);
+        let width = position.map_or(0, |(line, ..)| line.to_string().len());
+        let gutter = |text: &str| paint("1;34", &format!("{:>width$} |", text));
+        if let Some((line, column, end_line, end_column)) = position {
+            let text = source.text().lines().nth(line - 1).unwrap_or_default();
+            let end_column = match end_line == line {
+                true => end_column,
+                false => text.chars().count() + 1,
+            };
+            // keep the tabs, so the underline is aligned however wide they are
+            let indent = text
+                .chars()
+                .take(column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            let underline = "^".repeat(end_column.saturating_sub(column).max(1));
+            out += &format!(
+                "{}{}:{}:{}\n",
+                paint("1;34", &format!("{:>width$}--> ", "")),
+                source.file(),
+                line,
+                column
+            );
+            out += &format!("{}\n", gutter(""));
+            out += &format!("{} {}\n", gutter(&line.to_string()), text);
+            out += &format!(
+                "{} {}{}\n",
+                gutter(""),
+                indent,
+                paint(self.severity.style(), &underline)
+            );
+        }
+        for note in &self.notes {
+            let prefix = format!("{:>width$} = note:", "");
+            out += &format!("{} {}\n", paint("1", &prefix), note);
+        }
+        out
+    }
+
+    /// Serialize the diagnostic as a JSON object on a single line, with the
+    /// keys `code`, `severity`, `message`, `file`, `span` and `notes`.
+    ///
+    /// The span has the byte offsets, and the 1-based lines and columns of
+    /// its start and end, or is `null`, as is the missing code.
+    pub fn to_json(&self, source: &Source) -> String {
+        let code = self.code.map_or("null".to_string(), json_string);
+        let span = match (self.span, self.position(source)) {
+            (Some(span), Some((line, column, end_line, end_column))) => format!(
+                "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"end_line\":{},\
+                 \"end_column\":{}}}",
+                span.start, span.end, line, column, end_line, end_column
+            ),
+            _ => "null".to_string(),
+        };
+        let notes = self
+            .notes
+            .iter()
+            .map(|note| json_string(note))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{\"code\":{},\"severity\":{},\"message\":{},\"file\":{},\"span\":{},\"notes\":[{}]}}",
+            code,
+            json_string(&self.severity.to_string()),
+            json_string(&self.message),
+            json_string(source.file()),
+            span,
+            notes
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.message) }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl From<ParseError<usize, Token<'_>, &str>> for Diagnostic {
+    fn from(err: ParseError<usize, Token<'_>, &str>) -> Self {
+        let describe = |expected: Vec<String>| match expected.len() {
+            0 => None,
+            1 => Some(format!("expected {}", expected[0])),
+            _ => Some(format!("expected one of {}", expected.join(", "))),
+        };
+        let (diag, expected) = match err {
+            ParseError::InvalidToken { location } => (
+                Self::error(Some("E0001"), "invalid token").with_span(location, location + 1),
+                None,
+            ),
+            ParseError::UnrecognizedEof { location, expected } => (
+                Self::error(Some("E0002"), "unexpected end of file").with_span(location, location),
+                Some(expected),
+            ),
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => (
+                Self::error(Some("E0003"), format!("unexpected token `{}`", token.1))
+                    .with_span(start, end),
+                Some(expected),
+            ),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => (
+                Self::error(Some("E0004"), format!("extra token `{}`", token.1))
+                    .with_span(start, end),
+                None,
+            ),
+            ParseError::User { error } => (Self::error(None, error), None),
+        };
+        match expected.and_then(describe) {
+            Some(note) => diag.with_note(note),
+            None => diag,
+        }
+    }
+}
+
+/// Quote a string as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::SysYParser;
+
+    #[test]
+    fn test_parse_error() {
+        let src = "int main() {\n  return 1 +;\n}\n";
+        let source = Source::new("a.sy", src);
+        let err = SysYParser::new().parse(src).unwrap_err();
+        let diag = Diagnostic::from(err);
+        assert_eq!(diag.code, Some("E0003"));
+        assert_eq!(diag.span, Some(Span { start: 25, end: 26 }));
+        assert!(diag.notes[0].starts_with("expected one of \"(\""));
+        assert!(diag.render(&source, false).starts_with(
+            "error[E0003]: unexpected token `;`\n --> a.sy:2:13\n  |\n2 |   return 1 +;\n  |             \
+             ^\n  = note: expected one of "
+        ));
+
+        let json = diag.to_json(&source);
+        assert!(json.starts_with(
+            "{\"code\":\"E0003\",\"severity\":\"error\",\"message\":\"unexpected token `;`\",\
+             \"file\":\"a.sy\",\"span\":{\"start\":25,\"end\":26,\"line\":2,\"column\":13,\
+             \"end_line\":2,\"end_column\":14},\"notes\":[\"expected one of \\\"(\\\""
+        ));
+        assert!(!json.contains('\n'));
+
+        let err = SysYParser::new().parse("int main() {").unwrap_err();
+        let diag = Diagnostic::from(err);
+        assert_eq!(diag.code, Some("E0002"));
+        assert_eq!(diag.span, Some(Span { start: 12, end: 12 }));
+    }
+
+    #[test]
+    fn test_error_without_span() {
+        let source = Source::new("a.sy", "");
+        let diag = Diagnostic::error(None, "cannot read \"a.sy\"\t").with_note("a note");
+        assert_eq!(
+            diag.render(&source, false),
+            "error: cannot read \"a.sy\"\t\n = note: a note\n"
+        );
+        assert_eq!(
+            diag.to_json(&source),
+            "{\"code\":null,\"severity\":\"error\",\"message\":\"cannot read \\\"a.sy\\\"\\t\",\
+             \"file\":\"a.sy\",\"span\":null,\"notes\":[\"a note\"]}"
+        );
+    }
+
+    #[test]
+    fn test_type_error() {
+        let src = "int main() {\n\tint a = 1;\n\treturn a + b;\n}\n";
+        let source = Source::new("a.sy", src);
+        let mut ast = SysYParser::new().parse(src).unwrap();
+        let diag = ast.type_check().unwrap_err();
+        assert_eq!(diag.code, Some("E0101"));
+        assert_eq!(diag.span, Some(Span { start: 37, end: 38 }));
+        assert_eq!(
+            diag.render(&source, false),
+            "error[E0101]: cannot find `b` in this scope\n --> a.sy:3:13\n  |\n3 | \treturn a + b;\n  \
+             | \t           ^\n"
+        );
+        let colored = diag.render(&source, true);
+        assert!(colored.starts_with("\x1b[1;31merror[E0101]\x1b[0m\x1b[1m: cannot find"));
+        assert!(colored.contains("\t           \x1b[1;31m^\x1b[0m\n"));
+
+        // the global items are located too
+        let src = "int g = h;\nint f = 1 % (2 - 2);\n";
+        let source = Source::new("a.sy", src);
+        let mut ast = SysYParser::new().parse(src).unwrap();
+        let diag = ast.type_check().unwrap_err();
+        assert_eq!(diag.span, Some(Span { start: 8, end: 9 }));
+        assert!(diag.to_json(&source).contains(
+            "\"span\":{\"start\":8,\"end\":9,\"line\":1,\"column\":9,\"end_line\":1,\"end_column\":10}"
+        ));
+
+        let mut ast = SysYParser::new().parse(&src[11..]).unwrap();
+        let diag = ast.type_check().unwrap_err();
+        assert_eq!(diag.code, Some("E0106"));
+        assert_eq!(diag.span, Some(Span { start: 8, end: 18 }));
+    }
+}
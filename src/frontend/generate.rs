@@ -0,0 +1,509 @@
+//! A generator of random SysY programs, for fuzzing the frontend.
+//!
+//! The programs are valid: the names are declared before being used, the
+//! constants are initialized by constant expressions, the calls have the
+//! arguments of the callees, and the divisors are nonzero literals. They also
+//! terminate, the loops counting up to a bound with a counter not assigned
+//! elsewhere, and the functions only calling the ones defined before them.
+//!
+//! The size is bounded by [`GenOptions`], and the constructs can be turned off
+//! one by one, e.g., to fuzz the IR generation with the ones it supports.
+//! The arrays are not generated, the grammar not having them yet.
+
+use std::fmt::Write;
+
+use crate::infra::rng::Rng;
+
+/// The options of the generator.
+#[derive(Debug, Clone)]
+pub struct GenOptions {
+    /// The number of functions at most, besides `main`.
+    pub max_funcs: usize,
+    /// The number of statements at most, in the whole program.
+    pub max_stmts: usize,
+    /// The depth of the nested blocks and expressions at most.
+    pub max_depth: usize,
+    /// Generate the unary operators `-` and `!`, the grammar not having them
+    /// yet.
+    pub unary: bool,
+    /// Generate the comparisons and the logical operators, which SysY only
+    /// has in the conditions.
+    pub compare: bool,
+    /// Generate the if statements.
+    pub branches: bool,
+    /// Generate the while loops, with the break and continue statements.
+    pub loops: bool,
+    /// Generate the calls, and the functions besides `main`.
+    pub calls: bool,
+}
+
+impl Default for GenOptions {
+    fn default() -> Self {
+        GenOptions {
+            max_funcs: 3,
+            max_stmts: 40,
+            max_depth: 4,
+            unary: true,
+            compare: true,
+            branches: true,
+            loops: true,
+            calls: true,
+        }
+    }
+}
+
+impl GenOptions {
+    /// Only the straight-line code, without the branches, the loops, the
+    /// calls and the operators producing the booleans.
+    pub fn straight_line() -> Self {
+        GenOptions {
+            unary: false,
+            compare: false,
+            branches: false,
+            loops: false,
+            calls: false,
+            ..Default::default()
+        }
+    }
+}
+
+/// Generate a program from the seed.
+pub fn generate_program(seed: u64, options: &GenOptions) -> String {
+    let mut gen = Generator {
+        rng: Rng::new(seed),
+        options,
+        out: String::new(),
+        indent: 0,
+        scopes: vec![Vec::new()],
+        funcs: Vec::new(),
+        stmts: options.max_stmts,
+        loops: 0,
+        returns_int: false,
+        next_id: 0,
+    };
+    gen.program();
+    gen.out
+}
+
+/// A variable or a constant in scope.
+struct Var {
+    name: String,
+    is_const: bool,
+    /// A loop counter, not assigned but by its loop.
+    is_counter: bool,
+    /// Being initialized, so shadowing the outer ones of the name but not
+    /// usable yet, as in C.
+    initializing: bool,
+}
+
+/// A function defined before, callable.
+struct FuncSig {
+    name: String,
+    params: usize,
+    returns_int: bool,
+}
+
+struct Generator<'a> {
+    rng: Rng,
+    options: &'a GenOptions,
+    out: String,
+    indent: usize,
+    scopes: Vec<Vec<Var>>,
+    funcs: Vec<FuncSig>,
+    /// The statements left to generate.
+    stmts: usize,
+    /// The depth of the loops around.
+    loops: usize,
+    /// The current function returns `int`.
+    returns_int: bool,
+    next_id: usize,
+}
+
+impl Generator<'_> {
+    fn line(&mut self, line: &str) {
+        writeln!(self.out, "{:indent$}{}", "", line, indent = self.indent * 4).unwrap();
+    }
+
+    /// Get a new name, or sometimes one of an outer scope to shadow it.
+    fn fresh_name(&mut self, prefix: &str) -> String {
+        let outer = self.scopes[..self.scopes.len() - 1]
+            .iter()
+            .flatten()
+            .filter(|var| !var.is_counter)
+            .map(|var| var.name.clone())
+            .collect::<Vec<_>>();
+        let current = self.scopes.last().unwrap();
+        if !outer.is_empty() && self.rng.chance(1, 5) {
+            let name = self.rng.choose(&outer).clone();
+            if current.iter().all(|var| var.name != name) {
+                return name;
+            }
+        }
+        self.next_id += 1;
+        format!("{}{}", prefix, self.next_id)
+    }
+
+    fn declare(&mut self, name: String, is_const: bool, is_counter: bool) {
+        self.scopes.last_mut().unwrap().push(Var {
+            name,
+            is_const,
+            is_counter,
+            initializing: false,
+        });
+    }
+
+    /// Get the variables visible, the inner ones shadowing the outer ones.
+    fn visible(&self, filter: impl Fn(&Var) -> bool) -> Vec<String> {
+        let mut names: Vec<&str> = Vec::new();
+        let mut visible = Vec::new();
+        for var in self.scopes.iter().rev().flatten() {
+            if names.contains(&var.name.as_str()) {
+                continue;
+            }
+            names.push(&var.name);
+            if !var.initializing && filter(var) {
+                visible.push(var.name.clone());
+            }
+        }
+        visible
+    }
+
+    fn program(&mut self) {
+        let globals = self.rng.below(4);
+        for _ in 0..globals {
+            self.decl(true);
+        }
+        if self.options.calls {
+            for _ in 0..self.rng.below(self.options.max_funcs + 1) {
+                self.func();
+            }
+        }
+        self.line("int main() {");
+        self.body(&[], true);
+        self.line("}");
+    }
+
+    fn func(&mut self) {
+        self.next_id += 1;
+        let name = format!("f{}", self.next_id);
+        let params = (0..self.rng.below(4))
+            .map(|i| format!("p{}", i))
+            .collect::<Vec<_>>();
+        let returns_int = self.rng.chance(3, 4);
+        let params_text = params
+            .iter()
+            .map(|param| format!("int {}", param))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let ret = if returns_int { "int" } else { "void" };
+        self.line(&format!("{} {}({}) {{", ret, name, params_text));
+        self.body(&params, returns_int);
+        self.line("}");
+        // defined after the body, so it is not called recursively
+        self.funcs.push(FuncSig {
+            name,
+            params: params.len(),
+            returns_int,
+        });
+    }
+
+    /// Generate the body of a function, ending with a return.
+    ///
+    /// The parameters are in the scope of the body, so they are not declared
+    /// again there, as in C.
+    fn body(&mut self, params: &[String], returns_int: bool) {
+        self.returns_int = returns_int;
+        self.indent += 1;
+        self.scopes.push(Vec::new());
+        for param in params {
+            self.declare(param.clone(), false, false);
+        }
+        let items = self.rng.below(6) + 1;
+        for _ in 0..items {
+            self.item(self.options.max_depth);
+        }
+        if returns_int {
+            let expr = self.expr(self.options.max_depth, false);
+            self.line(&format!("return {};", expr));
+        }
+        self.scopes.pop();
+        self.indent -= 1;
+    }
+
+    /// Generate a declaration, of constants or variables, all initialized by
+    /// constant expressions at the top level.
+    ///
+    /// The local variables are always initialized, so no program reads an
+    /// uninitialized one, and the global ones are zero if not.
+    fn decl(&mut self, global: bool) {
+        let is_const = self.rng.chance(1, 3);
+        let mut defs = Vec::new();
+        for _ in 0..self.rng.below(3) + 1 {
+            let name = self.fresh_name(if is_const { "c" } else { "v" });
+            self.declare(name.clone(), is_const, false);
+            let var = self.scopes.last_mut().unwrap().last_mut().unwrap();
+            var.initializing = true;
+            let init = match (is_const, global) {
+                (false, true) if self.rng.chance(1, 4) => None,
+                (true, _) | (false, true) => Some(self.expr(2, true)),
+                (false, false) => Some(self.expr(self.options.max_depth, false)),
+            };
+            let var = self.scopes.last_mut().unwrap().last_mut().unwrap();
+            var.initializing = false;
+            defs.push(match init {
+                Some(init) => format!("{} = {}", name, init),
+                None => name,
+            });
+        }
+        let prefix = if is_const { "const int" } else { "int" };
+        self.line(&format!("{} {};", prefix, defs.join(", ")));
+    }
+
+    fn item(&mut self, depth: usize) {
+        if self.stmts == 0 {
+            return;
+        }
+        self.stmts -= 1;
+        match self.rng.chance(1, 4) {
+            true => self.decl(false),
+            false => self.stmt(depth),
+        }
+    }
+
+    fn block(&mut self, depth: usize) {
+        self.line("{");
+        self.indent += 1;
+        self.scopes.push(Vec::new());
+        for _ in 0..self.rng.below(4) {
+            self.item(depth - 1);
+        }
+        self.scopes.pop();
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn stmt(&mut self, depth: usize) {
+        let assignable = self.visible(|var| !var.is_const && !var.is_counter);
+        let void_funcs = self.funcs.iter().any(|func| !func.returns_int);
+        let mut kinds = vec!["expr", "empty"];
+        if !assignable.is_empty() {
+            kinds.extend(["assign"; 4]);
+        }
+        if depth > 1 {
+            kinds.push("block");
+            if self.options.branches {
+                kinds.extend(["if", "if"]);
+            }
+            if self.options.loops {
+                kinds.push("while");
+            }
+        }
+        if self.loops > 0 {
+            kinds.extend(["break", "continue"]);
+        }
+        if void_funcs {
+            kinds.push("call");
+        }
+        if self.rng.chance(1, 10) {
+            kinds.push("return");
+        }
+
+        match *self.rng.choose(&kinds) {
+            "assign" => {
+                let name = self.rng.choose(&assignable).clone();
+                let expr = self.expr(self.options.max_depth, false);
+                self.line(&format!("{} = {};", name, expr));
+            }
+            "expr" => {
+                let expr = self.expr(2, false);
+                self.line(&format!("{};", expr));
+            }
+            "empty" => self.line(";"),
+            "block" => self.block(depth),
+            "if" => {
+                let cond = self.cond();
+                self.line(&format!("if ({})", cond));
+                self.block(depth);
+                if self.rng.chance(1, 2) {
+                    self.line("else");
+                    self.block(depth);
+                }
+            }
+            "while" => {
+                // the counter is in a block of its own, shadowing nothing
+                self.line("{");
+                self.indent += 1;
+                self.scopes.push(Vec::new());
+                self.next_id += 1;
+                let counter = format!("i{}", self.next_id);
+                let bound = self.rng.range(0, 5);
+                self.line(&format!("int {} = 0;", counter));
+                self.declare(counter.clone(), false, true);
+                let cond = match self.options.compare && self.rng.chance(1, 2) {
+                    true => format!("{} < {} && {}", counter, bound, self.cond()),
+                    false => format!("{} < {}", counter, bound),
+                };
+                self.line(&format!("while ({}) {{", cond));
+                self.indent += 1;
+                self.line(&format!("{0} = {0} + 1;", counter));
+                self.loops += 1;
+                for _ in 0..self.rng.below(4) {
+                    self.item(depth - 1);
+                }
+                self.loops -= 1;
+                self.indent -= 1;
+                self.line("}");
+                self.scopes.pop();
+                self.indent -= 1;
+                self.line("}");
+            }
+            "break" => self.line("break;"),
+            "continue" => self.line("continue;"),
+            "call" => {
+                let funcs = self
+                    .funcs
+                    .iter()
+                    .filter(|func| !func.returns_int)
+                    .map(|func| (func.name.clone(), func.params))
+                    .collect::<Vec<_>>();
+                let (name, params) = self.rng.choose(&funcs).clone();
+                let call = self.call(&name, params, self.options.max_depth);
+                self.line(&format!("{};", call));
+            }
+            "return" => match self.returns_int {
+                true => {
+                    let expr = self.expr(self.options.max_depth, false);
+                    self.line(&format!("return {};", expr));
+                }
+                false => self.line("return;"),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn cond(&mut self) -> String {
+        match self.options.compare {
+            true => self.compare(self.options.max_depth),
+            false => self.expr(self.options.max_depth, false),
+        }
+    }
+
+    fn compare(&mut self, depth: usize) -> String {
+        let depth = depth.saturating_sub(1);
+        if depth > 0 && self.rng.chance(1, 3) {
+            let op = self.rng.choose(&["&&", "||"]);
+            let lhs = self.compare(depth);
+            let rhs = self.compare(depth);
+            return format!("{} {} {}", lhs, op, rhs);
+        }
+        if self.options.unary && self.rng.chance(1, 6) {
+            return format!("!{}", self.primary(depth, false));
+        }
+        let op = self.rng.choose(&["<", ">", "<=", ">=", "==", "!="]);
+        let lhs = self.expr(depth, false);
+        let rhs = self.expr(depth, false);
+        format!("{} {} {}", lhs, op, rhs)
+    }
+
+    fn call(&mut self, name: &str, params: usize, depth: usize) -> String {
+        let args = (0..params)
+            .map(|_| self.expr(depth.saturating_sub(1), false))
+            .collect::<Vec<_>>();
+        format!("{}({})", name, args.join(", "))
+    }
+
+    /// Generate an integer expression, only of the literals and the constants
+    /// if `constant`.
+    fn expr(&mut self, depth: usize, constant: bool) -> String {
+        if depth == 0 || self.rng.chance(1, 3) {
+            return self.primary(depth, constant);
+        }
+        let depth = depth - 1;
+        match self.rng.below(7) {
+            0..=3 => {
+                let op = self.rng.choose(&["+", "-", "*"]);
+                let lhs = self.expr(depth, constant);
+                let rhs = self.expr(depth, constant);
+                format!("{} {} {}", lhs, op, rhs)
+            }
+            4 => {
+                // a nonzero literal, so the division is defined and no
+                // constant divisor is zero
+                let op = self.rng.choose(&["/", "%"]);
+                let lhs = self.primary(depth, constant);
+                format!("{} {} {}", lhs, op, self.rng.range(1, 9))
+            }
+            5 => {
+                let op = match self.options.unary {
+                    true => self.rng.choose(&["-", "+"]),
+                    false => "+",
+                };
+                format!("{}{}", op, self.primary(depth, constant))
+            }
+            _ => self.primary(depth, constant),
+        }
+    }
+
+    fn primary(&mut self, depth: usize, constant: bool) -> String {
+        let vars = self.visible(|var| var.is_const || !constant);
+        let funcs = match constant || depth == 0 {
+            true => Vec::new(),
+            false => self
+                .funcs
+                .iter()
+                .filter(|func| func.returns_int)
+                .map(|func| (func.name.clone(), func.params))
+                .collect::<Vec<_>>(),
+        };
+        match self.rng.below(6) {
+            0 | 1 if !vars.is_empty() => self.rng.choose(&vars).clone(),
+            2 if !funcs.is_empty() => {
+                let (name, params) = self.rng.choose(&funcs).clone();
+                self.call(&name, params, depth)
+            }
+            3 if depth > 0 => format!("({})", self.expr(depth - 1, constant)),
+            _ => self.literal(),
+        }
+    }
+
+    fn literal(&mut self) -> String {
+        let value = match self.rng.below(10) {
+            0 => self.rng.range(0, i32::MAX as i64),
+            _ => self.rng.range(0, 100),
+        };
+        match self.rng.below(6) {
+            0 => format!("{:#x}", value),
+            1 if value != 0 => format!("0{:o}", value),
+            _ => value.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::SysYParser;
+
+    #[test]
+    fn test_generate_program() {
+        let options = GenOptions {
+            unary: false,
+            ..Default::default()
+        };
+        assert_eq!(generate_program(7, &options), generate_program(7, &options));
+        for seed in 0..20 {
+            let program = generate_program(seed, &options);
+            let mut ast = SysYParser::new().parse(&program).unwrap();
+            ast.type_check().unwrap();
+        }
+
+        // only the constructs enabled are generated
+        let options = GenOptions::straight_line();
+        for seed in 0..20 {
+            let program = generate_program(seed, &options);
+            for word in ["if", "while", "<", "!", "f1("] {
+                assert!(!program.contains(word), "`{}` in:\n{}", word, program);
+            }
+        }
+    }
+}
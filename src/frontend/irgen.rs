@@ -22,19 +22,67 @@ use super::ast::{
     VarDecl,
     VarDef,
 };
+use super::diagnostic::Span;
 use super::types::{Type, TypeKind as Tk};
 use crate::frontend::ast::{FuncCall, LVal, UnaryOp};
+use crate::infra::intern::Symbol;
 use crate::infra::linked_list::LinkedListContainer;
-use crate::ir::{self, Block, ConstantValue, Context, Func, Global, Inst, TargetInfo, Ty, Value};
+use crate::ir::{
+    self,
+    Block,
+    ConstantValue,
+    Context,
+    Func,
+    Global,
+    Inst,
+    Source,
+    TargetInfo,
+    Ty,
+    Value,
+};
+
+/// The function of the runtime reporting a failed check of `--sanitize`, and
+/// aborting the program, `_sysy_sanitize_trap(msg)` for the message as a C
+/// string.
+pub const SANITIZE_TRAP: &str = "_sysy_sanitize_trap";
+
+/// The options of the IR generation.
+#[derive(Debug, Clone, Default)]
+pub struct IrGenOptions {
+    /// Check the divisors are not zero before the divisions and remainders,
+    /// trapping with the location of the failed check in the source.
+    ///
+    /// The array accesses are to be checked against the bounds likewise, once
+    /// the frontend generates them.
+    pub sanitize: bool,
+}
 
 /// Generate IR from the AST.
-pub fn irgen(ast: &CompUnit, pointer_width: u8) -> Context {
+pub fn irgen(ast: &CompUnit, target: TargetInfo) -> Context {
     let mut irgen = IrGenContext::default();
+    irgen.ctx.set_target_info(target);
+
+    // Generate IR
+    ast.irgen(&mut irgen);
+
+    // Transfer ownership of the generated IR.
+    irgen.finish()
+}
 
-    // Set pointer width for target platform
-    irgen.ctx.set_target_info(TargetInfo {
-        ptr_size: pointer_width as u32,
-    });
+/// Generate IR from the AST of a source, with the options, and keep the source
+/// in the IR.
+pub fn irgen_with(
+    ast: &CompUnit,
+    target: TargetInfo,
+    src: &Source,
+    options: IrGenOptions,
+) -> Context {
+    let mut irgen = IrGenContext {
+        options,
+        ..Default::default()
+    };
+    irgen.ctx.set_target_info(target);
+    irgen.ctx.set_source(src.clone());
 
     // Generate IR
     ast.irgen(&mut irgen);
@@ -75,7 +123,7 @@ pub struct IrGenContext {
 
     // Current function and block
     pub curr_func: Option<Func>,
-    pub curr_func_name: Option<String>,
+    pub curr_func_name: Option<Symbol>,
     pub curr_block: Option<Block>,
 
     // Stacks for loop control flow.
@@ -85,6 +133,10 @@ pub struct IrGenContext {
     // Return block and slot
     pub curr_ret_slot: Option<Value>,
     pub curr_ret_block: Option<Block>,
+
+    pub options: IrGenOptions,
+    // The number of messages of the checks, naming their globals.
+    trap_msgs: usize,
 }
 
 impl IrGenContext {
@@ -158,10 +210,93 @@ impl IrGenContext {
         // ir_int_binary_op
     }
 
+    // Get the declaration of the trap of the checks, adding it if missing.
+    fn trap_decl(&mut self) -> Func {
+        if let Some(func) = self.ctx.func_by_name(SANITIZE_TRAP) {
+            return func;
+        }
+        let void = Ty::void(&mut self.ctx);
+        let ptr = Ty::ptr(&mut self.ctx);
+        let func = Func::new(&mut self.ctx, SANITIZE_TRAP.to_string(), void);
+        func.add_param(&mut self.ctx, ptr);
+        func
+    }
+
+    // Trap with the message, prefixed by the location of the span, or of the
+    // statement, if the condition holds, and continue in a new block
+    // otherwise.
+    fn gen_trap(&mut self, cond: Value, msg: &str, span: Option<Span>) {
+        let offset = span.map(|span| span.start as u32).or(self.ctx.curr_loc());
+        let msg = match (self.ctx.source(), offset) {
+            (Some(src), Some(offset)) => format!(
+                "{}:{}:{}: {}",
+                src.file(),
+                src.line(offset),
+                src.column(offset),
+                msg
+            ),
+            _ => msg.to_string(),
+        };
+        let i8 = Ty::i8(&mut self.ctx);
+        let bytes = msg.bytes().chain([0]).collect::<Vec<_>>();
+        let elems = bytes
+            .into_iter()
+            .map(|byte| ConstantValue::i8(&mut self.ctx, byte as i8))
+            .collect::<Vec<_>>();
+        let ty = Ty::array(&mut self.ctx, i8, elems.len());
+        let name = Symbol::from(format!("__sanitize_msg_{}", self.trap_msgs));
+        self.trap_msgs += 1;
+        let global = Global::new(&mut self.ctx, name, ConstantValue::Array { ty, elems });
+        global.set_const(&mut self.ctx, true);
+
+        let func = self.curr_func.unwrap();
+        let trap = Block::new(&mut self.ctx);
+        let cont = Block::new(&mut self.ctx);
+        func.push_back(&mut self.ctx, trap).unwrap();
+        func.push_back(&mut self.ctx, cont).unwrap();
+        let br = Inst::cond_br(&mut self.ctx, cond, trap, cont);
+        self.curr_block
+            .unwrap()
+            .push_back(&mut self.ctx, br)
+            .unwrap();
+
+        // the trap does not return, which the branch probabilities see from
+        // the loop
+        let decl = self.trap_decl();
+        let msg = Value::global_ref(&mut self.ctx, name, ty);
+        let call = Inst::call(&mut self.ctx, decl, vec![msg]);
+        trap.push_back(&mut self.ctx, call).unwrap();
+        let spin = Inst::br(&mut self.ctx, trap);
+        trap.push_back(&mut self.ctx, spin).unwrap();
+        self.curr_block = Some(cont);
+    }
+
+    // Check the divisor of a division or a remainder is not zero.
+    fn gen_div_check(&mut self, divisor: Value, span: Option<Span>) {
+        if divisor
+            .as_const_int(&self.ctx)
+            .is_some_and(|value| value != 0)
+        {
+            return;
+        }
+        let zero = Value::i32(&mut self.ctx, 0);
+        let op = ir::IntBinaryOp::ICmp {
+            cond: ir::IntCmpCond::Eq,
+        };
+        let cmp = Inst::ibinary(&mut self.ctx, op, divisor, zero);
+        self.curr_block
+            .unwrap()
+            .push_back(&mut self.ctx, cmp)
+            .unwrap();
+        let is_zero = cmp.result(&self.ctx).unwrap();
+        self.gen_trap(is_zero, "division by zero", span);
+    }
+
     // Generate a new local expression in ir given an expression in AST.
+    //
+    // The checks of `--sanitize` end the current block, so the instructions
+    // using the value go to the current block after it is generated.
     fn gen_local_expr(&mut self, expr: &Expr) -> Option<Value> {
-        let curr_block = self.curr_block.unwrap();
-
         match &expr.kind {
             // Constants -> generate a local constant value
             ExprKind::Const(v) => Some(self.gen_local_comptime(v)),
@@ -178,8 +313,12 @@ impl IrGenContext {
                 if is_float {
                     todo!("implement float binary!");
                 } else {
+                    if self.options.sanitize && matches!(op, BinaryOp::Div | BinaryOp::Mod) {
+                        self.gen_div_check(rhs, expr.span);
+                    }
                     let inst = Inst::ibinary(&mut self.ctx, ir_int_binary_op, lhs, rhs);
                     // Push the instruction to the current block
+                    let curr_block = self.curr_block.unwrap();
                     curr_block.push_back(&mut self.ctx, inst).unwrap();
                     Some(inst.result(&self.ctx).unwrap())
                 }
@@ -197,14 +336,14 @@ impl IrGenContext {
             // LValues -> Get the value
             ExprKind::LVal(LVal { ident }) => {
                 // Look up the symbol in the symbol table to get the IR value
-                let entry = self.symtable.lookup(ident).unwrap();
+                let entry = self.symtable.lookup(*ident).unwrap();
                 let ir_value = entry.ir_value.unwrap();
 
                 let ir_base_ty = self.gen_type(&entry.ty.clone());
 
                 let slot = if let IrGenResult::Global(slot) = ir_value {
                     // If the value is a global, get the global reference
-                    let name = slot.name(&self.ctx).to_string();
+                    let name = slot.name(&self.ctx);
                     let value_ty = slot.ty(&self.ctx);
                     Value::global_ref(&mut self.ctx, name, value_ty)
                 } else if let IrGenResult::Value(slot) = ir_value {
@@ -220,6 +359,7 @@ impl IrGenContext {
                 } else {
                     // Otherwise, we need to load the value, generate a load instruction
                     let load = Inst::load(&mut self.ctx, slot, ir_base_ty);
+                    let curr_block = self.curr_block.unwrap();
                     curr_block.push_back(&mut self.ctx, load).unwrap();
                     Some(load.result(&self.ctx).unwrap())
                 }
@@ -255,7 +395,7 @@ impl IrGen for CompUnit {
         // Generate system library function definitions
         irgen.gen_sysylib();
         // Generate IR for each item in the compilation unit
-        for item in &self.items {
+        for (_, item) in &self.items {
             item.irgen(irgen);
         }
         // Leave the global scope
@@ -282,9 +422,10 @@ impl IrGen for Item {
                             format!("__GLOBAL_CONST_{}", ident),
                             constant,
                         );
+                        slot.set_const(&mut irgen.ctx, true);
                         // Insert the symbol in the symbol table
                         irgen.symtable.insert(
-                            ident.clone(),
+                            *ident,
                             SymbolEntry {
                                 ty: init.ty().clone(),
                                 comptime: Some(comptime),
@@ -312,7 +453,7 @@ impl IrGen for Item {
                         );
                         // Insert the symbol in the symbol table
                         irgen.symtable.insert(
-                            ident.clone(),
+                            *ident,
                             SymbolEntry {
                                 ty: init.as_ref().unwrap().ty().clone(),
                                 comptime: Some(comptime),
@@ -339,10 +480,10 @@ impl IrGen for FuncDef {
         let func_ty = Type::func(param_tys.clone(), self.ret_ty.clone());
 
         let ir_ret_ty = irgen.gen_type(&self.ret_ty);
-        let func = Func::new(&mut irgen.ctx, self.ident.clone(), ir_ret_ty);
+        let func = Func::new(&mut irgen.ctx, self.ident, ir_ret_ty);
 
         irgen.symtable.insert_upper(
-            self.ident.clone(),
+            self.ident,
             SymbolEntry {
                 ty: func_ty,
                 comptime: None,
@@ -355,7 +496,7 @@ impl IrGen for FuncDef {
         func.push_back(&mut irgen.ctx, block).unwrap();
 
         irgen.curr_func = Some(func);
-        irgen.curr_func_name = Some(self.ident.clone());
+        irgen.curr_func_name = Some(self.ident);
         irgen.curr_block = Some(block);
 
         // block params
@@ -364,7 +505,7 @@ impl IrGen for FuncDef {
             let param = func.add_param(&mut irgen.ctx, ir_ty);
 
             irgen.symtable.insert(
-                ident.clone(),
+                *ident,
                 SymbolEntry {
                     ty: ty.clone(),
                     comptime: None,
@@ -385,7 +526,7 @@ impl IrGen for FuncDef {
                 // get old entry
                 let param = irgen
                     .symtable
-                    .lookup(ident)
+                    .lookup(*ident)
                     .unwrap()
                     .ir_value
                     .unwrap()
@@ -398,7 +539,7 @@ impl IrGen for FuncDef {
 
                 // set new entry
                 irgen.symtable.insert(
-                    ident.clone(),
+                    *ident,
                     SymbolEntry {
                         ty: ty.clone(),
                         comptime: None,
@@ -423,6 +564,16 @@ impl IrGen for FuncDef {
         // generate body
         self.body.irgen(irgen);
 
+        // fall through to the return block
+        let last_block = irgen.curr_block.unwrap();
+        if !last_block
+            .tail(&irgen.ctx)
+            .is_some_and(|inst| inst.is_terminator(&irgen.ctx))
+        {
+            let jump = Inst::br(&mut irgen.ctx, ret_block);
+            last_block.push_back(&mut irgen.ctx, jump).unwrap();
+        }
+
         // append return block
         func.push_back(&mut irgen.ctx, ret_block).unwrap();
 
@@ -456,7 +607,6 @@ impl IrGen for FuncDef {
 impl IrGen for Decl {
     fn irgen(&self, irgen: &mut IrGenContext) {
         let entry_block = irgen.curr_func.unwrap().head(&irgen.ctx).unwrap();
-        let curr_block = irgen.curr_block.unwrap();
         match self {
             Decl::ConstDecl(ConstDecl { defs, .. }) => {
                 for ConstDef { ident, init, .. } in defs {
@@ -469,7 +619,7 @@ impl IrGen for Decl {
 
                     entry_block.push_front(&mut irgen.ctx, stack_slot).unwrap();
                     irgen.symtable.insert(
-                        ident,
+                        *ident,
                         SymbolEntry {
                             ty: init.ty().clone(),
                             comptime: Some(comptime),
@@ -481,6 +631,7 @@ impl IrGen for Decl {
                     let init = irgen.gen_local_expr(init).unwrap();
                     let slot = stack_slot.result(&irgen.ctx).unwrap();
                     let store = Inst::store(&mut irgen.ctx, init, slot);
+                    let curr_block = irgen.curr_block.unwrap();
                     curr_block.push_back(&mut irgen.ctx, store).unwrap();
                 }
             }
@@ -492,7 +643,7 @@ impl IrGen for Decl {
 
                     entry_block.push_front(&mut irgen.ctx, stack_slot).unwrap();
                     irgen.symtable.insert(
-                        ident,
+                        *ident,
                         SymbolEntry {
                             ty: init.ty().clone(),
                             comptime: None,
@@ -505,6 +656,7 @@ impl IrGen for Decl {
                     let init = irgen.gen_local_expr(init).unwrap();
                     let slot = stack_slot.result(&irgen.ctx).unwrap();
                     let store = Inst::store(&mut irgen.ctx, init, slot);
+                    let curr_block = irgen.curr_block.unwrap();
                     curr_block.push_back(&mut irgen.ctx, store).unwrap();
                 }
             }
@@ -514,15 +666,13 @@ impl IrGen for Decl {
 
 impl IrGen for Stmt {
     fn irgen(&self, irgen: &mut IrGenContext) {
-        let curr_block = irgen.curr_block.unwrap();
-
         match self {
             Stmt::Assign(LVal { ident }, expr) => {
-                let entry = irgen.symtable.lookup(ident).unwrap();
+                let entry = irgen.symtable.lookup(*ident).unwrap();
                 let ir_value = entry.ir_value.unwrap();
 
                 let slot = if let IrGenResult::Global(slot) = ir_value {
-                    let name = slot.name(&irgen.ctx).to_string();
+                    let name = slot.name(&irgen.ctx);
                     let value_ty = slot.ty(&irgen.ctx);
                     Value::global_ref(&mut irgen.ctx, name, value_ty)
                 } else if let IrGenResult::Value(slot) = ir_value {
@@ -535,6 +685,7 @@ impl IrGen for Stmt {
 
                 let val = irgen.gen_local_expr(expr).unwrap();
                 let store = Inst::store(&mut irgen.ctx, val, store_dst);
+                let curr_block = irgen.curr_block.unwrap();
                 curr_block.push_back(&mut irgen.ctx, store).unwrap();
             }
             Stmt::Expr(ExprStmt { expr }) => {
@@ -572,6 +723,16 @@ impl IrGen for Stmt {
                     .unwrap()
                     .push_back(&mut irgen.ctx, jump)
                     .unwrap();
+
+                // The statements after the return are unreachable, but still
+                // generated, in a new block, so the jump ends its block.
+                let next = Block::new(&mut irgen.ctx);
+                irgen
+                    .curr_func
+                    .unwrap()
+                    .push_back(&mut irgen.ctx, next)
+                    .unwrap();
+                irgen.curr_block = Some(next);
             }
         }
     }
@@ -580,12 +741,18 @@ impl IrGen for Stmt {
 impl IrGen for ast::Block {
     fn irgen(&self, irgen: &mut IrGenContext) {
         irgen.symtable.enter_scope();
-        for item in self.items.iter() {
+        // Tag the instructions with the item they are generated from, and
+        // restore the enclosing item for the rest of it, e.g., the jumps after
+        // the body of a loop.
+        let outer_loc = irgen.ctx.curr_loc();
+        for (span, item) in self.items.iter() {
+            irgen.ctx.set_curr_loc(Some(span.start as u32));
             match item {
                 BlockItem::Decl(decl) => decl.irgen(irgen),
                 BlockItem::Stmt(stmt) => stmt.irgen(irgen),
             }
         }
+        irgen.ctx.set_curr_loc(outer_loc);
         irgen.symtable.leave_scope();
     }
 }
@@ -0,0 +1,220 @@
+//! A reducer of SysY programs, for the minimal reproductions of miscompiles.
+//!
+//! The program is reduced by edits of its text at the spans of the AST, in the
+//! spirit of C-Reduce: the items and the statements are removed, and the
+//! expressions replaced by one of their operands or by `0`. An edit is kept if
+//! the program still parses and type checks, and is still interesting, e.g.,
+//! still miscompiled, as decided by the caller. The edits are tried again from
+//! the first one after each one kept, until none is, so the result is minimal
+//! for them.
+
+use super::ast::{BlockItem, CompUnit, Decl, Expr, ExprKind, Item, Stmt};
+use super::diagnostic::Span;
+use super::{preprocess, SysYParser};
+
+/// A replacement of a range of the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+impl Edit {
+    /// Remove the range, with the whitespaces after it up to the end of the
+    /// line, and the line itself if nothing else is left on it.
+    fn delete(src: &str, span: Span) -> Self {
+        let line_start = src[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let mut end = span.end + (src[span.end..].len() - src[span.end..].trim_start().len());
+        // the whitespaces after the range, only up to the next line
+        if let Some(newline) = src[span.end..end].find('\n') {
+            end = span.end + newline + 1;
+        }
+        let start =
+            match src[line_start..span.start].trim().is_empty() && src[..end].ends_with('\n') {
+                true => line_start,
+                false => span.start,
+            };
+        Self {
+            span: Span { start, end },
+            text: String::new(),
+        }
+    }
+
+    /// Apply the edit to the text.
+    pub fn apply(&self, src: &str) -> String {
+        let mut text = src[..self.span.start].to_string();
+        text.push_str(&self.text);
+        text.push_str(&src[self.span.end..]);
+        text
+    }
+}
+
+/// Get the edits of a program, the removals of the items and the statements
+/// first, outer before inner, then the simplifications of the expressions.
+pub fn edits(src: &str, ast: &CompUnit) -> Vec<Edit> {
+    let mut removals = Vec::new();
+    let mut exprs = Vec::new();
+    for (span, item) in ast.items.iter() {
+        removals.push(Edit::delete(src, *span));
+        match item {
+            Item::Decl(decl) => decl_exprs(decl, &mut exprs),
+            Item::FuncDef(func) => block_edits(src, &func.body.items, &mut removals, &mut exprs),
+        }
+    }
+
+    let mut edits = removals;
+    for expr in exprs {
+        expr_edits(src, expr, &mut edits);
+    }
+    edits
+}
+
+fn decl_exprs<'a>(decl: &'a Decl, exprs: &mut Vec<&'a Expr>) {
+    match decl {
+        Decl::ConstDecl(decl) => exprs.extend(decl.defs.iter().map(|def| &def.init)),
+        Decl::VarDecl(decl) => exprs.extend(decl.defs.iter().filter_map(|def| def.init.as_ref())),
+    }
+}
+
+fn block_edits<'a>(
+    src: &str,
+    items: &'a [(Span, BlockItem)],
+    removals: &mut Vec<Edit>,
+    exprs: &mut Vec<&'a Expr>,
+) {
+    for (span, _) in items {
+        removals.push(Edit::delete(src, *span));
+    }
+    for (_, item) in items {
+        match item {
+            BlockItem::Decl(decl) => decl_exprs(decl, exprs),
+            BlockItem::Stmt(stmt) => stmt_edits(src, stmt, removals, exprs),
+        }
+    }
+}
+
+fn stmt_edits<'a>(src: &str, stmt: &'a Stmt, removals: &mut Vec<Edit>, exprs: &mut Vec<&'a Expr>) {
+    match stmt {
+        Stmt::Assign(_, expr) => exprs.push(expr),
+        Stmt::Expr(stmt) => exprs.extend(stmt.expr.as_ref()),
+        Stmt::Return(stmt) => exprs.extend(stmt.expr.as_ref()),
+        Stmt::Block(block) => block_edits(src, &block.items, removals, exprs),
+        Stmt::If(cond, then_stmt, else_stmt) => {
+            exprs.push(cond);
+            stmt_edits(src, then_stmt, removals, exprs);
+            if let Some(else_stmt) = else_stmt {
+                stmt_edits(src, else_stmt, removals, exprs);
+            }
+        }
+        Stmt::While(cond, body) => {
+            exprs.push(cond);
+            stmt_edits(src, body, removals, exprs);
+        }
+        Stmt::Break | Stmt::Continue => {}
+    }
+}
+
+/// Add the replacements of an expression by `0` and by its operands, then the
+/// ones of the operands.
+fn expr_edits(src: &str, expr: &Expr, edits: &mut Vec<Edit>) {
+    let mut replace = |span: Span, by: Option<Span>| {
+        let text = by.map_or("0", |by| &src[by.start..by.end]);
+        if text != &src[span.start..span.end] {
+            edits.push(Edit {
+                span,
+                text: text.to_string(),
+            });
+        }
+    };
+    if let Some(span) = expr.span {
+        replace(span, None);
+        if let ExprKind::Binary(_, lhs, rhs) = &expr.kind {
+            for operand in [lhs, rhs] {
+                if let Some(by) = operand.span {
+                    replace(span, Some(by));
+                }
+            }
+        }
+    }
+    match &expr.kind {
+        ExprKind::Binary(_, lhs, rhs) => {
+            expr_edits(src, lhs, edits);
+            expr_edits(src, rhs, edits);
+        }
+        ExprKind::Unary(_, expr) | ExprKind::Coercion(expr) => expr_edits(src, expr, edits),
+        ExprKind::FuncCall(call) => {
+            for arg in call.args.iter() {
+                expr_edits(src, arg, edits);
+            }
+        }
+        ExprKind::Const(_) | ExprKind::LVal(_) => {}
+    }
+}
+
+/// Parse and type check a program, as the compiler does.
+fn parse(src: &str) -> Option<CompUnit> {
+    let mut ast = SysYParser::new().parse(&preprocess(src)).ok()?;
+    ast.type_check().ok()?;
+    Some(ast)
+}
+
+/// Reduce a valid program while it is interesting, and get the reduced one,
+/// or `None` if it is invalid.
+///
+/// `interesting` is called with each valid candidate, and should be true for
+/// the program itself, or it is returned as it is.
+pub fn reduce(src: &str, mut interesting: impl FnMut(&str) -> bool) -> Option<String> {
+    parse(src)?;
+    let mut current = src.to_string();
+    'reduce: loop {
+        // the spans are the ones of the parse before the type checking, which
+        // adds the coercions without them
+        let ast = SysYParser::new().parse(&preprocess(&current)).ok()?;
+        for edit in edits(&current, &ast) {
+            let candidate = edit.apply(&current);
+            if parse(&candidate).is_some() && interesting(&candidate) {
+                current = candidate;
+                continue 'reduce;
+            }
+        }
+        return Some(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reduce() {
+        let program = "\
+int d = 2;
+int f(int a) { return a + 1; }
+int main() {
+    int a = 3;
+    int b = f(a) * 2;
+    a = a + b / d;
+    return a;
+}
+";
+        let mut runs = 0;
+        let reduced = reduce(program, |candidate| {
+            runs += 1;
+            candidate.contains("/ d")
+        })
+        .unwrap();
+        let expected = "\
+int d = 0;
+int main() {
+    int a = 0;
+    a = 0 / d;
+}
+";
+        assert_eq!(reduced, expected);
+        assert!(runs > 0);
+
+        // the edits keep the programs valid
+        assert_eq!(reduce("int main() { return x; }", |_| true), None);
+        assert_eq!(reduce(program, |_| false).as_deref(), Some(program));
+    }
+}
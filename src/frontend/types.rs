@@ -1,11 +1,11 @@
 //! Define the types in AST of SysY language.
 //! The types are used in the AST and the symbol table.
 
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 use std::{fmt, hash};
 
+use crate::infra::hash::HashMap;
+
 /// The type in AST
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TypeKind {
@@ -22,7 +22,7 @@ pub enum TypeKind {
 
 // The type in AST
 #[derive(Clone, Eq)]
-pub struct Type(Rc<TypeKind>);
+pub struct Type(Arc<TypeKind>);
 
 impl hash::Hash for Type {
     fn hash<H: hash::Hasher>(&self, state: &mut H) { self.0.hash(state) }
@@ -30,7 +30,7 @@ impl hash::Hash for Type {
 
 impl PartialEq for Type {
     // Just compare the pointers
-    fn eq(&self, other: &Self) -> bool { Rc::ptr_eq(&self.0, &other.0) }
+    fn eq(&self, other: &Self) -> bool { Arc::ptr_eq(&self.0, &other.0) }
 }
 
 impl fmt::Debug for Type {
@@ -59,28 +59,31 @@ impl fmt::Display for Type {
 }
 
 impl Type {
-    thread_local! {
-        /// The pool to implement singleton.
-        ///
-        /// Reference: https://github.com/pku-minic/koopa/blob/master/src/ir/types.rs
-        ///
-        /// XXX: This is not the only solution. In the implementation of IR, we use
-        /// `UniqueArena` to store types.
-        static POOL: RefCell<HashMap<TypeKind, Type>> = RefCell::new(HashMap::default());
+    /// The pool to implement singleton.
+    ///
+    /// Reference: https://github.com/pku-minic/koopa/blob/master/src/ir/types.rs
+    ///
+    /// The pool is shared by all the threads, so the types made on different
+    /// threads are still pointer-equal.
+    ///
+    /// XXX: This is not the only solution. In the implementation of IR, we use
+    /// `UniqueArena` to store types.
+    fn pool() -> &'static Mutex<HashMap<TypeKind, Type>> {
+        static POOL: OnceLock<Mutex<HashMap<TypeKind, Type>>> = OnceLock::new();
+        POOL.get_or_init(Mutex::default)
     }
 
     /// Create a new type.
     pub fn make(kind: TypeKind) -> Type {
-        Self::POOL.with(|pool| {
-            let mut pool = pool.borrow_mut();
-            if let Some(ty) = pool.get(&kind) {
-                ty.clone()
-            } else {
-                let ty = Type(Rc::new(kind.clone()));
-                pool.insert(kind, ty.clone());
-                ty
-            }
-        })
+        // the pool is consistent even if another thread panicked holding it
+        let mut pool = Self::pool().lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(ty) = pool.get(&kind) {
+            ty.clone()
+        } else {
+            let ty = Type(Arc::new(kind.clone()));
+            pool.insert(kind, ty.clone());
+            ty
+        }
     }
 
     /// Get the kind of the type.
@@ -183,6 +186,16 @@ mod tests {
         let int_type1 = Type::int();
         let int_type2 = Type::int();
 
-        assert!(Rc::ptr_eq(&int_type1.0, &int_type2.0));
+        assert!(Arc::ptr_eq(&int_type1.0, &int_type2.0));
+    }
+
+    #[test]
+    fn test_type_across_threads() {
+        let func_type = Type::func(vec![Type::int()], Type::void());
+        let other = std::thread::spawn(|| Type::func(vec![Type::int()], Type::void()))
+            .join()
+            .unwrap();
+        assert_eq!(func_type, other);
+        assert!(Arc::ptr_eq(&func_type.0, &other.0));
     }
 }
@@ -1,2 +1,8 @@
+pub mod diff;
+pub mod filecheck;
+pub mod hash;
+pub mod intern;
 pub mod linked_list;
+pub mod rng;
 pub mod storage;
+pub mod timing;
@@ -0,0 +1,199 @@
+//! Line diffs of texts, e.g., of the outputs of two compilations.
+//!
+//! The shortest edit script is found by the algorithm of Myers, in
+//! `O((N + M) D)` time for `D` differing lines, so the diff of two long
+//! listings differing in a few lines is fast. It is printed in the unified
+//! format, see [`unified_diff`].
+
+use std::fmt::Write;
+
+/// A line of a diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    /// A line in both texts.
+    Same(&'a str),
+    /// A line only in the old text.
+    Removed(&'a str),
+    /// A line only in the new text.
+    Added(&'a str),
+}
+
+/// Get the shortest diff turning the old lines into the new ones, the removed
+/// lines of each change before the added ones.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let a = old.lines().collect::<Vec<_>>();
+    let b = new.lines().collect::<Vec<_>>();
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let idx = |k: isize| (k + max as isize + 1) as usize;
+
+    // the furthest x reached on each diagonal k = x - y, before each step
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = match k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                true => v[idx(k + 1)],
+                false => v[idx(k - 1)] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let (d, k) = (d as isize, x - y);
+        let prev_k = match k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = v[idx(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            lines.push(DiffLine::Same(a[x as usize]));
+        }
+        if d > 0 {
+            match x == prev_x {
+                true => lines.push(DiffLine::Added(b[prev_y as usize])),
+                false => lines.push(DiffLine::Removed(a[prev_x as usize])),
+            }
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    lines.reverse();
+    // the script alternates the removals and the additions of a change, which
+    // reads better grouped
+    for change in lines.split_mut(|line| matches!(line, DiffLine::Same(_))) {
+        change.sort_by_key(|line| matches!(line, DiffLine::Added(_)));
+    }
+    lines
+}
+
+/// Print the diff of two texts in the unified format, with the lines of context
+/// around the changes, or nothing if they have the same lines.
+pub fn unified_diff(old: &str, new: &str, context: usize) -> String {
+    let lines = diff_lines(old, new);
+    let changes = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    // the ranges of the hunks, merged if their contexts overlap
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for i in changes {
+        let (start, end) = (
+            i.saturating_sub(context),
+            (i + context + 1).min(lines.len()),
+        );
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = String::new();
+    // the line numbers before each line of the diff, from 1
+    let (mut old_line, mut new_line) = (1, 1);
+    let mut pos = 0;
+    for (start, end) in hunks {
+        for line in &lines[pos..start] {
+            old_line += !matches!(line, DiffLine::Added(_)) as usize;
+            new_line += !matches!(line, DiffLine::Removed(_)) as usize;
+        }
+        let hunk = &lines[start..end];
+        let old_len = hunk
+            .iter()
+            .filter(|l| !matches!(l, DiffLine::Added(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|l| !matches!(l, DiffLine::Removed(_)))
+            .count();
+        // an empty range is numbered by the line before it
+        let old_start = old_line - (old_len == 0) as usize;
+        let new_start = new_line - (new_len == 0) as usize;
+        writeln!(
+            diff,
+            "@@ -{},{} +{},{} @@",
+            old_start, old_len, new_start, new_len
+        )
+        .unwrap();
+        for line in hunk {
+            match line {
+                DiffLine::Same(line) => writeln!(diff, " {}", line).unwrap(),
+                DiffLine::Removed(line) => writeln!(diff, "-{}", line).unwrap(),
+                DiffLine::Added(line) => writeln!(diff, "+{}", line).unwrap(),
+            }
+        }
+        old_line += old_len;
+        new_line += new_len;
+        pos = end;
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        use DiffLine::*;
+        let old = "a\nb\nc\nd\ne\n";
+        let new = "a\nx\nc\nd\ne\nf\n";
+        assert_eq!(
+            diff_lines(old, new),
+            [
+                Same("a"),
+                Removed("b"),
+                Added("x"),
+                Same("c"),
+                Same("d"),
+                Same("e"),
+                Added("f")
+            ]
+        );
+        assert_eq!(diff_lines("", "a"), [Added("a")]);
+        assert_eq!(diff_lines("a", ""), [Removed("a")]);
+        assert!(diff_lines("", "").is_empty());
+
+        // the diff gives back both texts
+        let texts = ["a b c a b b a", "c b a b a c", "a a a", "b", "c a b c"];
+        for (old, new) in texts.iter().flat_map(|a| texts.iter().map(move |b| (a, b))) {
+            let (old, new) = (old.replace(' ', "\n"), new.replace(' ', "\n"));
+            let lines = diff_lines(&old, &new);
+            let side = |skip: fn(&DiffLine) -> bool| {
+                let lines = lines.iter().filter(|line| !skip(line));
+                lines
+                    .map(|(Same(line) | Removed(line) | Added(line))| *line)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(side(|line| matches!(line, Added(_))).join("\n"), old);
+            assert_eq!(side(|line| matches!(line, Removed(_))).join("\n"), new);
+        }
+
+        let old = (0..20).map(|i| format!("{}\n", i)).collect::<String>();
+        let new = old.replacen("2\n", "two\n", 1).replace("17\n", "");
+        assert_eq!(
+            unified_diff(&old, &new, 2),
+            "@@ -1,5 +1,5 @@\n 0\n 1\n-2\n+two\n 3\n 4\n\
+             @@ -16,5 +16,4 @@\n 15\n 16\n-17\n 18\n 19\n"
+        );
+        assert_eq!(unified_diff(&old, &old, 2), "");
+        assert_eq!(unified_diff("", "a\n", 3), "@@ -0,0 +1,1 @@\n+a\n");
+    }
+}
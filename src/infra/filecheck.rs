@@ -0,0 +1,289 @@
+//! A matcher of the outputs against the directives in the style of LLVM
+//! FileCheck, for the regression tests of the passes.
+//!
+//! The directives are written in the comments of a fixture, `// CHECK: ...`
+//! in SysY or `; CHECK: ...` in the IR, and are matched in order:
+//!
+//! - `CHECK: pattern` matches the pattern after the previous match;
+//! - `CHECK-NEXT: pattern` matches it on the line right after the previous
+//!   match;
+//! - `CHECK-NOT: pattern` asserts that the pattern is absent between the
+//!   previous match and the next one, or the end of the output.
+//!
+//! A pattern is a literal string, except that a run of spaces in it matches
+//! any run of spaces and tabs, so the indentation of the output does not
+//! matter.
+
+use std::fmt;
+use std::ops::Range;
+
+use thiserror::Error;
+
+/// The kind of a directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckKind {
+    Check,
+    Next,
+    Not,
+}
+
+impl fmt::Display for CheckKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckKind::Check => write!(f, "CHECK"),
+            CheckKind::Next => write!(f, "CHECK-NEXT"),
+            CheckKind::Not => write!(f, "CHECK-NOT"),
+        }
+    }
+}
+
+/// A directive of a check file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub kind: CheckKind,
+    pub pattern: String,
+    /// The line of the directive in the check file, from 1.
+    pub line: usize,
+}
+
+/// An error of the directives, or a failed match, with the line of the
+/// directive in the check file.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CheckError {
+    #[error("line {0}: unknown directive `{1}`")]
+    UnknownDirective(usize, String),
+    #[error("line {0}: the pattern of {1} is empty")]
+    EmptyPattern(usize, CheckKind),
+    #[error("line {0}: CHECK-NEXT without a match before it")]
+    NextFirst(usize),
+    #[error("no CHECK directives")]
+    NoChecks,
+    #[error("line {line}: {kind}: `{pattern}` is not found after line {after} of the output")]
+    NotFound {
+        line: usize,
+        kind: CheckKind,
+        pattern: String,
+        after: usize,
+    },
+    #[error(
+        "line {line}: CHECK-NEXT: `{pattern}` is found on line {found} of the output, not on the \
+         next line {expected}"
+    )]
+    NotNextLine {
+        line: usize,
+        pattern: String,
+        found: usize,
+        expected: usize,
+    },
+    #[error("line {line}: CHECK-NOT: `{pattern}` is found on line {found} of the output")]
+    Excluded {
+        line: usize,
+        pattern: String,
+        found: usize,
+    },
+}
+
+/// Parse the directives in a check file.
+///
+/// A directive is a word `CHECK`, `CHECK-NEXT` or `CHECK-NOT` followed by a
+/// colon, anywhere in a line, so both the comments of SysY and of the IR
+/// work. Another word starting with `CHECK` followed by a colon is an error,
+/// rather than a misspelled check silently ignored.
+pub fn parse_checks(text: &str) -> Result<Vec<Check>, CheckError> {
+    let mut checks = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let Some((kind, pattern)) = directive(line, i + 1)? else {
+            continue;
+        };
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err(CheckError::EmptyPattern(i + 1, kind));
+        }
+        checks.push(Check {
+            kind,
+            pattern: pattern.to_string(),
+            line: i + 1,
+        });
+    }
+    match checks.iter().find(|check| check.kind != CheckKind::Not) {
+        Some(check) if check.kind == CheckKind::Next => Err(CheckError::NextFirst(check.line)),
+        Some(_) => Ok(checks),
+        None => Err(CheckError::NoChecks),
+    }
+}
+
+/// Find the directive in a line, and get its kind and the rest of the line.
+fn directive(line: &str, lineno: usize) -> Result<Option<(CheckKind, &str)>, CheckError> {
+    for (start, _) in line.match_indices("CHECK") {
+        let before = line[..start].chars().next_back();
+        if before.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+            continue;
+        }
+        let name_len = line[start..]
+            .find(|c: char| !(c.is_ascii_uppercase() || c == '-'))
+            .unwrap_or(line.len() - start);
+        let name = &line[start..start + name_len];
+        let Some(pattern) = line[start + name_len..].strip_prefix(':') else {
+            continue;
+        };
+        let kind = match name {
+            "CHECK" => CheckKind::Check,
+            "CHECK-NEXT" => CheckKind::Next,
+            "CHECK-NOT" => CheckKind::Not,
+            _ => return Err(CheckError::UnknownDirective(lineno, name.to_string())),
+        };
+        return Ok(Some((kind, pattern)));
+    }
+    Ok(None)
+}
+
+/// Match the output against the directives.
+pub fn run_checks(checks: &[Check], output: &str) -> Result<(), CheckError> {
+    let line_of = |offset: usize| output[..offset].matches('\n').count() + 1;
+    // the end of the previous match
+    let mut pos = 0;
+    let mut nots: Vec<&Check> = Vec::new();
+    let check_nots = |nots: &mut Vec<&Check>, range: Range<usize>| {
+        for not in nots.drain(..) {
+            if let Some(found) = find(&output[..range.end], range.start, &not.pattern) {
+                return Err(CheckError::Excluded {
+                    line: not.line,
+                    pattern: not.pattern.clone(),
+                    found: line_of(found.start),
+                });
+            }
+        }
+        Ok(())
+    };
+
+    for check in checks {
+        if check.kind == CheckKind::Not {
+            nots.push(check);
+            continue;
+        }
+        let found = find(output, pos, &check.pattern).ok_or_else(|| CheckError::NotFound {
+            line: check.line,
+            kind: check.kind,
+            pattern: check.pattern.clone(),
+            after: line_of(pos),
+        })?;
+        if check.kind == CheckKind::Next {
+            let (found, expected) = (line_of(found.start), line_of(pos) + 1);
+            if found != expected {
+                return Err(CheckError::NotNextLine {
+                    line: check.line,
+                    pattern: check.pattern.clone(),
+                    found,
+                    expected,
+                });
+            }
+        }
+        check_nots(&mut nots, pos..found.start)?;
+        pos = found.end;
+    }
+    check_nots(&mut nots, pos..output.len())
+}
+
+/// Match the output against the directives in the check file.
+pub fn filecheck(check_file: &str, output: &str) -> Result<(), CheckError> {
+    run_checks(&parse_checks(check_file)?, output)
+}
+
+/// Find the first match of the pattern in the text from the offset, the runs
+/// of spaces in the pattern matching the runs of spaces and tabs.
+fn find(text: &str, from: usize, pattern: &str) -> Option<Range<usize>> {
+    let words = pattern.split_whitespace().collect::<Vec<_>>();
+    let mut start = from;
+    while let Some(i) = text[start..].find(words[0]) {
+        let begin = start + i;
+        let mut end = begin + words[0].len();
+        let matched = words[1..].iter().all(|word| {
+            let rest = &text[end..];
+            let trimmed = rest.trim_start_matches([' ', '\t']);
+            if trimmed.len() == rest.len() || !trimmed.starts_with(word) {
+                return false;
+            }
+            end += rest.len() - trimmed.len() + word.len();
+            true
+        });
+        if matched {
+            return Some(begin..end);
+        }
+        start = begin + words[0].chars().next().unwrap().len_utf8();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filecheck() {
+        let output = "define i32 @main() {\nbb_0:\n\t%v9 = add i32 10, 2\n\tret i32 %v9\n}\n";
+        let checks = "// CHECK: define i32 @main\n\
+                      int a; // CHECK-NEXT: bb_0:\n\
+                      ; CHECK-NOT: alloca\n\
+                      ; CHECK: add i32 10, 2\n\
+                      ; CHECK-NEXT: ret i32 %v9\n\
+                      ; CHECK-NOT: store\n";
+        assert_eq!(filecheck(checks, output), Ok(()));
+        assert_eq!(parse_checks(checks).unwrap()[1].pattern, "bb_0:");
+
+        // the matches go forward, also within a line
+        assert_eq!(filecheck("CHECK: 10\nCHECK: 2", output), Ok(()));
+        assert!(matches!(
+            filecheck("CHECK: ret\nCHECK: add", output),
+            Err(CheckError::NotFound {
+                line: 2,
+                after: 4,
+                ..
+            })
+        ));
+        assert!(matches!(
+            filecheck("CHECK: define\nCHECK-NEXT: add", output),
+            Err(CheckError::NotNextLine {
+                found: 3,
+                expected: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            filecheck("CHECK: bb_0\nCHECK-NOT: add\nCHECK: ret", output),
+            Err(CheckError::Excluded {
+                line: 2,
+                found: 3,
+                ..
+            })
+        ));
+        assert!(matches!(
+            filecheck("CHECK: add\nCHECK-NOT: %v9", output),
+            Err(CheckError::Excluded { found: 4, .. })
+        ));
+
+        assert_eq!(
+            parse_checks("CHECK-NXT: a").unwrap_err(),
+            CheckError::UnknownDirective(1, "CHECK-NXT".into())
+        );
+        assert_eq!(
+            parse_checks("\n; CHECK:  ").unwrap_err(),
+            CheckError::EmptyPattern(2, CheckKind::Check)
+        );
+        assert_eq!(
+            parse_checks("CHECK-NOT: a\nCHECK-NEXT: b").unwrap_err(),
+            CheckError::NextFirst(2)
+        );
+        assert_eq!(
+            parse_checks("// CHECKS are here").unwrap_err(),
+            CheckError::NoChecks
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find("a  \tb", 0, "a b"), Some(0..5));
+        assert_eq!(find("ab a b", 0, "a b"), Some(3..6));
+        assert_eq!(find("a\nb", 0, "a b"), None);
+        assert_eq!(find("a b a b", 1, "a b"), Some(4..7));
+    }
+}
@@ -0,0 +1,17 @@
+//! The hash maps of the hot paths, e.g., the symbol tables, the interners and
+//! the value numbering.
+//!
+//! The keys are small, e.g., the handles of the arenas, and not chosen by an
+//! adversary, so the maps use the Fx hasher of rustc, much faster on them than
+//! the SipHash of the standard library. The maps are created with `default()`,
+//! not `new()`, which is only given for the standard hasher.
+
+use std::collections;
+
+pub use rustc_hash::{FxBuildHasher, FxHasher};
+
+/// A hash map with the fast hasher.
+pub type HashMap<K, V> = collections::HashMap<K, V, FxBuildHasher>;
+
+/// A hash set with the fast hasher.
+pub type HashSet<T> = collections::HashSet<T, FxBuildHasher>;
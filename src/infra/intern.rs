@@ -0,0 +1,174 @@
+//! The interner of the names, shared by the frontend and the IR.
+//!
+//! A name, e.g., of a variable, a function or a global, is interned once into
+//! a [`Symbol`], a copyable handle compared and hashed as an integer, and is
+//! resolved to its text only to print it, see [`Symbol::as_str`].
+//!
+//! The interner is shared by all the threads, as the type pool of the
+//! frontend, so the symbols of the same name are equal wherever they are made.
+//! Only interning a name takes the lock of the interner. A symbol points to its
+//! entry, which is never changed, so getting the text of a symbol does not.
+//!
+//! The entries are never freed, so the interner grows with the distinct names
+//! seen by the process, by the length of each name and a few words. It does
+//! not grow with the compilations, e.g., recompiling in `--watch` or compiling
+//! the sources of a directory only interns the names not seen before, and the
+//! lookups by name, see [`Symbol::lookup`], intern nothing.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+use super::hash::HashMap;
+
+/// An interned name.
+///
+/// The symbols are ordered by when they are interned, not by their texts.
+#[derive(Clone, Copy)]
+pub struct Symbol(&'static Entry);
+
+struct Entry {
+    index: u32,
+    name: Box<str>,
+}
+
+/// The symbols by their names.
+type Interner = HashMap<&'static str, Symbol>;
+
+impl Symbol {
+    fn interner() -> &'static RwLock<Interner> {
+        static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+        INTERNER.get_or_init(RwLock::default)
+    }
+
+    /// Intern a name.
+    pub fn intern(name: &str) -> Self {
+        if let Some(symbol) = Self::lookup(name) {
+            return symbol;
+        }
+        // the interner is consistent even if another thread panicked holding it
+        let mut interner = Self::interner()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        // another thread may have interned it meanwhile
+        if let Some(symbol) = interner.get(name) {
+            return *symbol;
+        }
+        let entry: &'static Entry = Box::leak(Box::new(Entry {
+            index: interner.len() as u32,
+            name: name.into(),
+        }));
+        interner.insert(&entry.name, Symbol(entry));
+        Symbol(entry)
+    }
+
+    /// Get the symbol of a name if it is interned, without interning it.
+    pub fn lookup(name: &str) -> Option<Self> {
+        Self::interner()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .copied()
+    }
+
+    /// Get the text of the symbol.
+    pub fn as_str(self) -> &'static str { &self.0.name }
+}
+
+/// A name to look up, see [`Symbol::lookup`].
+pub trait ToSymbol {
+    /// Get the symbol of the name, or `None` if it is not interned, so nothing
+    /// can be named by it.
+    fn to_symbol(&self) -> Option<Symbol>;
+}
+
+impl ToSymbol for Symbol {
+    fn to_symbol(&self) -> Option<Symbol> { Some(*self) }
+}
+
+impl ToSymbol for &str {
+    fn to_symbol(&self) -> Option<Symbol> { Symbol::lookup(self) }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool { self.0.index == other.0.index }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) { self.0.index.hash(state); }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering { self.0.index.cmp(&other.0.index) }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self { Symbol::intern(name) }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self { Symbol::intern(&name) }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str { self.as_str() }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool { self.as_str() == other }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool { self.as_str() == *other }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.as_str()) }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{:?}", self.as_str()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol() {
+        let a = Symbol::intern("test_symbol_a");
+        let b = Symbol::from("test_symbol_b".to_string());
+        assert_eq!(a, Symbol::from("test_symbol_a"));
+        assert_ne!(a, b);
+        assert_eq!(a.as_str(), "test_symbol_a");
+        assert_eq!(b, "test_symbol_b");
+        assert_eq!(format!("{} {:?}", a, b), "test_symbol_a \"test_symbol_b\"");
+    }
+
+    #[test]
+    fn test_symbol_lookup() {
+        assert_eq!(Symbol::lookup("test_symbol_lookup"), None);
+        // the lookup does not intern the name
+        assert_eq!(Symbol::lookup("test_symbol_lookup"), None);
+        let symbol = Symbol::intern("test_symbol_lookup");
+        assert_eq!(Symbol::lookup("test_symbol_lookup"), Some(symbol));
+        assert_eq!("test_symbol_lookup".to_symbol(), Some(symbol));
+        assert_eq!(symbol.to_symbol(), Some(symbol));
+    }
+
+    #[test]
+    fn test_symbol_across_threads() {
+        let symbol = Symbol::intern("test_symbol_thread");
+        let other = std::thread::spawn(|| Symbol::intern("test_symbol_thread"))
+            .join()
+            .unwrap();
+        assert_eq!(symbol, other);
+    }
+}
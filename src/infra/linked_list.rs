@@ -14,6 +14,8 @@
 //! compulsory. The [`Ctx`](LinkedListNode::Ctx) can be any type that is used to
 //! access the data.
 
+use std::iter::{FusedIterator, Rev};
+
 /// The error type for the linked list operations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum LinkedListError<N> {
@@ -26,6 +28,9 @@ pub enum LinkedListError<N> {
     /// The current node is not linked and the container is [`None`], we cannot
     /// insert a node before or after it.
     CurrentNodeNotLinked(N),
+    /// The nodes to [`splice`](LinkedListContainer::splice) are not a run in
+    /// one container, or the position node is in the run.
+    InvalidSpliceRange(N),
 }
 
 /// A container of linked lists.
@@ -144,6 +149,100 @@ where
         Ok(())
     }
 
+    /// Move a run of nodes into this linked list.
+    ///
+    /// The nodes from `first` to `last`, both included, are unlinked from their
+    /// container, and linked before `pos` in this one, or at the back if `pos`
+    /// is [`None`]. The run can also be moved inside this linked list.
+    ///
+    /// The relinking is O(1), but the run is walked to check it, and to update
+    /// the container of its nodes if it comes from another container, so the
+    /// time complexity is O(N) in the length of the run.
+    ///
+    /// # Parameters
+    ///
+    /// - `ctx`: The context that is used to access the data.
+    /// - `pos`: The node to move the run before, or [`None`] for the back.
+    /// - `first`: The first node of the run.
+    /// - `last`: The last node of the run.
+    ///
+    /// # Returns
+    ///
+    /// - [`Ok`] if the operation is successful.
+    /// - [`LinkedListError::PositionNodeNotInContainer`] if the position node
+    ///   is not in this container.
+    /// - [`LinkedListError::CurrentNodeNotLinked`] if the first node is not in
+    ///   a container.
+    /// - [`LinkedListError::InvalidSpliceRange`] if the last node does not
+    ///   follow the first one, or the position node is in the run.
+    fn splice(
+        self,
+        ctx: &mut Self::Ctx,
+        pos: Option<Node>,
+        first: Node,
+        last: Node,
+    ) -> Result<(), LinkedListError<Node>> {
+        if let Some(pos) = pos {
+            if pos.container(ctx) != Some(self) {
+                return Err(LinkedListError::PositionNodeNotInContainer(pos));
+            }
+        }
+        let Some(from) = first.container(ctx) else {
+            return Err(LinkedListError::CurrentNodeNotLinked(first));
+        };
+
+        let mut curr = first;
+        loop {
+            if Some(curr) == pos {
+                return Err(LinkedListError::InvalidSpliceRange(curr));
+            }
+            if curr == last {
+                break;
+            }
+            curr = curr
+                .next(ctx)
+                .ok_or(LinkedListError::InvalidSpliceRange(last))?;
+        }
+
+        // unlink the run from its container
+        let before = first.prev(ctx);
+        let after = last.next(ctx);
+        match before {
+            Some(before) => before.set_next(ctx, after),
+            None => from.set_head(ctx, after),
+        }
+        match after {
+            Some(after) => after.set_prev(ctx, before),
+            None => from.set_tail(ctx, before),
+        }
+
+        // link the run before the position
+        let (prev, next) = match pos {
+            Some(pos) => (pos.prev(ctx), Some(pos)),
+            None => (self.tail(ctx), None),
+        };
+        first.set_prev(ctx, prev);
+        last.set_next(ctx, next);
+        match prev {
+            Some(prev) => prev.set_next(ctx, Some(first)),
+            None => self.set_head(ctx, Some(first)),
+        }
+        match next {
+            Some(next) => next.set_prev(ctx, Some(last)),
+            None => self.set_tail(ctx, Some(last)),
+        }
+
+        if from != self {
+            let mut curr = Some(first);
+            while let Some(node) = curr {
+                node.set_container(ctx, Some(self));
+                curr = if node == last { None } else { node.next(ctx) };
+            }
+        }
+
+        Ok(())
+    }
+
     /// Move all the nodes in another linked list into this one.
     ///
     /// This operation will move all nodes from the other container to this one.
@@ -157,13 +256,10 @@ where
     /// - `ctx`: The context that is used to access the data.
     /// - `other`: The other container to be drained.
     fn append(self, ctx: &mut Self::Ctx, other: Self) {
-        let mut curr = other.head(ctx);
-        while let Some(node) = curr {
-            let next = node.next(ctx);
-            node.unlink(ctx);
-            // The node is unlinked, there should be no error.
-            self.push_back(ctx, node).unwrap_or_else(|_| unreachable!());
-            curr = next;
+        if let (Some(head), Some(tail)) = (other.head(ctx), other.tail(ctx)) {
+            // The run is the whole other linked list, there should be no error.
+            self.splice(ctx, None, head, tail)
+                .unwrap_or_else(|_| unreachable!());
         }
 
         debug_assert!(other.head(ctx).is_none());
@@ -198,18 +294,12 @@ where
             return Err(LinkedListError::PositionNodeNotInContainer(pos));
         }
 
-        let mut curr = self.tail(ctx);
-        while let Some(node) = curr {
-            if node == pos {
-                break;
-            }
-            let prev = node.prev(ctx);
-            node.unlink(ctx);
-            // The node is unlinked, there should be no error.
+        if let (Some(next), Some(tail)) = (pos.next(ctx), self.tail(ctx)) {
+            // The run is the rest of this linked list, there should be no error.
+            let head = other.head(ctx);
             other
-                .push_front(ctx, node)
+                .splice(ctx, head, next, tail)
                 .unwrap_or_else(|_| unreachable!());
-            curr = prev;
         }
 
         debug_assert!(self.tail(ctx) == Some(pos));
@@ -226,7 +316,7 @@ where
     /// # Returns
     ///
     /// The created iterator.
-    fn iter(self, ctx: &Self::Ctx) -> LinkedListIterator<Node> {
+    fn iter(self, ctx: &Self::Ctx) -> LinkedListIterator<'_, Node> {
         LinkedListIterator {
             ctx,
             curr_forward: self.head(ctx),
@@ -234,6 +324,20 @@ where
         }
     }
 
+    /// Create an iterator for the linked list, from the tail to the head.
+    ///
+    /// This is useful for the backward analyses, e.g., the liveness, without
+    /// collecting the nodes first.
+    ///
+    /// # Parameters
+    ///
+    /// - `ctx`: The context that is used to access the data.
+    ///
+    /// # Returns
+    ///
+    /// The created iterator.
+    fn iter_rev(self, ctx: &Self::Ctx) -> Rev<LinkedListIterator<'_, Node>> { self.iter(ctx).rev() }
+
     /// Extend the linked list with nodes from an iterator.
     ///
     /// The order of the nodes in the iterator is preserved.
@@ -276,6 +380,23 @@ where
     fn cursor(self, ctx: &Node::Ctx, strategy: CursorStrategy) -> LinkedListCursor<Node> {
         LinkedListCursor::new(self, ctx, strategy, CursorDirection::Forward)
     }
+
+    /// Create a [mutable cursor](LinkedListCursorMut) at the head of the
+    /// linked list.
+    ///
+    /// # Parameters
+    ///
+    /// - `ctx`: The context that is used to access the data.
+    ///
+    /// # Returns
+    ///
+    /// The created cursor, at the ghost position if the linked list is empty.
+    fn cursor_mut(self, ctx: &Self::Ctx) -> LinkedListCursorMut<Node> {
+        LinkedListCursorMut {
+            container: self,
+            curr: self.head(ctx),
+        }
+    }
 }
 
 /// The linked node trait.
@@ -507,7 +628,8 @@ pub trait LinkedListNode: Copy + Eq {
 /// [`LinkedListCursor`] by calling [`LinkedListContainer::cursor`].
 ///
 /// This is a double-ended iterator, and can be reversed by calling
-/// [`rev`](Self::rev).
+/// [`rev`](Self::rev). The nodes are visited once even if both ends are used,
+/// and the iteration stops where they meet.
 ///
 /// # Lifetimes
 ///
@@ -526,20 +648,33 @@ impl<'a, T: LinkedListNode> Iterator for LinkedListIterator<'a, T> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let curr = self.curr_forward;
-        self.curr_forward = curr.and_then(|node| node.next(self.ctx));
-        curr
+        let curr = self.curr_forward?;
+        if self.curr_backward == Some(curr) {
+            // the two ends meet, every node is visited
+            self.curr_forward = None;
+            self.curr_backward = None;
+        } else {
+            self.curr_forward = curr.next(self.ctx);
+        }
+        Some(curr)
     }
 }
 
 impl<'a, T: LinkedListNode> DoubleEndedIterator for LinkedListIterator<'a, T> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        let curr = self.curr_backward;
-        self.curr_backward = curr.and_then(|node| node.prev(self.ctx));
-        curr
+        let curr = self.curr_backward?;
+        if self.curr_forward == Some(curr) {
+            self.curr_forward = None;
+            self.curr_backward = None;
+        } else {
+            self.curr_backward = curr.prev(self.ctx);
+        }
+        Some(curr)
     }
 }
 
+impl<'a, T: LinkedListNode> FusedIterator for LinkedListIterator<'a, T> {}
+
 /// The strategy for the cursor to fetch the next node.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CursorStrategy {
@@ -724,6 +859,104 @@ impl<T: LinkedListNode> LinkedListCursor<T> {
     }
 }
 
+/// A bidirectional cursor for rewriting the linked list while moving over it.
+///
+/// Unlike [`LinkedListCursor`], the cursor points at a node, or at the ghost
+/// position between the [`tail`](LinkedListContainer::tail) and the
+/// [`head`](LinkedListContainer::head), and the nodes are inserted and removed
+/// at the cursor, so the cursor always stays in the linked list. Moving next
+/// from the tail, or previous from the head, reaches the ghost position, and
+/// moving again wraps around.
+///
+/// There is no lifetime parameter for the cursor, because the cursor does not
+/// reference to the arena directly.
+///
+/// # Type parameters
+///
+/// - `T`: The type of the linked nodes.
+pub struct LinkedListCursorMut<T: LinkedListNode> {
+    /// The container of the linked list.
+    container: T::Container,
+    /// The current node, [`None`] for the ghost position.
+    curr: Option<T>,
+}
+
+impl<T: LinkedListNode> LinkedListCursorMut<T> {
+    /// Get the current node, or [`None`] at the ghost position.
+    pub fn current(&self) -> Option<T> { self.curr }
+
+    /// Get the node after the current one, or the head at the ghost position.
+    pub fn peek_next(&self, ctx: &T::Ctx) -> Option<T> {
+        match self.curr {
+            Some(curr) => curr.next(ctx),
+            None => self.container.head(ctx),
+        }
+    }
+
+    /// Get the node before the current one, or the tail at the ghost position.
+    pub fn peek_prev(&self, ctx: &T::Ctx) -> Option<T> {
+        match self.curr {
+            Some(curr) => curr.prev(ctx),
+            None => self.container.tail(ctx),
+        }
+    }
+
+    /// Move the cursor to the next node.
+    pub fn move_next(&mut self, ctx: &T::Ctx) { self.curr = self.peek_next(ctx); }
+
+    /// Move the cursor to the previous node.
+    pub fn move_prev(&mut self, ctx: &T::Ctx) { self.curr = self.peek_prev(ctx); }
+
+    /// Insert a node before the current one, or at the tail at the ghost
+    /// position.
+    ///
+    /// The cursor stays at the current node.
+    ///
+    /// # Returns
+    ///
+    /// - [`Ok`] if the operation is successful.
+    /// - [`LinkedListError::NodeAlreadyInContainer`] if the node is already in
+    ///   a container.
+    pub fn insert_before(&mut self, ctx: &mut T::Ctx, node: T) -> Result<(), LinkedListError<T>> {
+        match self.curr {
+            Some(curr) => curr.insert_before(ctx, node),
+            None => self.container.push_back(ctx, node),
+        }
+    }
+
+    /// Insert a node after the current one, or at the head at the ghost
+    /// position.
+    ///
+    /// The cursor stays at the current node, so the inserted node is visited
+    /// by the next [`move_next`](Self::move_next).
+    ///
+    /// # Returns
+    ///
+    /// - [`Ok`] if the operation is successful.
+    /// - [`LinkedListError::NodeAlreadyInContainer`] if the node is already in
+    ///   a container.
+    pub fn insert_after(&mut self, ctx: &mut T::Ctx, node: T) -> Result<(), LinkedListError<T>> {
+        match self.curr {
+            Some(curr) => curr.insert_after(ctx, node),
+            None => self.container.push_front(ctx, node),
+        }
+    }
+
+    /// [`Unlink`](LinkedListNode::unlink) the current node, and move the
+    /// cursor to the next one.
+    ///
+    /// # Returns
+    ///
+    /// The removed node, or [`None`] at the ghost position, where nothing is
+    /// removed.
+    pub fn remove_current(&mut self, ctx: &mut T::Ctx) -> Option<T> {
+        let curr = self.curr?;
+        self.curr = curr.next(ctx);
+        curr.unlink(ctx);
+        Some(curr)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1195,4 +1428,148 @@ mod tests {
         assert_eq!(iter.next(), Some(node4));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_linked_list_cursor_mut() {
+        let arena = &mut TestArena::default();
+
+        let container = Container::new(arena);
+        let node1 = Node::new(arena, 1);
+        let node2 = Node::new(arena, 2);
+        let node3 = Node::new(arena, 3);
+        let node4 = Node::new(arena, 4);
+
+        let mut cursor = container.cursor_mut(arena);
+        assert_eq!(cursor.current(), None);
+        cursor.insert_before(arena, node2).unwrap();
+        cursor.insert_after(arena, node1).unwrap();
+        assert_eq!(cursor.current(), None);
+        assert_eq!(cursor.peek_next(arena), Some(node1));
+        assert_eq!(cursor.peek_prev(arena), Some(node2));
+
+        cursor.move_next(arena);
+        assert_eq!(cursor.current(), Some(node1));
+        cursor.move_next(arena);
+        assert_eq!(cursor.current(), Some(node2));
+        cursor.insert_after(arena, node4).unwrap();
+        cursor.insert_after(arena, node3).unwrap();
+        assert_eq!(
+            cursor.insert_after(arena, node1),
+            Err(LinkedListError::NodeAlreadyInContainer(node1))
+        );
+        let nodes = container.iter(arena).collect::<Vec<_>>();
+        assert_eq!(nodes, vec![node1, node2, node3, node4]);
+
+        // remove the even nodes while moving over the list
+        let mut cursor = container.cursor_mut(arena);
+        while let Some(node) = cursor.current() {
+            if node == node2 || node == node4 {
+                assert_eq!(cursor.remove_current(arena), Some(node));
+            } else {
+                cursor.move_next(arena);
+            }
+        }
+        assert_eq!(cursor.remove_current(arena), None);
+        let nodes = container.iter(arena).collect::<Vec<_>>();
+        assert_eq!(nodes, vec![node1, node3]);
+        assert_eq!(node2.container(arena), None);
+        assert_eq!(container.tail(arena), Some(node3));
+
+        // wrap around through the ghost position
+        cursor.move_prev(arena);
+        assert_eq!(cursor.current(), Some(node3));
+        cursor.move_prev(arena);
+        cursor.move_prev(arena);
+        assert_eq!(cursor.current(), None);
+        cursor.move_prev(arena);
+        assert_eq!(cursor.current(), Some(node3));
+    }
+
+    #[test]
+    fn test_linked_list_splice() {
+        let arena = &mut TestArena::default();
+
+        let container1 = Container::new(arena);
+        let container2 = Container::new(arena);
+        let nodes = (1..=6).map(|val| Node::new(arena, val)).collect::<Vec<_>>();
+        let [node1, node2, node3, node4, node5, node6] = nodes[..] else {
+            unreachable!()
+        };
+
+        container1
+            .extend(arena, [node1, node2, node3, node4])
+            .unwrap();
+        container2.extend(arena, [node5, node6]).unwrap();
+
+        // a run from the middle, before a node of another container
+        container2.splice(arena, Some(node6), node2, node3).unwrap();
+        let list1 = container1.iter(arena).collect::<Vec<_>>();
+        let list2 = container2.iter(arena).collect::<Vec<_>>();
+        assert_eq!(list1, vec![node1, node4]);
+        assert_eq!(list2, vec![node5, node2, node3, node6]);
+        assert_eq!(node2.container(arena), Some(container2));
+        assert_eq!(node3.container(arena), Some(container2));
+        assert_eq!(node4.prev(arena), Some(node1));
+
+        // a run inside the same container, to the back
+        container2.splice(arena, None, node5, node2).unwrap();
+        let list2 = container2.iter(arena).collect::<Vec<_>>();
+        assert_eq!(list2, vec![node3, node6, node5, node2]);
+        assert_eq!(container2.head(arena), Some(node3));
+        assert_eq!(container2.tail(arena), Some(node2));
+
+        // the whole list, to the front
+        container1.splice(arena, Some(node1), node3, node2).unwrap();
+        assert_eq!(container2.head(arena), None);
+        assert_eq!(container2.tail(arena), None);
+        let list1 = container1.iter(arena).collect::<Vec<_>>();
+        assert_eq!(list1, vec![node3, node6, node5, node2, node1, node4]);
+        let rev = container1.iter(arena).rev().collect::<Vec<_>>();
+        assert_eq!(rev, vec![node4, node1, node2, node5, node6, node3]);
+
+        assert_eq!(
+            container1.splice(arena, Some(node5), node6, node2),
+            Err(LinkedListError::InvalidSpliceRange(node5))
+        );
+        assert_eq!(
+            container1.splice(arena, None, node2, node6),
+            Err(LinkedListError::InvalidSpliceRange(node6))
+        );
+        assert_eq!(
+            container2.splice(arena, Some(node1), node2, node2),
+            Err(LinkedListError::PositionNodeNotInContainer(node1))
+        );
+    }
+
+    #[test]
+    fn test_linked_list_iter_rev() {
+        let arena = &mut TestArena::default();
+
+        let container = Container::new(arena);
+        let node1 = Node::new(arena, 1);
+        let node2 = Node::new(arena, 2);
+        let node3 = Node::new(arena, 3);
+
+        assert_eq!(container.iter_rev(arena).next(), None);
+
+        container.extend(arena, [node1, node2, node3]).unwrap();
+
+        let nodes = container.iter_rev(arena).collect::<Vec<_>>();
+        assert_eq!(nodes, vec![node3, node2, node1]);
+
+        // the two ends meet
+        let mut iter = container.iter(arena);
+        assert_eq!(iter.next(), Some(node1));
+        assert_eq!(iter.next_back(), Some(node3));
+        assert_eq!(iter.next_back(), Some(node2));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+
+        let mut iter = container.iter(arena);
+        assert_eq!(iter.next_back(), Some(node3));
+        assert_eq!(iter.next(), Some(node1));
+        assert_eq!(iter.next(), Some(node2));
+        assert_eq!(iter.next_back(), None);
+        assert_eq!(iter.next(), None);
+    }
 }
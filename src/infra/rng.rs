@@ -0,0 +1,87 @@
+//! A small seeded pseudo-random number generator, for the random tests.
+//!
+//! The generator is SplitMix64, good enough to explore the programs and the
+//! pass orderings, and reproducible from the seed printed by a failing test.
+//! It is not meant for anything needing unpredictable numbers.
+
+/// A pseudo-random number generator.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self { Rng { state: seed } }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Get a number in `0..n`.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if `n` is zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        assert!(n > 0, "empty range");
+        // the bias of the modulo is negligible for the small ranges used
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Get a number in `lo..=hi`.
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        assert!(lo <= hi, "empty range");
+        let span = (hi - lo) as u64 + 1;
+        lo + (self.next_u64() % span) as i64
+    }
+
+    /// Get true with the probability of `num / den`.
+    pub fn chance(&mut self, num: usize, den: usize) -> bool { self.below(den) < num }
+
+    /// Choose an element of a slice.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the slice is empty.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T { &items[self.below(items.len())] }
+
+    /// Shuffle a slice.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let numbers = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..8).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+        assert_eq!(numbers(42), numbers(42));
+        assert_ne!(numbers(42), numbers(43));
+
+        let mut rng = Rng::new(0);
+        let mut seen = [false; 5];
+        for _ in 0..100 {
+            let n = rng.range(-2, 2);
+            assert!((-2..=2).contains(&n));
+            seen[(n + 2) as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+
+        let mut items = (0..10).collect::<Vec<_>>();
+        rng.shuffle(&mut items);
+        items.sort();
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+    }
+}
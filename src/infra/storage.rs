@@ -12,11 +12,12 @@
 //! (or context) is not restricted, which tends to be more flexible.
 
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
-use std::hash::{DefaultHasher, Hash, Hasher};
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::{fmt, mem};
 
+use super::hash::{FxHasher, HashMap, HashSet};
+
 /// A trait for indexing into an arena.
 pub trait ArenaPtr: Copy + Eq + Hash {
     /// The arena type, which should support the pointer type.
@@ -114,8 +115,12 @@ where
 /// The pointer can only be allocated by [`GenericArena`]. One should not create
 /// a pointer manually.
 ///
-/// The internal of this pointer is a raw index into the arena. It is not
-/// generational, so the user should be careful after re-allocation.
+/// The internal of this pointer is a raw index into the arena. In debug builds,
+/// the pointer also records the generation of the entry, which is bumped on
+/// each de-allocation, so dereferencing a pointer to a reused entry panics
+/// instead of reading the data of another allocation. The generation is not
+/// compared, i.e., a stale pointer is still equal to the new pointer to the
+/// same entry, so the behavior is the same in release builds.
 ///
 /// The pointer implements [`Ord`] to allow sorting. The order is based on the
 /// raw index, i.e., the position of the data in the arena.
@@ -127,13 +132,18 @@ where
 pub struct GenericPtr<Data> {
     /// The raw index of the pointer.
     index: usize,
+    /// The generation of the entry when the pointer is allocated.
+    #[cfg(debug_assertions)]
+    generation: u32,
     _phantom: PhantomData<Data>,
 }
 
 impl<Data> GenericPtr<Data> {
-    fn from_index(index: usize) -> Self {
+    fn from_index(index: usize, _generation: u32) -> Self {
         Self {
             index,
+            #[cfg(debug_assertions)]
+            generation: _generation,
             _phantom: PhantomData,
         }
     }
@@ -252,6 +262,12 @@ pub struct GenericArena<Data> {
     /// This is the index of the first vacant entry, also the last deallocated
     /// entry.
     free_head: Option<usize>,
+    /// The number of occupied entries.
+    len: usize,
+    /// The generations of the entries, checked against the pointers in debug
+    /// builds.
+    #[cfg(debug_assertions)]
+    generations: Vec<u32>,
 }
 
 impl<Data> Default for GenericArena<Data> {
@@ -259,6 +275,9 @@ impl<Data> Default for GenericArena<Data> {
         Self {
             entries: Vec::new(),
             free_head: None,
+            len: 0,
+            #[cfg(debug_assertions)]
+            generations: Vec::new(),
         }
     }
 }
@@ -272,6 +291,55 @@ impl<Data> GenericArena<Data> {
         Self {
             entries: Vec::with_capacity(capacity),
             free_head: None,
+            len: 0,
+            #[cfg(debug_assertions)]
+            generations: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Get the number of the stored data.
+    ///
+    /// The vacant entries are not counted, they are reused by the later
+    /// allocations.
+    pub fn len(&self) -> usize { self.len }
+
+    /// Check if no data is stored.
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Get the generation of an entry, 0 in release builds.
+    fn generation(&self, _index: usize) -> u32 {
+        #[cfg(debug_assertions)]
+        return self.generations[_index];
+        #[cfg(not(debug_assertions))]
+        0
+    }
+
+    /// Bump the generation of a deallocated entry, invalidating its pointers.
+    fn bump_generation(&mut self, _index: usize) {
+        #[cfg(debug_assertions)]
+        {
+            self.generations[_index] = self.generations[_index].wrapping_add(1);
+        }
+    }
+
+    /// Check that a pointer is not to an entry reused since its allocation.
+    ///
+    /// This is only checked in debug builds.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the pointer is stale, and its entry is occupied again.
+    fn check(&self, _ptr: GenericPtr<Data>) {
+        #[cfg(debug_assertions)]
+        if let Some(GenericEntry::Occupied(_)) = self.entries.get(_ptr.index) {
+            let generation = self.generations[_ptr.index];
+            assert!(
+                _ptr.generation == generation,
+                "stale pointer {} of generation {} used, the entry is reused at generation {}",
+                _ptr,
+                _ptr.generation,
+                generation
+            );
         }
     }
 
@@ -290,6 +358,32 @@ impl<Data> GenericArena<Data> {
             GenericEntry::Vacant { .. } => None,
         })
     }
+
+    /// Deallocate all the data that does not satisfy the predicate.
+    ///
+    /// # Returns
+    ///
+    /// The number of deallocated entries.
+    pub fn retain<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(&Data) -> bool,
+    {
+        let mut count = 0;
+        for index in 0..self.entries.len() {
+            if let GenericEntry::Occupied(data) = &self.entries[index] {
+                if !f(data) {
+                    self.entries[index] = GenericEntry::Vacant {
+                        next: self.free_head,
+                    };
+                    self.free_head = Some(index);
+                    self.bump_generation(index);
+                    count += 1;
+                }
+            }
+        }
+        self.len -= count;
+        count
+    }
 }
 
 impl<Data> ArenaPtr for GenericPtr<Data> {
@@ -302,6 +396,7 @@ impl<Data> Arena<GenericPtr<Data>> for GenericArena<Data> {
     where
         F: FnOnce(GenericPtr<Data>) -> Data,
     {
+        self.len += 1;
         match self.free_head.take() {
             Some(index) => {
                 let entry = &mut self.entries[index];
@@ -311,15 +406,17 @@ impl<Data> Arena<GenericPtr<Data>> for GenericArena<Data> {
                     // we have a `free_head`, this entry should be vacant
                     GenericEntry::Occupied(_) => unreachable!(),
                 };
-                let ptr = GenericPtr::from_index(index);
-                *entry = GenericEntry::Occupied(f(ptr)); // set the entry to occupied
+                let ptr = GenericPtr::from_index(index, self.generation(index));
+                self.entries[index] = GenericEntry::Occupied(f(ptr)); // set the entry to occupied
                 ptr
             }
             None => {
                 // we have no `free_head`, so we need to push a new entry
                 let index = self.entries.len();
-                let ptr = GenericPtr::from_index(index);
+                let ptr = GenericPtr::from_index(index, 0);
                 self.entries.push(GenericEntry::Occupied(f(ptr)));
+                #[cfg(debug_assertions)]
+                self.generations.push(0);
                 ptr
             }
         }
@@ -327,9 +424,12 @@ impl<Data> Arena<GenericPtr<Data>> for GenericArena<Data> {
 
     fn try_dealloc(&mut self, ptr: GenericPtr<Data>) -> Option<Data> {
         let index = ptr.index();
-        if index >= self.entries.len() {
+        // a vacant entry is already in the free list, and linking it again
+        // would hand it out twice
+        if !matches!(self.entries.get(index)?, GenericEntry::Occupied(_)) {
             return None;
         }
+        self.check(ptr);
         let old_entry = mem::replace(
             &mut self.entries[index],
             GenericEntry::Vacant {
@@ -337,13 +437,16 @@ impl<Data> Arena<GenericPtr<Data>> for GenericArena<Data> {
             },
         );
         self.free_head = Some(index);
+        self.len -= 1;
+        self.bump_generation(index);
         match old_entry {
-            GenericEntry::Vacant { .. } => None,
+            GenericEntry::Vacant { .. } => unreachable!(),
             GenericEntry::Occupied(data) => Some(data),
         }
     }
 
     fn try_deref(&self, ptr: GenericPtr<Data>) -> Option<&Data> {
+        self.check(ptr);
         match self.entries.get(ptr.index())? {
             GenericEntry::Occupied(value) => Some(value),
             GenericEntry::Vacant { .. } => None,
@@ -351,6 +454,7 @@ impl<Data> Arena<GenericPtr<Data>> for GenericArena<Data> {
     }
 
     fn try_deref_mut(&mut self, ptr: GenericPtr<Data>) -> Option<&mut Data> {
+        self.check(ptr);
         match self.entries.get_mut(ptr.index())? {
             GenericEntry::Occupied(value) => Some(value),
             GenericEntry::Vacant { .. } => None,
@@ -364,7 +468,7 @@ pub struct UniqueArenaHash(u64);
 
 impl UniqueArenaHash {
     pub fn new<T: Hash + 'static + ?Sized>(val: &T) -> Self {
-        let mut hasher = DefaultHasher::new();
+        let mut hasher = FxHasher::default();
         val.hash(&mut hasher);
         std::any::TypeId::of::<T>().hash(&mut hasher);
         UniqueArenaHash(hasher.finish())
@@ -527,7 +631,8 @@ mod tests {
         assert_eq!(arena.iter().collect::<Vec<_>>(), vec![&1, &3]);
         assert_eq!(arena.iter_mut().collect::<Vec<_>>(), vec![&mut 1, &mut 3]);
         let ptr4 = arena.alloc(4);
-        // not a generational arena, so ptr4 is the same as ptr2
+        // the entry is reused, and the pointers are equal, only the generations
+        // differ
         assert_eq!(ptr2, ptr4);
         assert_eq!(ptr4.try_deref(&arena), Some(&4));
         assert_eq!(arena.iter().collect::<Vec<_>>(), vec![&1, &4, &3]);
@@ -537,12 +642,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_generic_arena_retain() {
+        let mut arena = GenericArena::default();
+        let ptrs = (0..6).map(|i| arena.alloc(i)).collect::<Vec<_>>();
+        assert_eq!(arena.retain(|x| x % 2 == 0), 3);
+        assert_eq!(arena.iter().collect::<Vec<_>>(), vec![&0, &2, &4]);
+        assert_eq!(arena.len(), 3);
+        assert_eq!(ptrs[1].try_deref(&arena), None);
+        // the deallocated entries are reused
+        let ptr = arena.alloc(6);
+        assert!(ptrs.contains(&ptr));
+        assert_eq!(arena.iter().count(), 4);
+    }
+
     #[test]
     fn test_generic_arena_double_free() {
         let mut arena = GenericArena::default();
         let ptr1 = arena.alloc(1);
         assert_eq!(arena.try_dealloc(ptr1), Some(1));
         assert_eq!(arena.try_dealloc(ptr1), None); // double free
+        assert!(arena.is_empty());
+
+        // the entry is in the free list only once
+        let ptr2 = arena.alloc(2);
+        let ptr3 = arena.alloc(3);
+        assert_eq!(ptr2, ptr1);
+        assert_ne!(ptr3, ptr2);
+        assert_eq!(ptr2.try_deref(&arena), Some(&2));
+        assert_eq!(arena.len(), 2);
     }
 
     #[test]
@@ -575,6 +703,18 @@ mod tests {
         assert_eq!(ptr3.try_deref_mut(&mut arena), Some(&mut 3));
     }
 
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "stale pointer *0 of generation 0 used")]
+    fn test_generic_arena_stale_deref() {
+        let mut arena = GenericArena::default();
+        let ptr1 = arena.alloc(1);
+        assert_eq!(arena.try_dealloc(ptr1), Some(1));
+        let ptr2 = arena.alloc(2);
+        assert_eq!(ptr2.try_deref(&arena), Some(&2));
+        ptr1.try_deref(&arena); // the entry is reused
+    }
+
     #[test]
     fn test_generic_arena_invalid_deref() {
         let mut arena = GenericArena::default();
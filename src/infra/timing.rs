@@ -0,0 +1,182 @@
+//! Profiling of the compilation phases.
+//!
+//! The phases are timed by wrapping them in [`time`], which records the
+//! wall-clock time and the peak memory of each phase when enabled by
+//! [`set_enabled`], and does nothing otherwise. The phases nest, e.g., the
+//! passes are timed inside the optimization, and the report shows them
+//! indented under it, see [`report`].
+//!
+//! The peak memory is the high-water mark of the resident set, read from
+//! `/proc/self/status`. It is reset at the start of each phase through
+//! `/proc/self/clear_refs`, so a phase is not charged for the memory of the
+//! earlier ones. Where the mark cannot be reset, it is the peak up to the end
+//! of the phase, and where it cannot be read at all, it is not reported.
+//!
+//! The records are kept per thread.
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+/// A timed phase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phase {
+    /// The name of the phase, e.g., `parsing`.
+    pub name: String,
+    /// The number of phases enclosing this one.
+    pub depth: usize,
+    /// The wall-clock time spent.
+    pub time: Duration,
+    /// The peak resident memory during the phase in KiB, if available.
+    pub peak_kib: Option<u64>,
+}
+
+#[derive(Default)]
+struct Timings {
+    enabled: bool,
+    /// The phases finished or running, in the order they are started.
+    phases: Vec<Phase>,
+    /// The indices of the running phases, the innermost last.
+    running: Vec<usize>,
+}
+
+thread_local! {
+    static TIMINGS: RefCell<Timings> = RefCell::default();
+}
+
+/// Read the high-water mark of the resident memory in KiB.
+fn read_peak() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line["VmHWM:".len()..]
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Reset the high-water mark of the resident memory to the current usage.
+fn reset_peak() { let _ = std::fs::write("/proc/self/clear_refs", "5"); }
+
+/// Enable or disable the recording of the phases on this thread.
+pub fn set_enabled(enabled: bool) { TIMINGS.with_borrow_mut(|timings| timings.enabled = enabled); }
+
+/// Run a phase, recording its time and peak memory if enabled.
+pub fn time<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    let enabled = TIMINGS.with_borrow_mut(|timings| {
+        if !timings.enabled {
+            return false;
+        }
+        // the mark is about to be reset, charge it to the enclosing phase
+        if let Some(&outer) = timings.running.last() {
+            let outer = &mut timings.phases[outer];
+            outer.peak_kib = outer.peak_kib.max(read_peak());
+        }
+        reset_peak();
+        timings.running.push(timings.phases.len());
+        let depth = timings.running.len() - 1;
+        timings.phases.push(Phase {
+            name: name.to_string(),
+            depth,
+            time: Duration::ZERO,
+            peak_kib: None,
+        });
+        true
+    });
+    if !enabled {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    let time = start.elapsed();
+    TIMINGS.with_borrow_mut(|timings| {
+        let idx = timings.running.pop().unwrap();
+        let phase = &mut timings.phases[idx];
+        phase.time = time;
+        phase.peak_kib = phase.peak_kib.max(read_peak());
+        let peak = phase.peak_kib;
+        if let Some(&outer) = timings.running.last() {
+            let outer = &mut timings.phases[outer];
+            outer.peak_kib = outer.peak_kib.max(peak);
+        }
+    });
+    result
+}
+
+/// Take the phases recorded so far on this thread, in the order they are
+/// started.
+pub fn take() -> Vec<Phase> {
+    TIMINGS.with_borrow_mut(|timings| {
+        assert!(timings.running.is_empty(), "phases are still running");
+        std::mem::take(&mut timings.phases)
+    })
+}
+
+/// Format the phases as a table, the nested ones indented under the
+/// enclosing ones.
+///
+/// The total is the sum of the outermost phases.
+pub fn report(phases: &[Phase]) -> String {
+    let mut report = String::new();
+    report.push_str("===== Phase timing report =====\n");
+    report.push_str(&format!(
+        "{:>12}  {:>10}  phase\n",
+        "time (ms)", "peak (KiB)"
+    ));
+    for phase in phases {
+        let ms = phase.time.as_secs_f64() * 1000.0;
+        let peak = phase
+            .peak_kib
+            .map_or("-".to_string(), |peak| peak.to_string());
+        let indent = "  ".repeat(phase.depth);
+        report.push_str(&format!(
+            "{:>12.3}  {:>10}  {}{}\n",
+            ms, peak, indent, phase.name
+        ));
+    }
+    let total = phases
+        .iter()
+        .filter(|phase| phase.depth == 0)
+        .map(|phase| phase.time)
+        .sum::<Duration>();
+    let peak = phases.iter().filter_map(|phase| phase.peak_kib).max();
+    let peak = peak.map_or("-".to_string(), |peak| peak.to_string());
+    let ms = total.as_secs_f64() * 1000.0;
+    report.push_str(&format!("{:>12.3}  {:>10}  total\n", ms, peak));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time() {
+        // nothing is recorded unless enabled
+        assert_eq!(time("a", || 1), 1);
+        assert!(take().is_empty());
+
+        set_enabled(true);
+        let result = time("outer", || {
+            time("inner", || std::thread::sleep(Duration::from_millis(2)));
+            time("next", || 2)
+        });
+        assert_eq!(result, 2);
+        set_enabled(false);
+        let phases = take();
+        let names = phases
+            .iter()
+            .map(|phase| (phase.name.as_str(), phase.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [("outer", 0), ("inner", 1), ("next", 1)]);
+        assert!(phases[0].time >= phases[1].time + phases[2].time);
+        assert!(phases[1].time >= Duration::from_millis(2));
+        assert!(phases[0].peak_kib >= phases[1].peak_kib);
+
+        let report = report(&phases);
+        assert!(report.contains("  outer\n"));
+        assert!(report.contains("    inner\n"));
+        assert!(report.lines().last().unwrap().ends_with("  total"));
+    }
+}
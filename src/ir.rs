@@ -1,17 +1,38 @@
+pub mod analysis;
+pub mod interp;
+pub mod passes;
+pub mod passman;
+
 mod block;
 mod context;
+mod debug;
 mod def_use;
+mod dot;
 mod func;
+mod gc;
 mod global;
 mod inst;
+mod link;
+mod serialize;
+mod source;
+mod ssa;
 mod ty;
 mod value;
+mod verify;
 
 pub use block::*;
 pub use context::*;
+pub use debug::*;
 pub use def_use::*;
+pub use dot::*;
 pub use func::*;
+pub use gc::*;
 pub use global::*;
 pub use inst::*;
+pub use link::*;
+pub use serialize::*;
+pub use source::*;
+pub use ssa::*;
 pub use ty::*;
 pub use value::*;
+pub use verify::*;
@@ -0,0 +1,33 @@
+//! Analyses on the IR.
+//!
+//! Analyses compute information about a function without modifying it. The
+//! results are invalidated once the function is changed, so they should be
+//! recomputed (or fetched from a cache) after transformations.
+
+mod alias;
+mod block_freq;
+mod branch_prob;
+mod call_graph;
+mod cfg;
+mod dominance;
+mod escape;
+mod induction;
+mod inline_cost;
+mod loops;
+mod memdep;
+mod range;
+mod trip_count;
+
+pub use alias::*;
+pub use block_freq::*;
+pub use branch_prob::*;
+pub use call_graph::*;
+pub use cfg::*;
+pub use dominance::*;
+pub use escape::*;
+pub use induction::*;
+pub use inline_cost::*;
+pub use loops::*;
+pub use memdep::*;
+pub use range::*;
+pub use trip_count::*;
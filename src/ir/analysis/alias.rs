@@ -0,0 +1,206 @@
+//! Basic alias analysis.
+//!
+//! Pointers are decomposed into an underlying object and a byte offset by
+//! walking through `getelementptr` instructions. Two accesses do not alias if
+//! they are based on different identified objects (allocas and globals), or on
+//! the same object with disjoint constant offsets.
+
+use crate::infra::intern::Symbol;
+use crate::infra::storage::ArenaPtr;
+use crate::ir::{ConstantValue, Context, Inst, InstKind, Value, ValueKind};
+
+/// The result of an alias query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AliasResult {
+    /// The two accesses never overlap.
+    NoAlias,
+    /// The two accesses may overlap.
+    MayAlias,
+    /// The two accesses are exactly the same memory.
+    MustAlias,
+}
+
+/// The object a pointer is derived from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PointerBase {
+    /// A stack slot allocated in the function.
+    Alloca(Inst),
+    /// A global variable.
+    Global(Symbol),
+    /// A pointer passed as a function parameter.
+    Param(Value),
+    /// Any other pointer, e.g., a loaded or merged one.
+    Unknown(Value),
+}
+
+impl PointerBase {
+    /// Check if the base is a distinct object, i.e., it does not overlap with
+    /// any other identified object.
+    pub fn is_identified(&self) -> bool {
+        matches!(self, PointerBase::Alloca(_) | PointerBase::Global(_))
+    }
+}
+
+/// A memory location accessed by a load or a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemLoc {
+    /// The pointer to the memory.
+    pub ptr: Value,
+    /// The number of bytes accessed.
+    pub size: usize,
+}
+
+impl MemLoc {
+    /// Get the location accessed by a load or a store.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(loc)`: The accessed location.
+    /// - `None`: The instruction is neither a load nor a store.
+    pub fn of_inst(ctx: &Context, inst: Inst) -> Option<Self> {
+        match inst.kind(ctx) {
+            InstKind::Load => Some(Self {
+                ptr: inst.operand(ctx, 0),
+                size: inst.result(ctx).unwrap().ty(ctx).bytewidth(ctx),
+            }),
+            InstKind::Store => Some(Self {
+                ptr: inst.operand(ctx, 1),
+                size: inst.operand(ctx, 0).ty(ctx).bytewidth(ctx),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Decompose a pointer into its base object and the byte offset from the base.
+///
+/// The offset is `None` if any index on the way is not a constant.
+pub fn decompose_pointer(ctx: &Context, ptr: Value) -> (PointerBase, Option<i64>) {
+    let mut ptr = ptr;
+    let mut offset = Some(0i64);
+    loop {
+        let inst = match &ptr.deref(ctx).kind {
+            ValueKind::Constant {
+                value: ConstantValue::GlobalRef { name, .. },
+            } => return (PointerBase::Global(*name), offset),
+            ValueKind::Param { .. } => return (PointerBase::Param(ptr), offset),
+            ValueKind::InstResult { inst, .. } => *inst,
+            _ => return (PointerBase::Unknown(ptr), offset),
+        };
+
+        match inst.kind(ctx) {
+            InstKind::Alloca { .. } => return (PointerBase::Alloca(inst), offset),
+            InstKind::GetElementPtr { bound_ty } => {
+                let mut ty = *bound_ty;
+                for (i, index) in inst.operand_iter(ctx).skip(1).enumerate() {
+                    if i != 0 {
+                        // Indices after the first one step into the array.
+                        ty = match ty.as_array(ctx) {
+                            Some((elem, _)) => elem,
+                            None => {
+                                offset = None;
+                                break;
+                            }
+                        };
+                    }
+                    offset = offset.zip(index.as_const_int(ctx)).map(|(offset, index)| {
+                        offset + index as i64 * ty.bytewidth(ctx) as i64
+                    });
+                }
+                ptr = inst.operand(ctx, 0);
+            }
+            _ => return (PointerBase::Unknown(ptr), offset),
+        }
+    }
+}
+
+/// Check if two memory locations may overlap.
+pub fn alias(ctx: &Context, a: MemLoc, b: MemLoc) -> AliasResult {
+    if a.ptr == b.ptr {
+        return if a.size == b.size {
+            AliasResult::MustAlias
+        } else {
+            AliasResult::MayAlias
+        };
+    }
+
+    let (a_base, a_offset) = decompose_pointer(ctx, a.ptr);
+    let (b_base, b_offset) = decompose_pointer(ctx, b.ptr);
+
+    if a_base != b_base {
+        return match (&a_base, &b_base) {
+            _ if a_base.is_identified() && b_base.is_identified() => AliasResult::NoAlias,
+            // A parameter cannot point to the stack frame of the callee.
+            (PointerBase::Alloca(_), PointerBase::Param(_))
+            | (PointerBase::Param(_), PointerBase::Alloca(_)) => AliasResult::NoAlias,
+            _ => AliasResult::MayAlias,
+        };
+    }
+
+    match (a_offset, b_offset) {
+        (Some(a_offset), Some(b_offset)) => {
+            if a_offset == b_offset && a.size == b.size {
+                AliasResult::MustAlias
+            } else if a_offset + a.size as i64 <= b_offset || b_offset + b.size as i64 <= a_offset {
+                AliasResult::NoAlias
+            } else {
+                AliasResult::MayAlias
+            }
+        }
+        _ => AliasResult::MayAlias,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::linked_list::LinkedListContainer;
+    use crate::ir::{Block, Func, Ty};
+
+    #[test]
+    fn test_alias() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let ptr = Ty::ptr(&mut ctx);
+        let arr = Ty::array(&mut ctx, i32, 4);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let param = func.add_param(&mut ctx, ptr);
+        let index = func.add_param(&mut ctx, i32);
+        let block = Block::new(&mut ctx);
+        func.push_back(&mut ctx, block).unwrap();
+
+        let a = Inst::alloca(&mut ctx, arr);
+        let b = Inst::alloca(&mut ctx, arr);
+        let (a_ptr, b_ptr) = (a.result(&ctx).unwrap(), b.result(&ctx).unwrap());
+        let zero = Value::i32(&mut ctx, 0);
+        let one = Value::i32(&mut ctx, 1);
+        let gep = |ctx: &mut Context, base, index| {
+            let inst = Inst::getelementptr(ctx, arr, base, vec![zero, index]);
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+        let a0 = gep(&mut ctx, a_ptr, zero);
+        let a0_again = gep(&mut ctx, a_ptr, zero);
+        let a1 = gep(&mut ctx, a_ptr, one);
+        let b0 = gep(&mut ctx, b_ptr, zero);
+        let a_index = gep(&mut ctx, a_ptr, index);
+        let g = Value::global_ref(&mut ctx, "g".to_string(), arr);
+
+        let loc = |ptr| MemLoc { ptr, size: 4 };
+        assert_eq!(alias(&ctx, loc(a0), loc(a0_again)), AliasResult::MustAlias);
+        assert_eq!(alias(&ctx, loc(a0), loc(a1)), AliasResult::NoAlias);
+        assert_eq!(alias(&ctx, loc(a0), loc(b0)), AliasResult::NoAlias);
+        assert_eq!(alias(&ctx, loc(a0), loc(a_index)), AliasResult::MayAlias);
+        assert_eq!(alias(&ctx, loc(a0), loc(g)), AliasResult::NoAlias);
+        assert_eq!(alias(&ctx, loc(a0), loc(param)), AliasResult::NoAlias);
+        assert_eq!(alias(&ctx, loc(g), loc(param)), AliasResult::MayAlias);
+        // overlapping but not the same
+        let wide = MemLoc { ptr: a0, size: 8 };
+        assert_eq!(alias(&ctx, wide, loc(a1)), AliasResult::MayAlias);
+
+        assert_eq!(
+            decompose_pointer(&ctx, a1),
+            (PointerBase::Alloca(a), Some(4))
+        );
+    }
+}
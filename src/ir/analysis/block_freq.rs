@@ -0,0 +1,159 @@
+//! Static block frequency estimation.
+//!
+//! The frequencies are derived from the branch probabilities by the algorithm
+//! of Wu and Larus. The frequencies are propagated along the forward edges in
+//! reverse postorder. A loop header is scaled by `1 / (1 - p)`, where `p` is
+//! the probability of returning to the header from itself, computed for the
+//! inner loops first.
+
+use std::collections::HashMap;
+
+use super::{BranchProbs, Cfg, Loop, LoopInfo};
+use crate::ir::Block;
+
+/// The maximum probability of returning to a loop header, which bounds the
+/// scale of a loop to 1024.
+const MAX_CYCLIC_PROB: f64 = 1.0 - 1.0 / 1024.0;
+
+/// The estimated execution frequencies of the blocks in a function, relative to
+/// the entry block.
+pub struct BlockFreqs {
+    freqs: HashMap<Block, f64>,
+    edge_freqs: HashMap<(Block, Block), f64>,
+}
+
+impl BlockFreqs {
+    /// Estimate the frequencies of all the reachable blocks.
+    pub fn new(cfg: &Cfg, loop_info: &LoopInfo, probs: &BranchProbs) -> Self {
+        let mut freqs = Self {
+            freqs: HashMap::new(),
+            edge_freqs: HashMap::new(),
+        };
+
+        // The cyclic probabilities of the inner loops are needed by the outer ones.
+        let mut cyclic_probs = HashMap::new();
+        let mut loops = loop_info.loops().collect::<Vec<_>>();
+        loops.sort_by_key(|&lp| std::cmp::Reverse(loop_info.depth(lp)));
+        for lp in loops {
+            let header = loop_info.header(lp);
+            let blocks = cfg
+                .rpo()
+                .filter(|&block| loop_info.contains(lp, block))
+                .collect::<Vec<_>>();
+            freqs.propagate(cfg, loop_info, probs, &cyclic_probs, &blocks, header);
+            let cyclic_prob = loop_info
+                .latches(lp)
+                .iter()
+                .map(|&latch| freqs.edge_freq(latch, header))
+                .sum::<f64>()
+                .min(MAX_CYCLIC_PROB);
+            cyclic_probs.insert(lp, cyclic_prob);
+        }
+
+        if let Some(entry) = cfg.entry() {
+            let blocks = cfg.rpo().collect::<Vec<_>>();
+            freqs.propagate(cfg, loop_info, probs, &cyclic_probs, &blocks, entry);
+        }
+        freqs
+    }
+
+    /// Propagate the frequencies in a region, with the head having frequency
+    /// one (before scaling if it is a loop header).
+    fn propagate(
+        &mut self,
+        cfg: &Cfg,
+        loop_info: &LoopInfo,
+        probs: &BranchProbs,
+        cyclic_probs: &HashMap<Loop, f64>,
+        blocks: &[Block],
+        head: Block,
+    ) {
+        for &block in blocks {
+            let freq = if block == head {
+                1.0
+            } else {
+                cfg.preds(block)
+                    .iter()
+                    .filter(|&&pred| !cfg.is_retreating_edge(pred, block))
+                    .map(|&pred| self.edge_freq(pred, block))
+                    .sum()
+            };
+            // The head of a loop region is not scaled, so that the cyclic
+            // probability of the loop itself can be computed.
+            let freq = match loop_info.loop_of(block) {
+                Some(lp) if loop_info.header(lp) == block => match cyclic_probs.get(&lp) {
+                    Some(cyclic_prob) => freq / (1.0 - cyclic_prob),
+                    None => freq,
+                },
+                _ => freq,
+            };
+            self.freqs.insert(block, freq);
+            for &succ in cfg.succs(block) {
+                self.edge_freqs
+                    .insert((block, succ), freq * probs.prob(block, succ));
+            }
+        }
+    }
+
+    /// Get the frequency of a block, relative to the entry.
+    ///
+    /// The frequency is zero if the block is unreachable.
+    pub fn freq(&self, block: Block) -> f64 { self.freqs.get(&block).copied().unwrap_or(0.0) }
+
+    /// Get the frequency of the edge `from -> to`, relative to the entry.
+    pub fn edge_freq(&self, from: Block, to: Block) -> f64 {
+        self.edge_freqs.get(&(from, to)).copied().unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::linked_list::LinkedListContainer;
+    use crate::ir::analysis::DomTree;
+    use crate::ir::{Context, Func, Inst, Ty, Value};
+
+    #[test]
+    fn test_block_freqs() {
+        // entry: br outer
+        // outer: cond_br c, inner, exit
+        // inner: cond_br c, inner, latch
+        // latch: br outer
+        // exit:  ret
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let i1 = Ty::i1(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let c = func.add_param(&mut ctx, i1);
+        let [entry, outer, inner, latch, exit] = [(); 5].map(|_| Block::new(&mut ctx));
+        for block in [entry, outer, inner, latch, exit] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let br = Inst::br(&mut ctx, outer);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::cond_br(&mut ctx, c, inner, exit);
+        outer.push_back(&mut ctx, br).unwrap();
+        let br = Inst::cond_br(&mut ctx, c, inner, latch);
+        inner.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, outer);
+        latch.push_back(&mut ctx, br).unwrap();
+        let zero = Value::i32(&mut ctx, 0);
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        let domtree = DomTree::new(&cfg);
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+        let probs = BranchProbs::new(&ctx, &cfg, &loop_info);
+        let freqs = BlockFreqs::new(&cfg, &loop_info, &probs);
+
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(freqs.freq(entry), 1.0));
+        // both loops are taken with probability 7/8, so each runs 8 times
+        assert!(close(freqs.freq(outer), 8.0));
+        assert!(close(freqs.freq(inner), 7.0 * 8.0));
+        assert!(close(freqs.freq(latch), 7.0));
+        assert!(close(freqs.freq(exit), 1.0));
+        assert!(close(freqs.edge_freq(inner, inner), 7.0 * 7.0));
+    }
+}
@@ -0,0 +1,234 @@
+//! Branch probability estimation.
+//!
+//! The probabilities of conditional branches are the ones of the profile if
+//! they have weights, see [`ProfileUse`](crate::ir::passes::ProfileUse), kept
+//! away from zero and one, since the profiled runs do not cover all the inputs.
+//! Otherwise they are guessed by heuristics in the spirit of Ball and Larus.
+//! The first applicable heuristic decides the probability of a branch:
+//!
+//! 1. A path that never reaches a return, e.g., an infinite loop or a call to
+//!    a function that does not return, is almost never taken.
+//! 2. Branches staying in a loop, including the back edges, are taken more
+//!    often than the ones leaving it.
+//! 3. Integers are rarely zero or negative, and rarely equal to each other.
+//!
+//! Otherwise the successors are assumed to be equally likely.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{Cfg, LoopInfo};
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::{Block, Context, InstKind, IntBinaryOp, IntCmpCond, Value};
+
+/// The probability of an edge into a path that never returns.
+const NORETURN_PROB: f64 = 1.0 / 1024.0;
+/// The probability of staying in a loop.
+const LOOP_PROB: f64 = 7.0 / 8.0;
+/// The probability of the likely side of a comparison.
+const CMP_PROB: f64 = 5.0 / 8.0;
+
+/// The estimated probabilities of the CFG edges of a function.
+pub struct BranchProbs {
+    probs: HashMap<(Block, Block), f64>,
+}
+
+impl BranchProbs {
+    /// Estimate the probabilities of all the edges from reachable blocks.
+    pub fn new(ctx: &Context, cfg: &Cfg, loop_info: &LoopInfo) -> Self {
+        let returning = Self::returning_blocks(ctx, cfg);
+
+        let mut probs = HashMap::new();
+        for block in cfg.rpo() {
+            match *cfg.succs(block) {
+                [] => {}
+                [succ] => {
+                    probs.insert((block, succ), 1.0);
+                }
+                [taken, not_taken] => {
+                    let prob =
+                        Self::taken_prob(ctx, loop_info, &returning, block, taken, not_taken);
+                    probs.insert((block, taken), prob);
+                    probs.insert((block, not_taken), 1.0 - prob);
+                }
+                ref succs => {
+                    for &succ in succs {
+                        probs.insert((block, succ), 1.0 / succs.len() as f64);
+                    }
+                }
+            }
+        }
+
+        Self { probs }
+    }
+
+    /// Find the blocks from which a return is reachable.
+    fn returning_blocks(ctx: &Context, cfg: &Cfg) -> HashSet<Block> {
+        let is_return = |block: &Block| {
+            block
+                .tail(ctx)
+                .is_some_and(|tail| matches!(tail.kind(ctx), InstKind::Ret))
+        };
+        let mut worklist = cfg
+            .blocks()
+            .iter()
+            .copied()
+            .filter(is_return)
+            .collect::<Vec<_>>();
+        let mut returning = HashSet::new();
+        while let Some(block) = worklist.pop() {
+            if returning.insert(block) {
+                worklist.extend(cfg.preds(block).iter().copied());
+            }
+        }
+        returning
+    }
+
+    /// Estimate the probability of the true side of a conditional branch.
+    fn taken_prob(
+        ctx: &Context,
+        loop_info: &LoopInfo,
+        returning: &HashSet<Block>,
+        block: Block,
+        taken: Block,
+        not_taken: Block,
+    ) -> f64 {
+        let term = block.tail(ctx).unwrap();
+        if let Some([then_w, else_w]) = term.branch_weights(ctx).filter(|&[t, e]| t + e > 0) {
+            let prob = then_w as f64 / (then_w + else_w) as f64;
+            return prob.clamp(NORETURN_PROB, 1.0 - NORETURN_PROB);
+        }
+
+        match (returning.contains(&taken), returning.contains(&not_taken)) {
+            (false, true) => return NORETURN_PROB,
+            (true, false) => return 1.0 - NORETURN_PROB,
+            _ => {}
+        }
+
+        if let Some(lp) = loop_info.loop_of(block) {
+            match (loop_info.contains(lp, taken), loop_info.contains(lp, not_taken)) {
+                (true, false) => return LOOP_PROB,
+                (false, true) => return 1.0 - LOOP_PROB,
+                _ => {}
+            }
+        }
+
+        let cond = term.operand(ctx, 0);
+        match Self::cmp_likely(ctx, cond) {
+            Some(true) => CMP_PROB,
+            Some(false) => 1.0 - CMP_PROB,
+            None => 0.5,
+        }
+    }
+
+    /// Guess the likely result of a comparison.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(likely)`: The likely result of the comparison.
+    /// - `None`: No heuristic applies.
+    fn cmp_likely(ctx: &Context, cond: Value) -> Option<bool> {
+        let inst = cond.def_inst(ctx)?;
+        let InstKind::IntBinary {
+            op: IntBinaryOp::ICmp { cond },
+        } = *inst.kind(ctx)
+        else {
+            return None;
+        };
+        let is_zero = |value: Value| value.as_const_int(ctx) == Some(0);
+        let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+        let cond = if is_zero(lhs) { cond.swap() } else { cond };
+        let against_zero = is_zero(lhs) || is_zero(rhs);
+
+        match cond {
+            IntCmpCond::Eq => Some(false),
+            IntCmpCond::Ne => Some(true),
+            IntCmpCond::Slt | IntCmpCond::Sle if against_zero => Some(false),
+            IntCmpCond::Sgt | IntCmpCond::Sge if against_zero => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Get the probability of the edge `from -> to`.
+    ///
+    /// The probability is zero if there is no such edge, or `from` is
+    /// unreachable.
+    pub fn prob(&self, from: Block, to: Block) -> f64 {
+        self.probs.get(&(from, to)).copied().unwrap_or(0.0)
+    }
+
+    /// Check if the edge `from -> to` is taken in most of the executions of
+    /// `from`.
+    pub fn is_likely(&self, from: Block, to: Block) -> bool { self.prob(from, to) > 0.5 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::analysis::DomTree;
+    use crate::ir::{Func, Inst, Ty};
+
+    #[test]
+    fn test_branch_probs() {
+        // entry:  c = icmp eq n, 0; cond_br c, done, header
+        // header: i = phi [0, entry], [i1, header]; i1 = add i, 1;
+        //         c1 = icmp slt i1, n; cond_br c1, header, check
+        // check:  c2 = icmp sgt n, 0; cond_br c2, done, spin
+        // spin:   br spin
+        // done:   ret 0
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let n = func.add_param(&mut ctx, i32);
+        let [entry, header, check, spin, done] = [(); 5].map(|_| Block::new(&mut ctx));
+        for block in [entry, header, check, spin, done] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let zero = Value::i32(&mut ctx, 0);
+        let one = Value::i32(&mut ctx, 1);
+
+        let icmp = |ctx: &mut Context, block: Block, cond, lhs, rhs| {
+            let inst = Inst::ibinary(ctx, IntBinaryOp::ICmp { cond }, lhs, rhs);
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+
+        let c = icmp(&mut ctx, entry, IntCmpCond::Eq, n, zero);
+        let br = Inst::cond_br(&mut ctx, c, done, header);
+        entry.push_back(&mut ctx, br).unwrap();
+
+        let phi = Inst::phi(&mut ctx, i32);
+        header.push_back(&mut ctx, phi).unwrap();
+        let i = phi.result(&ctx).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, i, one);
+        header.push_back(&mut ctx, add).unwrap();
+        let i1 = add.result(&ctx).unwrap();
+        phi.insert_incoming(&mut ctx, entry, zero);
+        phi.insert_incoming(&mut ctx, header, i1);
+        let c1 = icmp(&mut ctx, header, IntCmpCond::Slt, i1, n);
+        let br = Inst::cond_br(&mut ctx, c1, header, check);
+        header.push_back(&mut ctx, br).unwrap();
+
+        let c2 = icmp(&mut ctx, check, IntCmpCond::Sgt, n, zero);
+        let br = Inst::cond_br(&mut ctx, c2, done, spin);
+        check.push_back(&mut ctx, br).unwrap();
+
+        let br = Inst::br(&mut ctx, spin);
+        spin.push_back(&mut ctx, br).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        done.push_back(&mut ctx, ret).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        let domtree = DomTree::new(&cfg);
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+        let probs = BranchProbs::new(&ctx, &cfg, &loop_info);
+
+        assert_eq!(probs.prob(entry, done), 1.0 - CMP_PROB);
+        assert_eq!(probs.prob(entry, header), CMP_PROB);
+        assert_eq!(probs.prob(header, header), LOOP_PROB);
+        assert_eq!(probs.prob(header, check), 1.0 - LOOP_PROB);
+        assert_eq!(probs.prob(check, spin), NORETURN_PROB);
+        assert!(probs.is_likely(check, done));
+        assert_eq!(probs.prob(spin, spin), 1.0);
+        assert_eq!(probs.prob(entry, spin), 0.0);
+    }
+}
@@ -0,0 +1,241 @@
+//! Call graph of a module.
+//!
+//! The strongly connected components (SCCs) of the call graph group mutually
+//! recursive functions together. They are ordered bottom-up, i.e., the callees
+//! of a component are all in the same or earlier components, which is the
+//! processing order of interprocedural passes like inlining.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::{Context, Func, Inst, InstKind};
+
+/// The call graph of all the functions in a module.
+///
+/// Calls to functions not in the module (e.g., the runtime library) are not
+/// edges of the graph.
+pub struct CallGraph {
+    /// All the functions in the module.
+    funcs: Vec<Func>,
+    /// The deduplicated callees of each function.
+    callees: HashMap<Func, Vec<Func>>,
+    /// The deduplicated callers of each function.
+    callers: HashMap<Func, Vec<Func>>,
+    /// The call instructions to each function.
+    call_sites: HashMap<Func, Vec<Inst>>,
+    /// The SCCs in bottom-up order.
+    sccs: Vec<Vec<Func>>,
+    /// The index of the SCC of each function.
+    scc_index: HashMap<Func, usize>,
+}
+
+impl CallGraph {
+    /// Build the call graph of all the functions in the context.
+    pub fn new(ctx: &Context) -> Self {
+        let funcs = ctx.funcs().collect::<Vec<_>>();
+
+        let mut callees: HashMap<Func, Vec<Func>> = HashMap::new();
+        let mut callers: HashMap<Func, Vec<Func>> = HashMap::new();
+        let mut call_sites: HashMap<Func, Vec<Inst>> = HashMap::new();
+        for &func in funcs.iter() {
+            callees.entry(func).or_default();
+            callers.entry(func).or_default();
+            call_sites.entry(func).or_default();
+        }
+
+        for &caller in funcs.iter() {
+            for block in caller.iter(ctx) {
+                for inst in block.iter(ctx) {
+                    if !matches!(inst.kind(ctx), InstKind::Call) {
+                        continue;
+                    }
+                    let Some(callee) = ctx.func_by_name(inst.callee(ctx)) else {
+                        continue;
+                    };
+                    call_sites.get_mut(&callee).unwrap().push(inst);
+                    let caller_callees = callees.get_mut(&caller).unwrap();
+                    if !caller_callees.contains(&callee) {
+                        caller_callees.push(callee);
+                        callers.get_mut(&callee).unwrap().push(caller);
+                    }
+                }
+            }
+        }
+
+        let sccs = Self::compute_sccs(&funcs, &callees);
+        let scc_index = sccs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, scc)| scc.iter().map(move |&func| (func, i)))
+            .collect();
+
+        Self {
+            funcs,
+            callees,
+            callers,
+            call_sites,
+            sccs,
+            scc_index,
+        }
+    }
+
+    /// Compute the SCCs with an iterative version of Tarjan's algorithm.
+    ///
+    /// Tarjan's algorithm emits a component only after all the components
+    /// reachable from it, so the result is already bottom-up.
+    fn compute_sccs(funcs: &[Func], callees: &HashMap<Func, Vec<Func>>) -> Vec<Vec<Func>> {
+        let mut index: HashMap<Func, usize> = HashMap::new();
+        let mut lowlink: HashMap<Func, usize> = HashMap::new();
+        let mut on_stack = HashSet::new();
+        let mut stack = Vec::new();
+        let mut sccs = Vec::new();
+
+        for &root in funcs.iter() {
+            if index.contains_key(&root) {
+                continue;
+            }
+            // The functions being visited, with the index of the next callee.
+            let mut path = vec![(root, 0)];
+            index.insert(root, index.len());
+            lowlink.insert(root, index[&root]);
+            stack.push(root);
+            on_stack.insert(root);
+
+            while let Some((func, next)) = path.last_mut() {
+                let func = *func;
+                if let Some(&callee) = callees[&func].get(*next) {
+                    *next += 1;
+                    if !index.contains_key(&callee) {
+                        index.insert(callee, index.len());
+                        lowlink.insert(callee, index[&callee]);
+                        stack.push(callee);
+                        on_stack.insert(callee);
+                        path.push((callee, 0));
+                    } else if on_stack.contains(&callee) {
+                        let low = lowlink[&func].min(index[&callee]);
+                        lowlink.insert(func, low);
+                    }
+                    continue;
+                }
+
+                path.pop();
+                if let Some(&(parent, _)) = path.last() {
+                    let low = lowlink[&parent].min(lowlink[&func]);
+                    lowlink.insert(parent, low);
+                }
+                if lowlink[&func] == index[&func] {
+                    let mut scc = Vec::new();
+                    loop {
+                        let member = stack.pop().unwrap();
+                        on_stack.remove(&member);
+                        scc.push(member);
+                        if member == func {
+                            break;
+                        }
+                    }
+                    scc.reverse();
+                    sccs.push(scc);
+                }
+            }
+        }
+
+        sccs
+    }
+
+    /// Get all the functions in the module.
+    pub fn funcs(&self) -> &[Func] { &self.funcs }
+
+    /// Get the functions called by a function.
+    pub fn callees(&self, func: Func) -> &[Func] { &self.callees[&func] }
+
+    /// Get the functions calling a function.
+    pub fn callers(&self, func: Func) -> &[Func] { &self.callers[&func] }
+
+    /// Get the call instructions to a function.
+    pub fn call_sites(&self, func: Func) -> &[Inst] { &self.call_sites[&func] }
+
+    /// Get the SCCs in bottom-up order, i.e., callees before callers.
+    pub fn sccs(&self) -> &[Vec<Func>] { &self.sccs }
+
+    /// Get the index of the SCC of a function in [`CallGraph::sccs`].
+    pub fn scc_of(&self, func: Func) -> usize { self.scc_index[&func] }
+
+    /// Iterate over the functions in bottom-up order.
+    ///
+    /// Functions in the same SCC are visited in an unspecified order.
+    pub fn bottom_up(&self) -> impl Iterator<Item = Func> + '_ {
+        self.sccs.iter().flatten().copied()
+    }
+
+    /// Check if a function calls itself directly.
+    pub fn is_self_recursive(&self, func: Func) -> bool { self.callees[&func].contains(&func) }
+
+    /// Check if a function may call itself, directly or through other
+    /// functions.
+    pub fn is_recursive(&self, func: Func) -> bool {
+        self.sccs[self.scc_of(func)].len() > 1 || self.is_self_recursive(func)
+    }
+
+    /// Check if two functions are in the same recursive cycle, i.e., a call
+    /// between them may be recursive.
+    pub fn in_same_cycle(&self, a: Func, b: Func) -> bool {
+        self.scc_of(a) == self.scc_of(b) && (a != b || self.is_self_recursive(a))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Ty, Value};
+
+    #[test]
+    fn test_call_graph() {
+        // main -> even, leaf, putint; even <-> odd; fact -> fact
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let mut funcs = HashMap::new();
+        for name in ["main", "even", "odd", "fact", "leaf", "putint"] {
+            funcs.insert(name, Func::new(&mut ctx, name.to_string(), i32));
+        }
+
+        let define = |ctx: &mut Context, name: &str, callees: &[&str]| {
+            let func = funcs[name];
+            let block = Block::new(ctx);
+            func.push_back(ctx, block).unwrap();
+            for callee in callees {
+                let call = Inst::call(ctx, funcs[callee], vec![]);
+                block.push_back(ctx, call).unwrap();
+            }
+            let zero = Value::i32(ctx, 0);
+            let ret = Inst::ret(ctx, Some(zero));
+            block.push_back(ctx, ret).unwrap();
+            func
+        };
+
+        let main = define(&mut ctx, "main", &["even", "leaf", "putint", "leaf"]);
+        let even = define(&mut ctx, "even", &["odd"]);
+        let odd = define(&mut ctx, "odd", &["even"]);
+        let fact = define(&mut ctx, "fact", &["fact"]);
+        let leaf = define(&mut ctx, "leaf", &[]);
+        let putint = funcs["putint"];
+
+        let cg = CallGraph::new(&ctx);
+        assert_eq!(cg.callees(main), &[even, leaf, putint]);
+        assert_eq!(cg.call_sites(leaf).len(), 2);
+        assert_eq!(cg.callers(even), &[main, odd]);
+
+        assert!(cg.is_recursive(even) && cg.is_recursive(odd));
+        assert!(!cg.is_self_recursive(even));
+        assert!(cg.is_recursive(fact) && cg.is_self_recursive(fact));
+        assert!(!cg.is_recursive(main) && !cg.is_recursive(leaf));
+        assert!(cg.in_same_cycle(even, odd));
+        assert!(!cg.in_same_cycle(main, even) && !cg.in_same_cycle(leaf, leaf));
+
+        assert_eq!(cg.sccs().len(), 5);
+        assert_eq!(cg.sccs()[cg.scc_of(even)].len(), 2);
+        assert!(cg.scc_of(even) < cg.scc_of(main));
+        assert!(cg.scc_of(leaf) < cg.scc_of(main));
+        assert!(cg.scc_of(putint) < cg.scc_of(main));
+        assert_eq!(cg.bottom_up().count(), 6);
+    }
+}
@@ -0,0 +1,201 @@
+//! Control flow graph of a function.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::{Block, Context, Func};
+
+/// The control flow graph of a function.
+///
+/// The successors, predecessors and the traversal orders are computed once
+/// when the CFG is built, and can be queried cheaply afterwards. The CFG must
+/// be rebuilt after the function is modified.
+///
+/// The edges are deduplicated, e.g., a conditional branch to the same block in
+/// both directions only creates one edge.
+pub struct Cfg {
+    /// The entry block, `None` for function declarations.
+    entry: Option<Block>,
+    /// All the blocks in the layout order.
+    blocks: Vec<Block>,
+    succs: HashMap<Block, Vec<Block>>,
+    preds: HashMap<Block, Vec<Block>>,
+    /// The postorder of the reachable blocks.
+    postorder: Vec<Block>,
+    /// The index of each reachable block in the reverse postorder.
+    rpo_index: HashMap<Block, usize>,
+}
+
+impl Cfg {
+    /// Build the CFG of the function.
+    ///
+    /// The successors of a block are the successors of its last instruction.
+    pub fn new(ctx: &Context, func: Func) -> Self {
+        let blocks = func.iter(ctx).collect::<Vec<_>>();
+
+        let mut succs: HashMap<Block, Vec<Block>> = HashMap::new();
+        let mut preds: HashMap<Block, Vec<Block>> = HashMap::new();
+        for &block in blocks.iter() {
+            succs.entry(block).or_default();
+            preds.entry(block).or_default();
+        }
+
+        for &block in blocks.iter() {
+            let Some(tail) = block.tail(ctx) else {
+                continue;
+            };
+            for succ in tail.successor_iter(ctx) {
+                let block_succs = succs.get_mut(&block).unwrap();
+                if block_succs.contains(&succ) {
+                    continue;
+                }
+                block_succs.push(succ);
+                preds.entry(succ).or_default().push(block);
+            }
+        }
+
+        let entry = blocks.first().copied();
+        let postorder = match entry {
+            Some(entry) => Self::compute_postorder(entry, &succs),
+            None => Vec::new(),
+        };
+        let rpo_index = postorder
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, &block)| (block, i))
+            .collect();
+
+        Self {
+            entry,
+            blocks,
+            succs,
+            preds,
+            postorder,
+            rpo_index,
+        }
+    }
+
+    /// Compute the postorder with an iterative depth-first search.
+    fn compute_postorder(entry: Block, succs: &HashMap<Block, Vec<Block>>) -> Vec<Block> {
+        let mut postorder = Vec::new();
+        let mut visited = HashSet::new();
+        // The stack of (block, index of the next successor to visit).
+        let mut stack = vec![(entry, 0)];
+        visited.insert(entry);
+
+        while let Some((block, idx)) = stack.last_mut() {
+            let block_succs = succs.get(block).map(Vec::as_slice).unwrap_or_default();
+            if let Some(&succ) = block_succs.get(*idx) {
+                *idx += 1;
+                if visited.insert(succ) {
+                    stack.push((succ, 0));
+                }
+            } else {
+                postorder.push(*block);
+                stack.pop();
+            }
+        }
+
+        postorder
+    }
+
+    /// Get the entry block of the function.
+    pub fn entry(&self) -> Option<Block> { self.entry }
+
+    /// Get all the blocks in the layout order, including unreachable ones.
+    pub fn blocks(&self) -> &[Block] { &self.blocks }
+
+    /// Get the successors of a block.
+    pub fn succs(&self, block: Block) -> &[Block] {
+        self.succs.get(&block).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Get the predecessors of a block.
+    ///
+    /// Unreachable blocks are also counted as predecessors.
+    pub fn preds(&self, block: Block) -> &[Block] {
+        self.preds.get(&block).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Check if the block is reachable from the entry.
+    pub fn is_reachable(&self, block: Block) -> bool { self.rpo_index.contains_key(&block) }
+
+    /// Get the reachable blocks in postorder.
+    pub fn postorder(&self) -> &[Block] { &self.postorder }
+
+    /// Iterate over the reachable blocks in reverse postorder.
+    pub fn rpo(&self) -> impl DoubleEndedIterator<Item = Block> + '_ {
+        self.postorder.iter().rev().copied()
+    }
+
+    /// Get the index of the block in the reverse postorder.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(index)`: The index, the entry block has index 0.
+    /// - `None`: The block is unreachable.
+    pub fn rpo_index(&self, block: Block) -> Option<usize> { self.rpo_index.get(&block).copied() }
+
+    /// Check if the edge `from -> to` is a back edge in the depth-first
+    /// spanning tree, i.e., `to` is visited no later than `from` in reverse
+    /// postorder.
+    pub fn is_retreating_edge(&self, from: Block, to: Block) -> bool {
+        match (self.rpo_index(from), self.rpo_index(to)) {
+            (Some(from), Some(to)) => to <= from,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Inst, Ty, Value};
+
+    #[test]
+    fn test_cfg() {
+        // entry -> a, b; a -> c; b -> c; c -> a; d (unreachable) -> c
+        let mut ctx = Context::default();
+        let void = Ty::void(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), void);
+        let blocks = (0..5).map(|_| Block::new(&mut ctx)).collect::<Vec<_>>();
+        for &block in blocks.iter() {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [entry, a, b, c, d] = blocks[..] else {
+            unreachable!()
+        };
+
+        let cond = Value::i1(&mut ctx, true);
+        let br = Inst::cond_br(&mut ctx, cond, a, b);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, c);
+        a.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, c);
+        b.push_back(&mut ctx, br).unwrap();
+        let cond = Value::i1(&mut ctx, false);
+        let br = Inst::cond_br(&mut ctx, cond, a, a);
+        c.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, c);
+        d.push_back(&mut ctx, br).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        assert_eq!(cfg.entry(), Some(entry));
+        assert_eq!(cfg.succs(entry), &[a, b]);
+        assert_eq!(cfg.succs(c), &[a]);
+        assert_eq!(cfg.preds(c), &[a, b, d]);
+        assert_eq!(cfg.preds(a), &[entry, c]);
+
+        assert!(!cfg.is_reachable(d));
+        assert_eq!(cfg.postorder().len(), 4);
+        let rpo = cfg.rpo().collect::<Vec<_>>();
+        assert_eq!(rpo[0], entry);
+        // every forward edge goes forward in rpo
+        assert!(cfg.rpo_index(a) < cfg.rpo_index(c));
+        assert!(cfg.rpo_index(b) < cfg.rpo_index(c));
+        assert!(cfg.is_retreating_edge(c, a));
+        assert!(!cfg.is_retreating_edge(a, c));
+        assert_eq!(cfg.rpo_index(d), None);
+    }
+}
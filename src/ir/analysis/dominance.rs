@@ -0,0 +1,437 @@
+//! Dominator and post-dominator trees of a function.
+//!
+//! The immediate dominators are computed with the iterative algorithm from
+//! Cooper, Harvey and Kennedy, "A Simple, Fast Dominance Algorithm". The
+//! post-dominators are the dominators of the reversed CFG, rooted at a virtual
+//! exit succeeding all the returns.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use super::Cfg;
+use crate::ir::Block;
+
+/// The dominator tree of the reachable blocks of a function.
+pub struct DomTree {
+    /// The root of the tree, i.e., the entry block.
+    entry: Option<Block>,
+    /// The immediate dominator of each reachable block except the entry.
+    idoms: HashMap<Block, Block>,
+    /// The children of each block in the tree, in reverse postorder.
+    children: HashMap<Block, Vec<Block>>,
+    /// The preorder number of each block in the tree, and the largest preorder
+    /// number in its subtree, used to answer dominance queries in constant
+    /// time.
+    numbers: HashMap<Block, (usize, usize)>,
+}
+
+impl DomTree {
+    /// Compute the dominator tree from the CFG.
+    pub fn new(cfg: &Cfg) -> Self {
+        let rpo = cfg.rpo().collect::<Vec<_>>();
+        let Some(entry) = cfg.entry() else {
+            return Self {
+                entry: None,
+                idoms: HashMap::new(),
+                children: HashMap::new(),
+                numbers: HashMap::new(),
+            };
+        };
+
+        let idoms = immediate_dominators(&rpo, |block| cfg.preds(block).to_vec());
+        let mut children: HashMap<Block, Vec<Block>> = HashMap::new();
+        for &block in rpo.iter().skip(1) {
+            children.entry(idoms[&block]).or_default().push(block);
+        }
+        let numbers = number_subtrees(entry, &children);
+
+        Self {
+            entry: Some(entry),
+            idoms,
+            children,
+            numbers,
+        }
+    }
+
+    /// Get the root of the tree, i.e., the entry block.
+    pub fn root(&self) -> Option<Block> { self.entry }
+
+    /// Get the immediate dominator of a block.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(idom)`: The immediate dominator.
+    /// - `None`: The block is the entry, or unreachable.
+    pub fn idom(&self, block: Block) -> Option<Block> { self.idoms.get(&block).copied() }
+
+    /// Get the children of a block in the dominator tree.
+    pub fn children(&self, block: Block) -> &[Block] {
+        self.children
+            .get(&block)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Check if `a` dominates `b`. A block dominates itself.
+    ///
+    /// Unreachable blocks neither dominate nor are dominated by any block.
+    pub fn dominates(&self, a: Block, b: Block) -> bool {
+        match (self.numbers.get(&a), self.numbers.get(&b)) {
+            // The subtree of `a` is numbered from `a.0` to `a.1` in preorder.
+            (Some(a), Some(b)) => a.0 <= b.0 && b.0 <= a.1,
+            _ => false,
+        }
+    }
+
+    /// Check if `a` strictly dominates `b`.
+    pub fn strictly_dominates(&self, a: Block, b: Block) -> bool { a != b && self.dominates(a, b) }
+
+    /// Iterate over the blocks in the tree in preorder.
+    pub fn preorder(&self) -> impl Iterator<Item = Block> + '_ {
+        let mut stack = self.entry.into_iter().collect::<Vec<_>>();
+        std::iter::from_fn(move || {
+            let block = stack.pop()?;
+            stack.extend(self.children(block).iter().rev().copied());
+            Some(block)
+        })
+    }
+
+    /// Compute the dominance frontier of each reachable block.
+    pub fn frontiers(&self, cfg: &Cfg) -> HashMap<Block, Vec<Block>> {
+        let mut frontiers: HashMap<Block, Vec<Block>> = HashMap::new();
+        for block in cfg.rpo() {
+            let preds = cfg.preds(block);
+            if preds.len() < 2 {
+                continue;
+            }
+            let idom = self.idom(block);
+            for &pred in preds {
+                if !cfg.is_reachable(pred) {
+                    continue;
+                }
+                let mut runner = Some(pred);
+                while let Some(r) = runner {
+                    if Some(r) == idom {
+                        break;
+                    }
+                    let frontier = frontiers.entry(r).or_default();
+                    if !frontier.contains(&block) {
+                        frontier.push(block);
+                    }
+                    runner = self.idom(r);
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+/// Compute the immediate dominators in a graph, except for the root.
+///
+/// The nodes are given in the reverse postorder from the root, which comes
+/// first, with their predecessors. The predecessors not in the order are
+/// ignored.
+fn immediate_dominators<N, F>(rpo: &[N], preds: F) -> HashMap<N, N>
+where
+    N: Copy + Eq + Hash,
+    F: Fn(N) -> Vec<N>,
+{
+    let mut idoms: HashMap<N, N> = HashMap::new();
+    let Some(&root) = rpo.first() else {
+        return idoms;
+    };
+    let index = rpo
+        .iter()
+        .enumerate()
+        .map(|(i, &node)| (node, i))
+        .collect::<HashMap<_, _>>();
+
+    let intersect = |idoms: &HashMap<N, N>, mut a: N, mut b: N| {
+        while a != b {
+            while index[&a] > index[&b] {
+                a = idoms[&a];
+            }
+            while index[&b] > index[&a] {
+                b = idoms[&b];
+            }
+        }
+        a
+    };
+
+    // The root is its own dominator during the iteration.
+    idoms.insert(root, root);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in rpo.iter().skip(1) {
+            let mut new_idom = None;
+            for pred in preds(node) {
+                if !idoms.contains_key(&pred) {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    Some(idom) => intersect(&idoms, pred, idom),
+                    None => pred,
+                });
+            }
+            let new_idom = new_idom.unwrap();
+            if idoms.get(&node) != Some(&new_idom) {
+                idoms.insert(node, new_idom);
+                changed = true;
+            }
+        }
+    }
+    idoms.remove(&root);
+    idoms
+}
+
+/// Number the nodes of a tree in preorder, with the largest number in the
+/// subtree of each node, to answer the ancestor queries in constant time.
+fn number_subtrees<N>(root: N, children: &HashMap<N, Vec<N>>) -> HashMap<N, (usize, usize)>
+where
+    N: Copy + Eq + Hash,
+{
+    let mut numbers = HashMap::new();
+    let mut counter = 0;
+    // The stack of (node, index of the next child to visit).
+    let mut stack = vec![(root, 0)];
+    let mut pre = HashMap::new();
+    pre.insert(root, 0);
+    while let Some((node, idx)) = stack.last_mut() {
+        let node_children = children.get(node).map(Vec::as_slice).unwrap_or_default();
+        if let Some(&child) = node_children.get(*idx) {
+            *idx += 1;
+            counter += 1;
+            pre.insert(child, counter);
+            stack.push((child, 0));
+        } else {
+            numbers.insert(*node, (pre[node], counter));
+            stack.pop();
+        }
+    }
+    numbers
+}
+
+/// The post-dominator tree of the blocks of a function reaching a return.
+///
+/// The root of the tree is a virtual exit, so a function returning from
+/// several blocks has several top-level blocks, see [`PostDomTree::roots`].
+/// The unreachable blocks and the ones never returning, e.g., in an infinite
+/// loop, are not in the tree.
+pub struct PostDomTree {
+    /// The blocks immediately post-dominated by the virtual exit.
+    roots: Vec<Block>,
+    /// The immediate post-dominator of each block in the tree, except the
+    /// roots.
+    ipdoms: HashMap<Block, Block>,
+    /// The children of each block in the tree.
+    children: HashMap<Block, Vec<Block>>,
+    /// The preorder numbers in the tree, with `None` for the virtual exit.
+    numbers: HashMap<Option<Block>, (usize, usize)>,
+}
+
+impl PostDomTree {
+    /// Compute the post-dominator tree from the CFG.
+    pub fn new(cfg: &Cfg) -> Self {
+        // The nodes of the reversed CFG, `None` for the virtual exit.
+        let exits = cfg
+            .blocks()
+            .iter()
+            .copied()
+            .filter(|&block| cfg.is_reachable(block) && cfg.succs(block).is_empty())
+            .collect::<Vec<_>>();
+        let succs = |node: Option<Block>| match node {
+            None => exits.iter().copied().map(Some).collect::<Vec<_>>(),
+            Some(block) => cfg
+                .preds(block)
+                .iter()
+                .copied()
+                .filter(|&pred| cfg.is_reachable(pred))
+                .map(Some)
+                .collect(),
+        };
+        let preds = |node: Option<Block>| match node {
+            None => Vec::new(),
+            Some(block) if cfg.succs(block).is_empty() => vec![None],
+            Some(block) => cfg.succs(block).iter().copied().map(Some).collect(),
+        };
+
+        let mut postorder = Vec::new();
+        let mut visited = HashSet::from([None]);
+        let mut stack = vec![(None, succs(None), 0)];
+        while let Some((node, node_succs, idx)) = stack.last_mut() {
+            if let Some(&succ) = node_succs.get(*idx) {
+                *idx += 1;
+                if visited.insert(succ) {
+                    stack.push((succ, succs(succ), 0));
+                }
+            } else {
+                postorder.push(*node);
+                stack.pop();
+            }
+        }
+        let rpo = postorder.into_iter().rev().collect::<Vec<_>>();
+
+        let idoms = immediate_dominators(&rpo, preds);
+        let mut roots = Vec::new();
+        let mut ipdoms = HashMap::new();
+        let mut children: HashMap<Option<Block>, Vec<Option<Block>>> = HashMap::new();
+        for &node in rpo.iter().skip(1) {
+            let block = node.unwrap();
+            match idoms[&node] {
+                None => roots.push(block),
+                Some(ipdom) => {
+                    ipdoms.insert(block, ipdom);
+                }
+            }
+            children.entry(idoms[&node]).or_default().push(node);
+        }
+        let numbers = number_subtrees(None, &children);
+        let children = children
+            .into_iter()
+            .filter_map(|(parent, nodes)| Some((parent?, nodes.into_iter().flatten().collect())))
+            .collect();
+
+        Self {
+            roots,
+            ipdoms,
+            children,
+            numbers,
+        }
+    }
+
+    /// Get the blocks immediately post-dominated by the virtual exit, i.e.,
+    /// the returning blocks not post-dominated by another one.
+    pub fn roots(&self) -> &[Block] { &self.roots }
+
+    /// Get the immediate post-dominator of a block.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(ipdom)`: The immediate post-dominator.
+    /// - `None`: The block is a root, or not in the tree.
+    pub fn ipdom(&self, block: Block) -> Option<Block> { self.ipdoms.get(&block).copied() }
+
+    /// Get the children of a block in the post-dominator tree.
+    pub fn children(&self, block: Block) -> &[Block] {
+        self.children
+            .get(&block)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Check if `a` post-dominates `b`, i.e., every path from `b` to a return
+    /// goes through `a`. A block post-dominates itself.
+    pub fn post_dominates(&self, a: Block, b: Block) -> bool {
+        match (self.numbers.get(&Some(a)), self.numbers.get(&Some(b))) {
+            (Some(a), Some(b)) => a.0 <= b.0 && b.0 <= a.1,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::linked_list::LinkedListContainer;
+    use crate::ir::{Context, Func, Inst, Ty, Value};
+
+    #[test]
+    fn test_domtree() {
+        // entry -> a, b; a -> c; b -> c; c -> a, exit
+        let mut ctx = Context::default();
+        let void = Ty::void(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), void);
+        let blocks = (0..5).map(|_| Block::new(&mut ctx)).collect::<Vec<_>>();
+        for &block in blocks.iter() {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [entry, a, b, c, exit] = blocks[..] else {
+            unreachable!()
+        };
+
+        let cond = Value::i1(&mut ctx, true);
+        let br = Inst::cond_br(&mut ctx, cond, a, b);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, c);
+        a.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, c);
+        b.push_back(&mut ctx, br).unwrap();
+        let br = Inst::cond_br(&mut ctx, cond, a, exit);
+        c.push_back(&mut ctx, br).unwrap();
+        let ret = Inst::ret(&mut ctx, None);
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        let domtree = DomTree::new(&cfg);
+
+        assert_eq!(domtree.root(), Some(entry));
+        assert_eq!(domtree.idom(entry), None);
+        assert_eq!(domtree.idom(a), Some(entry));
+        assert_eq!(domtree.idom(b), Some(entry));
+        assert_eq!(domtree.idom(c), Some(entry));
+        assert_eq!(domtree.idom(exit), Some(c));
+
+        assert!(domtree.dominates(entry, exit));
+        assert!(domtree.dominates(c, exit));
+        assert!(domtree.dominates(a, a));
+        assert!(!domtree.strictly_dominates(a, a));
+        assert!(!domtree.dominates(a, c));
+        assert_eq!(domtree.preorder().count(), 5);
+
+        let frontiers = domtree.frontiers(&cfg);
+        assert_eq!(frontiers[&a], vec![c]);
+        assert_eq!(frontiers[&b], vec![c]);
+        assert_eq!(frontiers[&c], vec![a]);
+        assert!(!frontiers.contains_key(&exit));
+    }
+
+    #[test]
+    fn test_postdomtree() {
+        // entry -> a, b; a -> c, loop; b -> c, ret; c -> exit; loop -> loop
+        let mut ctx = Context::default();
+        let void = Ty::void(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), void);
+        let blocks = (0..6).map(|_| Block::new(&mut ctx)).collect::<Vec<_>>();
+        for &block in blocks.iter() {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [entry, a, b, c, exit, lp] = blocks[..] else {
+            unreachable!()
+        };
+
+        let cond = Value::i1(&mut ctx, true);
+        let br = Inst::cond_br(&mut ctx, cond, a, b);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::cond_br(&mut ctx, cond, c, lp);
+        a.push_back(&mut ctx, br).unwrap();
+        let br = Inst::cond_br(&mut ctx, cond, c, exit);
+        b.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, exit);
+        c.push_back(&mut ctx, br).unwrap();
+        let ret = Inst::ret(&mut ctx, None);
+        exit.push_back(&mut ctx, ret).unwrap();
+        let br = Inst::br(&mut ctx, lp);
+        lp.push_back(&mut ctx, br).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        let postdomtree = PostDomTree::new(&cfg);
+        assert_eq!(postdomtree.roots(), [exit]);
+        assert_eq!(postdomtree.ipdom(exit), None);
+        assert_eq!(postdomtree.ipdom(c), Some(exit));
+        assert_eq!(postdomtree.ipdom(b), Some(exit));
+        // the paths through the infinite loop never return
+        assert_eq!(postdomtree.ipdom(a), Some(c));
+        assert_eq!(postdomtree.ipdom(entry), Some(exit));
+        assert_eq!(postdomtree.ipdom(lp), None);
+        assert!(postdomtree.post_dominates(exit, entry));
+        assert!(postdomtree.post_dominates(c, a));
+        assert!(!postdomtree.post_dominates(c, entry));
+        assert!(!postdomtree.post_dominates(lp, lp));
+        let mut children = postdomtree.children(exit).to_vec();
+        children.sort();
+        let mut expected = vec![entry, b, c];
+        expected.sort();
+        assert_eq!(children, expected);
+    }
+}
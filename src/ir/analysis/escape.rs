@@ -0,0 +1,185 @@
+//! Escape analysis of allocas.
+//!
+//! The address of an alloca escapes if it, or a pointer derived from it with
+//! `getelementptr`, may be observed outside of the direct loads and stores in
+//! the function, i.e., it is:
+//!
+//! - passed to a call;
+//! - stored to memory as a value;
+//! - returned;
+//! - merged by a phi or passed as a block argument, which is not tracked.
+//!
+//! Memory of a non-escaping alloca can only be accessed by the loads and
+//! stores in the function, so it is not touched by calls.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::{Context, Func, Inst, InstKind, IntBinaryOp, Usable, Value};
+
+/// The escape information of the allocas in a function.
+pub struct EscapeInfo {
+    /// Whether the address of each alloca escapes.
+    allocas: HashMap<Inst, bool>,
+    /// The non-escaping scalar allocas only accessed by direct loads and
+    /// stores.
+    promotable: HashSet<Inst>,
+}
+
+impl EscapeInfo {
+    /// Analyze all the allocas in a function.
+    pub fn new(ctx: &Context, func: Func) -> Self {
+        let mut info = Self {
+            allocas: HashMap::new(),
+            promotable: HashSet::new(),
+        };
+
+        for block in func.iter(ctx) {
+            for inst in block.iter(ctx) {
+                let InstKind::Alloca { ty } = *inst.kind(ctx) else {
+                    continue;
+                };
+                let ptr = inst.result(ctx).unwrap();
+                let escapes = Self::escapes_from(ctx, ptr);
+                info.allocas.insert(inst, escapes);
+
+                let is_scalar = ty.as_array(ctx).is_none();
+                if !escapes && is_scalar && Self::only_direct_access(ctx, ptr) {
+                    info.promotable.insert(inst);
+                }
+            }
+        }
+
+        info
+    }
+
+    /// Check if the address of a pointer may escape through its users.
+    fn escapes_from(ctx: &Context, ptr: Value) -> bool {
+        let mut worklist = vec![ptr];
+        let mut visited = HashSet::new();
+        while let Some(ptr) = worklist.pop() {
+            if !visited.insert(ptr) {
+                continue;
+            }
+            for user in ptr.users(ctx) {
+                let inst = user.inst();
+                match inst.kind(ctx) {
+                    InstKind::Load => {}
+                    // Storing to the pointer is fine, storing the pointer
+                    // itself is not.
+                    InstKind::Store if user.idx() == 1 => {}
+                    InstKind::GetElementPtr { .. } if user.idx() == 0 => {
+                        worklist.push(inst.result(ctx).unwrap());
+                    }
+                    // Comparing the address does not leak it.
+                    InstKind::IntBinary {
+                        op: IntBinaryOp::ICmp { .. },
+                    } => {}
+                    _ => return true,
+                }
+            }
+        }
+        false
+    }
+
+    /// Check if the pointer is only used as the address of loads and stores.
+    fn only_direct_access(ctx: &Context, ptr: Value) -> bool {
+        ptr.users(ctx).into_iter().all(|user| {
+            let inst = user.inst();
+            match inst.kind(ctx) {
+                InstKind::Load => true,
+                InstKind::Store => user.idx() == 1,
+                _ => false,
+            }
+        })
+    }
+
+    /// Check if the address of the alloca may escape.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the instruction is not an alloca in the analyzed function.
+    pub fn escapes(&self, alloca: Inst) -> bool { self.allocas[&alloca] }
+
+    /// Check if the alloca can be promoted to SSA values, i.e., it does not
+    /// escape, is not an array, and is only accessed by direct loads and
+    /// stores.
+    pub fn is_promotable(&self, alloca: Inst) -> bool { self.promotable.contains(&alloca) }
+
+    /// Iterate over the allocas that can be promoted to SSA values.
+    pub fn promotable(&self) -> impl Iterator<Item = Inst> + '_ { self.promotable.iter().copied() }
+
+    /// Iterate over all the allocas in the function.
+    pub fn allocas(&self) -> impl Iterator<Item = Inst> + '_ { self.allocas.keys().copied() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Ty};
+
+    #[test]
+    fn test_escape() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let arr = Ty::array(&mut ctx, i32, 4);
+        let void = Ty::void(&mut ctx);
+        let callee = Func::new(&mut ctx, "use".to_string(), void);
+        let ptr = Ty::ptr(&mut ctx);
+        callee.add_param(&mut ctx, ptr);
+
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let block = Block::new(&mut ctx);
+        func.push_back(&mut ctx, block).unwrap();
+        let push = |ctx: &mut Context, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+
+        // a scalar only loaded and stored
+        let scalar = Inst::alloca(&mut ctx, i32);
+        let scalar_ptr = push(&mut ctx, scalar).unwrap();
+        // an array only accessed through getelementptr
+        let local_arr = Inst::alloca(&mut ctx, arr);
+        let local_arr_ptr = push(&mut ctx, local_arr).unwrap();
+        // an array passed to a call through getelementptr
+        let passed = Inst::alloca(&mut ctx, arr);
+        let passed_ptr = push(&mut ctx, passed).unwrap();
+        // a scalar whose address is stored
+        let stored = Inst::alloca(&mut ctx, i32);
+        let stored_ptr = push(&mut ctx, stored).unwrap();
+        let slot = Inst::alloca(&mut ctx, ptr);
+        let slot_ptr = push(&mut ctx, slot).unwrap();
+
+        let zero = Value::i32(&mut ctx, 0);
+        let store = Inst::store(&mut ctx, zero, scalar_ptr);
+        push(&mut ctx, store);
+        let load = Inst::load(&mut ctx, scalar_ptr, i32);
+        let value = push(&mut ctx, load).unwrap();
+
+        let gep = Inst::getelementptr(&mut ctx, arr, local_arr_ptr, vec![zero, zero]);
+        let elem = push(&mut ctx, gep).unwrap();
+        let store = Inst::store(&mut ctx, value, elem);
+        push(&mut ctx, store);
+
+        let gep = Inst::getelementptr(&mut ctx, arr, passed_ptr, vec![zero, zero]);
+        let elem = push(&mut ctx, gep).unwrap();
+        let call = Inst::call(&mut ctx, callee, vec![elem]);
+        push(&mut ctx, call);
+
+        let store = Inst::store(&mut ctx, stored_ptr, slot_ptr);
+        push(&mut ctx, store);
+
+        let ret = Inst::ret(&mut ctx, Some(value));
+        push(&mut ctx, ret);
+
+        let info = EscapeInfo::new(&ctx, func);
+        assert_eq!(info.allocas().count(), 5);
+        assert!(!info.escapes(scalar) && info.is_promotable(scalar));
+        assert!(!info.escapes(local_arr) && !info.is_promotable(local_arr));
+        assert!(info.escapes(passed));
+        assert!(info.escapes(stored) && !info.is_promotable(stored));
+        assert!(!info.escapes(slot) && info.is_promotable(slot));
+        assert_eq!(info.promotable().count(), 2);
+    }
+}
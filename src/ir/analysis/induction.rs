@@ -0,0 +1,367 @@
+//! Induction variable analysis.
+//!
+//! A basic induction variable is a phi in the loop header that starts with a
+//! loop-invariant value, and is incremented by a constant step on each back
+//! edge:
+//!
+//! ```text
+//! header: i = phi [start, preheader], [next, latch]
+//!         ...
+//!         next = add i, step
+//! ```
+//!
+//! A derived induction variable is an affine function `scale * i + offset` of
+//! a basic induction variable `i`, computed in the loop with `add`, `sub`,
+//! `mul` and `shl` by constants.
+
+use std::collections::HashMap;
+
+use super::{Cfg, Loop, LoopInfo};
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::{Context, Inst, InstKind, IntBinaryOp, Value};
+
+/// A basic induction variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasicIv {
+    /// The phi node in the header.
+    pub phi: Inst,
+    /// The value of the variable, i.e., the result of the phi.
+    pub value: Value,
+    /// The loop-invariant initial value.
+    pub start: Value,
+    /// The value in the next iteration, i.e., `value + step`.
+    pub next: Value,
+    /// The constant step.
+    pub step: i32,
+}
+
+/// A derived induction variable, `scale * base + offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DerivedIv {
+    /// The value of the variable.
+    pub value: Value,
+    /// The value of the basic induction variable it is derived from.
+    pub base: Value,
+    /// The constant factor.
+    pub scale: i32,
+    /// The constant offset.
+    pub offset: i32,
+}
+
+impl DerivedIv {
+    /// Get the step of the derived variable per iteration.
+    pub fn step(&self, base: &BasicIv) -> Option<i32> { self.scale.checked_mul(base.step) }
+}
+
+/// The induction variables of all the loops in a function.
+pub struct InductionVars {
+    basic: HashMap<Loop, Vec<BasicIv>>,
+    derived: HashMap<Loop, Vec<DerivedIv>>,
+    /// The loop and the index of each basic induction variable, by value.
+    basic_by_value: HashMap<Value, (Loop, usize)>,
+    /// The affine form of each derived induction variable, by value.
+    derived_by_value: HashMap<Value, DerivedIv>,
+}
+
+impl InductionVars {
+    /// Detect the induction variables of all the loops.
+    pub fn new(ctx: &Context, cfg: &Cfg, loop_info: &LoopInfo) -> Self {
+        let mut ivs = Self {
+            basic: HashMap::new(),
+            derived: HashMap::new(),
+            basic_by_value: HashMap::new(),
+            derived_by_value: HashMap::new(),
+        };
+        for lp in loop_info.loops() {
+            let basic = detect_basic(ctx, cfg, loop_info, lp);
+            for (i, iv) in basic.iter().enumerate() {
+                ivs.basic_by_value.insert(iv.value, (lp, i));
+            }
+            ivs.basic.insert(lp, basic);
+        }
+        for lp in loop_info.loops() {
+            ivs.detect_derived(ctx, cfg, loop_info, lp);
+        }
+        ivs
+    }
+
+    /// Get the basic induction variables of a loop.
+    pub fn basic_ivs(&self, lp: Loop) -> &[BasicIv] {
+        self.basic.get(&lp).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Get the derived induction variables of a loop, in reverse postorder of
+    /// their definitions.
+    pub fn derived_ivs(&self, lp: Loop) -> &[DerivedIv] {
+        self.derived.get(&lp).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Get the basic induction variable with the given value.
+    pub fn basic_iv(&self, value: Value) -> Option<&BasicIv> {
+        let (lp, idx) = self.basic_by_value.get(&value)?;
+        Some(&self.basic[lp][*idx])
+    }
+
+    /// Get the affine form of a value in terms of a basic induction variable.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(iv)`: The value is an induction variable. A basic induction
+    ///   variable is returned with a scale of 1 and an offset of 0.
+    /// - `None`: The value is not an induction variable.
+    pub fn affine(&self, value: Value) -> Option<DerivedIv> {
+        if self.basic_by_value.contains_key(&value) {
+            return Some(DerivedIv {
+                value,
+                base: value,
+                scale: 1,
+                offset: 0,
+            });
+        }
+        self.derived_by_value.get(&value).copied()
+    }
+
+    fn detect_derived(&mut self, ctx: &Context, cfg: &Cfg, loop_info: &LoopInfo, lp: Loop) {
+        let mut derived = Vec::new();
+        // The blocks are in reverse postorder, so the operands defined in the
+        // loop are visited before their users, except through phis.
+        for &block in loop_info.blocks(lp) {
+            if !cfg.is_reachable(block) {
+                continue;
+            }
+            for inst in block.iter(ctx) {
+                let InstKind::IntBinary { op } = *inst.kind(ctx) else {
+                    continue;
+                };
+                let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+                let value = inst.result(ctx).unwrap();
+                // Skip the increments of basic induction variables.
+                if self.basic_ivs(lp).iter().any(|iv| iv.next == value) {
+                    continue;
+                }
+
+                let in_loop = |iv: &DerivedIv| {
+                    let (base_loop, _) = self.basic_by_value[&iv.base];
+                    base_loop == lp
+                };
+                let affine = |v: Value| {
+                    self.affine(v)
+                        .or_else(|| derived.iter().find(|iv: &&DerivedIv| iv.value == v).copied())
+                        .filter(in_loop)
+                };
+
+                let new = match op {
+                    IntBinaryOp::Add | IntBinaryOp::Sub | IntBinaryOp::Mul => {
+                        let (iv, c, iv_on_lhs) = match (affine(lhs), rhs.as_const_int(ctx)) {
+                            (Some(iv), Some(c)) => (iv, c, true),
+                            _ => match (lhs.as_const_int(ctx), affine(rhs)) {
+                                (Some(c), Some(iv)) => (iv, c, false),
+                                _ => continue,
+                            },
+                        };
+                        match op {
+                            IntBinaryOp::Add => {
+                                iv.offset.checked_add(c).map(|offset| (iv.scale, offset))
+                            }
+                            IntBinaryOp::Sub if iv_on_lhs => {
+                                iv.offset.checked_sub(c).map(|offset| (iv.scale, offset))
+                            }
+                            IntBinaryOp::Sub => c
+                                .checked_sub(iv.offset)
+                                .zip(iv.scale.checked_neg())
+                                .map(|(offset, scale)| (scale, offset)),
+                            _ => iv.scale.checked_mul(c).zip(iv.offset.checked_mul(c)),
+                        }
+                        .map(|(scale, offset)| DerivedIv {
+                            value,
+                            base: iv.base,
+                            scale,
+                            offset,
+                        })
+                    }
+                    IntBinaryOp::Shl => {
+                        let (Some(iv), Some(c)) = (affine(lhs), rhs.as_const_int(ctx)) else {
+                            continue;
+                        };
+                        let factor = 1i32.checked_shl(c as u32).filter(|_| (0..31).contains(&c));
+                        factor
+                            .and_then(|f| iv.scale.checked_mul(f).zip(iv.offset.checked_mul(f)))
+                            .map(|(scale, offset)| DerivedIv {
+                                value,
+                                base: iv.base,
+                                scale,
+                                offset,
+                            })
+                    }
+                    _ => None,
+                };
+                if let Some(iv) = new {
+                    derived.push(iv);
+                }
+            }
+        }
+
+        for iv in derived.iter() {
+            self.derived_by_value.insert(iv.value, *iv);
+        }
+        self.derived.insert(lp, derived);
+    }
+}
+
+/// Detect the basic induction variables of a loop.
+fn detect_basic(ctx: &Context, cfg: &Cfg, loop_info: &LoopInfo, lp: Loop) -> Vec<BasicIv> {
+    let header = loop_info.header(lp);
+    let mut ivs = Vec::new();
+
+    for phi in header.iter(ctx).take_while(|inst| inst.is_phi(ctx)) {
+        let value = phi.result(ctx).unwrap();
+        let mut start = None;
+        let mut next = None;
+        let mut valid = true;
+        for (pred, incoming) in phi.incoming_iter(ctx) {
+            if !cfg.is_reachable(pred) {
+                continue;
+            }
+            let slot = if loop_info.contains(lp, pred) {
+                &mut next
+            } else {
+                &mut start
+            };
+            match slot {
+                Some(v) if *v != incoming => valid = false,
+                _ => *slot = Some(incoming),
+            }
+        }
+
+        let (Some(start), Some(next)) = (start, next) else {
+            continue;
+        };
+        if !valid || !loop_info.is_invariant(ctx, lp, start) {
+            continue;
+        }
+        if let Some(step) = constant_step(ctx, value, next) {
+            ivs.push(BasicIv {
+                phi,
+                value,
+                start,
+                next,
+                step,
+            });
+        }
+    }
+
+    ivs
+}
+
+/// Get the constant step if `next` is `iv + c` or `iv - c`.
+fn constant_step(ctx: &Context, iv: Value, next: Value) -> Option<i32> {
+    let inst = next.def_inst(ctx)?;
+    let InstKind::IntBinary { op } = *inst.kind(ctx) else {
+        return None;
+    };
+    let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+    let step = match op {
+        IntBinaryOp::Add if lhs == iv => rhs.as_const_int(ctx)?,
+        IntBinaryOp::Add if rhs == iv => lhs.as_const_int(ctx)?,
+        IntBinaryOp::Sub if lhs == iv => rhs.as_const_int(ctx)?.checked_neg()?,
+        _ => return None,
+    };
+    (step != 0).then_some(step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::analysis::DomTree;
+    use crate::ir::{Block, Func, IntCmpCond, Ty};
+
+    #[test]
+    fn test_induction_vars() {
+        // entry:  br header
+        // header: i = phi [1, entry], [next, header]
+        //         a = mul i, 4
+        //         b = add a, 8
+        //         c = sub 0, b
+        //         d = shl i, 2
+        //         e = add i, n     (not affine in a constant)
+        //         next = add i, 2
+        //         cond = icmp slt next, n
+        //         br cond, header, exit
+        // exit:   ret i
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let n = func.add_param(&mut ctx, i32);
+        let entry = Block::new(&mut ctx);
+        let header = Block::new(&mut ctx);
+        let exit = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+        func.push_back(&mut ctx, header).unwrap();
+        func.push_back(&mut ctx, exit).unwrap();
+
+        let br = Inst::br(&mut ctx, header);
+        entry.push_back(&mut ctx, br).unwrap();
+
+        let phi = Inst::phi(&mut ctx, i32);
+        let i = phi.result(&ctx).unwrap();
+        header.push_back(&mut ctx, phi).unwrap();
+        let binary = |ctx: &mut Context, op, lhs, rhs| {
+            let inst = Inst::ibinary(ctx, op, lhs, rhs);
+            header.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+        let (zero, two, four, eight) = (
+            Value::i32(&mut ctx, 0),
+            Value::i32(&mut ctx, 2),
+            Value::i32(&mut ctx, 4),
+            Value::i32(&mut ctx, 8),
+        );
+        let a = binary(&mut ctx, IntBinaryOp::Mul, i, four);
+        let b = binary(&mut ctx, IntBinaryOp::Add, a, eight);
+        let c = binary(&mut ctx, IntBinaryOp::Sub, zero, b);
+        let d = binary(&mut ctx, IntBinaryOp::Shl, i, two);
+        let e = binary(&mut ctx, IntBinaryOp::Add, i, n);
+        let next = binary(&mut ctx, IntBinaryOp::Add, i, two);
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let cond = binary(&mut ctx, slt, next, n);
+        let cond_br = Inst::cond_br(&mut ctx, cond, header, exit);
+        header.push_back(&mut ctx, cond_br).unwrap();
+        let one = Value::i32(&mut ctx, 1);
+        phi.insert_incoming(&mut ctx, entry, one);
+        phi.insert_incoming(&mut ctx, header, next);
+        let ret = Inst::ret(&mut ctx, Some(i));
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        let domtree = DomTree::new(&cfg);
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+        let ivs = InductionVars::new(&ctx, &cfg, &loop_info);
+        let lp = loop_info.top_level()[0];
+
+        assert_eq!(
+            ivs.basic_ivs(lp),
+            &[BasicIv {
+                phi,
+                value: i,
+                start: one,
+                next,
+                step: 2,
+            }]
+        );
+
+        let affine = |v| ivs.affine(v).map(|iv| (iv.base, iv.scale, iv.offset));
+        assert_eq!(affine(i), Some((i, 1, 0)));
+        assert_eq!(affine(a), Some((i, 4, 0)));
+        assert_eq!(affine(b), Some((i, 4, 8)));
+        assert_eq!(affine(c), Some((i, -4, -8)));
+        assert_eq!(affine(d), Some((i, 4, 0)));
+        assert_eq!(affine(e), None);
+        assert_eq!(affine(next), None);
+        assert_eq!(ivs.derived_ivs(lp).len(), 4);
+
+        let basic = ivs.basic_iv(i).unwrap();
+        assert_eq!(ivs.affine(c).unwrap().step(basic), Some(-8));
+    }
+}
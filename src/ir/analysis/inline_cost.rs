@@ -0,0 +1,337 @@
+//! Cost model of functions for inlining and unrolling.
+//!
+//! The cost of an instruction approximates the number of machine instructions
+//! it expands to. The weights and thresholds live in [`CostModel`], so all the
+//! size-sensitive transformations share the same tunable heuristics.
+//!
+//! With a profile, the thresholds follow the [`Hotness`] of the code: the hot
+//! code is given more room to grow, and the code never run none.
+
+use std::collections::HashMap;
+
+use super::{Cfg, DomTree, Loop, LoopInfo};
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::{Context, FloatBinaryOp, Func, Inst, InstKind, IntBinaryOp};
+
+/// How often code runs in the profiled runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Hotness {
+    /// Never run.
+    Cold,
+    /// Neither hot nor cold, or not profiled.
+    #[default]
+    Normal,
+    /// Run at least [`CostModel::hot_count`] times.
+    Hot,
+}
+
+/// The weights of instructions and the thresholds of transformations.
+#[derive(Debug, Clone)]
+pub struct CostModel {
+    /// The cost of a load or a store.
+    pub memory: u32,
+    /// The cost of a multiplication.
+    pub mul: u32,
+    /// The cost of a division or a remainder.
+    pub div: u32,
+    /// The cost of a call, excluding its arguments.
+    pub call: u32,
+    /// The cost of each argument of a call.
+    pub call_arg: u32,
+    /// The cost of any other instruction that produces code.
+    pub basic: u32,
+    /// The extra cost of a function containing loops, since inlining it rarely
+    /// pays off compared with the time spent in the loops.
+    pub loop_penalty: u32,
+    /// The extra cost of a function with allocas, which grow the frame of the
+    /// caller.
+    pub alloca_penalty: u32,
+    /// The maximum cost of a callee to be inlined.
+    pub inline_threshold: u32,
+    /// The maximum cost of a loop body after unrolling.
+    pub unroll_threshold: u32,
+    /// The profiled executions from which code is hot.
+    pub hot_count: u64,
+    /// The maximum cost of a hot callee to be inlined.
+    pub hot_inline_threshold: u32,
+    /// The maximum cost of a hot loop body after unrolling.
+    pub hot_unroll_threshold: u32,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            memory: 2,
+            mul: 3,
+            div: 10,
+            call: 5,
+            call_arg: 1,
+            basic: 1,
+            loop_penalty: 20,
+            alloca_penalty: 5,
+            inline_threshold: 50,
+            unroll_threshold: 200,
+            hot_count: 1000,
+            hot_inline_threshold: 150,
+            hot_unroll_threshold: 800,
+        }
+    }
+}
+
+impl CostModel {
+    /// Get the cost of an instruction.
+    ///
+    /// Allocas and phis cost nothing, as they are resolved to frame slots and
+    /// moves that are mostly coalesced away.
+    pub fn inst_cost(&self, ctx: &Context, inst: Inst) -> u32 {
+        match inst.kind(ctx) {
+            InstKind::Alloca { .. } | InstKind::Phi => 0,
+            InstKind::Load | InstKind::Store => self.memory,
+            InstKind::Call => {
+                let args = inst.operand_iter(ctx).count() as u32 - 1;
+                self.call + self.call_arg * args
+            }
+            InstKind::IntBinary { op } => match op {
+                IntBinaryOp::Mul | IntBinaryOp::SMulHi => self.mul,
+                IntBinaryOp::SDiv | IntBinaryOp::UDiv | IntBinaryOp::SRem | IntBinaryOp::URem => {
+                    self.div
+                }
+                _ => self.basic,
+            },
+            InstKind::FloatBinary { op } => match op {
+                FloatBinaryOp::FMul => self.mul,
+                FloatBinaryOp::FDiv => self.div,
+                _ => self.basic,
+            },
+            InstKind::GetElementPtr { .. }
+            | InstKind::Cast { .. }
+            | InstKind::Br
+            | InstKind::CondBr
+            | InstKind::Switch { .. }
+            | InstKind::Ret => self.basic,
+        }
+    }
+
+    /// Get the cost of one iteration of a loop, including its inner loops.
+    pub fn loop_cost(&self, ctx: &Context, loop_info: &LoopInfo, lp: Loop) -> u32 {
+        loop_info
+            .blocks(lp)
+            .iter()
+            .flat_map(|block| block.iter(ctx))
+            .map(|inst| self.inst_cost(ctx, inst))
+            .sum()
+    }
+
+    /// Classify code by its estimated executions in the profiled runs, `None`
+    /// if not profiled.
+    pub fn hotness(&self, executions: Option<f64>) -> Hotness {
+        match executions {
+            Some(count) if count < 1.0 => Hotness::Cold,
+            Some(count) if count >= self.hot_count as f64 => Hotness::Hot,
+            _ => Hotness::Normal,
+        }
+    }
+
+    /// Get the maximum cost of a callee to be inlined.
+    pub fn inline_threshold(&self, hotness: Hotness) -> u32 {
+        match hotness {
+            Hotness::Cold => 0,
+            Hotness::Normal => self.inline_threshold,
+            Hotness::Hot => self.hot_inline_threshold,
+        }
+    }
+
+    /// Get the maximum cost of a loop body after unrolling.
+    pub fn unroll_threshold(&self, hotness: Hotness) -> u32 {
+        match hotness {
+            Hotness::Cold => 0,
+            Hotness::Normal => self.unroll_threshold,
+            Hotness::Hot => self.hot_unroll_threshold,
+        }
+    }
+
+    /// Check if a loop body is small enough to be unrolled by the factor.
+    pub fn can_unroll(
+        &self,
+        ctx: &Context,
+        loop_info: &LoopInfo,
+        lp: Loop,
+        factor: u32,
+        hotness: Hotness,
+    ) -> bool {
+        self.loop_cost(ctx, loop_info, lp)
+            .checked_mul(factor)
+            .is_some_and(|cost| cost <= self.unroll_threshold(hotness))
+    }
+}
+
+/// The cost summary of a function.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FuncCost {
+    /// The number of instructions.
+    pub size: u32,
+    /// The weighted cost of the instructions, including the penalties.
+    pub cost: u32,
+    /// The number of call instructions.
+    pub calls: u32,
+    /// Whether the function allocates stack memory.
+    pub has_allocas: bool,
+    /// Whether the function contains loops.
+    pub has_loops: bool,
+    /// How often the function is entered in the profiled runs.
+    pub hotness: Hotness,
+}
+
+/// The costs of all the defined functions in a module.
+pub struct InlineCosts {
+    model: CostModel,
+    costs: HashMap<Func, FuncCost>,
+}
+
+impl InlineCosts {
+    /// Compute the costs of all the defined functions in the context.
+    pub fn new(ctx: &Context, model: CostModel) -> Self {
+        let costs = ctx
+            .funcs()
+            .filter(|func| !func.is_declaration(ctx))
+            .map(|func| (func, Self::compute(ctx, &model, func)))
+            .collect();
+        Self { model, costs }
+    }
+
+    fn compute(ctx: &Context, model: &CostModel, func: Func) -> FuncCost {
+        let mut cost = FuncCost::default();
+        for block in func.iter(ctx) {
+            for inst in block.iter(ctx) {
+                cost.size += 1;
+                cost.cost += model.inst_cost(ctx, inst);
+                match inst.kind(ctx) {
+                    InstKind::Call => cost.calls += 1,
+                    InstKind::Alloca { .. } => cost.has_allocas = true,
+                    _ => {}
+                }
+            }
+        }
+
+        let cfg = Cfg::new(ctx, func);
+        let domtree = DomTree::new(&cfg);
+        cost.has_loops = LoopInfo::new(&cfg, &domtree).loops().next().is_some();
+
+        if cost.has_loops {
+            cost.cost += model.loop_penalty;
+        }
+        if cost.has_allocas {
+            cost.cost += model.alloca_penalty;
+        }
+        cost.hotness = model.hotness(func.entry_count(ctx).map(|count| count as f64));
+        cost
+    }
+
+    /// Get the cost model the costs are computed with.
+    pub fn model(&self) -> &CostModel { &self.model }
+
+    /// Get the cost summary of a function.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(cost)`: The cost of the function.
+    /// - `None`: The function is a declaration.
+    pub fn cost(&self, func: Func) -> Option<&FuncCost> { self.costs.get(&func) }
+
+    /// Check if a callee is cheap enough to be inlined.
+    ///
+    /// A callee with a single call site is inlined regardless of its cost,
+    /// since the original body can be removed afterwards. Recursion must be
+    /// checked separately with the call graph.
+    pub fn should_inline(&self, callee: Func, call_sites: usize) -> bool {
+        match self.cost(callee) {
+            Some(_) if call_sites == 1 => true,
+            Some(cost) => cost.cost <= self.model.inline_threshold(cost.hotness),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Ty, Value};
+
+    #[test]
+    fn test_inline_costs() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let putint = Func::new(&mut ctx, "putint".to_string(), i32);
+        putint.add_param(&mut ctx, i32);
+
+        // small(x): y = x * x; z = y / 3; putint(z); ret z
+        let small = Func::new(&mut ctx, "small".to_string(), i32);
+        let x = small.add_param(&mut ctx, i32);
+        let block = Block::new(&mut ctx);
+        small.push_back(&mut ctx, block).unwrap();
+        let three = Value::i32(&mut ctx, 3);
+        let mul = Inst::ibinary(&mut ctx, IntBinaryOp::Mul, x, x);
+        let y = mul.result(&ctx).unwrap();
+        let div = Inst::ibinary(&mut ctx, IntBinaryOp::SDiv, y, three);
+        let z = div.result(&ctx).unwrap();
+        let call = Inst::call(&mut ctx, putint, vec![z]);
+        let ret = Inst::ret(&mut ctx, Some(z));
+        for inst in [mul, div, call, ret] {
+            block.push_back(&mut ctx, inst).unwrap();
+        }
+
+        // spin(): entry: br header; header: br header
+        let spin = Func::new(&mut ctx, "spin".to_string(), i32);
+        let entry = Block::new(&mut ctx);
+        let header = Block::new(&mut ctx);
+        spin.push_back(&mut ctx, entry).unwrap();
+        spin.push_back(&mut ctx, header).unwrap();
+        let br = Inst::br(&mut ctx, header);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, header);
+        header.push_back(&mut ctx, br).unwrap();
+
+        let model = CostModel::default();
+        let costs = InlineCosts::new(&ctx, model.clone());
+        assert_eq!(costs.cost(putint), None);
+
+        let small_cost = costs.cost(small).unwrap();
+        assert_eq!(small_cost.size, 4);
+        assert_eq!(small_cost.calls, 1);
+        assert!(!small_cost.has_loops && !small_cost.has_allocas);
+        let expected = model.mul + model.div + model.call + model.call_arg + model.basic;
+        assert_eq!(small_cost.cost, expected);
+        assert!(costs.should_inline(small, 3));
+
+        let spin_cost = costs.cost(spin).unwrap();
+        assert!(spin_cost.has_loops);
+        assert_eq!(spin_cost.cost, 2 * model.basic + model.loop_penalty);
+        assert!(!costs.should_inline(putint, 1));
+
+        let cfg = Cfg::new(&ctx, spin);
+        let domtree = DomTree::new(&cfg);
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+        let lp = loop_info.loop_of(header).unwrap();
+        assert_eq!(model.loop_cost(&ctx, &loop_info, lp), model.basic);
+        assert!(model.can_unroll(&ctx, &loop_info, lp, 8, Hotness::Normal));
+        assert!(!model.can_unroll(&ctx, &loop_info, lp, u32::MAX, Hotness::Normal));
+        assert!(!model.can_unroll(&ctx, &loop_info, lp, 8, Hotness::Cold));
+        let factor = model.hot_unroll_threshold / model.basic;
+        assert!(!model.can_unroll(&ctx, &loop_info, lp, factor, Hotness::Normal));
+        assert!(model.can_unroll(&ctx, &loop_info, lp, factor, Hotness::Hot));
+
+        // the profiled entries of the callees
+        let hot = model.hot_count;
+        for (count, hotness) in [
+            (0, Hotness::Cold),
+            (hot - 1, Hotness::Normal),
+            (hot, Hotness::Hot),
+        ] {
+            small.set_entry_count(&mut ctx, Some(count));
+            let costs = InlineCosts::new(&ctx, model.clone());
+            assert_eq!(costs.cost(small).unwrap().hotness, hotness);
+            assert_eq!(costs.should_inline(small, 3), hotness != Hotness::Cold);
+            assert!(costs.should_inline(small, 1));
+        }
+    }
+}
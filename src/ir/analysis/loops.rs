@@ -0,0 +1,323 @@
+//! Natural loop detection.
+//!
+//! A back edge is an edge `latch -> header` where the header dominates the
+//! latch. The natural loop of a header consists of the header and all the
+//! blocks that can reach one of its latches without passing the header. Back
+//! edges with the same header are merged into one loop.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{Cfg, DomTree};
+use crate::infra::linked_list::LinkedListNode;
+use crate::infra::storage::ArenaPtr;
+use crate::ir::{Block, Context, Value, ValueKind};
+
+/// A handle to a loop in a [`LoopInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Loop(usize);
+
+struct LoopData {
+    /// The header of the loop, which dominates all the blocks in the loop.
+    header: Block,
+    /// The sources of the back edges to the header, in reverse postorder.
+    latches: Vec<Block>,
+    /// All the blocks in the loop, including the nested loops, in reverse
+    /// postorder. The header is always the first one.
+    blocks: Vec<Block>,
+    /// The blocks outside the loop that are successors of the blocks in the
+    /// loop, in reverse postorder.
+    exits: Vec<Block>,
+    /// The innermost loop that contains this loop.
+    parent: Option<Loop>,
+    /// The loops directly nested in this loop.
+    children: Vec<Loop>,
+    /// The nesting depth, top-level loops have depth 1.
+    depth: usize,
+}
+
+/// The loop nesting forest of a function.
+pub struct LoopInfo {
+    loops: Vec<LoopData>,
+    /// The innermost loop of each block.
+    block_loops: HashMap<Block, Loop>,
+    /// The loops that are not nested in any other loop.
+    top_level: Vec<Loop>,
+}
+
+impl LoopInfo {
+    /// Detect the natural loops of a function.
+    pub fn new(cfg: &Cfg, domtree: &DomTree) -> Self {
+        let mut loops = Vec::new();
+
+        for header in cfg.rpo() {
+            let latches = cfg
+                .preds(header)
+                .iter()
+                .copied()
+                .filter(|&pred| domtree.dominates(header, pred))
+                .collect::<Vec<_>>();
+            if latches.is_empty() {
+                continue;
+            }
+
+            // Walk backwards from the latches until the header is reached.
+            let mut body = HashSet::new();
+            body.insert(header);
+            let mut worklist = latches.clone();
+            while let Some(block) = worklist.pop() {
+                if !body.insert(block) {
+                    continue;
+                }
+                worklist.extend(
+                    cfg.preds(block)
+                        .iter()
+                        .copied()
+                        .filter(|&pred| cfg.is_reachable(pred)),
+                );
+            }
+
+            let mut blocks = body.iter().copied().collect::<Vec<_>>();
+            blocks.sort_by_key(|&block| cfg.rpo_index(block));
+
+            let mut exits = Vec::new();
+            for &block in blocks.iter() {
+                for &succ in cfg.succs(block) {
+                    if !body.contains(&succ) && !exits.contains(&succ) {
+                        exits.push(succ);
+                    }
+                }
+            }
+            exits.sort_by_key(|&block| cfg.rpo_index(block));
+
+            let mut latches = latches;
+            latches.sort_by_key(|&block| cfg.rpo_index(block));
+
+            loops.push(LoopData {
+                header,
+                latches,
+                blocks,
+                exits,
+                parent: None,
+                children: Vec::new(),
+                depth: 1,
+            });
+        }
+
+        // Natural loops with different headers are either disjoint or nested,
+        // so the innermost loop of a block is the smallest one containing it.
+        let mut by_size = (0..loops.len()).collect::<Vec<_>>();
+        by_size.sort_by_key(|&i| loops[i].blocks.len());
+
+        let mut block_loops = HashMap::new();
+        for &i in by_size.iter() {
+            for &block in loops[i].blocks.iter() {
+                block_loops.entry(block).or_insert(Loop(i));
+            }
+        }
+
+        for &i in by_size.iter() {
+            let header = loops[i].header;
+            let parent = by_size
+                .iter()
+                .copied()
+                .filter(|&j| j != i && loops[j].blocks.len() > loops[i].blocks.len())
+                .find(|&j| loops[j].blocks.contains(&header));
+            if let Some(parent) = parent {
+                loops[i].parent = Some(Loop(parent));
+            }
+        }
+
+        let mut top_level = Vec::new();
+        // Loops are created in reverse postorder of the headers, so parents are
+        // visited before children.
+        for i in 0..loops.len() {
+            match loops[i].parent {
+                Some(Loop(parent)) => {
+                    loops[i].depth = loops[parent].depth + 1;
+                    loops[parent].children.push(Loop(i));
+                }
+                None => top_level.push(Loop(i)),
+            }
+        }
+
+        Self {
+            loops,
+            block_loops,
+            top_level,
+        }
+    }
+
+    /// Iterate over all the loops, outer loops first.
+    pub fn loops(&self) -> impl Iterator<Item = Loop> + '_ { (0..self.loops.len()).map(Loop) }
+
+    /// Get the loops that are not nested in any other loop.
+    pub fn top_level(&self) -> &[Loop] { &self.top_level }
+
+    /// Get the innermost loop that contains the block.
+    pub fn loop_of(&self, block: Block) -> Option<Loop> { self.block_loops.get(&block).copied() }
+
+    /// Get the loop nesting depth of the block, 0 if it is not in any loop.
+    pub fn depth_of(&self, block: Block) -> usize {
+        self.loop_of(block).map_or(0, |lp| self.depth(lp))
+    }
+
+    /// Check if the block is the header of a loop.
+    pub fn is_header(&self, block: Block) -> bool {
+        self.loop_of(block).is_some_and(|lp| self.header(lp) == block)
+    }
+
+    fn data(&self, lp: Loop) -> &LoopData { &self.loops[lp.0] }
+
+    /// Get the header of the loop.
+    pub fn header(&self, lp: Loop) -> Block { self.data(lp).header }
+
+    /// Get the latches of the loop, i.e., the sources of the back edges.
+    pub fn latches(&self, lp: Loop) -> &[Block] { &self.data(lp).latches }
+
+    /// Get the unique latch of the loop, if any.
+    pub fn single_latch(&self, lp: Loop) -> Option<Block> {
+        match self.latches(lp) {
+            [latch] => Some(*latch),
+            _ => None,
+        }
+    }
+
+    /// Get all the blocks in the loop, the header first.
+    pub fn blocks(&self, lp: Loop) -> &[Block] { &self.data(lp).blocks }
+
+    /// Get the exit blocks of the loop, i.e., the blocks outside the loop with
+    /// a predecessor in the loop.
+    pub fn exits(&self, lp: Loop) -> &[Block] { &self.data(lp).exits }
+
+    /// Get the exiting blocks of the loop, i.e., the blocks in the loop with a
+    /// successor outside the loop.
+    pub fn exiting_blocks(&self, cfg: &Cfg, lp: Loop) -> Vec<Block> {
+        self.blocks(lp)
+            .iter()
+            .copied()
+            .filter(|&block| cfg.succs(block).iter().any(|&succ| !self.contains(lp, succ)))
+            .collect()
+    }
+
+    /// Get the preheader of the loop, i.e., the unique predecessor of the
+    /// header outside the loop, if it only branches to the header.
+    pub fn preheader(&self, cfg: &Cfg, lp: Loop) -> Option<Block> {
+        let header = self.header(lp);
+        let mut outside = cfg
+            .preds(header)
+            .iter()
+            .copied()
+            .filter(|&pred| !self.contains(lp, pred));
+        let pred = outside.next()?;
+        if outside.next().is_some() || cfg.succs(pred) != [header] {
+            return None;
+        }
+        Some(pred)
+    }
+
+    /// Get the parent of the loop, i.e., the innermost loop containing it.
+    pub fn parent(&self, lp: Loop) -> Option<Loop> { self.data(lp).parent }
+
+    /// Get the loops directly nested in the loop.
+    pub fn children(&self, lp: Loop) -> &[Loop] { &self.data(lp).children }
+
+    /// Get the nesting depth of the loop, top-level loops have depth 1.
+    pub fn depth(&self, lp: Loop) -> usize { self.data(lp).depth }
+
+    /// Check if the loop contains the block, including in nested loops.
+    pub fn contains(&self, lp: Loop, block: Block) -> bool {
+        let mut current = self.loop_of(block);
+        while let Some(inner) = current {
+            if inner == lp {
+                return true;
+            }
+            current = self.parent(inner);
+        }
+        false
+    }
+
+    /// Check if the value is invariant in the loop, i.e., it is not defined by
+    /// an instruction or a block parameter inside the loop.
+    pub fn is_invariant(&self, ctx: &Context, lp: Loop, value: Value) -> bool {
+        match value.deref(ctx).kind {
+            ValueKind::InstResult { inst, .. } => inst
+                .container(ctx)
+                .is_none_or(|block| !self.contains(lp, block)),
+            ValueKind::BlockParam { block, .. } => !self.contains(lp, block),
+            ValueKind::Param { .. } | ValueKind::Constant { .. } => true,
+        }
+    }
+
+    /// Check if the loop has no nested loops.
+    pub fn is_innermost(&self, lp: Loop) -> bool { self.children(lp).is_empty() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::linked_list::LinkedListContainer;
+    use crate::ir::{Context, Func, Inst, Ty, Value};
+
+    #[test]
+    fn test_loop_info() {
+        // entry -> outer
+        // outer -> inner, exit
+        // inner -> inner, latch
+        // latch -> outer
+        let mut ctx = Context::default();
+        let void = Ty::void(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), void);
+        let blocks = (0..5).map(|_| Block::new(&mut ctx)).collect::<Vec<_>>();
+        for &block in blocks.iter() {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [entry, outer, inner, latch, exit] = blocks[..] else {
+            unreachable!()
+        };
+
+        let cond = Value::i1(&mut ctx, true);
+        let br = Inst::br(&mut ctx, outer);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::cond_br(&mut ctx, cond, inner, exit);
+        outer.push_back(&mut ctx, br).unwrap();
+        let br = Inst::cond_br(&mut ctx, cond, inner, latch);
+        inner.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, outer);
+        latch.push_back(&mut ctx, br).unwrap();
+        let ret = Inst::ret(&mut ctx, None);
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        let domtree = DomTree::new(&cfg);
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+
+        assert_eq!(loop_info.loops().count(), 2);
+        assert_eq!(loop_info.top_level().len(), 1);
+        let outer_loop = loop_info.top_level()[0];
+        let inner_loop = loop_info.loop_of(inner).unwrap();
+
+        assert_eq!(loop_info.header(outer_loop), outer);
+        assert_eq!(loop_info.latches(outer_loop), &[latch]);
+        assert_eq!(loop_info.blocks(outer_loop), &[outer, inner, latch]);
+        assert_eq!(loop_info.exits(outer_loop), &[exit]);
+        assert_eq!(loop_info.exiting_blocks(&cfg, outer_loop), vec![outer]);
+        assert_eq!(loop_info.preheader(&cfg, outer_loop), Some(entry));
+        assert_eq!(loop_info.children(outer_loop), &[inner_loop]);
+
+        assert_eq!(loop_info.header(inner_loop), inner);
+        assert_eq!(loop_info.single_latch(inner_loop), Some(inner));
+        assert_eq!(loop_info.exits(inner_loop), &[latch]);
+        assert_eq!(loop_info.parent(inner_loop), Some(outer_loop));
+        assert_eq!(loop_info.depth(inner_loop), 2);
+        assert!(loop_info.is_innermost(inner_loop));
+        // The outer header branches to the exit as well.
+        assert_eq!(loop_info.preheader(&cfg, inner_loop), None);
+
+        assert!(loop_info.contains(outer_loop, inner));
+        assert!(!loop_info.contains(inner_loop, latch));
+        assert_eq!(loop_info.depth_of(latch), 1);
+        assert_eq!(loop_info.depth_of(entry), 0);
+        assert!(loop_info.is_header(inner));
+        assert!(!loop_info.is_header(latch));
+    }
+}
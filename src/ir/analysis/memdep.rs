@@ -0,0 +1,287 @@
+//! Memory dependence analysis.
+//!
+//! For each load, the analysis finds the nearest preceding instructions on all
+//! paths that may write the loaded memory. For each store, it finds the
+//! instructions that may read the stored memory before it is overwritten.
+//! Both walk across blocks, so they can be used for store-to-load forwarding
+//! and dead store elimination beyond a single block.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{alias, AliasResult, Cfg, MemLoc};
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::{Block, Context, Inst, InstKind};
+
+/// The instructions that may define the memory read by a load.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadDeps {
+    /// The nearest stores or calls that may write the memory, one for each
+    /// path at most.
+    pub defs: Vec<Inst>,
+    /// Whether the load may read the memory as it was on function entry, i.e.,
+    /// some path from the entry reaches the load without any definition.
+    pub live_in: bool,
+}
+
+impl LoadDeps {
+    /// Get the single definition if there is exactly one on all the paths.
+    pub fn single_def(&self) -> Option<Inst> {
+        match self.defs[..] {
+            [def] if !self.live_in => Some(def),
+            _ => None,
+        }
+    }
+}
+
+/// The instructions that may read the memory written by a store.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreUses {
+    /// The loads or calls that may read the memory before it is overwritten.
+    pub uses: Vec<Inst>,
+    /// Whether the stored value may still be in memory when the function
+    /// returns.
+    pub live_out: bool,
+}
+
+/// The memory dependences of the loads and stores in a function.
+pub struct MemDep {
+    load_deps: HashMap<Inst, LoadDeps>,
+    store_uses: HashMap<Inst, StoreUses>,
+}
+
+/// The effect of an instruction on a memory location.
+enum Effect {
+    None,
+    /// The instruction may read the location.
+    Read,
+    /// The instruction may write the location, but not necessarily all of it.
+    MayWrite,
+    /// The instruction overwrites the whole location.
+    Kill,
+}
+
+fn effect(ctx: &Context, inst: Inst, loc: MemLoc) -> Effect {
+    match inst.kind(ctx) {
+        InstKind::Load => match alias(ctx, MemLoc::of_inst(ctx, inst).unwrap(), loc) {
+            AliasResult::NoAlias => Effect::None,
+            _ => Effect::Read,
+        },
+        InstKind::Store => match alias(ctx, MemLoc::of_inst(ctx, inst).unwrap(), loc) {
+            AliasResult::NoAlias => Effect::None,
+            AliasResult::MayAlias => Effect::MayWrite,
+            AliasResult::MustAlias => Effect::Kill,
+        },
+        InstKind::Call if inst.may_write_memory(ctx) => Effect::MayWrite,
+        InstKind::Call if inst.may_read_memory(ctx) => Effect::Read,
+        _ => Effect::None,
+    }
+}
+
+impl MemDep {
+    /// Compute the memory dependences of all the reachable loads and stores
+    /// in the function of the CFG.
+    pub fn new(ctx: &Context, cfg: &Cfg) -> Self {
+        let mut load_deps = HashMap::new();
+        let mut store_uses = HashMap::new();
+        for block in cfg.rpo() {
+            for inst in block.iter(ctx) {
+                match inst.kind(ctx) {
+                    InstKind::Load => {
+                        load_deps.insert(inst, Self::compute_load_deps(ctx, cfg, inst));
+                    }
+                    InstKind::Store => {
+                        store_uses.insert(inst, Self::compute_store_uses(ctx, cfg, inst));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Self {
+            load_deps,
+            store_uses,
+        }
+    }
+
+    /// Get the definitions of a load.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the instruction is not a reachable load.
+    pub fn load_deps(&self, load: Inst) -> &LoadDeps { &self.load_deps[&load] }
+
+    /// Get the uses of a store.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the instruction is not a reachable store.
+    pub fn store_uses(&self, store: Inst) -> &StoreUses { &self.store_uses[&store] }
+
+    fn compute_load_deps(ctx: &Context, cfg: &Cfg, load: Inst) -> LoadDeps {
+        let loc = MemLoc::of_inst(ctx, load).unwrap();
+        let mut deps = LoadDeps::default();
+
+        // Scan backwards from `inst`, returning the first definition.
+        let scan = |mut inst: Option<Inst>| {
+            while let Some(current) = inst {
+                if let Effect::MayWrite | Effect::Kill = effect(ctx, current, loc) {
+                    return Some(current);
+                }
+                inst = current.prev(ctx);
+            }
+            None
+        };
+
+        let block = load.container(ctx).unwrap();
+        if let Some(def) = scan(load.prev(ctx)) {
+            deps.defs.push(def);
+            return deps;
+        }
+
+        // The block of the load is scanned again from the end if it is reached
+        // through a loop.
+        let mut visited = HashSet::new();
+        let mut worklist = vec![block];
+        let mut first = true;
+        while let Some(block) = worklist.pop() {
+            if !first {
+                if !visited.insert(block) {
+                    continue;
+                }
+                if let Some(def) = scan(block.tail(ctx)) {
+                    if !deps.defs.contains(&def) {
+                        deps.defs.push(def);
+                    }
+                    continue;
+                }
+            }
+            first = false;
+            if Some(block) == cfg.entry() {
+                deps.live_in = true;
+            }
+            worklist.extend(
+                cfg.preds(block)
+                    .iter()
+                    .copied()
+                    .filter(|&pred| cfg.is_reachable(pred)),
+            );
+        }
+
+        deps
+    }
+
+    fn compute_store_uses(ctx: &Context, cfg: &Cfg, store: Inst) -> StoreUses {
+        let loc = MemLoc::of_inst(ctx, store).unwrap();
+        let mut uses = StoreUses::default();
+
+        // Scan forwards from `inst`, collecting the readers. Returns true if
+        // the end of the block is reached without the location being killed.
+        let scan = |mut inst: Option<Inst>, uses: &mut StoreUses| {
+            while let Some(current) = inst {
+                match effect(ctx, current, loc) {
+                    Effect::Read | Effect::MayWrite
+                        if current.may_read_memory(ctx) && !uses.uses.contains(&current) =>
+                    {
+                        uses.uses.push(current);
+                    }
+                    Effect::Kill => return false,
+                    _ => {}
+                }
+                if let InstKind::Ret = current.kind(ctx) {
+                    uses.live_out = true;
+                }
+                inst = current.next(ctx);
+            }
+            true
+        };
+
+        let block: Block = store.container(ctx).unwrap();
+        if !scan(store.next(ctx), &mut uses) {
+            return uses;
+        }
+
+        let mut visited = HashSet::new();
+        let mut worklist = cfg.succs(block).to_vec();
+        while let Some(block) = worklist.pop() {
+            if !visited.insert(block) {
+                continue;
+            }
+            if scan(block.head(ctx), &mut uses) {
+                worklist.extend(cfg.succs(block).iter().copied());
+            }
+        }
+
+        uses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Func, Ty, Value};
+
+    #[test]
+    fn test_memdep() {
+        // entry: store 1, a; store 2, b; cond_br then, join
+        // then:  store 3, a; br join
+        // join:  x = load a; y = load b; store 4, a; ret x
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let cond = func.add_param(&mut ctx, i32);
+        let entry = Block::new(&mut ctx);
+        let then = Block::new(&mut ctx);
+        let join = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+        func.push_back(&mut ctx, then).unwrap();
+        func.push_back(&mut ctx, join).unwrap();
+
+        let a = Inst::alloca(&mut ctx, i32);
+        let b = Inst::alloca(&mut ctx, i32);
+        entry.push_back(&mut ctx, a).unwrap();
+        entry.push_back(&mut ctx, b).unwrap();
+        let (a, b) = (a.result(&ctx).unwrap(), b.result(&ctx).unwrap());
+
+        let store = |ctx: &mut Context, block: Block, value: i32, ptr: Value| {
+            let value = Value::i32(ctx, value);
+            let inst = Inst::store(ctx, value, ptr);
+            block.push_back(ctx, inst).unwrap();
+            inst
+        };
+
+        let store_a1 = store(&mut ctx, entry, 1, a);
+        let store_b2 = store(&mut ctx, entry, 2, b);
+        let br = Inst::cond_br(&mut ctx, cond, then, join);
+        entry.push_back(&mut ctx, br).unwrap();
+
+        let store_a3 = store(&mut ctx, then, 3, a);
+        let br = Inst::br(&mut ctx, join);
+        then.push_back(&mut ctx, br).unwrap();
+
+        let load_a = Inst::load(&mut ctx, a, i32);
+        let load_b = Inst::load(&mut ctx, b, i32);
+        join.push_back(&mut ctx, load_a).unwrap();
+        join.push_back(&mut ctx, load_b).unwrap();
+        let store_a4 = store(&mut ctx, join, 4, a);
+        let x = load_a.result(&ctx).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(x));
+        join.push_back(&mut ctx, ret).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        let memdep = MemDep::new(&ctx, &cfg);
+
+        let deps = memdep.load_deps(load_a);
+        assert!(!deps.live_in);
+        assert_eq!(deps.defs.len(), 2);
+        assert!(deps.defs.contains(&store_a1) && deps.defs.contains(&store_a3));
+        assert_eq!(deps.single_def(), None);
+        assert_eq!(memdep.load_deps(load_b).single_def(), Some(store_b2));
+
+        assert_eq!(memdep.store_uses(store_a1).uses, vec![load_a]);
+        assert_eq!(memdep.store_uses(store_b2).uses, vec![load_b]);
+        assert!(memdep.store_uses(store_b2).live_out);
+        // killed by `store 4, a` on all paths
+        assert!(!memdep.store_uses(store_a3).live_out);
+        let uses = memdep.store_uses(store_a4);
+        assert!(uses.uses.is_empty() && uses.live_out);
+    }
+}
@@ -0,0 +1,501 @@
+//! Integer value range analysis.
+//!
+//! A conservative interval is computed for each integer value in a function,
+//! from:
+//!
+//! - constants;
+//! - interval arithmetic on the operands of instructions;
+//! - basic induction variables, which only move in the direction of their step
+//!   and are bounded by the exit test of their loop;
+//! - comparisons guarding the blocks, e.g., `v` is at most `n - 1` in the
+//!   blocks only reachable through the true edge of `br (icmp slt v, n)`.
+//!
+//! The analysis assumes that signed arithmetic on induction variables does not
+//! overflow, which is undefined behavior in SysY.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{Cfg, DomTree, InductionVars, LoopInfo};
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::{Block, CastOp, Context, Inst, InstKind, IntBinaryOp, IntCmpCond, Value};
+
+/// An inclusive interval of signed integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Range {
+    pub lo: i32,
+    pub hi: i32,
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "[{}, {}]", self.lo, self.hi) }
+}
+
+impl Range {
+    /// Create a range from its bounds.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if `lo` is greater than `hi`.
+    pub fn new(lo: i32, hi: i32) -> Self {
+        assert!(lo <= hi, "empty range");
+        Self { lo, hi }
+    }
+
+    /// The range of all `i32` values.
+    pub fn full() -> Self { Self::new(i32::MIN, i32::MAX) }
+
+    /// The range of all values of a signed integer with the given bit width.
+    /// `i1` values are treated as `0` and `1`.
+    pub fn full_of_width(bits: usize) -> Self {
+        match bits {
+            1 => Self::new(0, 1),
+            8 => Self::new(i8::MIN as i32, i8::MAX as i32),
+            _ => Self::full(),
+        }
+    }
+
+    /// The range of a single value.
+    pub fn constant(value: i32) -> Self { Self::new(value, value) }
+
+    /// Create a range from 64-bit bounds, which is the full range if any bound
+    /// is out of `i32`, i.e., the computation may wrap around.
+    fn from_i64(lo: i64, hi: i64) -> Self {
+        match (i32::try_from(lo), i32::try_from(hi)) {
+            (Ok(lo), Ok(hi)) => Self::new(lo, hi),
+            _ => Self::full(),
+        }
+    }
+
+    /// Get the single value in the range, if any.
+    pub fn as_constant(self) -> Option<i32> { (self.lo == self.hi).then_some(self.lo) }
+
+    /// Check if the range contains the value.
+    pub fn contains(self, value: i32) -> bool { self.lo <= value && value <= self.hi }
+
+    /// Check if all the values in the range are non-negative.
+    pub fn is_nonnegative(self) -> bool { self.lo >= 0 }
+
+    /// Check if the range is the full range of `i32`.
+    pub fn is_full(self) -> bool { self == Self::full() }
+
+    /// Get the smallest range containing both ranges.
+    pub fn union(self, other: Self) -> Self {
+        Self::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+
+    /// Get the intersection of two ranges, `None` if they are disjoint.
+    pub fn intersect(self, other: Self) -> Option<Self> {
+        let (lo, hi) = (self.lo.max(other.lo), self.hi.min(other.hi));
+        (lo <= hi).then(|| Self::new(lo, hi))
+    }
+
+    /// Get the range of `self + other` without wrapping, `None` if it may
+    /// overflow.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let lo = self.lo as i64 + other.lo as i64;
+        let hi = self.hi as i64 + other.hi as i64;
+        Some(Self::new(lo.try_into().ok()?, hi.try_into().ok()?))
+    }
+
+    /// Get the range of `self - other` without wrapping, `None` if it may
+    /// overflow.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let lo = self.lo as i64 - other.hi as i64;
+        let hi = self.hi as i64 - other.lo as i64;
+        Some(Self::new(lo.try_into().ok()?, hi.try_into().ok()?))
+    }
+
+    /// Get the range of `self * other` without wrapping, `None` if it may
+    /// overflow.
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        let products = [
+            self.lo as i64 * other.lo as i64,
+            self.lo as i64 * other.hi as i64,
+            self.hi as i64 * other.lo as i64,
+            self.hi as i64 * other.hi as i64,
+        ];
+        let lo = *products.iter().min().unwrap();
+        let hi = *products.iter().max().unwrap();
+        Some(Self::new(lo.try_into().ok()?, hi.try_into().ok()?))
+    }
+
+    /// Refine the range of `v` knowing that `v cond other` holds.
+    pub fn refine(self, cond: IntCmpCond, other: Self) -> Self {
+        let (lo, hi) = (self.lo as i64, self.hi as i64);
+        let (other_lo, other_hi) = (other.lo as i64, other.hi as i64);
+        let (lo, hi) = match cond {
+            IntCmpCond::Slt => (lo, hi.min(other_hi - 1)),
+            IntCmpCond::Sle => (lo, hi.min(other_hi)),
+            IntCmpCond::Sgt => (lo.max(other_lo + 1), hi),
+            IntCmpCond::Sge => (lo.max(other_lo), hi),
+            IntCmpCond::Eq => (lo.max(other_lo), hi.min(other_hi)),
+            IntCmpCond::Ne => match other.as_constant() {
+                Some(c) if c == self.lo => (lo + 1, hi),
+                Some(c) if c == self.hi => (lo, hi - 1),
+                _ => (lo, hi),
+            },
+        };
+        // An empty range means the guarded code is unreachable, keep the
+        // original range in that case.
+        if lo > hi {
+            return self;
+        }
+        Self::from_i64(lo, hi)
+    }
+}
+
+/// The value ranges of a function.
+pub struct ValueRanges {
+    /// The ranges of the non-constant integer values.
+    ranges: HashMap<Value, Range>,
+    /// The immediate dominators of the reachable blocks.
+    idoms: HashMap<Block, Block>,
+    /// The comparison guarding each block, i.e., the block has a single
+    /// predecessor, which branches on the comparison, and the block is reached
+    /// if the comparison is the given boolean.
+    guards: HashMap<Block, (Inst, bool)>,
+}
+
+impl ValueRanges {
+    /// Compute the value ranges of a function.
+    pub fn new(
+        ctx: &Context,
+        cfg: &Cfg,
+        domtree: &DomTree,
+        loop_info: &LoopInfo,
+        ivs: &InductionVars,
+    ) -> Self {
+        let mut ranges = Self {
+            ranges: HashMap::new(),
+            idoms: HashMap::new(),
+            guards: HashMap::new(),
+        };
+
+        for block in cfg.rpo() {
+            if let Some(idom) = domtree.idom(block) {
+                ranges.idoms.insert(block, idom);
+            }
+            let &[pred] = cfg.preds(block) else {
+                continue;
+            };
+            let Some(term) = pred.tail(ctx) else {
+                continue;
+            };
+            if !matches!(term.kind(ctx), InstKind::CondBr) {
+                continue;
+            }
+            let (then_dest, else_dest) = (term.successor(ctx, 0), term.successor(ctx, 1));
+            let Some(cmp) = term.operand(ctx, 0).def_inst(ctx) else {
+                continue;
+            };
+            if then_dest != else_dest && is_icmp(ctx, cmp) {
+                ranges.guards.insert(block, (cmp, block == then_dest));
+            }
+        }
+
+        // The loop bounds of the induction variables.
+        let mut iv_bounds = HashMap::new();
+        for lp in loop_info.loops() {
+            if let Some(test) = loop_info.exit_test(ctx, cfg, ivs, lp) {
+                iv_bounds.insert(test.iv.value, test);
+            }
+        }
+
+        // The operands are visited before the users in reverse postorder,
+        // except for phis.
+        for block in cfg.rpo() {
+            for inst in block.iter(ctx) {
+                let Some(result) = inst.result(ctx) else {
+                    continue;
+                };
+                let ty = result.ty(ctx);
+                if !ty.is_integer(ctx) {
+                    continue;
+                }
+                let full = Range::full_of_width(ty.bitwidth(ctx));
+
+                let range = if let Some(iv) = ivs.basic_iv(result) {
+                    let start = ranges.range(ctx, iv.start);
+                    let step = iv.step as i64;
+                    // The extreme value reached by the variable, if known.
+                    let extreme = iv_bounds.get(&result).and_then(|test| {
+                        let bound = ranges.range(ctx, test.bound);
+                        // The last value passing the test, and the one failing it
+                        // if the test is on the current value.
+                        let (last, failing) = match test.cond {
+                            IntCmpCond::Slt if step > 0 => (bound.hi as i64 - 1, step),
+                            IntCmpCond::Sle if step > 0 => (bound.hi as i64, step),
+                            IntCmpCond::Sgt if step < 0 => (bound.lo as i64 + 1, step),
+                            IntCmpCond::Sge if step < 0 => (bound.lo as i64, step),
+                            _ => return None,
+                        };
+                        Some(if test.on_next { last } else { last + failing })
+                    });
+                    if step > 0 {
+                        let hi = extreme.map_or(i32::MAX as i64, |e| e.max(start.hi as i64));
+                        Range::from_i64(start.lo as i64, hi)
+                    } else {
+                        let lo = extreme.map_or(i32::MIN as i64, |e| e.min(start.lo as i64));
+                        Range::from_i64(lo, start.hi as i64)
+                    }
+                } else {
+                    ranges.eval_inst(ctx, inst, block).unwrap_or(full)
+                };
+                // Keep the range within the bit width.
+                let range = range.intersect(full).unwrap_or(full);
+                ranges.ranges.insert(result, range);
+            }
+        }
+
+        ranges
+    }
+
+    /// Get the range of a value anywhere in the function.
+    pub fn range(&self, ctx: &Context, value: Value) -> Range {
+        if let Some(c) = value.as_const_int(ctx) {
+            return Range::constant(c);
+        }
+        if let Some(range) = self.ranges.get(&value) {
+            return *range;
+        }
+        let ty = value.ty(ctx);
+        if ty.is_integer(ctx) {
+            Range::full_of_width(ty.bitwidth(ctx))
+        } else {
+            Range::full()
+        }
+    }
+
+    /// Get the range of a value in a block, refined by the comparisons
+    /// guarding the block and its dominators.
+    pub fn range_at(&self, ctx: &Context, value: Value, block: Block) -> Range {
+        let mut range = self.range(ctx, value);
+        if range.as_constant().is_some() {
+            return range;
+        }
+        let mut current = Some(block);
+        while let Some(block) = current {
+            if let Some(&(cmp, taken)) = self.guards.get(&block) {
+                let InstKind::IntBinary {
+                    op: IntBinaryOp::ICmp { cond },
+                } = *cmp.kind(ctx)
+                else {
+                    unreachable!()
+                };
+                let cond = if taken { cond } else { cond.inverse() };
+                let (lhs, rhs) = (cmp.operand(ctx, 0), cmp.operand(ctx, 1));
+                if lhs == value {
+                    range = range.refine(cond, self.range(ctx, rhs));
+                } else if rhs == value {
+                    range = range.refine(cond.swap(), self.range(ctx, lhs));
+                }
+            }
+            current = self.idoms.get(&block).copied();
+        }
+        range
+    }
+
+    /// Check if a signed `add`, `sub` or `mul` instruction may overflow.
+    ///
+    /// Returns true for other instructions.
+    pub fn may_overflow(&self, ctx: &Context, inst: Inst) -> bool {
+        let InstKind::IntBinary { op } = *inst.kind(ctx) else {
+            return true;
+        };
+        let block = inst.container(ctx).unwrap();
+        let lhs = self.range_at(ctx, inst.operand(ctx, 0), block);
+        let rhs = self.range_at(ctx, inst.operand(ctx, 1), block);
+        let range = match op {
+            IntBinaryOp::Add => lhs.checked_add(rhs),
+            IntBinaryOp::Sub => lhs.checked_sub(rhs),
+            IntBinaryOp::Mul => lhs.checked_mul(rhs),
+            _ => return true,
+        };
+        let full = Range::full_of_width(inst.result(ctx).unwrap().ty(ctx).bitwidth(ctx));
+        range.is_none_or(|range| range.intersect(full) != Some(range))
+    }
+
+    /// Compute the range of the result of an instruction from the ranges of
+    /// its operands.
+    fn eval_inst(&self, ctx: &Context, inst: Inst, block: Block) -> Option<Range> {
+        let operand = |idx| self.range_at(ctx, inst.operand(ctx, idx), block);
+        match *inst.kind(ctx) {
+            InstKind::IntBinary { op } => {
+                let (lhs, rhs) = (operand(0), operand(1));
+                match op {
+                    IntBinaryOp::Add => lhs.checked_add(rhs),
+                    IntBinaryOp::Sub => lhs.checked_sub(rhs),
+                    IntBinaryOp::Mul => lhs.checked_mul(rhs),
+                    IntBinaryOp::SDiv => {
+                        let c = rhs.as_constant().filter(|&c| c > 0)? as i64;
+                        Some(Range::from_i64(lhs.lo as i64 / c, lhs.hi as i64 / c))
+                    }
+                    IntBinaryOp::SRem => {
+                        let c = rhs.as_constant().filter(|&c| c > 0)?;
+                        // The sign of the result follows the dividend.
+                        let lo = if lhs.is_nonnegative() { 0 } else { -(c - 1) };
+                        let hi = if lhs.hi < 0 { 0 } else { c - 1 };
+                        Some(Range::new(lo, hi))
+                    }
+                    IntBinaryOp::And if lhs.is_nonnegative() || rhs.is_nonnegative() => {
+                        // The result is no larger than any non-negative operand.
+                        let hi = [lhs, rhs]
+                            .iter()
+                            .filter(|r| r.is_nonnegative())
+                            .map(|r| r.hi)
+                            .min()
+                            .unwrap();
+                        Some(Range::new(0, hi))
+                    }
+                    IntBinaryOp::Shl => {
+                        let c = rhs.as_constant().filter(|c| (0..31).contains(c))?;
+                        lhs.checked_mul(Range::constant(1 << c))
+                    }
+                    IntBinaryOp::AShr => {
+                        let c = rhs.as_constant().filter(|c| (0..32).contains(c))?;
+                        Some(Range::new(lhs.lo >> c, lhs.hi >> c))
+                    }
+                    IntBinaryOp::ICmp { .. } => Some(Range::new(0, 1)),
+                    _ => None,
+                }
+            }
+            InstKind::Cast { op } => {
+                let src = operand(0);
+                match op {
+                    CastOp::Sext => Some(src),
+                    // Only `i1` is extended as an unsigned value, see
+                    // `Range::full_of_width`.
+                    CastOp::Zext if src.is_nonnegative() => Some(src),
+                    CastOp::Zext => {
+                        let bits = inst.operand(ctx, 0).ty(ctx).bitwidth(ctx);
+                        Some(Range::new(0, (1i32 << bits) - 1))
+                    }
+                    CastOp::Trunc | CastOp::SiToFp | CastOp::FpToSi => None,
+                }
+            }
+            InstKind::Phi => {
+                let mut range: Option<Range> = None;
+                for (_, value) in inst.incoming_iter(ctx) {
+                    // Values from back edges are not computed yet.
+                    if value.as_const_int(ctx).is_none() && !self.ranges.contains_key(&value) {
+                        return None;
+                    }
+                    let incoming = self.range(ctx, value);
+                    range = Some(range.map_or(incoming, |r| r.union(incoming)));
+                }
+                range
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_icmp(ctx: &Context, inst: Inst) -> bool {
+    matches!(
+        inst.kind(ctx),
+        InstKind::IntBinary {
+            op: IntBinaryOp::ICmp { .. }
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Func, Ty};
+
+    #[test]
+    fn test_range_arithmetic() {
+        let a = Range::new(0, 10);
+        let b = Range::new(-5, 5);
+        assert_eq!(a.checked_add(b), Some(Range::new(-5, 15)));
+        assert_eq!(a.checked_sub(b), Some(Range::new(-5, 15)));
+        assert_eq!(a.checked_mul(b), Some(Range::new(-50, 50)));
+        assert_eq!(Range::full().checked_add(a), None);
+        assert_eq!(a.refine(IntCmpCond::Slt, Range::constant(5)), Range::new(0, 4));
+        assert_eq!(a.refine(IntCmpCond::Ne, Range::constant(0)), Range::new(1, 10));
+        assert_eq!(a.refine(IntCmpCond::Sgt, Range::constant(20)), a);
+        assert_eq!(a.intersect(Range::new(11, 12)), None);
+        assert_eq!(a.to_string(), "[0, 10]");
+    }
+
+    #[test]
+    fn test_value_ranges() {
+        // entry:  br header
+        // header: i = phi [0, entry], [next, body]
+        //         cond = icmp slt i, 100
+        //         br cond, body, exit
+        // body:   idx = mul i, 4
+        //         rem = srem n, 8
+        //         next = add i, 1
+        //         br header
+        // exit:   ret i
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let n = func.add_param(&mut ctx, i32);
+        let blocks = (0..4).map(|_| Block::new(&mut ctx)).collect::<Vec<_>>();
+        for &block in blocks.iter() {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [entry, header, body, exit] = blocks[..] else {
+            unreachable!()
+        };
+
+        let br = Inst::br(&mut ctx, header);
+        entry.push_back(&mut ctx, br).unwrap();
+
+        let binary = |ctx: &mut Context, block: Block, op, lhs, rhs| {
+            let inst = Inst::ibinary(ctx, op, lhs, rhs);
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+        let (zero, one, four, eight, hundred) = (
+            Value::i32(&mut ctx, 0),
+            Value::i32(&mut ctx, 1),
+            Value::i32(&mut ctx, 4),
+            Value::i32(&mut ctx, 8),
+            Value::i32(&mut ctx, 100),
+        );
+
+        let phi = Inst::phi(&mut ctx, i32);
+        let i = phi.result(&ctx).unwrap();
+        header.push_back(&mut ctx, phi).unwrap();
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let cond = binary(&mut ctx, header, slt, i, hundred);
+        let cond_br = Inst::cond_br(&mut ctx, cond, body, exit);
+        header.push_back(&mut ctx, cond_br).unwrap();
+
+        let idx = binary(&mut ctx, body, IntBinaryOp::Mul, i, four);
+        let rem = binary(&mut ctx, body, IntBinaryOp::SRem, n, eight);
+        let next = binary(&mut ctx, body, IntBinaryOp::Add, i, one);
+        let br = Inst::br(&mut ctx, header);
+        body.push_back(&mut ctx, br).unwrap();
+        phi.insert_incoming(&mut ctx, entry, zero);
+        phi.insert_incoming(&mut ctx, body, next);
+
+        let ret = Inst::ret(&mut ctx, Some(i));
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let cfg = Cfg::new(&ctx, func);
+        let domtree = DomTree::new(&cfg);
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+        let ivs = InductionVars::new(&ctx, &cfg, &loop_info);
+        let ranges = ValueRanges::new(&ctx, &cfg, &domtree, &loop_info, &ivs);
+
+        assert_eq!(ranges.range(&ctx, i), Range::new(0, 100));
+        assert_eq!(ranges.range_at(&ctx, i, body), Range::new(0, 99));
+        assert_eq!(ranges.range_at(&ctx, i, exit), Range::constant(100));
+        assert_eq!(ranges.range(&ctx, idx), Range::new(0, 396));
+        assert_eq!(ranges.range(&ctx, next), Range::new(1, 100));
+        assert_eq!(ranges.range(&ctx, rem), Range::new(-7, 7));
+        assert_eq!(ranges.range(&ctx, cond), Range::new(0, 1));
+        assert!(ranges.range(&ctx, n).is_full());
+
+        let add = next.def_inst(&ctx).unwrap();
+        assert!(!ranges.may_overflow(&ctx, add));
+    }
+}
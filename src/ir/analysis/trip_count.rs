@@ -0,0 +1,327 @@
+//! Trip count estimation of loops.
+//!
+//! The trip count is computed for loops in the canonical form:
+//!
+//! ```text
+//! header: i = phi [c0, preheader], [next, latch]
+//!         ...
+//!         next = add i, c1
+//!         cond = icmp pred (i | next), n
+//!         br cond, ...
+//! ```
+//!
+//! where `i` is a basic induction variable with a constant start `c0`, the
+//! exit test is in the header or the latch, which is the only exiting block of
+//! the loop, and `n` is loop invariant. The count is exact if `n` is a
+//! constant, and bounded by the range of `i32` otherwise.
+
+use super::{BasicIv, Cfg, InductionVars, Loop, LoopInfo};
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::{Context, InstKind, IntBinaryOp, IntCmpCond, Value};
+
+/// The number of times the back edges of a loop are taken.
+///
+/// The header of the loop executes one more time than the trip count, so a
+/// loop tested in the header with a trip count of 0 never executes the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TripCount {
+    /// The exact trip count.
+    Exact(u64),
+    /// An upper bound of the trip count.
+    AtMost(u64),
+}
+
+impl TripCount {
+    /// Get the exact trip count, if known.
+    pub fn exact(self) -> Option<u64> {
+        match self {
+            TripCount::Exact(count) => Some(count),
+            TripCount::AtMost(_) => None,
+        }
+    }
+
+    /// Get an upper bound of the trip count.
+    pub fn upper_bound(self) -> u64 {
+        match self {
+            TripCount::Exact(count) | TripCount::AtMost(count) => count,
+        }
+    }
+}
+
+/// Count how many times `start + j * step` satisfies `cond` against `bound`
+/// for consecutive `j` from 0, i.e., the first `j` failing the test.
+///
+/// Returns `None` if the test never fails without wrapping around.
+fn count_tests(cond: IntCmpCond, start: i64, step: i64, bound: i64) -> Option<i64> {
+    if !cond.eval(start, bound) {
+        return Some(0);
+    }
+    let count = match cond {
+        IntCmpCond::Slt if step > 0 => (bound - start + step - 1) / step,
+        IntCmpCond::Sle if step > 0 => (bound - start) / step + 1,
+        IntCmpCond::Sgt if step < 0 => (start - bound - step - 1) / -step,
+        IntCmpCond::Sge if step < 0 => (start - bound) / -step + 1,
+        IntCmpCond::Ne if (bound - start) % step == 0 && (bound - start) / step > 0 => {
+            (bound - start) / step
+        }
+        // The value changes after the first test.
+        IntCmpCond::Eq => 1,
+        _ => return None,
+    };
+    Some(count)
+}
+
+fn in_i32_range(value: i64) -> bool { i32::try_from(value).is_ok() }
+
+/// The exit test of a loop in the canonical form, normalized to
+/// `tested cond bound`, where the loop continues while the condition holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitTest {
+    /// The induction variable controlling the loop.
+    pub iv: BasicIv,
+    /// Whether the test is on the next value of the induction variable,
+    /// otherwise on the value itself.
+    pub on_next: bool,
+    /// The condition to stay in the loop.
+    pub cond: IntCmpCond,
+    /// The loop-invariant bound.
+    pub bound: Value,
+}
+
+impl LoopInfo {
+    /// Find the exit test of a loop in the canonical form.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(test)`: The exit test on a basic induction variable.
+    /// - `None`: The loop is not in the canonical form.
+    pub fn exit_test(
+        &self,
+        ctx: &Context,
+        cfg: &Cfg,
+        ivs: &InductionVars,
+        lp: Loop,
+    ) -> Option<ExitTest> {
+        let header = self.header(lp);
+        let latch = self.single_latch(lp)?;
+        let exiting = match self.exiting_blocks(cfg, lp)[..] {
+            [exiting] if exiting == header || exiting == latch => exiting,
+            _ => return None,
+        };
+
+        let term = exiting.tail(ctx)?;
+        if !matches!(term.kind(ctx), InstKind::CondBr) {
+            return None;
+        }
+        let continue_on_true = self.contains(lp, term.successor(ctx, 0));
+        let cmp = term.operand(ctx, 0).def_inst(ctx)?;
+        let InstKind::IntBinary {
+            op: IntBinaryOp::ICmp { cond },
+        } = *cmp.kind(ctx)
+        else {
+            return None;
+        };
+        let cond = if continue_on_true { cond } else { cond.inverse() };
+        let (lhs, rhs) = (cmp.operand(ctx, 0), cmp.operand(ctx, 1));
+
+        for &iv in ivs.basic_ivs(lp) {
+            // Normalize the test to `tested cond bound`.
+            let (tested, bound, cond) = if lhs == iv.value || lhs == iv.next {
+                (lhs, rhs, cond)
+            } else if rhs == iv.value || rhs == iv.next {
+                (rhs, lhs, cond.swap())
+            } else {
+                continue;
+            };
+            if !self.is_invariant(ctx, lp, bound) {
+                return None;
+            }
+            return Some(ExitTest {
+                iv,
+                on_next: tested == iv.next,
+                cond,
+                bound,
+            });
+        }
+
+        None
+    }
+
+    /// Compute the trip count of a loop in the canonical form.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(count)`: The exact trip count or an upper bound.
+    /// - `None`: The loop is not in the canonical form, or may not terminate.
+    pub fn trip_count(
+        &self,
+        ctx: &Context,
+        cfg: &Cfg,
+        ivs: &InductionVars,
+        lp: Loop,
+    ) -> Option<TripCount> {
+        let test = self.exit_test(ctx, cfg, ivs, lp)?;
+        let init = test.iv.start.as_const_int(ctx)? as i64;
+        let step = test.iv.step as i64;
+        let start = if test.on_next { init + step } else { init };
+        if !in_i32_range(start) {
+            return None;
+        }
+
+        match test.bound.as_const_int(ctx) {
+            Some(bound) => {
+                let count = count_tests(test.cond, start, step, bound as i64)?;
+                // The value failing the test is also computed by the loop.
+                if !in_i32_range(start + count * step) {
+                    return None;
+                }
+                Some(TripCount::Exact(count as u64))
+            }
+            None => {
+                let extreme = match test.cond {
+                    IntCmpCond::Slt | IntCmpCond::Sle => i32::MAX,
+                    IntCmpCond::Sgt | IntCmpCond::Sge => i32::MIN,
+                    IntCmpCond::Eq => return Some(TripCount::AtMost(1)),
+                    IntCmpCond::Ne => return None,
+                };
+                let count = count_tests(test.cond, start, step, extreme as i64)?;
+                Some(TripCount::AtMost(count as u64))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::analysis::DomTree;
+    use crate::ir::{Block, Func, Inst, Ty};
+
+    /// Build `for (i = init; i cond bound; i += step)`, tested in the header if
+    /// `rotated` is false, or tested on the next value in the latch otherwise.
+    fn build_loop(
+        ctx: &mut Context,
+        init: i32,
+        step: i32,
+        cond: IntCmpCond,
+        bound: Option<i32>,
+        rotated: bool,
+    ) -> Func {
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, "f".to_string(), i32);
+        let n = func.add_param(ctx, i32);
+        let bound = match bound {
+            Some(bound) => Value::i32(ctx, bound),
+            None => n,
+        };
+
+        let entry = Block::new(ctx);
+        let header = Block::new(ctx);
+        let exit = Block::new(ctx);
+        func.push_back(ctx, entry).unwrap();
+        func.push_back(ctx, header).unwrap();
+        let latch = if rotated {
+            header
+        } else {
+            let latch = Block::new(ctx);
+            func.push_back(ctx, latch).unwrap();
+            latch
+        };
+        func.push_back(ctx, exit).unwrap();
+
+        let br = Inst::br(ctx, header);
+        entry.push_back(ctx, br).unwrap();
+
+        let phi = Inst::phi(ctx, i32);
+        let iv = phi.result(ctx).unwrap();
+        header.push_back(ctx, phi).unwrap();
+        let step = Value::i32(ctx, step);
+        let add = Inst::ibinary(ctx, IntBinaryOp::Add, iv, step);
+        let next = add.result(ctx).unwrap();
+        latch.push_back(ctx, add).unwrap();
+
+        let tested = if rotated { next } else { iv };
+        let cmp = Inst::ibinary(ctx, IntBinaryOp::ICmp { cond }, tested, bound);
+        header.push_back(ctx, cmp).unwrap();
+        let cond = cmp.result(ctx).unwrap();
+        let cond_br = Inst::cond_br(ctx, cond, latch, exit);
+        header.push_back(ctx, cond_br).unwrap();
+        if !rotated {
+            let br = Inst::br(ctx, header);
+            latch.push_back(ctx, br).unwrap();
+        }
+
+        let init = Value::i32(ctx, init);
+        phi.insert_incoming(ctx, entry, init);
+        phi.insert_incoming(ctx, latch, next);
+
+        let ret = Inst::ret(ctx, Some(iv));
+        exit.push_back(ctx, ret).unwrap();
+        func
+    }
+
+    fn trip_count(
+        init: i32,
+        step: i32,
+        cond: IntCmpCond,
+        bound: Option<i32>,
+        rotated: bool,
+    ) -> Option<TripCount> {
+        let mut ctx = Context::default();
+        let func = build_loop(&mut ctx, init, step, cond, bound, rotated);
+        let cfg = Cfg::new(&ctx, func);
+        let domtree = DomTree::new(&cfg);
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+        let lp = loop_info.top_level()[0];
+        let ivs = InductionVars::new(&ctx, &cfg, &loop_info);
+        loop_info.trip_count(&ctx, &cfg, &ivs, lp)
+    }
+
+    #[test]
+    fn test_trip_count_exact() {
+        use IntCmpCond::*;
+
+        // i = 0, 3, 6, 9
+        assert_eq!(
+            trip_count(0, 3, Slt, Some(10), false),
+            Some(TripCount::Exact(4))
+        );
+        assert_eq!(
+            trip_count(0, 3, Sle, Some(9), false),
+            Some(TripCount::Exact(4))
+        );
+        assert_eq!(
+            trip_count(10, -2, Sgt, Some(0), false),
+            Some(TripCount::Exact(5))
+        );
+        assert_eq!(
+            trip_count(0, 2, Ne, Some(10), false),
+            Some(TripCount::Exact(5))
+        );
+        // zero-trip loop
+        assert_eq!(
+            trip_count(10, 1, Slt, Some(10), false),
+            Some(TripCount::Exact(0))
+        );
+        // do { ... } while (++i < 5), the back edge is taken for 1..=4
+        assert_eq!(
+            trip_count(0, 1, Slt, Some(5), true),
+            Some(TripCount::Exact(4))
+        );
+    }
+
+    #[test]
+    fn test_trip_count_unknown() {
+        use IntCmpCond::*;
+
+        assert_eq!(
+            trip_count(0, 1, Slt, None, false),
+            Some(TripCount::AtMost(i32::MAX as u64))
+        );
+        // never terminates without overflow
+        assert_eq!(trip_count(0, -1, Slt, Some(10), false), None);
+        assert_eq!(trip_count(0, 3, Ne, Some(10), false), None);
+        // overflows before exiting
+        assert_eq!(trip_count(0, 4, Sle, Some(i32::MAX), false), None);
+    }
+}
@@ -1,19 +1,25 @@
-use std::collections::HashSet;
 use std::fmt;
 
 use super::context::Context;
 use super::def_use::{Usable, User};
 use super::func::Func;
 use super::inst::Inst;
+use super::ty::Ty;
+use super::value::Value;
+use crate::infra::hash::HashSet;
 use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
 use crate::infra::storage::{Arena, ArenaPtr, GenericPtr, Idx};
 
 pub struct BlockData {
-    _self_ptr: Block,
+    pub(super) self_ptr: Block,
 
     /// Users of this block.
     users: HashSet<User<Block>>,
 
+    /// The parameters of this block, only used in the block-parameter flavor
+    /// of SSA.
+    params: Vec<Value>,
+
     next: Option<Block>,
     prev: Option<Block>,
 
@@ -37,8 +43,9 @@ pub struct DisplayBlock<'ctx> {
 impl Block {
     pub fn new(ctx: &mut Context) -> Self {
         ctx.alloc_with(|self_ptr| BlockData {
-            _self_ptr: self_ptr,
-            users: HashSet::new(),
+            self_ptr,
+            users: HashSet::default(),
+            params: Vec::new(),
             next: None,
             prev: None,
             head: None,
@@ -54,12 +61,72 @@ impl Block {
         format!("%bb_{}", self.0.index())
     }
 
-    pub fn display(self, ctx: &Context) -> DisplayBlock { DisplayBlock { ctx, block: self } }
+    /// Add a parameter to the block.
+    pub fn add_param(self, ctx: &mut Context, ty: Ty) -> Value {
+        let index = self.deref(ctx).params.len() as u32;
+        let param = Value::new_block_param(ctx, self, ty, index);
+        self.deref_mut(ctx).params.push(param);
+        param
+    }
+
+    /// Get the parameters of the block.
+    pub fn params(self, ctx: &Context) -> &[Value] { &self.deref(ctx).params }
+
+    /// Remove all the parameters of the block and deallocate them.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if any of the parameters is still used.
+    pub fn clear_params(self, ctx: &mut Context) {
+        let params = std::mem::take(&mut self.deref_mut(ctx).params);
+        for param in params {
+            assert!(
+                param.users(ctx).into_iter().next().is_none(),
+                "removing a block parameter that is still used"
+            );
+            ctx.try_dealloc(param).unwrap();
+        }
+    }
+
+    /// Unlink the block from its function and deallocate it, with its
+    /// parameters.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the block still has instructions, or is still used.
+    pub fn remove(self, ctx: &mut Context) {
+        assert!(
+            self.head(ctx).is_none(),
+            "removing a block that still has instructions"
+        );
+        assert!(
+            self.users(ctx).into_iter().next().is_none(),
+            "removing a block that is still used"
+        );
+        self.clear_params(ctx);
+        self.unlink(ctx);
+        ctx.try_dealloc(self).unwrap();
+    }
+
+    pub fn display(self, ctx: &Context) -> DisplayBlock<'_> { DisplayBlock { ctx, block: self } }
 }
 
 impl fmt::Display for DisplayBlock<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "bb_{}:", self.block.0.index())?;
+        write!(f, "bb_{}", self.block.0.index())?;
+
+        let params = self.block.params(self.ctx);
+        if !params.is_empty() {
+            write!(f, "(")?;
+            for (i, param) in params.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", param.display(self.ctx, true))?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, ":")?;
 
         for inst in self.block.iter(self.ctx) {
             write!(f, "\n\t{}", inst.display(self.ctx))?;
@@ -6,9 +6,15 @@ use super::global::GlobalData;
 use super::inst::InstData;
 use super::ty::TyData;
 use super::value::ValueData;
-use super::Func;
+use super::{Func, Global, Source};
+use crate::infra::intern::ToSymbol;
 use crate::infra::storage::{GenericArena, UniqueArena};
 
+/// The properties of the target the IR depends on.
+///
+/// The backends derive the rest from these, e.g., the register width of
+/// RISC-V is the pointer width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TargetInfo {
     /// Pointer size in bytes.
     pub ptr_size: u32,
@@ -30,6 +36,11 @@ pub struct Context {
 
     /// Target information.
     pub(super) target: TargetInfo,
+
+    /// The source code the IR is generated from, if known.
+    source: Option<Source>,
+    /// The source offset the new instructions are tagged with.
+    pub(super) curr_loc: Option<u32>,
 }
 
 impl Default for Context {
@@ -46,14 +57,45 @@ impl Context {
             values: GenericArena::default(),
             globals: GenericArena::default(),
             target: TargetInfo { ptr_size },
+            source: None,
+            curr_loc: None,
         }
     }
 
     pub fn set_target_info(&mut self, target: TargetInfo) { self.target = target; }
 
+    pub fn target_info(&self) -> &TargetInfo { &self.target }
+
+    /// Set the source code the IR is generated from.
+    pub fn set_source(&mut self, source: Source) { self.source = Some(source); }
+
+    pub fn source(&self) -> Option<&Source> { self.source.as_ref() }
+
+    /// Set the byte offset in the source to tag the new instructions with, or
+    /// `None` to leave them untagged.
+    pub fn set_curr_loc(&mut self, loc: Option<u32>) { self.curr_loc = loc; }
+
+    pub fn curr_loc(&self) -> Option<u32> { self.curr_loc }
+
     pub fn funcs(&self) -> impl Iterator<Item = Func> + '_ {
         self.funcs.iter().map(|data| data.self_ptr)
     }
+
+    pub fn globals(&self) -> impl Iterator<Item = Global> + '_ {
+        self.globals.iter().map(|data| data.self_ptr)
+    }
+
+    /// Find a function by its name.
+    pub fn func_by_name(&self, name: impl ToSymbol) -> Option<Func> {
+        let name = name.to_symbol()?;
+        self.funcs().find(|func| func.name(self) == name)
+    }
+
+    /// Find a global variable by its name.
+    pub fn global_by_name(&self, name: impl ToSymbol) -> Option<Global> {
+        let name = name.to_symbol()?;
+        self.globals().find(|global| global.name(self) == name)
+    }
 }
 
 impl fmt::Display for Context {
@@ -0,0 +1,247 @@
+//! Context-aware debug printing of IR handles.
+//!
+//! The handles ([`Func`], [`Block`], [`Inst`], [`Value`], [`Global`]) are only
+//! arena indices, their derived [`Debug`](fmt::Debug) output does not tell
+//! much. The `debug` helpers pair a handle with the context, and print a
+//! concise description of the entity, e.g.:
+//!
+//! ```text
+//! %v3: i32, result of `%v3 = add i32 %v1, 1` in %bb_2 of @main
+//! ```
+//!
+//! Invalid (deallocated) handles are printed as `<invalid ...>` instead of
+//! panicking, so the helpers are safe to use in panic messages.
+
+use std::fmt;
+
+use super::{Block, Context, Func, Global, Inst, Value, ValueKind};
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::infra::storage::ArenaPtr;
+
+/// A handle paired with the context, for debug printing.
+pub struct DebugHandle<'ctx, T> {
+    ctx: &'ctx Context,
+    handle: T,
+}
+
+impl Func {
+    /// Get a debug-printable description of the function.
+    pub fn debug(self, ctx: &Context) -> DebugHandle<'_, Func> { DebugHandle { ctx, handle: self } }
+}
+
+impl Block {
+    /// Get a debug-printable description of the block.
+    pub fn debug(self, ctx: &Context) -> DebugHandle<'_, Block> { DebugHandle { ctx, handle: self } }
+}
+
+impl Inst {
+    /// Get a debug-printable description of the instruction.
+    pub fn debug(self, ctx: &Context) -> DebugHandle<'_, Inst> { DebugHandle { ctx, handle: self } }
+}
+
+impl Value {
+    /// Get a debug-printable description of the value.
+    pub fn debug(self, ctx: &Context) -> DebugHandle<'_, Value> { DebugHandle { ctx, handle: self } }
+}
+
+impl Global {
+    /// Get a debug-printable description of the global.
+    pub fn debug(self, ctx: &Context) -> DebugHandle<'_, Global> { DebugHandle { ctx, handle: self } }
+}
+
+/// Write the location of a block, i.e., its name and the function.
+fn fmt_block_location(f: &mut fmt::Formatter, ctx: &Context, block: Block) -> fmt::Result {
+    write!(f, "{}", block.name(ctx))?;
+    match block.container(ctx) {
+        Some(func) => write!(f, " of @{}", func.name(ctx)),
+        None => write!(f, " (detached)"),
+    }
+}
+
+impl fmt::Display for DebugHandle<'_, Func> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ctx, func) = (self.ctx, self.handle);
+        if func.try_deref(ctx).is_none() {
+            return write!(f, "<invalid func {:?}>", func);
+        }
+
+        write!(f, "@{}(", func.name(ctx))?;
+        for (i, param) in func.params(ctx).iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", param.ty(ctx).display(ctx))?;
+        }
+        write!(f, ") -> {}", func.ret_ty(ctx).display(ctx))?;
+
+        if func.is_declaration(ctx) {
+            write!(f, ", declaration")
+        } else {
+            write!(f, ", {} blocks", func.iter(ctx).count())
+        }
+    }
+}
+
+impl fmt::Display for DebugHandle<'_, Block> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ctx, block) = (self.ctx, self.handle);
+        if block.try_deref(ctx).is_none() {
+            return write!(f, "<invalid block {:?}>", block);
+        }
+
+        fmt_block_location(f, ctx, block)?;
+        write!(f, ", {} insts", block.iter(ctx).count())?;
+        if let Some(tail) = block.tail(ctx) {
+            write!(f, ", ends with `{}`", tail.display(ctx))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DebugHandle<'_, Inst> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ctx, inst) = (self.ctx, self.handle);
+        if inst.try_deref(ctx).is_none() {
+            return write!(f, "<invalid inst {:?}>", inst);
+        }
+
+        write!(f, "`{}` in ", inst.display(ctx))?;
+        match inst.container(ctx) {
+            Some(block) => fmt_block_location(f, ctx, block),
+            None => write!(f, "no block (detached)"),
+        }
+    }
+}
+
+impl fmt::Display for DebugHandle<'_, Value> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ctx, value) = (self.ctx, self.handle);
+        let Some(data) = value.try_deref(ctx) else {
+            return write!(f, "<invalid value {:?}>", value);
+        };
+
+        match &data.kind {
+            ValueKind::InstResult { inst, ty } => {
+                write!(
+                    f,
+                    "{}: {}, result of {}",
+                    value.display(ctx, false),
+                    ty.display(ctx),
+                    inst.debug(ctx)
+                )
+            }
+            ValueKind::Param { func, ty, index } => {
+                write!(
+                    f,
+                    "{}: {}, param #{} of @{}",
+                    value.display(ctx, false),
+                    ty.display(ctx),
+                    index,
+                    func.name(ctx)
+                )
+            }
+            ValueKind::BlockParam { block, ty, index } => {
+                write!(
+                    f,
+                    "{}: {}, param #{} of ",
+                    value.display(ctx, false),
+                    ty.display(ctx),
+                    index
+                )?;
+                fmt_block_location(f, ctx, *block)
+            }
+            ValueKind::Constant { .. } => {
+                write!(f, "constant {}", value.display(ctx, true))
+            }
+        }
+    }
+}
+
+impl fmt::Display for DebugHandle<'_, Global> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ctx, global) = (self.ctx, self.handle);
+        if global.try_deref(ctx).is_none() {
+            return write!(f, "<invalid global {:?}>", global);
+        }
+
+        write!(
+            f,
+            "@{}: {}, {} in {}",
+            global.name(ctx),
+            global.ty(ctx).display(ctx),
+            if global.is_const(ctx) { "constant" } else { "variable" },
+            global.section(ctx)
+        )
+    }
+}
+
+impl<T> fmt::Debug for DebugHandle<'_, T>
+where
+    Self: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(self, f) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::storage::Arena;
+    use crate::ir::{ConstantValue, IntBinaryOp, Ty};
+
+    #[test]
+    fn test_debug_handles() {
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "main".to_string(), i32);
+        let param = func.add_param(&mut ctx, i32);
+        let block = Block::new(&mut ctx);
+        func.push_back(&mut ctx, block).unwrap();
+
+        let one = Value::i32(&mut ctx, 1);
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, param, one);
+        let sum = add.result(&ctx).unwrap();
+        block.push_back(&mut ctx, add).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(sum));
+        block.push_back(&mut ctx, ret).unwrap();
+
+        let bb = block.name(&ctx);
+        let (p, s) = (
+            param.display(&ctx, false).to_string(),
+            sum.display(&ctx, false).to_string(),
+        );
+
+        assert_eq!(
+            func.debug(&ctx).to_string(),
+            "@main(i32) -> i32, 1 blocks"
+        );
+        assert_eq!(
+            block.debug(&ctx).to_string(),
+            format!("{bb} of @main, 2 insts, ends with `ret i32 {s}`")
+        );
+        assert_eq!(
+            sum.debug(&ctx).to_string(),
+            format!("{s}: i32, result of `{s} = add i32 {p}, 1` in {bb} of @main")
+        );
+        assert_eq!(
+            param.debug(&ctx).to_string(),
+            format!("{p}: i32, param #0 of @main")
+        );
+        assert_eq!(one.debug(&ctx).to_string(), "constant i32 1");
+        assert_eq!(format!("{:?}", one.debug(&ctx)), "constant i32 1");
+
+        let init = ConstantValue::i32(&mut ctx, 0);
+        let global = Global::new(&mut ctx, "g".to_string(), init);
+        assert_eq!(
+            global.debug(&ctx).to_string(),
+            "@g: i32, variable in .bss"
+        );
+
+        let detached = Inst::ret(&mut ctx, None);
+        assert_eq!(
+            detached.debug(&ctx).to_string(),
+            "`ret void` in no block (detached)"
+        );
+        ctx.try_dealloc(detached).unwrap();
+        assert!(detached.debug(&ctx).to_string().starts_with("<invalid inst"));
+    }
+}
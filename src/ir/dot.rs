@@ -0,0 +1,386 @@
+//! Graphviz rendering of the IR.
+//!
+//! The graphs are written in the DOT language, to be rendered with, e.g.,
+//! `dot -Tsvg main.cfg.dot -o main.svg`:
+//!
+//! - [`cfg_dot`]: the control-flow graph of a function, each block with its
+//!   instructions, and the edges of the conditional branches and the switches
+//!   labeled with the conditions taking them.
+//! - [`domtree_dot`] and [`postdomtree_dot`]: the dominator and post-dominator
+//!   trees of a function, each block with its phis, to check where they are
+//!   placed.
+//! - [`callgraph_dot`]: the call graph of the module, each function with its
+//!   number of instructions, to tune the inlining thresholds. The recursive
+//!   cycles are boxed, and the declarations dashed.
+//!
+//! [`GraphOptions`] selects the graphs written by `--emit-cfg`,
+//! `--emit-domtree` and `--emit-callgraph`, after the pipeline or around each
+//! run of a pass, so the effect of a pass can be compared side by side.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use super::analysis::{CallGraph, Cfg, DomTree, PostDomTree};
+use super::{Block, Context, Func, InstKind};
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+
+/// The options of writing the graphs of the functions.
+#[derive(Debug, Clone, Default)]
+pub struct GraphOptions {
+    /// The directory the control-flow graphs are written to, if any.
+    pub cfg: Option<PathBuf>,
+    /// The directory the dominator trees are written to, if any.
+    pub domtree: Option<PathBuf>,
+    /// Write the post-dominator trees along with the dominator trees.
+    pub postdomtree: bool,
+    /// The directory the call graph of the module is written to, if any.
+    pub callgraph: Option<PathBuf>,
+    /// Write the graphs before each run of this pass, instead of after the
+    /// pipeline.
+    pub before: Option<String>,
+    /// Write the graphs after each run of this pass, instead of after the
+    /// pipeline.
+    pub after: Option<String>,
+}
+
+impl GraphOptions {
+    /// Check if any graph is written.
+    pub fn enabled(&self) -> bool {
+        self.cfg.is_some() || self.domtree.is_some() || self.callgraph.is_some()
+    }
+
+    /// Check if the graphs are written around a pass instead of after the
+    /// pipeline.
+    pub fn around_pass(&self) -> bool { self.before.is_some() || self.after.is_some() }
+
+    /// Write the graphs of all the defined functions.
+    ///
+    /// The files are named after the function, the tag, and the graph, e.g.,
+    /// `main.after-simplifycfg.1.cfg.dot` with the tag `after-simplifycfg.1`,
+    /// or `main.domtree.dot` and `main.postdomtree.dot` without a tag. The
+    /// call graph is named `callgraph.dot`, after the tag if any.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: All the graphs are written.
+    /// - `Err((path, err))`: The first file that cannot be written.
+    pub fn write(&self, ctx: &Context, tag: &str) -> Result<(), (PathBuf, std::io::Error)> {
+        let tag = match tag {
+            "" => String::new(),
+            tag => format!(".{}", tag),
+        };
+        for func in ctx.funcs().filter(|func| !func.is_declaration(ctx)) {
+            let name = |graph: &str| format!("{}{}.{}.dot", func.name(ctx), tag, graph);
+            if let Some(dir) = &self.cfg {
+                write_file(&dir.join(name("cfg")), &cfg_dot(ctx, func))?;
+            }
+            if let Some(dir) = &self.domtree {
+                write_file(&dir.join(name("domtree")), &domtree_dot(ctx, func))?;
+                if self.postdomtree {
+                    let path = dir.join(name("postdomtree"));
+                    write_file(&path, &postdomtree_dot(ctx, func))?;
+                }
+            }
+        }
+        if let Some(dir) = &self.callgraph {
+            let path = dir.join(format!("callgraph{}.dot", tag));
+            write_file(&path, &callgraph_dot(ctx))?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a file, with the path in the error.
+fn write_file(path: &Path, contents: &str) -> Result<(), (PathBuf, std::io::Error)> {
+    std::fs::write(path, contents).map_err(|err| (path.to_path_buf(), err))
+}
+
+/// Escape a text in a quoted DOT string, with each line left-justified.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for line in text.lines() {
+        for c in line.chars() {
+            match c {
+                '"' | '\\' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                '\t' => escaped.push_str("  "),
+                _ => escaped.push(c),
+            }
+        }
+        escaped.push_str("\\l");
+    }
+    escaped
+}
+
+/// Render the control-flow graph of a function.
+///
+/// The blocks are the nodes, in the layout order, including the unreachable
+/// ones, and the entry is drawn bold.
+pub fn cfg_dot(ctx: &Context, func: Func) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "digraph \"{}\" {{", func.name(ctx)).unwrap();
+    writeln!(dot, "  node [shape=box, fontname=monospace];").unwrap();
+    let entry = func.head(ctx);
+    for block in func.iter(ctx) {
+        let style = if Some(block) == entry {
+            ", style=bold"
+        } else {
+            ""
+        };
+        writeln!(
+            dot,
+            "  \"{}\" [label=\"{}\"{}];",
+            block.name(ctx),
+            escape(&block.display(ctx).to_string()),
+            style
+        )
+        .unwrap();
+    }
+    for block in func.iter(ctx) {
+        let Some(term) = block.tail(ctx) else {
+            continue;
+        };
+        for (idx, succ) in term.successor_iter(ctx).enumerate() {
+            let label = match term.kind(ctx) {
+                InstKind::CondBr => Some(if idx == 0 { "T" } else { "F" }.to_string()),
+                InstKind::Switch { .. } if idx == 0 => Some("default".to_string()),
+                InstKind::Switch { cases } => Some(cases[idx - 1].to_string()),
+                _ => None,
+            };
+            write!(dot, "  \"{}\" -> \"{}\"", block.name(ctx), succ.name(ctx)).unwrap();
+            match label {
+                Some(label) => writeln!(dot, " [label=\"{}\"];", label).unwrap(),
+                None => writeln!(dot, ";").unwrap(),
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Get the label of a block in the trees, with its phis.
+fn tree_label(ctx: &Context, block: Block) -> String {
+    let display = block.display(ctx).to_string();
+    let mut label = display.lines().next().unwrap().to_string();
+    for phi in block.iter(ctx).take_while(|inst| inst.is_phi(ctx)) {
+        write!(label, "\n\t{}", phi.display(ctx)).unwrap();
+    }
+    escape(&label)
+}
+
+/// Render a tree of blocks, with the edges from the parents to the children.
+fn tree_dot(ctx: &Context, name: &str, root: &str, tree: &[(Block, Option<Block>)]) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "digraph \"{}\" {{", name).unwrap();
+    writeln!(dot, "  node [shape=box, fontname=monospace];").unwrap();
+    writeln!(dot, "  \"{}\" [shape=ellipse];", root).unwrap();
+    for &(block, _) in tree {
+        let label = tree_label(ctx, block);
+        writeln!(dot, "  \"{}\" [label=\"{}\"];", block.name(ctx), label).unwrap();
+    }
+    for &(block, parent) in tree {
+        let parent = parent.map_or_else(|| root.to_string(), |parent| parent.name(ctx));
+        writeln!(dot, "  \"{}\" -> \"{}\";", parent, block.name(ctx)).unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Render the dominator tree of a function.
+///
+/// The entry hangs from a node named after the function, so the trees of the
+/// functions can be told apart when rendered together. The unreachable blocks
+/// are left out.
+pub fn domtree_dot(ctx: &Context, func: Func) -> String {
+    let cfg = Cfg::new(ctx, func);
+    let domtree = DomTree::new(&cfg);
+    let tree = domtree
+        .preorder()
+        .map(|block| (block, domtree.idom(block)))
+        .collect::<Vec<_>>();
+    let root = format!("@{}", func.name(ctx));
+    tree_dot(ctx, &format!("{} dominators", func.name(ctx)), &root, &tree)
+}
+
+/// Render the post-dominator tree of a function.
+///
+/// The returning blocks not post-dominated by another one hang from the
+/// virtual exit, and the blocks not in the tree are left out, see
+/// [`PostDomTree`].
+pub fn postdomtree_dot(ctx: &Context, func: Func) -> String {
+    let cfg = Cfg::new(ctx, func);
+    let postdomtree = PostDomTree::new(&cfg);
+    let mut tree = Vec::new();
+    let mut stack = postdomtree
+        .roots()
+        .iter()
+        .rev()
+        .copied()
+        .collect::<Vec<_>>();
+    while let Some(block) = stack.pop() {
+        tree.push((block, postdomtree.ipdom(block)));
+        stack.extend(postdomtree.children(block).iter().rev().copied());
+    }
+    let name = format!("{} post-dominators", func.name(ctx));
+    tree_dot(ctx, &name, "exit", &tree)
+}
+
+/// Render the call graph of the module.
+///
+/// The edges are labeled with the number of calls if there are several. The
+/// SCCs of mutually recursive functions are drawn as clusters, and the
+/// self-recursive functions bold.
+pub fn callgraph_dot(ctx: &Context) -> String {
+    let cg = CallGraph::new(ctx);
+    let mut dot = String::new();
+    writeln!(dot, "digraph callgraph {{").unwrap();
+    writeln!(dot, "  node [shape=box, fontname=monospace];").unwrap();
+    for (i, scc) in cg.sccs().iter().enumerate() {
+        let cluster = scc.len() > 1;
+        if cluster {
+            writeln!(dot, "  subgraph cluster_{} {{", i).unwrap();
+            writeln!(
+                dot,
+                "    style=filled; color=lightgrey; label=\"SCC {}\";",
+                i
+            )
+            .unwrap();
+        }
+        for &func in scc {
+            let name = func.name(ctx);
+            let (label, style) = if func.is_declaration(ctx) {
+                (name.to_string(), ", style=dashed")
+            } else {
+                let insts = func
+                    .iter(ctx)
+                    .map(|block| block.iter(ctx).count())
+                    .sum::<usize>();
+                let style = match cg.is_self_recursive(func) {
+                    true => ", style=\"bold,filled\", fillcolor=white",
+                    false => ", style=filled, fillcolor=white",
+                };
+                (format!("{}\\n{} insts", name, insts), style)
+            };
+            let indent = if cluster { "    " } else { "  " };
+            writeln!(
+                dot,
+                "{}\"{}\" [label=\"{}\"{}];",
+                indent, name, label, style
+            )
+            .unwrap();
+        }
+        if cluster {
+            writeln!(dot, "  }}").unwrap();
+        }
+    }
+    for &caller in cg.funcs() {
+        for &callee in cg.callees(caller) {
+            let calls = cg
+                .call_sites(callee)
+                .iter()
+                .filter(|call| {
+                    let block = call.container(ctx).unwrap();
+                    block.container(ctx) == Some(caller)
+                })
+                .count();
+            write!(
+                dot,
+                "  \"{}\" -> \"{}\"",
+                caller.name(ctx),
+                callee.name(ctx)
+            )
+            .unwrap();
+            match calls {
+                1 => writeln!(dot, ";").unwrap(),
+                _ => writeln!(dot, " [label=\"{}\"];", calls).unwrap(),
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Inst, Ty, Value};
+
+    #[test]
+    fn test_cfg_dot() {
+        // entry: br c, then, exit; then: br exit; exit: ret 0
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let i1 = Ty::i1(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let c = func.add_param(&mut ctx, i1);
+        let [entry, then, exit] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, then, exit] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let br = Inst::cond_br(&mut ctx, c, then, exit);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, exit);
+        then.push_back(&mut ctx, br).unwrap();
+        let zero = Value::i32(&mut ctx, 0);
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let dot = cfg_dot(&ctx, func);
+        assert!(dot.starts_with("digraph \"f\" {\n"));
+        assert!(dot.ends_with("}\n"));
+        let [entry, then, exit] = [entry, then, exit].map(|block| block.name(&ctx));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"T\"];", entry, then)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"F\"];", entry, exit)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", then, exit)));
+        // the instructions are in the labels, one per line
+        assert!(dot.contains("\\l  ret i32 0\\l\"];"));
+        assert_eq!(escape("a \"b\"\n\tc\\"), "a \\\"b\\\"\\l  c\\\\\\l");
+
+        let dot = domtree_dot(&ctx, func);
+        assert!(dot.contains(&format!("\"@f\" -> \"{}\";", entry)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", entry, exit)));
+        let dot = postdomtree_dot(&ctx, func);
+        assert!(dot.contains(&format!("\"exit\" -> \"{}\";", exit)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", exit, then)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", exit, entry)));
+    }
+
+    #[test]
+    fn test_callgraph_dot() {
+        // main -> even (twice), putint; even <-> odd
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let [main, even, odd, putint] = ["main", "even", "odd", "putint"]
+            .map(|name| Func::new(&mut ctx, name.to_string(), i32));
+        for (func, callees) in [
+            (main, vec![even, even, putint]),
+            (even, vec![odd]),
+            (odd, vec![even]),
+        ] {
+            let entry = Block::new(&mut ctx);
+            func.push_back(&mut ctx, entry).unwrap();
+            for callee in callees {
+                let call = Inst::call(&mut ctx, callee, vec![]);
+                entry.push_back(&mut ctx, call).unwrap();
+            }
+            let zero = Value::i32(&mut ctx, 0);
+            let ret = Inst::ret(&mut ctx, Some(zero));
+            entry.push_back(&mut ctx, ret).unwrap();
+        }
+
+        let dot = callgraph_dot(&ctx);
+        assert!(dot.contains("\"main\" [label=\"main\\n4 insts\""));
+        assert!(dot.contains("\"putint\" [label=\"putint\", style=dashed];"));
+        assert!(dot.contains("\"main\" -> \"even\" [label=\"2\"];"));
+        assert!(dot.contains("\"main\" -> \"putint\";"));
+        // the recursive cycle is a cluster
+        let cluster = dot.find("subgraph cluster_").unwrap();
+        let end = cluster + dot[cluster..].find("  }").unwrap();
+        assert!(
+            dot[cluster..end].contains("\"even\" [") && dot[cluster..end].contains("\"odd\" [")
+        );
+        assert_eq!(dot.matches("subgraph").count(), 1);
+    }
+}
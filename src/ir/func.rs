@@ -4,18 +4,47 @@ use super::block::Block;
 use super::context::Context;
 use super::ty::Ty;
 use super::value::Value;
+use crate::infra::intern::Symbol;
 use crate::infra::linked_list::LinkedListContainer;
 use crate::infra::storage::{Arena, ArenaPtr, GenericPtr};
 
+/// The memory effect of a function, used to classify calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MemoryEffect {
+    /// The function does not access memory, and has no other side effects.
+    None,
+    /// The function may read memory, but does not write it.
+    ReadOnly,
+    /// The function may read and write memory, or have other side effects.
+    #[default]
+    ReadWrite,
+}
+
+impl fmt::Display for MemoryEffect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryEffect::None => write!(f, "readnone"),
+            MemoryEffect::ReadOnly => write!(f, "readonly"),
+            MemoryEffect::ReadWrite => Ok(()),
+        }
+    }
+}
+
 pub struct FuncData {
     pub(super) self_ptr: Func,
-    name: String,
+    name: Symbol,
     params: Vec<Value>,
     ret_ty: Ty,
-
+    /// The memory effect of the function.
+    memory: MemoryEffect,
+    /// If the function is visible outside of the module.
+    is_external: bool,
+    /// The times the function is entered in the profiled runs, if known.
+    entry_count: Option<u64>,
+
+    /// The first block of the function, `None` for declarations.
     head: Option<Block>,
     tail: Option<Block>,
-    // TODO: Distinguish `define` and `declare`.
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -27,12 +56,16 @@ pub struct DisplayFunc<'ctx> {
 }
 
 impl Func {
-    pub fn new(ctx: &mut Context, name: String, ret_ty: Ty) -> Self {
+    pub fn new(ctx: &mut Context, name: impl Into<Symbol>, ret_ty: Ty) -> Self {
+        let name = name.into();
         ctx.alloc_with(|self_ptr| FuncData {
             self_ptr,
             name,
             params: Vec::new(),
             ret_ty,
+            memory: MemoryEffect::default(),
+            is_external: false,
+            entry_count: None,
             head: None,
             tail: None,
         })
@@ -45,20 +78,65 @@ impl Func {
         param
     }
 
-    pub fn name(self, ctx: &Context) -> &str { &self.deref(ctx).name }
+    pub fn name(self, ctx: &Context) -> Symbol { self.deref(ctx).name }
 
     pub fn params(self, ctx: &Context) -> &[Value] { &self.deref(ctx).params }
 
     pub fn ret_ty(self, ctx: &Context) -> Ty { self.deref(ctx).ret_ty }
 
-    pub fn display(self, ctx: &Context) -> DisplayFunc { DisplayFunc { ctx, func: self } }
+    /// Get the memory effect of the function.
+    pub fn memory_effect(self, ctx: &Context) -> MemoryEffect { self.deref(ctx).memory }
+
+    /// Set the memory effect of the function.
+    pub fn set_memory_effect(self, ctx: &mut Context, memory: MemoryEffect) {
+        self.deref_mut(ctx).memory = memory;
+    }
+
+    /// Check if the function is visible outside of the module, i.e., it must be
+    /// kept even if it is never called in the module.
+    pub fn is_external(self, ctx: &Context) -> bool { self.deref(ctx).is_external }
+
+    /// Mark the function as externally visible or not.
+    pub fn set_external(self, ctx: &mut Context, is_external: bool) {
+        self.deref_mut(ctx).is_external = is_external;
+    }
+
+    /// Get the times the function is entered in the profiled runs, if known.
+    pub fn entry_count(self, ctx: &Context) -> Option<u64> { self.deref(ctx).entry_count }
+
+    pub fn set_entry_count(self, ctx: &mut Context, count: Option<u64>) {
+        self.deref_mut(ctx).entry_count = count;
+    }
+
+    /// Check if the function is only a declaration, i.e., it has no body.
+    pub fn is_declaration(self, ctx: &Context) -> bool { self.head(ctx).is_none() }
+
+    /// Get the names of the functions and globals the body refers to, e.g., the
+    /// callees, in the order of the references, with duplicates.
+    pub fn refs(self, ctx: &Context) -> Vec<Symbol> {
+        let mut refs = Vec::new();
+        for block in self.iter(ctx) {
+            for inst in block.iter(ctx) {
+                for operand in inst.operand_iter(ctx) {
+                    if let Some(value) = operand.as_const(ctx) {
+                        value.collect_refs(&mut refs);
+                    }
+                }
+            }
+        }
+        refs
+    }
+
+    pub fn display(self, ctx: &Context) -> DisplayFunc<'_> { DisplayFunc { ctx, func: self } }
 }
 
 impl fmt::Display for DisplayFunc<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let is_declaration = self.func.is_declaration(self.ctx);
         write!(
             f,
-            "define {} @{}(",
+            "{} {} @{}(",
+            if is_declaration { "declare" } else { "define" },
             self.func.ret_ty(self.ctx).display(self.ctx),
             self.func.name(self.ctx)
         )?;
@@ -70,7 +148,18 @@ impl fmt::Display for DisplayFunc<'_> {
             write!(f, "{}", param.ty(self.ctx).display(self.ctx))?;
         }
 
-        write!(f, ") {{")?;
+        write!(f, ")")?;
+
+        let memory = self.func.memory_effect(self.ctx);
+        if memory != MemoryEffect::ReadWrite {
+            write!(f, " {}", memory)?;
+        }
+
+        if is_declaration {
+            return Ok(());
+        }
+
+        write!(f, " {{")?;
 
         for block in self.func.iter(self.ctx) {
             write!(f, "\n{}", block.display(self.ctx))?;
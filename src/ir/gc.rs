@@ -0,0 +1,189 @@
+//! Garbage collection of the IR [`Context`].
+//!
+//! Transformations like DCE and inlining unlink blocks and instructions from
+//! the module, but the entities stay in the arenas. [`Context::gc`] marks all
+//! the entities reachable from the functions of the module, and sweeps the
+//! rest, so the freed slots can be reused by later allocations. The pass
+//! manager collects whenever the arenas have doubled since the last time, see
+//! [`Context::arena_len`].
+
+use super::{Context, Inst, Usable};
+use crate::infra::hash::HashSet;
+use crate::infra::linked_list::LinkedListContainer;
+
+/// Statistics of a garbage collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// The number of swept blocks.
+    pub blocks: usize,
+    /// The number of swept instructions.
+    pub insts: usize,
+    /// The number of swept values.
+    pub values: usize,
+}
+
+impl Context {
+    /// Get the number of the blocks, instructions and values in the arenas,
+    /// including the unreachable ones not swept yet.
+    pub fn arena_len(&self) -> usize { self.blocks.len() + self.insts.len() + self.values.len() }
+
+    /// Sweep all the blocks, instructions and values that are not reachable
+    /// from the functions in the module.
+    ///
+    /// An entity is reachable if:
+    /// - it is a block in a function, or a block referred to by a reachable
+    ///   instruction (as a successor or a phi incoming block);
+    /// - it is an instruction in a reachable block;
+    /// - it is a parameter of a function or a reachable block, or the result
+    ///   or an operand of a reachable instruction.
+    ///
+    /// Handles to the swept entities are invalidated, so this should only be
+    /// called when no such handles are held outside of the module, e.g.,
+    /// between passes.
+    ///
+    /// # Returns
+    ///
+    /// The number of swept entities of each kind.
+    pub fn gc(&mut self) -> GcStats {
+        let mut live_blocks = HashSet::default();
+        let mut live_insts = HashSet::default();
+        let mut live_values = HashSet::default();
+
+        // The blocks referred to are walked as the ones in the functions, even
+        // if they are not linked into any, so their instructions are kept too.
+        let mut worklist = Vec::new();
+        for func in self.funcs() {
+            live_values.extend(func.params(self).iter().copied());
+            worklist.extend(func.iter(self));
+        }
+        while let Some(block) = worklist.pop() {
+            if !live_blocks.insert(block) {
+                continue;
+            }
+            live_values.extend(block.params(self).iter().copied());
+            for inst in block.iter(self) {
+                live_insts.insert(inst);
+                live_values.extend(inst.result(self));
+                live_values.extend(inst.operand_iter(self));
+                worklist.extend(inst.successor_iter(self));
+                if inst.is_phi(self) {
+                    worklist.extend(inst.incoming_iter(self).map(|(block, _)| block));
+                }
+            }
+        }
+
+        // Remove the uses from the dead instructions, otherwise the live values
+        // and blocks will keep dangling users.
+        let is_dead = |inst: Inst| !live_insts.contains(&inst);
+        for &value in live_values.iter() {
+            let dead_users = value
+                .users(self)
+                .into_iter()
+                .filter(|user| is_dead(user.inst()))
+                .collect::<Vec<_>>();
+            for user in dead_users {
+                value.remove_user(self, user);
+            }
+        }
+        for &block in live_blocks.iter() {
+            let dead_users = block
+                .users(self)
+                .into_iter()
+                .filter(|user| is_dead(user.inst()))
+                .collect::<Vec<_>>();
+            for user in dead_users {
+                block.remove_user(self, user);
+            }
+        }
+
+        GcStats {
+            blocks: self
+                .blocks
+                .retain(|data| live_blocks.contains(&data.self_ptr)),
+            insts: self.insts.retain(|data| live_insts.contains(&data.self_ptr)),
+            values: self
+                .values
+                .retain(|data| live_values.contains(&data.self_ptr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::storage::ArenaPtr;
+    use crate::ir::{Block, Func, IntBinaryOp, Ty, Value};
+
+    #[test]
+    fn test_gc() {
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+
+        let func = Func::new(&mut ctx, "main".to_string(), i32);
+        let param = func.add_param(&mut ctx, i32);
+        let entry = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+
+        let one = Value::i32(&mut ctx, 1);
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, param, one);
+        let sum = add.result(&ctx).unwrap();
+        entry.push_back(&mut ctx, add).unwrap();
+
+        // An instruction that uses a live value, but is never inserted.
+        let two = Value::i32(&mut ctx, 2);
+        let dead = Inst::ibinary(&mut ctx, IntBinaryOp::Mul, sum, two);
+        // A block that is never inserted.
+        let dead_block = Block::new(&mut ctx);
+
+        let ret = Inst::ret(&mut ctx, Some(sum));
+        entry.push_back(&mut ctx, ret).unwrap();
+
+        assert_eq!(sum.users(&ctx).into_iter().count(), 2);
+
+        let stats = ctx.gc();
+        assert_eq!(
+            stats,
+            GcStats {
+                blocks: 1,
+                insts: 1,
+                // the result of the dead instruction, and the constant `2`
+                values: 2,
+            }
+        );
+
+        assert!(dead.try_deref(&ctx).is_none());
+        assert!(dead_block.try_deref(&ctx).is_none());
+        assert!(two.try_deref(&ctx).is_none());
+        // the entry block, `add`, `ret`, the parameter, `sum` and `1`
+        assert_eq!(ctx.arena_len(), 6);
+        assert!(one.try_deref(&ctx).is_some());
+        assert_eq!(sum.users(&ctx).into_iter().count(), 1);
+
+        // Nothing more to collect.
+        assert_eq!(ctx.gc(), GcStats::default());
+    }
+
+    #[test]
+    fn test_gc_unlinked_successor() {
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+
+        let func = Func::new(&mut ctx, "main", i32);
+        let entry = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+
+        // A successor not linked into the function, whose instructions are
+        // still reachable through the branch.
+        let exit = Block::new(&mut ctx);
+        let br = Inst::br(&mut ctx, exit);
+        entry.push_back(&mut ctx, br).unwrap();
+        let zero = Value::i32(&mut ctx, 0);
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        assert_eq!(ctx.gc(), GcStats::default());
+        assert!(ret.try_deref(&ctx).is_some());
+        assert!(zero.try_deref(&ctx).is_some());
+        assert_eq!(exit.iter(&ctx).collect::<Vec<_>>(), vec![ret]);
+    }
+}
@@ -1,31 +1,134 @@
 use std::fmt;
 
 use super::{ConstantValue, Context, Ty};
+use crate::infra::intern::Symbol;
 use crate::infra::storage::{Arena, ArenaPtr, GenericPtr};
 
+/// The section hint of a global variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    /// Initialized, writable data.
+    Data,
+    /// Read-only data.
+    Rodata,
+    /// Zero-initialized data.
+    Bss,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Section::Data => write!(f, ".data"),
+            Section::Rodata => write!(f, ".rodata"),
+            Section::Bss => write!(f, ".bss"),
+        }
+    }
+}
+
 pub struct GlobalData {
     pub(super) self_ptr: Global,
-    name: String,
+    name: Symbol,
     value: ConstantValue,
+    /// The explicit alignment in bytes, `None` for the natural alignment.
+    align: Option<usize>,
+    /// If the global is never written to.
+    is_const: bool,
+    /// If the global is visible outside of the module.
+    is_external: bool,
+    /// The explicit section hint, `None` to infer it from the global.
+    section: Option<Section>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Global(GenericPtr<GlobalData>);
 
 impl Global {
-    pub fn new(ctx: &mut Context, name: String, value: ConstantValue) -> Self {
+    pub fn new(ctx: &mut Context, name: impl Into<Symbol>, value: ConstantValue) -> Self {
+        let name = name.into();
         ctx.alloc_with(|self_ptr| GlobalData {
             self_ptr,
             name,
             value,
+            align: None,
+            is_const: false,
+            is_external: false,
+            section: None,
         })
     }
 
-    pub fn name(self, ctx: &Context) -> &str { &self.deref(ctx).name }
+    pub fn name(self, ctx: &Context) -> Symbol { self.deref(ctx).name }
 
     pub fn value(self, ctx: &Context) -> &ConstantValue { &self.deref(ctx).value }
 
     pub fn ty(self, ctx: &Context) -> Ty { self.value(ctx).ty() }
+
+    /// Get the alignment of the global in bytes.
+    ///
+    /// # Returns
+    /// - The explicit alignment if set, otherwise the natural alignment of the
+    ///   value type.
+    pub fn align(self, ctx: &Context) -> usize {
+        self.deref(ctx)
+            .align
+            .unwrap_or_else(|| self.ty(ctx).align(ctx))
+    }
+
+    /// Set the explicit alignment of the global.
+    ///
+    /// # Panics
+    /// - Panics if `align` is not a power of two.
+    pub fn set_align(self, ctx: &mut Context, align: usize) {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        self.deref_mut(ctx).align = Some(align);
+    }
+
+    /// Check if the global is read-only.
+    pub fn is_const(self, ctx: &Context) -> bool { self.deref(ctx).is_const }
+
+    /// Mark the global as read-only or not.
+    pub fn set_const(self, ctx: &mut Context, is_const: bool) {
+        self.deref_mut(ctx).is_const = is_const;
+    }
+
+    /// Check if the global is visible outside of the module, i.e., it must be
+    /// kept even if it is never used in the module.
+    pub fn is_external(self, ctx: &Context) -> bool { self.deref(ctx).is_external }
+
+    /// Mark the global as externally visible or not.
+    pub fn set_external(self, ctx: &mut Context, is_external: bool) {
+        self.deref_mut(ctx).is_external = is_external;
+    }
+
+    /// Get the section the global should be placed in.
+    ///
+    /// # Returns
+    /// - The explicit section hint if set.
+    /// - Otherwise, [`Section::Rodata`] for constants, [`Section::Bss`] for
+    ///   zero-initialized variables, and [`Section::Data`] for the rest.
+    pub fn section(self, ctx: &Context) -> Section {
+        let data = self.deref(ctx);
+        if let Some(section) = data.section {
+            return section;
+        }
+        if data.is_const {
+            Section::Rodata
+        } else if data.value.is_zero() {
+            Section::Bss
+        } else {
+            Section::Data
+        }
+    }
+
+    /// Get the explicit section hint of the global, if any.
+    pub fn section_hint(self, ctx: &Context) -> Option<Section> { self.deref(ctx).section }
+
+    /// Set the explicit section hint of the global.
+    pub fn set_section(self, ctx: &mut Context, section: Option<Section>) {
+        self.deref_mut(ctx).section = section;
+    }
+
+    /// Get the explicit alignment of the global, if any.
+    pub fn align_hint(self, ctx: &Context) -> Option<usize> { self.deref(ctx).align }
 }
 
 pub struct DisplayGlobal<'ctx> {
@@ -34,17 +137,26 @@ pub struct DisplayGlobal<'ctx> {
 }
 
 impl Global {
-    pub fn display(self, ctx: &Context) -> DisplayGlobal { DisplayGlobal { ctx, global: self } }
+    pub fn display(self, ctx: &Context) -> DisplayGlobal<'_> { DisplayGlobal { ctx, global: self } }
 }
 
 impl fmt::Display for DisplayGlobal<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = self.global.deref(self.ctx);
         write!(
             f,
-            "@{} = global {}",
-            self.global.name(self.ctx),
-            self.global.value(self.ctx).to_string(self.ctx, true)
-        )
+            "@{} = {} {}",
+            data.name,
+            if data.is_const { "constant" } else { "global" },
+            data.value.to_string(self.ctx, true)
+        )?;
+        if let Some(section) = data.section {
+            write!(f, ", section \"{}\"", section)?;
+        }
+        if let Some(align) = data.align {
+            write!(f, ", align {}", align)?;
+        }
+        Ok(())
     }
 }
 
@@ -69,3 +181,46 @@ impl Arena<Global> for Context {
         self.globals.try_deref_mut(ptr.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section() {
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let arr_ty = Ty::array(&mut ctx, i32, 4);
+
+        let one = ConstantValue::i32(&mut ctx, 1);
+        let data = Global::new(&mut ctx, "data".to_string(), one.clone());
+        let zero = ConstantValue::AggregateZero { ty: arr_ty };
+        let zeros = Global::new(&mut ctx, "zeros".to_string(), zero.clone());
+        let table = Global::new(&mut ctx, "table".to_string(), zero);
+        table.set_const(&mut ctx, true);
+        assert_eq!(data.section(&ctx), Section::Data);
+        assert_eq!(zeros.section(&ctx), Section::Bss);
+        // the constants are read-only even if zero-initialized
+        assert_eq!(table.section(&ctx), Section::Rodata);
+        assert_eq!(table.section_hint(&ctx), None);
+
+        // the explicit hints win over the inferred ones
+        zeros.set_section(&mut ctx, Some(Section::Data));
+        assert_eq!(zeros.section(&ctx), Section::Data);
+        assert_eq!(zeros.align(&ctx), 4);
+        zeros.set_align(&mut ctx, 16);
+        assert_eq!(zeros.align(&ctx), 16);
+        assert_eq!(data.align_hint(&ctx), None);
+
+        // only the explicit attributes are printed
+        assert_eq!(data.display(&ctx).to_string(), "@data = global i32 1");
+        assert_eq!(
+            table.display(&ctx).to_string(),
+            "@table = constant [4 x i32] zeroinitializer"
+        );
+        assert_eq!(
+            zeros.display(&ctx).to_string(),
+            "@zeros = global [4 x i32] zeroinitializer, section \".data\", align 16"
+        );
+    }
+}
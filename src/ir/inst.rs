@@ -4,12 +4,14 @@ use std::fmt;
 use super::block::Block;
 use super::context::Context;
 use super::def_use::{Operand, Usable};
+use super::func::{Func, MemoryEffect};
 use super::ty::Ty;
-use super::value::Value;
+use super::value::{ConstantValue, Value, ValueKind};
+use crate::infra::intern::Symbol;
 use crate::infra::linked_list::LinkedListNode;
 use crate::infra::storage::{Arena, ArenaPtr, GenericPtr};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IntCmpCond {
     Eq,
     Ne,
@@ -19,6 +21,46 @@ pub enum IntCmpCond {
     Sge,
 }
 
+impl IntCmpCond {
+    /// Get the condition with the operands swapped, i.e., `a cond b` is
+    /// equivalent to `b cond.swap() a`.
+    pub fn swap(self) -> Self {
+        match self {
+            IntCmpCond::Eq => IntCmpCond::Eq,
+            IntCmpCond::Ne => IntCmpCond::Ne,
+            IntCmpCond::Slt => IntCmpCond::Sgt,
+            IntCmpCond::Sle => IntCmpCond::Sge,
+            IntCmpCond::Sgt => IntCmpCond::Slt,
+            IntCmpCond::Sge => IntCmpCond::Sle,
+        }
+    }
+
+    /// Get the negated condition, i.e., `a cond.inverse() b` is equivalent to
+    /// `!(a cond b)`.
+    pub fn inverse(self) -> Self {
+        match self {
+            IntCmpCond::Eq => IntCmpCond::Ne,
+            IntCmpCond::Ne => IntCmpCond::Eq,
+            IntCmpCond::Slt => IntCmpCond::Sge,
+            IntCmpCond::Sle => IntCmpCond::Sgt,
+            IntCmpCond::Sgt => IntCmpCond::Sle,
+            IntCmpCond::Sge => IntCmpCond::Slt,
+        }
+    }
+
+    /// Evaluate the condition on two signed integers.
+    pub fn eval<T: Ord>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            IntCmpCond::Eq => lhs == rhs,
+            IntCmpCond::Ne => lhs != rhs,
+            IntCmpCond::Slt => lhs < rhs,
+            IntCmpCond::Sle => lhs <= rhs,
+            IntCmpCond::Sgt => lhs > rhs,
+            IntCmpCond::Sge => lhs >= rhs,
+        }
+    }
+}
+
 impl fmt::Display for IntCmpCond {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -32,11 +74,14 @@ impl fmt::Display for IntCmpCond {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IntBinaryOp {
     Add,
     Sub,
     Mul,
+    /// The high half of the signed product, i.e., `(lhs * rhs) >> bits`
+    /// computed in double width.
+    SMulHi,
     SDiv,
     UDiv,
     SRem,
@@ -47,15 +92,40 @@ pub enum IntBinaryOp {
     And,
     Or,
     Xor,
+    /// The signed minimum, e.g., from a clamping `a < b ? a : b`.
+    SMin,
+    /// The signed maximum.
+    SMax,
     ICmp { cond: IntCmpCond },
 }
 
+impl IntBinaryOp {
+    /// Check if the operands can be swapped without changing the result.
+    ///
+    /// Comparisons are not commutative, but can be swapped with
+    /// [`IntCmpCond::swap`].
+    pub fn is_commutative(self) -> bool {
+        matches!(
+            self,
+            IntBinaryOp::Add
+                | IntBinaryOp::Mul
+                | IntBinaryOp::SMulHi
+                | IntBinaryOp::And
+                | IntBinaryOp::Or
+                | IntBinaryOp::Xor
+                | IntBinaryOp::SMin
+                | IntBinaryOp::SMax
+        )
+    }
+}
+
 impl fmt::Display for IntBinaryOp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             IntBinaryOp::Add => write!(f, "add"),
             IntBinaryOp::Sub => write!(f, "sub"),
             IntBinaryOp::Mul => write!(f, "mul"),
+            IntBinaryOp::SMulHi => write!(f, "smulhi"),
             IntBinaryOp::SDiv => write!(f, "sdiv"),
             IntBinaryOp::UDiv => write!(f, "udiv"),
             IntBinaryOp::SRem => write!(f, "srem"),
@@ -66,16 +136,96 @@ impl fmt::Display for IntBinaryOp {
             IntBinaryOp::And => write!(f, "and"),
             IntBinaryOp::Or => write!(f, "or"),
             IntBinaryOp::Xor => write!(f, "xor"),
+            IntBinaryOp::SMin => write!(f, "smin"),
+            IntBinaryOp::SMax => write!(f, "smax"),
             IntBinaryOp::ICmp { cond } => write!(f, "icmp {}", cond),
         }
     }
 }
 
-#[derive(Debug)]
+/// The ordered floating-point comparisons, false if either operand is NaN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatCmpCond {
+    Oeq,
+    One,
+    Olt,
+    Ole,
+    Ogt,
+    Oge,
+}
+
+impl FloatCmpCond {
+    /// Get the condition with the operands swapped, i.e., `a cond b` is
+    /// equivalent to `b cond.swap() a`.
+    pub fn swap(self) -> Self {
+        match self {
+            FloatCmpCond::Oeq => FloatCmpCond::Oeq,
+            FloatCmpCond::One => FloatCmpCond::One,
+            FloatCmpCond::Olt => FloatCmpCond::Ogt,
+            FloatCmpCond::Ole => FloatCmpCond::Oge,
+            FloatCmpCond::Ogt => FloatCmpCond::Olt,
+            FloatCmpCond::Oge => FloatCmpCond::Ole,
+        }
+    }
+
+    /// Evaluate the condition on two floating-point numbers.
+    pub fn eval(self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            FloatCmpCond::Oeq => lhs == rhs,
+            // Unlike `!=`, false if either is NaN.
+            FloatCmpCond::One => lhs.partial_cmp(&rhs).is_some_and(|ord| ord.is_ne()),
+            FloatCmpCond::Olt => lhs < rhs,
+            FloatCmpCond::Ole => lhs <= rhs,
+            FloatCmpCond::Ogt => lhs > rhs,
+            FloatCmpCond::Oge => lhs >= rhs,
+        }
+    }
+}
+
+impl fmt::Display for FloatCmpCond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FloatCmpCond::Oeq => write!(f, "oeq"),
+            FloatCmpCond::One => write!(f, "one"),
+            FloatCmpCond::Olt => write!(f, "olt"),
+            FloatCmpCond::Ole => write!(f, "ole"),
+            FloatCmpCond::Ogt => write!(f, "ogt"),
+            FloatCmpCond::Oge => write!(f, "oge"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatBinaryOp {
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    /// The comparison, with an `i1` result.
+    FCmp { cond: FloatCmpCond },
+}
+
+impl fmt::Display for FloatBinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FloatBinaryOp::FAdd => write!(f, "fadd"),
+            FloatBinaryOp::FSub => write!(f, "fsub"),
+            FloatBinaryOp::FMul => write!(f, "fmul"),
+            FloatBinaryOp::FDiv => write!(f, "fdiv"),
+            FloatBinaryOp::FCmp { cond } => write!(f, "fcmp {}", cond),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CastOp {
     Zext,
     Sext,
     Trunc,
+    /// Signed integer to floating-point.
+    SiToFp,
+    /// Floating-point to signed integer, rounding towards zero.
+    FpToSi,
 }
 
 impl fmt::Display for CastOp {
@@ -84,11 +234,13 @@ impl fmt::Display for CastOp {
             CastOp::Zext => write!(f, "zext"),
             CastOp::Sext => write!(f, "sext"),
             CastOp::Trunc => write!(f, "trunc"),
+            CastOp::SiToFp => write!(f, "sitofp"),
+            CastOp::FpToSi => write!(f, "fptosi"),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum InstKind {
     Alloca {
         /// The type of the allocated memory.
@@ -103,10 +255,19 @@ pub enum InstKind {
     Call,
     Br,
     CondBr,
+    /// Multi-way branch on an integer, with the default destination as the
+    /// first successor, and the destinations of the cases following.
+    Switch {
+        /// The values of the cases, in the order of the successors.
+        cases: Vec<i32>,
+    },
     Ret,
     IntBinary {
         op: IntBinaryOp,
     },
+    FloatBinary {
+        op: FloatBinaryOp,
+    },
     Cast {
         op: CastOp,
     },
@@ -215,6 +376,28 @@ impl<T: Usable> OperandList<T> {
         }
     }
 
+    /// Replace the operand at the given index, and return the old one.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if there is no operand at the given index.
+    fn set(&mut self, idx: usize, operand: Operand<T>) -> Operand<T> {
+        match &mut self.operands[idx] {
+            OperandEntry::Occupied { operand: old } => std::mem::replace(old, operand),
+            _ => panic!("invalid operand index"),
+        }
+    }
+
+    /// Remove all the operands from the list.
+    fn drain(&mut self) -> impl Iterator<Item = Operand<T>> + '_ {
+        self.first_vacant = None;
+        self.len = 0;
+        self.operands.drain(..).filter_map(|entry| match entry {
+            OperandEntry::Occupied { operand } => Some(operand),
+            _ => None,
+        })
+    }
+
     /// Get the operand at the given index.
     ///
     /// # Panics
@@ -245,7 +428,7 @@ impl<T: Usable> OperandList<T> {
 
 pub struct InstData {
     /// Pointer to the instruction itself.
-    _self_ptr: Inst,
+    pub(super) self_ptr: Inst,
     /// The kind of the instruction.
     kind: InstKind,
     /// The operands of the instruction.
@@ -264,8 +447,19 @@ pub struct InstData {
     /// The phi node information, with `predecessor block --> operand` idx
     /// mapping.
     phi_node: HashMap<Block, usize>,
+    /// The arguments passed to the parameters of each successor, as operand
+    /// indices, indexed by the successor index.
+    ///
+    /// Only used for the block-parameter flavor of SSA.
+    succ_args: Vec<Vec<usize>>,
     /// The result of the instruction.
     result: Option<Value>,
+    /// The byte offset in the source of the statement the instruction is
+    /// generated from, if known.
+    loc: Option<u32>,
+    /// The profiled counts of the successors of a conditional branch, if
+    /// known.
+    branch_weights: Option<[u64; 2]>,
     // Linked list pointers.
     next: Option<Inst>,
     prev: Option<Inst>,
@@ -284,14 +478,18 @@ impl Inst {
     /// - `ctx`: The context to create the instruction.
     /// - `kind`: The kind of the instruction.
     /// - `ty`: The type of the instruction result.
-    fn new(ctx: &mut Context, kind: InstKind, ty: Ty) -> Self {
+    pub(super) fn new(ctx: &mut Context, kind: InstKind, ty: Ty) -> Self {
+        let loc = ctx.curr_loc;
         let inst = ctx.alloc_with(|self_ptr| InstData {
-            _self_ptr: self_ptr,
+            self_ptr,
             kind,
             operands: OperandList::default(),
             phi_node: HashMap::default(),
+            succ_args: Vec::new(),
             successors: OperandList::default(),
             result: None,
+            loc,
+            branch_weights: None,
             next: None,
             prev: None,
             container: None,
@@ -354,6 +552,23 @@ impl Inst {
         inst
     }
 
+    /// Create a new floating-point binary operation, with an `i1` result for
+    /// the comparisons.
+    pub fn fbinary(ctx: &mut Context, op: FloatBinaryOp, lhs: Value, rhs: Value) -> Self {
+        if !lhs.ty(ctx).is_float(ctx) || !rhs.ty(ctx).is_float(ctx) {
+            panic!("float binary operation with non-float operands");
+        }
+
+        let ty = match op {
+            FloatBinaryOp::FCmp { .. } => Ty::i1(ctx),
+            _ => lhs.ty(ctx),
+        };
+        let inst = Self::new(ctx, InstKind::FloatBinary { op }, ty);
+        inst.add_operand(ctx, lhs);
+        inst.add_operand(ctx, rhs);
+        inst
+    }
+
     /// Create a new `ret` instruction.
     pub fn ret(ctx: &mut Context, val: Option<Value>) -> Self {
         let void = Ty::void(ctx);
@@ -365,27 +580,95 @@ impl Inst {
     }
 
     /// Create a new `br` instruction.
-    pub fn br(ctx: &mut Context, dest: Block) -> Self {
+    pub fn br(ctx: &mut Context, dest: Block) -> Self { Self::br_with_args(ctx, dest, Vec::new()) }
+
+    /// Create a new `br` instruction, passing arguments to the parameters of
+    /// the destination block.
+    pub fn br_with_args(ctx: &mut Context, dest: Block, args: Vec<Value>) -> Self {
         let void = Ty::void(ctx);
         let inst = Self::new(ctx, InstKind::Br, void);
         inst.add_successor(ctx, dest);
+        for arg in args {
+            inst.push_successor_arg(ctx, 0, arg);
+        }
         inst
     }
 
     /// Create a new conditional branch instruction.
     pub fn cond_br(ctx: &mut Context, cond: Value, then_dest: Block, else_dest: Block) -> Self {
+        Self::cond_br_with_args(ctx, cond, then_dest, Vec::new(), else_dest, Vec::new())
+    }
+
+    /// Create a new conditional branch instruction, passing arguments to the
+    /// parameters of the destination blocks.
+    pub fn cond_br_with_args(
+        ctx: &mut Context,
+        cond: Value,
+        then_dest: Block,
+        then_args: Vec<Value>,
+        else_dest: Block,
+        else_args: Vec<Value>,
+    ) -> Self {
         let void = Ty::void(ctx);
         let inst = Self::new(ctx, InstKind::CondBr, void);
         inst.add_operand(ctx, cond);
         inst.add_successor(ctx, then_dest);
         inst.add_successor(ctx, else_dest);
+        for arg in then_args {
+            inst.push_successor_arg(ctx, 0, arg);
+        }
+        for arg in else_args {
+            inst.push_successor_arg(ctx, 1, arg);
+        }
+        inst
+    }
+
+    /// Create a new `switch` instruction, jumping to the destination of the
+    /// case matching `cond`, or to `default` if there is none.
+    pub fn switch(
+        ctx: &mut Context,
+        cond: Value,
+        default: Block,
+        cases: Vec<(i32, Block)>,
+    ) -> Self {
+        let void = Ty::void(ctx);
+        let values = cases.iter().map(|&(value, _)| value).collect();
+        let inst = Self::new(ctx, InstKind::Switch { cases: values }, void);
+        inst.add_operand(ctx, cond);
+        inst.add_successor(ctx, default);
+        for (_, dest) in cases {
+            inst.add_successor(ctx, dest);
+        }
+        inst
+    }
+
+    /// Create a new `call` instruction.
+    ///
+    /// The callee is stored as the first operand, a reference to the function,
+    /// and the arguments follow.
+    pub fn call(ctx: &mut Context, callee: Func, args: Vec<Value>) -> Self {
+        let ret_ty = callee.ret_ty(ctx);
+        let name = callee.name(ctx);
+        let callee = Value::global_ref(ctx, name, ret_ty);
+        let inst = Self::new(ctx, InstKind::Call, ret_ty);
+        inst.add_operand(ctx, callee);
+        for arg in args {
+            inst.add_operand(ctx, arg);
+        }
+        inst
+    }
+
+    /// Create a new cast instruction, converting `val` to `ty`.
+    pub fn cast(ctx: &mut Context, op: CastOp, val: Value, ty: Ty) -> Self {
+        let inst = Self::new(ctx, InstKind::Cast { op }, ty);
+        inst.add_operand(ctx, val);
         inst
     }
 
     // TODO: Implement constructors for other instructions.
 
     /// Create an operand and add it to the operand list.
-    fn add_operand(self, ctx: &mut Context, operand: Value) {
+    pub(super) fn add_operand(self, ctx: &mut Context, operand: Value) {
         let next_idx = self.deref_mut(ctx).operands.next_idx();
         let operand = Operand::new(ctx, operand, self, next_idx);
         self.try_deref_mut(ctx)
@@ -395,7 +678,7 @@ impl Inst {
     }
 
     /// Create a successor operand and add it to the successor list.
-    fn add_successor(self, ctx: &mut Context, successor: Block) {
+    pub(super) fn add_successor(self, ctx: &mut Context, successor: Block) {
         let next_idx = self.deref_mut(ctx).successors.next_idx();
         let operand = Operand::new(ctx, successor, self, next_idx);
         self.try_deref_mut(ctx)
@@ -404,6 +687,46 @@ impl Inst {
             .insert(operand);
     }
 
+    /// Replace the operand at the given index with `value`.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if there is no operand at the given index.
+    pub fn set_operand(self, ctx: &mut Context, idx: usize, value: Value) {
+        let operand = Operand::new(ctx, value, self, idx);
+        let old = self.deref_mut(ctx).operands.set(idx, operand);
+        old.drop(ctx);
+    }
+
+    /// Remove the instruction from its block and deallocate it.
+    ///
+    /// All the operands are dropped, and the result value is deallocated.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the result of the instruction is still used.
+    pub fn remove(self, ctx: &mut Context) {
+        if let Some(result) = self.result(ctx) {
+            assert!(
+                result.users(ctx).into_iter().next().is_none(),
+                "removing an instruction whose result is still used"
+            );
+            ctx.try_dealloc(result).unwrap();
+        }
+        self.unlink(ctx);
+
+        let operands = self.deref_mut(ctx).operands.drain().collect::<Vec<_>>();
+        for operand in operands {
+            operand.drop(ctx);
+        }
+        let successors = self.deref_mut(ctx).successors.drain().collect::<Vec<_>>();
+        for successor in successors {
+            successor.drop(ctx);
+        }
+
+        ctx.try_dealloc(self).unwrap();
+    }
+
     /// Get the operand at the given index.
     ///
     /// # Panics
@@ -440,16 +763,25 @@ impl Inst {
 
     /// Iterate over incoming block and values
     ///
+    /// The incomings are in the order of their operands, so the phis are
+    /// printed the same way in each run.
+    ///
     /// # Panics
     ///
     /// - Panics if the instruction is not a phi node.
     pub fn incoming_iter(self, ctx: &Context) -> impl Iterator<Item = (Block, Value)> + '_ {
         assert!(self.is_phi(ctx), "not a phi node");
 
-        self.deref(ctx)
+        let mut incomings = self
+            .deref(ctx)
             .phi_node
             .iter()
-            .map(move |(&block, &idx)| (block, self.operand(ctx, idx)))
+            .map(|(&block, &idx)| (idx, block))
+            .collect::<Vec<_>>();
+        incomings.sort_unstable();
+        incomings
+            .into_iter()
+            .map(move |(idx, block)| (block, self.operand(ctx, idx)))
     }
 
     /// Add an incoming value to the phi node.
@@ -506,17 +838,215 @@ impl Inst {
         self.deref(ctx).successors.iter().map(|op| op.used())
     }
 
+    /// Append an argument passed to the parameters of the successor at the
+    /// given index.
+    pub fn push_successor_arg(self, ctx: &mut Context, succ_idx: usize, arg: Value) {
+        let next_idx = self.deref_mut(ctx).operands.next_idx();
+        let operand = Operand::new(ctx, arg, self, next_idx);
+        let idx = self.deref_mut(ctx).operands.insert(operand);
+
+        let succ_args = &mut self.deref_mut(ctx).succ_args;
+        if succ_args.len() <= succ_idx {
+            succ_args.resize(succ_idx + 1, Vec::new());
+        }
+        succ_args[succ_idx].push(idx);
+    }
+
+    /// Iterate over the arguments passed to the successor at the given index.
+    pub fn successor_args(self, ctx: &Context, succ_idx: usize) -> impl Iterator<Item = Value> + '_ {
+        self.deref(ctx)
+            .succ_args
+            .get(succ_idx)
+            .into_iter()
+            .flatten()
+            .map(move |&idx| self.operand(ctx, idx))
+    }
+
+    /// Remove all the arguments passed to the successors.
+    pub fn clear_successor_args(self, ctx: &mut Context) {
+        let succ_args = std::mem::take(&mut self.deref_mut(ctx).succ_args);
+        for idx in succ_args.into_iter().flatten() {
+            let operand = self.deref_mut(ctx).operands.remove(idx);
+            operand.drop(ctx);
+        }
+    }
+
+    /// Replace the successor at the given index with `block`.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if there is no successor at the given index.
+    pub fn set_successor(self, ctx: &mut Context, idx: usize, block: Block) {
+        let successor = Operand::new(ctx, block, self, idx);
+        let old = self.deref_mut(ctx).successors.set(idx, successor);
+        old.drop(ctx);
+    }
+
+    /// Create a copy of the instruction, not linked to any block, with the
+    /// operands, the successors and the incoming blocks of phis mapped.
+    ///
+    /// The values and blocks missing from the maps are kept as they are.
+    pub fn clone_with(
+        self,
+        ctx: &mut Context,
+        values: &HashMap<Value, Value>,
+        blocks: &HashMap<Block, Block>,
+    ) -> Inst {
+        let value = |v: Value| values.get(&v).copied().unwrap_or(v);
+        let block = |b: Block| blocks.get(&b).copied().unwrap_or(b);
+
+        let ty = match self.result(ctx) {
+            Some(result) => result.ty(ctx),
+            None => Ty::void(ctx),
+        };
+        let inst = Self::new(ctx, self.kind(ctx).clone(), ty);
+        inst.set_loc(ctx, self.loc(ctx));
+        inst.set_branch_weights(ctx, self.branch_weights(ctx));
+
+        if self.is_phi(ctx) {
+            let incomings = self.incoming_iter(ctx).collect::<Vec<_>>();
+            for (pred, incoming) in incomings {
+                inst.insert_incoming(ctx, block(pred), value(incoming));
+            }
+            return inst;
+        }
+
+        // The arguments of the successors are operands too, but they are
+        // added along with their successors.
+        let data = self.deref(ctx);
+        let arg_indices = data.succ_args.concat();
+        let operands = data
+            .operands
+            .operands
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, entry)| match entry {
+                OperandEntry::Occupied { operand } if !arg_indices.contains(&idx) => {
+                    Some(operand.used())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let successors = self
+            .successor_iter(ctx)
+            .enumerate()
+            .map(|(idx, succ)| (succ, self.successor_args(ctx, idx).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+
+        for operand in operands {
+            inst.add_operand(ctx, value(operand));
+        }
+        for (idx, (succ, args)) in successors.into_iter().enumerate() {
+            inst.add_successor(ctx, block(succ));
+            for arg in args {
+                inst.push_successor_arg(ctx, idx, value(arg));
+            }
+        }
+        inst
+    }
+
     /// Get a displayable instance of the instruction.
-    pub fn display(self, ctx: &Context) -> DisplayInst { DisplayInst { ctx, inst: self } }
+    pub fn display(self, ctx: &Context) -> DisplayInst<'_> { DisplayInst { ctx, inst: self } }
 
     /// Get the result of the instruction.
     pub fn result(self, ctx: &Context) -> Option<Value> { self.deref(ctx).result }
 
+    /// Get the byte offset in the source of the statement the instruction is
+    /// generated from.
+    pub fn loc(self, ctx: &Context) -> Option<u32> { self.deref(ctx).loc }
+
+    pub fn set_loc(self, ctx: &mut Context, loc: Option<u32>) { self.deref_mut(ctx).loc = loc; }
+
+    /// Get the times each successor of a conditional branch is taken in the
+    /// profiled runs, if known.
+    pub fn branch_weights(self, ctx: &Context) -> Option<[u64; 2]> {
+        self.deref(ctx).branch_weights
+    }
+
+    pub fn set_branch_weights(self, ctx: &mut Context, weights: Option<[u64; 2]>) {
+        self.deref_mut(ctx).branch_weights = weights;
+    }
+
     /// Get the kind of the instruction.
     pub fn kind(self, ctx: &Context) -> &InstKind { &self.deref(ctx).kind }
 
     /// Check if this is a phi node.
     pub fn is_phi(self, ctx: &Context) -> bool { matches!(self.deref(ctx).kind, InstKind::Phi) }
+
+    /// Get the name of the called function.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the instruction is not a call.
+    pub fn callee(self, ctx: &Context) -> Symbol {
+        assert!(
+            matches!(self.kind(ctx), InstKind::Call),
+            "not a call instruction"
+        );
+        match &self.operand(ctx, 0).deref(ctx).kind {
+            ValueKind::Constant {
+                value: ConstantValue::GlobalRef { name, .. },
+            } => *name,
+            _ => panic!("callee is not a function reference"),
+        }
+    }
+
+    /// Get the memory effect of the called function.
+    ///
+    /// Calls to functions not in the module are assumed to read and write
+    /// memory.
+    fn callee_memory_effect(self, ctx: &Context) -> MemoryEffect {
+        ctx.func_by_name(self.callee(ctx))
+            .map(|func| func.memory_effect(ctx))
+            .unwrap_or_default()
+    }
+
+    /// Check if the instruction terminates a block.
+    pub fn is_terminator(self, ctx: &Context) -> bool {
+        matches!(
+            self.kind(ctx),
+            InstKind::Br | InstKind::CondBr | InstKind::Switch { .. } | InstKind::Ret
+        )
+    }
+
+    /// Check if the instruction may read memory.
+    pub fn may_read_memory(self, ctx: &Context) -> bool {
+        match self.kind(ctx) {
+            InstKind::Load => true,
+            InstKind::Call => self.callee_memory_effect(ctx) != MemoryEffect::None,
+            _ => false,
+        }
+    }
+
+    /// Check if the instruction may write memory.
+    pub fn may_write_memory(self, ctx: &Context) -> bool {
+        match self.kind(ctx) {
+            InstKind::Store => true,
+            InstKind::Call => self.callee_memory_effect(ctx) == MemoryEffect::ReadWrite,
+            _ => false,
+        }
+    }
+
+    /// Check if the instruction may have side effects, i.e., it cannot be
+    /// removed even if its result is unused.
+    ///
+    /// Terminators are considered to have side effects, because they change
+    /// the control flow.
+    pub fn may_have_side_effects(self, ctx: &Context) -> bool {
+        self.may_write_memory(ctx) || self.is_terminator(ctx)
+    }
+
+    /// Check if the instruction is pure, i.e., its result only depends on its
+    /// operands, and it has no side effects.
+    ///
+    /// Pure instructions with the same operands can be merged, and unused pure
+    /// instructions can be removed. `alloca` is not pure, because each one
+    /// yields a distinct address.
+    pub fn is_pure(self, ctx: &Context) -> bool {
+        !matches!(self.kind(ctx), InstKind::Alloca { .. })
+            && !self.may_read_memory(ctx)
+            && !self.may_have_side_effects(ctx)
+    }
 }
 
 pub struct DisplayInst<'ctx> {
@@ -524,8 +1054,92 @@ pub struct DisplayInst<'ctx> {
     inst: Inst,
 }
 
+impl DisplayInst<'_> {
+    /// Format a successor label, with the block arguments if any.
+    fn fmt_successor(&self, f: &mut fmt::Formatter, idx: usize) -> fmt::Result {
+        write!(
+            f,
+            "label {}",
+            self.inst.successor(self.ctx, idx).name(self.ctx)
+        )?;
+        let args = self.inst.successor_args(self.ctx, idx).collect::<Vec<_>>();
+        if !args.is_empty() {
+            write!(f, "(")?;
+            for (i, arg) in args.into_iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", arg.display(self.ctx, true))?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl DisplayInst<'_> {
+    /// Format `smulhi`, which LLVM does not have, as the product in double
+    /// width shifted right, with the intermediate values named after the
+    /// result, e.g., `%v3.wide`.
+    fn fmt_smulhi(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let result = self.inst.result(self.ctx).unwrap();
+        let name = result.display(self.ctx, false);
+        let ty = result.ty(self.ctx);
+        let bits = ty.bitwidth(self.ctx);
+        let wide = format!("i{}", bits * 2);
+        for (i, side) in ["lhs", "rhs"].into_iter().enumerate() {
+            let operand = self.inst.operand(self.ctx, i).display(self.ctx, true);
+            write!(f, "{}.{} = sext {} to {}\n\t", name, side, operand, wide)?;
+        }
+        write!(f, "{0}.wide = mul {1} {0}.lhs, {0}.rhs\n\t", name, wide)?;
+        write!(f, "{0}.hi = ashr {1} {0}.wide, {2}\n\t", name, wide, bits)?;
+        write!(
+            f,
+            "{0} = trunc {1} {0}.hi to {2}",
+            name,
+            wide,
+            ty.display(self.ctx)
+        )
+    }
+
+    /// Format `smin` and `smax`, which LLVM only has as intrinsics, as a
+    /// comparison picking one of the operands.
+    fn fmt_min_max(&self, f: &mut fmt::Formatter, cond: IntCmpCond) -> fmt::Result {
+        let name = self.inst.result(self.ctx).unwrap().display(self.ctx, false);
+        let lhs = self.inst.operand(self.ctx, 0);
+        let rhs = self.inst.operand(self.ctx, 1);
+        write!(
+            f,
+            "{}.cmp = icmp {} {}, {}\n\t",
+            name,
+            cond,
+            lhs.display(self.ctx, true),
+            rhs.display(self.ctx, false)
+        )?;
+        write!(
+            f,
+            "{0} = select i1 {0}.cmp, {1}, {2}",
+            name,
+            lhs.display(self.ctx, true),
+            rhs.display(self.ctx, true)
+        )
+    }
+}
+
 impl fmt::Display for DisplayInst<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.inst.kind(self.ctx) {
+            InstKind::IntBinary {
+                op: IntBinaryOp::SMulHi,
+            } => return self.fmt_smulhi(f),
+            InstKind::IntBinary {
+                op: IntBinaryOp::SMin,
+            } => return self.fmt_min_max(f, IntCmpCond::Slt),
+            InstKind::IntBinary {
+                op: IntBinaryOp::SMax,
+            } => return self.fmt_min_max(f, IntCmpCond::Sgt),
+            _ => {}
+        }
         if let Some(result) = self.inst.result(self.ctx) {
             write!(f, "{}", result.display(self.ctx, false))?;
             write!(f, " = ")?;
@@ -580,6 +1194,15 @@ impl fmt::Display for DisplayInst<'_> {
                     self.inst.operand(self.ctx, 1).display(self.ctx, false)
                 )?;
             }
+            InstKind::FloatBinary { op } => {
+                write!(
+                    f,
+                    "{} {}, {}",
+                    op,
+                    self.inst.operand(self.ctx, 0).display(self.ctx, true),
+                    self.inst.operand(self.ctx, 1).display(self.ctx, false)
+                )?;
+            }
             InstKind::Ret => {
                 if let Some(val) = self.inst.operand_iter(self.ctx).next() {
                     write!(f, "ret {}", val.display(self.ctx, true))?;
@@ -588,15 +1211,74 @@ impl fmt::Display for DisplayInst<'_> {
                 }
             }
             InstKind::Br => {
+                write!(f, "br ")?;
+                self.fmt_successor(f, 0)?;
+            }
+            InstKind::CondBr => {
                 write!(
                     f,
-                    "br label {}",
-                    self.inst.successor(self.ctx, 0).name(self.ctx)
+                    "br {}, ",
+                    self.inst.operand(self.ctx, 0).display(self.ctx, true)
                 )?;
+                self.fmt_successor(f, 0)?;
+                write!(f, ", ")?;
+                self.fmt_successor(f, 1)?;
             }
-            _ => {
-                dbg!(self.inst.kind(self.ctx));
-                todo!("implement the display for other instructions");
+            InstKind::Switch { cases } => {
+                write!(
+                    f,
+                    "switch {}, ",
+                    self.inst.operand(self.ctx, 0).display(self.ctx, true)
+                )?;
+                self.fmt_successor(f, 0)?;
+                write!(f, " [")?;
+                let ty = self.inst.operand(self.ctx, 0).ty(self.ctx);
+                for (i, value) in cases.iter().enumerate() {
+                    write!(f, "{} {}, ", ty.display(self.ctx), value)?;
+                    self.fmt_successor(f, i + 1)?;
+                    if i + 1 != cases.len() {
+                        write!(f, " ")?;
+                    }
+                }
+                write!(f, "]")?;
+            }
+            InstKind::Call => {
+                // The callee reference records the return type.
+                let ty = match &self.inst.operand(self.ctx, 0).deref(self.ctx).kind {
+                    ValueKind::Constant {
+                        value: ConstantValue::GlobalRef { value_ty, .. },
+                    } => *value_ty,
+                    _ => unreachable!("callee is not a function reference"),
+                };
+                write!(
+                    f,
+                    "call {} @{}(",
+                    ty.display(self.ctx),
+                    self.inst.callee(self.ctx)
+                )?;
+                for (i, arg) in self.inst.operand_iter(self.ctx).skip(1).enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg.display(self.ctx, true))?;
+                }
+                write!(f, ")")?;
+            }
+            InstKind::GetElementPtr { bound_ty } => {
+                write!(f, "getelementptr {}", bound_ty.display(self.ctx))?;
+                for operand in self.inst.operand_iter(self.ctx) {
+                    write!(f, ", {}", operand.display(self.ctx, true))?;
+                }
+            }
+            InstKind::Cast { op } => {
+                let ty = self.inst.result(self.ctx).unwrap().ty(self.ctx);
+                write!(
+                    f,
+                    "{} {} to {}",
+                    op,
+                    self.inst.operand(self.ctx, 0).display(self.ctx, true),
+                    ty.display(self.ctx)
+                )?;
             }
         }
 
@@ -685,4 +1367,91 @@ mod tests {
         assert_eq!(inst.operand(&ctx, 0), lhs);
         assert_eq!(inst.operand(&ctx, 1), rhs);
     }
+
+    #[test]
+    fn test_display_smulhi() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = func.add_param(&mut ctx, i32);
+        let magic = Value::i32(&mut ctx, -1840700269);
+        let inst = Inst::ibinary(&mut ctx, IntBinaryOp::SMulHi, x, magic);
+        let v = inst.result(&ctx).unwrap().display(&ctx, false).to_string();
+        let x = x.display(&ctx, false).to_string();
+
+        // LLVM has no `smulhi`, the product is computed in double width
+        let expected = [
+            format!("{v}.lhs = sext i32 {x} to i64"),
+            format!("{v}.rhs = sext i32 -1840700269 to i64"),
+            format!("{v}.wide = mul i64 {v}.lhs, {v}.rhs"),
+            format!("{v}.hi = ashr i64 {v}.wide, 32"),
+            format!("{v} = trunc i64 {v}.hi to i32"),
+        ];
+        assert_eq!(inst.display(&ctx).to_string(), expected.join("\n\t"));
+    }
+
+    #[test]
+    fn test_display_min_max() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = func.add_param(&mut ctx, i32);
+        let zero = Value::i32(&mut ctx, 0);
+        let min = Inst::ibinary(&mut ctx, IntBinaryOp::SMin, x, zero);
+        let max = Inst::ibinary(&mut ctx, IntBinaryOp::SMax, x, zero);
+        let x = x.display(&ctx, false).to_string();
+
+        for (inst, cond) in [(min, "slt"), (max, "sgt")] {
+            let v = inst.result(&ctx).unwrap().display(&ctx, false).to_string();
+            assert_eq!(
+                inst.display(&ctx).to_string(),
+                format!(
+                    "{v}.cmp = icmp {cond} i32 {x}, 0\n\t{v} = select i1 {v}.cmp, i32 {x}, i32 0"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_inst_side_effects() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let ptr = Value::global_ref(&mut ctx, "global".to_string(), i32);
+        let val = Value::i32(&mut ctx, 1);
+
+        let load = Inst::load(&mut ctx, ptr, i32);
+        assert!(load.may_read_memory(&ctx));
+        assert!(!load.may_write_memory(&ctx));
+        assert!(!load.may_have_side_effects(&ctx));
+        assert!(!load.is_pure(&ctx));
+
+        let store = Inst::store(&mut ctx, val, ptr);
+        assert!(store.may_write_memory(&ctx));
+        assert!(store.may_have_side_effects(&ctx));
+
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, val, val);
+        assert!(add.is_pure(&ctx));
+        assert!(!add.is_terminator(&ctx));
+
+        let alloca = Inst::alloca(&mut ctx, i32);
+        assert!(!alloca.is_pure(&ctx));
+        assert!(!alloca.may_have_side_effects(&ctx));
+
+        let ret = Inst::ret(&mut ctx, None);
+        assert!(ret.is_terminator(&ctx));
+        assert!(ret.may_have_side_effects(&ctx));
+
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let call = Inst::call(&mut ctx, func, vec![val]);
+        assert_eq!(call.callee(&ctx), "f");
+        assert!(call.may_write_memory(&ctx));
+
+        func.set_memory_effect(&mut ctx, MemoryEffect::ReadOnly);
+        assert!(call.may_read_memory(&ctx));
+        assert!(!call.may_have_side_effects(&ctx));
+        assert!(!call.is_pure(&ctx));
+
+        func.set_memory_effect(&mut ctx, MemoryEffect::None);
+        assert!(call.is_pure(&ctx));
+    }
 }
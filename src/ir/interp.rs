@@ -0,0 +1,766 @@
+//! An interpreter of the IR.
+//!
+//! The interpreter runs a module from `main`, so the middle end can be tested
+//! without a backend: a program interpreted after the passes should print the
+//! same output, and exit with the same code, as before them. The functions
+//! declared but not defined in the module are the ones of the SysY runtime,
+//! which are built in, reading the input and writing the output given to the
+//! interpreter, see [`Interpreter::new`].
+//!
+//! The memory is a flat array of bytes, with the globals first, from
+//! [`GLOBAL_BASE`], so a null pointer is never valid, and the stack after them.
+//! The values of the instructions are kept in the frames of the calls, and the
+//! frames in a stack of their own, so a deeply recursive program does not
+//! overflow the stack of the interpreter. The behaviors left undefined by the
+//! targets, e.g., a division by zero, or an access outside of the memory, stop
+//! the program with an error.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use thiserror::Error;
+
+use super::{
+    CastOp,
+    ConstantValue,
+    Context,
+    FloatBinaryOp,
+    Func,
+    Inst,
+    InstKind,
+    IntBinaryOp,
+    Ty,
+    TyData,
+    Value,
+    ValueKind,
+};
+use crate::infra::intern::Symbol;
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::infra::storage::ArenaPtr;
+
+mod runtime;
+
+pub use runtime::{format_hex_float, parse_float};
+
+/// The address of the first global.
+pub const GLOBAL_BASE: usize = 0x1000;
+
+/// The bytes of the stack taken by a call, besides its allocas, e.g., for the
+/// return address and the frame pointer.
+const CALL_FRAME_SIZE: usize = 16;
+
+/// The size of the stack by default, in bytes.
+pub const DEFAULT_STACK_SIZE: usize = 8 << 20;
+
+#[derive(Debug, Error)]
+pub enum InterpError {
+    #[error("there is no `main` to run")]
+    NoMain,
+    #[error("`{0}` is neither defined in the module nor in the SysY runtime")]
+    UnknownFunc(String),
+    #[error("access to {size} bytes at {addr:#x}, outside of the memory")]
+    OutOfBounds { addr: usize, size: usize },
+    #[error("division by zero in `{0}`")]
+    DivisionByZero(String),
+    #[error("stack overflow in `{0}`")]
+    StackOverflow(String),
+    #[error("a block of `{0}` does not end with a terminator")]
+    MissingTerminator(String),
+    #[error("no {0} left in the input")]
+    Input(&'static str),
+    #[error("cannot read the input or write the output: {0}")]
+    Io(#[from] io::Error),
+    #[error("the limit of {0} instructions executed is reached")]
+    StepLimit(u64),
+    #[error("{0}")]
+    Trap(String),
+}
+
+/// A value at run time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Val {
+    /// An `i1`, `i8` or `i32`, sign-extended, except the `i1`, which is 0 or 1.
+    Int(i32),
+    Float(f32),
+    Double(f64),
+    /// An address in the memory of the interpreter.
+    Ptr(usize),
+}
+
+impl Val {
+    /// Get the zero of a type.
+    fn zero(ctx: &Context, ty: Ty) -> Self {
+        match ty.try_deref(ctx).unwrap() {
+            TyData::Float32 => Val::Float(0.0),
+            TyData::Float64 => Val::Double(0.0),
+            TyData::Ptr => Val::Ptr(0),
+            _ => Val::Int(0),
+        }
+    }
+
+    /// Get the value of an integer.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the value is a floating-point number.
+    pub fn as_int(self) -> i32 {
+        match self {
+            Val::Int(value) => value,
+            Val::Ptr(addr) => addr as i32,
+            _ => panic!("expected an integer, found {:?}", self),
+        }
+    }
+
+    /// Get the value of a floating-point number, widened to `f64`.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the value is not a floating-point number.
+    pub fn as_float(self) -> f64 {
+        match self {
+            Val::Float(value) => value as f64,
+            Val::Double(value) => value,
+            _ => panic!("expected a floating-point number, found {:?}", self),
+        }
+    }
+
+    /// Get the address of a pointer.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the value is a floating-point number.
+    pub fn as_ptr(self) -> usize {
+        match self {
+            Val::Ptr(addr) => addr,
+            Val::Int(value) => value as u32 as usize,
+            _ => panic!("expected a pointer, found {:?}", self),
+        }
+    }
+}
+
+/// The frame of a call.
+struct Frame {
+    func: Func,
+    /// The next instruction to execute.
+    next: Option<Inst>,
+    values: HashMap<Value, Val>,
+    /// The stack pointer at the entry, restored on returning.
+    sp: usize,
+    /// The call of the caller, whose result is the returned value.
+    call: Option<Inst>,
+}
+
+/// An interpreter of a module, see the [module-level documentation](self).
+pub struct Interpreter<'a> {
+    ctx: &'a Context,
+    memory: Vec<u8>,
+    /// The addresses of the globals, by their names.
+    globals: HashMap<Symbol, usize>,
+    /// The bottom of the stack.
+    stack: usize,
+    /// The top of the stack, growing upwards.
+    sp: usize,
+    input: Box<dyn BufRead + 'a>,
+    output: Box<dyn Write + 'a>,
+    /// The number of instructions executed.
+    steps: u64,
+    step_limit: Option<u64>,
+    timers: runtime::Timers,
+    /// The last dump of the coverage counters, see [`Interpreter::coverage`].
+    coverage: Option<String>,
+    /// The last dump of the profile, see [`Interpreter::profile`].
+    profile: Option<String>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Create an interpreter of the module, with the globals initialized and
+    /// the stack of [`DEFAULT_STACK_SIZE`].
+    pub fn new(ctx: &'a Context, input: impl BufRead + 'a, output: impl Write + 'a) -> Self {
+        let mut globals = HashMap::new();
+        let mut end = GLOBAL_BASE;
+        for global in ctx.globals() {
+            end = end.next_multiple_of(global.align(ctx));
+            globals.insert(global.name(ctx), end);
+            end += global.ty(ctx).bytewidth(ctx);
+        }
+        let stack = end.next_multiple_of(16);
+        let mut interp = Interpreter {
+            ctx,
+            memory: vec![0; stack + DEFAULT_STACK_SIZE],
+            globals,
+            stack,
+            sp: stack,
+            input: Box::new(input),
+            output: Box::new(output),
+            steps: 0,
+            step_limit: None,
+            timers: runtime::Timers::default(),
+            coverage: None,
+            profile: None,
+        };
+        for global in ctx.globals() {
+            let addr = interp.globals[&global.name(ctx)];
+            interp.init(addr, global.value(ctx));
+        }
+        interp
+    }
+
+    /// Set the size of the stack, in bytes.
+    pub fn set_stack_size(&mut self, size: usize) { self.memory.resize(self.stack + size, 0); }
+
+    /// Stop the program once the number of instructions executed reaches the
+    /// limit, e.g., to interpret the programs that may not terminate.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) { self.step_limit = limit; }
+
+    /// Get the number of instructions executed.
+    pub fn steps(&self) -> u64 { self.steps }
+
+    /// Get the dump of the coverage counters of an instrumented program, as the
+    /// runtime writes it at exit, or `None` if not dumped (see
+    /// [`passes::Coverage`](super::passes::Coverage)).
+    pub fn coverage(&self) -> Option<&str> { self.coverage.as_deref() }
+
+    /// Get the profile dumped by an instrumented program, as the runtime writes
+    /// it at exit, or `None` if not dumped (see
+    /// [`passes::ProfileGenerate`](super::passes::ProfileGenerate)).
+    pub fn profile(&self) -> Option<&str> { self.profile.as_deref() }
+
+    /// Run `main`, and get the returned value, or 0 if it returns nothing.
+    ///
+    /// The output is flushed at the end, and the timers of the runtime are
+    /// reported to stderr if any was used, as the runtime does.
+    pub fn run_main(&mut self) -> Result<i32, InterpError> {
+        let main = self
+            .ctx
+            .func_by_name("main")
+            .filter(|main| !main.is_declaration(self.ctx))
+            .ok_or(InterpError::NoMain)?;
+        let ret = self.call(main, Vec::new())?;
+        self.output.flush()?;
+        self.timers.report();
+        Ok(ret.map_or(0, Val::as_int))
+    }
+
+    /// Call a function defined in the module, and get the returned value.
+    pub fn call(&mut self, func: Func, args: Vec<Val>) -> Result<Option<Val>, InterpError> {
+        let ctx = self.ctx;
+        let mut frames = vec![self.enter(func, args, None)];
+        loop {
+            let frame = frames.last_mut().unwrap();
+            let inst = frame
+                .next
+                .ok_or_else(|| InterpError::MissingTerminator(frame.func.name(ctx).to_string()))?;
+            frame.next = inst.next(ctx);
+            if inst.is_phi(ctx) {
+                // assigned by the branches
+                continue;
+            }
+            self.steps += 1;
+            if self.step_limit.is_some_and(|limit| self.steps > limit) {
+                return Err(InterpError::StepLimit(self.step_limit.unwrap()));
+            }
+
+            match inst.kind(ctx) {
+                InstKind::Call => {
+                    let args = inst
+                        .operand_iter(ctx)
+                        .skip(1)
+                        .map(|arg| self.eval(frame, arg))
+                        .collect::<Vec<_>>();
+                    let name = inst.callee(ctx);
+                    match ctx.func_by_name(name) {
+                        Some(callee) if !callee.is_declaration(ctx) => {
+                            let frame = self.enter(callee, args, Some(inst));
+                            if self.sp > self.memory.len() {
+                                return Err(InterpError::StackOverflow(name.to_string()));
+                            }
+                            frames.push(frame);
+                        }
+                        _ => {
+                            let value = self.call_runtime(name.as_str(), &args)?;
+                            if let (Some(result), Some(value)) = (inst.result(ctx), value) {
+                                frame.values.insert(result, value);
+                            }
+                        }
+                    }
+                }
+                InstKind::Ret => {
+                    let value = inst.operand_iter(ctx).next().map(|v| self.eval(frame, v));
+                    let frame = frames.pop().unwrap();
+                    self.sp = frame.sp;
+                    let Some(caller) = frames.last_mut() else {
+                        return Ok(value);
+                    };
+                    let result = frame.call.and_then(|call| call.result(ctx));
+                    if let (Some(result), Some(value)) = (result, value) {
+                        caller.values.insert(result, value);
+                    }
+                }
+                InstKind::Br => self.jump(frame, inst, 0),
+                InstKind::CondBr => {
+                    let cond = self.eval(frame, inst.operand(ctx, 0)).as_int();
+                    self.jump(frame, inst, (cond == 0) as usize);
+                }
+                InstKind::Switch { cases } => {
+                    let cond = self.eval(frame, inst.operand(ctx, 0)).as_int();
+                    let idx = cases.iter().position(|&case| case == cond);
+                    self.jump(frame, inst, idx.map_or(0, |idx| idx + 1));
+                }
+                _ => {
+                    let value = self.exec(frame, inst)?;
+                    if let (Some(result), Some(value)) = (inst.result(ctx), value) {
+                        frame.values.insert(result, value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Create the frame of a call, with the arguments passed to the
+    /// parameters.
+    ///
+    /// Each call takes [`CALL_FRAME_SIZE`] bytes of the stack, so a deep
+    /// recursion overflows it as on the targets.
+    fn enter(&mut self, func: Func, args: Vec<Val>, call: Option<Inst>) -> Frame {
+        let ctx = self.ctx;
+        let sp = self.sp;
+        self.sp += CALL_FRAME_SIZE;
+        Frame {
+            func,
+            next: func.head(ctx).and_then(|entry| entry.head(ctx)),
+            values: func.params(ctx).iter().copied().zip(args).collect(),
+            sp,
+            call,
+        }
+    }
+
+    /// Branch to a successor of the terminator, assigning the phis and the
+    /// parameters of the successor at once.
+    fn jump(&self, frame: &mut Frame, term: Inst, idx: usize) {
+        let ctx = self.ctx;
+        let pred = term.container(ctx).unwrap();
+        let dest = term.successor(ctx, idx);
+        let mut values = dest
+            .params(ctx)
+            .iter()
+            .zip(term.successor_args(ctx, idx))
+            .map(|(&param, arg)| (param, self.eval(frame, arg)))
+            .collect::<Vec<_>>();
+        for phi in dest.iter(ctx).take_while(|inst| inst.is_phi(ctx)) {
+            let value = self.eval(frame, phi.incoming(ctx, pred));
+            values.push((phi.result(ctx).unwrap(), value));
+        }
+        frame.values.extend(values);
+        frame.next = dest.head(ctx);
+    }
+
+    /// Get the value of an operand.
+    fn eval(&self, frame: &Frame, value: Value) -> Val {
+        match &value.deref(self.ctx).kind {
+            ValueKind::Constant { value } => self.constant(value),
+            _ => frame.values[&value],
+        }
+    }
+
+    /// Get the value of a scalar constant.
+    fn constant(&self, value: &ConstantValue) -> Val {
+        match value {
+            ConstantValue::Undef { ty } | ConstantValue::AggregateZero { ty } => {
+                Val::zero(self.ctx, *ty)
+            }
+            ConstantValue::Int1 { value, .. } => Val::Int(*value as i32),
+            ConstantValue::Int8 { value, .. } => Val::Int(*value as i32),
+            ConstantValue::Int32 { value, .. } => Val::Int(*value),
+            ConstantValue::Float32 { bits, .. } => Val::Float(f32::from_bits(*bits)),
+            ConstantValue::GlobalRef { name, .. } => {
+                Val::Ptr(self.globals.get(name).copied().unwrap_or(0))
+            }
+            ConstantValue::Array { .. } => panic!("an array is not a value of a register"),
+        }
+    }
+
+    /// Write the initializer of a global to the memory.
+    fn init(&mut self, addr: usize, value: &ConstantValue) {
+        match value {
+            ConstantValue::Undef { .. } | ConstantValue::AggregateZero { .. } => {}
+            ConstantValue::Array { ty, elems } => {
+                let (elem, _) = ty.as_array(self.ctx).unwrap();
+                let size = elem.bytewidth(self.ctx);
+                for (i, value) in elems.iter().enumerate() {
+                    self.init(addr + i * size, value);
+                }
+            }
+            _ => self.store(addr, value.ty(), self.constant(value)).unwrap(),
+        }
+    }
+
+    /// Execute an instruction other than a call or a terminator, and get its
+    /// result.
+    fn exec(&mut self, frame: &Frame, inst: Inst) -> Result<Option<Val>, InterpError> {
+        let ctx = self.ctx;
+        let operand = |idx| self.eval(frame, inst.operand(ctx, idx));
+        let value = match inst.kind(ctx) {
+            InstKind::Alloca { ty } => {
+                let addr = self.sp.next_multiple_of(ty.align(ctx).max(1));
+                self.sp = addr + ty.bytewidth(ctx);
+                if self.sp > self.memory.len() {
+                    return Err(InterpError::StackOverflow(frame.func.name(ctx).to_string()));
+                }
+                Val::Ptr(addr)
+            }
+            InstKind::Load => {
+                let ty = inst.result(ctx).unwrap().ty(ctx);
+                self.load(operand(0).as_ptr(), ty)?
+            }
+            InstKind::Store => {
+                let ty = inst.operand(ctx, 0).ty(ctx);
+                let (value, addr) = (operand(0), operand(1).as_ptr());
+                self.store(addr, ty, value)?;
+                return Ok(None);
+            }
+            InstKind::GetElementPtr { bound_ty } => {
+                let mut ty = *bound_ty;
+                let mut addr = operand(0).as_ptr() as i64;
+                for (i, idx) in inst.operand_iter(ctx).skip(1).enumerate() {
+                    if i > 0 {
+                        ty = ty.as_array(ctx).unwrap().0;
+                    }
+                    let idx = self.eval(frame, idx).as_int() as i64;
+                    addr += idx * ty.bytewidth(ctx) as i64;
+                }
+                Val::Ptr(addr as usize)
+            }
+            InstKind::IntBinary { op } => {
+                let bits = inst.operand(ctx, 0).ty(ctx).bitwidth(ctx) as u32;
+                let (lhs, rhs) = (operand(0).as_int(), operand(1).as_int());
+                let value = int_binary(*op, bits, lhs, rhs)
+                    .ok_or_else(|| InterpError::DivisionByZero(frame.func.name(ctx).to_string()))?;
+                Val::Int(value)
+            }
+            InstKind::FloatBinary { op } => {
+                let (lhs, rhs) = (operand(0).as_float(), operand(1).as_float());
+                let value = match op {
+                    FloatBinaryOp::FAdd => lhs + rhs,
+                    FloatBinaryOp::FSub => lhs - rhs,
+                    FloatBinaryOp::FMul => lhs * rhs,
+                    FloatBinaryOp::FDiv => lhs / rhs,
+                    FloatBinaryOp::FCmp { cond } => {
+                        return Ok(Some(Val::Int(cond.eval(lhs, rhs) as i32)))
+                    }
+                };
+                match operand(0) {
+                    Val::Double(_) => Val::Double(value),
+                    _ => Val::Float(value as f32),
+                }
+            }
+            InstKind::Cast { op } => {
+                let value = operand(0);
+                let from = inst.operand(ctx, 0).ty(ctx).bitwidth(ctx);
+                let ty = inst.result(ctx).unwrap().ty(ctx);
+                match op {
+                    CastOp::Zext if from < 32 => Val::Int(value.as_int() & ((1 << from) - 1)),
+                    CastOp::Sext if from == 1 => Val::Int(-value.as_int()),
+                    CastOp::Zext | CastOp::Sext => value,
+                    CastOp::Trunc => {
+                        Val::Int(truncate(value.as_int() as i64, ty.bitwidth(ctx) as u32))
+                    }
+                    CastOp::SiToFp if ty.bitwidth(ctx) == 64 => Val::Double(value.as_int() as f64),
+                    CastOp::SiToFp => Val::Float(value.as_int() as f32),
+                    CastOp::FpToSi => Val::Int(value.as_float() as i32),
+                }
+            }
+            InstKind::Phi
+            | InstKind::Call
+            | InstKind::Br
+            | InstKind::CondBr
+            | InstKind::Switch { .. }
+            | InstKind::Ret => unreachable!(),
+        };
+        Ok(Some(value))
+    }
+
+    /// Get the bytes of the memory in a range.
+    fn bytes(&self, addr: usize, size: usize) -> Result<&[u8], InterpError> {
+        addr.checked_add(size)
+            .filter(|&end| addr >= GLOBAL_BASE && end <= self.memory.len())
+            .map(|end| &self.memory[addr..end])
+            .ok_or(InterpError::OutOfBounds { addr, size })
+    }
+
+    /// Get the bytes of the memory in a range, to write them.
+    fn bytes_mut(&mut self, addr: usize, size: usize) -> Result<&mut [u8], InterpError> {
+        addr.checked_add(size)
+            .filter(|&end| addr >= GLOBAL_BASE && end <= self.memory.len())
+            .map(|end| &mut self.memory[addr..end])
+            .ok_or(InterpError::OutOfBounds { addr, size })
+    }
+
+    /// Load a value of a scalar type from the memory.
+    pub fn load(&self, addr: usize, ty: Ty) -> Result<Val, InterpError> {
+        let size = ty.bytewidth(self.ctx);
+        let bytes = self.bytes(addr, size)?;
+        let mut raw = [0; 8];
+        raw[..size].copy_from_slice(bytes);
+        let raw = u64::from_le_bytes(raw);
+        let value = match ty.try_deref(self.ctx).unwrap() {
+            TyData::Int1 => Val::Int(raw as i32 & 1),
+            TyData::Int8 => Val::Int(raw as i8 as i32),
+            TyData::Int32 => Val::Int(raw as i32),
+            TyData::Float32 => Val::Float(f32::from_bits(raw as u32)),
+            TyData::Float64 => Val::Double(f64::from_bits(raw)),
+            TyData::Ptr => Val::Ptr(raw as usize),
+            TyData::Void | TyData::Array { .. } => panic!("loading a value of an aggregate type"),
+        };
+        Ok(value)
+    }
+
+    /// Store a value of a scalar type to the memory.
+    pub fn store(&mut self, addr: usize, ty: Ty, value: Val) -> Result<(), InterpError> {
+        let size = ty.bytewidth(self.ctx);
+        let raw = match value {
+            Val::Int(value) => value as u32 as u64,
+            Val::Float(value) => value.to_bits() as u64,
+            Val::Double(value) => value.to_bits(),
+            Val::Ptr(addr) => addr as u64,
+        };
+        self.bytes_mut(addr, size)?
+            .copy_from_slice(&raw.to_le_bytes()[..size]);
+        Ok(())
+    }
+}
+
+/// Truncate an integer to the bit width, sign-extended to `i32` except for
+/// `i1`, which is 0 or 1.
+fn truncate(value: i64, bits: u32) -> i32 {
+    match bits {
+        1 => (value & 1) as i32,
+        8 => value as i8 as i32,
+        _ => value as i32,
+    }
+}
+
+/// Evaluate a binary operation on integers of the bit width, or get `None`
+/// for a division by zero.
+///
+/// The arithmetic wraps around, and the shift amounts are taken modulo the
+/// bit width, as the targets do.
+fn int_binary(op: IntBinaryOp, bits: u32, lhs: i32, rhs: i32) -> Option<i32> {
+    let mask = u64::MAX >> (64 - bits);
+    let (slhs, srhs) = (lhs as i64, rhs as i64);
+    let (ulhs, urhs) = (slhs as u64 & mask, srhs as u64 & mask);
+    let shift = (urhs % bits as u64) as u32;
+    let value = match op {
+        IntBinaryOp::Add => slhs + srhs,
+        IntBinaryOp::Sub => slhs - srhs,
+        IntBinaryOp::Mul => slhs * srhs,
+        IntBinaryOp::SMulHi => (slhs * srhs) >> bits,
+        IntBinaryOp::SDiv | IntBinaryOp::SRem | IntBinaryOp::UDiv | IntBinaryOp::URem
+            if urhs == 0 =>
+        {
+            return None
+        }
+        IntBinaryOp::SDiv => slhs / srhs,
+        IntBinaryOp::SRem => slhs % srhs,
+        IntBinaryOp::UDiv => (ulhs / urhs) as i64,
+        IntBinaryOp::URem => (ulhs % urhs) as i64,
+        IntBinaryOp::Shl => slhs << shift,
+        IntBinaryOp::LShr => (ulhs >> shift) as i64,
+        IntBinaryOp::AShr => slhs >> shift,
+        IntBinaryOp::And => slhs & srhs,
+        IntBinaryOp::Or => slhs | srhs,
+        IntBinaryOp::Xor => slhs ^ srhs,
+        IntBinaryOp::SMin => slhs.min(srhs),
+        IntBinaryOp::SMax => slhs.max(srhs),
+        IntBinaryOp::ICmp { cond } => return Some(cond.eval(slhs, srhs) as i32),
+    };
+    Some(truncate(value, bits))
+}
+
+/// Run `main` of a module, reading stdin and writing stdout, and get the exit
+/// code, i.e., the returned value modulo 256, as seen by the shell.
+///
+/// The coverage counters and the profile dumped by the program are written to
+/// the files named by `SYSY_COVERAGE` and `SYSY_PROFILE`, or `sysy.cov` and
+/// `sysy.profdata`, as the runtime does.
+pub fn interpret(ctx: &Context) -> Result<u8, InterpError> {
+    let stdout = io::stdout().lock();
+    let mut interp = Interpreter::new(ctx, io::stdin().lock(), io::BufWriter::new(stdout));
+    let code = interp.run_main()?;
+    let dumps = [
+        (interp.coverage(), "SYSY_COVERAGE", "sysy.cov"),
+        (interp.profile(), "SYSY_PROFILE", "sysy.profdata"),
+    ];
+    for (dump, var, default) in dumps {
+        if let Some(dump) = dump {
+            let path = std::env::var(var).unwrap_or_else(|_| default.to_string());
+            std::fs::write(path, dump)?;
+        }
+    }
+    Ok(code as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Global, IntCmpCond};
+
+    /// Build a module with:
+    ///
+    /// - `@a = [4 x i32] [1, 2, 3, 4]`;
+    /// - `fib(n)`, recursive: `n < 2 ? n : fib(n - 1) + fib(n - 2)`;
+    /// - `main`, summing `@a` in a loop with a phi, printing the sum and a
+    ///   newline, and returning `fib(getint()) + sum / d`, `d` read too.
+    fn module() -> Context {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let void = Ty::void(&mut ctx);
+        let arr = Ty::array(&mut ctx, i32, 4);
+        let elems = (1..=4).map(|v| ConstantValue::i32(&mut ctx, v)).collect();
+        Global::new(&mut ctx, "a", ConstantValue::Array { ty: arr, elems });
+        let putint = Func::new(&mut ctx, "putint", void);
+        putint.add_param(&mut ctx, i32);
+        let putch = Func::new(&mut ctx, "putch", void);
+        putch.add_param(&mut ctx, i32);
+        let getint = Func::new(&mut ctx, "getint", i32);
+        let [zero, one, two, four, newline] = [0, 1, 2, 4, 10].map(|v| Value::i32(&mut ctx, v));
+        let lt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let push = |ctx: &mut Context, block: Block, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+
+        let fib = Func::new(&mut ctx, "fib", i32);
+        let n = fib.add_param(&mut ctx, i32);
+        let [entry, base, rec] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, base, rec] {
+            fib.push_back(&mut ctx, block).unwrap();
+        }
+        let cmp = Inst::ibinary(&mut ctx, lt, n, two);
+        let cmp = push(&mut ctx, entry, cmp).unwrap();
+        let br = Inst::cond_br(&mut ctx, cmp, base, rec);
+        push(&mut ctx, entry, br);
+        let ret = Inst::ret(&mut ctx, Some(n));
+        push(&mut ctx, base, ret);
+        let n1 = Inst::ibinary(&mut ctx, IntBinaryOp::Sub, n, one);
+        let n1 = push(&mut ctx, rec, n1).unwrap();
+        let n2 = Inst::ibinary(&mut ctx, IntBinaryOp::Sub, n, two);
+        let n2 = push(&mut ctx, rec, n2).unwrap();
+        let f1 = Inst::call(&mut ctx, fib, vec![n1]);
+        let f1 = push(&mut ctx, rec, f1).unwrap();
+        let f2 = Inst::call(&mut ctx, fib, vec![n2]);
+        let f2 = push(&mut ctx, rec, f2).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, f1, f2);
+        let add = push(&mut ctx, rec, add);
+        let ret = Inst::ret(&mut ctx, add);
+        push(&mut ctx, rec, ret);
+
+        // entry: br header
+        // header: i = phi [0, entry], [i + 1, body]; s = phi [0, entry], [s + a[i],
+        // body]         br i < 4, body, exit
+        // body: ...; br header
+        let main = Func::new(&mut ctx, "main", i32);
+        let [entry, header, body, exit] = [(); 4].map(|_| Block::new(&mut ctx));
+        for block in [entry, header, body, exit] {
+            main.push_back(&mut ctx, block).unwrap();
+        }
+        let br = Inst::br(&mut ctx, header);
+        push(&mut ctx, entry, br);
+        let i = Inst::phi(&mut ctx, i32);
+        let iv = push(&mut ctx, header, i).unwrap();
+        let s = Inst::phi(&mut ctx, i32);
+        let sv = push(&mut ctx, header, s).unwrap();
+        let cmp = Inst::ibinary(&mut ctx, lt, iv, four);
+        let cmp = push(&mut ctx, header, cmp).unwrap();
+        let br = Inst::cond_br(&mut ctx, cmp, body, exit);
+        push(&mut ctx, header, br);
+        let a = Value::global_ref(&mut ctx, "a", arr);
+        let gep = Inst::getelementptr(&mut ctx, arr, a, vec![zero, iv]);
+        let gep = push(&mut ctx, body, gep).unwrap();
+        let load = Inst::load(&mut ctx, gep, i32);
+        let load = push(&mut ctx, body, load).unwrap();
+        let s1 = Inst::ibinary(&mut ctx, IntBinaryOp::Add, sv, load);
+        let s1 = push(&mut ctx, body, s1).unwrap();
+        let i1 = Inst::ibinary(&mut ctx, IntBinaryOp::Add, iv, one);
+        let i1 = push(&mut ctx, body, i1).unwrap();
+        let br = Inst::br(&mut ctx, header);
+        push(&mut ctx, body, br);
+        i.insert_incoming(&mut ctx, entry, zero);
+        i.insert_incoming(&mut ctx, body, i1);
+        s.insert_incoming(&mut ctx, entry, zero);
+        s.insert_incoming(&mut ctx, body, s1);
+
+        let print = Inst::call(&mut ctx, putint, vec![sv]);
+        push(&mut ctx, exit, print);
+        let print = Inst::call(&mut ctx, putch, vec![newline]);
+        push(&mut ctx, exit, print);
+        let n = Inst::call(&mut ctx, getint, Vec::new());
+        let n = push(&mut ctx, exit, n).unwrap();
+        let d = Inst::call(&mut ctx, getint, Vec::new());
+        let d = push(&mut ctx, exit, d).unwrap();
+        let f = Inst::call(&mut ctx, fib, vec![n]);
+        let f = push(&mut ctx, exit, f).unwrap();
+        let div = Inst::ibinary(&mut ctx, IntBinaryOp::SDiv, sv, d);
+        let div = push(&mut ctx, exit, div).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, f, div);
+        let add = push(&mut ctx, exit, add);
+        let ret = Inst::ret(&mut ctx, add);
+        push(&mut ctx, exit, ret);
+        ctx
+    }
+
+    #[test]
+    fn test_interp() {
+        let ctx = module();
+        let mut output = Vec::new();
+        let mut interp = Interpreter::new(&ctx, &b" 10\n-5"[..], &mut output);
+        assert_eq!(interp.run_main().unwrap(), 55 - 2);
+        assert!(interp.steps() > 100);
+        drop(interp);
+        assert_eq!(output, b"10\n");
+
+        let mut output = Vec::new();
+        let mut interp = Interpreter::new(&ctx, &b"3 0"[..], &mut output);
+        let err = interp.run_main().unwrap_err();
+        assert!(matches!(err, InterpError::DivisionByZero(func) if func == "main"));
+        let mut interp = Interpreter::new(&ctx, &b"3"[..], Vec::new());
+        assert!(matches!(
+            interp.run_main(),
+            Err(InterpError::Input("integer"))
+        ));
+        let mut interp = Interpreter::new(&ctx, &b"25 1"[..], Vec::new());
+        interp.set_step_limit(Some(1000));
+        assert!(matches!(
+            interp.run_main(),
+            Err(InterpError::StepLimit(1000))
+        ));
+
+        // each call takes some of the stack
+        let mut interp = Interpreter::new(&ctx, &b"1000 1"[..], Vec::new());
+        interp.set_stack_size(1000);
+        assert!(matches!(
+            interp.run_main(),
+            Err(InterpError::StackOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn test_int_binary() {
+        assert_eq!(
+            int_binary(IntBinaryOp::Add, 32, i32::MAX, 1),
+            Some(i32::MIN)
+        );
+        assert_eq!(
+            int_binary(IntBinaryOp::SDiv, 32, i32::MIN, -1),
+            Some(i32::MIN)
+        );
+        assert_eq!(int_binary(IntBinaryOp::SRem, 32, -7, 2), Some(-1));
+        assert_eq!(int_binary(IntBinaryOp::URem, 32, -1, 0), None);
+        assert_eq!(int_binary(IntBinaryOp::LShr, 32, -1, 28), Some(15));
+        assert_eq!(int_binary(IntBinaryOp::Shl, 32, 1, 33), Some(2));
+        assert_eq!(int_binary(IntBinaryOp::SMulHi, 32, 1 << 30, 8), Some(2));
+        assert_eq!(int_binary(IntBinaryOp::Add, 8, 127, 1), Some(-128));
+        assert_eq!(int_binary(IntBinaryOp::Xor, 1, 1, 1), Some(0));
+    }
+}
@@ -0,0 +1,410 @@
+//! The SysY runtime built in the interpreter.
+//!
+//! The functions behave as the ones of `sylib.c`, including the formats of
+//! `printf` and `scanf` they use, e.g., the floats are printed in hexadecimal
+//! by `%a`, see [`format_hex_float`]. `memset` is provided too, since the
+//! passes may call it, the dumps of the coverage counters and of the profile,
+//! kept by the interpreter instead of written to files, and the trap of the
+//! checks of `--sanitize`, failing the run with its message.
+
+use std::io::Write;
+use std::iter::Peekable;
+use std::time::{Duration, Instant};
+
+use super::{InterpError, Interpreter, Val};
+
+/// The timers of `starttime` and `stoptime`.
+#[derive(Default)]
+pub(super) struct Timers {
+    /// The start of the running timer.
+    start: Option<(i32, Instant)>,
+    /// The lines of the starts and the stops, and the times measured.
+    done: Vec<(i32, i32, Duration)>,
+}
+
+impl Timers {
+    /// Report the times to stderr, as the runtime does at exit.
+    pub(super) fn report(&self) {
+        if self.done.is_empty() {
+            return;
+        }
+        let hms = |time: Duration| {
+            let us = time.as_micros();
+            let s = us / 1_000_000;
+            format!(
+                "{}H-{}M-{}S-{}us",
+                s / 3600,
+                s / 60 % 60,
+                s % 60,
+                us % 1_000_000
+            )
+        };
+        for &(start, stop, time) in self.done.iter() {
+            eprintln!("Timer@{:04}-{:04}: {}", start, stop, hms(time));
+        }
+        let total = self.done.iter().map(|&(_, _, time)| time).sum();
+        eprintln!("TOTAL: {}", hms(total));
+    }
+}
+
+impl Interpreter<'_> {
+    /// Call a function of the runtime, and get the returned value.
+    pub(super) fn call_runtime(
+        &mut self,
+        name: &str,
+        args: &[Val],
+    ) -> Result<Option<Val>, InterpError> {
+        let arg = |idx: usize| args.get(idx).copied().unwrap_or(Val::Int(0));
+        let value = match name {
+            "getint" => Some(Val::Int(self.read_int()?)),
+            "getch" => {
+                let c = self.input.fill_buf()?.first().copied();
+                self.input.consume(c.is_some() as usize);
+                Some(Val::Int(c.map_or(-1, |c| c as i8 as i32)))
+            }
+            "getfloat" => Some(Val::Float(self.read_float()?)),
+            "getarray" | "getfarray" => {
+                let n = self.read_int()?;
+                let addr = arg(0).as_ptr();
+                for i in 0..n.max(0) as usize {
+                    let value = match name {
+                        "getarray" => Val::Int(self.read_int()?),
+                        _ => Val::Float(self.read_float()?),
+                    };
+                    self.write_word(addr + 4 * i, value)?;
+                }
+                Some(Val::Int(n))
+            }
+            "putint" => {
+                write!(self.output, "{}", arg(0).as_int())?;
+                None
+            }
+            "putch" => {
+                self.output.write_all(&[arg(0).as_int() as u8])?;
+                None
+            }
+            "putfloat" => {
+                write!(self.output, "{}", format_hex_float(arg(0).as_float()))?;
+                None
+            }
+            "putarray" | "putfarray" => {
+                let (n, addr) = (arg(0).as_int(), arg(1).as_ptr());
+                write!(self.output, "{}:", n)?;
+                for i in 0..n.max(0) as usize {
+                    let word = self.read_word(addr + 4 * i)?;
+                    match name {
+                        "putarray" => write!(self.output, " {}", word as i32)?,
+                        _ => {
+                            let value = f32::from_bits(word) as f64;
+                            write!(self.output, " {}", format_hex_float(value))?
+                        }
+                    }
+                }
+                writeln!(self.output)?;
+                None
+            }
+            "putf" => {
+                let format = self.read_c_str(arg(0).as_ptr())?;
+                let text = format_printf(&format, &args[1.min(args.len())..]);
+                self.output.write_all(&text)?;
+                None
+            }
+            "starttime" | "_sysy_starttime" => {
+                let line = args.first().map_or(0, |line| line.as_int());
+                self.timers.start = Some((line, Instant::now()));
+                None
+            }
+            "stoptime" | "_sysy_stoptime" => {
+                let line = args.first().map_or(0, |line| line.as_int());
+                if let Some((start, instant)) = self.timers.start.take() {
+                    self.timers.done.push((start, line, instant.elapsed()));
+                }
+                None
+            }
+            "memset" => {
+                let (addr, byte, size) = (arg(0).as_ptr(), arg(1).as_int(), arg(2).as_int());
+                self.bytes_mut(addr, size.max(0) as usize)?.fill(byte as u8);
+                None
+            }
+            "_sysy_coverage_dump" => {
+                let (n, map, counts) = (arg(0).as_int(), arg(1).as_ptr(), arg(2).as_ptr());
+                let mut dump = String::new();
+                for i in 0..n.max(0) as usize {
+                    let line = self.read_word(map + 8 * i)? as i32;
+                    let counter = self.read_word(map + 8 * i + 4)? as usize;
+                    let count = self.read_word(counts + 4 * counter)?;
+                    dump.push_str(&format!("{} {}\n", line, count));
+                }
+                self.coverage = Some(dump);
+                None
+            }
+            "_sysy_profile_dump" => {
+                let (n, mut names, layout) = (arg(0).as_int(), arg(1).as_ptr(), arg(2).as_ptr());
+                let mut counts = arg(3).as_ptr();
+                let mut dump = String::new();
+                for i in 0..n.max(0) as usize {
+                    let name = self.read_c_str(names)?;
+                    names += name.len() + 1;
+                    let entries = self.read_word(counts)?;
+                    dump.push_str(&format!("{} {}\n", String::from_utf8_lossy(&name), entries));
+                    counts += 4;
+                    for branch in 0..self.read_word(layout + 4 * i)? {
+                        let executed = self.read_word(counts)?;
+                        let taken = self.read_word(counts + 4)?;
+                        dump.push_str(&format!("  {} {} {}\n", branch, executed, taken));
+                        counts += 8;
+                    }
+                }
+                self.profile = Some(dump);
+                None
+            }
+            "_sysy_sanitize_trap" => {
+                let msg = self.read_c_str(arg(0).as_ptr())?;
+                return Err(InterpError::Trap(
+                    String::from_utf8_lossy(&msg).into_owned(),
+                ));
+            }
+            _ => return Err(InterpError::UnknownFunc(name.to_string())),
+        };
+        Ok(value)
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u32, InterpError> {
+        let bytes = self.bytes(addr, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn write_word(&mut self, addr: usize, value: Val) -> Result<(), InterpError> {
+        let word = match value {
+            Val::Float(value) => value.to_bits(),
+            value => value.as_int() as u32,
+        };
+        self.bytes_mut(addr, 4)?
+            .copy_from_slice(&word.to_le_bytes());
+        Ok(())
+    }
+
+    /// Read the bytes of a string ending with a null byte.
+    fn read_c_str(&self, addr: usize) -> Result<Vec<u8>, InterpError> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.bytes(addr + bytes.len(), 1)?[0] {
+                0 => return Ok(bytes),
+                byte => bytes.push(byte),
+            }
+        }
+    }
+
+    /// Skip the whitespace in the input, and read the longest token of the
+    /// bytes accepted.
+    fn read_token(&mut self, accept: impl Fn(&[u8], u8) -> bool) -> Result<String, InterpError> {
+        loop {
+            let buf = self.input.fill_buf()?;
+            let spaces = buf.iter().take_while(|c| c.is_ascii_whitespace()).count();
+            let done = spaces < buf.len() || buf.is_empty();
+            self.input.consume(spaces);
+            if done {
+                break;
+            }
+        }
+        let mut token = Vec::new();
+        while let Some(&c) = self.input.fill_buf()?.first() {
+            if !accept(&token, c) {
+                break;
+            }
+            token.push(c);
+            self.input.consume(1);
+        }
+        Ok(String::from_utf8(token).unwrap())
+    }
+
+    /// Read an integer as `scanf("%d")` does.
+    fn read_int(&mut self) -> Result<i32, InterpError> {
+        let token = self.read_token(|token, c| {
+            c.is_ascii_digit() || (token.is_empty() && (c == b'-' || c == b'+'))
+        })?;
+        token
+            .parse::<i64>()
+            .map(|value| value as i32)
+            .map_err(|_| InterpError::Input("integer"))
+    }
+
+    /// Read a float as `scanf("%a")` does, in decimal or in hexadecimal.
+    fn read_float(&mut self) -> Result<f32, InterpError> {
+        let token = self.read_token(|token, c| {
+            c.is_ascii_hexdigit()
+                || matches!(c, b'.' | b'x' | b'X' | b'p' | b'P')
+                || (matches!(c, b'-' | b'+')
+                    && matches!(token.last(), None | Some(b'e' | b'E' | b'p' | b'P')))
+        })?;
+        parse_float(&token).ok_or(InterpError::Input("float"))
+    }
+}
+
+/// Format a float as `printf("%a")` does, e.g., `0x1.8p+1` for 3.
+pub fn format_hex_float(value: f64) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        return format!("{}nan", sign);
+    }
+    if value.is_infinite() {
+        return format!("{}inf", sign);
+    }
+    if value == 0.0 {
+        return format!("{}0x0p+0", sign);
+    }
+    let bits = value.to_bits();
+    let (exp, mantissa) = ((bits >> 52) & 0x7ff, bits & ((1 << 52) - 1));
+    // the subnormals are printed with a leading 0
+    let (lead, exp) = match exp {
+        0 => (0, -1022),
+        _ => (1, exp as i64 - 1023),
+    };
+    let digits = format!("{:013x}", mantissa);
+    let digits = digits.trim_end_matches('0');
+    let point = if digits.is_empty() { "" } else { "." };
+    format!("{}0x{}{}{}p{:+}", sign, lead, point, digits, exp)
+}
+
+/// Parse a float as `strtof` does, in decimal, e.g., `1.5e3`, or in
+/// hexadecimal, e.g., `0x1.8p+1`.
+pub fn parse_float(s: &str) -> Option<f32> {
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let Some(hex) = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    else {
+        return s.parse().ok();
+    };
+    let (mantissa, exp) = match hex.find(['p', 'P']) {
+        Some(p) => (&hex[..p], hex[p + 1..].parse::<i32>().ok()?),
+        None => (hex, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+    let mut value = 0.0f64;
+    for c in int.chars().chain(frac.chars()) {
+        value = value * 16.0 + c.to_digit(16)? as f64;
+    }
+    let value = value * 2f64.powi(exp - 4 * frac.len() as i32);
+    Some(if negative { -value } else { value } as f32)
+}
+
+/// Parse a number in a format.
+fn number(bytes: &mut Peekable<impl Iterator<Item = u8>>) -> Option<usize> {
+    let mut n = None;
+    while let Some(&d @ b'0'..=b'9') = bytes.peek() {
+        n = Some(n.unwrap_or(0) * 10 + (d - b'0') as usize);
+        bytes.next();
+    }
+    n
+}
+
+/// Format the arguments as `printf` does, for the conversions `%d`, `%c`,
+/// `%f`, `%a`, `%x` and `%%`, with the flags `-` and `0`, the width and the
+/// precision.
+fn format_printf(format: &[u8], args: &[Val]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut args = args.iter().copied();
+    let mut bytes = format.iter().copied().peekable();
+    while let Some(c) = bytes.next() {
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        let (mut left, mut zero) = (false, false);
+        while let Some(&flag @ (b'-' | b'0')) = bytes.peek() {
+            left |= flag == b'-';
+            zero |= flag == b'0';
+            bytes.next();
+        }
+        let width = number(&mut bytes).unwrap_or(0);
+        let precision = match bytes.peek() {
+            Some(b'.') => {
+                bytes.next();
+                Some(number(&mut bytes).unwrap_or(0))
+            }
+            _ => None,
+        };
+        // the arguments missing or of another type are taken as zeros
+        let mut int = || match args.next() {
+            Some(arg @ (Val::Int(_) | Val::Ptr(_))) => arg.as_int(),
+            _ => 0,
+        };
+        let text = match bytes.next() {
+            Some(b'%') => "%".to_string(),
+            Some(b'd' | b'i') => int().to_string(),
+            Some(b'c') => (int() as u8 as char).to_string(),
+            Some(b'x') => format!("{:x}", int()),
+            Some(c @ (b'f' | b'a')) => {
+                let value = match args.next() {
+                    Some(arg @ (Val::Float(_) | Val::Double(_))) => arg.as_float(),
+                    _ => 0.0,
+                };
+                match c {
+                    b'f' => format!("{:.*}", precision.unwrap_or(6), value),
+                    _ => format_hex_float(value),
+                }
+            }
+            Some(c) => format!("%{}", c as char),
+            None => "%".to_string(),
+        };
+        let pad = width.saturating_sub(text.len());
+        match (left, zero) {
+            (true, _) => {
+                out.extend_from_slice(text.as_bytes());
+                out.resize(out.len() + pad, b' ');
+            }
+            (false, true) => {
+                // the zeros go after the sign
+                let (sign, digits) = text.split_at(text.starts_with('-') as usize);
+                out.extend_from_slice(sign.as_bytes());
+                out.resize(out.len() + pad, b'0');
+                out.extend_from_slice(digits.as_bytes());
+            }
+            (false, false) => {
+                out.resize(out.len() + pad, b' ');
+                out.extend_from_slice(text.as_bytes());
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_float() {
+        let cases = [
+            (1.0, "0x1p+0"),
+            (3.0, "0x1.8p+1"),
+            (0.5, "0x1p-1"),
+            (-0.0, "-0x0p+0"),
+            (0.1f32 as f64, "0x1.99999ap-4"),
+            (f64::INFINITY, "inf"),
+        ];
+        for (value, text) in cases {
+            assert_eq!(format_hex_float(value), text);
+            assert_eq!(parse_float(text), Some(value as f32));
+        }
+        assert_eq!(parse_float("1.5e3"), Some(1500.0));
+        assert_eq!(parse_float("-0X.8P2"), Some(-2.0));
+        assert_eq!(parse_float("0x"), None);
+
+        let args = [Val::Int(-7), Val::Double(2.5), Val::Int(65)];
+        let text = format_printf(b"%4d|%-3d|%03d|%.2f|%c|%%|%a", &[args[0]; 3]);
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "  -7|-7 |-07|0.00|\0|%|0x0p+0"
+        );
+        let text = format_printf(b"%d %.2f %c\n", &args);
+        assert_eq!(text, b"-7 2.50 A\n");
+    }
+}
@@ -0,0 +1,483 @@
+//! Linking of IR modules.
+//!
+//! [`Context::link`] merges another module into the current one, resolving
+//! symbols by name:
+//!
+//! - A function definition overrides a declaration with the same signature.
+//! - Two declarations of the same function are merged into one.
+//! - Two definitions with the same name are an error, so are two globals with
+//!   the same name, or a global and a function with the same name.
+//!
+//! A function can also be moved to a module of its own by
+//! [`Context::extract_func`], e.g., to be transformed on another thread, and
+//! moved back by [`Context::replace_body`].
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::{
+    Block,
+    ConstantValue,
+    Context,
+    Func,
+    Global,
+    Inst,
+    InstKind,
+    Ty,
+    TyData,
+    Usable,
+    Value,
+    ValueKind,
+};
+use crate::infra::intern::Symbol;
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::infra::storage::{Arena, ArenaPtr};
+
+/// Errors that can occur when linking two modules.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum LinkError {
+    #[error("duplicate definition of symbol `{0}`")]
+    DuplicateDefinition(String),
+    #[error("conflicting signatures of function `{0}`")]
+    SignatureMismatch(String),
+}
+
+/// The state of copying entities from another context.
+struct Linker<'a> {
+    src: &'a Context,
+    /// Mapping from values in the source context to the destination context.
+    values: HashMap<Value, Value>,
+    /// Mapping from blocks in the source context to the destination context.
+    blocks: HashMap<Block, Block>,
+    /// Whether to keep the source offsets of the instructions, only if the
+    /// contexts are generated from the same source.
+    locs: bool,
+}
+
+impl Linker<'_> {
+    fn ty(&self, dst: &mut Context, ty: Ty) -> Ty {
+        match ty.deref(self.src) {
+            TyData::Array { elem, len } => {
+                let elem = self.ty(dst, *elem);
+                Ty::array(dst, elem, *len)
+            }
+            data => dst.alloc(data.clone()),
+        }
+    }
+
+    fn constant(&self, dst: &mut Context, value: &ConstantValue) -> ConstantValue {
+        match value {
+            ConstantValue::Undef { ty } => ConstantValue::Undef {
+                ty: self.ty(dst, *ty),
+            },
+            ConstantValue::AggregateZero { ty } => ConstantValue::AggregateZero {
+                ty: self.ty(dst, *ty),
+            },
+            ConstantValue::Int1 { value, .. } => ConstantValue::i1(dst, *value),
+            ConstantValue::Int8 { value, .. } => ConstantValue::i8(dst, *value),
+            ConstantValue::Int32 { value, .. } => ConstantValue::i32(dst, *value),
+            ConstantValue::Float32 { bits, .. } => ConstantValue::f32(dst, f32::from_bits(*bits)),
+            ConstantValue::Array { ty, elems } => ConstantValue::Array {
+                ty: self.ty(dst, *ty),
+                elems: elems.iter().map(|elem| self.constant(dst, elem)).collect(),
+            },
+            ConstantValue::GlobalRef { name, value_ty, .. } => {
+                let value_ty = self.ty(dst, *value_ty);
+                ConstantValue::global_ref(dst, *name, value_ty)
+            }
+        }
+    }
+
+    fn operand(&mut self, dst: &mut Context, value: Value) -> Value {
+        if let ValueKind::Constant { value } = &value.deref(self.src).kind {
+            let value = self.constant(dst, value);
+            return Value::new_constant(dst, value);
+        }
+        self.values[&value]
+    }
+
+    fn inst_kind(&self, dst: &mut Context, kind: &InstKind) -> InstKind {
+        match kind {
+            InstKind::Alloca { ty } => InstKind::Alloca {
+                ty: self.ty(dst, *ty),
+            },
+            InstKind::GetElementPtr { bound_ty } => InstKind::GetElementPtr {
+                bound_ty: self.ty(dst, *bound_ty),
+            },
+            kind => kind.clone(),
+        }
+    }
+
+    /// Copy a global into the destination context.
+    fn global(&self, dst: &mut Context, global: Global) -> Global {
+        let value = self.constant(dst, global.value(self.src));
+        let new_global = Global::new(dst, global.name(self.src), value);
+        new_global.set_const(dst, global.is_const(self.src));
+        new_global.set_external(dst, global.is_external(self.src));
+        new_global.set_section(dst, global.section_hint(self.src));
+        if let Some(align) = global.align_hint(self.src) {
+            new_global.set_align(dst, align);
+        }
+        new_global
+    }
+
+    /// Declare a function of the source context in the destination context,
+    /// without its body.
+    fn declare(&self, dst: &mut Context, func: Func) -> Func {
+        let ret_ty = self.ty(dst, func.ret_ty(self.src));
+        let new_func = Func::new(dst, func.name(self.src), ret_ty);
+        for &param in func.params(self.src) {
+            let ty = self.ty(dst, param.ty(self.src));
+            new_func.add_param(dst, ty);
+        }
+        new_func.set_memory_effect(dst, func.memory_effect(self.src));
+        new_func.set_external(dst, func.is_external(self.src));
+        new_func.set_entry_count(dst, func.entry_count(self.src));
+        new_func
+    }
+
+    /// Copy the functions and globals named, if not in the destination
+    /// context, as declarations.
+    fn declare_refs(&self, dst: &mut Context, names: Vec<Symbol>) {
+        for name in names {
+            if dst.func_by_name(name).is_some() || dst.global_by_name(name).is_some() {
+                continue;
+            }
+            if let Some(func) = self.src.func_by_name(name) {
+                self.declare(dst, func);
+            } else if let Some(global) = self.src.global_by_name(name) {
+                self.global(dst, global);
+            }
+        }
+    }
+
+    /// Copy the body of `src_func` into `dst_func`, which must have no body.
+    fn body(&mut self, dst: &mut Context, src_func: Func, dst_func: Func) {
+        for (&src, &dst) in src_func.params(self.src).iter().zip(dst_func.params(dst)) {
+            self.values.insert(src, dst);
+        }
+
+        // Create all the blocks and instructions first, the operands may refer
+        // to values defined later.
+        let mut insts = Vec::new();
+        for src_block in src_func.iter(self.src) {
+            let block = Block::new(dst);
+            dst_func.push_back(dst, block).unwrap();
+            self.blocks.insert(src_block, block);
+            for &src_param in src_block.params(self.src) {
+                let ty = self.ty(dst, src_param.ty(self.src));
+                let param = block.add_param(dst, ty);
+                self.values.insert(src_param, param);
+            }
+
+            for src_inst in src_block.iter(self.src) {
+                let kind = self.inst_kind(dst, src_inst.kind(self.src));
+                let ty = match src_inst.result(self.src) {
+                    Some(result) => self.ty(dst, result.ty(self.src)),
+                    None => Ty::void(dst),
+                };
+                let inst = Inst::new(dst, kind, ty);
+                if self.locs {
+                    inst.set_loc(dst, src_inst.loc(self.src));
+                }
+                inst.set_branch_weights(dst, src_inst.branch_weights(self.src));
+                block.push_back(dst, inst).unwrap();
+                if let Some(result) = src_inst.result(self.src) {
+                    self.values.insert(result, inst.result(dst).unwrap());
+                }
+                insts.push((src_inst, inst));
+            }
+        }
+
+        for (src_inst, inst) in insts {
+            if src_inst.is_phi(self.src) {
+                let incomings = src_inst.incoming_iter(self.src).collect::<Vec<_>>();
+                for (block, value) in incomings {
+                    let value = self.operand(dst, value);
+                    inst.insert_incoming(dst, self.blocks[&block], value);
+                }
+            } else {
+                let mut operands = src_inst.operand_iter(self.src).collect::<Vec<_>>();
+                // The block arguments are copied with the successors.
+                match src_inst.kind(self.src) {
+                    InstKind::Br => operands.clear(),
+                    InstKind::CondBr | InstKind::Switch { .. } => operands.truncate(1),
+                    _ => {}
+                }
+                for value in operands {
+                    let value = self.operand(dst, value);
+                    inst.add_operand(dst, value);
+                }
+            }
+            let successors = src_inst.successor_iter(self.src).collect::<Vec<_>>();
+            for (i, succ) in successors.into_iter().enumerate() {
+                inst.add_successor(dst, self.blocks[&succ]);
+                let args = src_inst.successor_args(self.src, i).collect::<Vec<_>>();
+                for arg in args {
+                    let arg = self.operand(dst, arg);
+                    inst.push_successor_arg(dst, i, arg);
+                }
+            }
+        }
+    }
+}
+
+impl Context {
+    /// Link another module into this one.
+    ///
+    /// On error, this module is left unchanged.
+    ///
+    /// # Parameters
+    ///
+    /// - `other`: The module to be merged into this one.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: The modules are merged.
+    /// - `Err(LinkError)`: The symbols of the two modules conflict.
+    pub fn link(&mut self, other: &Context) -> Result<(), LinkError> {
+        // Resolve all the symbols before modifying anything.
+        for global in other.globals() {
+            let name = global.name(other);
+            if self.global_by_name(name).is_some() || self.func_by_name(name).is_some() {
+                return Err(LinkError::DuplicateDefinition(name.to_string()));
+            }
+        }
+
+        let mut linker = Linker {
+            src: other,
+            values: HashMap::new(),
+            blocks: HashMap::new(),
+            locs: false,
+        };
+
+        for func in other.funcs() {
+            let name = func.name(other);
+            if self.global_by_name(name).is_some() {
+                return Err(LinkError::DuplicateDefinition(name.to_string()));
+            }
+            let Some(existing) = self.func_by_name(name) else {
+                continue;
+            };
+            if !existing.is_declaration(self) && !func.is_declaration(other) {
+                return Err(LinkError::DuplicateDefinition(name.to_string()));
+            }
+
+            let ret_ty = linker.ty(self, func.ret_ty(other));
+            let param_tys = func
+                .params(other)
+                .iter()
+                .map(|param| linker.ty(self, param.ty(other)))
+                .collect::<Vec<_>>();
+            let existing_param_tys = existing
+                .params(self)
+                .iter()
+                .map(|param| param.ty(self))
+                .collect::<Vec<_>>();
+            if ret_ty != existing.ret_ty(self) || param_tys != existing_param_tys {
+                return Err(LinkError::SignatureMismatch(name.to_string()));
+            }
+        }
+
+        for global in other.globals() {
+            linker.global(self, global);
+        }
+
+        for func in other.funcs() {
+            let dst_func = match self.func_by_name(func.name(other)) {
+                Some(existing) => {
+                    if func.is_declaration(other) {
+                        continue;
+                    }
+                    existing
+                }
+                None => linker.declare(self, func),
+            };
+            dst_func.set_memory_effect(self, func.memory_effect(other));
+            if func.is_external(other) {
+                dst_func.set_external(self, true);
+            }
+            linker.body(self, func, dst_func);
+        }
+
+        Ok(())
+    }
+
+    /// Copy a function definition into a new module, with the declarations of
+    /// the functions and the globals it refers to.
+    ///
+    /// The new module can be transformed apart from this one, e.g., on another
+    /// thread, and the function moved back by [`Context::replace_body`].
+    ///
+    /// # Returns
+    ///
+    /// The new module and the function in it.
+    pub fn extract_func(&self, func: Func) -> (Context, Func) {
+        let mut dst = Context::new(self.target.ptr_size);
+        dst.curr_loc = self.curr_loc;
+        let mut linker = Linker {
+            src: self,
+            values: HashMap::new(),
+            blocks: HashMap::new(),
+            locs: true,
+        };
+        let dst_func = linker.declare(&mut dst, func);
+        linker.declare_refs(&mut dst, func.refs(self));
+        linker.body(&mut dst, func, dst_func);
+        (dst, dst_func)
+    }
+
+    /// Replace the body of a function by the one of a function in another
+    /// module with the same signature, e.g., extracted by
+    /// [`Context::extract_func`] and transformed.
+    ///
+    /// The functions and the globals the new body refers to are declared or
+    /// copied if not in this module. The old body is unlinked, and swept by
+    /// the next [`Context::gc`].
+    pub fn replace_body(&mut self, func: Func, other: &Context, other_func: Func) {
+        let mut linker = Linker {
+            src: other,
+            values: HashMap::new(),
+            blocks: HashMap::new(),
+            locs: true,
+        };
+        linker.declare_refs(self, other_func.refs(other));
+
+        // the parameters are kept, so their uses in the old body are dropped
+        for param in func.params(self).to_vec() {
+            for user in param.users(self).into_iter().collect::<Vec<_>>() {
+                param.remove_user(self, user);
+            }
+        }
+        for block in func.iter(self).collect::<Vec<_>>() {
+            block.unlink(self);
+        }
+        linker.body(self, other_func, func);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::IntBinaryOp;
+
+    /// Create a module with a function `name(i32) -> i32`, with a body that
+    /// returns `param + 1` if `define` is true.
+    fn module(name: &str, define: bool) -> Context {
+        let mut ctx = Context::new(8);
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, name.to_string(), i32);
+        let param = func.add_param(&mut ctx, i32);
+        if define {
+            let block = Block::new(&mut ctx);
+            func.push_back(&mut ctx, block).unwrap();
+            let one = Value::i32(&mut ctx, 1);
+            let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, param, one);
+            let sum = add.result(&ctx).unwrap();
+            let ret = Inst::ret(&mut ctx, Some(sum));
+            block.push_back(&mut ctx, add).unwrap();
+            block.push_back(&mut ctx, ret).unwrap();
+        }
+        ctx
+    }
+
+    #[test]
+    fn test_link_definition_overrides_declaration() {
+        let mut ctx = module("inc", false);
+        let decl = ctx.func_by_name("inc").unwrap();
+        assert!(decl.is_declaration(&ctx));
+        assert_eq!(format!("{}", ctx), "declare i32 @inc(i32)\n");
+
+        ctx.link(&module("inc", true)).unwrap();
+
+        assert_eq!(ctx.funcs().count(), 1);
+        assert!(!decl.is_declaration(&ctx));
+        assert!(format!("{}", ctx).starts_with("define i32 @inc(i32)"));
+
+        // The parameter of the declaration is used by the linked body.
+        let param = decl.params(&ctx)[0];
+        let add = decl.head(&ctx).unwrap().head(&ctx).unwrap();
+        assert_eq!(add.operand(&ctx, 0), param);
+    }
+
+    #[test]
+    fn test_link_new_symbols() {
+        let mut ctx = module("foo", true);
+        let mut other = module("bar", true);
+        let init = ConstantValue::i32(&mut other, 42);
+        Global::new(&mut other, "g".to_string(), init);
+
+        ctx.link(&other).unwrap();
+        assert!(ctx.func_by_name("bar").is_some());
+        assert!(ctx.global_by_name("g").is_some());
+    }
+
+    #[test]
+    fn test_extract_func() {
+        // f(x) stores x into @g and calls @h, @k is not referred to
+        let mut ctx = module("h", false);
+        ctx.link(&module("k", true)).unwrap();
+        let i32 = Ty::i32(&mut ctx);
+        let zero = ConstantValue::i32(&mut ctx, 0);
+        Global::new(&mut ctx, "g".to_string(), zero);
+        let f = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = f.add_param(&mut ctx, i32);
+        let block = Block::new(&mut ctx);
+        f.push_back(&mut ctx, block).unwrap();
+        let g = Value::global_ref(&mut ctx, "g".to_string(), i32);
+        let store = Inst::store(&mut ctx, x, g);
+        let h = ctx.func_by_name("h").unwrap();
+        let call = Inst::call(&mut ctx, h, vec![x]);
+        let result = call.result(&ctx);
+        let ret = Inst::ret(&mut ctx, result);
+        for inst in [store, call, ret] {
+            block.push_back(&mut ctx, inst).unwrap();
+        }
+
+        let (mut other, other_f) = ctx.extract_func(f);
+        assert_eq!(
+            other
+                .funcs()
+                .map(|func| func.name(&other))
+                .collect::<Vec<_>>(),
+            ["f", "h"]
+        );
+        assert!(other.global_by_name("g").is_some());
+        assert_eq!(other_f.head(&other).unwrap().iter(&other).count(), 3);
+
+        // call a new function before the return
+        let void = Ty::void(&mut other);
+        let l = Func::new(&mut other, "l".to_string(), void);
+        let call = Inst::call(&mut other, l, Vec::new());
+        let ret = other_f.tail(&other).unwrap().tail(&other).unwrap();
+        ret.insert_before(&mut other, call).unwrap();
+
+        ctx.replace_body(f, &other, other_f);
+        assert!(ctx.func_by_name("l").unwrap().is_declaration(&ctx));
+        assert_eq!(f.params(&ctx), &[x]);
+        assert_eq!(x.users(&ctx).into_iter().count(), 2);
+        assert_eq!(f.iter(&ctx).flat_map(|block| block.iter(&ctx)).count(), 4);
+        ctx.verify().unwrap();
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let mut ctx = module("foo", true);
+        assert_eq!(
+            ctx.link(&module("foo", true)),
+            Err(LinkError::DuplicateDefinition("foo".to_string()))
+        );
+
+        let mut other = Context::new(8);
+        let void = Ty::void(&mut other);
+        Func::new(&mut other, "foo".to_string(), void);
+        assert_eq!(
+            ctx.link(&other),
+            Err(LinkError::SignatureMismatch("foo".to_string()))
+        );
+
+        // Nothing is changed on error.
+        assert_eq!(ctx.funcs().count(), 1);
+    }
+}
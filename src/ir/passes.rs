@@ -0,0 +1,158 @@
+//! Transform passes on the IR.
+//!
+//! All the passes implement [`TransformPass`](super::passman::TransformPass),
+//! and are registered by [`register_passes`] under the names used in
+//! pipelines. The pipelines of the optimization levels are given by
+//! [`opt_pipeline`].
+
+mod arg_const;
+mod block_layout;
+mod canonicalize;
+mod const_global;
+mod coverage;
+mod dce;
+mod div_by_const;
+mod global_dce;
+mod global_to_local;
+mod gvn;
+mod inline;
+mod instcombine;
+mod licm;
+mod load_elim;
+mod loop_deletion;
+mod loop_idiom;
+mod loop_unroll;
+mod lsr;
+mod mem2reg;
+mod min_max;
+mod mul_by_const;
+mod phi_simplify;
+mod profile;
+mod pure_call;
+mod simplify_cfg;
+mod sroa;
+
+pub use arg_const::*;
+pub use block_layout::*;
+pub use canonicalize::*;
+pub use const_global::*;
+pub use coverage::*;
+pub use dce::*;
+pub use div_by_const::*;
+pub use global_dce::*;
+pub use global_to_local::*;
+pub use gvn::*;
+pub use inline::*;
+pub use instcombine::*;
+pub use licm::*;
+pub use load_elim::*;
+pub use loop_deletion::*;
+pub use loop_idiom::*;
+pub use loop_unroll::*;
+pub use lsr::*;
+pub use mem2reg::*;
+pub use min_max::*;
+pub use mul_by_const::*;
+pub use phi_simplify::*;
+pub use profile::*;
+pub use pure_call::*;
+pub use simplify_cfg::*;
+pub use sroa::*;
+
+use super::passman::PassManager;
+
+/// Register all the transform passes in the pass manager.
+pub fn register_passes(pm: &mut PassManager) {
+    pm.register("sroa", || Box::<Sroa>::default());
+    pm.register("global-dce", || Box::<GlobalDce>::default());
+    pm.register("lsr", || Box::<LoopStrengthReduce>::default());
+    pm.register("loop-deletion", || Box::<LoopDeletion>::default());
+    pm.register("instcombine", || Box::<InstCombine>::default());
+    pm.register("div-by-const", || Box::<DivByConst>::default());
+    pm.register("global-to-local", || Box::<GlobalToLocal>::default());
+    pm.register("const-global-prop", || Box::<ConstGlobalProp>::default());
+    pm.register("load-elim", || Box::<LoadElim>::default());
+    pm.register("block-layout", || Box::<BlockLayout>::default());
+    pm.register("loop-idiom", || Box::<LoopIdiom>::default());
+    pm.register("arg-const-prop", || Box::<ArgConstProp>::default());
+    pm.register("pure-call-elim", || Box::<PureCallElim>::default());
+    pm.register("mul-by-const", || Box::<MulByConst>::default());
+    pm.register("phi-simplify", || Box::<PhiSimplify>::default());
+    pm.register("canonicalize", || Box::<Canonicalize>::default());
+    pm.register("min-max", || Box::<MinMax>::default());
+    pm.register("mem2reg", || Box::<Mem2Reg>::default());
+    pm.register("simplifycfg", || Box::<SimplifyCfg>::default());
+    pm.register("dce", || Box::<Dce>::default());
+    pm.register("gvn", || Box::<Gvn>::default());
+    pm.register("licm", || Box::<Licm>::default());
+    pm.register("inline", || Box::<Inline>::default());
+    pm.register("loop-unroll", || Box::<LoopUnroll>::default());
+    pm.register("coverage", || Box::<Coverage>::default());
+    pm.register("profile-generate", || Box::<ProfileGenerate>::default());
+}
+
+/// The passes run at `-O1`: the allocas promoted to registers, the control
+/// flow simplified, and the dead code removed.
+const O1_PIPELINE: &str = "mem2reg,simplifycfg,dce";
+
+/// The full pipeline run at `-O2`.
+///
+/// The globals are localized and propagated first, and the small functions
+/// inlined, to expose the constants to the local passes. The redundancies are
+/// merged and hoisted out of the loops before the loops are deleted or
+/// unrolled. The strength reductions of the loops and the arithmetic follow
+/// the cleanups, whose results they need in the canonical form, and the blocks
+/// are laid out once the control flow is settled.
+const O2_PIPELINE: &str = "global-to-local,const-global-prop,arg-const-prop,inline,sroa,\
+                           mem2reg,simplifycfg,canonicalize,instcombine,gvn,load-elim,\
+                           pure-call-elim,phi-simplify,licm,loop-deletion,loop-idiom,\
+                           loop-unroll,instcombine,simplifycfg,lsr,div-by-const,\
+                           mul-by-const,instcombine,dce,block-layout,global-dce";
+
+/// Get the pipeline of an optimization level, as passed to `-O`.
+///
+/// `-O0` runs no pass, and the levels above `-O2` are the same as it. With
+/// `min_max`, `-O2` also replaces the branches picking the minimum or the
+/// maximum, for the targets with branch-free instructions for them, e.g.,
+/// RISC-V with Zbb.
+pub fn opt_pipeline(level: u8, min_max: bool) -> String {
+    match level {
+        0 => String::new(),
+        1 => O1_PIPELINE.to_string(),
+        _ if min_max => O2_PIPELINE.replacen("phi-simplify,", "phi-simplify,min-max,", 1),
+        _ => O2_PIPELINE.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opt_pipeline() {
+        let pipelines = (0..4)
+            .map(|level| {
+                let mut pm = PassManager::default();
+                register_passes(&mut pm);
+                pm.add_pipeline(&opt_pipeline(level, false)).unwrap();
+                pm.pipeline().collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert!(pipelines[0].is_empty());
+        assert_eq!(pipelines[1], ["mem2reg", "simplifycfg", "dce"]);
+        // each level runs the passes of the one below
+        assert!(pipelines[1].iter().all(|pass| pipelines[2].contains(pass)));
+        assert_eq!(pipelines[2].first(), Some(&"global-to-local"));
+        assert_eq!(pipelines[3], pipelines[2]);
+
+        // the minimum and the maximum are only recognized if asked
+        assert!(!pipelines[2].contains(&"min-max"));
+        let mut pm = PassManager::default();
+        register_passes(&mut pm);
+        pm.add_pipeline(&opt_pipeline(2, true)).unwrap();
+        let pipeline = pm.pipeline().collect::<Vec<_>>();
+        let phi_simplify = pipeline.iter().position(|&pass| pass == "phi-simplify");
+        assert_eq!(pipeline[phi_simplify.unwrap() + 1], "min-max");
+        assert_eq!(pipeline.len(), pipelines[2].len() + 1);
+    }
+}
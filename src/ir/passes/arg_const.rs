@@ -0,0 +1,197 @@
+//! Interprocedural constant argument propagation.
+//!
+//! If all the call sites of a function pass the same constant for a parameter,
+//! the uses of the parameter in the body are replaced by the constant. The
+//! signature is kept, so the callers are left unchanged.
+//!
+//! Only functions whose callers are all known are considered, i.e., functions
+//! that are not visible outside of the module and only referred to as callees.
+//! The propagation is repeated until no more parameters are replaced, so the
+//! constants flow down the call chains.
+
+use std::collections::HashSet;
+
+use crate::infra::intern::Symbol;
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::analysis::CallGraph;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{ConstantValue, Context, Func, InstKind, Usable, Value};
+
+/// The propagation of constant arguments into function bodies.
+#[derive(Default)]
+pub struct ArgConstProp;
+
+impl ArgConstProp {
+    /// Find the names of the functions referred to other than as callees.
+    fn address_taken(ctx: &Context) -> HashSet<Symbol> {
+        fn constant_refs(value: &ConstantValue, taken: &mut HashSet<Symbol>) {
+            match value {
+                ConstantValue::GlobalRef { name, .. } => {
+                    taken.insert(*name);
+                }
+                ConstantValue::Array { elems, .. } => {
+                    for elem in elems {
+                        constant_refs(elem, taken);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut taken = HashSet::new();
+        for global in ctx.globals() {
+            constant_refs(global.value(ctx), &mut taken);
+        }
+
+        for func in ctx.funcs() {
+            for block in func.iter(ctx) {
+                for inst in block.iter(ctx) {
+                    let is_call = matches!(inst.kind(ctx), InstKind::Call);
+                    for (idx, operand) in inst.operand_iter(ctx).enumerate() {
+                        if let Some(value) = operand.as_const(ctx) {
+                            if !is_call || idx != 0 {
+                                constant_refs(value, &mut taken);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        taken
+    }
+
+    /// Replace the parameters of a function that receive the same constant
+    /// from all the call sites.
+    fn propagate(ctx: &mut Context, cg: &CallGraph, func: Func) -> bool {
+        let sites = cg.call_sites(func);
+        let params = func.params(ctx).to_vec();
+        if sites.is_empty()
+            || sites
+                .iter()
+                .any(|site| site.operand_iter(ctx).count() != params.len() + 1)
+        {
+            return false;
+        }
+
+        let mut changed = false;
+        for (i, param) in params.into_iter().enumerate() {
+            if param.users(ctx).into_iter().next().is_none() {
+                continue;
+            }
+            let first = sites[0].operand(ctx, i + 1).as_const(ctx);
+            let Some(constant) = first.filter(|c| !matches!(c, ConstantValue::Undef { .. })) else {
+                continue;
+            };
+            if sites[1..]
+                .iter()
+                .any(|site| site.operand(ctx, i + 1).as_const(ctx) != Some(constant))
+            {
+                continue;
+            }
+            let constant = Value::new_constant(ctx, constant.clone());
+            param.replace_all_uses_with(ctx, constant);
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl TransformPass for ArgConstProp {
+    fn name(&self) -> &'static str { "arg-const-prop" }
+
+    fn run(&mut self, ctx: &mut Context, am: &mut AnalysisManager) -> bool {
+        let taken = Self::address_taken(ctx);
+        let cg = CallGraph::new(ctx);
+        let funcs = cg
+            .funcs()
+            .iter()
+            .copied()
+            .filter(|func| {
+                !func.is_external(ctx)
+                    && !func.is_declaration(ctx)
+                    && !taken.contains(&func.name(ctx))
+            })
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+        loop {
+            let mut changed_once = false;
+            for &func in funcs.iter() {
+                if Self::propagate(ctx, &cg, func) {
+                    am.invalidate(func);
+                    changed_once = true;
+                }
+            }
+            if !changed_once {
+                return changed;
+            }
+            changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Inst, IntBinaryOp, Ty};
+
+    #[test]
+    fn test_arg_const_prop() {
+        // main(x): f(3, x); f(3, 4); h(1); h(2)
+        // f(a, b): g(a); ret a + b
+        // g(c):    ret c * 2
+        // h(d):    ret d
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let [main, f, g, h] = ["main", "f", "g", "h"].map(|name| {
+            let func = Func::new(&mut ctx, name.to_string(), i32);
+            let block = Block::new(&mut ctx);
+            func.push_back(&mut ctx, block).unwrap();
+            func
+        });
+        let x = main.add_param(&mut ctx, i32);
+        let [a, b] = [(); 2].map(|_| f.add_param(&mut ctx, i32));
+        let c = g.add_param(&mut ctx, i32);
+        let d = h.add_param(&mut ctx, i32);
+        let push = |ctx: &mut Context, func: Func, inst: Inst| {
+            func.head(ctx).unwrap().push_back(ctx, inst).unwrap();
+            inst
+        };
+        let [one, two, three, four] = [1, 2, 3, 4].map(|v| Value::i32(&mut ctx, v));
+
+        for (callee, args) in [
+            (f, vec![three, x]),
+            (f, vec![three, four]),
+            (h, vec![one]),
+            (h, vec![two]),
+        ] {
+            let call = Inst::call(&mut ctx, callee, args);
+            push(&mut ctx, main, call);
+        }
+        let call = Inst::call(&mut ctx, g, vec![a]);
+        push(&mut ctx, f, call);
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, a, b);
+        push(&mut ctx, f, add);
+        let mul = Inst::ibinary(&mut ctx, IntBinaryOp::Mul, c, two);
+        push(&mut ctx, g, mul);
+        for (func, value) in [(main, x), (f, add.result(&ctx).unwrap())] {
+            let ret = Inst::ret(&mut ctx, Some(value));
+            push(&mut ctx, func, ret);
+        }
+        for (func, value) in [(g, mul.result(&ctx).unwrap()), (h, d)] {
+            let ret = Inst::ret(&mut ctx, Some(value));
+            push(&mut ctx, func, ret);
+        }
+
+        let mut am = AnalysisManager::default();
+        assert!(ArgConstProp.run(&mut ctx, &mut am));
+        assert_eq!(call.operand(&ctx, 1).as_const_int(&ctx), Some(3));
+        assert_eq!(add.operand(&ctx, 0).as_const_int(&ctx), Some(3));
+        assert_eq!(add.operand(&ctx, 1), b);
+        // propagated through f into g
+        assert_eq!(mul.operand(&ctx, 0).as_const_int(&ctx), Some(3));
+        let ret = h.head(&ctx).unwrap().tail(&ctx).unwrap();
+        assert_eq!(ret.operand(&ctx, 0), d);
+
+        assert!(!ArgConstProp.run(&mut ctx, &mut am));
+    }
+}
@@ -0,0 +1,237 @@
+//! Block layout and branch polarity.
+//!
+//! The blocks of a function are emitted in the order of the block list, and a
+//! conditional branch falls through to its second successor if it is the next
+//! block. Any other control transfer needs a jump.
+//!
+//! Guided by the branch probabilities, profiled or static, the blocks are
+//! reordered into chains, each block followed by its most likely successor not
+//! yet placed. Cold blocks, i.e., the ones only reached through very unlikely
+//! edges such as error paths, are moved to the end of the function, after the
+//! hot ones, and unreachable blocks come last.
+//!
+//! Afterwards, a conditional branch whose first successor is the next block is
+//! inverted if the condition can be negated for free, i.e., it is a constant or
+//! a comparison only used by the branch.
+
+use std::collections::HashSet;
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{BranchProbs, Cfg};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, IntBinaryOp, Usable, Value};
+
+/// The probability below which an edge leads to cold code.
+const COLD_PROB: f64 = 1.0 / 64.0;
+
+/// The reordering of blocks for fallthrough and the inversion of branches.
+#[derive(Clone, Default)]
+pub struct BlockLayout;
+
+impl BlockLayout {
+    /// Find the reachable blocks that are only reached through unlikely edges
+    /// or from other cold blocks.
+    fn cold_blocks(cfg: &Cfg, probs: &BranchProbs) -> HashSet<Block> {
+        let mut cold = HashSet::new();
+        for block in cfg.rpo().skip(1) {
+            let is_cold = cfg
+                .preds(block)
+                .iter()
+                .filter(|&&pred| cfg.is_reachable(pred) && !cfg.is_retreating_edge(pred, block))
+                .all(|&pred| cold.contains(&pred) || probs.prob(pred, block) < COLD_PROB);
+            if is_cold {
+                cold.insert(block);
+            }
+        }
+        cold
+    }
+
+    /// Compute the new order of the blocks.
+    fn layout(ctx: &Context, func: Func, cfg: &Cfg, probs: &BranchProbs) -> Vec<Block> {
+        let cold = Self::cold_blocks(cfg, probs);
+        let mut placed = HashSet::new();
+        let mut order = Vec::new();
+
+        // Hot chains are started first, in reverse postorder.
+        let seeds = cfg
+            .rpo()
+            .filter(|block| !cold.contains(block))
+            .chain(cfg.rpo().filter(|block| cold.contains(block)))
+            .collect::<Vec<_>>();
+        for seed in seeds {
+            let mut current = Some(seed);
+            while let Some(block) = current.filter(|&block| placed.insert(block)) {
+                order.push(block);
+                // Hot blocks are never pulled into a chain of cold ones, and
+                // vice versa.
+                current = cfg
+                    .succs(block)
+                    .iter()
+                    .copied()
+                    .filter(|succ| !placed.contains(succ))
+                    .filter(|succ| cold.contains(succ) == cold.contains(&block))
+                    .fold(None, |best: Option<Block>, succ| match best {
+                        Some(best) if probs.prob(block, best) >= probs.prob(block, succ) => {
+                            Some(best)
+                        }
+                        _ => Some(succ),
+                    });
+            }
+        }
+
+        order.extend(func.iter(ctx).filter(|block| !cfg.is_reachable(*block)));
+        order
+    }
+
+    /// Get the negation of a branch condition, if it is free.
+    fn negate(ctx: &mut Context, cond: Value) -> Option<Value> {
+        if let Some(value) = cond.as_const_int(ctx) {
+            return Some(Value::i1(ctx, value == 0));
+        }
+        let inst = cond.def_inst(ctx)?;
+        let InstKind::IntBinary {
+            op: IntBinaryOp::ICmp { cond: cmp },
+        } = *inst.kind(ctx)
+        else {
+            return None;
+        };
+        if cond.users(ctx).into_iter().nth(1).is_some() {
+            return None;
+        }
+        let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+        let op = IntBinaryOp::ICmp {
+            cond: cmp.inverse(),
+        };
+        let negated = Inst::ibinary(ctx, op, lhs, rhs);
+        inst.insert_after(ctx, negated).unwrap();
+        let negated = negated.result(ctx).unwrap();
+        cond.replace_all_uses_with(ctx, negated);
+        inst.remove(ctx);
+        Some(negated)
+    }
+
+    /// Invert the conditional branches whose first successor is the next
+    /// block.
+    fn invert_branches(ctx: &mut Context, func: Func) -> bool {
+        let blocks = func.iter(ctx).collect::<Vec<_>>();
+        let mut changed = false;
+        for (&block, &next) in blocks.iter().zip(blocks.iter().skip(1)) {
+            let term = block.tail(ctx).unwrap();
+            if !matches!(term.kind(ctx), InstKind::CondBr) {
+                continue;
+            }
+            let (then_dest, else_dest) = (term.successor(ctx, 0), term.successor(ctx, 1));
+            if then_dest != next || else_dest == next {
+                continue;
+            }
+            let Some(cond) = Self::negate(ctx, term.operand(ctx, 0)) else {
+                continue;
+            };
+            let then_args = term.successor_args(ctx, 0).collect();
+            let else_args = term.successor_args(ctx, 1).collect();
+            let inverted =
+                Inst::cond_br_with_args(ctx, cond, else_dest, else_args, then_dest, then_args);
+            let weights = term
+                .branch_weights(ctx)
+                .map(|[then_w, else_w]| [else_w, then_w]);
+            inverted.set_branch_weights(ctx, weights);
+            term.insert_before(ctx, inverted).unwrap();
+            term.remove(ctx);
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl TransformPass for BlockLayout {
+    fn name(&self) -> &'static str { "block-layout" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let probs = am.get::<BranchProbs>(ctx, func);
+        let order = Self::layout(ctx, func, &cfg, &probs);
+
+        let mut changed = !order.iter().copied().eq(func.iter(ctx));
+        if changed {
+            for &block in order.iter() {
+                block.unlink(ctx);
+                func.push_back(ctx, block).unwrap();
+            }
+        }
+        changed |= Self::invert_branches(ctx, func);
+
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{IntCmpCond, Ty};
+
+    #[test]
+    fn test_block_layout() {
+        // entry: c1 = icmp eq x, 0; br c1, err, a
+        // err:   br err
+        // merge: ret
+        // b:     br merge
+        // a:     c2 = icmp ne y, 0; br c2, b, merge
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let void = Ty::void(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), void);
+        let x = func.add_param(&mut ctx, i32);
+        let y = func.add_param(&mut ctx, i32);
+        let [entry, err, merge, b, a] = [(); 5].map(|_| Block::new(&mut ctx));
+        for block in [entry, err, merge, b, a] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let icmp = |ctx: &mut Context, block: Block, cond: IntCmpCond, value: Value| {
+            let zero = Value::i32(ctx, 0);
+            let inst = Inst::ibinary(ctx, IntBinaryOp::ICmp { cond }, value, zero);
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+
+        let c1 = icmp(&mut ctx, entry, IntCmpCond::Eq, x);
+        let br = Inst::cond_br(&mut ctx, c1, err, a);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, err);
+        err.push_back(&mut ctx, br).unwrap();
+        let ret = Inst::ret(&mut ctx, None);
+        merge.push_back(&mut ctx, ret).unwrap();
+        let br = Inst::br(&mut ctx, merge);
+        b.push_back(&mut ctx, br).unwrap();
+        let c2 = icmp(&mut ctx, a, IntCmpCond::Ne, y);
+        let br = Inst::cond_br(&mut ctx, c2, b, merge);
+        a.push_back(&mut ctx, br).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(BlockLayout.run_on_func(&mut ctx, func, &mut am));
+        let order = func.iter(&ctx).collect::<Vec<_>>();
+        assert_eq!(order, [entry, a, b, merge, err]);
+
+        // `a` now falls through to `b` on the inverted condition
+        let term = a.tail(&ctx).unwrap();
+        assert_eq!(term.successor(&ctx, 0), merge);
+        assert_eq!(term.successor(&ctx, 1), b);
+        let cmp = term.operand(&ctx, 0).def_inst(&ctx).unwrap();
+        assert!(matches!(
+            cmp.kind(&ctx),
+            InstKind::IntBinary {
+                op: IntBinaryOp::ICmp {
+                    cond: IntCmpCond::Eq
+                }
+            }
+        ));
+        // `entry` already falls through to its else side
+        assert_eq!(entry.tail(&ctx).unwrap().successor(&ctx, 0), err);
+
+        assert!(!BlockLayout.run_on_func(&mut ctx, func, &mut am));
+    }
+}
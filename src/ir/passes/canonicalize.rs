@@ -0,0 +1,280 @@
+//! IR canonicalization.
+//!
+//! Equivalent forms of the same construct are rewritten into one, so the
+//! pattern matching in later passes only needs to handle that form:
+//!
+//! - Constants are on the right of commutative operations.
+//! - Comparisons have constants on the right, and compare two non-constants
+//!   with `slt` or `sle` rather than `sgt` or `sge`, by swapping the operands.
+//! - A `getelementptr` with all the indices zero is its base pointer.
+//! - A `getelementptr` on the result of another, with a zero first index and
+//!   the element type of the inner one as the bound type, is merged into a
+//!   single `getelementptr`:
+//!
+//!   ```text
+//!   p = getelementptr [4 x [8 x i32]], a, 0, i
+//!   q = getelementptr [8 x i32], p, 0, j
+//!   ; becomes
+//!   q = getelementptr [4 x [8 x i32]], a, 0, i, j
+//!   ```
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Context, Func, Inst, InstKind, IntBinaryOp, IntCmpCond, Ty, Usable, Value};
+
+/// The rewriting of instructions into canonical forms.
+#[derive(Clone, Default)]
+pub struct Canonicalize;
+
+impl Canonicalize {
+    /// Get the canonical form of a binary operation, if it is not.
+    fn canonical_binary(ctx: &Context, inst: Inst, op: IntBinaryOp) -> Option<IntBinaryOp> {
+        let is_const = |idx| inst.operand(ctx, idx).as_const(ctx).is_some();
+        let (lhs_const, rhs_const) = (is_const(0), is_const(1));
+        match op {
+            IntBinaryOp::ICmp { cond } if lhs_const && !rhs_const => {
+                Some(IntBinaryOp::ICmp { cond: cond.swap() })
+            }
+            IntBinaryOp::ICmp {
+                cond: cond @ (IntCmpCond::Sgt | IntCmpCond::Sge),
+            } if !lhs_const && !rhs_const => Some(IntBinaryOp::ICmp { cond: cond.swap() }),
+            _ if op.is_commutative() && lhs_const && !rhs_const => Some(op),
+            _ => None,
+        }
+    }
+
+    /// Get the type indexed by the last index of a `getelementptr`.
+    fn indexed_ty(ctx: &Context, gep: Inst) -> Option<Ty> {
+        let InstKind::GetElementPtr { bound_ty } = *gep.kind(ctx) else {
+            return None;
+        };
+        let mut ty = bound_ty;
+        for _ in 2..gep.operand_iter(ctx).count() {
+            ty = ty.as_array(ctx)?.0;
+        }
+        Some(ty)
+    }
+
+    /// Get the canonical form of a `getelementptr`, if it is not.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(Ok(value))`: The base pointer, if all the indices are zero.
+    /// - `Some(Err((bound_ty, operands)))`: The merged `getelementptr`.
+    /// - `None`: The `getelementptr` is canonical.
+    #[allow(clippy::type_complexity)]
+    fn canonical_gep(
+        ctx: &Context,
+        gep: Inst,
+        bound_ty: Ty,
+    ) -> Option<Result<Value, (Ty, Vec<Value>)>> {
+        let operands = gep.operand_iter(ctx).collect::<Vec<_>>();
+        let is_zero = |value: &Value| value.as_const_int(ctx) == Some(0);
+        if operands[1..].iter().all(is_zero) {
+            return Some(Ok(operands[0]));
+        }
+
+        let inner = operands[0].def_inst(ctx)?;
+        let InstKind::GetElementPtr { bound_ty: inner_ty } = *inner.kind(ctx) else {
+            return None;
+        };
+        if !is_zero(&operands[1]) || Self::indexed_ty(ctx, inner) != Some(bound_ty) {
+            return None;
+        }
+        let mut merged = inner.operand_iter(ctx).collect::<Vec<_>>();
+        merged.extend_from_slice(&operands[2..]);
+        Some(Err((inner_ty, merged)))
+    }
+
+    /// Remove an unused `getelementptr` and the chain of unused ones it is
+    /// based on.
+    fn remove_dead(ctx: &mut Context, ptr: Value) {
+        let Some(inst) = ptr.def_inst(ctx) else {
+            return;
+        };
+        if !matches!(inst.kind(ctx), InstKind::GetElementPtr { .. })
+            || ptr.users(ctx).into_iter().next().is_some()
+        {
+            return;
+        }
+        let base = inst.operand(ctx, 0);
+        inst.remove(ctx);
+        Self::remove_dead(ctx, base);
+    }
+
+    /// Canonicalize an instruction.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(value)`: The value to replace the instruction with.
+    /// - `None`: The instruction is canonical.
+    fn canonicalize(ctx: &mut Context, inst: Inst) -> Option<Value> {
+        match *inst.kind(ctx) {
+            InstKind::IntBinary { op } => {
+                let op = Self::canonical_binary(ctx, inst, op)?;
+                let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+                let swapped = Inst::ibinary(ctx, op, rhs, lhs);
+                inst.insert_before(ctx, swapped).unwrap();
+                swapped.result(ctx)
+            }
+            InstKind::GetElementPtr { bound_ty } => {
+                match Self::canonical_gep(ctx, inst, bound_ty)? {
+                    Ok(base) => Some(base),
+                    Err((bound_ty, operands)) => {
+                        let merged =
+                            Inst::getelementptr(ctx, bound_ty, operands[0], operands[1..].to_vec());
+                        inst.insert_before(ctx, merged).unwrap();
+                        merged.result(ctx)
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl TransformPass for Canonicalize {
+    fn name(&self) -> &'static str { "canonicalize" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        loop {
+            let insts = func
+                .iter(ctx)
+                .flat_map(|block| block.iter(ctx))
+                .collect::<Vec<_>>();
+
+            let mut changed_once = false;
+            for inst in insts {
+                let Some(value) = Self::canonicalize(ctx, inst) else {
+                    continue;
+                };
+                let base = inst.operand(ctx, 0);
+                inst.result(ctx).unwrap().replace_all_uses_with(ctx, value);
+                inst.remove(ctx);
+                Self::remove_dead(ctx, base);
+                changed_once = true;
+            }
+            if !changed_once {
+                break;
+            }
+            changed = true;
+        }
+
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Block;
+
+    #[test]
+    fn test_canonicalize() {
+        // entry: a = alloca [4 x [8 x i32]]
+        //        s1 = add 3, x; s2 = icmp slt 3, x; s3 = icmp sge x, y
+        //        s4 = sub 3, x
+        //        p = getelementptr [4 x [8 x i32]], a, 0, x
+        //        q = getelementptr [8 x i32], p, 0, y
+        //        r = getelementptr [8 x i32], a, 0, 0
+        //        load q; load r; ...
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let row = Ty::array(&mut ctx, i32, 8);
+        let matrix = Ty::array(&mut ctx, row, 4);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = func.add_param(&mut ctx, i32);
+        let y = func.add_param(&mut ctx, i32);
+        let block = Block::new(&mut ctx);
+        func.push_back(&mut ctx, block).unwrap();
+        let push = |ctx: &mut Context, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx).unwrap()
+        };
+        let [zero, three] = [0, 3].map(|v| Value::i32(&mut ctx, v));
+
+        let alloca = Inst::alloca(&mut ctx, matrix);
+        let a = push(&mut ctx, alloca);
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let sge = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Sge,
+        };
+        let binaries = [
+            (IntBinaryOp::Add, three, x),
+            (slt, three, x),
+            (sge, x, y),
+            (IntBinaryOp::Sub, three, x),
+        ];
+        let results = binaries.map(|(op, lhs, rhs)| {
+            let inst = Inst::ibinary(&mut ctx, op, lhs, rhs);
+            push(&mut ctx, inst)
+        });
+        let gep = Inst::getelementptr(&mut ctx, matrix, a, vec![zero, x]);
+        let p = push(&mut ctx, gep);
+        let gep = Inst::getelementptr(&mut ctx, row, p, vec![zero, y]);
+        let q = push(&mut ctx, gep);
+        let gep = Inst::getelementptr(&mut ctx, row, a, vec![zero, zero]);
+        let r = push(&mut ctx, gep);
+        let loads = [q, r].map(|ptr| {
+            let load = Inst::load(&mut ctx, ptr, i32);
+            push(&mut ctx, load);
+            load
+        });
+        let uses = results.map(|value| {
+            let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, value, x);
+            push(&mut ctx, add);
+            add
+        });
+        let ret = Inst::ret(&mut ctx, None);
+        block.push_back(&mut ctx, ret).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(Canonicalize.run_on_func(&mut ctx, func, &mut am));
+
+        let shape = |value: Value| {
+            let inst = value.def_inst(&ctx).unwrap();
+            let InstKind::IntBinary { op } = *inst.kind(&ctx) else {
+                unreachable!()
+            };
+            (op, inst.operand(&ctx, 0), inst.operand(&ctx, 1))
+        };
+        let shapes = uses.map(|add| shape(add.operand(&ctx, 0)));
+        assert_eq!((shapes[0].0, shapes[0].1), (IntBinaryOp::Add, x));
+        let sgt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Sgt,
+        };
+        assert_eq!((shapes[1].0, shapes[1].1), (sgt, x));
+        let sle = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Sle,
+        };
+        assert_eq!(shapes[2], (sle, y, x));
+        // not commutative
+        assert_eq!(shapes[3], (IntBinaryOp::Sub, three, x));
+
+        let merged = loads[0].operand(&ctx, 0).def_inst(&ctx).unwrap();
+        assert!(matches!(
+            merged.kind(&ctx),
+            InstKind::GetElementPtr { bound_ty } if *bound_ty == matrix
+        ));
+        let operands = merged.operand_iter(&ctx).collect::<Vec<_>>();
+        assert_eq!(operands[0], a);
+        assert_eq!(operands[2..], [x, y]);
+        assert_eq!(loads[1].operand(&ctx, 0), a);
+        // the inner getelementptr is removed
+        let geps = block
+            .iter(&ctx)
+            .filter(|inst| matches!(inst.kind(&ctx), InstKind::GetElementPtr { .. }))
+            .count();
+        assert_eq!(geps, 1);
+
+        assert!(!Canonicalize.run_on_func(&mut ctx, func, &mut am));
+    }
+}
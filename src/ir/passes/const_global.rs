@@ -0,0 +1,270 @@
+//! Constant global propagation.
+//!
+//! A global is never written after initialization if it is marked constant,
+//! or it is not visible outside of the module and its address is only used by
+//! loads, directly or through `getelementptr`s. Such globals are marked
+//! constant, and the loads from them at constant offsets are replaced by the
+//! corresponding parts of the initializers.
+//!
+//! The globals left unused can be removed by global DCE afterwards.
+
+use std::collections::HashSet;
+
+use crate::infra::intern::Symbol;
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::analysis::{decompose_pointer, PointerBase};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{ConstantValue, Context, Func, Inst, InstKind, Ty, Usable, Value};
+
+/// The propagation of read-only global initializers into loads.
+#[derive(Default)]
+pub struct ConstGlobalProp;
+
+impl ConstGlobalProp {
+    /// Check if a use of a global address only reads the memory.
+    fn only_read(ctx: &Context, inst: Inst, idx: usize) -> bool {
+        match inst.kind(ctx) {
+            InstKind::Load => true,
+            InstKind::GetElementPtr { .. } if idx == 0 => inst
+                .result(ctx)
+                .unwrap()
+                .users(ctx)
+                .into_iter()
+                .all(|user| Self::only_read(ctx, user.inst(), user.idx())),
+            _ => false,
+        }
+    }
+
+    /// Find the names of the globals that are never written.
+    fn read_only_globals(ctx: &Context) -> HashSet<Symbol> {
+        // Globals referred to by other globals have their addresses stored.
+        fn constant_refs(value: &ConstantValue, written: &mut HashSet<Symbol>) {
+            match value {
+                ConstantValue::GlobalRef { name, .. } => {
+                    written.insert(*name);
+                }
+                ConstantValue::Array { elems, .. } => {
+                    for elem in elems {
+                        constant_refs(elem, written);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut written = HashSet::new();
+        for global in ctx.globals() {
+            constant_refs(global.value(ctx), &mut written);
+        }
+
+        for func in ctx.funcs() {
+            for block in func.iter(ctx) {
+                for inst in block.iter(ctx) {
+                    for (idx, operand) in inst.operand_iter(ctx).enumerate() {
+                        if let Some(ConstantValue::GlobalRef { name, .. }) = operand.as_const(ctx) {
+                            if !Self::only_read(ctx, inst, idx) {
+                                written.insert(*name);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        ctx.globals()
+            .filter(|global| {
+                global.is_const(ctx)
+                    || (!global.is_external(ctx) && !written.contains(&global.name(ctx)))
+            })
+            .map(|global| global.name(ctx))
+            .collect()
+    }
+
+    /// Get the value of type `ty` at the byte offset in a constant.
+    fn constant_at(ctx: &mut Context, value: &ConstantValue, offset: i64, ty: Ty) -> Option<Value> {
+        let size = value.ty().bytewidth(ctx) as i64;
+        if offset < 0 || offset + ty.bytewidth(ctx) as i64 > size || !ty.is_integer(ctx) {
+            return None;
+        }
+        match value {
+            ConstantValue::Undef { .. } => Some(Value::undef(ctx, ty)),
+            ConstantValue::AggregateZero { .. } => Some(Value::int(ctx, ty, 0)),
+            ConstantValue::Array {
+                ty: array_ty,
+                elems,
+            } => {
+                let (elem_ty, _) = array_ty.as_array(ctx).unwrap();
+                let elem_size = elem_ty.bytewidth(ctx) as i64;
+                let elem = &elems[(offset / elem_size) as usize];
+                Self::constant_at(ctx, elem, offset % elem_size, ty)
+            }
+            _ if offset == 0 && value.ty() == ty => Some(Value::new_constant(ctx, value.clone())),
+            _ => None,
+        }
+    }
+
+    /// Remove a `getelementptr` chain that is no longer used.
+    fn remove_dead(ctx: &mut Context, ptr: Value) {
+        let Some(inst) = ptr.def_inst(ctx) else {
+            return;
+        };
+        if !matches!(inst.kind(ctx), InstKind::GetElementPtr { .. })
+            || ptr.users(ctx).into_iter().next().is_some()
+        {
+            return;
+        }
+        let base = inst.operand(ctx, 0);
+        inst.remove(ctx);
+        Self::remove_dead(ctx, base);
+    }
+
+    /// Replace the loads from the read-only globals in a function.
+    fn propagate(ctx: &mut Context, func: Func, read_only: &HashSet<Symbol>) -> bool {
+        let loads = func
+            .iter(ctx)
+            .flat_map(|block| block.iter(ctx))
+            .filter(|inst| matches!(inst.kind(ctx), InstKind::Load))
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+        for load in loads {
+            let ptr = load.operand(ctx, 0);
+            let (PointerBase::Global(name), Some(offset)) = decompose_pointer(ctx, ptr) else {
+                continue;
+            };
+            if !read_only.contains(&name) {
+                continue;
+            }
+            let Some(global) = ctx.global_by_name(name) else {
+                continue;
+            };
+            let init = global.value(ctx).clone();
+            let result = load.result(ctx).unwrap();
+            let ty = result.ty(ctx);
+            let Some(value) = Self::constant_at(ctx, &init, offset, ty) else {
+                continue;
+            };
+            result.replace_all_uses_with(ctx, value);
+            load.remove(ctx);
+            Self::remove_dead(ctx, ptr);
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl TransformPass for ConstGlobalProp {
+    fn name(&self) -> &'static str { "const-global-prop" }
+
+    fn run(&mut self, ctx: &mut Context, am: &mut AnalysisManager) -> bool {
+        let read_only = Self::read_only_globals(ctx);
+
+        let mut changed = false;
+        let globals = ctx.globals().collect::<Vec<_>>();
+        for global in globals {
+            if read_only.contains(&global.name(ctx)) && !global.is_const(ctx) {
+                global.set_const(ctx, true);
+                changed = true;
+            }
+        }
+
+        let funcs = ctx.funcs().collect::<Vec<_>>();
+        for func in funcs {
+            if Self::propagate(ctx, func, &read_only) {
+                am.invalidate(func);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::passes::GlobalDce;
+    use crate::ir::{Block, Global, IntBinaryOp};
+
+    #[test]
+    fn test_const_global_prop() {
+        // main: load @n; load @table[1]; load @zeros[5]; load @table[i];
+        //       store 1, @w; load @w; load @ext
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let arr = Ty::array(&mut ctx, i32, 3);
+        let zeros_ty = Ty::array(&mut ctx, i32, 8);
+
+        let ten = ConstantValue::i32(&mut ctx, 10);
+        let n = Global::new(&mut ctx, "n".to_string(), ten);
+        let elems = [1, 2, 3].map(|v| ConstantValue::i32(&mut ctx, v)).to_vec();
+        let table_value = ConstantValue::Array { ty: arr, elems };
+        let table = Global::new(&mut ctx, "table".to_string(), table_value);
+        let zeros_value = ConstantValue::AggregateZero { ty: zeros_ty };
+        let zeros = Global::new(&mut ctx, "zeros".to_string(), zeros_value);
+        let [w, ext] = ["w", "ext"].map(|name| {
+            let ten = ConstantValue::i32(&mut ctx, 10);
+            Global::new(&mut ctx, name.to_string(), ten)
+        });
+        ext.set_external(&mut ctx, true);
+
+        let main = Func::new(&mut ctx, "main".to_string(), i32);
+        let i = main.add_param(&mut ctx, i32);
+        let block = Block::new(&mut ctx);
+        main.push_back(&mut ctx, block).unwrap();
+        let push = |ctx: &mut Context, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+        let load_at = |ctx: &mut Context, name: &str, bound_ty: Ty, index: Option<Value>| {
+            let mut ptr = Value::global_ref(ctx, name.to_string(), bound_ty);
+            if let Some(index) = index {
+                let zero = Value::i32(ctx, 0);
+                let gep = Inst::getelementptr(ctx, bound_ty, ptr, vec![zero, index]);
+                ptr = push(ctx, gep).unwrap();
+            }
+            let load = Inst::load(ctx, ptr, i32);
+            push(ctx, load).unwrap()
+        };
+
+        let [one, five] = [1, 5].map(|v| Value::i32(&mut ctx, v));
+        let mut sum = load_at(&mut ctx, "n", i32, None);
+        for value in [
+            load_at(&mut ctx, "table", arr, Some(one)),
+            load_at(&mut ctx, "zeros", zeros_ty, Some(five)),
+            load_at(&mut ctx, "table", arr, Some(i)),
+        ] {
+            let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, sum, value);
+            sum = push(&mut ctx, add).unwrap();
+        }
+        let w_ref = Value::global_ref(&mut ctx, "w".to_string(), i32);
+        let store = Inst::store(&mut ctx, one, w_ref);
+        push(&mut ctx, store);
+        let w_load = load_at(&mut ctx, "w", i32, None);
+        let ext_load = load_at(&mut ctx, "ext", i32, None);
+        let ret = Inst::ret(&mut ctx, Some(sum));
+        push(&mut ctx, ret);
+
+        let mut am = AnalysisManager::default();
+        assert!(ConstGlobalProp.run(&mut ctx, &mut am));
+        assert!(n.is_const(&ctx) && table.is_const(&ctx) && zeros.is_const(&ctx));
+        assert!(!w.is_const(&ctx) && !ext.is_const(&ctx));
+
+        // n + 2 + 0 + table[i]
+        let adds = block
+            .iter(&ctx)
+            .filter(|inst| matches!(inst.kind(&ctx), InstKind::IntBinary { .. }))
+            .map(|inst| inst.operand(&ctx, 1).as_const_int(&ctx))
+            .collect::<Vec<_>>();
+        assert_eq!(adds, [Some(2), Some(0), None]);
+        let first = block
+            .iter(&ctx)
+            .find(|inst| matches!(inst.kind(&ctx), InstKind::IntBinary { .. }))
+            .unwrap();
+        assert_eq!(first.operand(&ctx, 0).as_const_int(&ctx), Some(10));
+        assert!(w_load.def_inst(&ctx).is_some() && ext_load.def_inst(&ctx).is_some());
+
+        assert!(!ConstGlobalProp.run(&mut ctx, &mut am));
+        assert!(GlobalDce.run(&mut ctx, &mut am));
+        let globals = ctx.globals().collect::<HashSet<_>>();
+        assert_eq!(globals, HashSet::from([table, w, ext]));
+    }
+}
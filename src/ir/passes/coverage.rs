@@ -0,0 +1,357 @@
+//! Coverage instrumentation.
+//!
+//! Each block of the defined functions is given a counter in the global
+//! [`COVERAGE_COUNTS`], incremented on entering the block, and `main` calls
+//! [`COVERAGE_DUMP`] of the runtime before returning, with the table
+//! [`COVERAGE_MAP`] of the pairs `(line, counter)` of the source lines the
+//! blocks come from. The runtime writes the count of each pair as a line `line
+//! count` to the file named by `SYSY_COVERAGE`, or `sysy.cov`, and
+//! [`coverage_report`] annotates the source with the counts of the dumps of one
+//! or more runs, as `gcov` does.
+//!
+//! The pass runs before the optimizations, so the counters count the blocks of
+//! the source: the passes keep the increments as any other store, duplicating
+//! or merging them with the code around them.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use thiserror::Error;
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{
+    ConstantValue,
+    Context,
+    Func,
+    Global,
+    Inst,
+    InstKind,
+    IntBinaryOp,
+    Source,
+    Ty,
+    Value,
+};
+
+/// The global of the counters of the blocks.
+pub const COVERAGE_COUNTS: &str = "__coverage_counts";
+
+/// The global of the pairs `(line, counter)`, flattened.
+pub const COVERAGE_MAP: &str = "__coverage_map";
+
+/// The function of the runtime dumping the counts,
+/// `_sysy_coverage_dump(n, map, counts)` for the `n` pairs of the map.
+pub const COVERAGE_DUMP: &str = "_sysy_coverage_dump";
+
+/// The instrumentation of the blocks with execution counters.
+#[derive(Default)]
+pub struct Coverage;
+
+impl Coverage {
+    /// Get the declaration of the dump of the runtime, adding it if missing.
+    fn dump_decl(ctx: &mut Context) -> Func {
+        if let Some(func) = ctx.func_by_name(COVERAGE_DUMP) {
+            return func;
+        }
+        let void = Ty::void(ctx);
+        let ptr = Ty::ptr(ctx);
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, COVERAGE_DUMP.to_string(), void);
+        for ty in [i32, ptr, ptr] {
+            func.add_param(ctx, ty);
+        }
+        func
+    }
+
+    /// Get the sorted source lines of the instructions of a block.
+    fn block_lines(ctx: &Context, source: &Source, insts: &[Inst]) -> Vec<i32> {
+        let mut lines = insts
+            .iter()
+            .filter_map(|inst| inst.loc(ctx))
+            .map(|loc| source.line(loc) as i32)
+            .collect::<Vec<_>>();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+}
+
+/// Create the instructions adding `amount` to the counter `index` of the
+/// global array `counts`, to be inserted in order.
+pub(super) fn increment(
+    ctx: &mut Context,
+    counts: &str,
+    counts_ty: Ty,
+    index: usize,
+    amount: Value,
+) -> [Inst; 4] {
+    let i32 = Ty::i32(ctx);
+    let base = Value::global_ref(ctx, counts.to_string(), counts_ty);
+    let zero = Value::i32(ctx, 0);
+    let index = Value::i32(ctx, index as i32);
+    let ptr = Inst::getelementptr(ctx, counts_ty, base, vec![zero, index]);
+    let load = Inst::load(ctx, ptr.result(ctx).unwrap(), i32);
+    let add = Inst::ibinary(ctx, IntBinaryOp::Add, load.result(ctx).unwrap(), amount);
+    let store = Inst::store(ctx, add.result(ctx).unwrap(), ptr.result(ctx).unwrap());
+    [ptr, load, add, store]
+}
+
+impl TransformPass for Coverage {
+    fn name(&self) -> &'static str { "coverage" }
+
+    fn run(&mut self, ctx: &mut Context, _: &mut AnalysisManager) -> bool {
+        if ctx.global_by_name(COVERAGE_COUNTS).is_some() {
+            // already instrumented
+            return false;
+        }
+        let blocks = ctx
+            .funcs()
+            .filter(|func| !func.is_declaration(ctx))
+            .flat_map(|func| func.iter(ctx))
+            .collect::<Vec<_>>();
+        if blocks.is_empty() {
+            return false;
+        }
+
+        let i32 = Ty::i32(ctx);
+        let counts_ty = Ty::array(ctx, i32, blocks.len());
+        let counts = Global::new(
+            ctx,
+            COVERAGE_COUNTS.to_string(),
+            ConstantValue::AggregateZero { ty: counts_ty },
+        );
+        counts.set_align(ctx, 4);
+
+        let mut pairs = Vec::new();
+        for (counter, &block) in blocks.iter().enumerate() {
+            let insts = block.iter(ctx).collect::<Vec<_>>();
+            if let Some(source) = ctx.source() {
+                for line in Self::block_lines(ctx, source, &insts) {
+                    pairs.push((line, counter as i32));
+                }
+            }
+
+            // the increment, after the phis
+            let first = insts.into_iter().find(|inst| !inst.is_phi(ctx));
+            let one = Value::i32(ctx, 1);
+            for inst in increment(ctx, COVERAGE_COUNTS, counts_ty, counter, one) {
+                match first {
+                    Some(first) => first.insert_before(ctx, inst).unwrap(),
+                    None => block.push_back(ctx, inst).unwrap(),
+                }
+            }
+        }
+
+        let map_ty = Ty::array(ctx, i32, (2 * pairs.len()).max(1));
+        let mut elems = pairs
+            .iter()
+            .flat_map(|&(line, counter)| [line, counter])
+            .map(|value| ConstantValue::i32(ctx, value))
+            .collect::<Vec<_>>();
+        if elems.is_empty() {
+            elems.push(ConstantValue::i32(ctx, 0));
+        }
+        let map = Global::new(
+            ctx,
+            COVERAGE_MAP.to_string(),
+            ConstantValue::Array { ty: map_ty, elems },
+        );
+        map.set_const(ctx, true);
+
+        let Some(main) = ctx
+            .func_by_name("main")
+            .filter(|main| !main.is_declaration(ctx))
+        else {
+            return true;
+        };
+        let rets = main
+            .iter(ctx)
+            .filter_map(|block| block.tail(ctx))
+            .filter(|inst| matches!(inst.kind(ctx), InstKind::Ret))
+            .collect::<Vec<_>>();
+        let dump = Self::dump_decl(ctx);
+        for ret in rets {
+            let n = Value::i32(ctx, pairs.len() as i32);
+            let map = Value::global_ref(ctx, COVERAGE_MAP.to_string(), map_ty);
+            let counts = Value::global_ref(ctx, COVERAGE_COUNTS.to_string(), counts_ty);
+            let call = Inst::call(ctx, dump, vec![n, map, counts]);
+            ret.insert_before(ctx, call).unwrap();
+        }
+        true
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CoverageError {
+    #[error("line {0} of the dump is not `line count`")]
+    Malformed(usize),
+    #[error("the dump counts line {0}, past the end of the source")]
+    LineOutOfRange(usize),
+}
+
+/// Parse a dump of the runtime, and add the counts to the ones of the lines.
+///
+/// A line of several blocks is counted as the most executed one, e.g., the
+/// header of a loop.
+pub fn parse_coverage_dump(
+    dump: &str,
+    counts: &mut BTreeMap<usize, u64>,
+) -> Result<(), CoverageError> {
+    let mut lines = BTreeMap::new();
+    for (idx, text) in dump.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        let mut fields = text.split_whitespace().map(str::parse::<u64>);
+        let (Some(Ok(line)), Some(Ok(count)), None) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(CoverageError::Malformed(idx + 1));
+        };
+        let max = lines.entry(line as usize).or_insert(0);
+        *max = count.max(*max);
+    }
+    for (line, count) in lines {
+        *counts.entry(line).or_insert(0) += count;
+    }
+    Ok(())
+}
+
+/// Annotate the source with the counts of the dumps of its runs, summed, as
+/// `gcov` does:
+///
+/// ```text
+///         -:    1:int main() {
+///         3:    2:  int a = getint();
+///     #####:    3:  if (a < 0) return 1;
+/// ```
+///
+/// The lines of no block are marked by `-`, and the ones never executed by
+/// `#####`. The percentage of the lines executed follows.
+pub fn coverage_report(source: &Source, dumps: &[&str]) -> Result<String, CoverageError> {
+    let mut counts = BTreeMap::new();
+    for dump in dumps {
+        parse_coverage_dump(dump, &mut counts)?;
+    }
+    let num_lines = source.text().lines().count();
+    if let Some((&line, _)) = counts.range(num_lines + 1..).next() {
+        return Err(CoverageError::LineOutOfRange(line));
+    }
+
+    let mut report = String::new();
+    for (idx, text) in source.text().lines().enumerate() {
+        let count = match counts.get(&(idx + 1)) {
+            None => "-".to_string(),
+            Some(0) => "#####".to_string(),
+            Some(count) => count.to_string(),
+        };
+        writeln!(report, "{:>9}:{:>5}:{}", count, idx + 1, text).unwrap();
+    }
+    let executed = counts.values().filter(|&&count| count > 0).count();
+    match counts.len() {
+        0 => writeln!(report, "No executable lines").unwrap(),
+        total => writeln!(
+            report,
+            "Lines executed: {:.2}% of {}",
+            executed as f64 * 100.0 / total as f64,
+            total
+        )
+        .unwrap(),
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::interp::Interpreter;
+    use crate::ir::{Block, IntCmpCond};
+
+    #[test]
+    fn test_coverage() {
+        // int main() {
+        //   int a = getint();
+        //   if (a < 0)
+        //     return 1;
+        //   return 0;
+        // }
+        let text =
+            "int main() {\n  int a = getint();\n  if (a < 0)\n    return 1;\n  return 0;\n}\n";
+        let loc = |line: usize| {
+            text.lines()
+                .take(line - 1)
+                .map(|l| l.len() + 1)
+                .sum::<usize>() as u32
+        };
+        let mut ctx = Context::default();
+        ctx.set_source(Source::new("a.sy", text));
+        let i32 = Ty::i32(&mut ctx);
+        let getint = Func::new(&mut ctx, "getint".to_string(), i32);
+        let main = Func::new(&mut ctx, "main".to_string(), i32);
+        let [entry, then, exit] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, then, exit] {
+            main.push_back(&mut ctx, block).unwrap();
+        }
+        let [zero, one] = [0, 1].map(|v| Value::i32(&mut ctx, v));
+
+        ctx.set_curr_loc(Some(loc(2)));
+        let call = Inst::call(&mut ctx, getint, Vec::new());
+        ctx.set_curr_loc(Some(loc(3)));
+        let lt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let a = call.result(&ctx).unwrap();
+        let cmp = Inst::ibinary(&mut ctx, lt, a, zero);
+        let cond = cmp.result(&ctx).unwrap();
+        let br = Inst::cond_br(&mut ctx, cond, then, exit);
+        for inst in [call, cmp, br] {
+            entry.push_back(&mut ctx, inst).unwrap();
+        }
+        ctx.set_curr_loc(Some(loc(4)));
+        let ret = Inst::ret(&mut ctx, Some(one));
+        then.push_back(&mut ctx, ret).unwrap();
+        ctx.set_curr_loc(Some(loc(5)));
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        exit.push_back(&mut ctx, ret).unwrap();
+        ctx.set_curr_loc(None);
+
+        let mut am = AnalysisManager::default();
+        assert!(Coverage.run(&mut ctx, &mut am));
+        assert!(!Coverage.run(&mut ctx, &mut am), "instrumented twice");
+        ctx.verify().unwrap();
+
+        let run = |input: &str| {
+            let mut interp = Interpreter::new(&ctx, input.as_bytes(), Vec::new());
+            interp.run_main().unwrap();
+            interp.coverage().unwrap().to_string()
+        };
+        let dumps = [run("5"), run("-5"), run("7")];
+        assert_eq!(dumps[0], "2 1\n3 1\n4 0\n5 1\n");
+
+        let dumps = dumps.iter().map(String::as_str).collect::<Vec<_>>();
+        let report = coverage_report(ctx.source().unwrap(), &dumps).unwrap();
+        let expected = [
+            "        -:    1:int main() {",
+            "        3:    2:  int a = getint();",
+            "        3:    3:  if (a < 0)",
+            "        1:    4:    return 1;",
+            "        2:    5:  return 0;",
+            "        -:    6:}",
+            "Lines executed: 100.00% of 4",
+        ];
+        assert_eq!(report.lines().collect::<Vec<_>>(), expected);
+
+        let report = coverage_report(ctx.source().unwrap(), &dumps[..1]).unwrap();
+        assert!(report.contains("    #####:    4:    return 1;"));
+        assert!(report.ends_with("Lines executed: 75.00% of 4\n"));
+
+        let source = ctx.source().unwrap();
+        assert_eq!(
+            coverage_report(source, &["2 1 1"]),
+            Err(CoverageError::Malformed(1))
+        );
+        assert_eq!(
+            coverage_report(source, &["9 1"]),
+            Err(CoverageError::LineOutOfRange(9))
+        );
+    }
+}
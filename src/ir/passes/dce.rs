@@ -0,0 +1,140 @@
+//! Dead code elimination.
+//!
+//! The instructions are assumed dead until proven live:
+//!
+//! - An instruction that may have side effects, i.e., a terminator, a store or
+//!   a call writing memory, is live.
+//! - The instructions defining the operands of a live instruction are live.
+//!
+//! Everything else is removed, including unused loads, allocas and calls not
+//! writing memory. Since liveness only flows from the side effects, cycles of
+//! dead instructions, e.g., an induction variable only used by its own
+//! increment, are removed as well.
+
+use std::collections::HashSet;
+
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Context, Func, Inst, Value};
+
+/// The removal of instructions not contributing to any side effect.
+#[derive(Clone, Default)]
+pub struct Dce;
+
+impl Dce {
+    /// Get the values used by an instruction.
+    fn used_values(ctx: &Context, inst: Inst) -> Vec<Value> {
+        if inst.is_phi(ctx) {
+            inst.incoming_iter(ctx).map(|(_, value)| value).collect()
+        } else {
+            inst.operand_iter(ctx).collect()
+        }
+    }
+}
+
+impl TransformPass for Dce {
+    fn name(&self) -> &'static str { "dce" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let insts = func
+            .iter(ctx)
+            .flat_map(|block| block.iter(ctx))
+            .collect::<Vec<_>>();
+
+        let mut live = HashSet::new();
+        let mut worklist = insts
+            .iter()
+            .copied()
+            .filter(|inst| inst.may_have_side_effects(ctx))
+            .collect::<Vec<_>>();
+        while let Some(inst) = worklist.pop() {
+            if !live.insert(inst) {
+                continue;
+            }
+            for value in Self::used_values(ctx, inst) {
+                if let Some(def) = value.def_inst(ctx) {
+                    worklist.push(def);
+                }
+            }
+        }
+
+        // The dead values are only used by dead instructions, which may come
+        // in any order, so all the uses are replaced first.
+        let dead = insts
+            .into_iter()
+            .filter(|inst| !live.contains(inst))
+            .collect::<Vec<_>>();
+        for &inst in dead.iter() {
+            if let Some(result) = inst.result(ctx) {
+                let undef = Value::undef(ctx, result.ty(ctx));
+                result.replace_all_uses_with(ctx, undef);
+            }
+        }
+        for &inst in dead.iter() {
+            inst.remove(ctx);
+        }
+
+        if !dead.is_empty() {
+            am.invalidate(func);
+        }
+        !dead.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, IntBinaryOp, Ty};
+
+    #[test]
+    fn test_dce() {
+        // entry: p = alloca i32; store 1, p; br header
+        // header: i = phi [entry: 0, header: i1]; i1 = i + 1; x = load p;
+        //         br c, header, exit
+        // exit: ret 0
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let i1_ty = Ty::i1(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let c = func.add_param(&mut ctx, i1_ty);
+        let [entry, header, exit] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, header, exit] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [zero, one] = [0, 1].map(|v| Value::i32(&mut ctx, v));
+
+        let alloca = Inst::alloca(&mut ctx, i32);
+        let p = alloca.result(&ctx).unwrap();
+        let store = Inst::store(&mut ctx, one, p);
+        let entry_br = Inst::br(&mut ctx, header);
+        for inst in [alloca, store, entry_br] {
+            entry.push_back(&mut ctx, inst).unwrap();
+        }
+
+        let phi = Inst::phi(&mut ctx, i32);
+        let i = phi.result(&ctx).unwrap();
+        let inc = Inst::ibinary(&mut ctx, IntBinaryOp::Add, i, one);
+        let i1 = inc.result(&ctx).unwrap();
+        phi.insert_incoming(&mut ctx, entry, zero);
+        phi.insert_incoming(&mut ctx, header, i1);
+        let load = Inst::load(&mut ctx, p, i32);
+        let br = Inst::cond_br(&mut ctx, c, header, exit);
+        for inst in [phi, inc, load, br] {
+            header.push_back(&mut ctx, inst).unwrap();
+        }
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(Dce.run_on_func(&mut ctx, func, &mut am));
+        // the store keeps the alloca alive
+        assert_eq!(
+            entry.iter(&ctx).collect::<Vec<_>>(),
+            [alloca, store, entry_br]
+        );
+        assert_eq!(header.iter(&ctx).collect::<Vec<_>>(), [br]);
+        assert!(!Dce.run_on_func(&mut ctx, func, &mut am));
+    }
+}
@@ -0,0 +1,236 @@
+//! Strength reduction of signed division and remainder by constants.
+//!
+//! `sdiv x, d` and `srem x, d` on `i32` with a constant `d`, where `|d| >= 2`
+//! and `d` is not `i32::MIN`, are replaced by sequences without division:
+//!
+//! - If `|d| = 2^k`, the dividend is biased by `2^k - 1` when negative, so the
+//!   arithmetic shift rounds towards zero:
+//!
+//!   ```text
+//!   bias = lshr (ashr x, 31), 32 - k
+//!   q    = ashr (add x, bias), k         ; negated if d < 0
+//!   r    = sub x, (and (add x, bias), -2^k)
+//!   ```
+//!
+//! - Otherwise, the quotient is computed with a multiplication by a magic
+//!   number `m` and a shift `s`, as in Hacker's Delight, chapter 10:
+//!
+//!   ```text
+//!   q = smulhi x, m                      ; add or sub x if the signs differ
+//!   q = ashr q, s
+//!   q = add q, (lshr q, 31)              ; round towards zero
+//!   r = sub x, (mul q, d)
+//!   ```
+
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Context, Func, Inst, InstKind, IntBinaryOp, Value};
+
+/// The strength reduction of division and remainder by constants.
+#[derive(Clone, Default)]
+pub struct DivByConst;
+
+/// Compute the magic number and the shift for signed division by `d`.
+///
+/// # Panics
+///
+/// - Panics if `|d| < 2`.
+fn magic(d: i32) -> (i32, u32) {
+    assert!(d.unsigned_abs() >= 2, "no magic number for {}", d);
+    const TWO31: u32 = 1 << 31;
+
+    let ad = d.unsigned_abs();
+    let t = TWO31 + (d as u32 >> 31);
+    // The absolute value of the largest dividend with `nc % d == d - 1`.
+    let anc = t - 1 - t % ad;
+    let mut p = 31;
+    let (mut q1, mut r1) = (TWO31 / anc, TWO31 % anc);
+    let (mut q2, mut r2) = (TWO31 / ad, TWO31 % ad);
+    loop {
+        p += 1;
+        // Keep `q1 = 2^p / anc` and `q2 = 2^p / ad`, with the remainders.
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+        let delta = ad - r2;
+        if q1 > delta || (q1 == delta && r1 != 0) {
+            break;
+        }
+    }
+
+    let m = q2.wrapping_add(1) as i32;
+    let m = if d < 0 { m.wrapping_neg() } else { m };
+    (m, p - 32)
+}
+
+impl DivByConst {
+    /// Compute `x / d` or `x % d` before an instruction.
+    fn expand(ctx: &mut Context, before: Inst, is_rem: bool, x: Value, d: i32) -> Value {
+        use IntBinaryOp::*;
+
+        let [zero, k31] = [0, 31].map(|v| Value::i32(ctx, v));
+
+        if d.unsigned_abs().is_power_of_two() {
+            let k = d.trailing_zeros() as i32;
+            let [k, rk, mask] = [k, 32 - k, -(1 << k)].map(|v| Value::i32(ctx, v));
+            let sign = Value::binary_before(ctx, before, AShr, x, k31);
+            let bias = Value::binary_before(ctx, before, LShr, sign, rk);
+            let biased = Value::binary_before(ctx, before, Add, x, bias);
+            if is_rem {
+                let rounded = Value::binary_before(ctx, before, And, biased, mask);
+                return Value::binary_before(ctx, before, Sub, x, rounded);
+            }
+            let q = Value::binary_before(ctx, before, AShr, biased, k);
+            if d < 0 {
+                return Value::binary_before(ctx, before, Sub, zero, q);
+            }
+            return q;
+        }
+
+        let (m, s) = magic(d);
+        let signs = (d > 0, m > 0);
+        let [m, s, d] = [m, s as i32, d].map(|v| Value::i32(ctx, v));
+        let mut q = Value::binary_before(ctx, before, SMulHi, x, m);
+        match signs {
+            (true, false) => q = Value::binary_before(ctx, before, Add, q, x),
+            (false, true) => q = Value::binary_before(ctx, before, Sub, q, x),
+            _ => {}
+        }
+        q = Value::binary_before(ctx, before, AShr, q, s);
+        let sign = Value::binary_before(ctx, before, LShr, q, k31);
+        q = Value::binary_before(ctx, before, Add, q, sign);
+        if !is_rem {
+            return q;
+        }
+        let prod = Value::binary_before(ctx, before, Mul, q, d);
+        Value::binary_before(ctx, before, Sub, x, prod)
+    }
+}
+
+impl TransformPass for DivByConst {
+    fn name(&self) -> &'static str { "div-by-const" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let insts = func
+            .iter(ctx)
+            .flat_map(|block| block.iter(ctx))
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+        for inst in insts {
+            let is_rem = match inst.kind(ctx) {
+                InstKind::IntBinary {
+                    op: IntBinaryOp::SDiv,
+                } => false,
+                InstKind::IntBinary {
+                    op: IntBinaryOp::SRem,
+                } => true,
+                _ => continue,
+            };
+            let (x, d) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+            if x.ty(ctx).bitwidth(ctx) != 32 {
+                continue;
+            }
+            let Some(d) = d
+                .as_const_int(ctx)
+                .filter(|&d| d != i32::MIN && d.abs() >= 2)
+            else {
+                continue;
+            };
+            let value = Self::expand(ctx, inst, is_rem, x, d);
+            inst.result(ctx).unwrap().replace_all_uses_with(ctx, value);
+            inst.remove(ctx);
+            changed = true;
+        }
+
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Ty};
+
+    /// Build `ret op x, d`, where `x` is the parameter or a constant.
+    fn build(ctx: &mut Context, op: IntBinaryOp, x: Option<i32>, d: i32) -> (Func, Inst) {
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, "f".to_string(), i32);
+        let param = func.add_param(ctx, i32);
+        let block = Block::new(ctx);
+        func.push_back(ctx, block).unwrap();
+        let x = x.map_or(param, |x| Value::i32(ctx, x));
+        let d = Value::i32(ctx, d);
+        let div = Inst::ibinary(ctx, op, x, d);
+        block.push_back(ctx, div).unwrap();
+        let ret = Inst::ret(ctx, div.result(ctx));
+        block.push_back(ctx, ret).unwrap();
+        (func, ret)
+    }
+
+    #[test]
+    fn test_div_by_const() {
+        // Both signs of each, and `i32::MIN` as a dividend.
+        let divisors = [2, 3, 5, 6, 7, 8, 10, 641, 1 << 30, i32::MAX];
+        let divisors = divisors.into_iter().flat_map(|d| [d, -d]);
+        let dividends = [0, 1, 7, 100, 123456789, i32::MAX];
+        let dividends = dividends
+            .into_iter()
+            .flat_map(|x| [x, -x])
+            .chain([i32::MIN])
+            .collect::<Vec<_>>();
+
+        // The expanded sequences are folded into the exact results.
+        for d in divisors {
+            for &x in dividends.iter() {
+                for (op, expected) in [
+                    (IntBinaryOp::SDiv, x.wrapping_div(d)),
+                    (IntBinaryOp::SRem, x.wrapping_rem(d)),
+                ] {
+                    let mut ctx = Context::default();
+                    let (func, ret) = build(&mut ctx, op, Some(x), d);
+                    let mut am = AnalysisManager::default();
+                    assert!(DivByConst.run_on_func(&mut ctx, func, &mut am));
+                    let result = ret.operand(&ctx, 0).as_const_int(&ctx);
+                    assert_eq!(result, Some(expected), "{} {} {}", op, x, d);
+                }
+            }
+        }
+
+        let mut ctx = Context::default();
+        let (func, _) = build(&mut ctx, IntBinaryOp::SRem, None, 7);
+        let mut am = AnalysisManager::default();
+        assert!(DivByConst.run_on_func(&mut ctx, func, &mut am));
+        let ops = func
+            .head(&ctx)
+            .unwrap()
+            .iter(&ctx)
+            .filter_map(|inst| match inst.kind(&ctx) {
+                InstKind::IntBinary { op } => Some(*op),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(ops.contains(&IntBinaryOp::SMulHi));
+        assert!(!ops.contains(&IntBinaryOp::SRem));
+
+        // division by 1 is left to instruction combining
+        let mut ctx = Context::default();
+        let (func, _) = build(&mut ctx, IntBinaryOp::SDiv, None, 1);
+        let mut am = AnalysisManager::default();
+        assert!(!DivByConst.run_on_func(&mut ctx, func, &mut am));
+    }
+}
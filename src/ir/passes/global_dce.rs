@@ -0,0 +1,144 @@
+//! Global dead code elimination.
+//!
+//! Functions and globals are live if they are reachable from the roots, i.e.,
+//! `main` and the symbols marked as external, through references in function
+//! bodies and global initializers. All the other functions and globals are
+//! removed from the module.
+
+use std::collections::HashSet;
+
+use crate::infra::intern::Symbol;
+use crate::infra::storage::Arena;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Context, Global};
+
+/// The removal of unreferenced functions and globals.
+#[derive(Default)]
+pub struct GlobalDce;
+
+impl GlobalDce {
+    /// Find the names of the live symbols.
+    fn live_symbols(ctx: &Context) -> HashSet<Symbol> {
+        let mut worklist = ctx
+            .funcs()
+            .filter(|func| func.name(ctx) == "main" || func.is_external(ctx))
+            .map(|func| func.name(ctx))
+            .chain(
+                ctx.globals()
+                    .filter(|global| global.is_external(ctx))
+                    .map(|global| global.name(ctx)),
+            )
+            .collect::<Vec<_>>();
+
+        let mut live = HashSet::new();
+        while let Some(name) = worklist.pop() {
+            if !live.insert(name) {
+                continue;
+            }
+            if let Some(func) = ctx.func_by_name(name) {
+                worklist.extend(func.refs(ctx));
+            } else if let Some(global) = ctx.global_by_name(name) {
+                global.value(ctx).collect_refs(&mut worklist);
+            }
+        }
+        live
+    }
+}
+
+impl TransformPass for GlobalDce {
+    fn name(&self) -> &'static str { "global-dce" }
+
+    fn run(&mut self, ctx: &mut Context, _: &mut AnalysisManager) -> bool {
+        let live = Self::live_symbols(ctx);
+
+        let dead_funcs = ctx
+            .funcs()
+            .filter(|func| !live.contains(&func.name(ctx)))
+            .collect::<Vec<_>>();
+        let dead_globals = ctx
+            .globals()
+            .filter(|global| !live.contains(&global.name(ctx)))
+            .collect::<Vec<_>>();
+        if dead_funcs.is_empty() && dead_globals.is_empty() {
+            return false;
+        }
+
+        for func in dead_funcs {
+            ctx.try_dealloc(func).unwrap();
+        }
+        for global in dead_globals {
+            Arena::<Global>::try_dealloc(ctx, global).unwrap();
+        }
+        // Sweep the bodies of the removed functions, and their uses of the
+        // remaining values.
+        ctx.gc();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::linked_list::LinkedListContainer;
+    use crate::infra::storage::ArenaPtr;
+    use crate::ir::{Block, ConstantValue, Func, Inst, Ty, Value};
+
+    #[test]
+    fn test_global_dce() {
+        // main calls used, which loads @g; unused loads @h and calls itself;
+        // @exported is external, @table refers to @g2.
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let arr = Ty::array(&mut ctx, i32, 1);
+        let mut globals = Vec::new();
+        for name in ["g", "h", "g2", "exported"] {
+            let zero = ConstantValue::i32(&mut ctx, 0);
+            globals.push(Global::new(&mut ctx, name.to_string(), zero));
+        }
+        let [g, h, g2, exported] = globals[..] else {
+            unreachable!()
+        };
+        let g2_ref = ConstantValue::global_ref(&mut ctx, "g2".to_string(), i32);
+        let table_value = ConstantValue::Array {
+            ty: arr,
+            elems: vec![g2_ref],
+        };
+        let table = Global::new(&mut ctx, "table".to_string(), table_value);
+        exported.set_external(&mut ctx, true);
+
+        let funcs = ["main", "used", "unused", "putint", "hook"]
+            .map(|name| Func::new(&mut ctx, name.to_string(), i32));
+        let [main, used, unused, putint, hook] = funcs;
+        hook.set_external(&mut ctx, true);
+
+        let define = |ctx: &mut Context, func: Func, callees: &[Func], global: &str| {
+            let block = Block::new(ctx);
+            func.push_back(ctx, block).unwrap();
+            for &callee in callees {
+                let call = Inst::call(ctx, callee, vec![]);
+                block.push_back(ctx, call).unwrap();
+            }
+            let ptr = Value::global_ref(ctx, global.to_string(), i32);
+            let load = Inst::load(ctx, ptr, i32);
+            block.push_back(ctx, load).unwrap();
+            let result = load.result(ctx);
+            let ret = Inst::ret(ctx, result);
+            block.push_back(ctx, ret).unwrap();
+        };
+        define(&mut ctx, main, &[used, putint], "table");
+        define(&mut ctx, used, &[], "g");
+        define(&mut ctx, unused, &[unused], "h");
+        define(&mut ctx, hook, &[], "g");
+
+        let mut am = AnalysisManager::default();
+        assert!(GlobalDce.run(&mut ctx, &mut am));
+
+        let funcs = ctx.funcs().collect::<HashSet<_>>();
+        assert_eq!(funcs, HashSet::from([main, used, putint, hook]));
+        let globals = ctx.globals().collect::<HashSet<_>>();
+        assert_eq!(globals, HashSet::from([g, g2, table, exported]));
+        assert!(h.try_deref(&ctx).is_none());
+
+        assert!(!GlobalDce.run(&mut ctx, &mut am));
+    }
+}
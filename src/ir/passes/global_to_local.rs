@@ -0,0 +1,255 @@
+//! Promotion of globals to locals.
+//!
+//! A global is rewritten into an alloca, initialized at the entry of the
+//! function, if:
+//!
+//! - it is an integer scalar that is not visible outside of the module;
+//! - it is only accessed by direct loads and stores of its type, i.e., its
+//!   address is not taken, and it is not referred to by other globals;
+//! - all the accesses are in the same function, which is executed at most once
+//!   per run of the program, so the value does not need to survive between
+//!   calls.
+//!
+//! A function is executed at most once if it is `main` without callers, or it
+//! is not recursive and has a single call site outside of loops in such a
+//! function. The new allocas can be promoted to SSA values afterwards.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::infra::intern::Symbol;
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::infra::storage::Arena;
+use crate::ir::analysis::{CallGraph, LoopInfo};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{ConstantValue, Context, Func, Global, Inst, InstKind, Value};
+
+/// The promotion of globals accessed by a single function to locals.
+#[derive(Default)]
+pub struct GlobalToLocal;
+
+/// The accesses to a global, as the instructions and the operand indices.
+type Accesses = Vec<(Inst, usize)>;
+
+impl GlobalToLocal {
+    /// Collect the direct accesses to each global referred to in function
+    /// bodies.
+    ///
+    /// # Returns
+    ///
+    /// The accesses of each global, and the names of the globals referred to
+    /// otherwise, which cannot be promoted.
+    fn collect_accesses(ctx: &Context) -> (HashMap<Symbol, Accesses>, HashSet<Symbol>) {
+        let mut accesses: HashMap<Symbol, Accesses> = HashMap::new();
+        let mut taken = HashSet::new();
+        for func in ctx.funcs() {
+            for block in func.iter(ctx) {
+                for inst in block.iter(ctx) {
+                    for (idx, operand) in inst.operand_iter(ctx).enumerate() {
+                        let Some(ConstantValue::GlobalRef { name, value_ty, .. }) =
+                            operand.as_const(ctx)
+                        else {
+                            continue;
+                        };
+                        let access_ty = match inst.kind(ctx) {
+                            InstKind::Load => inst.result(ctx).map(|value| value.ty(ctx)),
+                            InstKind::Store if idx == 1 => Some(inst.operand(ctx, 0).ty(ctx)),
+                            _ => None,
+                        };
+                        if access_ty == Some(*value_ty) {
+                            accesses.entry(*name).or_default().push((inst, idx));
+                        } else {
+                            taken.insert(*name);
+                        }
+                    }
+                }
+            }
+        }
+
+        fn constant_refs(value: &ConstantValue, taken: &mut HashSet<Symbol>) {
+            match value {
+                ConstantValue::GlobalRef { name, .. } => {
+                    taken.insert(*name);
+                }
+                ConstantValue::Array { elems, .. } => {
+                    for elem in elems {
+                        constant_refs(elem, taken);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for global in ctx.globals() {
+            constant_refs(global.value(ctx), &mut taken);
+        }
+
+        (accesses, taken)
+    }
+
+    /// Check if a function is executed at most once per run of the program.
+    fn runs_once(ctx: &Context, cg: &CallGraph, am: &mut AnalysisManager, func: Func) -> bool {
+        if func.name(ctx) == "main" {
+            return cg.callers(func).is_empty();
+        }
+        if func.is_external(ctx) || cg.is_recursive(func) {
+            return false;
+        }
+        let &[site] = cg.call_sites(func) else {
+            return false;
+        };
+        let block = site.container(ctx).unwrap();
+        let caller = block.container(ctx).unwrap();
+        Self::runs_once(ctx, cg, am, caller)
+            && am.get::<LoopInfo>(ctx, caller).loop_of(block).is_none()
+    }
+
+    /// Rewrite the accesses to a global into accesses to a new alloca in the
+    /// function, and remove the global.
+    fn promote(ctx: &mut Context, global: Global, func: Func, accesses: Accesses) {
+        let entry = func.head(ctx).unwrap();
+        let init = global.value(ctx).clone();
+        let alloca = Inst::alloca(ctx, init.ty());
+        let slot = alloca.result(ctx).unwrap();
+        if !matches!(init, ConstantValue::Undef { .. }) {
+            let init = Value::new_constant(ctx, init);
+            let store = Inst::store(ctx, init, slot);
+            entry.push_front(ctx, store).unwrap();
+        }
+        entry.push_front(ctx, alloca).unwrap();
+
+        for (inst, idx) in accesses {
+            inst.set_operand(ctx, idx, slot);
+        }
+        Arena::<Global>::try_dealloc(ctx, global).unwrap();
+    }
+}
+
+impl TransformPass for GlobalToLocal {
+    fn name(&self) -> &'static str { "global-to-local" }
+
+    fn run(&mut self, ctx: &mut Context, am: &mut AnalysisManager) -> bool {
+        let (mut accesses, taken) = Self::collect_accesses(ctx);
+        let cg = CallGraph::new(ctx);
+
+        let globals = ctx.globals().collect::<Vec<_>>();
+        let mut changed = false;
+        for global in globals {
+            let name = global.name(ctx);
+            if global.is_external(ctx) || !global.ty(ctx).is_integer(ctx) || taken.contains(&name) {
+                continue;
+            }
+            let Some(global_accesses) = accesses.remove(&name) else {
+                continue;
+            };
+            let block_of = |&(inst, _): &(Inst, usize)| inst.container(ctx).unwrap();
+            let func = block_of(&global_accesses[0]).container(ctx).unwrap();
+            if global_accesses
+                .iter()
+                .any(|access| block_of(access).container(ctx) != Some(func))
+                || !Self::runs_once(ctx, &cg, am, func)
+            {
+                continue;
+            }
+            Self::promote(ctx, global, func, global_accesses);
+            am.invalidate(func);
+            changed = true;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Ty};
+
+    #[test]
+    fn test_global_to_local() {
+        // main: store 1, @g; load @g; load @shared; call once(); call twice();
+        //       call twice(); call take(@taken); load @exported
+        // once: load @once_only; load @shared
+        // twice: load @twice_only
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let ptr = Ty::ptr(&mut ctx);
+        let names = [
+            "g",
+            "shared",
+            "once_only",
+            "twice_only",
+            "taken",
+            "exported",
+        ];
+        let [g, shared, once_only, twice_only, taken, exported] = names.map(|name| {
+            let five = ConstantValue::i32(&mut ctx, 5);
+            Global::new(&mut ctx, name.to_string(), five)
+        });
+        exported.set_external(&mut ctx, true);
+
+        let [main, once, twice] =
+            ["main", "once", "twice"].map(|name| Func::new(&mut ctx, name.to_string(), i32));
+        let take = Func::new(&mut ctx, "take".to_string(), i32);
+        take.add_param(&mut ctx, ptr);
+
+        let push = |ctx: &mut Context, func: Func, inst: Inst| {
+            if func.head(ctx).is_none() {
+                let block = Block::new(ctx);
+                func.push_back(ctx, block).unwrap();
+            }
+            func.head(ctx).unwrap().push_back(ctx, inst).unwrap();
+            inst
+        };
+        let load = |ctx: &mut Context, func: Func, name: &str| {
+            let ptr = Value::global_ref(ctx, name.to_string(), i32);
+            let load = Inst::load(ctx, ptr, i32);
+            push(ctx, func, load)
+        };
+
+        let one = Value::i32(&mut ctx, 1);
+        let g_ref = Value::global_ref(&mut ctx, "g".to_string(), i32);
+        let store = Inst::store(&mut ctx, one, g_ref);
+        push(&mut ctx, main, store);
+        let g_load = load(&mut ctx, main, "g");
+        load(&mut ctx, main, "shared");
+        for callee in [once, twice, twice] {
+            let call = Inst::call(&mut ctx, callee, vec![]);
+            push(&mut ctx, main, call);
+        }
+        let taken_ref = Value::global_ref(&mut ctx, "taken".to_string(), i32);
+        let call = Inst::call(&mut ctx, take, vec![taken_ref]);
+        push(&mut ctx, main, call);
+        load(&mut ctx, main, "exported");
+        let once_load = load(&mut ctx, once, "once_only");
+        load(&mut ctx, once, "shared");
+        load(&mut ctx, twice, "twice_only");
+        for func in [main, once, twice] {
+            let zero = Value::i32(&mut ctx, 0);
+            let ret = Inst::ret(&mut ctx, Some(zero));
+            push(&mut ctx, func, ret);
+        }
+
+        let mut am = AnalysisManager::default();
+        assert!(GlobalToLocal.run(&mut ctx, &mut am));
+        let globals = ctx.globals().collect::<HashSet<_>>();
+        assert_eq!(
+            globals,
+            HashSet::from([shared, twice_only, taken, exported])
+        );
+        assert!(!globals.contains(&g) && !globals.contains(&once_only));
+
+        // the allocas are initialized at the entry
+        for (func, load) in [(main, g_load), (once, once_load)] {
+            let entry = func.head(&ctx).unwrap();
+            let alloca = entry.head(&ctx).unwrap();
+            assert!(matches!(alloca.kind(&ctx), InstKind::Alloca { .. }));
+            let slot = alloca.result(&ctx).unwrap();
+            let init = alloca.next(&ctx).unwrap();
+            assert!(matches!(init.kind(&ctx), InstKind::Store));
+            assert_eq!(init.operand(&ctx, 0).as_const_int(&ctx), Some(5));
+            assert_eq!(init.operand(&ctx, 1), slot);
+            assert_eq!(load.operand(&ctx, 0), slot);
+        }
+        assert_eq!(store.operand(&ctx, 1), g_load.operand(&ctx, 0));
+
+        assert!(!GlobalToLocal.run(&mut ctx, &mut am));
+    }
+}
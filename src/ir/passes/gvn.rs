@@ -0,0 +1,264 @@
+//! Global value numbering.
+//!
+//! Pure instructions computing the same operation on the same operands are
+//! merged. The blocks are visited in a preorder walk of the dominator tree,
+//! with the expressions available in the dominators, so an instruction is
+//! only replaced by an equivalent one dominating it:
+//!
+//! - The integer arithmetic, the float arithmetic, the casts and the
+//!   `getelementptr`s are numbered. Loads are left to load elimination.
+//! - The operands of commutative integer operations are ordered, so `a + b` and
+//!   `b + a` are the same.
+//! - Constants are compared by value.
+//! - The phis in the same block with the same incoming values are merged.
+//!
+//! An instruction replaced by another one has all its uses redirected before
+//! the dominated blocks are visited, so the expressions using it are matched
+//! with the ones using the replacement.
+
+use crate::infra::hash::HashMap;
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::analysis::{Cfg, DomTree};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{
+    Block,
+    CastOp,
+    ConstantValue,
+    Context,
+    FloatBinaryOp,
+    Func,
+    Inst,
+    InstKind,
+    IntBinaryOp,
+    Ty,
+    Value,
+};
+
+/// An operand of an expression, with constants compared by value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Operand {
+    Value(Value),
+    Const(ConstantValue),
+}
+
+impl Operand {
+    fn new(ctx: &Context, value: Value) -> Self {
+        match value.as_const(ctx) {
+            Some(constant) => Operand::Const(constant.clone()),
+            None => Operand::Value(value),
+        }
+    }
+}
+
+/// The operation of an expression.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Op {
+    Int(IntBinaryOp),
+    Float(FloatBinaryOp),
+    Cast(CastOp, Ty),
+    GetElementPtr(Ty),
+}
+
+/// An expression computed by a pure instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Expr {
+    op: Op,
+    operands: Vec<Operand>,
+}
+
+impl Expr {
+    /// Get the expression computed by an instruction, if it is numbered.
+    fn of_inst(ctx: &Context, inst: Inst) -> Option<Self> {
+        let op = match *inst.kind(ctx) {
+            InstKind::IntBinary { op } => Op::Int(op),
+            InstKind::FloatBinary { op } => Op::Float(op),
+            InstKind::Cast { op } => Op::Cast(op, inst.result(ctx).unwrap().ty(ctx)),
+            InstKind::GetElementPtr { bound_ty } => Op::GetElementPtr(bound_ty),
+            _ => return None,
+        };
+        let mut operands = inst
+            .operand_iter(ctx)
+            .map(|value| Operand::new(ctx, value))
+            .collect::<Vec<_>>();
+        if let Op::Int(op) = op {
+            // Constants go last, and values by their identity.
+            let swap = match (&operands[0], &operands[1]) {
+                (Operand::Const(_), Operand::Value(_)) => true,
+                (Operand::Value(lhs), Operand::Value(rhs)) => lhs > rhs,
+                _ => false,
+            };
+            if op.is_commutative() && swap {
+                operands.swap(0, 1);
+            }
+        }
+        Some(Self { op, operands })
+    }
+}
+
+/// The merging of equivalent pure instructions.
+#[derive(Clone, Default)]
+pub struct Gvn;
+
+impl Gvn {
+    /// Merge the phis in a block with the same incoming values.
+    fn merge_phis(ctx: &mut Context, block: Block) -> bool {
+        let phis = block
+            .iter(ctx)
+            .take_while(|inst| inst.is_phi(ctx))
+            .collect::<Vec<_>>();
+        let mut seen = HashMap::default();
+        let mut changed = false;
+        for phi in phis {
+            let mut incomings = phi
+                .incoming_iter(ctx)
+                .map(|(pred, value)| (pred, Operand::new(ctx, value)))
+                .collect::<Vec<_>>();
+            incomings.sort_by_key(|(pred, _)| *pred);
+            let ty = phi.result(ctx).unwrap().ty(ctx);
+            match seen.get(&(ty, incomings.clone())) {
+                Some(&leader) => {
+                    phi.result(ctx).unwrap().replace_all_uses_with(ctx, leader);
+                    phi.remove(ctx);
+                    changed = true;
+                }
+                None => {
+                    seen.insert((ty, incomings), phi.result(ctx).unwrap());
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// The step of the dominator tree walk.
+enum Step {
+    /// Number the expressions in the block, and visit its children.
+    Enter(Block),
+    /// Forget the expressions of a block leaving its subtree.
+    Leave(Vec<Expr>),
+}
+
+impl TransformPass for Gvn {
+    fn name(&self) -> &'static str { "gvn" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let domtree = am.get::<DomTree>(ctx, func);
+
+        let mut changed = false;
+        let mut available: HashMap<Expr, Value> = HashMap::default();
+        let mut stack = cfg.entry().map(Step::Enter).into_iter().collect::<Vec<_>>();
+        while let Some(step) = stack.pop() {
+            let block = match step {
+                Step::Enter(block) => block,
+                Step::Leave(exprs) => {
+                    for expr in exprs {
+                        available.remove(&expr);
+                    }
+                    continue;
+                }
+            };
+
+            changed |= Self::merge_phis(ctx, block);
+            let mut exprs = Vec::new();
+            let mut cursor = block.cursor_mut(ctx);
+            while let Some(inst) = cursor.current() {
+                let expr = match inst.is_pure(ctx) {
+                    true => Expr::of_inst(ctx, inst),
+                    false => None,
+                };
+                let Some(expr) = expr else {
+                    cursor.move_next(ctx);
+                    continue;
+                };
+                let result = inst.result(ctx).unwrap();
+                match available.get(&expr) {
+                    Some(&leader) => {
+                        result.replace_all_uses_with(ctx, leader);
+                        cursor.remove_current(ctx);
+                        inst.remove(ctx);
+                        changed = true;
+                    }
+                    None => {
+                        available.insert(expr.clone(), result);
+                        exprs.push(expr);
+                        cursor.move_next(ctx);
+                    }
+                }
+            }
+
+            stack.push(Step::Leave(exprs));
+            for &child in domtree.children(block) {
+                stack.push(Step::Enter(child));
+            }
+        }
+
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gvn() {
+        // entry: a = x + y; m = x * 2; br c, then, else
+        // then: b = y + x; d = x * 2; s = b - d; ret s
+        // else: e = y - x; ret e
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let i1 = Ty::i1(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = func.add_param(&mut ctx, i32);
+        let y = func.add_param(&mut ctx, i32);
+        let c = func.add_param(&mut ctx, i1);
+        let [entry, then, else_] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, then, else_] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [two, another_two] = [2, 2].map(|v| Value::i32(&mut ctx, v));
+
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, x, y);
+        let mul = Inst::ibinary(&mut ctx, IntBinaryOp::Mul, x, two);
+        let br = Inst::cond_br(&mut ctx, c, then, else_);
+        for inst in [add, mul, br] {
+            entry.push_back(&mut ctx, inst).unwrap();
+        }
+        let (a, m) = (add.result(&ctx).unwrap(), mul.result(&ctx).unwrap());
+
+        let add_again = Inst::ibinary(&mut ctx, IntBinaryOp::Add, y, x);
+        let mul_again = Inst::ibinary(&mut ctx, IntBinaryOp::Mul, x, another_two);
+        let b = add_again.result(&ctx).unwrap();
+        let d = mul_again.result(&ctx).unwrap();
+        let sub = Inst::ibinary(&mut ctx, IntBinaryOp::Sub, b, d);
+        let s = sub.result(&ctx).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(s));
+        for inst in [add_again, mul_again, sub, ret] {
+            then.push_back(&mut ctx, inst).unwrap();
+        }
+
+        let sub_swapped = Inst::ibinary(&mut ctx, IntBinaryOp::Sub, y, x);
+        let e = sub_swapped.result(&ctx).unwrap();
+        let ret_else = Inst::ret(&mut ctx, Some(e));
+        else_.push_back(&mut ctx, sub_swapped).unwrap();
+        else_.push_back(&mut ctx, ret_else).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(Gvn.run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(then.iter(&ctx).collect::<Vec<_>>(), [sub, ret]);
+        assert_eq!(sub.operand(&ctx, 0), a);
+        assert_eq!(sub.operand(&ctx, 1), m);
+        // `y - x` is not `x - y`, nor available from `then`
+        assert_eq!(
+            else_.iter(&ctx).collect::<Vec<_>>(),
+            [sub_swapped, ret_else]
+        );
+        assert!(!Gvn.run_on_func(&mut ctx, func, &mut am));
+    }
+}
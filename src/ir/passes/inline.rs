@@ -0,0 +1,274 @@
+//! Function inlining.
+//!
+//! The callers are processed bottom-up in the call graph, so the callees are
+//! already inlined into when their costs are taken. A call is inlined if:
+//!
+//! - the callee is defined in the module, and is not recursive;
+//! - the callee is cheap enough for how often it is entered in the profiled
+//!   runs, or has a single call site, as decided by
+//!   [`InlineCosts::should_inline`];
+//! - an argument is passed for each parameter.
+//!
+//! The block of the call is split after it, the reachable blocks of the
+//! callee are cloned in between, and the returns branch to the rest of the
+//! block, with a phi merging the returned values. The allocas of the callee
+//! are moved to the entry of the caller, so inlining in a loop does not grow
+//! the stack. The callees left without callers are removed by global DCE.
+
+use std::collections::HashMap;
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{CallGraph, Cfg, CostModel, InlineCosts};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, Value};
+
+/// The inlining of calls to small or single-use functions.
+#[derive(Default)]
+pub struct Inline {
+    /// The heuristics deciding which callees are inlined.
+    pub model: CostModel,
+}
+
+impl Inline {
+    /// Find a call in the function to inline.
+    fn find_site(&self, ctx: &Context, caller: Func) -> Option<(Inst, Func)> {
+        let cg = CallGraph::new(ctx);
+        let costs = InlineCosts::new(ctx, self.model.clone());
+        caller
+            .iter(ctx)
+            .flat_map(|block| block.iter(ctx))
+            .filter(|inst| matches!(inst.kind(ctx), InstKind::Call))
+            .find_map(|call| {
+                let callee = ctx.func_by_name(call.callee(ctx))?;
+                let inlinable = !callee.is_declaration(ctx)
+                    && !cg.is_recursive(callee)
+                    && costs.should_inline(callee, cg.call_sites(callee).len())
+                    && call.operand_iter(ctx).count() == callee.params(ctx).len() + 1
+                    && callee.iter(ctx).all(|block| block.params(ctx).is_empty());
+                inlinable.then_some((call, callee))
+            })
+    }
+
+    /// Inline a call to the callee.
+    fn inline(ctx: &mut Context, caller: Func, call: Inst, callee: Func) {
+        let block = call.container(ctx).unwrap();
+        let caller_entry = caller.head(ctx).unwrap();
+
+        // The instructions after the call go to a new block, so the successors
+        // see it as the predecessor instead.
+        let cont = Block::new(ctx);
+        block.insert_after(ctx, cont).unwrap();
+        block.split(ctx, cont, call).unwrap();
+        let succs = cont
+            .tail(ctx)
+            .map(|term| term.successor_iter(ctx).collect::<Vec<_>>())
+            .unwrap_or_default();
+        for succ in succs {
+            let phis = succ
+                .iter(ctx)
+                .take_while(|inst| inst.is_phi(ctx))
+                .collect::<Vec<_>>();
+            for phi in phis {
+                if phi.incoming_iter(ctx).any(|(pred, _)| pred == block) {
+                    let value = phi.incoming(ctx, block);
+                    phi.remove_incoming(ctx, block);
+                    phi.insert_incoming(ctx, cont, value);
+                }
+            }
+        }
+
+        let mut values = HashMap::new();
+        for (idx, param) in callee.params(ctx).to_vec().into_iter().enumerate() {
+            values.insert(param, call.operand(ctx, idx + 1));
+        }
+        let cfg = Cfg::new(ctx, callee);
+        let mut blocks = HashMap::new();
+        for old in cfg.rpo() {
+            let new = Block::new(ctx);
+            cont.insert_before(ctx, new).unwrap();
+            blocks.insert(old, new);
+        }
+
+        // The phis are created first, since their incoming values may be
+        // defined later, and the other instructions are cloned in reverse
+        // postorder, after the definitions of their operands.
+        let mut phis = Vec::new();
+        for old in cfg.rpo() {
+            let new = blocks[&old];
+            let old_phis = old
+                .iter(ctx)
+                .take_while(|inst| inst.is_phi(ctx))
+                .collect::<Vec<_>>();
+            for phi in old_phis {
+                let result = phi.result(ctx).unwrap();
+                let new_phi = Inst::phi(ctx, result.ty(ctx));
+                new.push_back(ctx, new_phi).unwrap();
+                values.insert(result, new_phi.result(ctx).unwrap());
+                phis.push((phi, new_phi));
+            }
+        }
+
+        let mut returns = Vec::new();
+        for old in cfg.rpo() {
+            let new = blocks[&old];
+            let insts = old
+                .iter(ctx)
+                .skip_while(|inst| inst.is_phi(ctx))
+                .collect::<Vec<_>>();
+            for inst in insts {
+                if matches!(inst.kind(ctx), InstKind::Ret) {
+                    let value = inst.operand_iter(ctx).next();
+                    returns.push((new, value.map(|v| values.get(&v).copied().unwrap_or(v))));
+                    let br = Inst::br(ctx, cont);
+                    new.push_back(ctx, br).unwrap();
+                    continue;
+                }
+                let clone = inst.clone_with(ctx, &values, &blocks);
+                if let Some(result) = inst.result(ctx) {
+                    values.insert(result, clone.result(ctx).unwrap());
+                }
+                if matches!(inst.kind(ctx), InstKind::Alloca { .. }) {
+                    caller_entry.push_front(ctx, clone).unwrap();
+                } else {
+                    new.push_back(ctx, clone).unwrap();
+                }
+            }
+        }
+
+        for (phi, new_phi) in phis {
+            let incomings = phi.incoming_iter(ctx).collect::<Vec<_>>();
+            for (pred, value) in incomings {
+                let Some(&pred) = blocks.get(&pred) else {
+                    continue;
+                };
+                let value = values.get(&value).copied().unwrap_or(value);
+                new_phi.insert_incoming(ctx, pred, value);
+            }
+        }
+
+        if let Some(result) = call.result(ctx) {
+            let value = match returns.as_slice() {
+                [] => Value::undef(ctx, result.ty(ctx)),
+                [(_, value)] => value.unwrap(),
+                _ => {
+                    let phi = Inst::phi(ctx, result.ty(ctx));
+                    for (pred, value) in returns {
+                        phi.insert_incoming(ctx, pred, value.unwrap());
+                    }
+                    cont.push_front(ctx, phi).unwrap();
+                    phi.result(ctx).unwrap()
+                }
+            };
+            result.replace_all_uses_with(ctx, value);
+        }
+        call.remove(ctx);
+        let callee_entry = blocks[&cfg.entry().unwrap()];
+        let br = Inst::br(ctx, callee_entry);
+        block.push_back(ctx, br).unwrap();
+    }
+}
+
+impl TransformPass for Inline {
+    fn name(&self) -> &'static str { "inline" }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        // Each call inlined brings the calls of the callee, which are to
+        // functions further down the call graph, so this terminates.
+        while let Some((call, callee)) = self.find_site(ctx, func) {
+            Self::inline(ctx, func, call, callee);
+            changed = true;
+        }
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+
+    fn run(&mut self, ctx: &mut Context, am: &mut AnalysisManager) -> bool {
+        let order = CallGraph::new(ctx).bottom_up().collect::<Vec<_>>();
+        let mut changed = false;
+        for func in order {
+            if !func.is_declaration(ctx) {
+                changed |= self.run_on_func(ctx, func, am);
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{IntBinaryOp, Ty};
+
+    #[test]
+    fn test_inline() {
+        // g(a, c): entry: br c, t, e; t: ret a; e: s = alloca i32; ret 0
+        // f(x, c): entry: v = g(x, c); w = v + 1; ret w
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let i1 = Ty::i1(&mut ctx);
+        let g = Func::new(&mut ctx, "g".to_string(), i32);
+        let a = g.add_param(&mut ctx, i32);
+        let c = g.add_param(&mut ctx, i1);
+        let [entry, t, e] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, t, e] {
+            g.push_back(&mut ctx, block).unwrap();
+        }
+        let [zero, one] = [0, 1].map(|v| Value::i32(&mut ctx, v));
+        let br = Inst::cond_br(&mut ctx, c, t, e);
+        entry.push_back(&mut ctx, br).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(a));
+        t.push_back(&mut ctx, ret).unwrap();
+        let alloca = Inst::alloca(&mut ctx, i32);
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        e.push_back(&mut ctx, alloca).unwrap();
+        e.push_back(&mut ctx, ret).unwrap();
+
+        let f = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = f.add_param(&mut ctx, i32);
+        let y = f.add_param(&mut ctx, i1);
+        let f_entry = Block::new(&mut ctx);
+        f.push_back(&mut ctx, f_entry).unwrap();
+        let call = Inst::call(&mut ctx, g, vec![x, y]);
+        let v = call.result(&ctx).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, v, one);
+        let w = add.result(&ctx).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(w));
+        for inst in [call, add, ret] {
+            f_entry.push_back(&mut ctx, inst).unwrap();
+        }
+
+        let mut am = AnalysisManager::default();
+        assert!(Inline::default().run(&mut ctx, &mut am));
+        let blocks = f.iter(&ctx).collect::<Vec<_>>();
+        assert_eq!(blocks.len(), 5);
+        let insts = blocks
+            .iter()
+            .flat_map(|block| block.iter(&ctx))
+            .collect::<Vec<_>>();
+        assert!(insts
+            .iter()
+            .all(|inst| !matches!(inst.kind(&ctx), InstKind::Call)));
+        // the alloca is moved to the entry
+        assert!(matches!(
+            f_entry.head(&ctx).unwrap().kind(&ctx),
+            InstKind::Alloca { .. }
+        ));
+
+        // the returned values are merged in the rest of the block
+        let cont = blocks[4];
+        let phi = cont.head(&ctx).unwrap();
+        assert!(phi.is_phi(&ctx));
+        let incomings = phi
+            .incoming_iter(&ctx)
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>();
+        assert_eq!(incomings.len(), 2);
+        assert!(incomings.contains(&x) && incomings.contains(&zero));
+        assert_eq!(add.container(&ctx), Some(cont));
+        assert_eq!(add.operand(&ctx, 0), phi.result(&ctx).unwrap());
+        assert!(!Inline::default().run(&mut ctx, &mut am));
+    }
+}
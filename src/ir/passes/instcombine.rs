@@ -0,0 +1,405 @@
+//! Instruction combining.
+//!
+//! Peephole rewrites of integer instructions, applied repeatedly until no rule
+//! matches:
+//!
+//! - constant folding;
+//! - algebraic identities, e.g., `x + 0`, `x * 1`, `x * 0`, `x - x`, `x & x`;
+//! - double negation, `x - (0 - y)` to `x + y`, and `(x ^ c) ^ c` to `x` by
+//!   reassociation;
+//! - reassociation of constants, e.g., `(x + c1) + c2` to `x + (c1 + c2)`;
+//! - canonicalization: constants on the right of commutative operations and
+//!   comparisons, `x - c` to `x + (-c)`, and `sle`/`sge` against a constant to
+//!   `slt`/`sgt`;
+//! - strength reduction of `x * 2^k` to `x << k`;
+//! - redundant casts, e.g., casts to the same type, nested extensions, and
+//!   truncations of extended values, e.g., `trunc (zext x)` back to `x`.
+//!
+//! Integer and cast instructions whose results are unused are removed.
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{
+    CastOp,
+    Context,
+    Func,
+    Inst,
+    InstKind,
+    IntBinaryOp,
+    IntCmpCond,
+    Ty,
+    Usable,
+    Value,
+};
+
+/// The combining of integer and cast instructions.
+#[derive(Clone, Default)]
+pub struct InstCombine;
+
+/// The signed range of an integer type, with `i1` as `[-1, 0]`.
+fn signed_range(ctx: &Context, ty: Ty) -> (i64, i64) {
+    let bits = ty.bitwidth(ctx);
+    (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+}
+
+impl InstCombine {
+    /// Insert a binary operation before an instruction.
+    fn binary(ctx: &mut Context, before: Inst, op: IntBinaryOp, lhs: Value, rhs: Value) -> Value {
+        let inst = Inst::ibinary(ctx, op, lhs, rhs);
+        before.insert_before(ctx, inst).unwrap();
+        inst.result(ctx).unwrap()
+    }
+
+    /// Insert a cast before an instruction, unless the value already has the
+    /// type.
+    fn cast(ctx: &mut Context, before: Inst, op: CastOp, value: Value, ty: Ty) -> Value {
+        if value.ty(ctx) == ty {
+            return value;
+        }
+        let inst = Inst::cast(ctx, op, value, ty);
+        before.insert_before(ctx, inst).unwrap();
+        inst.result(ctx).unwrap()
+    }
+
+    /// Combine an instruction.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(value)`: The value to replace the result of the instruction
+    ///   with, new instructions are inserted before it.
+    /// - `None`: No rule applies.
+    fn combine(ctx: &mut Context, inst: Inst) -> Option<Value> {
+        match *inst.kind(ctx) {
+            InstKind::IntBinary { op } => Self::combine_binary(ctx, inst, op),
+            InstKind::Cast { op } => Self::combine_cast(ctx, inst, op),
+            _ => None,
+        }
+    }
+
+    fn combine_binary(ctx: &mut Context, inst: Inst, op: IntBinaryOp) -> Option<Value> {
+        use IntBinaryOp::*;
+
+        let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+        if let Some(folded) = Value::fold_binary(ctx, op, lhs, rhs) {
+            return Some(folded);
+        }
+
+        let ty = lhs.ty(ctx);
+        let c = rhs.as_const_int(ctx).map(i64::from);
+        if lhs.as_const_int(ctx).is_some() && c.is_none() {
+            let swapped = match op {
+                Add | Mul | And | Or | Xor => Some(op),
+                ICmp { cond } => Some(ICmp { cond: cond.swap() }),
+                _ => None,
+            };
+            if let Some(op) = swapped {
+                return Some(Self::binary(ctx, inst, op, rhs, lhs));
+            }
+        }
+
+        let is_bool = ty.bitwidth(ctx) == 1;
+        let (min, max) = signed_range(ctx, ty);
+        let value = match (op, c) {
+            (Add | Sub | Or | Xor | Shl | LShr | AShr, Some(0)) => lhs,
+            (Mul | SDiv | UDiv, Some(1)) => lhs,
+            (Mul | And, Some(0)) => rhs,
+            (SRem | URem, Some(1)) => Value::int(ctx, ty, 0),
+            (Sub | Xor, _) if lhs == rhs => Value::int(ctx, ty, 0),
+            (And | Or, _) if lhs == rhs => lhs,
+            (ICmp { cond }, _) if lhs == rhs => Value::i1(ctx, cond.eval(0, 0)),
+            // `x != false` and `x == true` are `x`, where `c` is `1` for `true`.
+            (ICmp { cond }, Some(c))
+                if is_bool && matches!((cond, c), (IntCmpCond::Ne, 0) | (IntCmpCond::Eq, 1)) =>
+            {
+                lhs
+            }
+            (Sub, Some(c)) => {
+                let neg = Value::int(ctx, ty, -c);
+                Self::binary(ctx, inst, Add, lhs, neg)
+            }
+            (Mul, Some(c)) if rhs.is_power_of_two(ctx) => {
+                let shift = Value::int(ctx, ty, c.trailing_zeros() as i64);
+                Self::binary(ctx, inst, Shl, lhs, shift)
+            }
+            (
+                ICmp {
+                    cond: IntCmpCond::Sle,
+                },
+                Some(c),
+            ) if !is_bool && c != max => {
+                let bound = Value::int(ctx, ty, c + 1);
+                let slt = ICmp {
+                    cond: IntCmpCond::Slt,
+                };
+                Self::binary(ctx, inst, slt, lhs, bound)
+            }
+            (
+                ICmp {
+                    cond: IntCmpCond::Sge,
+                },
+                Some(c),
+            ) if !is_bool && c != min => {
+                let bound = Value::int(ctx, ty, c - 1);
+                let sgt = ICmp {
+                    cond: IntCmpCond::Sgt,
+                };
+                Self::binary(ctx, inst, sgt, lhs, bound)
+            }
+            (Sub, None) => {
+                // `x - (0 - y)` is `x + y`.
+                let neg = rhs.def_inst(ctx).filter(|def| {
+                    matches!(def.kind(ctx), InstKind::IntBinary { op: Sub })
+                        && def.operand(ctx, 0).is_zero(ctx)
+                })?;
+                let y = neg.operand(ctx, 1);
+                Self::binary(ctx, inst, Add, lhs, y)
+            }
+            (Add | Mul | And | Or | Xor, Some(_)) => {
+                // `(x op c1) op c2` is `x op (c1 op c2)`.
+                let inner = lhs.def_inst(ctx).filter(|def| {
+                    matches!(*def.kind(ctx), InstKind::IntBinary { op: inner } if inner == op)
+                })?;
+                let (x, c1) = (inner.operand(ctx, 0), inner.operand(ctx, 1));
+                c1.as_const_int(ctx)?;
+                let folded = Value::fold_binary(ctx, op, c1, rhs)?;
+                Self::binary(ctx, inst, op, x, folded)
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn combine_cast(ctx: &mut Context, inst: Inst, op: CastOp) -> Option<Value> {
+        let value = inst.operand(ctx, 0);
+        let ty = inst.result(ctx).unwrap().ty(ctx);
+        let src_ty = value.ty(ctx);
+        if src_ty == ty {
+            return Some(value);
+        }
+
+        if let Some(c) = value.as_const_int(ctx) {
+            // Take the raw bits, `as_const_int` treats `true` as `1`.
+            let bits = src_ty.bitwidth(ctx);
+            let raw = c as i64 & ((1 << bits) - 1);
+            let folded = match op {
+                CastOp::Zext => raw,
+                CastOp::Sext => (raw << (64 - bits)) >> (64 - bits),
+                CastOp::Trunc => c as i64,
+                CastOp::SiToFp | CastOp::FpToSi => return None,
+            };
+            return Some(Value::int(ctx, ty, folded));
+        }
+
+        let inner = value.def_inst(ctx)?;
+        let InstKind::Cast { op: inner_op } = *inner.kind(ctx) else {
+            return None;
+        };
+        let src = inner.operand(ctx, 0);
+        let (src_bits, bits) = (src.ty(ctx).bitwidth(ctx), ty.bitwidth(ctx));
+        let value = match (inner_op, op) {
+            // A zero-extended value is non-negative, so sign extension keeps
+            // the zero bits.
+            (CastOp::Sext, CastOp::Sext) | (CastOp::Zext, CastOp::Zext | CastOp::Sext) => {
+                Self::cast(ctx, inst, inner_op, src, ty)
+            }
+            (CastOp::Trunc, CastOp::Trunc) => Self::cast(ctx, inst, CastOp::Trunc, src, ty),
+            (CastOp::Sext | CastOp::Zext, CastOp::Trunc) if src_bits <= bits => {
+                Self::cast(ctx, inst, inner_op, src, ty)
+            }
+            (CastOp::Sext | CastOp::Zext, CastOp::Trunc) => {
+                Self::cast(ctx, inst, CastOp::Trunc, src, ty)
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Remove the unused integer and cast instructions in a function.
+    ///
+    /// Users are visited before their operands in straight-line code, so
+    /// chains of dead instructions are mostly removed in one sweep.
+    fn remove_dead(ctx: &mut Context, func: Func) -> bool {
+        let mut changed = false;
+        let insts = func
+            .iter(ctx)
+            .flat_map(|block| block.iter(ctx))
+            .collect::<Vec<_>>();
+        for inst in insts.into_iter().rev() {
+            if !matches!(
+                inst.kind(ctx),
+                InstKind::IntBinary { .. } | InstKind::Cast { .. }
+            ) {
+                continue;
+            }
+            let result = inst.result(ctx).unwrap();
+            if result.users(ctx).into_iter().next().is_none() {
+                inst.remove(ctx);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl TransformPass for InstCombine {
+    fn name(&self) -> &'static str { "instcombine" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        loop {
+            let mut combined = false;
+            // Instructions inserted during the sweep are visited in the next.
+            let insts = func
+                .iter(ctx)
+                .flat_map(|block| block.iter(ctx))
+                .collect::<Vec<_>>();
+            for inst in insts {
+                if let Some(value) = Self::combine(ctx, inst) {
+                    inst.result(ctx).unwrap().replace_all_uses_with(ctx, value);
+                    inst.remove(ctx);
+                    combined = true;
+                }
+            }
+            combined |= Self::remove_dead(ctx, func);
+            if !combined {
+                break;
+            }
+            changed = true;
+        }
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Block;
+
+    /// Build a function with a single block, returning the value computed by
+    /// `body` from the parameter.
+    fn build(
+        ctx: &mut Context,
+        param_ty: Ty,
+        ret_ty: Ty,
+        body: impl FnOnce(&mut Context, Block, Value) -> Value,
+    ) -> (Func, Inst) {
+        let func = Func::new(ctx, "f".to_string(), ret_ty);
+        let x = func.add_param(ctx, param_ty);
+        let block = Block::new(ctx);
+        func.push_back(ctx, block).unwrap();
+        let value = body(ctx, block, x);
+        let ret = Inst::ret(ctx, Some(value));
+        block.push_back(ctx, ret).unwrap();
+        (func, ret)
+    }
+
+    fn push(ctx: &mut Context, block: Block, inst: Inst) -> Value {
+        block.push_back(ctx, inst).unwrap();
+        inst.result(ctx).unwrap()
+    }
+
+    fn combine(ctx: &mut Context, func: Func, ret: Inst) -> Option<Inst> {
+        let mut am = AnalysisManager::default();
+        assert!(InstCombine.run_on_func(ctx, func, &mut am));
+        assert!(!InstCombine.run_on_func(ctx, func, &mut am));
+        let block = func.head(ctx).unwrap();
+        assert_eq!(block.iter(ctx).count(), 2);
+        ret.operand(ctx, 0).def_inst(ctx)
+    }
+
+    #[test]
+    fn test_instcombine() {
+        use IntBinaryOp::*;
+
+        // -(-((x + 0) * 1)) * 8 is x << 3
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let (func, ret) = build(&mut ctx, i32, i32, |ctx, block, x| {
+            let [zero, one, eight] = [0, 1, 8].map(|v| Value::i32(ctx, v));
+            let add = Inst::ibinary(ctx, Add, x, zero);
+            let a = push(ctx, block, add);
+            let mul = Inst::ibinary(ctx, Mul, a, one);
+            let b = push(ctx, block, mul);
+            let neg = Inst::ibinary(ctx, Sub, zero, b);
+            let c = push(ctx, block, neg);
+            let neg = Inst::ibinary(ctx, Sub, zero, c);
+            let d = push(ctx, block, neg);
+            let mul = Inst::ibinary(ctx, Mul, d, eight);
+            push(ctx, block, mul)
+        });
+        let shl = combine(&mut ctx, func, ret).unwrap();
+        assert!(matches!(shl.kind(&ctx), InstKind::IntBinary { op: Shl }));
+        assert_eq!(shl.operand(&ctx, 0), func.params(&ctx)[0]);
+        assert_eq!(shl.operand(&ctx, 1).as_const_int(&ctx), Some(3));
+
+        // 5 <= x is x > 4
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let i1 = Ty::i1(&mut ctx);
+        let (func, ret) = build(&mut ctx, i32, i1, |ctx, block, x| {
+            let five = Value::i32(ctx, 5);
+            let sle = ICmp {
+                cond: IntCmpCond::Sle,
+            };
+            let cmp = Inst::ibinary(ctx, sle, five, x);
+            push(ctx, block, cmp)
+        });
+        let cmp = combine(&mut ctx, func, ret).unwrap();
+        assert!(matches!(
+            cmp.kind(&ctx),
+            InstKind::IntBinary {
+                op: ICmp {
+                    cond: IntCmpCond::Sgt
+                }
+            }
+        ));
+        assert_eq!(cmp.operand(&ctx, 0), func.params(&ctx)[0]);
+        assert_eq!(cmp.operand(&ctx, 1).as_const_int(&ctx), Some(4));
+
+        // trunc (sext (sext x to i8) to i32) to i8 is sext x to i8
+        let mut ctx = Context::default();
+        let [i1, i8, i32] = [Ty::i1, Ty::i8, Ty::i32].map(|ty| ty(&mut ctx));
+        let (func, ret) = build(&mut ctx, i1, i8, |ctx, block, x| {
+            let sext = Inst::cast(ctx, CastOp::Sext, x, i8);
+            let a = push(ctx, block, sext);
+            let sext = Inst::cast(ctx, CastOp::Sext, a, i32);
+            let b = push(ctx, block, sext);
+            let trunc = Inst::cast(ctx, CastOp::Trunc, b, i8);
+            push(ctx, block, trunc)
+        });
+        let sext = combine(&mut ctx, func, ret).unwrap();
+        assert!(matches!(
+            sext.kind(&ctx),
+            InstKind::Cast { op: CastOp::Sext }
+        ));
+        assert_eq!(sext.operand(&ctx, 0), func.params(&ctx)[0]);
+
+        // (x - x) + ((x ^ -1 ^ -1) - 2 - 3 + 5) is x
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let (func, ret) = build(&mut ctx, i32, i32, |ctx, block, x| {
+            let [m1, m2, two, three, five] = [-1, -1, 2, 3, 5].map(|v| Value::i32(ctx, v));
+            let sub = Inst::ibinary(ctx, Sub, x, x);
+            let a = push(ctx, block, sub);
+            let xor = Inst::ibinary(ctx, Xor, x, m1);
+            let b = push(ctx, block, xor);
+            let xor = Inst::ibinary(ctx, Xor, b, m2);
+            let mut c = push(ctx, block, xor);
+            for (op, rhs) in [(Sub, two), (Sub, three), (Add, five)] {
+                let inst = Inst::ibinary(ctx, op, c, rhs);
+                c = push(ctx, block, inst);
+            }
+            let add = Inst::ibinary(ctx, Add, a, c);
+            push(ctx, block, add)
+        });
+        let mut am = AnalysisManager::default();
+        assert!(InstCombine.run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(ret.operand(&ctx, 0), func.params(&ctx)[0]);
+        assert_eq!(func.head(&ctx).unwrap().iter(&ctx).count(), 1);
+    }
+}
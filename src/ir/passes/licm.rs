@@ -0,0 +1,265 @@
+//! Loop-invariant code motion.
+//!
+//! The instructions computing the same value in every iteration are hoisted
+//! to the preheader of the loop, if:
+//!
+//! - all their operands are invariant in the loop, including the results of the
+//!   instructions hoisted before;
+//! - they are pure, or loads of memory not written in the loop;
+//! - they cannot trap, since the preheader runs even if the instruction would
+//!   not. A division or a remainder is only hoisted with a constant divisor
+//!   other than 0 and -1, and a load only from an identified object at a
+//!   constant offset in bounds. Calls are never hoisted, as they may not
+//!   return.
+//!
+//! The inner loops are processed first, so the instructions hoisted to their
+//! preheaders can be hoisted further out of the enclosing loops.
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{
+    alias,
+    decompose_pointer,
+    AliasResult,
+    Cfg,
+    EscapeInfo,
+    Loop,
+    LoopInfo,
+    MemLoc,
+    PointerBase,
+};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, IntBinaryOp};
+
+/// The hoisting of loop-invariant instructions.
+#[derive(Clone, Default)]
+pub struct Licm;
+
+impl Licm {
+    /// Check if an instruction may trap when executed speculatively.
+    fn may_trap(ctx: &Context, inst: Inst) -> bool {
+        match inst.kind(ctx) {
+            InstKind::IntBinary {
+                op: IntBinaryOp::SDiv | IntBinaryOp::UDiv | IntBinaryOp::SRem | IntBinaryOp::URem,
+            } => !matches!(
+                inst.operand(ctx, 1).as_const_int(ctx),
+                Some(divisor) if divisor != 0 && divisor != -1
+            ),
+            InstKind::Load => {
+                let size = inst.result(ctx).unwrap().ty(ctx).bytewidth(ctx) as i64;
+                let object_size = match decompose_pointer(ctx, inst.operand(ctx, 0)) {
+                    (PointerBase::Alloca(alloca), Some(offset)) => match *alloca.kind(ctx) {
+                        InstKind::Alloca { ty } => Some((ty.bytewidth(ctx), offset)),
+                        _ => None,
+                    },
+                    (PointerBase::Global(name), Some(offset)) => ctx
+                        .global_by_name(name)
+                        .map(|global| (global.ty(ctx).bytewidth(ctx), offset)),
+                    _ => None,
+                };
+                !object_size.is_some_and(|(object_size, offset)| {
+                    offset >= 0 && offset + size <= object_size as i64
+                })
+            }
+            InstKind::Call => true,
+            _ => false,
+        }
+    }
+
+    /// Check if the memory read by a load may be written in the loop.
+    fn is_clobbered(
+        ctx: &Context,
+        loop_info: &LoopInfo,
+        escape: &EscapeInfo,
+        lp: Loop,
+        load: Inst,
+    ) -> bool {
+        let loc = MemLoc::of_inst(ctx, load).unwrap();
+        let private = match decompose_pointer(ctx, loc.ptr).0 {
+            PointerBase::Alloca(alloca) => !escape.escapes(alloca),
+            _ => false,
+        };
+        loop_info
+            .blocks(lp)
+            .iter()
+            .flat_map(|block| block.iter(ctx))
+            .filter(|inst| inst.may_write_memory(ctx))
+            .any(|inst| match MemLoc::of_inst(ctx, inst) {
+                Some(store) => alias(ctx, loc, store) != AliasResult::NoAlias,
+                // Calls cannot access the allocas not escaping.
+                None => !private,
+            })
+    }
+
+    /// Check if an instruction can be hoisted out of the loop.
+    fn can_hoist(
+        ctx: &Context,
+        loop_info: &LoopInfo,
+        escape: &EscapeInfo,
+        lp: Loop,
+        inst: Inst,
+    ) -> bool {
+        let is_load = matches!(inst.kind(ctx), InstKind::Load);
+        if !inst.is_pure(ctx) && !is_load {
+            return false;
+        }
+        if inst.is_phi(ctx)
+            || !inst
+                .operand_iter(ctx)
+                .all(|operand| loop_info.is_invariant(ctx, lp, operand))
+        {
+            return false;
+        }
+        if is_load && Self::is_clobbered(ctx, loop_info, escape, lp, inst) {
+            return false;
+        }
+        !Self::may_trap(ctx, inst)
+    }
+
+    /// Hoist the invariant instructions of a loop to the preheader.
+    fn hoist(
+        ctx: &mut Context,
+        loop_info: &LoopInfo,
+        escape: &EscapeInfo,
+        lp: Loop,
+        preheader: Block,
+    ) -> bool {
+        let mut changed = false;
+        // An instruction may become invariant once its operands are hoisted,
+        // if it comes first in the layout.
+        loop {
+            let insts = loop_info
+                .blocks(lp)
+                .iter()
+                .flat_map(|block| block.iter(ctx))
+                .collect::<Vec<_>>();
+            let mut changed_once = false;
+            for inst in insts {
+                if !Self::can_hoist(ctx, loop_info, escape, lp, inst) {
+                    continue;
+                }
+                inst.unlink(ctx);
+                let term = preheader.tail(ctx).unwrap();
+                term.insert_before(ctx, inst).unwrap();
+                changed_once = true;
+            }
+            if !changed_once {
+                return changed;
+            }
+            changed = true;
+        }
+    }
+}
+
+impl TransformPass for Licm {
+    fn name(&self) -> &'static str { "licm" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let loop_info = am.get::<LoopInfo>(ctx, func);
+        let escape = am.get::<EscapeInfo>(ctx, func);
+
+        // Moving instructions does not change the loops.
+        let mut changed = false;
+        let loops = loop_info.loops().collect::<Vec<_>>();
+        for lp in loops.into_iter().rev() {
+            let Some(preheader) = loop_info.preheader(&cfg, lp) else {
+                continue;
+            };
+            changed |= Self::hoist(ctx, &loop_info, &escape, lp, preheader);
+        }
+
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{IntCmpCond, Ty, Value};
+
+    #[test]
+    fn test_licm() {
+        // entry: g = alloca i32; store n, g; br header
+        // header: i = phi [entry: 0, header: i1]
+        //         t = n * 3; u = t + 1   ; hoisted
+        //         q = n / 4              ; hoisted
+        //         d = n / k              ; may divide by zero
+        //         x = load g             ; hoisted
+        //         store i, p             ; `p` may alias nothing local
+        //         i1 = i + 1; br i1 < 10, header, exit
+        // exit: ret u + q + d + x
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let ptr = Ty::ptr(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let n = func.add_param(&mut ctx, i32);
+        let k = func.add_param(&mut ctx, i32);
+        let p = func.add_param(&mut ctx, ptr);
+        let [entry, header, exit] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, header, exit] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [zero, one, three, four, ten] = [0, 1, 3, 4, 10].map(|v| Value::i32(&mut ctx, v));
+
+        let alloca = Inst::alloca(&mut ctx, i32);
+        let g = alloca.result(&ctx).unwrap();
+        let store = Inst::store(&mut ctx, n, g);
+        let entry_br = Inst::br(&mut ctx, header);
+        for inst in [alloca, store, entry_br] {
+            entry.push_back(&mut ctx, inst).unwrap();
+        }
+
+        let phi = Inst::phi(&mut ctx, i32);
+        let i = phi.result(&ctx).unwrap();
+        let mul = Inst::ibinary(&mut ctx, IntBinaryOp::Mul, n, three);
+        let t = mul.result(&ctx).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, t, one);
+        let div_const = Inst::ibinary(&mut ctx, IntBinaryOp::SDiv, n, four);
+        let div_var = Inst::ibinary(&mut ctx, IntBinaryOp::SDiv, n, k);
+        let load = Inst::load(&mut ctx, g, i32);
+        let store_p = Inst::store(&mut ctx, i, p);
+        let inc = Inst::ibinary(&mut ctx, IntBinaryOp::Add, i, one);
+        let i1 = inc.result(&ctx).unwrap();
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let cmp = Inst::ibinary(&mut ctx, slt, i1, ten);
+        let cond = cmp.result(&ctx).unwrap();
+        let br = Inst::cond_br(&mut ctx, cond, header, exit);
+        let body = [
+            phi, mul, add, div_const, div_var, load, store_p, inc, cmp, br,
+        ];
+        for inst in body {
+            header.push_back(&mut ctx, inst).unwrap();
+        }
+        phi.insert_incoming(&mut ctx, entry, zero);
+        phi.insert_incoming(&mut ctx, header, i1);
+
+        let mut sum = add.result(&ctx).unwrap();
+        for inst in [div_const, div_var, load] {
+            let value = inst.result(&ctx).unwrap();
+            let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, sum, value);
+            exit.push_back(&mut ctx, add).unwrap();
+            sum = add.result(&ctx).unwrap();
+        }
+        let ret = Inst::ret(&mut ctx, Some(sum));
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(Licm.run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(
+            entry.iter(&ctx).collect::<Vec<_>>(),
+            [alloca, store, mul, add, div_const, load, entry_br]
+        );
+        assert_eq!(
+            header.iter(&ctx).collect::<Vec<_>>(),
+            [phi, div_var, store_p, inc, cmp, br]
+        );
+        assert!(!Licm.run_on_func(&mut ctx, func, &mut am));
+    }
+}
@@ -0,0 +1,191 @@
+//! Store-to-load forwarding and redundant load elimination.
+//!
+//! A load is replaced by a value already known to be in the loaded memory:
+//!
+//! - The value of a store or the result of a load of exactly the same memory,
+//!   found by scanning backwards through the block and the chain of single
+//!   predecessors, with no possible write to the memory in between.
+//! - The value of a store that is the single definition of the loaded memory on
+//!   all the paths, given by the memory dependence analysis. The store
+//!   dominates the load in that case, so this also works across merge points.
+//!
+//! The types of the accesses must match, no conversion is done.
+
+use std::collections::HashSet;
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{alias, AliasResult, Cfg, MemDep, MemLoc};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Context, Func, Inst, InstKind, Value};
+
+/// The forwarding of stored and loaded values to later loads.
+#[derive(Clone, Default)]
+pub struct LoadElim;
+
+impl LoadElim {
+    /// Find an earlier access of the same memory as a load, with no write to
+    /// the memory in between.
+    fn find_available(ctx: &Context, cfg: &Cfg, load: Inst) -> Option<Value> {
+        let loc = MemLoc::of_inst(ctx, load).unwrap();
+        let ty = load.result(ctx).unwrap().ty(ctx);
+
+        let mut block = load.container(ctx).unwrap();
+        let mut visited = HashSet::from([block]);
+        let mut inst = load.prev(ctx);
+        loop {
+            while let Some(current) = inst {
+                inst = current.prev(ctx);
+                let (value, is_store) = match current.kind(ctx) {
+                    InstKind::Load => (current.result(ctx).unwrap(), false),
+                    InstKind::Store => (current.operand(ctx, 0), true),
+                    InstKind::Call if current.may_write_memory(ctx) => return None,
+                    _ => continue,
+                };
+                match alias(ctx, MemLoc::of_inst(ctx, current).unwrap(), loc) {
+                    AliasResult::MustAlias if value.ty(ctx) == ty => return Some(value),
+                    AliasResult::MustAlias | AliasResult::MayAlias if is_store => return None,
+                    _ => {}
+                }
+            }
+
+            let &[pred] = cfg.preds(block) else {
+                return None;
+            };
+            if !visited.insert(pred) {
+                return None;
+            }
+            block = pred;
+            inst = pred.tail(ctx);
+        }
+    }
+
+    /// Find the value of the single store defining the memory of a load.
+    fn find_forwarded(ctx: &Context, memdep: &MemDep, load: Inst) -> Option<Value> {
+        let store = memdep.load_deps(load).single_def()?;
+        if !matches!(store.kind(ctx), InstKind::Store) {
+            return None;
+        }
+        let (store_loc, load_loc) = (MemLoc::of_inst(ctx, store)?, MemLoc::of_inst(ctx, load)?);
+        let value = store.operand(ctx, 0);
+        let matches = alias(ctx, store_loc, load_loc) == AliasResult::MustAlias
+            && value.ty(ctx) == load.result(ctx).unwrap().ty(ctx);
+        matches.then_some(value)
+    }
+}
+
+impl TransformPass for LoadElim {
+    fn name(&self) -> &'static str { "load-elim" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let cfg = am.get::<Cfg>(ctx, func);
+        // Only loads are removed, which are never definitions of other loads,
+        // so the dependences stay valid during the pass.
+        let memdep = am.get::<MemDep>(ctx, func);
+        let loads = cfg
+            .rpo()
+            .flat_map(|block| block.iter(ctx))
+            .filter(|inst| matches!(inst.kind(ctx), InstKind::Load))
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+        for load in loads {
+            let Some(value) = Self::find_available(ctx, &cfg, load)
+                .or_else(|| Self::find_forwarded(ctx, &memdep, load))
+            else {
+                continue;
+            };
+            load.result(ctx).unwrap().replace_all_uses_with(ctx, value);
+            load.remove(ctx);
+            changed = true;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Ty};
+
+    #[test]
+    fn test_load_elim() {
+        // entry: a = alloca; y1 = load p; y2 = load p; call f(); y3 = load p
+        //        br next
+        // next:  y4 = load p; store 1, a; x1 = load a; cond_br c, then, join
+        // then:  store 2, p; br join
+        // join:  x2 = load a; y5 = load p; ret
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let ptr = Ty::ptr(&mut ctx);
+        let void = Ty::void(&mut ctx);
+        let callee = Func::new(&mut ctx, "f".to_string(), void);
+        let func = Func::new(&mut ctx, "g".to_string(), void);
+        let p = func.add_param(&mut ctx, ptr);
+        let c = func.add_param(&mut ctx, i32);
+        let [entry, next, then, join] = [(); 4].map(|_| Block::new(&mut ctx));
+        for block in [entry, next, then, join] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let push = |ctx: &mut Context, block: Block, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst
+        };
+        let load = |ctx: &mut Context, block: Block, ptr: Value| {
+            let load = Inst::load(ctx, ptr, i32);
+            push(ctx, block, load)
+        };
+        let [one, two] = [1, 2].map(|v| Value::i32(&mut ctx, v));
+
+        let alloca = Inst::alloca(&mut ctx, i32);
+        let a = push(&mut ctx, entry, alloca).result(&ctx).unwrap();
+        let y1 = load(&mut ctx, entry, p);
+        let y2 = load(&mut ctx, entry, p);
+        let call = Inst::call(&mut ctx, callee, vec![]);
+        push(&mut ctx, entry, call);
+        let y3 = load(&mut ctx, entry, p);
+        let br = Inst::br(&mut ctx, next);
+        push(&mut ctx, entry, br);
+
+        let y4 = load(&mut ctx, next, p);
+        let store = Inst::store(&mut ctx, one, a);
+        push(&mut ctx, next, store);
+        let x1 = load(&mut ctx, next, a);
+        let br = Inst::cond_br(&mut ctx, c, then, join);
+        push(&mut ctx, next, br);
+
+        let store = Inst::store(&mut ctx, two, p);
+        push(&mut ctx, then, store);
+        let br = Inst::br(&mut ctx, join);
+        push(&mut ctx, then, br);
+
+        let x2 = load(&mut ctx, join, a);
+        let y5 = load(&mut ctx, join, p);
+        let loads = [x1, y1, y2, y3, y4, x2, y5].map(|load| load.result(&ctx).unwrap());
+        let uses = loads.map(|value| {
+            let use_inst = Inst::ibinary(&mut ctx, crate::ir::IntBinaryOp::Add, value, one);
+            push(&mut ctx, join, use_inst)
+        });
+        let ret = Inst::ret(&mut ctx, None);
+        push(&mut ctx, join, ret);
+
+        let mut am = AnalysisManager::default();
+        assert!(LoadElim.run_on_func(&mut ctx, func, &mut am));
+
+        let used = uses.map(|inst| inst.operand(&ctx, 0));
+        // x1 and x2 are forwarded from the store, y2 repeats y1, y4 repeats y3
+        // across the edge, y3 is clobbered by the call, y5 by the store in
+        // `then`
+        assert_eq!(used[0].as_const_int(&ctx), Some(1));
+        assert_eq!(used[1], loads[1]);
+        assert_eq!(used[2], loads[1]);
+        assert_eq!(used[3], loads[3]);
+        assert_eq!(used[4], loads[3]);
+        assert_eq!(used[5].as_const_int(&ctx), Some(1));
+        assert_eq!(used[6], loads[6]);
+
+        am.invalidate(func);
+        assert!(!LoadElim.run_on_func(&mut ctx, func, &mut am));
+    }
+}
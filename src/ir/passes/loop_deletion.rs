@@ -0,0 +1,233 @@
+//! Dead loop deletion.
+//!
+//! A loop is deleted if it has no observable effect, i.e.:
+//!
+//! - no instruction in the loop may have side effects or return;
+//! - no value defined in the loop is used outside of it;
+//! - it has a preheader and a single exit block, so the preheader can branch
+//!   to the exit directly;
+//! - it terminates, proven by a known trip count.
+//!
+//! Whether a loop that may not terminate can be deleted is a policy choice:
+//! an infinite loop without side effects hangs the program, which is
+//! observable. [`LoopDeletion::delete_infinite`] allows deleting such loops,
+//! assuming every loop eventually terminates.
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{Cfg, InductionVars, Loop, LoopInfo};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, Usable, Value};
+
+/// The deletion of loops without observable effects.
+#[derive(Clone, Default)]
+pub struct LoopDeletion {
+    /// Delete loops even if they are not proven to terminate.
+    pub delete_infinite: bool,
+}
+
+impl LoopDeletion {
+    /// Check if a loop can be deleted.
+    ///
+    /// # Returns
+    ///
+    /// - `Some((preheader, exit))`: The loop can be deleted.
+    /// - `None`: The loop must be kept.
+    fn check(
+        &self,
+        ctx: &Context,
+        cfg: &Cfg,
+        loop_info: &LoopInfo,
+        ivs: &InductionVars,
+        lp: Loop,
+    ) -> Option<(Block, Block)> {
+        let preheader = loop_info.preheader(cfg, lp)?;
+        let &[exit] = loop_info.exits(lp) else {
+            return None;
+        };
+        if !exit.params(ctx).is_empty() {
+            return None;
+        }
+
+        for &block in loop_info.blocks(lp) {
+            for inst in block.iter(ctx) {
+                let has_effects = match inst.kind(ctx) {
+                    InstKind::Br | InstKind::CondBr | InstKind::Switch { .. } => false,
+                    _ => inst.may_have_side_effects(ctx),
+                };
+                if has_effects {
+                    return None;
+                }
+                let Some(result) = inst.result(ctx) else {
+                    continue;
+                };
+                let used_outside = result.users(ctx).into_iter().any(|user| {
+                    let container = user.inst().container(ctx);
+                    container.is_none_or(|block| !loop_info.contains(lp, block))
+                });
+                if used_outside {
+                    return None;
+                }
+            }
+        }
+
+        // The phis in the exit must receive the same invariant value from all
+        // the exiting blocks.
+        for phi in exit.iter(ctx).take_while(|inst| inst.is_phi(ctx)) {
+            let mut incomings = phi
+                .incoming_iter(ctx)
+                .filter(|(pred, _)| loop_info.contains(lp, *pred))
+                .map(|(_, value)| value);
+            let first = incomings.next()?;
+            if !incomings.all(|value| value == first) {
+                return None;
+            }
+        }
+
+        let terminates = loop_info.trip_count(ctx, cfg, ivs, lp).is_some();
+        (terminates || self.delete_infinite).then_some((preheader, exit))
+    }
+
+    /// Delete a loop by branching from the preheader to the exit directly.
+    fn delete(ctx: &mut Context, loop_info: &LoopInfo, lp: Loop, preheader: Block, exit: Block) {
+        let blocks = loop_info.blocks(lp).to_vec();
+
+        let phis = exit
+            .iter(ctx)
+            .take_while(|inst| inst.is_phi(ctx))
+            .collect::<Vec<_>>();
+        for phi in phis {
+            let mut value = None;
+            for &block in blocks.iter() {
+                if phi.incoming_iter(ctx).any(|(pred, _)| pred == block) {
+                    value = Some(phi.incoming(ctx, block));
+                    phi.remove_incoming(ctx, block);
+                }
+            }
+            phi.insert_incoming(ctx, preheader, value.unwrap());
+        }
+
+        let term = preheader.tail(ctx).unwrap();
+        term.remove(ctx);
+        let br = Inst::br(ctx, exit);
+        preheader.push_back(ctx, br).unwrap();
+
+        // The values are only used in the loop, so they can be replaced
+        // before the instructions are removed in any order.
+        let insts = blocks
+            .iter()
+            .flat_map(|block| block.iter(ctx))
+            .collect::<Vec<_>>();
+        for &inst in insts.iter() {
+            if let Some(result) = inst.result(ctx) {
+                let undef = Value::undef(ctx, result.ty(ctx));
+                result.replace_all_uses_with(ctx, undef);
+            }
+        }
+        for inst in insts {
+            inst.remove(ctx);
+        }
+        for block in blocks {
+            block.remove(ctx);
+        }
+    }
+}
+
+impl TransformPass for LoopDeletion {
+    fn name(&self) -> &'static str { "loop-deletion" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        loop {
+            let cfg = am.get::<Cfg>(ctx, func);
+            let loop_info = am.get::<LoopInfo>(ctx, func);
+            let ivs = am.get::<InductionVars>(ctx, func);
+            let Some((lp, preheader, exit)) = loop_info.loops().find_map(|lp| {
+                self.check(ctx, &cfg, &loop_info, &ivs, lp)
+                    .map(|(preheader, exit)| (lp, preheader, exit))
+            }) else {
+                return changed;
+            };
+            Self::delete(ctx, &loop_info, lp, preheader, exit);
+            am.invalidate(func);
+            changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{IntBinaryOp, IntCmpCond, Ty};
+
+    /// Build `for (i = 0; i < 10; i++) s = s + i; return n;` if `bounded`, or
+    /// the same loop tested by `i != n` otherwise.
+    fn build(ctx: &mut Context, bounded: bool) -> (Func, Block, Block) {
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, "f".to_string(), i32);
+        let n = func.add_param(ctx, i32);
+        let [entry, header, exit] = [(); 3].map(|_| Block::new(ctx));
+        for block in [entry, header, exit] {
+            func.push_back(ctx, block).unwrap();
+        }
+        let [zero, one, ten] = [0, 1, 10].map(|v| Value::i32(ctx, v));
+
+        let br = Inst::br(ctx, header);
+        entry.push_back(ctx, br).unwrap();
+
+        let i = Inst::phi(ctx, i32);
+        let s = Inst::phi(ctx, i32);
+        header.push_back(ctx, i).unwrap();
+        header.push_back(ctx, s).unwrap();
+        let (iv, sv) = (i.result(ctx).unwrap(), s.result(ctx).unwrap());
+        let add = Inst::ibinary(ctx, IntBinaryOp::Add, sv, iv);
+        let inc = Inst::ibinary(ctx, IntBinaryOp::Add, iv, one);
+        let (s1, i1) = (add.result(ctx).unwrap(), inc.result(ctx).unwrap());
+        let cmp = if bounded {
+            let slt = IntBinaryOp::ICmp {
+                cond: IntCmpCond::Slt,
+            };
+            Inst::ibinary(ctx, slt, i1, ten)
+        } else {
+            let ne = IntBinaryOp::ICmp {
+                cond: IntCmpCond::Ne,
+            };
+            Inst::ibinary(ctx, ne, i1, n)
+        };
+        let br = Inst::cond_br(ctx, cmp.result(ctx).unwrap(), header, exit);
+        for inst in [add, inc, cmp, br] {
+            header.push_back(ctx, inst).unwrap();
+        }
+        i.insert_incoming(ctx, entry, zero);
+        i.insert_incoming(ctx, header, i1);
+        s.insert_incoming(ctx, entry, zero);
+        s.insert_incoming(ctx, header, s1);
+
+        let ret = Inst::ret(ctx, Some(n));
+        exit.push_back(ctx, ret).unwrap();
+        (func, entry, exit)
+    }
+
+    #[test]
+    fn test_loop_deletion() {
+        let mut ctx = Context::default();
+        let (func, entry, exit) = build(&mut ctx, true);
+        let mut am = AnalysisManager::default();
+        assert!(LoopDeletion::default().run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(func.iter(&ctx).collect::<Vec<_>>(), [entry, exit]);
+        assert_eq!(entry.tail(&ctx).unwrap().successor(&ctx, 0), exit);
+
+        // `i != n` never becomes false if `n` is not positive, unless `i`
+        // wraps around, so the loop is not proven to terminate
+        let mut ctx = Context::default();
+        let (func, _, _) = build(&mut ctx, false);
+        let mut am = AnalysisManager::default();
+        assert!(!LoopDeletion::default().run_on_func(&mut ctx, func, &mut am));
+        let mut pass = LoopDeletion {
+            delete_infinite: true,
+        };
+        assert!(pass.run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(func.iter(&ctx).count(), 2);
+    }
+}
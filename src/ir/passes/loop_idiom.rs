@@ -0,0 +1,448 @@
+//! Loop idiom recognition.
+//!
+//! The idioms are recognized in loops tested in the header, with a preheader,
+//! a single latch and an exact trip count `n`:
+//!
+//! - A store of a constant whose bytes are all equal, e.g., zero, to
+//!   consecutive elements of an array, is replaced by a call to `memset` in the
+//!   preheader, filling the `n` elements at once:
+//!
+//!   ```text
+//!   p = getelementptr T, base, ..., i     ; i stepped by 1
+//!   store c, p
+//!   ```
+//!
+//! - The value after the loop of a reduction `s = phi [s0, preheader], [next,
+//!   latch]` with `next = add s, x` is computed in closed form in the
+//!   preheader, `s0 + n * x` if `x` is loop invariant, or `s0 + n * x0 + d * n
+//!   * (n - 1) / 2` if `x` is an induction variable starting at `x0` and
+//!   stepped by `d`.
+//!
+//! The store must be executed exactly once per iteration, i.e., in a block of
+//! the loop other than the header and dominating the latch, and it must be the
+//! only memory access in the loop. The loops themselves are left in place, and
+//! can be removed by dead loop deletion when nothing in them is used anymore.
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{Cfg, DomTree, InductionVars, Loop, LoopInfo};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, IntBinaryOp, Ty, Usable, Value};
+
+/// The replacement of array filling loops and reductions.
+#[derive(Clone, Default)]
+pub struct LoopIdiom;
+
+/// The increment of a reduction in each iteration.
+enum Increment {
+    /// A loop-invariant value.
+    Invariant(Value),
+    /// An induction variable, `start + k * step` in the `k`-th iteration.
+    Affine { start: i32, step: i32 },
+}
+
+/// An idiom found in a loop.
+enum Idiom {
+    /// A store filling `count` elements, starting at the index `start` of the
+    /// last operand of `gep`.
+    Memset {
+        store: Inst,
+        gep: Inst,
+        start: i32,
+        count: u64,
+    },
+    /// A reduction phi in the header, used after the loop.
+    Reduction {
+        phi: Inst,
+        lp: Loop,
+        init: Value,
+        inc: Increment,
+        count: u64,
+    },
+}
+
+impl LoopIdiom {
+    /// Find the first idiom to rewrite, with the preheader of its loop.
+    fn find_idiom(
+        ctx: &Context,
+        cfg: &Cfg,
+        domtree: &DomTree,
+        loop_info: &LoopInfo,
+        ivs: &InductionVars,
+    ) -> Option<(Block, Idiom)> {
+        for lp in loop_info.loops() {
+            let header = loop_info.header(lp);
+            let (Some(preheader), Some(latch)) =
+                (loop_info.preheader(cfg, lp), loop_info.single_latch(lp))
+            else {
+                continue;
+            };
+            if loop_info.exiting_blocks(cfg, lp) != [header] {
+                continue;
+            }
+            let Some(count) = loop_info
+                .trip_count(ctx, cfg, ivs, lp)
+                .and_then(|count| count.exact())
+            else {
+                continue;
+            };
+
+            // The start and the step of an induction variable of the loop.
+            let iv_of = |value: Value| {
+                let iv = ivs.affine(value)?;
+                let base = ivs.basic_iv(iv.base)?;
+                if !ivs.basic_ivs(lp).contains(base) {
+                    return None;
+                }
+                let start = base.start.as_const_int(ctx)?;
+                let start = iv.scale.checked_mul(start)?.checked_add(iv.offset)?;
+                Some((start, iv.step(base)?))
+            };
+            let once_per_iter = |block: Block| {
+                loop_info.loop_of(block) == Some(lp) && domtree.dominates(block, latch)
+            };
+
+            for phi in header.iter(ctx).take_while(|inst| inst.is_phi(ctx)) {
+                let value = phi.result(ctx).unwrap();
+                let used_after = value.users(ctx).into_iter().any(|user| {
+                    let container = user.inst().container(ctx);
+                    container.is_some_and(|block| !loop_info.contains(lp, block))
+                });
+                let ty = value.ty(ctx);
+                if !used_after || !ty.is_integer(ctx) || ty.bitwidth(ctx) != 32 {
+                    continue;
+                }
+                let Some(add) = phi.incoming(ctx, latch).def_inst(ctx) else {
+                    continue;
+                };
+                if !matches!(
+                    add.kind(ctx),
+                    InstKind::IntBinary {
+                        op: IntBinaryOp::Add
+                    }
+                ) || !once_per_iter(add.container(ctx).unwrap())
+                {
+                    continue;
+                }
+                let x = match (add.operand(ctx, 0), add.operand(ctx, 1)) {
+                    (lhs, x) | (x, lhs) if lhs == value => x,
+                    _ => continue,
+                };
+                let inc = if loop_info.is_invariant(ctx, lp, x) {
+                    Increment::Invariant(x)
+                } else if let Some((start, step)) = iv_of(x) {
+                    Increment::Affine { start, step }
+                } else {
+                    continue;
+                };
+                let init = phi.incoming(ctx, preheader);
+                let idiom = Idiom::Reduction {
+                    phi,
+                    lp,
+                    init,
+                    inc,
+                    count,
+                };
+                return Some((preheader, idiom));
+            }
+
+            let mut accesses = loop_info
+                .blocks(lp)
+                .iter()
+                .flat_map(|block| block.iter(ctx))
+                .filter(|inst| inst.may_read_memory(ctx) || inst.may_write_memory(ctx));
+            let (Some(store), None) = (accesses.next(), accesses.next()) else {
+                continue;
+            };
+            let block = store.container(ctx).unwrap();
+            if !matches!(store.kind(ctx), InstKind::Store)
+                || block == header
+                || !once_per_iter(block)
+            {
+                continue;
+            }
+            if let Some(idiom) = Self::check_memset(ctx, loop_info, lp, store, count, iv_of) {
+                return Some((preheader, idiom));
+            }
+        }
+        None
+    }
+
+    /// Check if a store in a loop fills an array with a constant.
+    fn check_memset(
+        ctx: &Context,
+        loop_info: &LoopInfo,
+        lp: Loop,
+        store: Inst,
+        count: u64,
+        iv_of: impl Fn(Value) -> Option<(i32, i32)>,
+    ) -> Option<Idiom> {
+        let value = store.operand(ctx, 0);
+        let ty = value.ty(ctx);
+        let c = value.as_const_int(ctx)?;
+        if ty.bitwidth(ctx) != 32 || c.to_le_bytes().iter().any(|&b| b != c as u8) {
+            return None;
+        }
+
+        let gep = store.operand(ctx, 1).def_inst(ctx)?;
+        if !matches!(gep.kind(ctx), InstKind::GetElementPtr { .. }) {
+            return None;
+        }
+        let operands = gep.operand_iter(ctx).collect::<Vec<_>>();
+        let (&index, rest) = operands.split_last()?;
+        if rest.is_empty() || !rest.iter().all(|&op| loop_info.is_invariant(ctx, lp, op)) {
+            return None;
+        }
+        let (start, step) = iv_of(index)?;
+        let stride = Self::stride_ty(ctx, gep, rest.len()).bytewidth(ctx);
+        let size = count.checked_mul(stride as u64)?;
+        if step != 1 || stride != ty.bytewidth(ctx) || count == 0 || size > i32::MAX as u64 {
+            return None;
+        }
+        Some(Idiom::Memset {
+            store,
+            gep,
+            start,
+            count,
+        })
+    }
+
+    /// Get the type stepped over by the index operand of a `getelementptr`.
+    fn stride_ty(ctx: &Context, gep: Inst, idx: usize) -> Ty {
+        let InstKind::GetElementPtr { bound_ty } = *gep.kind(ctx) else {
+            unreachable!()
+        };
+        let mut ty = bound_ty;
+        for _ in 1..idx {
+            ty = ty.as_array(ctx).expect("index into a non-array type").0;
+        }
+        ty
+    }
+
+    /// Get the declaration of `memset(ptr, byte, size)`, adding it if missing.
+    fn memset_decl(ctx: &mut Context) -> Func {
+        if let Some(func) = ctx.func_by_name("memset") {
+            return func;
+        }
+        let void = Ty::void(ctx);
+        let ptr = Ty::ptr(ctx);
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, "memset".to_string(), void);
+        for ty in [ptr, i32, i32] {
+            func.add_param(ctx, ty);
+        }
+        func
+    }
+
+    /// Rewrite an idiom, inserting the new code at the end of the preheader.
+    fn rewrite(ctx: &mut Context, loop_info: &LoopInfo, preheader: Block, idiom: Idiom) {
+        let term = preheader.tail(ctx).unwrap();
+        match idiom {
+            Idiom::Memset {
+                store,
+                gep,
+                start,
+                count,
+            } => {
+                let InstKind::GetElementPtr { bound_ty } = *gep.kind(ctx) else {
+                    unreachable!()
+                };
+                let mut operands = gep.operand_iter(ctx).collect::<Vec<_>>();
+                *operands.last_mut().unwrap() = Value::i32(ctx, start);
+                let ptr = Inst::getelementptr(ctx, bound_ty, operands[0], operands[1..].to_vec());
+                term.insert_before(ctx, ptr).unwrap();
+
+                let value = store.operand(ctx, 0);
+                let byte = value.as_const_int(ctx).unwrap() & 0xff;
+                let size = (count * value.ty(ctx).bytewidth(ctx) as u64) as i32;
+                let args = vec![
+                    ptr.result(ctx).unwrap(),
+                    Value::i32(ctx, byte),
+                    Value::i32(ctx, size),
+                ];
+                let memset = Self::memset_decl(ctx);
+                let call = Inst::call(ctx, memset, args);
+                term.insert_before(ctx, call).unwrap();
+
+                store.remove(ctx);
+                if gep
+                    .result(ctx)
+                    .unwrap()
+                    .users(ctx)
+                    .into_iter()
+                    .next()
+                    .is_none()
+                {
+                    gep.remove(ctx);
+                }
+            }
+            Idiom::Reduction {
+                phi,
+                lp,
+                init,
+                inc,
+                count,
+            } => {
+                let n = count as i128;
+                let total = match inc {
+                    Increment::Invariant(x) => {
+                        let n = Value::i32(ctx, n as i32);
+                        Value::binary_before(ctx, term, IntBinaryOp::Mul, x, n)
+                    }
+                    Increment::Affine { start, step } => {
+                        // Wrapped to 32 bits, as the additions in the loop.
+                        let total = n * start as i128 + step as i128 * n * (n - 1) / 2;
+                        Value::i32(ctx, total as i32)
+                    }
+                };
+                let result = Value::binary_before(ctx, term, IntBinaryOp::Add, init, total);
+
+                let value = phi.result(ctx).unwrap();
+                for user in value.users(ctx).into_iter().collect::<Vec<_>>() {
+                    let inst = user.inst();
+                    if inst
+                        .container(ctx)
+                        .is_some_and(|block| !loop_info.contains(lp, block))
+                    {
+                        inst.set_operand(ctx, user.idx(), result);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl TransformPass for LoopIdiom {
+    fn name(&self) -> &'static str { "loop-idiom" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        loop {
+            let cfg = am.get::<Cfg>(ctx, func);
+            let domtree = am.get::<DomTree>(ctx, func);
+            let loop_info = am.get::<LoopInfo>(ctx, func);
+            let ivs = am.get::<InductionVars>(ctx, func);
+            let Some((preheader, idiom)) = Self::find_idiom(ctx, &cfg, &domtree, &loop_info, &ivs)
+            else {
+                return changed;
+            };
+            Self::rewrite(ctx, &loop_info, preheader, idiom);
+            am.invalidate(func);
+            changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::IntCmpCond;
+
+    #[test]
+    fn test_loop_idiom() {
+        // entry:  a = alloca [10 x i32]; br header
+        // header: i = phi [0, entry], [next, body]
+        //         s = phi [5, entry], [s2, body]
+        //         t = phi [x, entry], [t2, body]
+        //         c = icmp slt i, 10; br c, body, exit
+        // body:   p = getelementptr [10 x i32], a, 0, i; store -1, p
+        //         s2 = add s, i; t2 = add t, x; next = add i, 1; br header
+        // exit:   r = add s, t; ret r
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let arr = Ty::array(&mut ctx, i32, 10);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = func.add_param(&mut ctx, i32);
+        let [entry, header, body, exit] = [(); 4].map(|_| Block::new(&mut ctx));
+        for block in [entry, header, body, exit] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let push = |ctx: &mut Context, block: Block, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst
+        };
+        let [zero, one, five, ten, minus_one] = [0, 1, 5, 10, -1].map(|v| Value::i32(&mut ctx, v));
+
+        let alloca = Inst::alloca(&mut ctx, arr);
+        let a = push(&mut ctx, entry, alloca).result(&ctx).unwrap();
+        let br = Inst::br(&mut ctx, header);
+        push(&mut ctx, entry, br);
+
+        let [i, s, t] = [(); 3].map(|_| {
+            let phi = Inst::phi(&mut ctx, i32);
+            push(&mut ctx, header, phi)
+        });
+        let value = |inst: Inst| inst.result(&ctx).unwrap();
+        let (iv, sv, tv) = (value(i), value(s), value(t));
+        let cmp = Inst::ibinary(
+            &mut ctx,
+            IntBinaryOp::ICmp {
+                cond: IntCmpCond::Slt,
+            },
+            iv,
+            ten,
+        );
+        let c = push(&mut ctx, header, cmp).result(&ctx).unwrap();
+        let br = Inst::cond_br(&mut ctx, c, body, exit);
+        push(&mut ctx, header, br);
+
+        let gep = Inst::getelementptr(&mut ctx, arr, a, vec![zero, iv]);
+        let p = push(&mut ctx, body, gep).result(&ctx).unwrap();
+        let store = Inst::store(&mut ctx, minus_one, p);
+        push(&mut ctx, body, store);
+        let add = |ctx: &mut Context, lhs: Value, rhs: Value| {
+            let inst = Inst::ibinary(ctx, IntBinaryOp::Add, lhs, rhs);
+            push(ctx, body, inst).result(ctx).unwrap()
+        };
+        let s2 = add(&mut ctx, sv, iv);
+        let t2 = add(&mut ctx, tv, x);
+        let next = add(&mut ctx, iv, one);
+        let br = Inst::br(&mut ctx, header);
+        push(&mut ctx, body, br);
+        for (phi, init, next) in [(i, zero, next), (s, five, s2), (t, x, t2)] {
+            phi.insert_incoming(&mut ctx, entry, init);
+            phi.insert_incoming(&mut ctx, body, next);
+        }
+
+        let sum = Inst::ibinary(&mut ctx, IntBinaryOp::Add, sv, tv);
+        push(&mut ctx, exit, sum);
+        let result = sum.result(&ctx);
+        let ret = Inst::ret(&mut ctx, result);
+        push(&mut ctx, exit, ret);
+
+        let mut am = AnalysisManager::default();
+        assert!(LoopIdiom.run_on_func(&mut ctx, func, &mut am));
+
+        // 5 + (0 + 1 + ... + 9) and x + 10 * x
+        assert_eq!(sum.operand(&ctx, 0).as_const_int(&ctx), Some(50));
+        let t_after = sum.operand(&ctx, 1).def_inst(&ctx).unwrap();
+        assert_eq!(t_after.container(&ctx), Some(entry));
+        assert_eq!(t_after.operand(&ctx, 0), x);
+        let scaled = t_after.operand(&ctx, 1).def_inst(&ctx).unwrap();
+        assert!(matches!(
+            scaled.kind(&ctx),
+            InstKind::IntBinary {
+                op: IntBinaryOp::Mul
+            }
+        ));
+        assert_eq!(scaled.operand(&ctx, 1).as_const_int(&ctx), Some(10));
+
+        // memset(&a[0][0], 255, 40) before the branch in the entry
+        assert!(body
+            .iter(&ctx)
+            .all(|inst| !matches!(inst.kind(&ctx), InstKind::Store)));
+        let call = entry.tail(&ctx).unwrap().prev(&ctx).unwrap();
+        assert_eq!(call.callee(&ctx), "memset");
+        let args = call
+            .operand_iter(&ctx)
+            .skip(2)
+            .map(|arg| arg.as_const_int(&ctx));
+        assert_eq!(args.collect::<Vec<_>>(), [Some(255), Some(40)]);
+        let ptr = call.operand(&ctx, 1).def_inst(&ctx).unwrap();
+        assert_eq!(ptr.operand(&ctx, 0), a);
+        assert_eq!(ptr.operand(&ctx, 2).as_const_int(&ctx), Some(0));
+
+        assert!(!LoopIdiom.run_on_func(&mut ctx, func, &mut am));
+    }
+}
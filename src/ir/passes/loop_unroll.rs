@@ -0,0 +1,376 @@
+//! Full loop unrolling.
+//!
+//! A loop is replaced by a copy of its body per iteration, if:
+//!
+//! - it is innermost, with a preheader and a single latch;
+//! - it has a single exiting block, the header or the latch, which is the only
+//!   predecessor of the single exit block;
+//! - its trip count is exact, and the copies are within the unroll threshold of
+//!   the [`CostModel`], for the hotness of the header in the profiled runs.
+//!
+//! The header runs one more time than the trip count, so the copies are
+//! chained from the preheader, each one branching to the next instead of the
+//! exit test, and the last one leaving to the exit. The header phis become the
+//! values of the previous copy, which the later passes fold if the induction
+//! variables start from constants. If the test is in the header, the last
+//! copy is only the header.
+
+use std::collections::HashMap;
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{BlockFreqs, Cfg, CostModel, InductionVars, Loop, LoopInfo};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, Usable, Value};
+
+/// The shape of a loop to unroll.
+struct Plan {
+    lp: Loop,
+    preheader: Block,
+    latch: Block,
+    exiting: Block,
+    exit: Block,
+    /// The number of times the back edge is taken.
+    count: usize,
+}
+
+/// The full unrolling of small loops with a constant trip count.
+#[derive(Clone, Default)]
+pub struct LoopUnroll {
+    /// The heuristics limiting the size of the unrolled loops.
+    pub model: CostModel,
+}
+
+impl LoopUnroll {
+    /// Check if a loop can be unrolled.
+    fn check(
+        &self,
+        ctx: &Context,
+        cfg: &Cfg,
+        loop_info: &LoopInfo,
+        ivs: &InductionVars,
+        freqs: &BlockFreqs,
+        lp: Loop,
+    ) -> Option<Plan> {
+        if !loop_info.is_innermost(lp) {
+            return None;
+        }
+        let preheader = loop_info.preheader(cfg, lp)?;
+        let latch = loop_info.single_latch(lp)?;
+        let &[exit] = loop_info.exits(lp) else {
+            return None;
+        };
+        let &[exiting] = loop_info.exiting_blocks(cfg, lp).as_slice() else {
+            return None;
+        };
+        let header = loop_info.header(lp);
+        if cfg.preds(exit) != [exiting] || (exiting != header && exiting != latch) {
+            return None;
+        }
+        let term = exiting.tail(ctx)?;
+        if !matches!(term.kind(ctx), InstKind::CondBr) || cfg.succs(exiting).len() != 2 {
+            return None;
+        }
+        let has_params = loop_info
+            .blocks(lp)
+            .iter()
+            .chain([&exit])
+            .any(|block| !block.params(ctx).is_empty());
+        if has_params {
+            return None;
+        }
+
+        let count = loop_info.trip_count(ctx, cfg, ivs, lp)?.exact()?;
+        let factor = u32::try_from(count + 1).ok()?;
+        let func = header.container(ctx)?;
+        let executions = func
+            .entry_count(ctx)
+            .map(|entries| entries as f64 * freqs.freq(header));
+        self.model
+            .can_unroll(ctx, loop_info, lp, factor, self.model.hotness(executions))
+            .then_some(Plan {
+                lp,
+                preheader,
+                latch,
+                exiting,
+                exit,
+                count: count as usize,
+            })
+    }
+
+    /// Replace a loop by the copies of its body.
+    fn unroll(ctx: &mut Context, cfg: &Cfg, loop_info: &LoopInfo, plan: Plan) {
+        let Plan {
+            lp,
+            preheader,
+            latch,
+            exiting,
+            exit,
+            count,
+        } = plan;
+        let header = loop_info.header(lp);
+        // The body without the back edge is acyclic, so the reverse postorder
+        // visits the definitions before the uses.
+        let mut blocks = loop_info.blocks(lp).to_vec();
+        blocks.sort_by_key(|&block| cfg.rpo_index(block));
+        let next = cfg
+            .succs(exiting)
+            .iter()
+            .copied()
+            .find(|&succ| succ != exit)
+            .unwrap();
+
+        let mut copies = Vec::new();
+        for k in 0..=count {
+            let copied = if k == count && exiting == header {
+                &blocks[..1]
+            } else {
+                &blocks[..]
+            };
+            let mut map = HashMap::new();
+            for &old in copied {
+                let new = Block::new(ctx);
+                header.insert_before(ctx, new).unwrap();
+                map.insert(old, new);
+            }
+            copies.push(map);
+        }
+
+        let mut values = HashMap::new();
+        for k in 0..=count {
+            let mut curr = HashMap::new();
+            let phis = header
+                .iter(ctx)
+                .take_while(|inst| inst.is_phi(ctx))
+                .collect::<Vec<_>>();
+            for phi in phis {
+                let value = if k == 0 {
+                    phi.incoming(ctx, preheader)
+                } else {
+                    let value = phi.incoming(ctx, latch);
+                    values.get(&value).copied().unwrap_or(value)
+                };
+                curr.insert(phi.result(ctx).unwrap(), value);
+            }
+
+            let map = &copies[k];
+            for &old in blocks.iter().filter(|block| map.contains_key(block)) {
+                let new = map[&old];
+                let insts = old.iter(ctx).collect::<Vec<_>>();
+                for inst in insts {
+                    if old == header && inst.is_phi(ctx) {
+                        continue;
+                    }
+                    if old == exiting && inst.is_terminator(ctx) {
+                        let dest = match k == count {
+                            true => exit,
+                            false if next == header => copies[k + 1][&header],
+                            false => map[&next],
+                        };
+                        let br = Inst::br(ctx, dest);
+                        new.push_back(ctx, br).unwrap();
+                        continue;
+                    }
+                    let clone = inst.clone_with(ctx, &curr, map);
+                    if old == latch && inst.is_terminator(ctx) {
+                        // The back edge goes to the next copy.
+                        let succs = clone.successor_iter(ctx).collect::<Vec<_>>();
+                        for (idx, succ) in succs.into_iter().enumerate() {
+                            if succ == map[&header] {
+                                clone.set_successor(ctx, idx, copies[k + 1][&header]);
+                            }
+                        }
+                    }
+                    if let Some(result) = inst.result(ctx) {
+                        curr.insert(result, clone.result(ctx).unwrap());
+                    }
+                    new.push_back(ctx, clone).unwrap();
+                }
+            }
+            values = curr;
+        }
+
+        // The values used after the loop are the ones of the last copy, which
+        // is where the loop is left.
+        let insts = blocks
+            .iter()
+            .flat_map(|block| block.iter(ctx))
+            .collect::<Vec<_>>();
+        for &inst in insts.iter() {
+            let Some(result) = inst.result(ctx) else {
+                continue;
+            };
+            let users = result.users(ctx).into_iter().collect::<Vec<_>>();
+            for user in users {
+                let outside = user
+                    .inst()
+                    .container(ctx)
+                    .is_some_and(|block| !loop_info.contains(lp, block));
+                if outside {
+                    let value = match values.get(&result) {
+                        Some(&value) => value,
+                        None => Value::undef(ctx, result.ty(ctx)),
+                    };
+                    user.inst().set_operand(ctx, user.idx(), value);
+                }
+            }
+        }
+        let last_exiting = copies[count][&exiting];
+        let phis = exit
+            .iter(ctx)
+            .take_while(|inst| inst.is_phi(ctx))
+            .collect::<Vec<_>>();
+        for phi in phis {
+            let value = phi.incoming(ctx, exiting);
+            phi.remove_incoming(ctx, exiting);
+            phi.insert_incoming(ctx, last_exiting, value);
+        }
+
+        let term = preheader.tail(ctx).unwrap();
+        let succs = term.successor_iter(ctx).collect::<Vec<_>>();
+        for (idx, succ) in succs.into_iter().enumerate() {
+            if succ == header {
+                term.set_successor(ctx, idx, copies[0][&header]);
+            }
+        }
+
+        // The original values are only used in the original loop now.
+        for &inst in insts.iter() {
+            if let Some(result) = inst.result(ctx) {
+                let undef = Value::undef(ctx, result.ty(ctx));
+                result.replace_all_uses_with(ctx, undef);
+            }
+        }
+        for inst in insts {
+            inst.remove(ctx);
+        }
+        for block in blocks {
+            block.remove(ctx);
+        }
+    }
+}
+
+impl TransformPass for LoopUnroll {
+    fn name(&self) -> &'static str { "loop-unroll" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        loop {
+            let cfg = am.get::<Cfg>(ctx, func);
+            let loop_info = am.get::<LoopInfo>(ctx, func);
+            let ivs = am.get::<InductionVars>(ctx, func);
+            let freqs = am.get::<BlockFreqs>(ctx, func);
+            let Some(plan) = loop_info
+                .loops()
+                .find_map(|lp| self.check(ctx, &cfg, &loop_info, &ivs, &freqs, lp))
+            else {
+                return changed;
+            };
+            Self::unroll(ctx, &cfg, &loop_info, plan);
+            am.invalidate(func);
+            changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{IntBinaryOp, IntCmpCond, Ty};
+
+    /// Build `s = 0; for (i = 0; i < 3; i++) s = s + i; return s;`, tested in
+    /// the header if `rotated` is false, or in the latch, which is the header,
+    /// with `i + 1 < 3` otherwise.
+    fn build(ctx: &mut Context, rotated: bool) -> (Func, Inst) {
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, "f".to_string(), i32);
+        let [entry, header, body, exit] = [(); 4].map(|_| Block::new(ctx));
+        for block in [entry, header, body, exit] {
+            func.push_back(ctx, block).unwrap();
+        }
+        let [zero, one, three] = [0, 1, 3].map(|v| Value::i32(ctx, v));
+        let br = Inst::br(ctx, header);
+        entry.push_back(ctx, br).unwrap();
+
+        let i = Inst::phi(ctx, i32);
+        let s = Inst::phi(ctx, i32);
+        header.push_back(ctx, i).unwrap();
+        header.push_back(ctx, s).unwrap();
+        let (iv, sv) = (i.result(ctx).unwrap(), s.result(ctx).unwrap());
+        let add = Inst::ibinary(ctx, IntBinaryOp::Add, sv, iv);
+        let inc = Inst::ibinary(ctx, IntBinaryOp::Add, iv, one);
+        let (s1, i1) = (add.result(ctx).unwrap(), inc.result(ctx).unwrap());
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let (latch, ret) = if rotated {
+            let cmp = Inst::ibinary(ctx, slt, i1, three);
+            let br = Inst::cond_br(ctx, cmp.result(ctx).unwrap(), header, exit);
+            for inst in [add, inc, cmp, br] {
+                header.push_back(ctx, inst).unwrap();
+            }
+            body.unlink(ctx);
+            (header, Inst::ret(ctx, Some(s1)))
+        } else {
+            let cmp = Inst::ibinary(ctx, slt, iv, three);
+            let br = Inst::cond_br(ctx, cmp.result(ctx).unwrap(), body, exit);
+            header.push_back(ctx, cmp).unwrap();
+            header.push_back(ctx, br).unwrap();
+            let br = Inst::br(ctx, header);
+            for inst in [add, inc, br] {
+                body.push_back(ctx, inst).unwrap();
+            }
+            (body, Inst::ret(ctx, Some(sv)))
+        };
+        i.insert_incoming(ctx, entry, zero);
+        i.insert_incoming(ctx, latch, i1);
+        s.insert_incoming(ctx, entry, zero);
+        s.insert_incoming(ctx, latch, s1);
+        exit.push_back(ctx, ret).unwrap();
+        (func, ret)
+    }
+
+    /// Evaluate the straight-line code left after unrolling.
+    fn eval(ctx: &Context, func: Func) -> i32 {
+        let mut values: HashMap<Value, i32> = HashMap::new();
+        let get = |values: &HashMap<Value, i32>, value: Value| {
+            value.as_const_int(ctx).unwrap_or_else(|| values[&value])
+        };
+        let mut block = func.head(ctx).unwrap();
+        loop {
+            for inst in block.iter(ctx) {
+                match inst.kind(ctx) {
+                    InstKind::IntBinary { op } => {
+                        let lhs = get(&values, inst.operand(ctx, 0));
+                        let rhs = get(&values, inst.operand(ctx, 1));
+                        let result = match op {
+                            IntBinaryOp::Add => lhs + rhs,
+                            _ => (lhs < rhs) as i32,
+                        };
+                        values.insert(inst.result(ctx).unwrap(), result);
+                    }
+                    InstKind::Br => block = inst.successor(ctx, 0),
+                    InstKind::Ret => return get(&values, inst.operand(ctx, 0)),
+                    kind => panic!("unexpected {:?}", kind),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_loop_unroll() {
+        for rotated in [false, true] {
+            let mut ctx = Context::default();
+            let (func, ret) = build(&mut ctx, rotated);
+            let mut am = AnalysisManager::default();
+            assert!(LoopUnroll::default().run_on_func(&mut ctx, func, &mut am));
+            // 3 iterations, and the header once more if tested there
+            let blocks = func.iter(&ctx).count();
+            assert_eq!(blocks, if rotated { 2 + 3 } else { 2 + 3 * 2 + 1 });
+            assert!(ret.container(&ctx).is_some());
+            assert_eq!(eval(&ctx, func), 3);
+            assert!(!LoopUnroll::default().run_on_func(&mut ctx, func, &mut am));
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Loop strength reduction.
+//!
+//! Multiplications of induction variables in a loop are replaced by new
+//! induction variables that are incremented on each iteration:
+//!
+//! - An address `getelementptr T, base, ..., iv, ...` with a loop-invariant
+//!   base and indices except the induction variable becomes a pointer phi,
+//!   starting at the address of the first iteration and advanced by the stride
+//!   of the index.
+//! - A derived induction variable `scale * i + offset` computed by `mul` or
+//!   `shl` becomes an integer phi, advanced by `scale * step`.
+//!
+//! Loops need a preheader and a single latch. One rewrite is done at a time,
+//! with the analyses recomputed, so the start values hoisted into the
+//! preheader can be reduced again in the enclosing loops.
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{Cfg, DerivedIv, InductionVars, Loop, LoopInfo};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, IntBinaryOp, Ty, Usable, Value};
+
+/// The strength reduction of induction variable multiplications in loops.
+#[derive(Clone, Default)]
+pub struct LoopStrengthReduce;
+
+/// A rewrite found in a loop.
+enum Candidate {
+    /// A `getelementptr` with the induction variable at the given index
+    /// operand.
+    Gep { gep: Inst, idx: usize, iv: DerivedIv },
+    /// A derived induction variable computed with a multiplication.
+    Mul { iv: DerivedIv },
+}
+
+/// The blocks of a loop to insert new instructions into.
+struct LoopShape {
+    preheader: Block,
+    header: Block,
+    latch: Block,
+}
+
+impl LoopStrengthReduce {
+    /// Find the first candidate to rewrite, with the loop it is in and the
+    /// step of its induction variable.
+    fn find_candidate(
+        ctx: &Context,
+        cfg: &Cfg,
+        loop_info: &LoopInfo,
+        ivs: &InductionVars,
+    ) -> Option<(LoopShape, i32, Candidate)> {
+        // Inner loops first, they are executed more often.
+        let mut loops = loop_info.loops().collect::<Vec<_>>();
+        loops.sort_by_key(|&lp| std::cmp::Reverse(loop_info.depth(lp)));
+
+        for lp in loops {
+            let (Some(preheader), Some(latch)) =
+                (loop_info.preheader(cfg, lp), loop_info.single_latch(lp))
+            else {
+                continue;
+            };
+            let shape = LoopShape {
+                preheader,
+                header: loop_info.header(lp),
+                latch,
+            };
+            let iv_of = |value: Value| {
+                let iv = ivs.affine(value)?;
+                let base = ivs.basic_iv(iv.base)?;
+                let step = iv.step(base)?;
+                (ivs.basic_ivs(lp).contains(base)).then_some((iv, step))
+            };
+
+            for &block in loop_info.blocks(lp) {
+                if loop_info.loop_of(block) != Some(lp) {
+                    continue;
+                }
+                for inst in block.iter(ctx) {
+                    if let Some((candidate, step)) = Self::check(ctx, loop_info, lp, inst, iv_of) {
+                        return Some((shape, step, candidate));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Check if an instruction can be strength reduced.
+    fn check(
+        ctx: &Context,
+        loop_info: &LoopInfo,
+        lp: Loop,
+        inst: Inst,
+        iv_of: impl Fn(Value) -> Option<(DerivedIv, i32)>,
+    ) -> Option<(Candidate, i32)> {
+        match inst.kind(ctx) {
+            InstKind::GetElementPtr { .. } => {
+                let mut variant = inst
+                    .operand_iter(ctx)
+                    .enumerate()
+                    .filter(|&(_, op)| !loop_info.is_invariant(ctx, lp, op));
+                let (idx, index) = variant.next()?;
+                if idx == 0 || variant.next().is_some() {
+                    return None;
+                }
+                let (iv, step) = iv_of(index)?;
+                Some((Candidate::Gep { gep: inst, idx, iv }, step))
+            }
+            InstKind::IntBinary {
+                op: IntBinaryOp::Mul | IntBinaryOp::Shl,
+            } => {
+                let (iv, step) = iv_of(inst.result(ctx).unwrap())?;
+                (iv.scale != 1).then_some((Candidate::Mul { iv }, step))
+            }
+            _ => None,
+        }
+    }
+
+    /// Compute the value of a derived induction variable in the first
+    /// iteration, before the terminator of the preheader.
+    fn start_value(ctx: &mut Context, shape: &LoopShape, iv: DerivedIv) -> Value {
+        let start = iv
+            .base
+            .def_inst(ctx)
+            .unwrap()
+            .incoming(ctx, shape.preheader);
+        let term = shape.preheader.tail(ctx).unwrap();
+        let scale = Value::i32(ctx, iv.scale);
+        let offset = Value::i32(ctx, iv.offset);
+        let scaled = Value::binary_before(ctx, term, IntBinaryOp::Mul, start, scale);
+        Value::binary_before(ctx, term, IntBinaryOp::Add, scaled, offset)
+    }
+
+    /// Create a phi in the header, advanced by `next` in the latch.
+    fn new_phi(
+        ctx: &mut Context,
+        shape: &LoopShape,
+        ty: Ty,
+        start: Value,
+        next: impl FnOnce(&mut Context, Value) -> Inst,
+    ) -> Value {
+        let phi = Inst::phi(ctx, ty);
+        shape.header.push_front(ctx, phi).unwrap();
+        let value = phi.result(ctx).unwrap();
+        let inc = next(ctx, value);
+        shape.latch.tail(ctx).unwrap().insert_before(ctx, inc).unwrap();
+        phi.insert_incoming(ctx, shape.preheader, start);
+        phi.insert_incoming(ctx, shape.latch, inc.result(ctx).unwrap());
+        value
+    }
+
+    /// Get the type stepped over by the index operand of a `getelementptr`.
+    fn stride_ty(ctx: &Context, gep: Inst, idx: usize) -> Ty {
+        let InstKind::GetElementPtr { bound_ty } = *gep.kind(ctx) else {
+            unreachable!()
+        };
+        let mut ty = bound_ty;
+        for _ in 1..idx {
+            ty = ty.as_array(ctx).expect("index into a non-array type").0;
+        }
+        ty
+    }
+
+    /// Rewrite a candidate, where `step` is the step of its induction variable.
+    fn rewrite(ctx: &mut Context, shape: LoopShape, step: i32, candidate: Candidate) {
+        match candidate {
+            Candidate::Gep { gep, idx, iv } => {
+                // The address of the first iteration.
+                let InstKind::GetElementPtr { bound_ty } = *gep.kind(ctx) else {
+                    unreachable!()
+                };
+                let start_index = Self::start_value(ctx, &shape, iv);
+                let mut operands = gep.operand_iter(ctx).collect::<Vec<_>>();
+                operands[idx] = start_index;
+                let start = Inst::getelementptr(ctx, bound_ty, operands[0], operands[1..].to_vec());
+                let term = shape.preheader.tail(ctx).unwrap();
+                term.insert_before(ctx, start).unwrap();
+                let start = start.result(ctx).unwrap();
+
+                let stride_ty = Self::stride_ty(ctx, gep, idx);
+                let ptr_ty = gep.result(ctx).unwrap().ty(ctx);
+                let phi = Self::new_phi(ctx, &shape, ptr_ty, start, |ctx, ptr| {
+                    let stride = Value::i32(ctx, step);
+                    Inst::getelementptr(ctx, stride_ty, ptr, vec![stride])
+                });
+
+                let old = gep.result(ctx).unwrap();
+                let index = gep.operand(ctx, idx);
+                old.replace_all_uses_with(ctx, phi);
+                gep.remove(ctx);
+                Self::remove_dead(ctx, index);
+            }
+            Candidate::Mul { iv } => {
+                let start = Self::start_value(ctx, &shape, iv);
+                let phi = Self::new_phi(ctx, &shape, start.ty(ctx), start, |ctx, value| {
+                    let step = Value::i32(ctx, step);
+                    Inst::ibinary(ctx, IntBinaryOp::Add, value, step)
+                });
+                iv.value.replace_all_uses_with(ctx, phi);
+                Self::remove_dead(ctx, iv.value);
+            }
+        }
+    }
+
+    /// Remove the unused computation of an index, and the operands only used
+    /// by it.
+    fn remove_dead(ctx: &mut Context, value: Value) {
+        let Some(inst) = value.def_inst(ctx) else {
+            return;
+        };
+        if !matches!(inst.kind(ctx), InstKind::IntBinary { .. })
+            || value.users(ctx).into_iter().next().is_some()
+        {
+            return;
+        }
+        let operands = inst.operand_iter(ctx).collect::<Vec<_>>();
+        inst.remove(ctx);
+        for operand in operands {
+            Self::remove_dead(ctx, operand);
+        }
+    }
+}
+
+impl TransformPass for LoopStrengthReduce {
+    fn name(&self) -> &'static str { "lsr" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        loop {
+            let cfg = am.get::<Cfg>(ctx, func);
+            let loop_info = am.get::<LoopInfo>(ctx, func);
+            let ivs = am.get::<InductionVars>(ctx, func);
+            let Some((shape, step, candidate)) = Self::find_candidate(ctx, &cfg, &loop_info, &ivs)
+            else {
+                return changed;
+            };
+            Self::rewrite(ctx, shape, step, candidate);
+            am.invalidate(func);
+            changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::IntCmpCond;
+
+    #[test]
+    fn test_lsr() {
+        // entry:  br header
+        // header: i = phi [0, entry], [i1, header]
+        //         k = mul i, 3; j = add k, 1; p = gep [16 x [4 x i32]], a, 0, i, 2
+        //         store j, p; i1 = add i, 1; c = icmp slt i1, 16
+        //         cond_br c, header, exit
+        // exit:   ret k
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let ptr = Ty::ptr(&mut ctx);
+        let row = Ty::array(&mut ctx, i32, 4);
+        let matrix = Ty::array(&mut ctx, row, 16);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let a = func.add_param(&mut ctx, ptr);
+        let [entry, header, exit] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, header, exit] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [zero, one, two, three, sixteen] = [0, 1, 2, 3, 16].map(|v| Value::i32(&mut ctx, v));
+
+        let br = Inst::br(&mut ctx, header);
+        entry.push_back(&mut ctx, br).unwrap();
+
+        let push = |ctx: &mut Context, inst: Inst| {
+            header.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+        let phi = Inst::phi(&mut ctx, i32);
+        let i = push(&mut ctx, phi).unwrap();
+        let mul = Inst::ibinary(&mut ctx, IntBinaryOp::Mul, i, three);
+        let k = push(&mut ctx, mul).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, k, one);
+        let j = push(&mut ctx, add).unwrap();
+        let gep = Inst::getelementptr(&mut ctx, matrix, a, vec![zero, i, two]);
+        let p = push(&mut ctx, gep).unwrap();
+        let store = Inst::store(&mut ctx, j, p);
+        push(&mut ctx, store);
+        let inc = Inst::ibinary(&mut ctx, IntBinaryOp::Add, i, one);
+        let i1 = push(&mut ctx, inc).unwrap();
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let cmp = Inst::ibinary(&mut ctx, slt, i1, sixteen);
+        let c = push(&mut ctx, cmp).unwrap();
+        let br = Inst::cond_br(&mut ctx, c, header, exit);
+        push(&mut ctx, br);
+        phi.insert_incoming(&mut ctx, entry, zero);
+        phi.insert_incoming(&mut ctx, header, i1);
+        let ret = Inst::ret(&mut ctx, Some(k));
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(LoopStrengthReduce.run_on_func(&mut ctx, func, &mut am));
+
+        // no multiplications or variable-indexed addresses left in the loop
+        let kinds = header
+            .iter(&ctx)
+            .map(|inst| inst.kind(&ctx).clone())
+            .collect::<Vec<_>>();
+        assert!(!kinds.iter().any(|kind| matches!(
+            kind,
+            InstKind::IntBinary {
+                op: IntBinaryOp::Mul
+            }
+        )));
+        assert_eq!(kinds.iter().filter(|kind| matches!(kind, InstKind::Phi)).count(), 3);
+
+        // the store address starts at a[0][2], and advances by a row
+        let p = store.operand(&ctx, 1);
+        let p_phi = p.def_inst(&ctx).unwrap();
+        assert!(p_phi.is_phi(&ctx));
+        let start = p_phi.incoming(&ctx, entry).def_inst(&ctx).unwrap();
+        let indices = start
+            .operand_iter(&ctx)
+            .skip(1)
+            .map(|index| index.as_const_int(&ctx))
+            .collect::<Vec<_>>();
+        assert_eq!(indices, [Some(0), Some(0), Some(2)]);
+        let next = p_phi.incoming(&ctx, header).def_inst(&ctx).unwrap();
+        let stride = match *next.kind(&ctx) {
+            InstKind::GetElementPtr { bound_ty } => bound_ty,
+            _ => panic!("pointer not advanced by getelementptr"),
+        };
+        assert_eq!(stride, row);
+        assert_eq!(next.operand(&ctx, 1).as_const_int(&ctx), Some(1));
+
+        // k = 3 * i is an induction variable starting at 0, stepping by 3
+        let k = ret.operand(&ctx, 0).def_inst(&ctx).unwrap();
+        assert!(k.is_phi(&ctx));
+        assert_eq!(k.incoming(&ctx, entry).as_const_int(&ctx), Some(0));
+        let k_next = k.incoming(&ctx, header).def_inst(&ctx).unwrap();
+        assert_eq!(k_next.operand(&ctx, 1).as_const_int(&ctx), Some(3));
+
+        assert!(!LoopStrengthReduce.run_on_func(&mut ctx, func, &mut am));
+    }
+}
@@ -0,0 +1,270 @@
+//! Promotion of allocas to SSA values.
+//!
+//! An alloca is promoted if it does not escape, is not an array, and is only
+//! accessed by direct loads and stores of its own type, as given by
+//! [`EscapeInfo::is_promotable`]. The promotion is the classic construction
+//! of Cytron et al.:
+//!
+//! - Phis are placed at the iterated dominance frontiers of the blocks storing
+//!   to the alloca.
+//! - The blocks are renamed in a preorder walk of the dominator tree, each load
+//!   replaced by the value last stored on the way from the entry, and the
+//!   stores removed. A load before any store reads `undef`.
+//!
+//! The phis are not pruned, the dead ones are left to DCE.
+
+use std::collections::HashMap;
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::{Cfg, DomTree, EscapeInfo};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, Ty, Usable, Value};
+
+/// The promotion of scalar allocas to SSA values.
+#[derive(Clone, Default)]
+pub struct Mem2Reg;
+
+impl Mem2Reg {
+    /// Get the type of a promotable alloca, if all the loads and stores access
+    /// it with that type.
+    fn promoted_ty(ctx: &Context, alloca: Inst) -> Option<Ty> {
+        let InstKind::Alloca { ty } = *alloca.kind(ctx) else {
+            return None;
+        };
+        let ptr = alloca.result(ctx).unwrap();
+        let accessed_ty = |inst: Inst| match inst.kind(ctx) {
+            InstKind::Load => inst.result(ctx).unwrap().ty(ctx),
+            _ => inst.operand(ctx, 0).ty(ctx),
+        };
+        ptr.users(ctx)
+            .into_iter()
+            .all(|user| accessed_ty(user.inst()) == ty)
+            .then_some(ty)
+    }
+
+    /// Place the phis of an alloca at the iterated dominance frontiers of the
+    /// blocks storing to it.
+    fn place_phis(
+        ctx: &mut Context,
+        cfg: &Cfg,
+        frontiers: &HashMap<Block, Vec<Block>>,
+        alloca: Inst,
+        ty: Ty,
+    ) -> Vec<Inst> {
+        let ptr = alloca.result(ctx).unwrap();
+        let mut worklist = ptr
+            .users(ctx)
+            .into_iter()
+            .map(|user| user.inst())
+            .filter(|inst| matches!(inst.kind(ctx), InstKind::Store))
+            .filter_map(|inst| inst.container(ctx))
+            .filter(|&block| cfg.is_reachable(block))
+            .collect::<Vec<_>>();
+
+        let mut phis = Vec::new();
+        let mut placed = HashMap::new();
+        while let Some(block) = worklist.pop() {
+            for &frontier in frontiers.get(&block).into_iter().flatten() {
+                if placed.contains_key(&frontier) {
+                    continue;
+                }
+                let phi = Inst::phi(ctx, ty);
+                frontier.push_front(ctx, phi).unwrap();
+                // The unreachable predecessors are never renamed.
+                for &pred in cfg.preds(frontier) {
+                    if !cfg.is_reachable(pred) {
+                        let undef = Value::undef(ctx, ty);
+                        phi.insert_incoming(ctx, pred, undef);
+                    }
+                }
+                placed.insert(frontier, phi);
+                phis.push(phi);
+                worklist.push(frontier);
+            }
+        }
+        phis
+    }
+
+    /// Replace the loads and stores of the allocas in a block, with the values
+    /// reaching the block in `values`.
+    fn rename_block(
+        ctx: &mut Context,
+        block: Block,
+        slots: &HashMap<Value, usize>,
+        phis: &HashMap<Inst, usize>,
+        values: &mut [Value],
+    ) {
+        let insts = block.iter(ctx).collect::<Vec<_>>();
+        for inst in insts {
+            match inst.kind(ctx) {
+                InstKind::Phi => {
+                    if let Some(&slot) = phis.get(&inst) {
+                        values[slot] = inst.result(ctx).unwrap();
+                    }
+                }
+                InstKind::Load => {
+                    let Some(&slot) = slots.get(&inst.operand(ctx, 0)) else {
+                        continue;
+                    };
+                    let result = inst.result(ctx).unwrap();
+                    result.replace_all_uses_with(ctx, values[slot]);
+                    inst.remove(ctx);
+                }
+                InstKind::Store => {
+                    let Some(&slot) = slots.get(&inst.operand(ctx, 1)) else {
+                        continue;
+                    };
+                    values[slot] = inst.operand(ctx, 0);
+                    inst.remove(ctx);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl TransformPass for Mem2Reg {
+    fn name(&self) -> &'static str { "mem2reg" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        if func.is_declaration(ctx) {
+            return false;
+        }
+        let escape = am.get::<EscapeInfo>(ctx, func);
+        let mut allocas = escape
+            .promotable()
+            .filter_map(|alloca| Self::promoted_ty(ctx, alloca).map(|ty| (alloca, ty)))
+            .collect::<Vec<_>>();
+        if allocas.is_empty() {
+            return false;
+        }
+        // Keep the placement of the phis deterministic.
+        allocas.sort();
+
+        let cfg = am.get::<Cfg>(ctx, func);
+        let domtree = am.get::<DomTree>(ctx, func);
+        let frontiers = domtree.frontiers(&cfg);
+
+        let mut slots = HashMap::new();
+        let mut phis = HashMap::new();
+        let mut values = Vec::new();
+        for (slot, &(alloca, ty)) in allocas.iter().enumerate() {
+            slots.insert(alloca.result(ctx).unwrap(), slot);
+            for phi in Self::place_phis(ctx, &cfg, &frontiers, alloca, ty) {
+                phis.insert(phi, slot);
+            }
+            values.push(Value::undef(ctx, ty));
+        }
+
+        // Walk the dominator tree, restoring the reaching values when leaving
+        // a subtree.
+        let mut stack = cfg
+            .entry()
+            .map(|entry| (entry, None))
+            .into_iter()
+            .collect::<Vec<_>>();
+        while let Some((block, saved)) = stack.pop() {
+            if let Some(saved) = saved {
+                values = saved;
+                continue;
+            }
+            stack.push((block, Some(values.clone())));
+            Self::rename_block(ctx, block, &slots, &phis, &mut values);
+            for &succ in cfg.succs(block) {
+                let succ_phis = succ
+                    .iter(ctx)
+                    .take_while(|inst| inst.is_phi(ctx))
+                    .filter_map(|phi| phis.get(&phi).map(|&slot| (phi, slot)))
+                    .collect::<Vec<_>>();
+                for (phi, slot) in succ_phis {
+                    phi.insert_incoming(ctx, block, values[slot]);
+                }
+            }
+            for &child in domtree.children(block) {
+                stack.push((child, None));
+            }
+        }
+
+        // The accesses left are in unreachable blocks.
+        for (alloca, ty) in allocas {
+            let ptr = alloca.result(ctx).unwrap();
+            let accesses = ptr
+                .users(ctx)
+                .into_iter()
+                .map(|user| user.inst())
+                .collect::<Vec<_>>();
+            for inst in accesses {
+                if let Some(result) = inst.result(ctx) {
+                    let undef = Value::undef(ctx, ty);
+                    result.replace_all_uses_with(ctx, undef);
+                }
+                inst.remove(ctx);
+            }
+            alloca.remove(ctx);
+        }
+
+        am.invalidate(func);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build `int x = 0; if (c) x = 1; return x;` with `x` on the stack.
+    #[test]
+    fn test_mem2reg() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let i1 = Ty::i1(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let c = func.add_param(&mut ctx, i1);
+        let [entry, then, merge] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, then, merge] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let zero = Value::i32(&mut ctx, 0);
+        let one = Value::i32(&mut ctx, 1);
+
+        let alloca = Inst::alloca(&mut ctx, i32);
+        let x = alloca.result(&ctx).unwrap();
+        let store = Inst::store(&mut ctx, zero, x);
+        let br = Inst::cond_br(&mut ctx, c, then, merge);
+        for inst in [alloca, store, br] {
+            entry.push_back(&mut ctx, inst).unwrap();
+        }
+        let store = Inst::store(&mut ctx, one, x);
+        let br = Inst::br(&mut ctx, merge);
+        then.push_back(&mut ctx, store).unwrap();
+        then.push_back(&mut ctx, br).unwrap();
+        let load = Inst::load(&mut ctx, x, i32);
+        let loaded = load.result(&ctx);
+        let ret = Inst::ret(&mut ctx, loaded);
+        merge.push_back(&mut ctx, load).unwrap();
+        merge.push_back(&mut ctx, ret).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(Mem2Reg.run_on_func(&mut ctx, func, &mut am));
+        let memory = func
+            .iter(&ctx)
+            .flat_map(|block| block.iter(&ctx))
+            .filter(|inst| {
+                matches!(
+                    inst.kind(&ctx),
+                    InstKind::Alloca { .. } | InstKind::Load | InstKind::Store
+                )
+            })
+            .count();
+        assert_eq!(memory, 0);
+
+        let phi = merge.head(&ctx).unwrap();
+        assert!(phi.is_phi(&ctx));
+        assert_eq!(phi.incoming(&ctx, entry), zero);
+        assert_eq!(phi.incoming(&ctx, then), one);
+        assert_eq!(ret.operand(&ctx, 0), phi.result(&ctx).unwrap());
+        assert!(!Mem2Reg.run_on_func(&mut ctx, func, &mut am));
+    }
+}
@@ -0,0 +1,269 @@
+//! Recognition of the signed minimum and maximum.
+//!
+//! SysY has no conditional expression, so a clamp like `a < b ? a : b` is
+//! written with an `if`, and becomes a branch and a phi after mem2reg:
+//!
+//! ```text
+//! entry: c = icmp slt a, b; br c, then, merge
+//! then:  br merge
+//! merge: m = phi [a, then], [b, entry]
+//! ```
+//!
+//! If the phis of the merge block all pick between the compared values, they
+//! are replaced by `smin` and `smax`, and the branch by a jump to the merge
+//! block, so the backends can use their branch-free sequences, e.g., `min`
+//! with the RISC-V Zbb extension. The arms between the branch and the merge
+//! block may only jump.
+//!
+//! Without such instructions, the backends need a longer sequence of masks for
+//! `smin` and `smax`, so the pass is only part of `-O2` for the targets having
+//! them, see [`opt_pipeline`](super::opt_pipeline).
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::Cfg;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, IntBinaryOp, IntCmpCond, Usable};
+
+/// The replacement of the branches picking the smaller or the larger value.
+#[derive(Clone, Default)]
+pub struct MinMax;
+
+/// A branch between two values, found by [`MinMax::check`].
+struct Diamond {
+    /// The block ending with the branch.
+    head: Block,
+    /// The block the arms join at.
+    merge: Block,
+    /// The arms, i.e., the blocks only jumping to the merge block.
+    arms: Vec<Block>,
+    /// The phis in the merge block, with the operations replacing them.
+    phis: Vec<(Inst, IntBinaryOp)>,
+}
+
+impl MinMax {
+    /// Follow a successor of the branch in `head` to the merge block.
+    ///
+    /// # Returns
+    ///
+    /// The merge block, and the predecessor of it on the path, which is the
+    /// arm if the successor only jumps to the merge block.
+    fn follow(ctx: &Context, cfg: &Cfg, head: Block, succ: Block) -> (Block, Block) {
+        let only_jumps = succ.params(ctx).is_empty()
+            && cfg.preds(succ) == [head]
+            && succ.head(ctx) == succ.tail(ctx)
+            && succ.tail(ctx).is_some_and(|term| {
+                matches!(term.kind(ctx), InstKind::Br) && term.successor_args(ctx, 0).count() == 0
+            });
+        match only_jumps {
+            true => (succ.tail(ctx).unwrap().successor(ctx, 0), succ),
+            false => (succ, head),
+        }
+    }
+
+    /// Check if the branch at the end of a block picks between the compared
+    /// values.
+    fn check(ctx: &Context, cfg: &Cfg, head: Block) -> Option<Diamond> {
+        let term = head.tail(ctx)?;
+        if !matches!(term.kind(ctx), InstKind::CondBr)
+            || (0..2).any(|i| term.successor_args(ctx, i).count() > 0)
+        {
+            return None;
+        }
+        let cmp = term.operand(ctx, 0).def_inst(ctx)?;
+        let InstKind::IntBinary {
+            op: IntBinaryOp::ICmp { cond },
+        } = *cmp.kind(ctx)
+        else {
+            return None;
+        };
+        let (x, y) = (cmp.operand(ctx, 0), cmp.operand(ctx, 1));
+        if x.ty(ctx).bitwidth(ctx) == 1 {
+            return None;
+        }
+        // `c ? x : y` is the minimum for `x < y`, and the maximum for `x > y`.
+        let (pick_x_op, pick_y_op) = match cond {
+            IntCmpCond::Slt | IntCmpCond::Sle => (IntBinaryOp::SMin, IntBinaryOp::SMax),
+            IntCmpCond::Sgt | IntCmpCond::Sge => (IntBinaryOp::SMax, IntBinaryOp::SMin),
+            IntCmpCond::Eq | IntCmpCond::Ne => return None,
+        };
+
+        let (then_dest, else_dest) = (term.successor(ctx, 0), term.successor(ctx, 1));
+        if then_dest == else_dest {
+            return None;
+        }
+        let (merge, then_pred) = Self::follow(ctx, cfg, head, then_dest);
+        let (else_merge, else_pred) = Self::follow(ctx, cfg, head, else_dest);
+        if merge != else_merge || merge == head || then_pred == else_pred {
+            return None;
+        }
+        if cfg.preds(merge).len() != 2 || !merge.params(ctx).is_empty() {
+            return None;
+        }
+
+        let mut phis = Vec::new();
+        for phi in merge.iter(ctx).take_while(|inst| inst.is_phi(ctx)) {
+            let picked = (phi.incoming(ctx, then_pred), phi.incoming(ctx, else_pred));
+            let op = if picked == (x, y) {
+                pick_x_op
+            } else if picked == (y, x) {
+                pick_y_op
+            } else {
+                return None;
+            };
+            phis.push((phi, op));
+        }
+        if phis.is_empty() {
+            return None;
+        }
+
+        let arms = [then_pred, else_pred]
+            .into_iter()
+            .filter(|&pred| pred != head)
+            .collect();
+        Some(Diamond {
+            head,
+            merge,
+            arms,
+            phis,
+        })
+    }
+
+    /// Replace the phis by the operations, and the branch by a jump.
+    fn replace(ctx: &mut Context, diamond: Diamond) {
+        let cmp = diamond
+            .head
+            .tail(ctx)
+            .unwrap()
+            .operand(ctx, 0)
+            .def_inst(ctx)
+            .unwrap();
+        let (x, y) = (cmp.operand(ctx, 0), cmp.operand(ctx, 1));
+        for (phi, op) in diamond.phis {
+            let inst = Inst::ibinary(ctx, op, x, y);
+            inst.set_loc(ctx, phi.loc(ctx));
+            let last_phi = diamond
+                .merge
+                .iter(ctx)
+                .take_while(|inst| inst.is_phi(ctx))
+                .last()
+                .unwrap();
+            last_phi.insert_after(ctx, inst).unwrap();
+            let result = inst.result(ctx).unwrap();
+            phi.result(ctx).unwrap().replace_all_uses_with(ctx, result);
+            phi.remove(ctx);
+        }
+
+        diamond.head.tail(ctx).unwrap().remove(ctx);
+        let br = Inst::br(ctx, diamond.merge);
+        diamond.head.push_back(ctx, br).unwrap();
+        if cmp
+            .result(ctx)
+            .unwrap()
+            .users(ctx)
+            .into_iter()
+            .next()
+            .is_none()
+        {
+            cmp.remove(ctx);
+        }
+        for arm in diamond.arms {
+            arm.tail(ctx).unwrap().remove(ctx);
+            arm.remove(ctx);
+        }
+    }
+}
+
+impl TransformPass for MinMax {
+    fn name(&self) -> &'static str { "min-max" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        loop {
+            let cfg = am.get::<Cfg>(ctx, func);
+            let diamond = func
+                .iter(ctx)
+                .find_map(|block| Self::check(ctx, &cfg, block));
+            let Some(diamond) = diamond else {
+                break;
+            };
+            Self::replace(ctx, diamond);
+            am.invalidate(func);
+            changed = true;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Ty, Value};
+
+    /// Build `ret phi [a, then], [b, entry]` after `br (icmp cond a, b), then,
+    /// merge`, swapping `a` and `b` in the phi if asked.
+    fn build(ctx: &mut Context, cond: IntCmpCond, swap: bool) -> (Func, Inst) {
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, "f".to_string(), i32);
+        let a = func.add_param(ctx, i32);
+        let b = func.add_param(ctx, i32);
+        let [entry, then, merge] = [(); 3].map(|_| Block::new(ctx));
+        for block in [entry, then, merge] {
+            func.push_back(ctx, block).unwrap();
+        }
+        let cmp = Inst::ibinary(ctx, IntBinaryOp::ICmp { cond }, a, b);
+        entry.push_back(ctx, cmp).unwrap();
+        let br = Inst::cond_br(ctx, cmp.result(ctx).unwrap(), then, merge);
+        entry.push_back(ctx, br).unwrap();
+        let br = Inst::br(ctx, merge);
+        then.push_back(ctx, br).unwrap();
+        let phi = Inst::phi(ctx, i32);
+        let (picked, other) = if swap { (b, a) } else { (a, b) };
+        phi.insert_incoming(ctx, then, picked);
+        phi.insert_incoming(ctx, entry, other);
+        merge.push_back(ctx, phi).unwrap();
+        let ret = Inst::ret(ctx, phi.result(ctx));
+        merge.push_back(ctx, ret).unwrap();
+        (func, ret)
+    }
+
+    #[test]
+    fn test_min_max() {
+        for (cond, swap, op) in [
+            (IntCmpCond::Slt, false, IntBinaryOp::SMin),
+            (IntCmpCond::Sle, true, IntBinaryOp::SMax),
+            (IntCmpCond::Sgt, false, IntBinaryOp::SMax),
+            (IntCmpCond::Sge, true, IntBinaryOp::SMin),
+        ] {
+            let mut ctx = Context::default();
+            let (func, ret) = build(&mut ctx, cond, swap);
+            let mut am = AnalysisManager::default();
+            assert!(MinMax.run_on_func(&mut ctx, func, &mut am));
+            let inst = ret.operand(&ctx, 0).def_inst(&ctx).unwrap();
+            assert!(matches!(inst.kind(&ctx), InstKind::IntBinary { op: o } if *o == op));
+            let params = func.params(&ctx).to_vec();
+            assert_eq!(inst.operand_iter(&ctx).collect::<Vec<_>>(), params);
+            // the comparison and the arm are removed
+            assert_eq!(func.iter(&ctx).count(), 2);
+            assert_eq!(func.head(&ctx).unwrap().iter(&ctx).count(), 1);
+        }
+
+        // the equality picks nothing
+        let mut ctx = Context::default();
+        let (func, _) = build(&mut ctx, IntCmpCond::Eq, false);
+        let mut am = AnalysisManager::default();
+        assert!(!MinMax.run_on_func(&mut ctx, func, &mut am));
+
+        // a phi picking another value blocks the replacement
+        let mut ctx = Context::default();
+        let (func, ret) = build(&mut ctx, IntCmpCond::Slt, false);
+        let phi = ret.operand(&ctx, 0).def_inst(&ctx).unwrap();
+        let then = func.head(&ctx).unwrap().next(&ctx).unwrap();
+        phi.remove_incoming(&mut ctx, then);
+        let zero = Value::i32(&mut ctx, 0);
+        phi.insert_incoming(&mut ctx, then, zero);
+        let mut am = AnalysisManager::default();
+        assert!(!MinMax.run_on_func(&mut ctx, func, &mut am));
+    }
+}
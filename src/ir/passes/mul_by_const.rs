@@ -0,0 +1,242 @@
+//! Strength reduction of multiplication by constants.
+//!
+//! `mul x, c` on `i32` with a constant `c` is replaced by shifts and additions
+//! if they are cheaper than the multiplication on the target. The constant is
+//! written in the non-adjacent form, i.e., as a sum of signed powers of two
+//! with the fewest terms, e.g., `7 = 8 - 1` and `10 = 8 + 2`:
+//!
+//! ```text
+//! t = shl x, 3
+//! r = sub t, x        ; x * 7
+//! ```
+//!
+//! Each term other than `x` itself costs a shift, and combining the terms
+//! costs an addition or a subtraction each, plus a negation if all the terms
+//! are negative. The arithmetic wraps around the same way as the
+//! multiplication, so the result is exact for all constants.
+
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Context, Func, Inst, InstKind, IntBinaryOp, Value};
+
+/// The costs of the integer operations on the target, in cycles.
+#[derive(Debug, Clone, Copy)]
+pub struct MulCosts {
+    /// The cost of a multiplication.
+    pub mul: u32,
+    /// The cost of an addition or a subtraction.
+    pub add: u32,
+    /// The cost of a shift by a constant.
+    pub shift: u32,
+}
+
+impl Default for MulCosts {
+    /// The costs on a typical in-order RISC-V core.
+    fn default() -> Self {
+        Self {
+            mul: 3,
+            add: 1,
+            shift: 1,
+        }
+    }
+}
+
+/// The strength reduction of multiplications by constants.
+#[derive(Clone, Default)]
+pub struct MulByConst {
+    /// The costs of the operations to compare.
+    pub costs: MulCosts,
+}
+
+/// Write a constant in the non-adjacent form.
+///
+/// # Returns
+///
+/// The terms `(negative, k)` for `-2^k` or `2^k`, from the lowest `k`.
+fn naf(c: i32) -> Vec<(bool, u32)> {
+    let mut terms = Vec::new();
+    let mut c = c as i64;
+    let mut k = 0;
+    while c != 0 {
+        if c & 1 != 0 {
+            // Pick the digit making the rest divisible by 4.
+            let digit = 2 - c.rem_euclid(4);
+            terms.push((digit < 0, k));
+            c -= digit;
+        }
+        c >>= 1;
+        k += 1;
+    }
+    terms
+}
+
+impl MulByConst {
+    /// Get the cost of computing the terms with shifts and additions.
+    fn cost(&self, terms: &[(bool, u32)]) -> u32 {
+        let shifts = terms.iter().filter(|&&(_, k)| k != 0).count() as u32;
+        let mut adds = terms.len() as u32 - 1;
+        if terms.iter().all(|&(negative, _)| negative) {
+            adds += 1;
+        }
+        shifts * self.costs.shift + adds * self.costs.add
+    }
+
+    /// Compute the sum of the terms times `x` before an instruction.
+    fn expand(ctx: &mut Context, before: Inst, x: Value, mut terms: Vec<(bool, u32)>) -> Value {
+        // Start with a positive term, so only the rest need subtractions.
+        terms.sort_by_key(|&(negative, k)| (negative, std::cmp::Reverse(k)));
+        let shifted = |ctx: &mut Context, k: u32| {
+            if k == 0 {
+                return x;
+            }
+            let k = Value::i32(ctx, k as i32);
+            Value::binary_before(ctx, before, IntBinaryOp::Shl, x, k)
+        };
+
+        let (negative, k) = terms[0];
+        let mut result = shifted(ctx, k);
+        if negative {
+            let zero = Value::i32(ctx, 0);
+            result = Value::binary_before(ctx, before, IntBinaryOp::Sub, zero, result);
+        }
+        for &(negative, k) in terms[1..].iter() {
+            let term = shifted(ctx, k);
+            let op = if negative {
+                IntBinaryOp::Sub
+            } else {
+                IntBinaryOp::Add
+            };
+            result = Value::binary_before(ctx, before, op, result, term);
+        }
+        result
+    }
+}
+
+impl TransformPass for MulByConst {
+    fn name(&self) -> &'static str { "mul-by-const" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let insts = func
+            .iter(ctx)
+            .flat_map(|block| block.iter(ctx))
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+        for inst in insts {
+            if !matches!(
+                inst.kind(ctx),
+                InstKind::IntBinary {
+                    op: IntBinaryOp::Mul
+                }
+            ) {
+                continue;
+            }
+            let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+            if lhs.ty(ctx).bitwidth(ctx) != 32 {
+                continue;
+            }
+            let (x, c) = match (lhs.as_const_int(ctx), rhs.as_const_int(ctx)) {
+                (_, Some(c)) => (lhs, c),
+                (Some(c), None) => (rhs, c),
+                (None, None) => continue,
+            };
+            let terms = naf(c);
+            if terms.is_empty() || self.cost(&terms) >= self.costs.mul {
+                continue;
+            }
+            let value = Self::expand(ctx, inst, x, terms);
+            inst.result(ctx).unwrap().replace_all_uses_with(ctx, value);
+            inst.remove(ctx);
+            changed = true;
+        }
+
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Ty};
+
+    /// Build `ret mul x, c`, where `x` is the parameter or a constant.
+    fn build(ctx: &mut Context, x: Option<i32>, c: i32) -> (Func, Inst) {
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, "f".to_string(), i32);
+        let param = func.add_param(ctx, i32);
+        let block = Block::new(ctx);
+        func.push_back(ctx, block).unwrap();
+        let x = x.map_or(param, |x| Value::i32(ctx, x));
+        let c = Value::i32(ctx, c);
+        let mul = Inst::ibinary(ctx, IntBinaryOp::Mul, x, c);
+        block.push_back(ctx, mul).unwrap();
+        let ret = Inst::ret(ctx, mul.result(ctx));
+        block.push_back(ctx, ret).unwrap();
+        (func, ret)
+    }
+
+    /// Count the multiplications left in a function.
+    fn muls(ctx: &Context, func: Func) -> usize {
+        func.head(ctx)
+            .unwrap()
+            .iter(ctx)
+            .filter(|inst| {
+                matches!(
+                    inst.kind(ctx),
+                    InstKind::IntBinary {
+                        op: IntBinaryOp::Mul
+                    }
+                )
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_mul_by_const() {
+        // Everything is expanded when multiplication is expensive, and the
+        // sequences are folded into the exact results.
+        let mut pass = MulByConst {
+            costs: MulCosts {
+                mul: 100,
+                add: 1,
+                shift: 1,
+            },
+        };
+        let factors = [1, 2, 3, 5, 7, 10, 15, 100, 12345, i32::MAX, i32::MIN];
+        let factors = factors.into_iter().flat_map(|c| [c, c.wrapping_neg()]);
+        let xs = [1, 3, -7, 65535, i32::MAX, i32::MIN];
+        for c in factors {
+            for x in xs {
+                let mut ctx = Context::default();
+                let (func, ret) = build(&mut ctx, Some(x), c);
+                let mut am = AnalysisManager::default();
+                // Multiplications of two constants are folded by instruction
+                // combining instead, but are still expanded correctly.
+                assert!(pass.run_on_func(&mut ctx, func, &mut am));
+                let result = ret.operand(&ctx, 0).as_const_int(&ctx);
+                assert_eq!(result, Some(x.wrapping_mul(c)), "{} * {}", x, c);
+            }
+        }
+
+        // With the default costs, 7 = 8 - 1 is cheaper, 10 = 8 + 2 is not.
+        let mut ctx = Context::default();
+        let (func, _) = build(&mut ctx, None, 7);
+        let mut am = AnalysisManager::default();
+        assert!(MulByConst::default().run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(muls(&ctx, func), 0);
+
+        let mut ctx = Context::default();
+        let (func, _) = build(&mut ctx, None, 10);
+        let mut am = AnalysisManager::default();
+        assert!(!MulByConst::default().run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(muls(&ctx, func), 1);
+        pass.costs.mul = 4;
+        assert!(pass.run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(muls(&ctx, func), 0);
+    }
+}
@@ -0,0 +1,175 @@
+//! Phi simplification.
+//!
+//! Phis left degenerate by other transformations are cleaned up:
+//!
+//! - The incoming values from blocks that are no longer predecessors, e.g.,
+//!   after an edge is removed from the CFG, are dropped.
+//! - A phi whose incoming values are all the same value, ignoring the phi
+//!   itself, is replaced by that value. Constants are compared by value.
+//!
+//! Replacing a phi may make others trivial, so this is repeated until no phi
+//! changes.
+
+use std::collections::HashSet;
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::Cfg;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Context, Func, Inst, Value};
+
+/// The removal of trivial phis and stale incoming values.
+#[derive(Clone, Default)]
+pub struct PhiSimplify;
+
+impl PhiSimplify {
+    /// Drop the incoming values of a phi from blocks that are not
+    /// predecessors.
+    fn remove_stale(ctx: &mut Context, cfg: &Cfg, phi: Inst) -> bool {
+        let block = phi.container(ctx).unwrap();
+        let preds = cfg.preds(block).iter().copied().collect::<HashSet<_>>();
+        let stale = phi
+            .incoming_iter(ctx)
+            .map(|(pred, _)| pred)
+            .filter(|pred| !preds.contains(pred))
+            .collect::<Vec<_>>();
+        for &pred in stale.iter() {
+            phi.remove_incoming(ctx, pred);
+        }
+        !stale.is_empty()
+    }
+
+    /// Get the only value a phi can take, if any.
+    fn unique_value(ctx: &Context, phi: Inst) -> Option<Value> {
+        let this = phi.result(ctx).unwrap();
+        let mut values = phi
+            .incoming_iter(ctx)
+            .map(|(_, value)| value)
+            .filter(|&value| value != this);
+        let first = values.next()?;
+        let same = |value: Value| {
+            value == first
+                || value
+                    .as_const(ctx)
+                    .is_some_and(|c| first.as_const(ctx) == Some(c))
+        };
+        values.all(same).then_some(first)
+    }
+}
+
+impl TransformPass for PhiSimplify {
+    fn name(&self) -> &'static str { "phi-simplify" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let mut changed = false;
+        loop {
+            let phis = func
+                .iter(ctx)
+                .flat_map(|block| block.iter(ctx).take_while(|inst| inst.is_phi(ctx)))
+                .collect::<Vec<_>>();
+
+            let mut changed_once = false;
+            for phi in phis {
+                changed_once |= Self::remove_stale(ctx, &cfg, phi);
+                let Some(value) = Self::unique_value(ctx, phi) else {
+                    continue;
+                };
+                phi.result(ctx).unwrap().replace_all_uses_with(ctx, value);
+                phi.remove(ctx);
+                changed_once = true;
+            }
+            if !changed_once {
+                break;
+            }
+            changed = true;
+        }
+
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, IntBinaryOp, Ty};
+
+    #[test]
+    fn test_phi_simplify() {
+        // entry: br c, a, b
+        // a:     br m
+        // b:     br m
+        // s:     ret 0
+        // m:     p1 = phi [x, a], [x, b]; p2 = phi [1, a], [1, b]
+        //        p3 = phi [x, a], [y, b], [z, s]; br h
+        // h:     p4 = phi [y, m], [p4, h]; br c, h, exit
+        // exit:  ret p1 + p2 + p3 + p4
+        let mut ctx = Context::default();
+        let i1 = Ty::i1(&mut ctx);
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let c = func.add_param(&mut ctx, i1);
+        let [x, y, z] = [(); 3].map(|_| func.add_param(&mut ctx, i32));
+        let [entry, a, b, s, m, h, exit] = [(); 7].map(|_| Block::new(&mut ctx));
+        for block in [entry, a, b, s, m, h, exit] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let push = |ctx: &mut Context, block: Block, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst
+        };
+        let phi = |ctx: &mut Context, block: Block, incomings: &[(Block, Value)]| {
+            let phi = Inst::phi(ctx, i32);
+            for &(pred, value) in incomings {
+                phi.insert_incoming(ctx, pred, value);
+            }
+            push(ctx, block, phi)
+        };
+
+        let br = Inst::cond_br(&mut ctx, c, a, b);
+        push(&mut ctx, entry, br);
+        for block in [a, b] {
+            let br = Inst::br(&mut ctx, m);
+            push(&mut ctx, block, br);
+        }
+        let zero = Value::i32(&mut ctx, 0);
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        push(&mut ctx, s, ret);
+
+        let [one_a, one_b] = [(); 2].map(|_| Value::i32(&mut ctx, 1));
+        let p1 = phi(&mut ctx, m, &[(a, x), (b, x)]);
+        let p2 = phi(&mut ctx, m, &[(a, one_a), (b, one_b)]);
+        let p3 = phi(&mut ctx, m, &[(a, x), (b, y), (s, z)]);
+        let br = Inst::br(&mut ctx, h);
+        push(&mut ctx, m, br);
+        let p4 = phi(&mut ctx, h, &[(m, y)]);
+        let p4_value = p4.result(&ctx).unwrap();
+        p4.insert_incoming(&mut ctx, h, p4_value);
+        let br = Inst::cond_br(&mut ctx, c, h, exit);
+        push(&mut ctx, h, br);
+
+        let values = [p1, p2, p3, p4].map(|phi| phi.result(&ctx).unwrap());
+        let adds = values.map(|value| {
+            let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, value, zero);
+            push(&mut ctx, exit, add)
+        });
+        let ret = Inst::ret(&mut ctx, None);
+        push(&mut ctx, exit, ret);
+
+        let mut am = AnalysisManager::default();
+        assert!(PhiSimplify.run_on_func(&mut ctx, func, &mut am));
+        let used = adds.map(|add| add.operand(&ctx, 0));
+        assert_eq!(used[0], x);
+        assert_eq!(used[1].as_const_int(&ctx), Some(1));
+        assert_eq!(used[2], values[2]);
+        assert_eq!(used[3], y);
+        let incomings = p3.incoming_iter(&ctx).map(|(pred, _)| pred);
+        assert_eq!(incomings.collect::<HashSet<_>>(), HashSet::from([a, b]));
+
+        assert!(!PhiSimplify.run_on_func(&mut ctx, func, &mut am));
+    }
+}
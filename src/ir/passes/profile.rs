@@ -0,0 +1,464 @@
+//! Profiling instrumentation, and the profiles it collects.
+//!
+//! Each defined function is given a counter of its entries, and each
+//! conditional branch two counters, of its executions and of the times its
+//! first successor is taken, in the global [`PROFILE_COUNTS`]. `main` calls
+//! [`PROFILE_DUMP`] of the runtime before returning, which writes the profile
+//! to the file named by `SYSY_PROFILE`, or `sysy.profdata`, in the text format
+//! of [`Profile`]:
+//!
+//! ```text
+//! fib 177
+//!   0 177 88
+//! main 1
+//!   0 11 10
+//!   1 10 3
+//! ```
+//!
+//! i.e., a function as `name entries`, followed by its branches as `index
+//! executions taken`, numbered in the order of the blocks. The numbering is the
+//! one of the IR before the optimizations, where the pass runs, so a profile
+//! applies to the same source compiled by the same compiler; the branches of
+//! `switch` are not counted.
+//!
+//! [`ProfileUse`] annotates the IR with the counts of a profile, at the same
+//! point, for the [branch probabilities](crate::ir::analysis::BranchProbs) and
+//! the [cost model](crate::ir::analysis::CostModel) of the optimizations.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use thiserror::Error;
+
+use super::coverage::increment;
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, CastOp, ConstantValue, Context, Func, Global, Inst, InstKind, Ty, Value};
+
+/// The global of the counters, for each function its entries, then the
+/// executions and the taken ones of each branch.
+pub const PROFILE_COUNTS: &str = "__profile_counts";
+
+/// The global of the numbers of the branches of the functions.
+pub const PROFILE_LAYOUT: &str = "__profile_layout";
+
+/// The global of the names of the functions, each ended by a null byte.
+pub const PROFILE_NAMES: &str = "__profile_names";
+
+/// The function of the runtime dumping the profile,
+/// `_sysy_profile_dump(n, names, layout, counts)` for the `n` functions.
+pub const PROFILE_DUMP: &str = "_sysy_profile_dump";
+
+/// The counts of a conditional branch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCounts {
+    /// The times the branch is executed.
+    pub executed: u64,
+    /// The times its first successor is taken.
+    pub taken: u64,
+}
+
+/// The counts of a function.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FuncProfile {
+    /// The times the function is entered.
+    pub entries: u64,
+    /// The counts of its conditional branches, in the order of the blocks.
+    pub branches: Vec<BranchCounts>,
+}
+
+/// A profile of a program, by the names of the functions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub funcs: BTreeMap<String, FuncProfile>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ProfileError {
+    #[error("line {0} of the profile is neither `name entries` nor `index executions taken`")]
+    Malformed(usize),
+    #[error("line {0} of the profile counts a branch out of order, or of no function")]
+    UnexpectedBranch(usize),
+}
+
+impl Profile {
+    /// Parse a profile in the text format.
+    pub fn parse(text: &str) -> Result<Self, ProfileError> {
+        let mut funcs: Vec<(String, FuncProfile)> = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let num = |field: &str| {
+                field
+                    .parse::<u64>()
+                    .map_err(|_| ProfileError::Malformed(idx + 1))
+            };
+            let indented = line.starts_with(char::is_whitespace);
+            match fields[..] {
+                [] => {}
+                [name, entries] if !indented => {
+                    let func = FuncProfile {
+                        entries: num(entries)?,
+                        branches: Vec::new(),
+                    };
+                    funcs.push((name.to_string(), func));
+                }
+                [index, executed, taken] if indented => {
+                    let (_, func) = funcs
+                        .last_mut()
+                        .ok_or(ProfileError::UnexpectedBranch(idx + 1))?;
+                    if num(index)? != func.branches.len() as u64 {
+                        return Err(ProfileError::UnexpectedBranch(idx + 1));
+                    }
+                    func.branches.push(BranchCounts {
+                        executed: num(executed)?,
+                        taken: num(taken)?,
+                    });
+                }
+                _ => return Err(ProfileError::Malformed(idx + 1)),
+            }
+        }
+        Ok(Profile {
+            funcs: funcs.into_iter().collect(),
+        })
+    }
+
+    /// Get the profile of a function.
+    pub fn func(&self, name: &str) -> Option<&FuncProfile> { self.funcs.get(name) }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, func) in self.funcs.iter() {
+            writeln!(f, "{} {}", name, func.entries)?;
+            for (idx, branch) in func.branches.iter().enumerate() {
+                writeln!(f, "  {} {} {}", idx, branch.executed, branch.taken)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Get the blocks of a function ending with a conditional branch, in the order
+/// of the profiles.
+pub fn profiled_branches(ctx: &Context, func: Func) -> Vec<Block> {
+    func.iter(ctx)
+        .filter(|block| {
+            block
+                .tail(ctx)
+                .is_some_and(|tail| matches!(tail.kind(ctx), InstKind::CondBr))
+        })
+        .collect()
+}
+
+/// The annotation of the functions with the entry counts of a profile, see
+/// [`Func::entry_count`], and of their branches with the times each successor
+/// is taken, see [`Inst::branch_weights`].
+///
+/// A function whose branches are not the ones of the profile, i.e., the source
+/// or the compiler changed since, is left unannotated.
+pub struct ProfileUse {
+    profile: Profile,
+}
+
+impl ProfileUse {
+    pub fn new(profile: Profile) -> Self { Self { profile } }
+}
+
+impl TransformPass for ProfileUse {
+    fn name(&self) -> &'static str { "profile-use" }
+
+    fn run(&mut self, ctx: &mut Context, _: &mut AnalysisManager) -> bool {
+        let funcs = ctx.funcs().collect::<Vec<_>>();
+        let mut changed = false;
+        for func in funcs {
+            let Some(profile) = self.profile.func(func.name(ctx).as_str()) else {
+                continue;
+            };
+            let blocks = profiled_branches(ctx, func);
+            if func.is_declaration(ctx) || blocks.len() != profile.branches.len() {
+                continue;
+            }
+            func.set_entry_count(ctx, Some(profile.entries));
+            for (block, counts) in blocks.into_iter().zip(profile.branches.iter()) {
+                let taken = counts.taken.min(counts.executed);
+                let weights = [taken, counts.executed - taken];
+                block
+                    .tail(ctx)
+                    .unwrap()
+                    .set_branch_weights(ctx, Some(weights));
+            }
+            changed = true;
+        }
+        changed
+    }
+}
+
+/// The instrumentation of the function entries and the branches with
+/// counters, see the [module-level documentation](self).
+#[derive(Default)]
+pub struct ProfileGenerate;
+
+impl ProfileGenerate {
+    /// Get the declaration of the dump of the runtime, adding it if missing.
+    fn dump_decl(ctx: &mut Context) -> Func {
+        if let Some(func) = ctx.func_by_name(PROFILE_DUMP) {
+            return func;
+        }
+        let void = Ty::void(ctx);
+        let ptr = Ty::ptr(ctx);
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, PROFILE_DUMP.to_string(), void);
+        for ty in [i32, ptr, ptr, ptr] {
+            func.add_param(ctx, ty);
+        }
+        func
+    }
+
+    /// Create a constant global array of `i32` or `i8`.
+    fn const_array(ctx: &mut Context, name: &str, elem: Ty, elems: Vec<ConstantValue>) -> Ty {
+        let ty = Ty::array(ctx, elem, elems.len());
+        let global = Global::new(ctx, name.to_string(), ConstantValue::Array { ty, elems });
+        global.set_const(ctx, true);
+        ty
+    }
+}
+
+impl TransformPass for ProfileGenerate {
+    fn name(&self) -> &'static str { "profile-generate" }
+
+    fn run(&mut self, ctx: &mut Context, _: &mut AnalysisManager) -> bool {
+        if ctx.global_by_name(PROFILE_COUNTS).is_some() {
+            // already instrumented
+            return false;
+        }
+        let funcs = ctx
+            .funcs()
+            .filter(|func| !func.is_declaration(ctx))
+            .map(|func| (func, profiled_branches(ctx, func)))
+            .collect::<Vec<_>>();
+        if funcs.is_empty() {
+            return false;
+        }
+
+        let i32 = Ty::i32(ctx);
+        let i8 = Ty::i8(ctx);
+        let num_counters = funcs
+            .iter()
+            .map(|(_, branches)| 1 + 2 * branches.len())
+            .sum();
+        let counts_ty = Ty::array(ctx, i32, num_counters);
+        let counts = Global::new(
+            ctx,
+            PROFILE_COUNTS.to_string(),
+            ConstantValue::AggregateZero { ty: counts_ty },
+        );
+        counts.set_align(ctx, 4);
+
+        let mut counter = 0;
+        for (func, branches) in funcs.iter() {
+            let entry = func.head(ctx).unwrap();
+            let first = entry.iter(ctx).find(|inst| !inst.is_phi(ctx)).unwrap();
+            let one = Value::i32(ctx, 1);
+            for inst in increment(ctx, PROFILE_COUNTS, counts_ty, counter, one) {
+                first.insert_before(ctx, inst).unwrap();
+            }
+            counter += 1;
+
+            for block in branches {
+                let br = block.tail(ctx).unwrap();
+                // the integer comparisons give an `i32`, the others an `i1`
+                let cond = br.operand(ctx, 0);
+                let taken = match cond.ty(ctx) == i32 {
+                    true => cond,
+                    false => {
+                        let zext = Inst::cast(ctx, CastOp::Zext, cond, i32);
+                        br.insert_before(ctx, zext).unwrap();
+                        zext.result(ctx).unwrap()
+                    }
+                };
+                let one = Value::i32(ctx, 1);
+                for (idx, amount) in [(counter, one), (counter + 1, taken)] {
+                    for inst in increment(ctx, PROFILE_COUNTS, counts_ty, idx, amount) {
+                        br.insert_before(ctx, inst).unwrap();
+                    }
+                }
+                counter += 2;
+            }
+        }
+
+        let layout = funcs
+            .iter()
+            .map(|(_, branches)| ConstantValue::i32(ctx, branches.len() as i32))
+            .collect();
+        let layout_ty = Self::const_array(ctx, PROFILE_LAYOUT, i32, layout);
+        let names = funcs
+            .iter()
+            .flat_map(|(func, _)| {
+                func.name(ctx)
+                    .as_str()
+                    .bytes()
+                    .chain([0])
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let names = names
+            .into_iter()
+            .map(|byte| ConstantValue::i8(ctx, byte as i8))
+            .collect();
+        let names_ty = Self::const_array(ctx, PROFILE_NAMES, i8, names);
+
+        let Some(main) = ctx
+            .func_by_name("main")
+            .filter(|main| !main.is_declaration(ctx))
+        else {
+            return true;
+        };
+        let rets = main
+            .iter(ctx)
+            .filter_map(|block| block.tail(ctx))
+            .filter(|inst| matches!(inst.kind(ctx), InstKind::Ret))
+            .collect::<Vec<_>>();
+        let dump = Self::dump_decl(ctx);
+        for ret in rets {
+            let args = vec![
+                Value::i32(ctx, funcs.len() as i32),
+                Value::global_ref(ctx, PROFILE_NAMES.to_string(), names_ty),
+                Value::global_ref(ctx, PROFILE_LAYOUT.to_string(), layout_ty),
+                Value::global_ref(ctx, PROFILE_COUNTS.to_string(), counts_ty),
+            ];
+            let call = Inst::call(ctx, dump, args);
+            ret.insert_before(ctx, call).unwrap();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::analysis::BranchProbs;
+    use crate::ir::interp::Interpreter;
+    use crate::ir::{IntBinaryOp, IntCmpCond};
+
+    /// Build `int f(int x) { if (x < 0) return 1; return 0; }` and `int main()
+    /// { return f(getint()) + f(0); }`, and get `f` with its branch.
+    fn build(ctx: &mut Context) -> (Func, Inst) {
+        let i32 = Ty::i32(ctx);
+        let getint = Func::new(ctx, "getint".to_string(), i32);
+        let f = Func::new(ctx, "f".to_string(), i32);
+        let x = f.add_param(ctx, i32);
+        let main = Func::new(ctx, "main".to_string(), i32);
+        let [zero, one] = [0, 1].map(|v| Value::i32(ctx, v));
+
+        let [entry, neg, pos] = [(); 3].map(|_| Block::new(ctx));
+        for block in [entry, neg, pos] {
+            f.push_back(ctx, block).unwrap();
+        }
+        let lt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let cmp = Inst::ibinary(ctx, lt, x, zero);
+        let cond = cmp.result(ctx).unwrap();
+        let br = Inst::cond_br(ctx, cond, neg, pos);
+        entry.push_back(ctx, cmp).unwrap();
+        entry.push_back(ctx, br).unwrap();
+        let ret = Inst::ret(ctx, Some(one));
+        neg.push_back(ctx, ret).unwrap();
+        let ret = Inst::ret(ctx, Some(zero));
+        pos.push_back(ctx, ret).unwrap();
+
+        let block = Block::new(ctx);
+        main.push_back(ctx, block).unwrap();
+        let input = Inst::call(ctx, getint, Vec::new());
+        let a = input.result(ctx).unwrap();
+        let call1 = Inst::call(ctx, f, vec![a]);
+        let call2 = Inst::call(ctx, f, vec![zero]);
+        let (r1, r2) = (call1.result(ctx).unwrap(), call2.result(ctx).unwrap());
+        let add = Inst::ibinary(ctx, IntBinaryOp::Add, r1, r2);
+        let sum = add.result(ctx).unwrap();
+        let ret = Inst::ret(ctx, Some(sum));
+        for inst in [input, call1, call2, add, ret] {
+            block.push_back(ctx, inst).unwrap();
+        }
+        (f, br)
+    }
+
+    #[test]
+    fn test_profile_generate() {
+        let mut ctx = Context::default();
+        build(&mut ctx);
+
+        let mut am = AnalysisManager::default();
+        assert!(ProfileGenerate.run(&mut ctx, &mut am));
+        assert!(
+            !ProfileGenerate.run(&mut ctx, &mut am),
+            "instrumented twice"
+        );
+        ctx.verify().unwrap();
+
+        let mut interp = Interpreter::new(&ctx, "-3".as_bytes(), Vec::new());
+        assert_eq!(interp.run_main().unwrap(), 1);
+        let dump = interp.profile().unwrap();
+        assert_eq!(dump, "f 2\n  0 2 1\nmain 1\n");
+
+        let profile = Profile::parse(dump).unwrap();
+        let expected = FuncProfile {
+            entries: 2,
+            branches: vec![BranchCounts {
+                executed: 2,
+                taken: 1,
+            }],
+        };
+        assert_eq!(profile.func("f"), Some(&expected));
+        assert_eq!(profile.func("main").unwrap().entries, 1);
+        assert_eq!(Profile::parse(&profile.to_string()), Ok(profile));
+
+        assert_eq!(
+            Profile::parse("f 1\n  0 1"),
+            Err(ProfileError::Malformed(2))
+        );
+        assert_eq!(Profile::parse("f x"), Err(ProfileError::Malformed(1)));
+        assert_eq!(
+            Profile::parse("  0 1 1"),
+            Err(ProfileError::UnexpectedBranch(1))
+        );
+        assert_eq!(
+            Profile::parse("f 1\n  1 1 1"),
+            Err(ProfileError::UnexpectedBranch(2))
+        );
+    }
+
+    #[test]
+    fn test_profile_use() {
+        let mut ctx = Context::default();
+        let (f, br) = build(&mut ctx);
+        let main = ctx.func_by_name("main").unwrap();
+        let mut am = AnalysisManager::default();
+
+        // a profile of other branches
+        let stale = Profile::parse("f 2\nmain 1\n").unwrap();
+        assert!(ProfileUse::new(stale).run(&mut ctx, &mut am));
+        assert_eq!(f.entry_count(&ctx), None);
+        assert_eq!(br.branch_weights(&ctx), None);
+        assert_eq!(main.entry_count(&ctx), Some(1));
+
+        let profile = Profile::parse("f 3000\n  0 3000 300\nmain 1\n").unwrap();
+        assert!(ProfileUse::new(profile).run(&mut ctx, &mut am));
+        assert_eq!(f.entry_count(&ctx), Some(3000));
+        assert_eq!(br.branch_weights(&ctx), Some([300, 2700]));
+
+        let [neg, pos] = [0, 1].map(|idx| br.successor(&ctx, idx));
+        let entry = br.container(&ctx).unwrap();
+        let probs = am.get::<BranchProbs>(&ctx, f);
+        assert_eq!(probs.prob(entry, neg), 0.1);
+        assert_eq!(probs.prob(entry, pos), 0.9);
+
+        let profile = Profile::parse("f 3000\n  0 3000 3000\nmain 1\n").unwrap();
+        assert!(ProfileUse::new(profile).run(&mut ctx, &mut am));
+        am.invalidate(f);
+        let probs = am.get::<BranchProbs>(&ctx, f);
+        // `x < 0` is guessed unlikely, but always true in the profile
+        assert!(probs.is_likely(entry, neg));
+        assert!(probs.prob(entry, pos) > 0.0);
+    }
+}
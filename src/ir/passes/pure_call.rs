@@ -0,0 +1,222 @@
+//! Elimination of redundant and dead calls to side-effect-free functions.
+//!
+//! The memory effects of the callees decide what can be done with a call:
+//!
+//! - A call to a `readnone` function only depends on its arguments, so it is
+//!   replaced by an earlier call with the same arguments in a dominating
+//!   position.
+//! - A call to a `readonly` function also depends on the memory, so it is only
+//!   replaced by an earlier identical call in the same block, with no possible
+//!   write to memory in between.
+//! - A call to either kind of function whose result is unused is removed.
+//!
+//! Constant arguments are compared by value, as the constants are not uniqued.
+
+use std::collections::HashMap;
+
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::ir::analysis::DomTree;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{ConstantValue, Context, Func, Inst, InstKind, Usable, Value};
+
+/// The deduplication and removal of calls to side-effect-free functions.
+#[derive(Clone, Default)]
+pub struct PureCallElim;
+
+/// An argument of a call, compared by value if it is a constant.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ArgKey {
+    Value(Value),
+    Const(ConstantValue),
+}
+
+/// The callee and the arguments of a call.
+type CallKey = (String, Vec<ArgKey>);
+
+impl PureCallElim {
+    /// Get the key of a call to compare it with others.
+    fn key(ctx: &Context, call: Inst) -> CallKey {
+        let args = call
+            .operand_iter(ctx)
+            .skip(1)
+            .map(|arg| match arg.as_const(ctx) {
+                Some(value) => ArgKey::Const(value.clone()),
+                None => ArgKey::Value(arg),
+            })
+            .collect();
+        (call.callee(ctx).to_string(), args)
+    }
+
+    /// Replace the calls that repeat an earlier one.
+    fn dedup(ctx: &mut Context, domtree: &DomTree) -> bool {
+        let mut changed = false;
+        // The earlier `readnone` calls, in the preorder of the dominator tree.
+        let mut pure_calls: HashMap<CallKey, Vec<Inst>> = HashMap::new();
+        for block in domtree.preorder().collect::<Vec<_>>() {
+            // The earlier `readonly` calls in the block, since the last write.
+            let mut readonly_calls: HashMap<CallKey, Inst> = HashMap::new();
+            for inst in block.iter(ctx).collect::<Vec<_>>() {
+                if inst.may_write_memory(ctx) {
+                    readonly_calls.clear();
+                    continue;
+                }
+                if !matches!(inst.kind(ctx), InstKind::Call) {
+                    continue;
+                }
+                let Some(result) = inst.result(ctx) else {
+                    continue;
+                };
+                let key = Self::key(ctx, inst);
+                let earlier = if inst.is_pure(ctx) {
+                    let calls = pure_calls.entry(key).or_default();
+                    let earlier = calls
+                        .iter()
+                        .copied()
+                        .find(|call| domtree.dominates(call.container(ctx).unwrap(), block));
+                    if earlier.is_none() {
+                        calls.push(inst);
+                    }
+                    earlier
+                } else {
+                    let earlier = readonly_calls.get(&key).copied();
+                    readonly_calls.entry(key).or_insert(inst);
+                    earlier
+                };
+                if let Some(earlier) = earlier {
+                    result.replace_all_uses_with(ctx, earlier.result(ctx).unwrap());
+                    inst.remove(ctx);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Remove the calls without side effects whose results are unused.
+    fn remove_dead(ctx: &mut Context, func: Func) -> bool {
+        let mut changed = false;
+        loop {
+            let dead = func
+                .iter(ctx)
+                .flat_map(|block| block.iter(ctx))
+                .filter(|&inst| {
+                    matches!(inst.kind(ctx), InstKind::Call)
+                        && !inst.may_have_side_effects(ctx)
+                        && inst
+                            .result(ctx)
+                            .is_none_or(|result| result.users(ctx).into_iter().next().is_none())
+                })
+                .collect::<Vec<_>>();
+            if dead.is_empty() {
+                return changed;
+            }
+            for inst in dead {
+                inst.remove(ctx);
+            }
+            changed = true;
+        }
+    }
+}
+
+impl TransformPass for PureCallElim {
+    fn name(&self) -> &'static str { "pure-call-elim" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let domtree = am.get::<DomTree>(ctx, func);
+        let mut changed = Self::dedup(ctx, &domtree);
+        changed |= Self::remove_dead(ctx, func);
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, IntBinaryOp, MemoryEffect, Ty};
+
+    #[test]
+    fn test_pure_call_elim() {
+        // entry: a = sq(x); b = sq(5); r1 = get(p); br next
+        // next:  c = sq(x); d = sq(5); e = sq(y); r2 = get(p); store 1, p
+        //        r3 = get(p); sq(sq(y)); ret a + b + c + d + e + r1 + r2 + r3
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let ptr = Ty::ptr(&mut ctx);
+        let sq = Func::new(&mut ctx, "sq".to_string(), i32);
+        sq.add_param(&mut ctx, i32);
+        sq.set_memory_effect(&mut ctx, MemoryEffect::None);
+        let get = Func::new(&mut ctx, "get".to_string(), i32);
+        get.add_param(&mut ctx, ptr);
+        get.set_memory_effect(&mut ctx, MemoryEffect::ReadOnly);
+
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = func.add_param(&mut ctx, i32);
+        let y = func.add_param(&mut ctx, i32);
+        let p = func.add_param(&mut ctx, ptr);
+        let [entry, next] = [(); 2].map(|_| Block::new(&mut ctx));
+        for block in [entry, next] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let call = |ctx: &mut Context, block: Block, callee: Func, arg: Value| {
+            let call = Inst::call(ctx, callee, vec![arg]);
+            block.push_back(ctx, call).unwrap();
+            call.result(ctx).unwrap()
+        };
+        let five = Value::i32(&mut ctx, 5);
+        let a = call(&mut ctx, entry, sq, x);
+        let b = call(&mut ctx, entry, sq, five);
+        let r1 = call(&mut ctx, entry, get, p);
+        let br = Inst::br(&mut ctx, next);
+        entry.push_back(&mut ctx, br).unwrap();
+
+        let c = call(&mut ctx, next, sq, x);
+        let five = Value::i32(&mut ctx, 5);
+        let d = call(&mut ctx, next, sq, five);
+        let e = call(&mut ctx, next, sq, y);
+        let r2 = call(&mut ctx, next, get, p);
+        let one = Value::i32(&mut ctx, 1);
+        let store = Inst::store(&mut ctx, one, p);
+        next.push_back(&mut ctx, store).unwrap();
+        let r3 = call(&mut ctx, next, get, p);
+        let inner = call(&mut ctx, next, sq, y);
+        call(&mut ctx, next, sq, inner);
+
+        let mut sum = a;
+        let values = [b, c, d, e, r1, r2, r3];
+        let adds = values.map(|value| {
+            let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, sum, value);
+            next.push_back(&mut ctx, add).unwrap();
+            sum = add.result(&ctx).unwrap();
+            add
+        });
+        let ret = Inst::ret(&mut ctx, Some(sum));
+        next.push_back(&mut ctx, ret).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(PureCallElim.run_on_func(&mut ctx, func, &mut am));
+        let used = adds.map(|add| add.operand(&ctx, 1));
+        // c and d repeat a and b, the readonly r2 is in another block than r1,
+        // and r3 follows a store
+        assert_eq!(used[1], a);
+        assert_eq!(used[2], b);
+        assert_eq!(used[3], e);
+        assert_eq!(used[4], r1);
+        assert_eq!(used[5], r2);
+        assert_eq!(used[6], r3);
+
+        // sq(y) in the unused sq(sq(y)) is merged into e, and both calls of the
+        // chain are removed
+        let calls = next
+            .iter(&ctx)
+            .filter(|inst| matches!(inst.kind(&ctx), InstKind::Call))
+            .count();
+        assert_eq!(calls, 3);
+
+        assert!(!PureCallElim.run_on_func(&mut ctx, func, &mut am));
+    }
+}
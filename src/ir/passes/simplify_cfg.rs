@@ -0,0 +1,371 @@
+//! CFG simplification.
+//!
+//! The control flow is simplified until nothing changes:
+//!
+//! - A conditional branch or a switch on a constant, or with the same
+//!   destination in all directions, becomes an unconditional branch.
+//! - The blocks unreachable from the entry are removed.
+//! - A block with a single predecessor only branching to it is merged into the
+//!   predecessor.
+//! - An empty block only branching to another one is bypassed, unless the phis
+//!   of the destination tell the predecessors apart. An empty block entering a
+//!   loop is kept as the preheader.
+//!
+//! Blocks with parameters are left untouched, the pass works on the phi
+//! flavor of SSA.
+
+use std::collections::HashSet;
+
+use crate::infra::linked_list::LinkedListContainer;
+use crate::ir::analysis::Cfg;
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Block, Context, Func, Inst, InstKind, Value};
+
+/// The simplification of the control flow.
+#[derive(Clone, Default)]
+pub struct SimplifyCfg;
+
+impl SimplifyCfg {
+    /// Get the phis at the beginning of a block.
+    fn phis(ctx: &Context, block: Block) -> Vec<Inst> {
+        block
+            .iter(ctx)
+            .take_while(|inst| inst.is_phi(ctx))
+            .collect()
+    }
+
+    /// Drop the incoming values of the phis in `block` from `pred`.
+    fn remove_incoming(ctx: &mut Context, block: Block, pred: Block) {
+        for phi in Self::phis(ctx, block) {
+            if phi.incoming_iter(ctx).any(|(b, _)| b == pred) {
+                phi.remove_incoming(ctx, pred);
+            }
+        }
+    }
+
+    /// Check if two values are the same, comparing constants by value.
+    fn same_value(ctx: &Context, a: Value, b: Value) -> bool {
+        a == b || a.as_const(ctx).is_some_and(|c| b.as_const(ctx) == Some(c))
+    }
+
+    /// Get the only destination a terminator can branch to, if known.
+    fn known_dest(ctx: &Context, term: Inst) -> Option<Block> {
+        let cond = match term.kind(ctx) {
+            InstKind::CondBr | InstKind::Switch { .. } => term.operand(ctx, 0).as_const_int(ctx),
+            _ => return None,
+        };
+        let dest = match (term.kind(ctx), cond) {
+            (InstKind::CondBr, Some(cond)) => term.successor(ctx, if cond != 0 { 0 } else { 1 }),
+            (InstKind::Switch { cases }, Some(cond)) => {
+                let idx = cases.iter().position(|&case| case == cond);
+                term.successor(ctx, idx.map_or(0, |idx| idx + 1))
+            }
+            _ => term.successor(ctx, 0),
+        };
+        term.successor_iter(ctx)
+            .all(|succ| succ == dest || cond.is_some())
+            .then_some(dest)
+    }
+
+    /// Replace the branches with a known destination by unconditional ones.
+    fn fold_branches(ctx: &mut Context, func: Func) -> bool {
+        let mut changed = false;
+        for block in func.iter(ctx).collect::<Vec<_>>() {
+            let Some(term) = block.tail(ctx) else {
+                continue;
+            };
+            let Some(dest) = Self::known_dest(ctx, term) else {
+                continue;
+            };
+            let succs = term.successor_iter(ctx).collect::<HashSet<_>>();
+            if succs.iter().any(|succ| !succ.params(ctx).is_empty()) {
+                continue;
+            }
+            for succ in succs {
+                if succ != dest {
+                    Self::remove_incoming(ctx, succ, block);
+                }
+            }
+            let br = Inst::br(ctx, dest);
+            br.set_loc(ctx, term.loc(ctx));
+            term.remove(ctx);
+            block.push_back(ctx, br).unwrap();
+            changed = true;
+        }
+        changed
+    }
+
+    /// Remove the blocks unreachable from the entry.
+    fn remove_unreachable(ctx: &mut Context, cfg: &Cfg) -> bool {
+        let dead = cfg
+            .blocks()
+            .iter()
+            .copied()
+            .filter(|&block| !cfg.is_reachable(block))
+            .collect::<Vec<_>>();
+        for &block in dead.iter() {
+            for &succ in cfg.succs(block) {
+                if cfg.is_reachable(succ) {
+                    Self::remove_incoming(ctx, succ, block);
+                }
+            }
+        }
+
+        // The values may still be used in other unreachable blocks, so they
+        // are replaced before the instructions are removed.
+        let insts = dead
+            .iter()
+            .flat_map(|block| block.iter(ctx))
+            .collect::<Vec<_>>();
+        for &inst in insts.iter() {
+            if let Some(result) = inst.result(ctx) {
+                let undef = Value::undef(ctx, result.ty(ctx));
+                result.replace_all_uses_with(ctx, undef);
+            }
+        }
+        for inst in insts {
+            inst.remove(ctx);
+        }
+        for &block in dead.iter() {
+            block.remove(ctx);
+        }
+        !dead.is_empty()
+    }
+
+    /// Merge the blocks into their single predecessors only branching to
+    /// them.
+    fn merge_blocks(ctx: &mut Context, cfg: &Cfg) -> bool {
+        // The blocks whose edges are changed, for which the CFG is stale.
+        let mut touched = HashSet::new();
+        for &block in cfg.blocks() {
+            if Some(block) == cfg.entry() || !block.params(ctx).is_empty() {
+                continue;
+            }
+            let &[pred] = cfg.preds(block) else {
+                continue;
+            };
+            if pred == block || cfg.succs(pred) != [block] {
+                continue;
+            }
+            if touched.contains(&pred) || touched.contains(&block) {
+                continue;
+            }
+
+            for phi in Self::phis(ctx, block) {
+                let value = phi.incoming(ctx, pred);
+                phi.result(ctx).unwrap().replace_all_uses_with(ctx, value);
+                phi.remove(ctx);
+            }
+            pred.tail(ctx).unwrap().remove(ctx);
+            pred.append(ctx, block);
+            for &succ in cfg.succs(block) {
+                for phi in Self::phis(ctx, succ) {
+                    if phi.incoming_iter(ctx).any(|(b, _)| b == block) {
+                        let value = phi.incoming(ctx, block);
+                        phi.remove_incoming(ctx, block);
+                        phi.insert_incoming(ctx, pred, value);
+                    }
+                }
+                touched.insert(succ);
+            }
+            block.remove(ctx);
+            touched.insert(pred);
+            touched.insert(block);
+        }
+        !touched.is_empty()
+    }
+
+    /// Redirect the predecessors of the empty blocks to their destinations.
+    ///
+    /// The bypassed blocks become unreachable if all the predecessors are
+    /// redirected, and are removed in the next round.
+    fn bypass_empty(ctx: &mut Context, cfg: &Cfg) -> bool {
+        let mut changed = false;
+        let mut touched = HashSet::new();
+        for &block in cfg.blocks() {
+            if Some(block) == cfg.entry() || !cfg.is_reachable(block) {
+                continue;
+            }
+            let Some(term) = block.head(ctx) else {
+                continue;
+            };
+            if block.tail(ctx) != Some(term) || !matches!(term.kind(ctx), InstKind::Br) {
+                continue;
+            }
+            let dest = term.successor(ctx, 0);
+            if dest == block || !dest.params(ctx).is_empty() {
+                continue;
+            }
+            let enters_loop = cfg
+                .preds(dest)
+                .iter()
+                .any(|&pred| cfg.is_retreating_edge(pred, dest));
+            if enters_loop || touched.contains(&block) || touched.contains(&dest) {
+                continue;
+            }
+
+            let phis = Self::phis(ctx, dest);
+            for &pred in cfg.preds(block) {
+                if touched.contains(&pred) || pred == block {
+                    continue;
+                }
+                // A predecessor of both must get the same values either way.
+                let is_dest_pred = cfg.preds(dest).contains(&pred);
+                let conflicts = phis.iter().any(|&phi| {
+                    is_dest_pred
+                        && !Self::same_value(ctx, phi.incoming(ctx, pred), phi.incoming(ctx, block))
+                });
+                if conflicts {
+                    continue;
+                }
+
+                let pred_term = pred.tail(ctx).unwrap();
+                let indices = pred_term
+                    .successor_iter(ctx)
+                    .enumerate()
+                    .filter(|&(_, succ)| succ == block)
+                    .map(|(idx, _)| idx)
+                    .collect::<Vec<_>>();
+                for idx in indices {
+                    pred_term.set_successor(ctx, idx, dest);
+                }
+                if !is_dest_pred {
+                    for &phi in phis.iter() {
+                        let value = phi.incoming(ctx, block);
+                        phi.insert_incoming(ctx, pred, value);
+                    }
+                }
+                touched.insert(pred);
+                changed = true;
+            }
+            touched.insert(block);
+            touched.insert(dest);
+        }
+        changed
+    }
+}
+
+impl TransformPass for SimplifyCfg {
+    fn name(&self) -> &'static str { "simplifycfg" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        if func.is_declaration(ctx) {
+            return false;
+        }
+        let mut changed = false;
+        loop {
+            let mut changed_once = Self::fold_branches(ctx, func);
+            changed_once |= Self::remove_unreachable(ctx, &Cfg::new(ctx, func));
+            changed_once |= Self::merge_blocks(ctx, &Cfg::new(ctx, func));
+            changed_once |= Self::bypass_empty(ctx, &Cfg::new(ctx, func));
+            if !changed_once {
+                break;
+            }
+            changed = true;
+        }
+
+        if changed {
+            am.invalidate(func);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{IntBinaryOp, Ty};
+
+    #[test]
+    fn test_simplify_cfg() {
+        // entry: br true, a, b
+        // a: br empty
+        // b: br join
+        // empty: br join
+        // join: x = phi [a: 1, b: 2]; y = x + 1; br tail
+        // tail: ret y
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let [entry, a, b, empty, join, tail] = [(); 6].map(|_| Block::new(&mut ctx));
+        for block in [entry, a, b, empty, join, tail] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let cond = Value::i1(&mut ctx, true);
+        let [one, two] = [1, 2].map(|v| Value::i32(&mut ctx, v));
+
+        let br = Inst::cond_br(&mut ctx, cond, a, b);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, empty);
+        a.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, join);
+        b.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, join);
+        empty.push_back(&mut ctx, br).unwrap();
+        let phi = Inst::phi(&mut ctx, i32);
+        phi.insert_incoming(&mut ctx, empty, one);
+        phi.insert_incoming(&mut ctx, b, two);
+        let x = phi.result(&ctx).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, x, one);
+        let br = Inst::br(&mut ctx, tail);
+        for inst in [phi, add, br] {
+            join.push_back(&mut ctx, inst).unwrap();
+        }
+        let y = add.result(&ctx).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(y));
+        tail.push_back(&mut ctx, ret).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(SimplifyCfg.run_on_func(&mut ctx, func, &mut am));
+        // everything is folded into the entry
+        assert_eq!(func.iter(&ctx).collect::<Vec<_>>(), [entry]);
+        let insts = entry.iter(&ctx).collect::<Vec<_>>();
+        assert_eq!(insts, [add, ret]);
+        assert_eq!(add.operand(&ctx, 0), one);
+        assert!(!SimplifyCfg.run_on_func(&mut ctx, func, &mut am));
+    }
+
+    #[test]
+    fn test_bypass_empty() {
+        // entry: br c, empty, join
+        // empty: br join
+        // join: x = phi [entry: 1, empty: 2]; ret x
+        //
+        // `empty` distinguishes the two edges to `join`, so it is kept.
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let i1 = Ty::i1(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let c = func.add_param(&mut ctx, i1);
+        let [entry, empty, join] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, empty, join] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let [one, two] = [1, 2].map(|v| Value::i32(&mut ctx, v));
+        let br = Inst::cond_br(&mut ctx, c, empty, join);
+        entry.push_back(&mut ctx, br).unwrap();
+        let br = Inst::br(&mut ctx, join);
+        empty.push_back(&mut ctx, br).unwrap();
+        let phi = Inst::phi(&mut ctx, i32);
+        phi.insert_incoming(&mut ctx, entry, one);
+        phi.insert_incoming(&mut ctx, empty, two);
+        let x = phi.result(&ctx).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(x));
+        join.push_back(&mut ctx, phi).unwrap();
+        join.push_back(&mut ctx, ret).unwrap();
+
+        let mut am = AnalysisManager::default();
+        assert!(!SimplifyCfg.run_on_func(&mut ctx, func, &mut am));
+
+        // with the same value on both edges, the phi does not care, and the
+        // blocks are merged afterwards
+        phi.remove_incoming(&mut ctx, empty);
+        let another_one = Value::i32(&mut ctx, 1);
+        phi.insert_incoming(&mut ctx, empty, another_one);
+        assert!(SimplifyCfg.run_on_func(&mut ctx, func, &mut am));
+        assert_eq!(func.iter(&ctx).collect::<Vec<_>>(), [entry]);
+        assert_eq!(ret.operand(&ctx, 0), one);
+    }
+}
@@ -0,0 +1,238 @@
+//! Scalar replacement of aggregates.
+//!
+//! A local array is split into one scalar alloca per accessed element if:
+//!
+//! - its address does not escape;
+//! - it is only accessed by loads and stores through `getelementptr`s with
+//!   constant indices;
+//! - the accesses do not partially overlap, and the same element is always
+//!   accessed with the same type;
+//! - it has at most [`Sroa::max_elems`] elements.
+//!
+//! The new allocas are only accessed by direct loads and stores, so they can
+//! be promoted to SSA values afterwards.
+
+use std::collections::BTreeMap;
+
+use crate::infra::linked_list::LinkedListNode;
+use crate::ir::analysis::{decompose_pointer, EscapeInfo, PointerBase};
+use crate::ir::passman::{AnalysisManager, TransformPass};
+use crate::ir::{Context, Func, Inst, InstKind, Ty, Usable, Value};
+
+/// The scalar replacement of small local arrays.
+#[derive(Clone)]
+pub struct Sroa {
+    /// The maximum number of elements of an array to be split.
+    pub max_elems: usize,
+}
+
+impl Default for Sroa {
+    fn default() -> Self { Self { max_elems: 64 } }
+}
+
+/// The accesses to a splittable array.
+struct Accesses {
+    /// The `getelementptr`s derived from the array, in the order of discovery.
+    geps: Vec<Inst>,
+    /// The loads and stores, with the accessed byte offset.
+    accesses: Vec<(Inst, i64)>,
+    /// The type accessed at each byte offset.
+    elems: BTreeMap<i64, Ty>,
+}
+
+impl Sroa {
+    /// Collect the accesses to an array, or `None` if it cannot be split.
+    fn collect(ctx: &Context, alloca: Inst) -> Option<Accesses> {
+        let mut accesses = Accesses {
+            geps: Vec::new(),
+            accesses: Vec::new(),
+            elems: BTreeMap::new(),
+        };
+
+        let mut worklist = vec![alloca.result(ctx).unwrap()];
+        while let Some(ptr) = worklist.pop() {
+            for user in ptr.users(ctx) {
+                let inst = user.inst();
+                let ty = match inst.kind(ctx) {
+                    InstKind::GetElementPtr { .. } if user.idx() == 0 => {
+                        accesses.geps.push(inst);
+                        worklist.push(inst.result(ctx).unwrap());
+                        continue;
+                    }
+                    // Direct accesses to the array are left alone, they are
+                    // rare and would need the first element to be split.
+                    _ if ptr == alloca.result(ctx).unwrap() => return None,
+                    InstKind::Load => inst.result(ctx).unwrap().ty(ctx),
+                    InstKind::Store if user.idx() == 1 => inst.operand(ctx, 0).ty(ctx),
+                    _ => return None,
+                };
+                let (base, offset) = decompose_pointer(ctx, ptr);
+                debug_assert_eq!(base, PointerBase::Alloca(alloca));
+                let offset = offset?;
+                if *accesses.elems.entry(offset).or_insert(ty) != ty {
+                    return None;
+                }
+                accesses.accesses.push((inst, offset));
+            }
+        }
+
+        // All the accesses must be in bounds and disjoint.
+        let InstKind::Alloca { ty } = *alloca.kind(ctx) else {
+            unreachable!()
+        };
+        let size = ty.bytewidth(ctx) as i64;
+        let mut end = 0;
+        for (&offset, ty) in accesses.elems.iter() {
+            if offset < end {
+                return None;
+            }
+            end = offset + ty.bytewidth(ctx) as i64;
+        }
+        if accesses.elems.keys().next().is_some_and(|&offset| offset < 0) || end > size {
+            return None;
+        }
+
+        Some(accesses)
+    }
+
+    /// Split an array into scalars.
+    fn split(ctx: &mut Context, alloca: Inst, accesses: Accesses) {
+        let mut scalars: BTreeMap<i64, Value> = BTreeMap::new();
+        for (&offset, &ty) in accesses.elems.iter() {
+            let scalar = Inst::alloca(ctx, ty);
+            alloca.insert_before(ctx, scalar).unwrap();
+            scalars.insert(offset, scalar.result(ctx).unwrap());
+        }
+
+        for (inst, offset) in accesses.accesses {
+            let idx = match inst.kind(ctx) {
+                InstKind::Load => 0,
+                _ => 1,
+            };
+            inst.set_operand(ctx, idx, scalars[&offset]);
+        }
+        // Users are discovered after the pointers they use.
+        for gep in accesses.geps.into_iter().rev() {
+            gep.remove(ctx);
+        }
+        alloca.remove(ctx);
+    }
+
+    fn is_candidate(&self, ctx: &Context, escape: &EscapeInfo, alloca: Inst) -> bool {
+        let InstKind::Alloca { ty } = *alloca.kind(ctx) else {
+            return false;
+        };
+        let Some(elem_ty) = ty.as_array(ctx).map(|(elem, _)| elem) else {
+            return false;
+        };
+        let mut elem = elem_ty;
+        while let Some((inner, _)) = elem.as_array(ctx) {
+            elem = inner;
+        }
+        let elems = ty.bytewidth(ctx) / elem.bytewidth(ctx).max(1);
+        !escape.escapes(alloca) && elems <= self.max_elems
+    }
+}
+
+impl TransformPass for Sroa {
+    fn name(&self) -> &'static str { "sroa" }
+
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let escape = am.get::<EscapeInfo>(ctx, func);
+        let mut allocas = escape
+            .allocas()
+            .filter(|&alloca| self.is_candidate(ctx, &escape, alloca))
+            .collect::<Vec<_>>();
+        // Keep the order of the new allocas deterministic.
+        allocas.sort();
+
+        let mut changed = false;
+        for alloca in allocas {
+            if let Some(accesses) = Self::collect(ctx, alloca) {
+                Self::split(ctx, alloca, accesses);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::linked_list::LinkedListContainer;
+    use crate::ir::{Block, IntBinaryOp};
+
+    #[test]
+    fn test_sroa() {
+        // a = alloca [2 x [2 x i32]]; b = alloca [4 x i32]
+        // a[0][1] = 1; a[1][0] = 2; x = a[0][1] + a[1][0]
+        // b[i] = x; ret b[1]
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let row = Ty::array(&mut ctx, i32, 2);
+        let matrix = Ty::array(&mut ctx, row, 2);
+        let vector = Ty::array(&mut ctx, i32, 4);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let i = func.add_param(&mut ctx, i32);
+        let block = Block::new(&mut ctx);
+        func.push_back(&mut ctx, block).unwrap();
+        let push = |ctx: &mut Context, inst: Inst| {
+            block.push_back(ctx, inst).unwrap();
+            inst.result(ctx)
+        };
+
+        let a = Inst::alloca(&mut ctx, matrix);
+        let a_ptr = push(&mut ctx, a).unwrap();
+        let b = Inst::alloca(&mut ctx, vector);
+        let b_ptr = push(&mut ctx, b).unwrap();
+        let [zero, one, two] = [0, 1, 2].map(|v| Value::i32(&mut ctx, v));
+
+        let gep = Inst::getelementptr(&mut ctx, matrix, a_ptr, vec![zero, zero]);
+        let a0 = push(&mut ctx, gep).unwrap();
+        let gep = Inst::getelementptr(&mut ctx, row, a0, vec![zero, one]);
+        let a01 = push(&mut ctx, gep).unwrap();
+        let gep = Inst::getelementptr(&mut ctx, matrix, a_ptr, vec![zero, one, zero]);
+        let a10 = push(&mut ctx, gep).unwrap();
+        let store = Inst::store(&mut ctx, one, a01);
+        push(&mut ctx, store);
+        let store = Inst::store(&mut ctx, two, a10);
+        push(&mut ctx, store);
+        let load = Inst::load(&mut ctx, a01, i32);
+        let lhs = push(&mut ctx, load).unwrap();
+        let load = Inst::load(&mut ctx, a10, i32);
+        let rhs = push(&mut ctx, load).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, lhs, rhs);
+        let x = push(&mut ctx, add).unwrap();
+
+        let gep = Inst::getelementptr(&mut ctx, vector, b_ptr, vec![zero, i]);
+        let bi = push(&mut ctx, gep).unwrap();
+        let store = Inst::store(&mut ctx, x, bi);
+        push(&mut ctx, store);
+        let gep = Inst::getelementptr(&mut ctx, vector, b_ptr, vec![zero, one]);
+        let b1 = push(&mut ctx, gep).unwrap();
+        let load = Inst::load(&mut ctx, b1, i32);
+        let y = push(&mut ctx, load).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(y));
+        push(&mut ctx, ret);
+
+        let mut am = AnalysisManager::default();
+        assert!(Sroa::default().run_on_func(&mut ctx, func, &mut am));
+        am.invalidate(func);
+
+        // `a` is split into two scalars, `b` is indexed by a variable
+        let escape = am.get::<EscapeInfo>(&ctx, func);
+        assert_eq!(escape.allocas().count(), 3);
+        assert_eq!(escape.promotable().count(), 2);
+        assert!(escape.allocas().any(|alloca| alloca == b));
+        let geps = block
+            .iter(&ctx)
+            .filter(|inst| matches!(inst.kind(&ctx), InstKind::GetElementPtr { .. }))
+            .count();
+        assert_eq!(geps, 2);
+
+        assert!(!Sroa::default().run_on_func(&mut ctx, func, &mut am));
+    }
+}
@@ -0,0 +1,1053 @@
+//! Pass manager of the IR.
+//!
+//! There are two kinds of passes:
+//!
+//! - [`AnalysisPass`]: computes information about a function without modifying
+//!   it. The results are cached by the [`AnalysisManager`], and shared by all
+//!   the passes until they are invalidated.
+//! - [`TransformPass`]: modifies the module, and reports whether anything is
+//!   changed, so that the stale analyses can be invalidated.
+//!
+//! Transform passes are registered in the [`PassManager`] by name, and the
+//! pipeline can be built from a list of names, e.g., from the command line.
+//! The passes transforming each function on its own can be run on the
+//! functions in parallel, see [`PassManager::set_parallel`].
+//! For debugging, the IR can be dumped before or after each pass with
+//! [`PrintOptions`], the time spent in each pass can be recorded with
+//! [`PassManager::set_time_passes`], the IR can be checked by the verifier
+//! after each pass with [`PassManager::set_verify_each`], and the graphs of
+//! the functions can be written around a pass with [`GraphOptions`].
+
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use thiserror::Error;
+
+use super::analysis::{
+    BlockFreqs,
+    BranchProbs,
+    Cfg,
+    DomTree,
+    EscapeInfo,
+    InductionVars,
+    LoopInfo,
+    MemDep,
+    PostDomTree,
+    ValueRanges,
+};
+use super::{Context, Func, GraphOptions, VerifyError};
+use crate::infra::timing;
+
+/// Errors that can occur when building or running a pipeline.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PassError {
+    #[error("unknown pass `{0}`")]
+    UnknownPass(String),
+    #[error("invalid IR before the passes: {0}")]
+    InvalidInput(Box<VerifyError>),
+    #[error("invalid IR after pass `{0}`: {1}")]
+    BrokenBy(&'static str, Box<VerifyError>),
+    #[error("cannot write `{}`: {1}", .0.display())]
+    WriteGraph(PathBuf, String),
+}
+
+/// An analysis of a function.
+///
+/// The analysis is implemented by its result type, so it is queried by the
+/// type, e.g., `am.get::<DomTree>(ctx, func)`.
+pub trait AnalysisPass: Sized + 'static {
+    /// The name of the analysis, for debugging.
+    const NAME: &'static str;
+
+    /// Compute the analysis of a function.
+    ///
+    /// Other analyses this one depends on can be fetched from the manager.
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self;
+}
+
+/// A transformation of the module.
+pub trait TransformPass {
+    /// The name of the pass, used for registration and debugging.
+    fn name(&self) -> &'static str;
+
+    /// Transform a function.
+    ///
+    /// # Returns
+    ///
+    /// Whether the function is changed.
+    fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+        let _ = (ctx, func, am);
+        false
+    }
+
+    /// Create a pass to transform a function on another thread, or `None` if
+    /// the pass cannot, e.g., if it looks into the bodies of other functions.
+    ///
+    /// [`TransformPass::run_on_func`] of the new pass is only given the
+    /// function, the declarations of the functions it calls, and the globals it
+    /// refers to, in a module of its own, see [`Context::extract_func`].
+    fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { None }
+
+    /// Transform the module.
+    ///
+    /// By default, each function definition is transformed by
+    /// [`TransformPass::run_on_func`], and the analyses of the changed ones
+    /// are invalidated before the next function is processed. The time spent
+    /// on each function is recorded if passes are timed. Interprocedural
+    /// passes should override this instead.
+    ///
+    /// If the pass can be forked, see [`TransformPass::fork`], and the manager
+    /// runs the passes in parallel, see [`PassManager::set_parallel`], the
+    /// functions are transformed in parallel instead, each in a module of its
+    /// own.
+    ///
+    /// # Returns
+    ///
+    /// Whether the module is changed.
+    fn run(&mut self, ctx: &mut Context, am: &mut AnalysisManager) -> bool {
+        let funcs = ctx
+            .funcs()
+            .filter(|func| !func.is_declaration(ctx))
+            .collect::<Vec<_>>();
+        if am.parallel {
+            if let Some(passes) = funcs.iter().map(|_| self.fork()).collect() {
+                return run_in_parallel(ctx, am, funcs, passes);
+            }
+        }
+        let mut changed = false;
+        for func in funcs {
+            let start = Instant::now();
+            let func_changed = self.run_on_func(ctx, func, am);
+            if let Some(timings) = am.timings.as_mut() {
+                timings.push((func, start.elapsed(), func_changed));
+            }
+            if func_changed {
+                am.invalidate(func);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Transform the function definitions in parallel, each by a pass forked for
+/// it, see [`TransformPass::fork`].
+///
+/// Each function is extracted into a module of its own, transformed with an
+/// analysis cache of its own, and its body is moved back if it is changed, in
+/// the order of the functions.
+///
+/// # Returns
+///
+/// Whether the module is changed.
+fn run_in_parallel(
+    ctx: &mut Context,
+    am: &mut AnalysisManager,
+    funcs: Vec<Func>,
+    passes: Vec<Box<dyn TransformPass + Send>>,
+) -> bool {
+    let results = funcs
+        .par_iter()
+        .map(|&func| ctx.extract_func(func))
+        .zip(passes)
+        .map(|((mut module, func), mut pass)| {
+            let start = Instant::now();
+            let changed = pass.run_on_func(&mut module, func, &mut AnalysisManager::default());
+            (module, func, start.elapsed(), changed)
+        })
+        .collect::<Vec<_>>();
+
+    let mut changed = false;
+    for (func, (module, new_func, time, func_changed)) in funcs.into_iter().zip(results) {
+        if let Some(timings) = am.timings.as_mut() {
+            timings.push((func, time, func_changed));
+        }
+        if func_changed {
+            ctx.replace_body(func, &module, new_func);
+            am.invalidate(func);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The cache of analysis results.
+#[derive(Default)]
+pub struct AnalysisManager {
+    cache: HashMap<(TypeId, Func), Rc<dyn Any>>,
+    /// The time spent by the running pass on each function and whether it is
+    /// changed, if passes are timed.
+    timings: Option<Vec<(Func, Duration, bool)>>,
+    /// Whether the functions may be transformed in parallel.
+    parallel: bool,
+}
+
+impl AnalysisManager {
+    /// Get the analysis of a function, computing it if not cached.
+    ///
+    /// The result is reference counted, so it can be held while other
+    /// analyses are queried.
+    pub fn get<A: AnalysisPass>(&mut self, ctx: &Context, func: Func) -> Rc<A> {
+        let key = (TypeId::of::<A>(), func);
+        if let Some(result) = self.cache.get(&key) {
+            return result.clone().downcast().unwrap();
+        }
+        let result = Rc::new(A::run(ctx, func, self));
+        self.cache.insert(key, result.clone());
+        result
+    }
+
+    /// Get the analysis of a function only if it is cached.
+    pub fn get_cached<A: AnalysisPass>(&self, func: Func) -> Option<Rc<A>> {
+        self.cache
+            .get(&(TypeId::of::<A>(), func))
+            .map(|result| result.clone().downcast().unwrap())
+    }
+
+    /// Check if the analysis of a function is cached.
+    pub fn is_cached<A: AnalysisPass>(&self, func: Func) -> bool {
+        self.cache.contains_key(&(TypeId::of::<A>(), func))
+    }
+
+    /// Invalidate all the analyses of a function.
+    pub fn invalidate(&mut self, func: Func) { self.cache.retain(|(_, f), _| *f != func); }
+
+    /// Invalidate all the cached analyses.
+    pub fn invalidate_all(&mut self) { self.cache.clear(); }
+}
+
+impl AnalysisPass for Cfg {
+    const NAME: &'static str = "cfg";
+
+    fn run(ctx: &Context, func: Func, _: &mut AnalysisManager) -> Self { Cfg::new(ctx, func) }
+}
+
+impl AnalysisPass for DomTree {
+    const NAME: &'static str = "domtree";
+
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self {
+        DomTree::new(&am.get::<Cfg>(ctx, func))
+    }
+}
+
+impl AnalysisPass for PostDomTree {
+    const NAME: &'static str = "postdomtree";
+
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self {
+        PostDomTree::new(&am.get::<Cfg>(ctx, func))
+    }
+}
+
+impl AnalysisPass for LoopInfo {
+    const NAME: &'static str = "loops";
+
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let domtree = am.get::<DomTree>(ctx, func);
+        LoopInfo::new(&cfg, &domtree)
+    }
+}
+
+impl AnalysisPass for InductionVars {
+    const NAME: &'static str = "induction";
+
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let loop_info = am.get::<LoopInfo>(ctx, func);
+        InductionVars::new(ctx, &cfg, &loop_info)
+    }
+}
+
+impl AnalysisPass for ValueRanges {
+    const NAME: &'static str = "range";
+
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let domtree = am.get::<DomTree>(ctx, func);
+        let loop_info = am.get::<LoopInfo>(ctx, func);
+        let ivs = am.get::<InductionVars>(ctx, func);
+        ValueRanges::new(ctx, &cfg, &domtree, &loop_info, &ivs)
+    }
+}
+
+impl AnalysisPass for MemDep {
+    const NAME: &'static str = "memdep";
+
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self {
+        MemDep::new(ctx, &am.get::<Cfg>(ctx, func))
+    }
+}
+
+impl AnalysisPass for EscapeInfo {
+    const NAME: &'static str = "escape";
+
+    fn run(ctx: &Context, func: Func, _: &mut AnalysisManager) -> Self {
+        EscapeInfo::new(ctx, func)
+    }
+}
+
+impl AnalysisPass for BranchProbs {
+    const NAME: &'static str = "branch-prob";
+
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let loop_info = am.get::<LoopInfo>(ctx, func);
+        BranchProbs::new(ctx, &cfg, &loop_info)
+    }
+}
+
+impl AnalysisPass for BlockFreqs {
+    const NAME: &'static str = "block-freq";
+
+    fn run(ctx: &Context, func: Func, am: &mut AnalysisManager) -> Self {
+        let cfg = am.get::<Cfg>(ctx, func);
+        let loop_info = am.get::<LoopInfo>(ctx, func);
+        let probs = am.get::<BranchProbs>(ctx, func);
+        BlockFreqs::new(&cfg, &loop_info, &probs)
+    }
+}
+
+/// The options of dumping the IR around the passes in the pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct PrintOptions {
+    /// Dump the IR before each pass.
+    pub before_all: bool,
+    /// Dump the IR after each pass.
+    pub after_all: bool,
+    /// Only dump the function with this name, instead of the whole module.
+    pub func: Option<String>,
+}
+
+impl PrintOptions {
+    /// Dump the IR with a header naming the pass.
+    ///
+    /// Nothing is written if the function to dump does not exist, e.g., after
+    /// it is removed by a pass.
+    fn dump(&self, out: &mut dyn Write, ctx: &Context, when: &str, pass: &str) -> io::Result<()> {
+        match &self.func {
+            Some(name) => {
+                let Some(func) = ctx.func_by_name(name.as_str()) else {
+                    return Ok(());
+                };
+                writeln!(out, "; *** IR Dump {} {} on @{} ***", when, pass, name)?;
+                writeln!(out, "{}", func.display(ctx))
+            }
+            None => {
+                writeln!(out, "; *** IR Dump {} {} ***", when, pass)?;
+                write!(out, "{}", ctx)
+            }
+        }
+    }
+}
+
+/// The time spent by a pass and whether it changed anything.
+#[derive(Debug, Clone)]
+pub struct PassTiming {
+    /// The name of the pass.
+    pub pass: &'static str,
+    /// The name of the function the pass is run on, or `None` if the pass is
+    /// run on the whole module.
+    pub func: Option<String>,
+    /// The wall-clock time spent.
+    pub time: Duration,
+    /// Whether the function or the module is changed.
+    pub changed: bool,
+}
+
+/// A constructor of a registered transform pass.
+pub type PassFactory = fn() -> Box<dyn TransformPass>;
+
+/// The manager of the transform passes and the analysis cache.
+#[derive(Default)]
+pub struct PassManager {
+    /// The registered passes, in the registration order.
+    registry: Vec<(&'static str, PassFactory)>,
+    /// The passes to run, in order.
+    pipeline: Vec<Box<dyn TransformPass>>,
+    /// The names of the passes skipped when running the pipeline.
+    disabled: HashSet<&'static str>,
+    am: AnalysisManager,
+    /// When to dump the IR.
+    print: PrintOptions,
+    /// Where to dump the IR, or the standard error if not set.
+    print_out: Option<Box<dyn Write>>,
+    /// The timings of the passes run so far, if passes are timed.
+    timings: Option<Vec<PassTiming>>,
+    /// Whether to verify the IR after each pass.
+    verify_each: bool,
+    /// The graphs written around a pass.
+    graphs: GraphOptions,
+    /// The size of the arenas to collect the garbage at, see
+    /// [`Context::gc`].
+    gc_threshold: usize,
+}
+
+/// The least size of the arenas to collect the garbage at, so the small
+/// modules are not collected after every pass.
+const GC_MIN_THRESHOLD: usize = 1 << 14;
+
+impl PassManager {
+    /// Register a pass by name, so it can be added to the pipeline with
+    /// [`PassManager::add_pass_by_name`].
+    ///
+    /// # Panics
+    ///
+    /// - Panics if a pass with the same name is already registered.
+    pub fn register(&mut self, name: &'static str, factory: PassFactory) {
+        assert!(
+            self.registry
+                .iter()
+                .all(|(registered, _)| *registered != name),
+            "pass `{}` is registered twice",
+            name
+        );
+        self.registry.push((name, factory));
+    }
+
+    /// Get the names of all the registered passes.
+    pub fn registered(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.registry.iter().map(|(name, _)| *name)
+    }
+
+    /// Append a pass to the pipeline.
+    pub fn add_pass(&mut self, pass: Box<dyn TransformPass>) { self.pipeline.push(pass); }
+
+    /// Append a registered pass to the pipeline.
+    pub fn add_pass_by_name(&mut self, name: &str) -> Result<(), PassError> {
+        let (_, factory) = self
+            .registry
+            .iter()
+            .find(|(registered, _)| *registered == name)
+            .ok_or_else(|| PassError::UnknownPass(name.to_string()))?;
+        self.pipeline.push(factory());
+        Ok(())
+    }
+
+    /// Append a list of registered passes to the pipeline.
+    ///
+    /// Nothing is added if any of the names is unknown.
+    pub fn add_passes_by_name<'a>(
+        &mut self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), PassError> {
+        let names = names.into_iter().collect::<Vec<_>>();
+        if let Some(name) = names
+            .iter()
+            .find(|name| self.registered().all(|registered| registered != **name))
+        {
+            return Err(PassError::UnknownPass(name.to_string()));
+        }
+        for name in names {
+            self.add_pass_by_name(name)?;
+        }
+        Ok(())
+    }
+
+    /// Append the passes in a comma-separated list of names, e.g.,
+    /// `"sroa,instcombine,phi-simplify"`.
+    ///
+    /// Whitespace around the names and empty names are ignored. Nothing is
+    /// added if any of the names is unknown.
+    pub fn add_pipeline(&mut self, pipeline: &str) -> Result<(), PassError> {
+        self.add_passes_by_name(
+            pipeline
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty()),
+        )
+    }
+
+    /// Skip all the occurrences of a registered pass when running the
+    /// pipeline, e.g., to find the pass responsible for a miscompilation.
+    pub fn disable_pass(&mut self, name: &str) -> Result<(), PassError> {
+        let name = self
+            .registered()
+            .find(|registered| *registered == name)
+            .ok_or_else(|| PassError::UnknownPass(name.to_string()))?;
+        self.disabled.insert(name);
+        Ok(())
+    }
+
+    /// Get the names of the passes in the pipeline.
+    pub fn pipeline(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.pipeline.iter().map(|pass| pass.name())
+    }
+
+    /// Set when to dump the IR while running the pipeline.
+    pub fn set_print_options(&mut self, options: PrintOptions) { self.print = options; }
+
+    /// Dump the IR to a writer instead of the standard error.
+    pub fn set_print_output(&mut self, out: Box<dyn Write>) { self.print_out = Some(out); }
+
+    /// Record the time spent by each pass on each function, and whether it is
+    /// changed, when running the pipeline.
+    pub fn set_time_passes(&mut self, enabled: bool) { self.timings = enabled.then(Vec::new); }
+
+    /// Get the timings recorded so far, in the order the passes are run.
+    pub fn timings(&self) -> &[PassTiming] { self.timings.as_deref().unwrap_or_default() }
+
+    /// Summarize the timings of each pass, in the order of the first run.
+    ///
+    /// Each line has the total time spent by a pass, the number of functions
+    /// or modules it changed out of those it is run on, and its name.
+    pub fn timing_report(&self) -> String {
+        let mut passes: Vec<(&'static str, Duration, usize, usize)> = Vec::new();
+        for timing in self.timings() {
+            let idx = match passes.iter().position(|(pass, ..)| *pass == timing.pass) {
+                Some(idx) => idx,
+                None => {
+                    passes.push((timing.pass, Duration::ZERO, 0, 0));
+                    passes.len() - 1
+                }
+            };
+            let (_, time, changed, runs) = &mut passes[idx];
+            *time += timing.time;
+            *changed += timing.changed as usize;
+            *runs += 1;
+        }
+
+        let mut report = String::new();
+        report.push_str("===== Pass timing report =====\n");
+        report.push_str(&format!("{:>12}  {:>9}  pass\n", "time (ms)", "changed"));
+        let mut total = Duration::ZERO;
+        for (pass, time, changed, runs) in passes {
+            let changed = format!("{}/{}", changed, runs);
+            let ms = time.as_secs_f64() * 1000.0;
+            report.push_str(&format!("{:>12.3}  {:>9}  {}\n", ms, changed, pass));
+            total += time;
+        }
+        let ms = total.as_secs_f64() * 1000.0;
+        report.push_str(&format!("{:>12.3}  {:>9}  total\n", ms, ""));
+        report
+    }
+
+    /// Verify the IR before the pipeline and after each pass, stopping at the
+    /// first pass that breaks it, see [`PassManager::try_run`].
+    pub fn set_verify_each(&mut self, enabled: bool) { self.verify_each = enabled; }
+
+    /// Write the graphs of the functions before or after each run of a pass,
+    /// see [`GraphOptions`].
+    ///
+    /// The files of the `n`-th run of a pass, from 1, are tagged with
+    /// `before-<pass>.<n>` or `after-<pass>.<n>`.
+    pub fn set_graph_options(&mut self, options: GraphOptions) -> Result<(), PassError> {
+        for name in [&options.before, &options.after].into_iter().flatten() {
+            if self.registered().all(|registered| registered != name) {
+                return Err(PassError::UnknownPass(name.to_string()));
+            }
+        }
+        self.graphs = options;
+        Ok(())
+    }
+
+    /// Transform the functions in parallel on the global thread pool of rayon,
+    /// by the passes that can, see [`TransformPass::fork`].
+    ///
+    /// The result does not depend on the number of threads, but the blocks and
+    /// the values are numbered differently than in the sequential run.
+    pub fn set_parallel(&mut self, enabled: bool) { self.am.parallel = enabled; }
+
+    /// Get the analysis cache, e.g., to query analyses after the pipeline.
+    pub fn analyses(&mut self) -> &mut AnalysisManager { &mut self.am }
+
+    /// Run the pipeline on the module.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the IR cannot be written to the print output.
+    /// - Panics if the IR is invalid when verified, see
+    ///   [`PassManager::try_run`].
+    pub fn run(&mut self, ctx: &mut Context) -> bool {
+        self.try_run(ctx).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Run the pipeline on the module, verifying the IR if enabled.
+    ///
+    /// All the cached analyses are invalidated after a pass changes the
+    /// module, since interprocedural passes may change any function. The
+    /// unreachable entities may be swept then, see [`Context::gc`].
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(changed)`: Whether the module is changed by any pass.
+    /// - `Err(PassError::InvalidInput)`: The IR is invalid before the passes.
+    /// - `Err(PassError::BrokenBy)`: The first pass after which the IR is
+    ///   invalid. The passes after it are not run.
+    /// - `Err(PassError::WriteGraph)`: A graph cannot be written around a
+    ///   pass, see [`PassManager::set_graph_options`].
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the IR cannot be written to the print output.
+    pub fn try_run(&mut self, ctx: &mut Context) -> Result<bool, PassError> {
+        if self.verify_each {
+            ctx.verify().map_err(PassError::InvalidInput)?;
+        }
+        let mut stderr = io::stderr();
+        let out = self.print_out.as_deref_mut().unwrap_or(&mut stderr);
+        let mut changed = false;
+        let mut runs: HashMap<&'static str, usize> = HashMap::new();
+        for pass in self.pipeline.iter_mut() {
+            if self.disabled.contains(pass.name()) {
+                continue;
+            }
+            let run = runs.entry(pass.name()).or_default();
+            *run += 1;
+            let run = *run;
+            if self.graphs.before.as_deref() == Some(pass.name()) {
+                let tag = format!("before-{}.{}", pass.name(), run);
+                self.graphs
+                    .write(ctx, &tag)
+                    .map_err(|(path, err)| PassError::WriteGraph(path, err.to_string()))?;
+            }
+            if self.print.before_all {
+                self.print.dump(out, ctx, "Before", pass.name()).unwrap();
+            }
+            if self.timings.is_some() {
+                self.am.timings = Some(Vec::new());
+            }
+            let start = Instant::now();
+            let pass_changed = timing::time(pass.name(), || pass.run(ctx, &mut self.am));
+            let time = start.elapsed();
+            if let Some(timings) = self.timings.as_mut() {
+                let funcs = self.am.timings.take().unwrap();
+                if funcs.is_empty() {
+                    timings.push(PassTiming {
+                        pass: pass.name(),
+                        func: None,
+                        time,
+                        changed: pass_changed,
+                    });
+                }
+                for (func, time, changed) in funcs {
+                    timings.push(PassTiming {
+                        pass: pass.name(),
+                        func: Some(func.name(ctx).to_string()),
+                        time,
+                        changed,
+                    });
+                }
+            }
+            if pass_changed {
+                self.am.invalidate_all();
+                changed = true;
+                // the analyses are dropped, so no handles to the swept
+                // entities are left, and the arenas are collected whenever
+                // they have doubled, in amortized linear time
+                if ctx.arena_len() >= self.gc_threshold {
+                    timing::time("gc", || ctx.gc());
+                    self.gc_threshold = (ctx.arena_len() * 2).max(GC_MIN_THRESHOLD);
+                }
+            }
+            if self.print.after_all {
+                self.print.dump(out, ctx, "After", pass.name()).unwrap();
+            }
+            if self.graphs.after.as_deref() == Some(pass.name()) {
+                let tag = format!("after-{}.{}", pass.name(), run);
+                self.graphs
+                    .write(ctx, &tag)
+                    .map_err(|(path, err)| PassError::WriteGraph(path, err.to_string()))?;
+            }
+            if self.verify_each {
+                ctx.verify()
+                    .map_err(|err| PassError::BrokenBy(pass.name(), err))?;
+            }
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+    use crate::infra::storage::ArenaPtr;
+    use crate::ir::{Block, GraphOptions, Inst, Problem, Ty, Usable, Value};
+
+    /// Remove the blocks unreachable from the entry.
+    #[derive(Clone, Default)]
+    struct RemoveUnreachable;
+
+    impl TransformPass for RemoveUnreachable {
+        fn name(&self) -> &'static str { "remove-unreachable" }
+
+        fn fork(&self) -> Option<Box<dyn TransformPass + Send>> { Some(Box::new(self.clone())) }
+
+        fn run_on_func(&mut self, ctx: &mut Context, func: Func, am: &mut AnalysisManager) -> bool {
+            let cfg = am.get::<Cfg>(ctx, func);
+            let dead = cfg
+                .blocks()
+                .iter()
+                .copied()
+                .filter(|&block| !cfg.is_reachable(block))
+                .collect::<Vec<_>>();
+            for &block in dead.iter() {
+                block.unlink(ctx);
+            }
+            !dead.is_empty()
+        }
+    }
+
+    #[derive(Default)]
+    struct Nop;
+
+    impl TransformPass for Nop {
+        fn name(&self) -> &'static str { "nop" }
+    }
+
+    #[test]
+    fn test_pass_manager() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let entry = Block::new(&mut ctx);
+        let dead = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+        func.push_back(&mut ctx, dead).unwrap();
+        let zero = Value::i32(&mut ctx, 0);
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        entry.push_back(&mut ctx, ret).unwrap();
+        let dead_ret = Inst::ret(&mut ctx, Some(zero));
+        dead.push_back(&mut ctx, dead_ret).unwrap();
+
+        let mut pm = PassManager::default();
+        pm.register("remove-unreachable", || Box::<RemoveUnreachable>::default());
+        pm.register("nop", || Box::<Nop>::default());
+        assert_eq!(
+            pm.add_passes_by_name(["nop", "dce"]),
+            Err(PassError::UnknownPass("dce".to_string()))
+        );
+        assert_eq!(pm.pipeline().count(), 0);
+        pm.add_passes_by_name(["nop", "remove-unreachable", "nop"])
+            .unwrap();
+        assert_eq!(
+            pm.pipeline().collect::<Vec<_>>(),
+            ["nop", "remove-unreachable", "nop"]
+        );
+
+        // cached analyses are shared, and dropped after changes
+        let am = pm.analyses();
+        let domtree = am.get::<DomTree>(&ctx, func);
+        assert!(am.is_cached::<Cfg>(func));
+        assert!(Rc::ptr_eq(&domtree, &am.get::<DomTree>(&ctx, func)));
+        assert_eq!(am.get::<Cfg>(&ctx, func).blocks().len(), 2);
+
+        assert!(pm.run(&mut ctx));
+        assert!(!pm.analyses().is_cached::<Cfg>(func));
+        // the unlinked block is swept after the change
+        assert!(dead.try_deref(&ctx).is_none());
+        assert!(dead_ret.try_deref(&ctx).is_none());
+        assert!(zero.try_deref(&ctx).is_some());
+        assert_eq!(pm.analyses().get::<Cfg>(&ctx, func).blocks(), &[entry]);
+        assert!(!pm.run(&mut ctx));
+    }
+
+    #[test]
+    fn test_run_in_parallel() {
+        // f(x) returns x, and g calls f, both with a dead block
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let f = Func::new(&mut ctx, "f".to_string(), i32);
+        let x = f.add_param(&mut ctx, i32);
+        let g = Func::new(&mut ctx, "g".to_string(), i32);
+        let mut entries = Vec::new();
+        for func in [f, g] {
+            let entry = Block::new(&mut ctx);
+            entries.push(entry);
+            let dead = Block::new(&mut ctx);
+            func.push_back(&mut ctx, entry).unwrap();
+            func.push_back(&mut ctx, dead).unwrap();
+            let value = match func == f {
+                true => x,
+                false => {
+                    let one = Value::i32(&mut ctx, 1);
+                    let call = Inst::call(&mut ctx, f, vec![one]);
+                    entry.push_back(&mut ctx, call).unwrap();
+                    call.result(&ctx).unwrap()
+                }
+            };
+            for block in [entry, dead] {
+                let ret = Inst::ret(&mut ctx, Some(value));
+                block.push_back(&mut ctx, ret).unwrap();
+            }
+        }
+
+        let mut pm = PassManager::default();
+        pm.add_pass(Box::new(RemoveUnreachable));
+        pm.set_time_passes(true);
+        pm.set_verify_each(true);
+        pm.set_parallel(true);
+        assert_eq!(pm.try_run(&mut ctx), Ok(true));
+
+        // the functions and their parameters are kept, with the bodies moved
+        // back from the modules of their own
+        assert_eq!(ctx.funcs().collect::<Vec<_>>(), [f, g]);
+        assert_eq!(f.params(&ctx), &[x]);
+        assert_eq!(x.users(&ctx).into_iter().count(), 1);
+        for (func, entry) in [f, g].into_iter().zip(entries) {
+            assert_eq!(func.iter(&ctx).count(), 1);
+            assert_ne!(func.head(&ctx), Some(entry));
+        }
+        let call = g.head(&ctx).unwrap().head(&ctx).unwrap();
+        assert_eq!(call.callee(&ctx), f.name(&ctx));
+        let timings = pm
+            .timings()
+            .iter()
+            .map(|timing| (timing.func.as_deref(), timing.changed))
+            .collect::<Vec<_>>();
+        assert_eq!(timings, [(Some("f"), true), (Some("g"), true)]);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let entry = Block::new(&mut ctx);
+        let dead = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+        func.push_back(&mut ctx, dead).unwrap();
+        for block in [entry, dead] {
+            let zero = Value::i32(&mut ctx, 0);
+            let ret = Inst::ret(&mut ctx, Some(zero));
+            block.push_back(&mut ctx, ret).unwrap();
+        }
+
+        let mut pm = PassManager::default();
+        pm.register("remove-unreachable", || Box::<RemoveUnreachable>::default());
+        pm.register("nop", || Box::<Nop>::default());
+        assert_eq!(
+            pm.add_pipeline("nop,dce"),
+            Err(PassError::UnknownPass("dce".to_string()))
+        );
+        assert_eq!(
+            pm.disable_pass("dce"),
+            Err(PassError::UnknownPass("dce".to_string()))
+        );
+        pm.add_pipeline(" nop, remove-unreachable,,nop ").unwrap();
+        assert_eq!(
+            pm.pipeline().collect::<Vec<_>>(),
+            ["nop", "remove-unreachable", "nop"]
+        );
+
+        // disabled passes stay in the pipeline, but are not run
+        pm.disable_pass("remove-unreachable").unwrap();
+        assert!(!pm.run(&mut ctx));
+        assert_eq!(func.iter(&ctx).count(), 2);
+    }
+
+    #[test]
+    fn test_graph_options() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let entry = Block::new(&mut ctx);
+        let dead = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+        func.push_back(&mut ctx, dead).unwrap();
+        for block in [entry, dead] {
+            let zero = Value::i32(&mut ctx, 0);
+            let ret = Inst::ret(&mut ctx, Some(zero));
+            block.push_back(&mut ctx, ret).unwrap();
+        }
+
+        let mut pm = PassManager::default();
+        pm.register("remove-unreachable", || Box::<RemoveUnreachable>::default());
+        pm.register("nop", || Box::<Nop>::default());
+        let dir = std::env::temp_dir().join(format!("nkucc-graphs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = GraphOptions {
+            cfg: Some(dir.clone()),
+            after: Some("dce".to_string()),
+            ..GraphOptions::default()
+        };
+        assert_eq!(
+            pm.set_graph_options(options.clone()),
+            Err(PassError::UnknownPass("dce".to_string()))
+        );
+        pm.set_graph_options(GraphOptions {
+            after: Some("nop".to_string()),
+            ..options
+        })
+        .unwrap();
+        pm.add_pipeline("nop,remove-unreachable,nop").unwrap();
+        pm.run(&mut ctx);
+
+        // each run of the pass has its own graphs
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        let first = read("f.after-nop.1.cfg.dot");
+        let second = read("f.after-nop.2.cfg.dot");
+        std::fs::remove_dir_all(&dir).unwrap();
+        let dead = format!("\"{}\" [", dead.name(&ctx));
+        assert!(first.contains(&dead));
+        assert!(!second.contains(&dead));
+    }
+
+    #[test]
+    fn test_time_passes() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        for (name, reachable) in [("f", true), ("g", false)] {
+            let func = Func::new(&mut ctx, name.to_string(), i32);
+            let entry = Block::new(&mut ctx);
+            func.push_back(&mut ctx, entry).unwrap();
+            let zero = Value::i32(&mut ctx, 0);
+            let ret = Inst::ret(&mut ctx, Some(zero));
+            entry.push_back(&mut ctx, ret).unwrap();
+            if !reachable {
+                let dead = Block::new(&mut ctx);
+                func.push_back(&mut ctx, dead).unwrap();
+                let ret = Inst::ret(&mut ctx, Some(zero));
+                dead.push_back(&mut ctx, ret).unwrap();
+            }
+        }
+        Func::new(&mut ctx, "h".to_string(), i32);
+
+        let mut pm = PassManager::default();
+        pm.register("remove-unreachable", || Box::<RemoveUnreachable>::default());
+        pm.register("nop", || Box::<Nop>::default());
+        pm.add_pipeline("remove-unreachable,nop").unwrap();
+        assert!(pm.run(&mut ctx));
+        assert!(pm.timings().is_empty());
+
+        pm.set_time_passes(true);
+        pm.add_pipeline("remove-unreachable").unwrap();
+        pm.run(&mut ctx);
+        let timings = pm
+            .timings()
+            .iter()
+            .map(|timing| (timing.pass, timing.func.as_deref(), timing.changed))
+            .collect::<Vec<_>>();
+        // declarations are not run on
+        assert_eq!(
+            timings,
+            [
+                ("remove-unreachable", Some("f"), false),
+                ("remove-unreachable", Some("g"), false),
+                ("nop", Some("f"), false),
+                ("nop", Some("g"), false),
+                ("remove-unreachable", Some("f"), false),
+                ("remove-unreachable", Some("g"), false),
+            ]
+        );
+        let report = pm.timing_report();
+        assert!(report
+            .lines()
+            .nth(2)
+            .unwrap()
+            .ends_with("0/4  remove-unreachable"));
+        assert!(report.lines().nth(3).unwrap().ends_with("0/2  nop"));
+        assert!(report.lines().nth(4).unwrap().ends_with("total"));
+    }
+
+    /// Remove the terminator of the entry block, breaking the IR.
+    #[derive(Default)]
+    struct RemoveTerminator;
+
+    impl TransformPass for RemoveTerminator {
+        fn name(&self) -> &'static str { "remove-terminator" }
+
+        fn run_on_func(&mut self, ctx: &mut Context, func: Func, _: &mut AnalysisManager) -> bool {
+            let entry = func.head(ctx).unwrap();
+            entry.tail(ctx).unwrap().remove(ctx);
+            true
+        }
+    }
+
+    #[test]
+    fn test_verify_each() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "f".to_string(), i32);
+        let entry = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+        let zero = Value::i32(&mut ctx, 0);
+        let ret = Inst::ret(&mut ctx, Some(zero));
+        entry.push_back(&mut ctx, ret).unwrap();
+
+        let mut pm = PassManager::default();
+        pm.register("remove-terminator", || Box::<RemoveTerminator>::default());
+        pm.register("nop", || Box::<Nop>::default());
+        pm.add_pipeline("nop,remove-terminator,nop,remove-terminator")
+            .unwrap();
+        pm.set_verify_each(true);
+        pm.set_time_passes(true);
+        let err = pm.try_run(&mut ctx).unwrap_err();
+        let PassError::BrokenBy(pass, err) = err else {
+            panic!("unexpected error: {}", err);
+        };
+        assert_eq!(pass, "remove-terminator");
+        assert_eq!(err.func, "f");
+        assert_eq!(err.problem, Problem::MissingTerminator);
+        // the passes after the broken one are not run
+        assert_eq!(pm.timings().len(), 2);
+
+        // the broken IR is rejected before any pass
+        pm.set_time_passes(true);
+        assert!(matches!(
+            pm.try_run(&mut ctx),
+            Err(PassError::InvalidInput(_))
+        ));
+        assert!(pm.timings().is_empty());
+    }
+
+    /// A writer into a shared buffer, to inspect the dumps.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().write(buf) }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_print_options() {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        for name in ["f", "g"] {
+            let func = Func::new(&mut ctx, name.to_string(), i32);
+            let entry = Block::new(&mut ctx);
+            func.push_back(&mut ctx, entry).unwrap();
+            let zero = Value::i32(&mut ctx, 0);
+            let ret = Inst::ret(&mut ctx, Some(zero));
+            entry.push_back(&mut ctx, ret).unwrap();
+        }
+
+        let mut pm = PassManager::default();
+        pm.register("nop", || Box::<Nop>::default());
+        pm.add_passes_by_name(["nop", "nop"]).unwrap();
+        let buf = SharedBuf::default();
+        pm.set_print_output(Box::new(buf.clone()));
+        pm.set_print_options(PrintOptions {
+            after_all: true,
+            ..Default::default()
+        });
+        pm.run(&mut ctx);
+        let dump = String::from_utf8(buf.0.take()).unwrap();
+        assert_eq!(dump.matches("; *** IR Dump After nop ***").count(), 2);
+        assert!(!dump.contains("Before"));
+        assert_eq!(dump.matches("@g(").count(), 2);
+
+        pm.set_print_options(PrintOptions {
+            before_all: true,
+            after_all: false,
+            func: Some("f".to_string()),
+        });
+        pm.run(&mut ctx);
+        let dump = String::from_utf8(buf.0.take()).unwrap();
+        assert_eq!(
+            dump.matches("; *** IR Dump Before nop on @f ***").count(),
+            2
+        );
+        assert!(dump.contains("@f("));
+        assert!(!dump.contains("@g("));
+    }
+}
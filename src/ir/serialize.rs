@@ -0,0 +1,992 @@
+//! Binary serialization of the IR [`Context`].
+//!
+//! The format is a compact, self-describing byte stream that stores the
+//! logical structure of the module instead of the raw arenas:
+//!
+//! ```text
+//! "NKIR" version:u8 ptr_size:u32
+//! globals: n { name constant align is_const is_external section }
+//! funcs:   n { name ret_ty memory is_external params: n { ty } blocks: n { params: n { ty } insts: n { inst } } }
+//! ```
+//!
+//! Types and constants are encoded structurally. Inside a function, parameters
+//! (of the function and the blocks) and instruction results are numbered in
+//! program order (function parameters first),
+//! and operands refer to them by this local number, so phi nodes can refer to
+//! values defined later in the function. Blocks are referred to by their
+//! position in the function.
+//!
+//! Deserialization rebuilds the module with the normal constructors, so the
+//! arena indices of the loaded context are not the same as the original one,
+//! but the IR is structurally identical.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::{
+    Block,
+    CastOp,
+    ConstantValue,
+    Context,
+    FloatBinaryOp,
+    FloatCmpCond,
+    Func,
+    Global,
+    Inst,
+    InstKind,
+    IntBinaryOp,
+    IntCmpCond,
+    MemoryEffect,
+    TargetInfo,
+    Section,
+    Ty,
+    TyData,
+    Value,
+    ValueKind,
+};
+use crate::infra::linked_list::LinkedListContainer;
+use crate::infra::storage::{Arena, ArenaPtr};
+
+const MAGIC: &[u8; 4] = b"NKIR";
+const VERSION: u8 = 6;
+
+/// Errors that can occur when deserializing a [`Context`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DeserializeError {
+    #[error("invalid magic number, not a serialized IR module")]
+    InvalidMagic,
+    #[error("unsupported format version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unexpected end of input")]
+    UnexpectedEof,
+    #[error("invalid {what} tag {tag}")]
+    InvalidTag { what: &'static str, tag: u8 },
+    #[error("invalid utf-8 string")]
+    InvalidUtf8,
+    #[error("variable-length integer overflowing 64 bits")]
+    IntegerOverflow,
+    #[error("reference to undefined value #{0}")]
+    InvalidValueRef(usize),
+    #[error("reference to undefined block #{0}")]
+    InvalidBlockRef(usize),
+    #[error("invalid alignment {0}")]
+    InvalidAlign(usize),
+    #[error("{0} trailing bytes after the module")]
+    TrailingBytes(usize),
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) { self.buf.push(v); }
+
+    /// Write an unsigned LEB128 integer.
+    fn uleb(&mut self, mut v: u64) {
+        loop {
+            let byte = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                self.buf.push(byte);
+                break;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    /// Write a signed integer with zigzag encoding.
+    fn sleb(&mut self, v: i64) { self.uleb(((v << 1) ^ (v >> 63)) as u64); }
+
+    fn str(&mut self, s: &str) {
+        self.uleb(s.len() as u64);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn ty(&mut self, ctx: &Context, ty: Ty) {
+        match ty.deref(ctx) {
+            TyData::Void => self.u8(0),
+            TyData::Int1 => self.u8(1),
+            TyData::Int8 => self.u8(2),
+            TyData::Int32 => self.u8(3),
+            TyData::Float32 => self.u8(4),
+            TyData::Float64 => self.u8(5),
+            TyData::Ptr => self.u8(6),
+            TyData::Array { elem, len } => {
+                self.u8(7);
+                self.ty(ctx, *elem);
+                self.uleb(*len as u64);
+            }
+        }
+    }
+
+    fn constant(&mut self, ctx: &Context, value: &ConstantValue) {
+        match value {
+            ConstantValue::Undef { ty } => {
+                self.u8(0);
+                self.ty(ctx, *ty);
+            }
+            ConstantValue::AggregateZero { ty } => {
+                self.u8(1);
+                self.ty(ctx, *ty);
+            }
+            ConstantValue::Int1 { value, .. } => {
+                self.u8(2);
+                self.u8(*value as u8);
+            }
+            ConstantValue::Int8 { value, .. } => {
+                self.u8(3);
+                self.sleb(*value as i64);
+            }
+            ConstantValue::Int32 { value, .. } => {
+                self.u8(4);
+                self.sleb(*value as i64);
+            }
+            ConstantValue::Array { ty, elems } => {
+                self.u8(5);
+                self.ty(ctx, *ty);
+                self.uleb(elems.len() as u64);
+                for elem in elems {
+                    self.constant(ctx, elem);
+                }
+            }
+            ConstantValue::GlobalRef { name, value_ty, .. } => {
+                self.u8(6);
+                self.str(name.as_str());
+                self.ty(ctx, *value_ty);
+            }
+            ConstantValue::Float32 { bits, .. } => {
+                self.u8(7);
+                self.uleb(*bits as u64);
+            }
+        }
+    }
+
+    fn int_binary_op(&mut self, op: IntBinaryOp) {
+        let tag = match op {
+            IntBinaryOp::Add => 0,
+            IntBinaryOp::Sub => 1,
+            IntBinaryOp::Mul => 2,
+            IntBinaryOp::SDiv => 3,
+            IntBinaryOp::UDiv => 4,
+            IntBinaryOp::SRem => 5,
+            IntBinaryOp::URem => 6,
+            IntBinaryOp::Shl => 7,
+            IntBinaryOp::LShr => 8,
+            IntBinaryOp::AShr => 9,
+            IntBinaryOp::And => 10,
+            IntBinaryOp::Or => 11,
+            IntBinaryOp::Xor => 12,
+            IntBinaryOp::SMulHi => 14,
+            IntBinaryOp::SMin => 15,
+            IntBinaryOp::SMax => 16,
+            IntBinaryOp::ICmp { cond } => {
+                self.u8(13);
+                let cond = match cond {
+                    IntCmpCond::Eq => 0,
+                    IntCmpCond::Ne => 1,
+                    IntCmpCond::Slt => 2,
+                    IntCmpCond::Sle => 3,
+                    IntCmpCond::Sgt => 4,
+                    IntCmpCond::Sge => 5,
+                };
+                self.u8(cond);
+                return;
+            }
+        };
+        self.u8(tag);
+    }
+
+    fn float_binary_op(&mut self, op: FloatBinaryOp) {
+        let tag = match op {
+            FloatBinaryOp::FAdd => 0,
+            FloatBinaryOp::FSub => 1,
+            FloatBinaryOp::FMul => 2,
+            FloatBinaryOp::FDiv => 3,
+            FloatBinaryOp::FCmp { cond } => {
+                self.u8(4);
+                let cond = match cond {
+                    FloatCmpCond::Oeq => 0,
+                    FloatCmpCond::One => 1,
+                    FloatCmpCond::Olt => 2,
+                    FloatCmpCond::Ole => 3,
+                    FloatCmpCond::Ogt => 4,
+                    FloatCmpCond::Oge => 5,
+                };
+                self.u8(cond);
+                return;
+            }
+        };
+        self.u8(tag);
+    }
+
+    fn inst_kind(&mut self, ctx: &Context, kind: &InstKind) {
+        match kind {
+            InstKind::Alloca { ty } => {
+                self.u8(0);
+                self.ty(ctx, *ty);
+            }
+            InstKind::Phi => self.u8(1),
+            InstKind::Load => self.u8(2),
+            InstKind::Store => self.u8(3),
+            InstKind::GetElementPtr { bound_ty } => {
+                self.u8(4);
+                self.ty(ctx, *bound_ty);
+            }
+            InstKind::Call => self.u8(5),
+            InstKind::Br => self.u8(6),
+            InstKind::CondBr => self.u8(7),
+            InstKind::Ret => self.u8(8),
+            InstKind::IntBinary { op } => {
+                self.u8(9);
+                self.int_binary_op(*op);
+            }
+            InstKind::Cast { op } => {
+                self.u8(10);
+                self.u8(match op {
+                    CastOp::Zext => 0,
+                    CastOp::Sext => 1,
+                    CastOp::Trunc => 2,
+                    CastOp::SiToFp => 3,
+                    CastOp::FpToSi => 4,
+                });
+            }
+            InstKind::FloatBinary { op } => {
+                self.u8(11);
+                self.float_binary_op(*op);
+            }
+            InstKind::Switch { cases } => {
+                self.u8(12);
+                self.uleb(cases.len() as u64);
+                for &value in cases {
+                    self.sleb(value as i64);
+                }
+            }
+        }
+    }
+
+    fn operand(&mut self, ctx: &Context, numbering: &HashMap<Value, usize>, value: Value) {
+        match &value.deref(ctx).kind {
+            ValueKind::Constant { value } => {
+                self.u8(1);
+                self.constant(ctx, value);
+            }
+            ValueKind::InstResult { .. }
+            | ValueKind::Param { .. }
+            | ValueKind::BlockParam { .. } => {
+                self.u8(0);
+                self.uleb(numbering[&value] as u64);
+            }
+        }
+    }
+
+    fn func(&mut self, ctx: &Context, func: Func) {
+        self.str(func.name(ctx).as_str());
+        self.ty(ctx, func.ret_ty(ctx));
+        self.u8(match func.memory_effect(ctx) {
+            MemoryEffect::None => 0,
+            MemoryEffect::ReadOnly => 1,
+            MemoryEffect::ReadWrite => 2,
+        });
+        self.u8(func.is_external(ctx) as u8);
+
+        let mut numbering = HashMap::new();
+        let mut block_numbering = HashMap::new();
+
+        self.uleb(func.params(ctx).len() as u64);
+        for &param in func.params(ctx) {
+            self.ty(ctx, param.ty(ctx));
+            numbering.insert(param, numbering.len());
+        }
+
+        // Number all the values and blocks first, operands may refer to values
+        // defined later in the function.
+        for (i, block) in func.iter(ctx).enumerate() {
+            block_numbering.insert(block, i);
+            for &param in block.params(ctx) {
+                numbering.insert(param, numbering.len());
+            }
+            for inst in block.iter(ctx) {
+                if let Some(result) = inst.result(ctx) {
+                    numbering.insert(result, numbering.len());
+                }
+            }
+        }
+
+        self.uleb(block_numbering.len() as u64);
+        for block in func.iter(ctx) {
+            self.uleb(block.params(ctx).len() as u64);
+            for &param in block.params(ctx) {
+                self.ty(ctx, param.ty(ctx));
+            }
+            self.uleb(block.iter(ctx).count() as u64);
+            for inst in block.iter(ctx) {
+                self.inst_kind(ctx, inst.kind(ctx));
+                match inst.result(ctx) {
+                    Some(result) => self.ty(ctx, result.ty(ctx)),
+                    None => self.u8(0), // void
+                }
+
+                if inst.is_phi(ctx) {
+                    // Sort the incomings to make the output deterministic.
+                    let mut incomings = inst
+                        .incoming_iter(ctx)
+                        .map(|(block, value)| (block_numbering[&block], value))
+                        .collect::<Vec<_>>();
+                    incomings.sort_by_key(|(block, _)| *block);
+                    self.uleb(incomings.len() as u64);
+                    for (block, value) in incomings {
+                        self.uleb(block as u64);
+                        self.operand(ctx, &numbering, value);
+                    }
+                } else {
+                    let mut operands = inst.operand_iter(ctx).collect::<Vec<_>>();
+                    // The block arguments are also stored as operands, they are
+                    // written with the successors. Only the condition of a
+                    // conditional branch or a switch is a plain operand.
+                    match inst.kind(ctx) {
+                        InstKind::Br => operands.clear(),
+                        InstKind::CondBr | InstKind::Switch { .. } => operands.truncate(1),
+                        _ => {}
+                    }
+                    self.uleb(operands.len() as u64);
+                    for operand in operands {
+                        self.operand(ctx, &numbering, operand);
+                    }
+                }
+
+                let successors = inst.successor_iter(ctx).collect::<Vec<_>>();
+                self.uleb(successors.len() as u64);
+                for (i, succ) in successors.into_iter().enumerate() {
+                    self.uleb(block_numbering[&succ] as u64);
+                    let args = inst.successor_args(ctx, i).collect::<Vec<_>>();
+                    self.uleb(args.len() as u64);
+                    for arg in args {
+                        self.operand(ctx, &numbering, arg);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+/// A reference to an operand, before the referred value is created.
+enum OperandRef {
+    Local(usize),
+    Constant(ConstantValue),
+}
+
+/// An instruction record, before the instruction is created.
+struct InstRecord {
+    kind: InstKind,
+    ty: Ty,
+    operands: Vec<OperandRef>,
+    incomings: Vec<(usize, OperandRef)>,
+    successors: Vec<(usize, Vec<OperandRef>)>,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Result<u8, DeserializeError> {
+        let byte = *self
+            .bytes
+            .get(self.pos)
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn uleb(&mut self) -> Result<u64, DeserializeError> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            // the 10th byte holds the last bit, and ends the integer
+            if shift == 63 && byte & 0xfe != 0 {
+                return Err(DeserializeError::IntegerOverflow);
+            }
+            result |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize, DeserializeError> { Ok(self.uleb()? as usize) }
+
+    /// Get the number of the bytes not read yet.
+    fn remaining(&self) -> usize { self.bytes.len() - self.pos }
+
+    fn sleb(&mut self) -> Result<i64, DeserializeError> {
+        let v = self.uleb()?;
+        Ok(((v >> 1) as i64) ^ -((v & 1) as i64))
+    }
+
+    fn str(&mut self) -> Result<String, DeserializeError> {
+        let len = self.usize()?;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        let s = std::str::from_utf8(&self.bytes[self.pos..end])
+            .map_err(|_| DeserializeError::InvalidUtf8)?
+            .to_string();
+        self.pos = end;
+        Ok(s)
+    }
+
+    fn ty(&mut self, ctx: &mut Context) -> Result<Ty, DeserializeError> {
+        let ty = match self.u8()? {
+            0 => Ty::void(ctx),
+            1 => Ty::i1(ctx),
+            2 => Ty::i8(ctx),
+            3 => Ty::i32(ctx),
+            4 => ctx.alloc(TyData::Float32),
+            5 => ctx.alloc(TyData::Float64),
+            6 => Ty::ptr(ctx),
+            7 => {
+                let elem = self.ty(ctx)?;
+                let len = self.usize()?;
+                Ty::array(ctx, elem, len)
+            }
+            tag => return Err(DeserializeError::InvalidTag { what: "type", tag }),
+        };
+        Ok(ty)
+    }
+
+    fn constant(&mut self, ctx: &mut Context) -> Result<ConstantValue, DeserializeError> {
+        let value = match self.u8()? {
+            0 => {
+                let ty = self.ty(ctx)?;
+                ConstantValue::undef(ctx, ty)
+            }
+            1 => ConstantValue::AggregateZero { ty: self.ty(ctx)? },
+            2 => {
+                let value = self.u8()? != 0;
+                ConstantValue::i1(ctx, value)
+            }
+            3 => {
+                let value = self.sleb()? as i8;
+                ConstantValue::i8(ctx, value)
+            }
+            4 => {
+                let value = self.sleb()? as i32;
+                ConstantValue::i32(ctx, value)
+            }
+            5 => {
+                let ty = self.ty(ctx)?;
+                let len = self.usize()?;
+                // each element takes a byte at least, so a corrupted length
+                // is not trusted beyond the input
+                let mut elems = Vec::with_capacity(len.min(self.remaining()));
+                for _ in 0..len {
+                    elems.push(self.constant(ctx)?);
+                }
+                ConstantValue::Array { ty, elems }
+            }
+            6 => {
+                let name = self.str()?;
+                let value_ty = self.ty(ctx)?;
+                ConstantValue::global_ref(ctx, name, value_ty)
+            }
+            7 => {
+                let bits = self.uleb()? as u32;
+                ConstantValue::f32(ctx, f32::from_bits(bits))
+            }
+            tag => return Err(DeserializeError::InvalidTag { what: "constant", tag }),
+        };
+        Ok(value)
+    }
+
+    fn int_binary_op(&mut self) -> Result<IntBinaryOp, DeserializeError> {
+        let op = match self.u8()? {
+            0 => IntBinaryOp::Add,
+            1 => IntBinaryOp::Sub,
+            2 => IntBinaryOp::Mul,
+            3 => IntBinaryOp::SDiv,
+            4 => IntBinaryOp::UDiv,
+            5 => IntBinaryOp::SRem,
+            6 => IntBinaryOp::URem,
+            7 => IntBinaryOp::Shl,
+            8 => IntBinaryOp::LShr,
+            9 => IntBinaryOp::AShr,
+            10 => IntBinaryOp::And,
+            11 => IntBinaryOp::Or,
+            12 => IntBinaryOp::Xor,
+            14 => IntBinaryOp::SMulHi,
+            15 => IntBinaryOp::SMin,
+            16 => IntBinaryOp::SMax,
+            13 => {
+                let cond = match self.u8()? {
+                    0 => IntCmpCond::Eq,
+                    1 => IntCmpCond::Ne,
+                    2 => IntCmpCond::Slt,
+                    3 => IntCmpCond::Sle,
+                    4 => IntCmpCond::Sgt,
+                    5 => IntCmpCond::Sge,
+                    tag => {
+                        return Err(DeserializeError::InvalidTag {
+                            what: "compare condition",
+                            tag,
+                        })
+                    }
+                };
+                IntBinaryOp::ICmp { cond }
+            }
+            tag => {
+                return Err(DeserializeError::InvalidTag {
+                    what: "binary operator",
+                    tag,
+                })
+            }
+        };
+        Ok(op)
+    }
+
+    fn float_binary_op(&mut self) -> Result<FloatBinaryOp, DeserializeError> {
+        let op = match self.u8()? {
+            0 => FloatBinaryOp::FAdd,
+            1 => FloatBinaryOp::FSub,
+            2 => FloatBinaryOp::FMul,
+            3 => FloatBinaryOp::FDiv,
+            4 => {
+                let cond = match self.u8()? {
+                    0 => FloatCmpCond::Oeq,
+                    1 => FloatCmpCond::One,
+                    2 => FloatCmpCond::Olt,
+                    3 => FloatCmpCond::Ole,
+                    4 => FloatCmpCond::Ogt,
+                    5 => FloatCmpCond::Oge,
+                    tag => {
+                        return Err(DeserializeError::InvalidTag {
+                            what: "compare condition",
+                            tag,
+                        })
+                    }
+                };
+                FloatBinaryOp::FCmp { cond }
+            }
+            tag => {
+                return Err(DeserializeError::InvalidTag {
+                    what: "binary operator",
+                    tag,
+                })
+            }
+        };
+        Ok(op)
+    }
+
+    fn inst_kind(&mut self, ctx: &mut Context) -> Result<InstKind, DeserializeError> {
+        let kind = match self.u8()? {
+            0 => InstKind::Alloca { ty: self.ty(ctx)? },
+            1 => InstKind::Phi,
+            2 => InstKind::Load,
+            3 => InstKind::Store,
+            4 => InstKind::GetElementPtr {
+                bound_ty: self.ty(ctx)?,
+            },
+            5 => InstKind::Call,
+            6 => InstKind::Br,
+            7 => InstKind::CondBr,
+            8 => InstKind::Ret,
+            9 => InstKind::IntBinary {
+                op: self.int_binary_op()?,
+            },
+            10 => {
+                let op = match self.u8()? {
+                    0 => CastOp::Zext,
+                    1 => CastOp::Sext,
+                    2 => CastOp::Trunc,
+                    3 => CastOp::SiToFp,
+                    4 => CastOp::FpToSi,
+                    tag => {
+                        return Err(DeserializeError::InvalidTag {
+                            what: "cast operator",
+                            tag,
+                        })
+                    }
+                };
+                InstKind::Cast { op }
+            }
+            11 => InstKind::FloatBinary {
+                op: self.float_binary_op()?,
+            },
+            12 => {
+                let len = self.uleb()?;
+                let cases = (0..len)
+                    .map(|_| self.sleb().map(|value| value as i32))
+                    .collect::<Result<_, _>>()?;
+                InstKind::Switch { cases }
+            }
+            tag => {
+                return Err(DeserializeError::InvalidTag {
+                    what: "instruction",
+                    tag,
+                })
+            }
+        };
+        Ok(kind)
+    }
+
+    fn operand(&mut self, ctx: &mut Context) -> Result<OperandRef, DeserializeError> {
+        match self.u8()? {
+            0 => Ok(OperandRef::Local(self.usize()?)),
+            1 => Ok(OperandRef::Constant(self.constant(ctx)?)),
+            tag => Err(DeserializeError::InvalidTag {
+                what: "operand",
+                tag,
+            }),
+        }
+    }
+
+    fn func(&mut self, ctx: &mut Context) -> Result<(), DeserializeError> {
+        let name = self.str()?;
+        let ret_ty = self.ty(ctx)?;
+        let func = Func::new(ctx, name, ret_ty);
+        let memory = match self.u8()? {
+            0 => MemoryEffect::None,
+            1 => MemoryEffect::ReadOnly,
+            2 => MemoryEffect::ReadWrite,
+            tag => {
+                return Err(DeserializeError::InvalidTag {
+                    what: "memory effect",
+                    tag,
+                })
+            }
+        };
+        func.set_memory_effect(ctx, memory);
+        func.set_external(ctx, self.u8()? != 0);
+
+        let mut values = Vec::new();
+        for _ in 0..self.usize()? {
+            let ty = self.ty(ctx)?;
+            values.push(func.add_param(ctx, ty));
+        }
+
+        let mut records = Vec::new();
+        for _ in 0..self.usize()? {
+            let mut params = Vec::new();
+            for _ in 0..self.usize()? {
+                params.push(self.ty(ctx)?);
+            }
+            let mut insts = Vec::new();
+            for _ in 0..self.usize()? {
+                let kind = self.inst_kind(ctx)?;
+                let ty = self.ty(ctx)?;
+
+                let mut operands = Vec::new();
+                let mut incomings = Vec::new();
+                if matches!(kind, InstKind::Phi) {
+                    for _ in 0..self.usize()? {
+                        let block = self.usize()?;
+                        incomings.push((block, self.operand(ctx)?));
+                    }
+                } else {
+                    for _ in 0..self.usize()? {
+                        operands.push(self.operand(ctx)?);
+                    }
+                }
+
+                let mut successors = Vec::new();
+                for _ in 0..self.usize()? {
+                    let succ = self.usize()?;
+                    let mut args = Vec::new();
+                    for _ in 0..self.usize()? {
+                        args.push(self.operand(ctx)?);
+                    }
+                    successors.push((succ, args));
+                }
+
+                insts.push(InstRecord {
+                    kind,
+                    ty,
+                    operands,
+                    incomings,
+                    successors,
+                });
+            }
+            records.push((params, insts));
+        }
+
+        // Create all the blocks and instructions, so that all the values are
+        // available before resolving the operands.
+        let mut blocks = Vec::new();
+        let mut insts = Vec::new();
+        for (params, block_records) in records.iter() {
+            let block = Block::new(ctx);
+            func.push_back(ctx, block).unwrap();
+            blocks.push(block);
+            for &ty in params {
+                values.push(block.add_param(ctx, ty));
+            }
+            for record in block_records {
+                let inst = Inst::new(ctx, record.kind.clone(), record.ty);
+                block.push_back(ctx, inst).unwrap();
+                if let Some(result) = inst.result(ctx) {
+                    values.push(result);
+                }
+                insts.push(inst);
+            }
+        }
+
+        let resolve = |ctx: &mut Context, operand: OperandRef| match operand {
+            OperandRef::Local(idx) => values
+                .get(idx)
+                .copied()
+                .ok_or(DeserializeError::InvalidValueRef(idx)),
+            OperandRef::Constant(value) => Ok(Value::new_constant(ctx, value)),
+        };
+        let block_at = |idx: usize| {
+            blocks
+                .get(idx)
+                .copied()
+                .ok_or(DeserializeError::InvalidBlockRef(idx))
+        };
+
+        let records = records.into_iter().flat_map(|(_, insts)| insts);
+        for (inst, record) in insts.into_iter().zip(records) {
+            for operand in record.operands {
+                let value = resolve(ctx, operand)?;
+                inst.add_operand(ctx, value);
+            }
+            for (block, operand) in record.incomings {
+                let block = block_at(block)?;
+                let value = resolve(ctx, operand)?;
+                inst.insert_incoming(ctx, block, value);
+            }
+            for (i, (succ, args)) in record.successors.into_iter().enumerate() {
+                let succ = block_at(succ)?;
+                inst.add_successor(ctx, succ);
+                for arg in args {
+                    let value = resolve(ctx, arg)?;
+                    inst.push_successor_arg(ctx, i, value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Context {
+    /// Serialize the whole module into bytes.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut writer = Writer { buf: Vec::new() };
+        writer.buf.extend_from_slice(MAGIC);
+        writer.u8(VERSION);
+        writer.buf.extend_from_slice(&self.target.ptr_size.to_le_bytes());
+
+        writer.uleb(self.globals.iter().count() as u64);
+        for global in self.globals.iter() {
+            let global = global.self_ptr;
+            writer.str(global.name(self).as_str());
+            writer.constant(self, global.value(self));
+            writer.uleb(global.align_hint(self).unwrap_or(0) as u64);
+            writer.u8(global.is_const(self) as u8);
+            writer.u8(global.is_external(self) as u8);
+            writer.u8(match global.section_hint(self) {
+                None => 0,
+                Some(Section::Data) => 1,
+                Some(Section::Rodata) => 2,
+                Some(Section::Bss) => 3,
+            });
+        }
+
+        writer.uleb(self.funcs().count() as u64);
+        for func in self.funcs() {
+            writer.func(self, func);
+        }
+
+        writer.buf
+    }
+
+    /// Deserialize a module from bytes produced by [`Context::serialize`].
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(DeserializeError::InvalidMagic);
+        }
+
+        let mut reader = Reader {
+            bytes,
+            pos: MAGIC.len(),
+        };
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(DeserializeError::UnsupportedVersion(version));
+        }
+
+        let mut ptr_size = [0u8; 4];
+        for byte in ptr_size.iter_mut() {
+            *byte = reader.u8()?;
+        }
+
+        let mut ctx = Context::default();
+        ctx.set_target_info(TargetInfo {
+            ptr_size: u32::from_le_bytes(ptr_size),
+        });
+
+        for _ in 0..reader.usize()? {
+            let name = reader.str()?;
+            let value = reader.constant(&mut ctx)?;
+            let global = Global::new(&mut ctx, name, value);
+            match reader.usize()? {
+                0 => {}
+                align if align.is_power_of_two() => global.set_align(&mut ctx, align),
+                align => return Err(DeserializeError::InvalidAlign(align)),
+            }
+            global.set_const(&mut ctx, reader.u8()? != 0);
+            global.set_external(&mut ctx, reader.u8()? != 0);
+            let section = match reader.u8()? {
+                0 => None,
+                1 => Some(Section::Data),
+                2 => Some(Section::Rodata),
+                3 => Some(Section::Bss),
+                tag => return Err(DeserializeError::InvalidTag { what: "section", tag }),
+            };
+            global.set_section(&mut ctx, section);
+        }
+
+        for _ in 0..reader.usize()? {
+            reader.func(&mut ctx)?;
+        }
+
+        if reader.pos != bytes.len() {
+            return Err(DeserializeError::TrailingBytes(bytes.len() - reader.pos));
+        }
+
+        Ok(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_module() -> Context {
+        let mut ctx = Context::new(8);
+
+        let i32 = Ty::i32(&mut ctx);
+        let arr = Ty::array(&mut ctx, i32, 2);
+        let one = ConstantValue::i32(&mut ctx, 1);
+        let two = ConstantValue::i32(&mut ctx, -2);
+        let global = Global::new(
+            &mut ctx,
+            "arr".to_string(),
+            ConstantValue::Array {
+                ty: arr,
+                elems: vec![one, two],
+            },
+        );
+        global.set_const(&mut ctx, true);
+        global.set_align(&mut ctx, 16);
+
+        let func = Func::new(&mut ctx, "max".to_string(), i32);
+        func.set_external(&mut ctx, true);
+        let a = func.add_param(&mut ctx, i32);
+        let b = func.add_param(&mut ctx, i32);
+
+        let entry = Block::new(&mut ctx);
+        let then_block = Block::new(&mut ctx);
+        let exit = Block::new(&mut ctx);
+        func.push_back(&mut ctx, entry).unwrap();
+        func.push_back(&mut ctx, then_block).unwrap();
+        func.push_back(&mut ctx, exit).unwrap();
+
+        let cond = Inst::ibinary(
+            &mut ctx,
+            IntBinaryOp::ICmp {
+                cond: IntCmpCond::Slt,
+            },
+            a,
+            b,
+        );
+        let cond_val = cond.result(&ctx).unwrap();
+        let br = Inst::cond_br(&mut ctx, cond_val, then_block, exit);
+        entry.push_back(&mut ctx, cond).unwrap();
+        entry.push_back(&mut ctx, br).unwrap();
+
+        let jump = Inst::br(&mut ctx, exit);
+        then_block.push_back(&mut ctx, jump).unwrap();
+
+        let phi = Inst::phi(&mut ctx, i32);
+        phi.insert_incoming(&mut ctx, entry, a);
+        phi.insert_incoming(&mut ctx, then_block, b);
+        let phi_val = phi.result(&ctx).unwrap();
+        let ret = Inst::ret(&mut ctx, Some(phi_val));
+        exit.push_back(&mut ctx, phi).unwrap();
+        exit.push_back(&mut ctx, ret).unwrap();
+
+        ctx
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let ctx = build_module();
+        let bytes = ctx.serialize();
+
+        let loaded = Context::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.serialize(), bytes);
+
+        let global = loaded.globals.iter().next().unwrap().self_ptr;
+        assert!(global.is_const(&loaded));
+        assert_eq!(global.align(&loaded), 16);
+        assert_eq!(global.section(&loaded), Section::Rodata);
+
+        let func = loaded.funcs().next().unwrap();
+        assert_eq!(func.name(&loaded), "max");
+        assert!(func.is_external(&loaded));
+        assert_eq!(func.params(&loaded).len(), 2);
+        assert_eq!(func.iter(&loaded).count(), 3);
+
+        let exit = func.tail(&loaded).unwrap();
+        let phi = exit.head(&loaded).unwrap();
+        assert!(phi.is_phi(&loaded));
+        assert_eq!(phi.incoming_iter(&loaded).count(), 2);
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        assert_eq!(
+            Context::deserialize(b"ELF").err(),
+            Some(DeserializeError::InvalidMagic)
+        );
+        assert_eq!(
+            Context::deserialize(b"NKIR\x09").err(),
+            Some(DeserializeError::UnsupportedVersion(9))
+        );
+
+        let bytes = build_module().serialize();
+        assert_eq!(
+            Context::deserialize(&bytes[..bytes.len() - 1]).err(),
+            Some(DeserializeError::UnexpectedEof)
+        );
+
+        let header = [&MAGIC[..], &[VERSION], &8u32.to_le_bytes()].concat();
+        // the count of the globals, with 11 bytes
+        let bytes = [&header[..], &[0xff; 10], &[0x01]].concat();
+        assert_eq!(
+            Context::deserialize(&bytes).err(),
+            Some(DeserializeError::IntegerOverflow)
+        );
+        // the 10th byte with more than the last bit
+        let bytes = [&header[..], &[0xff; 9], &[0x02]].concat();
+        assert_eq!(
+            Context::deserialize(&bytes).err(),
+            Some(DeserializeError::IntegerOverflow)
+        );
+        // a global `@a` of an array of `u64::MAX` elements, then the end
+        let bytes = [&header[..], &[1, 1, b'a', 5, 3], &[0xff; 9], &[0x01]].concat();
+        assert_eq!(
+            Context::deserialize(&bytes).err(),
+            Some(DeserializeError::UnexpectedEof)
+        );
+    }
+}
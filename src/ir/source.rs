@@ -0,0 +1,82 @@
+//! The source code the IR is generated from.
+//!
+//! The instructions generated by the frontend are tagged with the byte offset
+//! of the statement they come from (see [`Inst::loc`](super::Inst::loc)). The
+//! offsets are resolved to lines with the [`Source`] kept in the context, e.g.,
+//! to interleave the statements with the emitted assembly.
+
+/// A source file, with the start offsets of its lines.
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// The name of the file.
+    file: String,
+    /// The text of the file.
+    text: String,
+    /// The byte offsets of the starts of the lines.
+    line_starts: Vec<usize>,
+}
+
+impl Source {
+    pub fn new(file: impl Into<String>, text: impl Into<String>) -> Self {
+        let text = text.into();
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            file: file.into(),
+            text,
+            line_starts,
+        }
+    }
+
+    pub fn file(&self) -> &str { &self.file }
+
+    pub fn text(&self) -> &str { &self.text }
+
+    /// Get the 1-based line number of a byte offset.
+    pub fn line(&self, offset: u32) -> usize {
+        self.line_starts
+            .partition_point(|&start| start <= offset as usize)
+    }
+
+    /// Get the 1-based column of a byte offset, counted in characters.
+    pub fn column(&self, offset: u32) -> usize {
+        let start = self.line_starts[self.line(offset) - 1];
+        self.text[start..offset as usize].chars().count() + 1
+    }
+
+    /// Get the text of a 1-based line, without the surrounding whitespace.
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.text.len());
+        self.text[start..end].trim()
+    }
+
+    /// Describe the line of a byte offset, as `file:line  statement`.
+    pub fn describe(&self, offset: u32) -> String {
+        let line = self.line(offset);
+        format!("{}:{}  {}", self.file, line, self.line_text(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_lines() {
+        let source = Source::new("a.sy", "int main() {\n  return 0;\n}");
+        assert_eq!(source.line(0), 1);
+        assert_eq!(source.line(12), 1);
+        assert_eq!(source.line(13), 2);
+        assert_eq!(source.line(25), 3);
+        assert_eq!(source.describe(15), "a.sy:2  return 0;");
+        assert_eq!(source.line_text(3), "}");
+        assert_eq!(source.column(0), 1);
+        assert_eq!(source.column(15), 3);
+    }
+}
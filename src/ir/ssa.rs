@@ -0,0 +1,264 @@
+//! Conversion between the SSA flavors.
+//!
+//! The IR supports two flavors of SSA form to merge values from different
+//! predecessors:
+//!
+//! - [`SsaFlavor::Phi`]: phi nodes at the beginning of blocks, each mapping the
+//!   predecessors to the incoming values.
+//! - [`SsaFlavor::BlockParam`]: blocks have parameters, like functions, and
+//!   branch instructions pass arguments to the parameters of the successors.
+//!
+//! Both flavors can be converted into each other, so passes can choose the one
+//! that fits best, e.g., block parameters do not need to be fixed up when the
+//! CFG edges are changed.
+
+use super::{Block, Context, Func, Inst, Usable};
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+
+/// The flavor of SSA form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SsaFlavor {
+    /// Phi nodes at the beginning of blocks.
+    #[default]
+    Phi,
+    /// Block parameters, passed by branch arguments.
+    BlockParam,
+}
+
+impl Func {
+    /// Convert all the phi nodes in the function into block parameters.
+    ///
+    /// Each phi becomes a parameter of its block, and the incoming values are
+    /// passed by the terminators of the predecessors.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if a predecessor of a phi has no terminator.
+    pub fn phis_to_block_params(self, ctx: &mut Context) {
+        let blocks = self.iter(ctx).collect::<Vec<_>>();
+        for block in blocks {
+            let phis = block
+                .iter(ctx)
+                .take_while(|inst| inst.is_phi(ctx))
+                .collect::<Vec<_>>();
+
+            for phi in phis {
+                let result = phi.result(ctx).unwrap();
+                let param = block.add_param(ctx, result.ty(ctx));
+
+                let mut incomings = phi.incoming_iter(ctx).collect::<Vec<_>>();
+                // Keep the order of the arguments deterministic.
+                incomings.sort_by_key(|(pred, _)| *pred);
+                for (pred, value) in incomings {
+                    let term = pred.tail(ctx).expect("predecessor without terminator");
+                    let succs = term.successor_iter(ctx).collect::<Vec<_>>();
+                    for (i, succ) in succs.into_iter().enumerate() {
+                        if succ == block {
+                            term.push_successor_arg(ctx, i, value);
+                        }
+                    }
+                }
+
+                result.replace_all_uses_with(ctx, param);
+                phi.remove(ctx);
+            }
+        }
+    }
+
+    /// Convert all the block parameters in the function into phi nodes.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if a branch passes a different number of arguments than the
+    ///   parameters of the destination.
+    pub fn block_params_to_phis(self, ctx: &mut Context) {
+        let blocks = self.iter(ctx).collect::<Vec<_>>();
+
+        // Arguments of a branch may be parameters of other blocks, so all the
+        // phis are created before any parameter is removed.
+        let mut converted = Vec::new();
+        let mut branches = Vec::new();
+        for block in blocks {
+            let params = block.params(ctx).to_vec();
+            if params.is_empty() {
+                continue;
+            }
+
+            let mut phis: Vec<Inst> = Vec::new();
+            for &param in params.iter() {
+                let phi = Inst::phi(ctx, param.ty(ctx));
+                match phis.last() {
+                    Some(last) => last.insert_after(ctx, phi).unwrap(),
+                    None => block.push_front(ctx, phi).unwrap(),
+                }
+                phis.push(phi);
+            }
+
+            let mut users = block.users(ctx).into_iter().collect::<Vec<_>>();
+            users.sort();
+            for user in users {
+                let term = user.inst();
+                let pred = term.container(ctx).expect("branch not in a block");
+                let args = term.successor_args(ctx, user.idx()).collect::<Vec<_>>();
+                assert_eq!(
+                    args.len(),
+                    phis.len(),
+                    "mismatched number of block arguments"
+                );
+                for (&phi, arg) in phis.iter().zip(args) {
+                    phi.insert_incoming(ctx, pred, arg);
+                }
+                branches.push(term);
+            }
+
+            converted.push((block, params, phis));
+        }
+
+        for (_, params, phis) in converted.iter() {
+            for (param, phi) in params.iter().zip(phis) {
+                param.replace_all_uses_with(ctx, phi.result(ctx).unwrap());
+            }
+        }
+
+        for term in branches {
+            term.clear_successor_args(ctx);
+        }
+
+        for (block, ..) in converted {
+            block.clear_params(ctx);
+        }
+    }
+
+    /// Convert the function into the given SSA flavor.
+    pub fn convert_ssa_flavor(self, ctx: &mut Context, flavor: SsaFlavor) {
+        match flavor {
+            SsaFlavor::Phi => self.block_params_to_phis(ctx),
+            SsaFlavor::BlockParam => self.phis_to_block_params(ctx),
+        }
+    }
+}
+
+impl Block {
+    /// Check if the block merges values in the given SSA flavor, i.e., it has
+    /// phi nodes or parameters.
+    pub fn has_merges(self, ctx: &Context, flavor: SsaFlavor) -> bool {
+        match flavor {
+            SsaFlavor::Phi => self.head(ctx).is_some_and(|inst| inst.is_phi(ctx)),
+            SsaFlavor::BlockParam => !self.params(ctx).is_empty(),
+        }
+    }
+}
+
+impl Context {
+    /// Convert all the functions into the given SSA flavor.
+    pub fn convert_ssa_flavor(&mut self, flavor: SsaFlavor) {
+        let funcs = self.funcs().collect::<Vec<_>>();
+        for func in funcs {
+            func.convert_ssa_flavor(self, flavor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{IntBinaryOp, IntCmpCond, Ty, Value};
+
+    /// Build a counting loop:
+    ///
+    /// ```text
+    /// entry: br loop
+    /// loop:  i = phi [0, entry], [next, loop]
+    ///        next = add i, 1
+    ///        cond = icmp slt next, n
+    ///        br cond, loop, exit
+    /// exit:  ret next
+    /// ```
+    fn build_loop(ctx: &mut Context) -> Func {
+        let i32 = Ty::i32(ctx);
+        let func = Func::new(ctx, "count".to_string(), i32);
+        let n = func.add_param(ctx, i32);
+
+        let entry = Block::new(ctx);
+        let body = Block::new(ctx);
+        let exit = Block::new(ctx);
+        func.push_back(ctx, entry).unwrap();
+        func.push_back(ctx, body).unwrap();
+        func.push_back(ctx, exit).unwrap();
+
+        let br = Inst::br(ctx, body);
+        entry.push_back(ctx, br).unwrap();
+
+        let phi = Inst::phi(ctx, i32);
+        let i = phi.result(ctx).unwrap();
+        let one = Value::i32(ctx, 1);
+        let add = Inst::ibinary(ctx, IntBinaryOp::Add, i, one);
+        let next = add.result(ctx).unwrap();
+        let cmp = Inst::ibinary(
+            ctx,
+            IntBinaryOp::ICmp {
+                cond: IntCmpCond::Slt,
+            },
+            next,
+            n,
+        );
+        let cond = cmp.result(ctx).unwrap();
+        let cond_br = Inst::cond_br(ctx, cond, body, exit);
+        body.push_back(ctx, phi).unwrap();
+        body.push_back(ctx, add).unwrap();
+        body.push_back(ctx, cmp).unwrap();
+        body.push_back(ctx, cond_br).unwrap();
+
+        let zero = Value::i32(ctx, 0);
+        phi.insert_incoming(ctx, entry, zero);
+        phi.insert_incoming(ctx, body, next);
+
+        let ret = Inst::ret(ctx, Some(next));
+        exit.push_back(ctx, ret).unwrap();
+
+        func
+    }
+
+    #[test]
+    fn test_ssa_flavor_roundtrip() {
+        let mut ctx = Context::new(8);
+        let func = build_loop(&mut ctx);
+        let blocks = func.iter(&ctx).collect::<Vec<_>>();
+        let (entry, body) = (blocks[0], blocks[1]);
+        assert!(body.has_merges(&ctx, SsaFlavor::Phi));
+
+        ctx.convert_ssa_flavor(SsaFlavor::BlockParam);
+        assert!(!body.has_merges(&ctx, SsaFlavor::Phi));
+        assert!(body.has_merges(&ctx, SsaFlavor::BlockParam));
+
+        let param = body.params(&ctx)[0];
+        let add = body.head(&ctx).unwrap();
+        assert_eq!(add.operand(&ctx, 0), param);
+
+        let br = entry.tail(&ctx).unwrap();
+        let args = br.successor_args(&ctx, 0).collect::<Vec<_>>();
+        assert_eq!(args.len(), 1);
+        assert_eq!(args[0].as_const_int(&ctx), Some(0));
+
+        let cond_br = body.tail(&ctx).unwrap();
+        let args = cond_br.successor_args(&ctx, 0).collect::<Vec<_>>();
+        assert_eq!(args, vec![add.result(&ctx).unwrap()]);
+        assert_eq!(cond_br.successor_args(&ctx, 1).count(), 0);
+
+        // The block parameters survive serialization.
+        let loaded = Context::deserialize(&ctx.serialize()).unwrap();
+        assert_eq!(loaded.serialize(), ctx.serialize());
+
+        ctx.convert_ssa_flavor(SsaFlavor::Phi);
+        assert!(body.params(&ctx).is_empty());
+        assert_eq!(br.successor_args(&ctx, 0).count(), 0);
+
+        let phi = body.head(&ctx).unwrap();
+        assert!(phi.is_phi(&ctx));
+        assert_eq!(phi.incoming(&ctx, entry).as_const_int(&ctx), Some(0));
+        assert_eq!(phi.incoming(&ctx, body), add.result(&ctx).unwrap());
+        assert_eq!(add.operand(&ctx, 0), phi.result(&ctx).unwrap());
+        // only the condition is left
+        assert_eq!(cond_br.operand_iter(&ctx).count(), 1);
+    }
+}
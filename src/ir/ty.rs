@@ -72,6 +72,12 @@ impl Ty {
     /// Fetch a type representing `i32`.
     pub fn i32(ctx: &mut Context) -> Self { ctx.alloc(TyData::Int32) }
 
+    /// Fetch a type representing `f32`.
+    pub fn f32(ctx: &mut Context) -> Self { ctx.alloc(TyData::Float32) }
+
+    /// Fetch a type representing `f64`.
+    pub fn f64(ctx: &mut Context) -> Self { ctx.alloc(TyData::Float64) }
+
     /// Fetch a type representing a pointer.
     pub fn ptr(ctx: &mut Context) -> Self { ctx.alloc(TyData::Ptr) }
 
@@ -98,6 +104,10 @@ impl Ty {
         )
     }
 
+    pub fn is_ptr(&self, ctx: &Context) -> bool {
+        matches!(self.try_deref(ctx).unwrap(), TyData::Ptr)
+    }
+
     /// Get the bit width of the type.
     pub fn bitwidth(&self, ctx: &Context) -> usize {
         match self.try_deref(ctx).unwrap() {
@@ -112,6 +122,26 @@ impl Ty {
         }
     }
 
+    /// Get the size of the type in bytes, i.e., the number of bytes it takes
+    /// in memory.
+    pub fn bytewidth(&self, ctx: &Context) -> usize {
+        match self.try_deref(ctx).unwrap() {
+            TyData::Array { elem, len } => elem.bytewidth(ctx) * len,
+            _ => self.bitwidth(ctx).div_ceil(8),
+        }
+    }
+
+    /// Get the natural alignment of the type in bytes.
+    pub fn align(&self, ctx: &Context) -> usize {
+        match self.try_deref(ctx).unwrap() {
+            TyData::Void | TyData::Int1 | TyData::Int8 => 1,
+            TyData::Int32 | TyData::Float32 => 4,
+            TyData::Float64 => 8,
+            TyData::Ptr => ctx.target.ptr_size as usize,
+            TyData::Array { elem, .. } => elem.align(ctx),
+        }
+    }
+
     /// Try to dereference the type as an array.
     pub fn as_array(&self, ctx: &Context) -> Option<(Ty, usize)> {
         match self.try_deref(ctx).unwrap() {
@@ -121,7 +151,7 @@ impl Ty {
     }
 
     /// Get the displayable type.
-    pub fn display(self, ctx: &Context) -> DisplayTy { DisplayTy { ctx, ty: self } }
+    pub fn display(self, ctx: &Context) -> DisplayTy<'_> { DisplayTy { ctx, ty: self } }
 }
 
 impl ArenaPtr for Ty {
@@ -1,13 +1,17 @@
-use std::collections::HashSet;
 use std::fmt;
 
+use super::block::Block;
 use super::context::Context;
 use super::def_use::{Usable, User};
 use super::func::Func;
-use super::inst::Inst;
+use super::inst::{Inst, IntBinaryOp};
 use super::ty::Ty;
+use crate::infra::hash::HashSet;
+use crate::infra::intern::Symbol;
+use crate::infra::linked_list::LinkedListNode;
 use crate::infra::storage::{Arena, ArenaPtr, GenericPtr, Idx};
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ConstantValue {
     /// The undefined value.
     Undef { ty: Ty },
@@ -19,6 +23,9 @@ pub enum ConstantValue {
     Int8 { ty: Ty, value: i8 },
     /// A 32-bit integer constant.
     Int32 { ty: Ty, value: i32 },
+    /// A single-precision floating-point constant, kept as the raw bits so
+    /// that the constants can be compared and hashed.
+    Float32 { ty: Ty, bits: u32 },
     /// An array constant.
     Array { ty: Ty, elems: Vec<ConstantValue> },
     /// Global variables/functions are treated as constants, because their
@@ -27,13 +34,26 @@ pub enum ConstantValue {
         /// The pointer type.
         ty: Ty,
         /// The name of the global variable/function.
-        name: String,
+        name: Symbol,
         /// The type of the value that the global variable/function points to.
         value_ty: Ty,
     },
 }
 
 impl ConstantValue {
+    /// Collect the names of the functions and globals the constant refers to.
+    pub fn collect_refs(&self, refs: &mut Vec<Symbol>) {
+        match self {
+            ConstantValue::GlobalRef { name, .. } => refs.push(*name),
+            ConstantValue::Array { elems, .. } => {
+                for elem in elems {
+                    elem.collect_refs(refs);
+                }
+            }
+            _ => {}
+        }
+    }
+
     pub fn ty(&self) -> Ty {
         match self {
             ConstantValue::Undef { ty } => *ty,
@@ -41,6 +61,7 @@ impl ConstantValue {
             ConstantValue::Int1 { ty, .. } => *ty,
             ConstantValue::Int8 { ty, .. } => *ty,
             ConstantValue::Int32 { ty, .. } => *ty,
+            ConstantValue::Float32 { ty, .. } => *ty,
             ConstantValue::Array { ty, .. } => *ty,
             ConstantValue::GlobalRef { ty, .. } => *ty,
         }
@@ -61,12 +82,119 @@ impl ConstantValue {
         ConstantValue::Int32 { ty: i32, value }
     }
 
-    pub fn global_ref(ctx: &mut Context, name: String, value_ty: Ty) -> ConstantValue {
+    pub fn f32(ctx: &mut Context, value: f32) -> ConstantValue {
+        let f32 = Ty::f32(ctx);
+        ConstantValue::Float32 {
+            ty: f32,
+            bits: value.to_bits(),
+        }
+    }
+
+    pub fn global_ref(ctx: &mut Context, name: impl Into<Symbol>, value_ty: Ty) -> ConstantValue {
         let ty = Ty::ptr(ctx);
+        let name = name.into();
         ConstantValue::GlobalRef { ty, name, value_ty }
     }
 
-    pub fn undef(ctx: &mut Context, ty: Ty) -> ConstantValue { ConstantValue::Undef { ty } }
+    pub fn undef(_ctx: &mut Context, ty: Ty) -> ConstantValue { ConstantValue::Undef { ty } }
+
+    /// Check if the constant is all zero bits.
+    ///
+    /// Undefined values are also considered zero, because they can be
+    /// materialized as anything.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            ConstantValue::Undef { .. } | ConstantValue::AggregateZero { .. } => true,
+            ConstantValue::Int1 { value, .. } => !value,
+            ConstantValue::Int8 { value, .. } => *value == 0,
+            ConstantValue::Int32 { value, .. } => *value == 0,
+            ConstantValue::Float32 { bits, .. } => *bits == 0,
+            ConstantValue::Array { elems, .. } => elems.iter().all(|elem| elem.is_zero()),
+            ConstantValue::GlobalRef { .. } => false,
+        }
+    }
+
+    /// Get the value of an integer constant, sign-extended to `i32`.
+    ///
+    /// `true` of `i1` is treated as `1`.
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            ConstantValue::Int1 { value, .. } => Some(*value as i32),
+            ConstantValue::Int8 { value, .. } => Some(*value as i32),
+            ConstantValue::Int32 { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Fold a binary operation on two integer constants.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(ConstantValue)`: The folded constant, arithmetic wraps around
+    ///   the bit width of the operands.
+    /// - `None`: The operands are not integer constants of the same type, or
+    ///   the result is undefined, e.g., division by zero, signed division
+    ///   overflow, or shifting by more than the bit width.
+    pub fn fold_binary(
+        ctx: &mut Context,
+        op: IntBinaryOp,
+        lhs: &ConstantValue,
+        rhs: &ConstantValue,
+    ) -> Option<ConstantValue> {
+        let (bits, lhs, rhs) = match (lhs, rhs) {
+            (ConstantValue::Int1 { value: a, .. }, ConstantValue::Int1 { value: b, .. }) => {
+                (1, *a as u64, *b as u64)
+            }
+            (ConstantValue::Int8 { value: a, .. }, ConstantValue::Int8 { value: b, .. }) => {
+                (8, *a as u8 as u64, *b as u8 as u64)
+            }
+            (ConstantValue::Int32 { value: a, .. }, ConstantValue::Int32 { value: b, .. }) => {
+                (32, *a as u32 as u64, *b as u32 as u64)
+            }
+            _ => return None,
+        };
+
+        let mask = (1u64 << bits) - 1;
+        // Sign-extend the raw bits into `i64`.
+        let sext = |v: u64| ((v << (64 - bits)) as i64) >> (64 - bits);
+        let (slhs, srhs) = (sext(lhs), sext(rhs));
+        let smin = sext(1 << (bits - 1));
+
+        let result = match op {
+            IntBinaryOp::Add => lhs.wrapping_add(rhs),
+            IntBinaryOp::Sub => lhs.wrapping_sub(rhs),
+            IntBinaryOp::Mul => lhs.wrapping_mul(rhs),
+            IntBinaryOp::SMulHi => ((slhs * srhs) >> bits) as u64,
+            IntBinaryOp::SDiv | IntBinaryOp::SRem if srhs == 0 || (slhs == smin && srhs == -1) => {
+                return None
+            }
+            IntBinaryOp::SDiv => (slhs / srhs) as u64,
+            IntBinaryOp::SRem => (slhs % srhs) as u64,
+            IntBinaryOp::UDiv | IntBinaryOp::URem if rhs == 0 => return None,
+            IntBinaryOp::UDiv => lhs / rhs,
+            IntBinaryOp::URem => lhs % rhs,
+            IntBinaryOp::Shl | IntBinaryOp::LShr | IntBinaryOp::AShr if rhs >= bits => return None,
+            IntBinaryOp::Shl => lhs << rhs,
+            IntBinaryOp::LShr => lhs >> rhs,
+            IntBinaryOp::AShr => (slhs >> rhs) as u64,
+            IntBinaryOp::And => lhs & rhs,
+            IntBinaryOp::Or => lhs | rhs,
+            IntBinaryOp::Xor => lhs ^ rhs,
+            IntBinaryOp::SMin => slhs.min(srhs) as u64,
+            IntBinaryOp::SMax => slhs.max(srhs) as u64,
+            IntBinaryOp::ICmp { cond } => {
+                let result = cond.eval(slhs, srhs);
+                return Some(ConstantValue::i1(ctx, result));
+            }
+        } & mask;
+
+        let value = match bits {
+            1 => ConstantValue::i1(ctx, result != 0),
+            8 => ConstantValue::i8(ctx, result as u8 as i8),
+            _ => ConstantValue::i32(ctx, result as u32 as i32),
+        };
+        Some(value)
+    }
 
     pub fn to_string(&self, ctx: &Context, typed: bool) -> String {
         let mut s = if typed {
@@ -81,6 +209,9 @@ impl ConstantValue {
             ConstantValue::Int1 { value, .. } => s.push_str(&value.to_string()),
             ConstantValue::Int8 { value, .. } => s.push_str(&value.to_string()),
             ConstantValue::Int32 { value, .. } => s.push_str(&value.to_string()),
+            ConstantValue::Float32 { bits, .. } => {
+                s.push_str(&format!("{:?}", f32::from_bits(*bits)))
+            }
             ConstantValue::Array { elems, .. } => {
                 s.push('[');
                 for (i, elem) in elems.iter().enumerate() {
@@ -93,7 +224,7 @@ impl ConstantValue {
             }
             ConstantValue::GlobalRef { name, .. } => {
                 s.push('@');
-                s.push_str(name);
+                s.push_str(name.as_str());
             }
         }
 
@@ -106,12 +237,14 @@ pub enum ValueKind {
     InstResult { inst: Inst, ty: Ty },
     /// The value is a function parameter.
     Param { func: Func, ty: Ty, index: u32 },
+    /// The value is a block parameter, passed by the branch instructions.
+    BlockParam { block: Block, ty: Ty, index: u32 },
     /// The value is an invariant constant.
     Constant { value: ConstantValue },
 }
 
 pub struct ValueData {
-    _self_ptr: Value,
+    pub(super) self_ptr: Value,
     pub kind: ValueKind,
     /// The user of this value.
     ///
@@ -132,7 +265,9 @@ pub struct DisplayValue<'ctx> {
 impl fmt::Display for DisplayValue<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.value.try_deref(self.ctx).unwrap().kind {
-            ValueKind::InstResult { ty, .. } | ValueKind::Param { ty, .. } => {
+            ValueKind::InstResult { ty, .. }
+            | ValueKind::Param { ty, .. }
+            | ValueKind::BlockParam { ty, .. } => {
                 // We use the arena index directly as the value number. This is not a good way
                 // to number values in a real compiler, but only for debugging purposes.
                 if self.with_type {
@@ -151,9 +286,9 @@ impl fmt::Display for DisplayValue<'_> {
 impl Value {
     fn new(ctx: &mut Context, kind: ValueKind) -> Self {
         ctx.alloc_with(|self_ptr| ValueData {
-            _self_ptr: self_ptr,
+            self_ptr,
             kind,
-            users: HashSet::new(),
+            users: HashSet::default(),
         })
     }
 
@@ -161,6 +296,7 @@ impl Value {
         match self.try_deref(ctx).unwrap().kind {
             ValueKind::InstResult { ty, .. } => ty,
             ValueKind::Param { ty, .. } => ty,
+            ValueKind::BlockParam { ty, .. } => ty,
             ValueKind::Constant { ref value } => value.ty(),
         }
     }
@@ -169,11 +305,19 @@ impl Value {
         Self::new(ctx, ValueKind::Param { func, ty, index })
     }
 
+    pub(super) fn new_block_param(ctx: &mut Context, block: Block, ty: Ty, index: u32) -> Self {
+        Self::new(ctx, ValueKind::BlockParam { block, ty, index })
+    }
+
     pub(super) fn new_inst_result(ctx: &mut Context, inst: Inst, ty: Ty) -> Self {
         Self::new(ctx, ValueKind::InstResult { inst, ty })
     }
 
-    pub fn display(self, ctx: &Context, with_type: bool) -> DisplayValue {
+    pub(super) fn new_constant(ctx: &mut Context, value: ConstantValue) -> Self {
+        Self::new(ctx, ValueKind::Constant { value })
+    }
+
+    pub fn display(self, ctx: &Context, with_type: bool) -> DisplayValue<'_> {
         DisplayValue {
             ctx,
             value: self,
@@ -185,6 +329,30 @@ impl Value {
         matches!(self.try_deref(ctx).unwrap().kind, ValueKind::Param { .. })
     }
 
+    pub fn is_block_param(&self, ctx: &Context) -> bool {
+        matches!(
+            self.try_deref(ctx).unwrap().kind,
+            ValueKind::BlockParam { .. }
+        )
+    }
+
+    /// Get the instruction that defines this value, if it is an instruction
+    /// result.
+    pub fn def_inst(self, ctx: &Context) -> Option<Inst> {
+        match self.deref(ctx).kind {
+            ValueKind::InstResult { inst, .. } => Some(inst),
+            _ => None,
+        }
+    }
+
+    /// Replace all the uses of this value with `new`.
+    pub fn replace_all_uses_with(self, ctx: &mut Context, new: Value) {
+        let users = self.users(ctx).into_iter().collect::<Vec<_>>();
+        for user in users {
+            user.inst().set_operand(ctx, user.idx(), new);
+        }
+    }
+
     pub fn i1(ctx: &mut Context, value: bool) -> Self {
         let value = ConstantValue::i1(ctx, value);
         Self::new(ctx, ValueKind::Constant { value })
@@ -200,7 +368,27 @@ impl Value {
         Self::new(ctx, ValueKind::Constant { value })
     }
 
-    pub fn global_ref(ctx: &mut Context, name: String, value_ty: Ty) -> Self {
+    pub fn f32(ctx: &mut Context, value: f32) -> Self {
+        let value = ConstantValue::f32(ctx, value);
+        Self::new(ctx, ValueKind::Constant { value })
+    }
+
+    /// Create an integer constant of the given type, truncating the value to
+    /// its bit width.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the type is not an integer type.
+    pub fn int(ctx: &mut Context, ty: Ty, value: i64) -> Self {
+        match ty.bitwidth(ctx) {
+            1 if ty.is_integer(ctx) => Self::i1(ctx, value & 1 != 0),
+            8 if ty.is_integer(ctx) => Self::i8(ctx, value as i8),
+            32 if ty.is_integer(ctx) => Self::i32(ctx, value as i32),
+            _ => panic!("not an integer type"),
+        }
+    }
+
+    pub fn global_ref(ctx: &mut Context, name: impl Into<Symbol>, value_ty: Ty) -> Self {
         let value = ConstantValue::global_ref(ctx, name, value_ty);
         Self::new(ctx, ValueKind::Constant { value })
     }
@@ -209,6 +397,60 @@ impl Value {
         let value = ConstantValue::undef(ctx, ty);
         Self::new(ctx, ValueKind::Constant { value })
     }
+
+    /// Get the constant if the value is a constant.
+    pub fn as_const(self, ctx: &Context) -> Option<&ConstantValue> {
+        match &self.deref(ctx).kind {
+            ValueKind::Constant { value } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the value of an integer constant, sign-extended to `i32`.
+    pub fn as_const_int(self, ctx: &Context) -> Option<i32> {
+        self.as_const(ctx).and_then(ConstantValue::as_int)
+    }
+
+    /// Check if the value is the integer constant `0`.
+    pub fn is_zero(self, ctx: &Context) -> bool { self.as_const_int(ctx) == Some(0) }
+
+    /// Check if the value is the integer constant `1`.
+    pub fn is_one(self, ctx: &Context) -> bool { self.as_const_int(ctx) == Some(1) }
+
+    /// Check if the value is a positive integer constant that is a power of
+    /// two.
+    pub fn is_power_of_two(self, ctx: &Context) -> bool {
+        self.as_const_int(ctx)
+            .is_some_and(|v| v > 0 && (v as u32).is_power_of_two())
+    }
+
+    /// Fold a binary operation on two constant values into a new constant.
+    ///
+    /// See [`ConstantValue::fold_binary`] for the folding rules.
+    pub fn fold_binary(ctx: &mut Context, op: IntBinaryOp, lhs: Value, rhs: Value) -> Option<Value> {
+        // Only integer constants can be folded, which are cheap to clone.
+        let lhs = lhs.as_const(ctx).filter(|c| c.as_int().is_some())?.clone();
+        let rhs = rhs.as_const(ctx).filter(|c| c.as_int().is_some())?.clone();
+        let value = ConstantValue::fold_binary(ctx, op, &lhs, &rhs)?;
+        Some(Self::new(ctx, ValueKind::Constant { value }))
+    }
+
+    /// Compute a binary operation before an instruction, folding constants,
+    /// i.e., the operation is only inserted if it cannot be folded.
+    pub fn binary_before(
+        ctx: &mut Context,
+        before: Inst,
+        op: IntBinaryOp,
+        lhs: Value,
+        rhs: Value,
+    ) -> Value {
+        if let Some(folded) = Self::fold_binary(ctx, op, lhs, rhs) {
+            return folded;
+        }
+        let inst = Inst::ibinary(ctx, op, lhs, rhs);
+        before.insert_before(ctx, inst).unwrap();
+        inst.result(ctx).unwrap()
+    }
 }
 
 impl ArenaPtr for Value {
@@ -246,3 +488,72 @@ impl Usable for Value {
         self.try_deref_mut(arena).unwrap().users.remove(&user);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::IntCmpCond;
+
+    #[test]
+    fn test_value_const_queries() {
+        let mut ctx = Context::default();
+        let zero = Value::i32(&mut ctx, 0);
+        let one = Value::i1(&mut ctx, true);
+        let eight = Value::i32(&mut ctx, 8);
+        let neg = Value::i32(&mut ctx, i32::MIN);
+
+        assert!(zero.is_zero(&ctx));
+        assert!(one.is_one(&ctx));
+        assert!(eight.is_power_of_two(&ctx));
+        assert!(!neg.is_power_of_two(&ctx));
+        assert!(!zero.is_power_of_two(&ctx));
+        assert_eq!(eight.as_const_int(&ctx), Some(8));
+
+        let ty = Ty::i32(&mut ctx);
+        let undef = Value::undef(&mut ctx, ty);
+        assert_eq!(undef.as_const_int(&ctx), None);
+        assert!(!undef.is_zero(&ctx));
+    }
+
+    #[test]
+    fn test_value_fold_binary() {
+        let mut ctx = Context::default();
+        let max = Value::i32(&mut ctx, i32::MAX);
+        let one = Value::i32(&mut ctx, 1);
+        let zero = Value::i32(&mut ctx, 0);
+        let minus_one = Value::i32(&mut ctx, -1);
+        let min = Value::i32(&mut ctx, i32::MIN);
+        let shift = Value::i32(&mut ctx, 32);
+
+        let int = |ctx: &mut Context, op, lhs, rhs| {
+            Value::fold_binary(ctx, op, lhs, rhs).and_then(|v| v.as_const_int(ctx))
+        };
+
+        assert_eq!(int(&mut ctx, IntBinaryOp::Add, max, one), Some(i32::MIN));
+        assert_eq!(int(&mut ctx, IntBinaryOp::Sub, zero, one), Some(-1));
+        assert_eq!(int(&mut ctx, IntBinaryOp::SDiv, minus_one, one), Some(-1));
+        assert_eq!(int(&mut ctx, IntBinaryOp::SDiv, one, zero), None);
+        assert_eq!(int(&mut ctx, IntBinaryOp::SDiv, min, minus_one), None);
+        assert_eq!(int(&mut ctx, IntBinaryOp::UDiv, minus_one, max), Some(2));
+        assert_eq!(int(&mut ctx, IntBinaryOp::SRem, minus_one, max), Some(-1));
+        assert_eq!(int(&mut ctx, IntBinaryOp::AShr, min, one), Some(i32::MIN / 2));
+        assert_eq!(int(&mut ctx, IntBinaryOp::LShr, min, one), Some(1 << 30));
+        assert_eq!(int(&mut ctx, IntBinaryOp::Shl, one, shift), None);
+        assert_eq!(int(&mut ctx, IntBinaryOp::Xor, minus_one, max), Some(i32::MIN));
+        assert_eq!(int(&mut ctx, IntBinaryOp::SMulHi, min, min), Some(1 << 30));
+        assert_eq!(int(&mut ctx, IntBinaryOp::SMulHi, minus_one, one), Some(-1));
+        assert_eq!(int(&mut ctx, IntBinaryOp::SMin, minus_one, max), Some(-1));
+        assert_eq!(int(&mut ctx, IntBinaryOp::SMax, min, one), Some(1));
+
+        let slt = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        assert_eq!(int(&mut ctx, slt, minus_one, zero), Some(1));
+
+        let byte = Value::i8(&mut ctx, 127);
+        let byte_one = Value::i8(&mut ctx, 1);
+        assert_eq!(int(&mut ctx, IntBinaryOp::Add, byte, byte_one), Some(-128));
+        // mismatched types are not folded
+        assert_eq!(int(&mut ctx, IntBinaryOp::Add, byte, one), None);
+    }
+}
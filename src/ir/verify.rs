@@ -0,0 +1,479 @@
+//! The verifier of the IR invariants.
+//!
+//! The passes assume well-formed IR, and a pass breaking it usually shows up
+//! passes later, or as a miscompilation. [`Context::verify`] checks each
+//! function:
+//!
+//! - the blocks end with a terminator, and only there;
+//! - the phis are at the start of the blocks, with an incoming value from each
+//!   predecessor and from nothing else;
+//! - the instructions and the blocks are linked to their containers, and the
+//!   successors are blocks of the same function;
+//! - the operands are alive, defined in the same function, and recorded as used
+//!   by the instruction;
+//! - the definitions dominate the uses, the incoming values of a phi the ends
+//!   of the incoming blocks, in the reachable blocks;
+//! - the operands have the types the instructions expect, e.g., the operands of
+//!   a binary operation have the type of its result, and the branch arguments
+//!   the types of the block parameters.
+//!
+//! With `--verify-each`, the module is verified after each pass, see
+//! [`PassManager::set_verify_each`](super::passman::PassManager::set_verify_each).
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use rayon::prelude::*;
+
+use super::analysis::{Cfg, DomTree};
+use super::{
+    Block,
+    Context,
+    FloatBinaryOp,
+    Func,
+    Inst,
+    InstKind,
+    IntBinaryOp,
+    Ty,
+    Usable,
+    Value,
+    ValueKind,
+};
+use crate::infra::linked_list::{LinkedListContainer, LinkedListNode};
+use crate::infra::storage::ArenaPtr;
+
+/// The invariants broken, found by the verifier.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Problem {
+    #[error("the block does not end with a terminator")]
+    MissingTerminator,
+    #[error("the terminator is followed by another instruction")]
+    NotLast,
+    #[error("the phi is not at the start of the block")]
+    MisplacedPhi,
+    #[error("the incoming blocks are [{found}], but the predecessors are [{expected}]")]
+    WrongIncoming { expected: String, found: String },
+    #[error("the instruction or the block is linked to another container")]
+    WrongContainer,
+    #[error("an operand is deallocated")]
+    DeadValue,
+    #[error("`{0}` is defined in another function")]
+    ForeignValue(String),
+    #[error("the successor {0} is not a block of the function")]
+    ForeignBlock(String),
+    #[error("the definition of `{0}` does not dominate the use")]
+    NotDominated(String),
+    #[error("the use of `{0}` is not recorded")]
+    UnrecordedUse(String),
+    #[error("`{value}` is not of type `{expected}`")]
+    WrongType { value: String, expected: String },
+    #[error("{found} arguments are passed to {expected} parameters")]
+    WrongArgCount { expected: usize, found: usize },
+}
+
+/// An error found by the verifier, in a block or an instruction.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub struct VerifyError {
+    /// The name of the function.
+    pub func: String,
+    /// The name of the block.
+    pub block: String,
+    /// The instruction, as printed in the IR, if the problem is in one.
+    pub inst: Option<String>,
+    pub problem: Problem,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(inst) = &self.inst {
+            write!(f, "`{}` in ", inst)?;
+        }
+        write!(f, "{} of @{}: {}", self.block, self.func, self.problem)
+    }
+}
+
+impl Context {
+    /// Check the invariants of all the functions, and get the error of the
+    /// first one broken.
+    ///
+    /// The functions are checked in parallel, on the threads of `--jobs`.
+    pub fn verify(&self) -> Result<(), Box<VerifyError>> {
+        let funcs = self
+            .funcs()
+            .filter(|func| !func.is_declaration(self))
+            .collect::<Vec<_>>();
+        let results = funcs
+            .par_iter()
+            .map(|&func| Verifier::new(self, func).verify())
+            .collect::<Vec<_>>();
+        results.into_iter().collect()
+    }
+}
+
+struct Verifier<'a> {
+    ctx: &'a Context,
+    func: Func,
+    cfg: Cfg,
+    dom: DomTree,
+    /// The position of each instruction in its block.
+    positions: HashMap<Inst, usize>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(ctx: &'a Context, func: Func) -> Self {
+        let cfg = Cfg::new(ctx, func);
+        let dom = DomTree::new(&cfg);
+        let positions = func
+            .iter(ctx)
+            .flat_map(|block| block.iter(ctx).enumerate())
+            .map(|(pos, inst)| (inst, pos))
+            .collect();
+        Self {
+            ctx,
+            func,
+            cfg,
+            dom,
+            positions,
+        }
+    }
+
+    fn verify(&self) -> Result<(), Box<VerifyError>> {
+        let ctx = self.ctx;
+        for block in self.func.iter(ctx) {
+            let err = |inst: Option<Inst>, problem| {
+                Box::new(VerifyError {
+                    func: self.func.name(ctx).to_string(),
+                    block: block.name(ctx),
+                    inst: inst.map(|inst| inst.display(ctx).to_string()),
+                    problem,
+                })
+            };
+            if block.container(ctx) != Some(self.func) {
+                return Err(err(None, Problem::WrongContainer));
+            }
+            if !block.tail(ctx).is_some_and(|tail| tail.is_terminator(ctx)) {
+                return Err(err(None, Problem::MissingTerminator));
+            }
+            let mut after_phis = false;
+            for inst in block.iter(ctx) {
+                if inst.container(ctx) != Some(block) {
+                    return Err(err(Some(inst), Problem::WrongContainer));
+                }
+                if inst.is_terminator(ctx) && block.tail(ctx) != Some(inst) {
+                    return Err(err(Some(inst), Problem::NotLast));
+                }
+                if inst.is_phi(ctx) && after_phis {
+                    return Err(err(Some(inst), Problem::MisplacedPhi));
+                }
+                after_phis |= !inst.is_phi(ctx);
+                self.check_inst(block, inst)
+                    .map_err(|problem| err(Some(inst), problem))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the operands and the successors of an instruction.
+    fn check_inst(&self, block: Block, inst: Inst) -> Result<(), Problem> {
+        let ctx = self.ctx;
+        let name = |value: Value| value.display(ctx, false).to_string();
+        let operands = match inst.is_phi(ctx) {
+            true => inst
+                .incoming_iter(ctx)
+                .map(|(pred, value)| (value, Some(pred)))
+                .collect::<Vec<_>>(),
+            false => inst.operand_iter(ctx).map(|value| (value, None)).collect(),
+        };
+        for &(value, pred) in operands.iter() {
+            if value.try_deref(ctx).is_none() {
+                return Err(Problem::DeadValue);
+            }
+            if !value.users(ctx).into_iter().any(|user| user.inst() == inst) {
+                return Err(Problem::UnrecordedUse(name(value)));
+            }
+            // the value is used at the end of the incoming block of a phi
+            let (use_block, use_pos) = match pred {
+                Some(pred) => (pred, usize::MAX),
+                None => (block, self.positions[&inst]),
+            };
+            self.check_def(value, use_block, use_pos)?;
+        }
+
+        for succ in inst.successor_iter(ctx) {
+            if succ.container(ctx) != Some(self.func) {
+                return Err(Problem::ForeignBlock(succ.name(ctx)));
+            }
+        }
+        if inst.is_phi(ctx) {
+            let expected = self.cfg.preds(block).to_vec();
+            let found = operands
+                .iter()
+                .filter_map(|&(_, pred)| pred)
+                .collect::<Vec<_>>();
+            if expected.iter().collect::<HashSet<_>>() != found.iter().collect() {
+                let names = |blocks: &[Block]| {
+                    let mut names = blocks
+                        .iter()
+                        .map(|block| block.name(ctx))
+                        .collect::<Vec<_>>();
+                    names.sort();
+                    names.join(", ")
+                };
+                return Err(Problem::WrongIncoming {
+                    expected: names(&expected),
+                    found: names(&found),
+                });
+            }
+        }
+        self.check_types(inst)
+    }
+
+    /// Check that a value is available at a position in a block, i.e., its
+    /// definition dominates it.
+    fn check_def(&self, value: Value, block: Block, pos: usize) -> Result<(), Problem> {
+        let ctx = self.ctx;
+        let name = || value.display(ctx, false).to_string();
+        let (def_block, def_pos) = match value.deref(ctx).kind {
+            ValueKind::InstResult { inst, .. } => match inst.container(ctx) {
+                Some(def_block) if self.positions.contains_key(&inst) => {
+                    (def_block, Some(self.positions[&inst]))
+                }
+                _ => return Err(Problem::ForeignValue(name())),
+            },
+            ValueKind::Param { func, .. } if func == self.func => return Ok(()),
+            ValueKind::Param { .. } => return Err(Problem::ForeignValue(name())),
+            ValueKind::BlockParam { block, .. } => match block.container(ctx) {
+                Some(func) if func == self.func => (block, None),
+                _ => return Err(Problem::ForeignValue(name())),
+            },
+            ValueKind::Constant { .. } => return Ok(()),
+        };
+        // anything goes in the unreachable code
+        if !self.cfg.is_reachable(block) {
+            return Ok(());
+        }
+        let dominates = match def_block == block {
+            true => def_pos.is_none_or(|def_pos| def_pos < pos),
+            false => self.dom.dominates(def_block, block),
+        };
+        match dominates && self.cfg.is_reachable(def_block) {
+            true => Ok(()),
+            false => Err(Problem::NotDominated(name())),
+        }
+    }
+
+    /// Check the types of the operands of an instruction.
+    fn check_types(&self, inst: Inst) -> Result<(), Problem> {
+        let ctx = self.ctx;
+        let check = |value: Value, ok: bool, expected: &dyn fmt::Display| match ok {
+            true => Ok(()),
+            false => Err(Problem::WrongType {
+                value: value.display(ctx, true).to_string(),
+                expected: expected.to_string(),
+            }),
+        };
+        let expect = |value: Value, ty: Ty| check(value, value.ty(ctx) == ty, &ty.display(ctx));
+        let expect_i1 = |value: Value| {
+            let ty = value.ty(ctx);
+            check(value, ty.is_integer(ctx) && ty.bitwidth(ctx) == 1, &"i1")
+        };
+        let expect_int = |value: Value| check(value, value.ty(ctx).is_integer(ctx), &"an integer");
+        let expect_ptr = |value: Value| check(value, value.ty(ctx).is_ptr(ctx), &"ptr");
+        let result_ty = inst.result(ctx).map(|result| result.ty(ctx));
+        match *inst.kind(ctx) {
+            InstKind::Phi => {
+                for (_, value) in inst.incoming_iter(ctx) {
+                    expect(value, result_ty.unwrap())?;
+                }
+            }
+            InstKind::Load => expect_ptr(inst.operand(ctx, 0))?,
+            InstKind::Store => expect_ptr(inst.operand(ctx, 1))?,
+            InstKind::IntBinary { op } => {
+                let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+                expect(rhs, lhs.ty(ctx))?;
+                // the comparisons are built with the type of the operands, but
+                // folded to `i1` constants
+                match op {
+                    IntBinaryOp::ICmp { .. } => expect_int(inst.result(ctx).unwrap())?,
+                    _ => expect(inst.result(ctx).unwrap(), lhs.ty(ctx))?,
+                }
+            }
+            InstKind::FloatBinary { op } => {
+                let (lhs, rhs) = (inst.operand(ctx, 0), inst.operand(ctx, 1));
+                expect(rhs, lhs.ty(ctx))?;
+                match op {
+                    FloatBinaryOp::FCmp { .. } => expect_i1(inst.result(ctx).unwrap())?,
+                    _ => expect(inst.result(ctx).unwrap(), lhs.ty(ctx))?,
+                }
+            }
+            InstKind::CondBr | InstKind::Switch { .. } => expect_int(inst.operand(ctx, 0))?,
+            InstKind::Ret => {
+                let ret_ty = self.func.ret_ty(ctx);
+                match inst.operand_iter(ctx).next() {
+                    Some(value) => expect(value, ret_ty)?,
+                    None if ret_ty.is_void(ctx) => {}
+                    None => {
+                        return Err(Problem::WrongArgCount {
+                            expected: 1,
+                            found: 0,
+                        })
+                    }
+                }
+            }
+            InstKind::Call => {
+                if let Some(callee) = ctx.func_by_name(inst.callee(ctx)) {
+                    let args = inst.operand_iter(ctx).skip(1).collect::<Vec<_>>();
+                    let params = callee.params(ctx);
+                    if args.len() != params.len() {
+                        return Err(Problem::WrongArgCount {
+                            expected: params.len(),
+                            found: args.len(),
+                        });
+                    }
+                    for (&arg, param) in args.iter().zip(params) {
+                        expect(arg, param.ty(ctx))?;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        for (idx, succ) in inst.successor_iter(ctx).enumerate() {
+            let args = inst.successor_args(ctx, idx).collect::<Vec<_>>();
+            let params = succ.params(ctx);
+            if args.len() != params.len() {
+                return Err(Problem::WrongArgCount {
+                    expected: params.len(),
+                    found: args.len(),
+                });
+            }
+            for (&arg, param) in args.iter().zip(params) {
+                expect(arg, param.ty(ctx))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::IntCmpCond;
+
+    /// The parts of `max(a, 0)` broken by the tests.
+    struct Max {
+        ctx: Context,
+        then: Block,
+        cond_br: Inst,
+        phi: Inst,
+        a: Value,
+    }
+
+    fn build_max() -> Max {
+        let mut ctx = Context::default();
+        let i32 = Ty::i32(&mut ctx);
+        let func = Func::new(&mut ctx, "max".to_string(), i32);
+        let a = func.add_param(&mut ctx, i32);
+        let [entry, then, exit] = [(); 3].map(|_| Block::new(&mut ctx));
+        for block in [entry, then, exit] {
+            func.push_back(&mut ctx, block).unwrap();
+        }
+        let zero = Value::i32(&mut ctx, 0);
+        let cmp = IntBinaryOp::ICmp {
+            cond: IntCmpCond::Slt,
+        };
+        let cmp = Inst::ibinary(&mut ctx, cmp, a, zero);
+        let cond = cmp.result(&ctx).unwrap();
+        let cond_br = Inst::cond_br(&mut ctx, cond, then, exit);
+        let br = Inst::br(&mut ctx, exit);
+        let phi = Inst::phi(&mut ctx, i32);
+        phi.insert_incoming(&mut ctx, entry, a);
+        phi.insert_incoming(&mut ctx, then, zero);
+        let result = phi.result(&ctx);
+        let ret = Inst::ret(&mut ctx, result);
+        for (block, inst) in [
+            (entry, cmp),
+            (entry, cond_br),
+            (then, br),
+            (exit, phi),
+            (exit, ret),
+        ] {
+            block.push_back(&mut ctx, inst).unwrap();
+        }
+        Max {
+            ctx,
+            then,
+            cond_br,
+            phi,
+            a,
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let max = build_max();
+        assert_eq!(max.ctx.verify(), Ok(()));
+
+        let Max { mut ctx, then, .. } = build_max();
+        let br = then.tail(&ctx).unwrap();
+        br.remove(&mut ctx);
+        let err = ctx.verify().unwrap_err();
+        assert_eq!(err.problem, Problem::MissingTerminator);
+        assert_eq!((err.func.as_str(), err.inst.as_deref()), ("max", None));
+        assert_eq!(err.block, then.name(&ctx));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} of @max: the block does not end with a terminator",
+                then.name(&ctx)
+            )
+        );
+
+        let Max {
+            mut ctx, then, phi, ..
+        } = build_max();
+        phi.remove_incoming(&mut ctx, then);
+        let err = ctx.verify().unwrap_err();
+        assert!(matches!(err.problem, Problem::WrongIncoming { .. }));
+        assert_eq!(err.inst, Some(phi.display(&ctx).to_string()));
+    }
+
+    #[test]
+    fn test_verify_operands() {
+        let Max {
+            mut ctx,
+            cond_br,
+            a,
+            ..
+        } = build_max();
+        let i32 = a.ty(&ctx);
+        let ptr = Value::global_ref(&mut ctx, "g".to_string(), i32);
+        cond_br.set_operand(&mut ctx, 0, ptr);
+        let err = ctx.verify().unwrap_err();
+        assert_eq!(
+            err.problem,
+            Problem::WrongType {
+                value: "ptr @g".to_string(),
+                expected: "an integer".to_string(),
+            }
+        );
+
+        // the result of the phi is used in a block it does not dominate
+        let Max {
+            mut ctx,
+            then,
+            phi,
+            a,
+            ..
+        } = build_max();
+        let sum = phi.result(&ctx).unwrap();
+        let add = Inst::ibinary(&mut ctx, IntBinaryOp::Add, a, sum);
+        then.push_front(&mut ctx, add).unwrap();
+        let err = ctx.verify().unwrap_err();
+        assert_eq!(err.block, then.name(&ctx));
+        assert_eq!(
+            err.problem,
+            Problem::NotDominated(sum.display(&ctx, false).to_string())
+        );
+    }
+}
@@ -1,4 +1,6 @@
 pub mod backend;
+pub mod config;
+pub mod driver;
 pub mod frontend;
 pub mod infra;
 pub mod ir;
@@ -0,0 +1,178 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <stdarg.h>
+#include <sys/time.h>
+#include "sylib.h"
+/* Timing states, declared in the header */
+struct timeval _sysy_start, _sysy_end;
+int _sysy_l1[_SYSY_N], _sysy_l2[_SYSY_N];
+int _sysy_h[_SYSY_N], _sysy_m[_SYSY_N], _sysy_s[_SYSY_N], _sysy_us[_SYSY_N];
+int _sysy_idx;
+/* Input & output functions */
+int getint()
+{
+  int t;
+  scanf("%d", &t);
+  return t;
+}
+int getch()
+{
+  char c;
+  scanf("%c", &c);
+  return (int)c;
+}
+float getfloat()
+{
+  float n;
+  scanf("%a", &n);
+  return n;
+}
+
+int getarray(int a[])
+{
+  int n;
+  scanf("%d", &n);
+  for (int i = 0; i < n; i++)
+    scanf("%d", &a[i]);
+  return n;
+}
+
+int getfarray(float a[])
+{
+  int n;
+  scanf("%d", &n);
+  for (int i = 0; i < n; i++)
+  {
+    scanf("%a", &a[i]);
+  }
+  return n;
+}
+void putint(int a) { printf("%d", a); }
+void putch(int a) { printf("%c", a); }
+void putarray(int n, int a[])
+{
+  printf("%d:", n);
+  for (int i = 0; i < n; i++)
+    printf(" %d", a[i]);
+  printf("\n");
+}
+void putfloat(float a)
+{
+  printf("%a", a);
+}
+void putfarray(int n, float a[])
+{
+  printf("%d:", n);
+  for (int i = 0; i < n; i++)
+  {
+    printf(" %a", a[i]);
+  }
+  printf("\n");
+}
+
+void putf(char a[], ...)
+{
+  va_list args;
+  va_start(args, a);
+  vfprintf(stdout, a, args);
+  va_end(args);
+}
+
+/* Timing function implementation */
+__attribute((constructor)) void before_main()
+{
+  for (int i = 0; i < _SYSY_N; i++)
+    _sysy_h[i] = _sysy_m[i] = _sysy_s[i] = _sysy_us[i] = 0;
+  _sysy_idx = 1;
+}
+__attribute((destructor)) void after_main()
+{
+  for (int i = 1; i < _sysy_idx; i++)
+  {
+    fprintf(stderr, "Timer@%04d-%04d: %dH-%dM-%dS-%dus\n",
+            _sysy_l1[i], _sysy_l2[i], _sysy_h[i], _sysy_m[i], _sysy_s[i], _sysy_us[i]);
+    _sysy_us[0] += _sysy_us[i];
+    _sysy_s[0] += _sysy_s[i];
+    _sysy_us[0] %= 1000000;
+    _sysy_m[0] += _sysy_m[i];
+    _sysy_s[0] %= 60;
+    _sysy_h[0] += _sysy_h[i];
+    _sysy_m[0] %= 60;
+  }
+  fprintf(stderr, "TOTAL: %dH-%dM-%dS-%dus\n", _sysy_h[0], _sysy_m[0], _sysy_s[0], _sysy_us[0]);
+}
+void _sysy_starttime(int lineno)
+{
+  _sysy_l1[_sysy_idx] = lineno;
+  gettimeofday(&_sysy_start, NULL);
+}
+void _sysy_stoptime(int lineno)
+{
+  gettimeofday(&_sysy_end, NULL);
+  _sysy_l2[_sysy_idx] = lineno;
+  _sysy_us[_sysy_idx] += 1000000 * (_sysy_end.tv_sec - _sysy_start.tv_sec) + _sysy_end.tv_usec - _sysy_start.tv_usec;
+  _sysy_s[_sysy_idx] += _sysy_us[_sysy_idx] / 1000000;
+  _sysy_us[_sysy_idx] %= 1000000;
+  _sysy_m[_sysy_idx] += _sysy_s[_sysy_idx] / 60;
+  _sysy_s[_sysy_idx] %= 60;
+  _sysy_h[_sysy_idx] += _sysy_m[_sysy_idx] / 60;
+  _sysy_m[_sysy_idx] %= 60;
+  _sysy_idx++;
+}
+
+void starttime()
+{
+  _sysy_l1[_sysy_idx] = __LINE__;
+  gettimeofday(&_sysy_start, NULL);
+}
+void stoptime()
+{
+  gettimeofday(&_sysy_end, NULL);
+  _sysy_l2[_sysy_idx] = __LINE__;
+  _sysy_us[_sysy_idx] += 1000000 * (_sysy_end.tv_sec - _sysy_start.tv_sec) + _sysy_end.tv_usec - _sysy_start.tv_usec;
+  _sysy_s[_sysy_idx] += _sysy_us[_sysy_idx] / 1000000;
+  _sysy_us[_sysy_idx] %= 1000000;
+  _sysy_m[_sysy_idx] += _sysy_s[_sysy_idx] / 60;
+  _sysy_s[_sysy_idx] %= 60;
+  _sysy_h[_sysy_idx] += _sysy_m[_sysy_idx] / 60;
+  _sysy_m[_sysy_idx] %= 60;
+  _sysy_idx++;
+}
+
+/* Coverage dump, called by the programs instrumented with --coverage */
+void _sysy_coverage_dump(int n, int map[], int counts[])
+{
+  const char *path = getenv("SYSY_COVERAGE");
+  FILE *file = fopen(path ? path : "sysy.cov", "w");
+  if (!file)
+    return;
+  for (int i = 0; i < n; i++)
+    fprintf(file, "%d %u\n", map[2 * i], (unsigned)counts[map[2 * i + 1]]);
+  fclose(file);
+}
+
+/* Profile dump, called by the programs instrumented with --profile-generate */
+void _sysy_profile_dump(int n, char names[], int layout[], int counts[])
+{
+  const char *path = getenv("SYSY_PROFILE");
+  FILE *file = fopen(path ? path : "sysy.profdata", "w");
+  if (!file)
+    return;
+  for (int i = 0; i < n; i++)
+  {
+    fprintf(file, "%s %u\n", names, (unsigned)*counts++);
+    for (int j = 0; j < layout[i]; j++, counts += 2)
+      fprintf(file, "  %d %u %u\n", j, (unsigned)counts[0], (unsigned)counts[1]);
+    names += strlen(names) + 1;
+  }
+  fclose(file);
+}
+
+/* Sanitizer trap, called by the programs compiled with --sanitize */
+void _sysy_sanitize_trap(char msg[])
+{
+  fflush(stdout);
+  fprintf(stderr, "%s\n", msg);
+  abort();
+}
@@ -0,0 +1,296 @@
+#!/usr/bin/env python3
+"""
+Benchmark runner for the performance test suite.
+
+Each test case is compiled by each compiler given at each optimization level,
+run under QEMU, or on a board through SSH, and timed by the timers of sylib,
+i.e., the `TOTAL` line `stoptime` prints to stderr, or by the wall clock if the
+program has no timers. The times are written to `bench.csv`, and compared in a
+Markdown table `bench.md`, with the speedups over the first configuration.
+
+The compilers are the builds of the revisions to compare, e.g.,
+
+    git worktree add /tmp/base main && cargo build --release --manifest-path /tmp/base/Cargo.toml
+    python3 tests/bench/bench.py --compiler base=/tmp/base/target/release/main \\
+        --compiler head=./target/release/main --opt-levels 0,2
+
+and the results of the earlier runs can be compared too, by `--baseline` with
+their `bench.csv`.
+"""
+
+import argparse
+import csv
+import math
+import os
+import re
+import shutil
+import subprocess
+import time
+from dataclasses import dataclass, field
+from typing import Dict, List, Optional, Tuple
+
+# ANSI color codes for colored output
+class Colors:
+    RESET = "\033[0m"
+    GREEN = "\033[92m"
+    RED = "\033[91m"
+    YELLOW = "\033[93m"
+    BLUE = "\033[94m"
+    BOLD = "\033[1m"
+
+@dataclass
+class Config:
+    timeout: int = 600
+    repeat: int = 1
+    opt_levels: List[int] = field(default_factory=lambda: [2])
+    compilers: List[Tuple[str, str]] = field(default_factory=list)
+    output_dir: str = "./bench_output"
+    testcase_dir: str = "./tests/testcase/performance_test"
+    target: str = "riscv64"
+    cc: str = "riscv64-linux-gnu-gcc"
+    qemu: str = "qemu-riscv64"
+    ssh: Optional[str] = None
+    remote_dir: str = "/tmp/nkucc-bench"
+    baselines: List[str] = field(default_factory=list)
+
+def parse_compiler(arg: str) -> Tuple[str, str]:
+    label, sep, path = arg.partition("=")
+    if not sep:
+        # a path alone is labelled by itself
+        return arg, arg
+    return label, path
+
+def parse_args() -> Config:
+    parser = argparse.ArgumentParser(description="Benchmark runner for the performance test suite.")
+    parser.add_argument("--timeout", type=int, default=600, help="Timeout for each run in seconds.")
+    parser.add_argument("--repeat", type=int, default=1, help="Runs of each test case, the fastest one counted.")
+    parser.add_argument("--opt-levels", default="2", help="Comma-separated optimization levels, e.g., 0,2.")
+    parser.add_argument("--compiler", action="append", default=[],
+                        help="A compiler as LABEL=PATH, repeated to compare revisions; ./target/release/main by default.")
+    parser.add_argument("--output-dir", default="./bench_output", help="Directory to store the executables and the reports.")
+    parser.add_argument("--testcase-dir", default="./tests/testcase/performance_test", help="Directory containing the test cases.")
+    parser.add_argument("--target", default="riscv64", help="The target architecture of the compilers.")
+    parser.add_argument("--cc", default="riscv64-linux-gnu-gcc", help="The cross compiler assembling and linking the code.")
+    parser.add_argument("--qemu", default="qemu-riscv64", help="The emulator running the executables.")
+    parser.add_argument("--ssh", help="Run on a board through SSH, as USER@HOST, not under QEMU.")
+    parser.add_argument("--remote-dir", default="/tmp/nkucc-bench", help="Directory on the board to copy the executables to.")
+    parser.add_argument("--baseline", action="append", default=[], help="The bench.csv of an earlier run to compare with.")
+
+    args = parser.parse_args()
+
+    return Config(
+        timeout=args.timeout,
+        repeat=max(args.repeat, 1),
+        opt_levels=[int(level) for level in args.opt_levels.split(",")],
+        compilers=[parse_compiler(arg) for arg in args.compiler] or [("HEAD", "./target/release/main")],
+        output_dir=args.output_dir,
+        testcase_dir=args.testcase_dir,
+        target=args.target,
+        cc=args.cc,
+        qemu=args.qemu,
+        ssh=args.ssh,
+        remote_dir=args.remote_dir,
+        baselines=args.baseline,
+    )
+
+@dataclass
+class Result:
+    testcase: str
+    config: str
+    status: str
+    time_us: Optional[int] = None
+
+def run(command: List[str], timeout: int, stdin_path: Optional[str] = None) -> Optional[subprocess.CompletedProcess]:
+    """
+    Run a command with a timeout, and get its result, or `None` on timeout.
+    """
+    stdin = open(stdin_path, "rb") if stdin_path else subprocess.DEVNULL
+    try:
+        return subprocess.run(command, stdin=stdin, stdout=subprocess.PIPE, stderr=subprocess.PIPE, timeout=timeout)
+    except subprocess.TimeoutExpired:
+        return None
+    finally:
+        if stdin_path:
+            stdin.close()
+
+def parse_timer(stderr: str) -> Optional[int]:
+    """
+    Get the total time in microseconds the sylib timers print, e.g.,
+    `TOTAL: 0H-0M-1S-234567us`, or `None` if no timer was stopped, the total
+    being printed at exit anyway.
+    """
+    if "Timer@" not in stderr:
+        return None
+    match = re.search(r"TOTAL: (\d+)H-(\d+)M-(\d+)S-(\d+)us", stderr)
+    if not match:
+        return None
+    h, m, s, us = map(int, match.groups())
+    return ((h * 60 + m) * 60 + s) * 1_000_000 + us
+
+def program_output(stdout: bytes, returncode: int) -> str:
+    """
+    Get the output of a program as the `.out` files have it.
+    """
+    output = stdout.decode(errors="replace")
+    if output and not output.endswith("\n"):
+        output += "\n"
+    return output + f"{returncode & 0xff}\n"
+
+def same_output(a: str, b: str) -> bool:
+    """
+    Compare two outputs ignoring the whitespaces, as the judge does.
+    """
+    normalize = lambda s: [line for line in ("".join(line.split()) for line in s.splitlines()) if line]
+    return normalize(a) == normalize(b)
+
+def find_testcases(testcase_dir: str) -> List[str]:
+    """
+    Recursively find all test case files ending with .sy in the given directory.
+    """
+    testcases = []
+    for root, _, files in os.walk(testcase_dir):
+        for file in sorted(files):
+            if file.endswith(".sy"):
+                testcases.append(os.path.join(root, file).rsplit(".", 1)[0])
+    return sorted(testcases)
+
+def execute(config: Config, exec_path: str, in_path: Optional[str]) -> Tuple[Optional[subprocess.CompletedProcess], float]:
+    """
+    Run an executable under QEMU or on the board, and get its result and the
+    wall time in seconds.
+    """
+    if config.ssh:
+        remote_path = f"{config.remote_dir}/{os.path.basename(exec_path)}"
+        copy = run(["scp", "-q", exec_path, f"{config.ssh}:{remote_path}"], config.timeout)
+        if copy is None or copy.returncode != 0:
+            return None, 0.0
+        command = ["ssh", config.ssh, remote_path]
+    else:
+        command = [config.qemu, exec_path]
+    start = time.perf_counter()
+    result = run(command, config.timeout, in_path)
+    return result, time.perf_counter() - start
+
+def bench(config: Config, testcase: str, label: str, compiler: str, opt_level: int) -> Result:
+    basename = os.path.basename(testcase)
+    name = f"{label} -O{opt_level}"
+    in_path = f"{testcase}.in" if os.path.isfile(f"{testcase}.in") else None
+    exec_path = os.path.join(config.output_dir, f"{basename}.{label}.O{opt_level}")
+
+    compile_command = [
+        compiler, "--target", config.target, "--static", "--cc", config.cc,
+        f"-O{opt_level}", "-o", exec_path, f"{testcase}.sy",
+    ]
+    compiled = run(compile_command, config.timeout)
+    if compiled is None or compiled.returncode != 0:
+        status = "CE" if compiled is not None else "CTLE"
+        print(f"{Colors.RED}[ {status} ] {basename} by {name}: {' '.join(compile_command)}{Colors.RESET}")
+        return Result(basename, name, status)
+
+    with open(f"{testcase}.out") as f:
+        expected = f.read()
+    best = None
+    for _ in range(config.repeat):
+        result, wall = execute(config, exec_path, in_path)
+        if result is None:
+            print(f"{Colors.YELLOW}[ TLE ] {basename} by {name}{Colors.RESET}")
+            return Result(basename, name, "TLE")
+        if result.returncode < 0:
+            print(f"{Colors.RED}[ RE ] {basename} by {name}{Colors.RESET}")
+            return Result(basename, name, "RE")
+        if not same_output(expected, program_output(result.stdout, result.returncode)):
+            print(f"{Colors.RED}[ WA ] {basename} by {name}{Colors.RESET}")
+            return Result(basename, name, "WA")
+        time_us = parse_timer(result.stderr.decode(errors="replace"))
+        if time_us is None:
+            time_us = int(wall * 1_000_000)
+        best = time_us if best is None else min(best, time_us)
+
+    print(f"{Colors.GREEN}[ AC ] {basename} by {name}: {best / 1000:.3f}ms{Colors.RESET}")
+    return Result(basename, name, "AC", best)
+
+def load_results(path: str) -> List[Result]:
+    with open(path, newline="") as f:
+        return [
+            Result(row["testcase"], row["config"], row["status"], int(row["time_us"]) if row["time_us"] else None)
+            for row in csv.DictReader(f)
+        ]
+
+def write_csv(results: List[Result], path: str) -> None:
+    with open(path, "w", newline="") as f:
+        writer = csv.writer(f)
+        writer.writerow(["testcase", "config", "status", "time_us"])
+        for result in results:
+            writer.writerow([result.testcase, result.config, result.status, "" if result.time_us is None else result.time_us])
+
+def report(results: List[Result]) -> str:
+    """
+    Make the Markdown table comparing the configurations, the times in
+    milliseconds with the speedups over the first configuration.
+    """
+    configs: List[str] = []
+    testcases: List[str] = []
+    table: Dict[Tuple[str, str], Result] = {}
+    for result in results:
+        if result.config not in configs:
+            configs.append(result.config)
+        if result.testcase not in testcases:
+            testcases.append(result.testcase)
+        table[(result.testcase, result.config)] = result
+
+    def cell(result: Optional[Result], base: Optional[Result]) -> str:
+        if result is None:
+            return "-"
+        if result.time_us is None:
+            return result.status
+        text = f"{result.time_us / 1000:.3f}"
+        if base is not None and base.time_us is not None and result is not base:
+            text += f" ({max(base.time_us, 1) / max(result.time_us, 1):.2f}x)"
+        return text
+
+    md = "# Benchmark Result\n\n"
+    md += "| Testcase | " + " | ".join(f"{config} (ms)" for config in configs) + " |\n"
+    md += "| -------- |" + " ------ |" * len(configs) + "\n"
+    for testcase in testcases:
+        base = table.get((testcase, configs[0]))
+        cells = [cell(table.get((testcase, config)), base) for config in configs]
+        md += f"| `{testcase}` | " + " | ".join(cells) + " |\n"
+
+    # the geometric means over the test cases passed by all the configurations
+    passed = [t for t in testcases if all(table.get((t, c)) and table[(t, c)].time_us is not None for c in configs)]
+    if passed:
+        means = [math.exp(sum(math.log(max(table[(t, c)].time_us, 1)) for t in passed) / len(passed)) for c in configs]
+        cells = [f"{means[0] / 1000:.3f}"] + [f"{mean / 1000:.3f} ({means[0] / mean:.2f}x)" for mean in means[1:]]
+        md += f"| **geomean** ({len(passed)}) | " + " | ".join(cells) + " |\n"
+    return md
+
+def main() -> None:
+    config = parse_args()
+    if os.path.exists(config.output_dir):
+        shutil.rmtree(config.output_dir)
+    os.makedirs(config.output_dir, exist_ok=True)
+    if config.ssh:
+        run(["ssh", config.ssh, "mkdir", "-p", config.remote_dir], config.timeout)
+
+    results: List[Result] = []
+    for path in config.baselines:
+        results.extend(load_results(path))
+
+    testcases = find_testcases(config.testcase_dir)
+    if not testcases:
+        print(f"{Colors.RED}No test cases found in {config.testcase_dir}{Colors.RESET}")
+        exit(1)
+    for testcase in testcases:
+        for label, compiler in config.compilers:
+            for opt_level in config.opt_levels:
+                results.append(bench(config, testcase, label, compiler, opt_level))
+
+    csv_path = os.path.join(config.output_dir, "bench.csv")
+    write_csv(results, csv_path)
+    md_path = os.path.join(config.output_dir, "bench.md")
+    with open(md_path, "w") as f:
+        f.write(report(results))
+    print(f"{Colors.BOLD}Benchmark results written to {csv_path} and {md_path}{Colors.RESET}")
+
+if __name__ == "__main__":
+    main()
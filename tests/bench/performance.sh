@@ -0,0 +1,2 @@
+# 性能测试
+python3 ./tests/bench/bench.py --testcase-dir ./tests/testcase/performance_test/ --opt-levels 0,2
\ No newline at end of file
@@ -0,0 +1,119 @@
+//! The helpers shared by the integration tests.
+
+// each test uses some of them
+#![allow(dead_code)]
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// The directory of the fixtures, `tests/`.
+pub fn tests_dir() -> PathBuf { Path::new(env!("CARGO_MANIFEST_DIR")).join("tests") }
+
+/// Collect the `.sy` files under the directory, sorted, except in the
+/// subdirectories of the names given.
+pub fn find_fixtures(dir: &Path, skip: &[&str]) -> Vec<PathBuf> {
+    let mut fixtures = Vec::new();
+    find_fixtures_in(dir, skip, &mut fixtures);
+    fixtures
+}
+
+fn find_fixtures_in(dir: &Path, skip: &[&str], fixtures: &mut Vec<PathBuf>) {
+    let mut entries = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            if !skip.iter().any(|name| path.ends_with(name)) {
+                find_fixtures_in(&path, skip, fixtures);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "sy") {
+            fixtures.push(path);
+        }
+    }
+}
+
+/// Get the output of a program as the `.out` files have it: what it prints,
+/// ended by a newline if not empty, and the exit code.
+pub fn program_output(stdout: &[u8], code: i32) -> String {
+    let mut output = String::from_utf8_lossy(stdout).into_owned();
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+    output.push_str(&format!("{}\n", code & 0xff));
+    output
+}
+
+/// Compare two outputs of the programs ignoring the whitespaces, as
+/// `diff -Bw` does, and so the judge.
+pub fn same_output(a: &str, b: &str) -> bool {
+    let normalize = |s: &str| {
+        s.lines()
+            .map(|line| line.split_whitespace().collect::<String>())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Run a command with the input as stdin, and get its output, or `None` if
+/// it is killed for running longer than the timeout.
+pub fn run_with_timeout(
+    cmd: &mut Command,
+    input: &[u8],
+    timeout: Duration,
+) -> io::Result<Option<Output>> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // the pipes are served aside, so neither the program nor this blocks on
+    // a full one
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            buf
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().unwrap()));
+    let stderr = read(Box::new(child.stderr.take().unwrap()));
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    let _ = writer.join();
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap(),
+        stderr: stderr.join().unwrap(),
+    }))
+}
+
+/// Check if a tool can be run, by its `--version`.
+pub fn has_tool(tool: &str) -> bool {
+    Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
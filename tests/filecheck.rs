@@ -0,0 +1,70 @@
+//! The FileCheck tests of the fixtures under `tests/filecheck/`.
+//!
+//! Each fixture is a SysY source with a `// RUN:` line, giving the options of
+//! the compiler, and the directives of [`nkucc::infra::filecheck`]. The output
+//! of the compiler, written to a temporary file by `-o`, is matched against
+//! the directives, e.g.:
+//!
+//! ```c
+//! // RUN: --emit ir --passes mem2reg
+//! int main() { int a = 1; return a; }
+//! // CHECK-NOT: alloca
+//! // CHECK: ret i32 1
+//! ```
+
+use std::path::Path;
+use std::process::Command;
+
+use nkucc::infra::filecheck::filecheck;
+
+#[test]
+fn test_filecheck() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/filecheck");
+    let mut paths = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sy"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty(), "no fixtures under `{}`", dir.display());
+
+    let tmp = std::env::temp_dir().join(format!("nkucc-filecheck-{}", std::process::id()));
+    std::fs::create_dir_all(&tmp).unwrap();
+    let mut failures = Vec::new();
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let Some(args) = text.lines().find_map(|line| line.split_once("RUN:")) else {
+            failures.push(format!("{}: no RUN line", name));
+            continue;
+        };
+        let output = tmp.join(&name).with_extension("out");
+        let result = Command::new(env!("CARGO_BIN_EXE_main"))
+            .arg("--no-config")
+            .args(args.1.split_whitespace())
+            .arg("-o")
+            .arg(&output)
+            .arg(&path)
+            .output()
+            .unwrap();
+        if !result.status.success() {
+            failures.push(format!(
+                "{}: the compiler failed\n{}",
+                name,
+                String::from_utf8_lossy(&result.stderr)
+            ));
+            continue;
+        }
+        let output = std::fs::read_to_string(&output).unwrap();
+        if let Err(err) = filecheck(&text, &output) {
+            failures.push(format!("{}: {}\noutput:\n{}", name, err, output));
+        }
+    }
+    let _ = std::fs::remove_dir_all(&tmp);
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
@@ -0,0 +1,199 @@
+//! The fuzzing of the compiler by the random programs of
+//! [`nkucc::frontend::generate_program`].
+//!
+//! - The frontend: each program must be parsed and type checked without an
+//!   error or a panic, and the programs of the constructs the IR generation
+//!   supports must also be lowered without a panic into a module the verifier
+//!   accepts.
+//! - The passes: each lowered program is optimized by random orderings of the
+//!   passes, verified after each pass, and must exit with the same code and
+//!   print the same output in the interpreter as without optimization. Every
+//!   other ordering transforms the functions in parallel, as `--jobs` does.
+//!
+//! The seeds are `0..FUZZ_ITERS`, 200 by default, from `FUZZ_SEED` if given,
+//! e.g., `FUZZ_SEED=1000 FUZZ_ITERS=100000 cargo test --release --test fuzz`
+//! for a longer run. A failing program is printed with its seed, and the
+//! passes miscompiling it.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use nkucc::backend::target::TARGETS;
+use nkucc::frontend::{generate_program, irgen, CompUnit, Diagnostic, GenOptions, SysYParser};
+use nkucc::infra::rng::Rng;
+use nkucc::ir::interp::Interpreter;
+use nkucc::ir::passes::register_passes;
+use nkucc::ir::passman::PassManager;
+use nkucc::ir::Context;
+
+/// The orderings of the passes tried on each program.
+const ORDERINGS: usize = 4;
+
+/// The number of passes in an ordering at most, a pass possibly repeated.
+const MAX_PASSES: usize = 16;
+
+/// The limit of the instructions interpreted, so a miscompiled loop does not
+/// hang the test.
+const STEP_LIMIT: u64 = 10_000_000;
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid {}: `{}`", name, value)),
+        Err(_) => default,
+    }
+}
+
+/// Compile the program as far as the options allow, and get the error.
+fn check(program: &str, lower: bool) -> Result<(), String> {
+    let mut ast = SysYParser::new()
+        .parse(program)
+        .map_err(|err| format!("parsing failed: {}", Diagnostic::from(err).message))?;
+    ast.type_check()
+        .map_err(|diag| format!("type checking failed: {}", diag.message))?;
+    if lower {
+        let ir = irgen(&ast, TARGETS[0].info());
+        ir.verify()
+            .map_err(|err| format!("the IR is invalid: {}", err))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_fuzz_frontend() {
+    let start = env_u64("FUZZ_SEED", 0);
+    let iters = env_u64("FUZZ_ITERS", 200);
+    // the grammar does not have the unary `-` and `!` yet
+    let full = GenOptions {
+        unary: false,
+        ..Default::default()
+    };
+    // the constructs the IR generation supports
+    let lowered = GenOptions::straight_line();
+
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut failures = Vec::new();
+    for seed in start..start + iters {
+        for (name, options, lower) in [("all", &full, false), ("straight-line", &lowered, true)] {
+            let program = generate_program(seed, options);
+            let result = panic::catch_unwind(AssertUnwindSafe(|| check(&program, lower)))
+                .unwrap_or_else(|payload| Err(format!("panicked: {}", panic_message(&payload))));
+            if let Err(err) = result {
+                failures.push(format!("seed {} ({}): {}\n{}", seed, name, err, program));
+            }
+        }
+        // keep the report readable
+        if failures.len() >= 5 {
+            break;
+        }
+    }
+    panic::set_hook(hook);
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
+
+fn parse(program: &str) -> CompUnit {
+    let mut ast = SysYParser::new().parse(program).unwrap();
+    ast.type_check().unwrap();
+    ast
+}
+
+/// Run the program, and get its output and exit code, or the error.
+fn run(ir: &Context) -> Result<(String, i32), String> {
+    let mut output = Vec::new();
+    let mut interp = Interpreter::new(ir, &[][..], &mut output);
+    interp.set_step_limit(Some(STEP_LIMIT));
+    let code = interp.run_main().map_err(|err| err.to_string())?;
+    drop(interp);
+    Ok((String::from_utf8_lossy(&output).into_owned(), code))
+}
+
+/// Optimize the program by the passes, verifying after each, and run it.
+fn optimize_and_run(
+    ast: &CompUnit,
+    passes: &[&str],
+    parallel: bool,
+) -> Result<(String, i32), String> {
+    let mut ir = irgen(ast, TARGETS[0].info());
+    let mut pm = PassManager::default();
+    register_passes(&mut pm);
+    pm.add_pipeline(&passes.join(",")).unwrap();
+    pm.set_verify_each(true);
+    pm.set_parallel(parallel);
+    pm.try_run(&mut ir).map_err(|err| err.to_string())?;
+    run(&ir)
+}
+
+#[test]
+fn test_fuzz_passes() {
+    let start = env_u64("FUZZ_SEED", 0);
+    let iters = env_u64("FUZZ_ITERS", 200);
+    let options = GenOptions::straight_line();
+    let mut pm = PassManager::default();
+    register_passes(&mut pm);
+    let names = pm.registered().collect::<Vec<_>>();
+
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut failures = Vec::new();
+    for seed in start..start + iters {
+        let program = generate_program(seed, &options);
+        let ast = parse(&program);
+        let expected = run(&irgen(&ast, TARGETS[0].info()))
+            .unwrap_or_else(|err| panic!("seed {}: the program failed: {}", seed, err));
+
+        let mut rng = Rng::new(seed);
+        for ordering in 0..ORDERINGS {
+            let passes = (0..rng.below(MAX_PASSES) + 1)
+                .map(|_| *rng.choose(&names))
+                .collect::<Vec<_>>();
+            let parallel = ordering % 2 == 1;
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                optimize_and_run(&ast, &passes, parallel)
+            }))
+            .unwrap_or_else(|payload| Err(format!("panicked: {}", panic_message(&payload))));
+            let error = match result {
+                Ok(actual) if actual == expected => continue,
+                Ok((output, code)) => format!(
+                    "exits with {} and prints {:?}, instead of {} and {:?}",
+                    code, output, expected.1, expected.0
+                ),
+                Err(err) => err,
+            };
+            failures.push(format!(
+                "seed {}, --passes {}{}: {}\n{}",
+                seed,
+                passes.join(","),
+                if parallel { " --jobs 2" } else { "" },
+                error,
+                program
+            ));
+            // one ordering is enough to reproduce it
+            break;
+        }
+        if failures.len() >= 5 {
+            break;
+        }
+    }
+    panic::set_hook(hook);
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
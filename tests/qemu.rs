@@ -0,0 +1,336 @@
+//! The end-to-end tests of the RISC-V backend under QEMU.
+//!
+//! Each functional fixture is compiled by `nkucc` for `riscv64` at each
+//! optimization level, linked statically with the bundled runtime by the cross
+//! compiler, and run by `qemu-riscv64` with the `.in` file as stdin. Its output
+//! and exit code are compared with the expected ones, ignoring the whitespaces
+//! as the judge does. They are by default the `.out` file of the fixture, and
+//! with `QEMU_ORACLE=gcc` the ones of the fixture compiled by GCC as C, or as
+//! C++ for the constants sizing the arrays, with the same runtime.
+//!
+//! A program differing at a level is compiled again with fewer passes of the
+//! level as long as it still differs, and the failure gives the command
+//! reproducing it with the passes left, to start the debugging from.
+//!
+//! The tools and the runs are configured by the environment:
+//!
+//! - `QEMU_RISCV64`: the emulator, `qemu-riscv64` by default;
+//! - `RISCV64_CC`: the cross compiler, `riscv64-linux-gnu-gcc` by default;
+//! - `RISCV64_CXX`: the cross compiler of C++ for the oracle, `RISCV64_CC` with
+//!   `g++` for `gcc` by default;
+//! - `QEMU_ORACLE`: `out` or `gcc`, the expected outputs, `out` by default;
+//! - `QEMU_OPT_LEVELS`: the optimization levels, `0,1,2` by default;
+//! - `QEMU_TIMEOUT`: the seconds a program may run, 10 by default.
+//!
+//! The test is skipped if a tool is missing, unless `QEMU_REQUIRED=1`, e.g., in
+//! the CI having them. The fixtures the compiler rejects or crashes on are
+//! skipped too, the frontend not supporting all of SysY yet; the snapshots
+//! catch the regressions of the ones it compiles. So are the ones GCC cannot
+//! build or crashing when built by it, with no behavior to compare with.
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use common::{find_fixtures, has_tool, program_output, run_with_timeout, same_output, tests_dir};
+use nkucc::driver::{SYLIB_C, SYLIB_H};
+use nkucc::infra::diff::unified_diff;
+use nkucc::ir::passes::opt_pipeline;
+
+/// The header included by the programs compiled by the oracle, declaring the
+/// runtime, and defining the timing macros as `nkucc` does.
+const PRELUDE: &str = "#ifdef __cplusplus\nextern \"C\" {\n#endif\n#include \"sylib.h\"\n\
+                       #ifdef __cplusplus\n}\n#endif\n\
+                       #define starttime() _sysy_starttime(__LINE__)\n\
+                       #define stoptime() _sysy_stoptime(__LINE__)\n";
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+/// The tools and the options of the runs.
+struct Runner {
+    qemu: String,
+    cc: String,
+    timeout: Duration,
+    tmp: PathBuf,
+}
+
+/// How a program is compiled by `nkucc`.
+#[derive(Clone, Copy)]
+enum Opt<'a> {
+    Level(&'a str),
+    Passes(&'a [String]),
+}
+
+impl Runner {
+    fn nkucc(&self, path: &Path, opt: Opt, exe: &Path) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_main"));
+        cmd.args(["--no-config", "--target", "riscv64", "--static"]);
+        match opt {
+            Opt::Level(level) => cmd.arg(format!("-O{}", level)),
+            Opt::Passes([]) => cmd.arg("-O0"),
+            Opt::Passes(passes) => cmd.args(["--passes", &passes.join(",")]),
+        };
+        cmd.args(["--cc", &self.cc, "-o"]).arg(exe).arg(path);
+        cmd
+    }
+
+    /// Run the executable under QEMU, and get its output as the `.out` files
+    /// have it.
+    fn run(&self, exe: &Path, input: &[u8]) -> Result<String, String> {
+        let output = run_with_timeout(Command::new(&self.qemu).arg(exe), input, self.timeout)
+            .map_err(|err| format!("cannot run `{}`: {}", self.qemu, err))?
+            .ok_or_else(|| format!("timed out after {:?}", self.timeout))?;
+        match output.status.code() {
+            Some(code) => Ok(program_output(&output.stdout, code)),
+            None => Err(format!("killed by {}", output.status)),
+        }
+    }
+
+    /// Compile the program by `nkucc` and run it, and get the error if it
+    /// does not give the expected output.
+    fn check(&self, path: &Path, opt: Opt, input: &[u8], expected: &str) -> Result<(), String> {
+        let exe = self.tmp.join("a.out");
+        let output = self.nkucc(path, opt, &exe).output().unwrap();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("cannot be built\n{}", stderr));
+        }
+        let actual = self.run(&exe, input)?;
+        match same_output(expected, &actual) {
+            true => Ok(()),
+            false => Err(format!(
+                "the output differs\n{}",
+                unified_diff(expected, &actual, 3)
+            )),
+        }
+    }
+
+    /// Remove the passes of the level one by one while the program still
+    /// fails, and get the passes left.
+    fn minimize(&self, path: &Path, level: &str, input: &[u8], expected: &str) -> Vec<String> {
+        let level = level.parse().unwrap_or(2);
+        let mut passes = opt_pipeline(level, false)
+            .split(',')
+            .filter(|pass| !pass.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        'reduce: loop {
+            for i in 0..passes.len() {
+                let mut fewer = passes.clone();
+                fewer.remove(i);
+                if self
+                    .check(path, Opt::Passes(&fewer), input, expected)
+                    .is_err()
+                {
+                    passes = fewer;
+                    continue 'reduce;
+                }
+            }
+            return passes;
+        }
+    }
+}
+
+/// The reference compiler, building the programs as C, or as C++ if not valid
+/// C, e.g., for an array sized by a constant at the top level.
+struct Oracle {
+    cc: String,
+    cxx: String,
+    dir: PathBuf,
+}
+
+impl Oracle {
+    /// Write the runtime and build it, once for the programs.
+    fn new(cc: String, cxx: String, dir: &Path) -> Result<Self, String> {
+        let dir = dir.join("oracle");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, text) in [
+            ("sylib.h", SYLIB_H),
+            ("sylib.c", SYLIB_C),
+            ("prelude.h", PRELUDE),
+        ] {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        let status = Command::new(&cc)
+            .args(["-c", "-O2", "-w", "-o"])
+            .arg(dir.join("sylib.o"))
+            .arg(dir.join("sylib.c"))
+            .status()
+            .map_err(|err| format!("cannot run `{}`: {}", cc, err))?;
+        if !status.success() {
+            return Err(format!("`{}` cannot build the runtime", cc));
+        }
+        Ok(Oracle { cc, cxx, dir })
+    }
+
+    fn command(&self, path: &Path, exe: &Path, cxx: bool) -> Command {
+        let (cc, lang) = match cxx {
+            true => (&self.cxx, "c++"),
+            false => (&self.cc, "c"),
+        };
+        let mut cmd = Command::new(cc);
+        // the arithmetic wraps around in SysY as in `nkucc`
+        cmd.args(["-O2", "-fwrapv", "-w", "-static", "-include"])
+            .arg(self.dir.join("prelude.h"));
+        if cxx {
+            cmd.arg("-fpermissive");
+        }
+        cmd.args(["-x", lang])
+            .arg(path)
+            .args(["-x", "none"])
+            .arg(self.dir.join("sylib.o"))
+            .arg("-o")
+            .arg(exe);
+        cmd
+    }
+
+    /// Build the program, and get the command building it, or `None` if it
+    /// cannot be.
+    fn build(&self, path: &Path, exe: &Path) -> Option<Command> {
+        [false, true].into_iter().find_map(|cxx| {
+            let mut cmd = self.command(path, exe, cxx);
+            let status = cmd.stderr(std::process::Stdio::null()).status().ok()?;
+            status.success().then_some(cmd)
+        })
+    }
+}
+
+/// Print a command to be pasted in a shell.
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn test_qemu() {
+    let qemu = env_or("QEMU_RISCV64", "qemu-riscv64");
+    let cc = env_or("RISCV64_CC", "riscv64-linux-gnu-gcc");
+    let cxx = env_or("RISCV64_CXX", &cc.replace("gcc", "g++"));
+    let gcc_oracle = match env_or("QEMU_ORACLE", "out").as_str() {
+        "out" => false,
+        "gcc" => true,
+        oracle => panic!("invalid QEMU_ORACLE: `{}`", oracle),
+    };
+    let levels = env_or("QEMU_OPT_LEVELS", "0,1,2");
+    let timeout = env_or("QEMU_TIMEOUT", "10")
+        .parse()
+        .map(Duration::from_secs)
+        .expect("invalid QEMU_TIMEOUT");
+    let required = std::env::var_os("QEMU_REQUIRED").is_some_and(|v| v == "1");
+
+    let mut tools = vec![&qemu, &cc];
+    if gcc_oracle {
+        tools.push(&cxx);
+    }
+    for tool in tools {
+        if !has_tool(tool) {
+            assert!(!required, "`{}` is not found", tool);
+            eprintln!("skipped: `{}` is not found", tool);
+            return;
+        }
+    }
+
+    let dir = tests_dir().join("testcase");
+    let tmp = std::env::temp_dir().join(format!("nkucc-qemu-{}", std::process::id()));
+    std::fs::create_dir_all(&tmp).unwrap();
+    let oracle = gcc_oracle.then(|| Oracle::new(cc.clone(), cxx, &tmp).unwrap());
+    let runner = Runner {
+        qemu,
+        cc,
+        timeout,
+        tmp: tmp.clone(),
+    };
+
+    let mut failures = Vec::new();
+    let (mut passed, mut skipped) = (0, 0);
+    for path in find_fixtures(&dir, &[]) {
+        let name = path.strip_prefix(&dir).unwrap().display().to_string();
+        let input_path = path.with_extension("in");
+        let input = std::fs::read(&input_path).unwrap_or_default();
+        let stdin = match input_path.exists() {
+            true => format!(" < {}", input_path.display()),
+            false => String::new(),
+        };
+        let (expected, reference) = match &oracle {
+            None => match std::fs::read_to_string(path.with_extension("out")) {
+                Ok(expected) => (expected, None),
+                Err(_) => continue,
+            },
+            Some(oracle) => {
+                let exe = tmp.join("ref");
+                let Some(cmd) = oracle.build(&path, &exe) else {
+                    skipped += 1;
+                    continue;
+                };
+                // a program crashing as C, e.g., dividing by zero, has no
+                // behavior to compare with
+                let Ok(expected) = runner.run(&exe, &input) else {
+                    skipped += 1;
+                    continue;
+                };
+                (expected, Some(command_line(&cmd)))
+            }
+        };
+
+        for level in levels.split(',') {
+            let err = match runner.check(&path, Opt::Level(level), &input, &expected) {
+                Ok(()) => {
+                    passed += 1;
+                    continue;
+                }
+                Err(err) => err,
+            };
+            // the rejected and the unsupported programs, as opposed to the
+            // assembly not assembling or linking
+            if err.contains("error[") || err.contains("panicked") {
+                skipped += 1;
+                break;
+            }
+            let passes;
+            let opt = match err.starts_with("cannot be built") {
+                true => Opt::Level(level),
+                false => {
+                    passes = runner.minimize(&path, level, &input, &expected);
+                    Opt::Passes(&passes)
+                }
+            };
+            let exe = Path::new("a.out");
+            let mut failure = format!(
+                "{} -O{}: {}\nto reproduce: {} && {} {}{}",
+                name,
+                level,
+                err,
+                command_line(&runner.nkucc(&path, opt, exe)),
+                runner.qemu,
+                exe.display(),
+                stdin
+            );
+            if let Some(reference) = &reference {
+                failure.push_str(&format!("\nthe reference: {}", reference));
+            }
+            failures.push(failure);
+        }
+    }
+    // kept for the reference commands of the failures
+    if failures.is_empty() {
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+
+    eprintln!(
+        "{} runs passed, {} fixtures skipped as unsupported",
+        passed, skipped
+    );
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
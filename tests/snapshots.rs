@@ -0,0 +1,197 @@
+//! The golden tests over the fixtures under `tests/`.
+//!
+//! Each `.sy` fixture is compiled at `-O2` for the first target, and the AST,
+//! the IR and the assembly are compared with the snapshots under
+//! `tests/snapshots/`, at the path of the fixture relative to `tests/`, with
+//! the extension of the output, i.e., `.ast`, `.ll` or `.s`. A fixture that
+//! fails to compile has the rendered diagnostic in an `.err` snapshot instead.
+//! The fixtures panicking in the compiler without a snapshot are skipped, the
+//! frontend not supporting all of SysY yet. The fixtures under
+//! `tests/filecheck/` are checked by their directives instead.
+//!
+//! Run with `UPDATE_SNAPSHOTS=1` to write the snapshots instead, e.g., after
+//! changing the output on purpose, and review the changes with `git diff`.
+//!
+//! The fixtures with an `.out` file are also run by the interpreter, with the
+//! `.in` file as stdin, and their output followed by the exit code is compared
+//! with it, ignoring the whitespaces as the judge does.
+
+mod common;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use common::{find_fixtures, program_output, same_output, tests_dir};
+use nkucc::backend::target::{CodegenOptions, TARGETS};
+use nkucc::frontend::{irgen, Diagnostic, SysYParser};
+use nkucc::infra::diff::unified_diff;
+use nkucc::ir::interp::Interpreter;
+use nkucc::ir::passes::{opt_pipeline, register_passes};
+use nkucc::ir::passman::PassManager;
+use nkucc::ir::{Context, Source};
+
+/// The kinds of snapshots, by their extensions.
+const KINDS: [&str; 4] = ["ast", "ll", "s", "err"];
+
+/// The limit of the instructions interpreted, so a miscompiled loop does not
+/// hang the test.
+const STEP_LIMIT: u64 = 200_000_000;
+
+/// The outputs of compiling a fixture.
+struct Outputs {
+    snapshots: Vec<(&'static str, String)>,
+    /// The optimized IR, if compiled.
+    ir: Option<Context>,
+}
+
+fn compile(src: &Source) -> Result<Outputs, String> {
+    let mut ast = match SysYParser::new().parse(src.text()) {
+        Ok(ast) => ast,
+        Err(err) => {
+            let err = Diagnostic::from(err).render(src, false);
+            return Ok(Outputs {
+                snapshots: vec![("err", err)],
+                ir: None,
+            });
+        }
+    };
+    if let Err(err) = ast.type_check() {
+        return Ok(Outputs {
+            snapshots: vec![("err", err.render(src, false))],
+            ir: None,
+        });
+    }
+    let mut snapshots = vec![("ast", format!("{:#?}\n", ast))];
+
+    let target = TARGETS[0];
+    let mut ir = irgen(&ast, target.info());
+    let mut pm = PassManager::default();
+    register_passes(&mut pm);
+    pm.add_pipeline(&opt_pipeline(2, false))
+        .map_err(|err| err.to_string())?;
+    pm.try_run(&mut ir).map_err(|err| err.to_string())?;
+    snapshots.push(("ll", ir.to_string()));
+
+    let asm = target
+        .emit_asm(&ir, &CodegenOptions::default())
+        .map_err(|err| err.to_string())?;
+    snapshots.push(("s", asm));
+    Ok(Outputs {
+        snapshots,
+        ir: Some(ir),
+    })
+}
+
+/// Run the program, and get its output followed by the exit code, as the
+/// `.out` files have it.
+fn run(ir: &Context, input: &[u8]) -> Result<String, String> {
+    let mut stdout = Vec::new();
+    let mut interp = Interpreter::new(ir, input, &mut stdout);
+    interp.set_step_limit(Some(STEP_LIMIT));
+    let code = interp.run_main().map_err(|err| err.to_string())?;
+    drop(interp);
+    Ok(program_output(&stdout, code))
+}
+
+#[test]
+fn test_snapshots() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let tests = tests_dir();
+    let snapshot_dir = tests.join("snapshots");
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some_and(|v| v == "1");
+
+    // the fixtures of FileCheck have their own checks
+    let paths = find_fixtures(&tests, &["snapshots", "filecheck"]);
+    assert!(!paths.is_empty(), "no fixtures under `{}`", tests.display());
+
+    // the panics of the unsupported fixtures are expected, keep them quiet
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failures = Vec::new();
+    let (mut checked, mut skipped) = (0, 0);
+    for path in paths {
+        let rel = path.strip_prefix(&tests).unwrap();
+        let name = path.strip_prefix(root).unwrap().display().to_string();
+        let snapshot = |kind: &str| snapshot_dir.join(rel).with_extension(kind);
+        let has_snapshots = KINDS.iter().any(|kind| snapshot(kind).exists());
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let src = Source::new(name.clone(), text);
+        let outputs = match panic::catch_unwind(AssertUnwindSafe(|| compile(&src))) {
+            Ok(Ok(outputs)) => outputs,
+            Ok(Err(err)) => {
+                failures.push(format!("{}: {}", name, err));
+                continue;
+            }
+            Err(_) if has_snapshots && !update => {
+                failures.push(format!("{}: the compiler panicked", name));
+                continue;
+            }
+            Err(_) => {
+                skipped += 1;
+                continue;
+            }
+        };
+        checked += 1;
+
+        if update {
+            std::fs::create_dir_all(snapshot("ast").parent().unwrap()).unwrap();
+            for kind in KINDS {
+                let _ = std::fs::remove_file(snapshot(kind));
+            }
+            for (kind, text) in &outputs.snapshots {
+                std::fs::write(snapshot(kind), text).unwrap();
+            }
+        } else {
+            for kind in KINDS {
+                let expected = std::fs::read_to_string(snapshot(kind)).ok();
+                let actual = outputs
+                    .snapshots
+                    .iter()
+                    .find(|(k, _)| *k == kind)
+                    .map(|(_, text)| text);
+                match (expected, actual) {
+                    (Some(expected), Some(actual)) if expected != *actual => {
+                        let diff = unified_diff(&expected, actual, 3);
+                        failures.push(format!("{}: the .{} differs\n{}", name, kind, diff))
+                    }
+                    (Some(_), None) => failures.push(format!("{}: no .{} is emitted", name, kind)),
+                    (None, Some(_)) => failures.push(format!(
+                        "{}: no .{} snapshot, run with UPDATE_SNAPSHOTS=1",
+                        name, kind
+                    )),
+                    _ => {}
+                }
+            }
+        }
+
+        let out = path.with_extension("out");
+        let (Some(ir), Ok(expected)) = (&outputs.ir, std::fs::read_to_string(&out)) else {
+            continue;
+        };
+        let input = std::fs::read(path.with_extension("in")).unwrap_or_default();
+        match panic::catch_unwind(AssertUnwindSafe(|| run(ir, &input))) {
+            Ok(Ok(actual)) if !same_output(&expected, &actual) => failures.push(format!(
+                "{}: the output differs\n{}",
+                name,
+                unified_diff(&expected, &actual, 3)
+            )),
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => failures.push(format!("{}: the program failed: {}", name, err)),
+            Err(_) => failures.push(format!("{}: the interpreter panicked", name)),
+        }
+    }
+    panic::set_hook(hook);
+
+    eprintln!(
+        "{} fixtures checked, {} skipped as unsupported",
+        checked, skipped
+    );
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
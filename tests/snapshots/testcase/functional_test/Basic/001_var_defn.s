@@ -0,0 +1,10 @@
+	.attribute arch, "rv64gc"
+	.text
+	.global main
+	.align 1
+	.type main, @function
+main:
+.Lbb_0:
+	addi a0, zero, 8
+	ret
+
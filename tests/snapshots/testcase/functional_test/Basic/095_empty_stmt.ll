@@ -0,0 +1,4 @@
+define i32 @main() {
+bb_0:
+	ret i32 21
+}